@@ -0,0 +1,95 @@
+//! Representative workloads for the tree-walking executor: recursive calls,
+//! table churn, string building and closure creation. Each benchmark reports
+//! throughput in executed statements, taken from the executor's
+//! `PerfCounters`, so a slowdown can be told apart from extra work.
+//! Run with `cargo bench --bench tree_walker`.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use muscm::executor::Executor;
+use muscm::lua_interpreter::LuaInterpreter;
+use muscm::lua_parser::{parse, tokenize, Chunk, TokenSlice};
+use std::hint::black_box;
+
+const FIB: &str = "
+local function fib(n)
+    if n < 2 then return n end
+    return fib(n - 1) + fib(n - 2)
+end
+return fib(18)
+";
+
+const TABLE_CHURN: &str = "
+local total = 0
+for round = 1, 50 do
+    local t = {}
+    for i = 1, 100 do
+        t[i] = {id = i, name = 'item'}
+    end
+    for i = 1, 100 do
+        total = total + t[i].id
+        t[i] = nil
+    end
+end
+return total
+";
+
+const STRING_CONCAT: &str = "
+local s = ''
+for i = 1, 2000 do
+    s = s .. 'x'
+end
+local parts = ''
+for i = 1, 500 do
+    parts = parts .. tostring(i) .. ','
+end
+return #s + #parts
+";
+
+const NESTED_CLOSURES: &str = "
+local function counter()
+    local count = 0
+    return function(step)
+        local function bump() count = count + step end
+        bump()
+        return count
+    end
+end
+local sum = 0
+for i = 1, 500 do
+    local next = counter()
+    sum = sum + next(i) + next(1)
+end
+return sum
+";
+
+fn parse_block(code: &str) -> Chunk {
+    let tokens = tokenize(code).unwrap();
+    let (_, block) = parse(TokenSlice::from(tokens.as_slice())).unwrap();
+    block
+}
+
+fn run(block: &Chunk) -> Executor {
+    let mut executor = Executor::new();
+    let mut interp = LuaInterpreter::new();
+    interp.use_vm = false;
+    executor.execute_block(block, &mut interp).unwrap();
+    executor
+}
+
+fn bench_workloads(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tree_walker");
+    for (name, code) in [
+        ("fib", FIB),
+        ("table_churn", TABLE_CHURN),
+        ("string_concat", STRING_CONCAT),
+        ("nested_closures", NESTED_CLOSURES),
+    ] {
+        let block = parse_block(code);
+        group.throughput(Throughput::Elements(run(&block).perf().statements));
+        group.bench_function(name, |b| b.iter(|| run(black_box(&block))));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_workloads);
+criterion_main!(benches);
@@ -0,0 +1,161 @@
+//! Compares the tree-walking executor with the bytecode VM on loops at the
+//! top level and inside functions, and name-based scopes with resolved
+//! slots inside functions. Run with `cargo bench --bench vm`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use muscm::compiler::compile;
+use muscm::executor::Executor;
+use muscm::lua_interpreter::LuaInterpreter;
+use muscm::lua_parser::{parse, tokenize, Chunk, TokenSlice};
+use muscm::resolver::resolve;
+use muscm::vm::Vm;
+use std::hint::black_box;
+
+const SUM_LOOP: &str = "
+local sum = 0
+for i = 1, 10000 do
+    sum = sum + i * 2
+end
+return sum
+";
+
+const WHILE_LOOP: &str = "
+local n, steps = 27, 0
+while n > 1 do
+    if n % 2 == 0 then n = n // 2 else n = 3 * n + 1 end
+    steps = steps + 1
+end
+return steps
+";
+
+const FUNCTION_LOOP: &str = "
+local function sum_to(n)
+    local sum = 0
+    for i = 1, n do
+        local twice = i * 2
+        sum = sum + twice
+    end
+    return sum
+end
+return sum_to(10000)
+";
+
+const FIB: &str = "
+local function fib(n)
+    if n < 2 then return n end
+    return fib(n - 1) + fib(n - 2)
+end
+return fib(18)
+";
+
+const TABLE_LOOP: &str = "
+local function fill(n)
+    local t = {}
+    for i = 1, n do t[i] = {x = i, y = i * 2} end
+    local total = 0
+    for _, p in ipairs(t) do total = total + p.x + p.y end
+    return total
+end
+return fill(2000)
+";
+
+const METHOD_LOOP: &str = "
+local Counter = {}
+Counter.__index = Counter
+function Counter.new() return setmetatable({n = 0}, Counter) end
+function Counter:add(k) self.n = self.n + k end
+local function run(n)
+    local c = Counter.new()
+    for i = 1, n do c:add(i) end
+    return c.n
+end
+return run(5000)
+";
+
+fn parse_block(code: &str) -> Chunk {
+    let tokens = tokenize(code).unwrap();
+    let (_, block) = parse(TokenSlice::from(tokens.as_slice())).unwrap();
+    block
+}
+
+/// An interpreter that keeps every function on the tree-walker
+fn tree_walker() -> LuaInterpreter {
+    let mut interp = LuaInterpreter::new();
+    interp.use_vm = false;
+    interp
+}
+
+fn bench_loops(c: &mut Criterion) {
+    for (name, code) in [("sum_loop", SUM_LOOP), ("collatz_while", WHILE_LOOP)] {
+        let block = parse_block(code);
+        let chunk = compile(&block).unwrap();
+        let mut group = c.benchmark_group(name);
+
+        group.bench_function("tree_walker", |b| {
+            b.iter(|| {
+                let mut interp = tree_walker();
+                Executor::new()
+                    .execute_block(black_box(&block), &mut interp)
+                    .unwrap()
+            })
+        });
+        group.bench_function("vm", |b| {
+            b.iter(|| {
+                let mut interp = LuaInterpreter::new();
+                Vm::new().run(black_box(&chunk), &mut interp).unwrap()
+            })
+        });
+        group.finish();
+    }
+}
+
+/// Hot loops inside functions, which the VM compiles when they are called
+fn bench_functions(c: &mut Criterion) {
+    for (name, code) in [
+        ("function_loop", FUNCTION_LOOP),
+        ("fib", FIB),
+        ("table_loop", TABLE_LOOP),
+        ("method_loop", METHOD_LOOP),
+    ] {
+        let block = resolve(&parse_block(code));
+        let chunk = compile(&block).unwrap();
+        let mut group = c.benchmark_group(format!("{}_in_function", name));
+
+        group.bench_function("tree_walker", |b| {
+            b.iter(|| {
+                let mut interp = tree_walker();
+                Executor::new()
+                    .execute_block(black_box(&block), &mut interp)
+                    .unwrap()
+            })
+        });
+        group.bench_function("vm", |b| {
+            b.iter(|| {
+                let mut interp = LuaInterpreter::new();
+                Vm::new().run(black_box(&chunk), &mut interp).unwrap()
+            })
+        });
+        group.finish();
+    }
+}
+
+fn bench_resolution(c: &mut Criterion) {
+    let block = parse_block(FUNCTION_LOOP);
+    let resolved = resolve(&block);
+    let mut group = c.benchmark_group("function_loop");
+
+    for (name, block) in [("named_scopes", &block), ("resolved_slots", &resolved)] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut interp = tree_walker();
+                Executor::new()
+                    .execute_block(black_box(block), &mut interp)
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_loops, bench_functions, bench_resolution);
+criterion_main!(benches);
@@ -0,0 +1,32 @@
+//! Identifies the Lua parser a build was made with
+//!
+//! The chunk cache keeps parsed chunks on disk between runs, and a chunk is
+//! only valid for the parser that produced it. `MUSCM_PARSER_ID` is a hash
+//! of every file under `src/lua_parser`, so a change to the AST layout or to
+//! how source parses gives the build a new identity, while rebuilding the
+//! same sources keeps it.
+
+use std::path::Path;
+
+fn main() {
+    let dir = Path::new("src/lua_parser");
+    println!("cargo:rerun-if-changed={}", dir.display());
+
+    let mut files: Vec<_> = std::fs::read_dir(dir)
+        .expect("src/lua_parser is readable")
+        .map(|entry| entry.expect("src/lua_parser is readable").path())
+        .collect();
+    files.sort();
+
+    // FNV-1a, as the chunk cache uses for sources
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for file in files {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        let contents = std::fs::read(&file).expect("parser source is readable");
+        for &byte in name.as_bytes().iter().chain(&[0]).chain(&contents) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    println!("cargo:rustc-env=MUSCM_PARSER_ID={:016x}", hash);
+}
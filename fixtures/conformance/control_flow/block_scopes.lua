@@ -0,0 +1,28 @@
+-- locals end with their block; assignments to undeclared names are globals
+
+local x = "outer"
+if true then
+    local x = "inner"
+    created = "global"
+end
+print("if", x, created)
+
+local i = 0
+while i < 2 do
+    i = i + 1
+    local x = i
+end
+print("while", x)
+
+local a, b = 1, 2
+for _, v in ipairs({3}) do
+    a, b = b, v
+end
+print("locals", a, b)
+
+local tries = 0
+repeat
+    local done = tries == 1
+    tries = tries + 1
+until done
+print("repeat", tries)
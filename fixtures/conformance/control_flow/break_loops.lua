@@ -0,0 +1,65 @@
+-- break leaves only the innermost loop, from any nesting of blocks
+
+local i = 0
+while true do
+    i = i + 1
+    if i == 3 then
+        break
+    end
+end
+print("while", i)
+
+local n = 0
+repeat
+    n = n + 1
+    if n == 2 then
+        break
+    end
+until false
+print("repeat", n)
+
+for k = 1, 10 do
+    if k > 4 then
+        break
+    end
+    print("for", k)
+end
+
+for _, v in ipairs({"a", "b", "c", "d"}) do
+    if v == "c" then
+        break
+    end
+    print("ipairs", v)
+end
+
+for outer = 1, 3 do
+    for inner = 1, 3 do
+        if inner == 2 then
+            break
+        end
+        print("nested", outer, inner)
+    end
+end
+
+local steps = 0
+while steps < 10 do
+    steps = steps + 1
+    do
+        if steps == 5 then
+            break
+        end
+    end
+end
+print("do block", steps)
+
+for k = 1, 3 do
+    if k == 1 then
+        print("elseif", k)
+    elseif k == 2 then
+        break
+    else
+        print("unreachable")
+    end
+end
+
+print("after loops")
@@ -0,0 +1,43 @@
+-- each iteration gets fresh control variables
+
+local fns = {}
+for i = 1, 3 do
+    fns[i] = function()
+        return i
+    end
+end
+print("captured", fns[1](), fns[2](), fns[3]())
+
+local count = 0
+for i = 1, 3 do
+    i = i * 10
+    count = count + 1
+end
+print("assigning does not change iterations", count)
+
+local seen = {}
+for k, v in pairs({10, 20}) do
+    seen[#seen + 1] = function()
+        return k + v
+    end
+end
+print("generic captured", seen[1](), seen[2]())
+
+local closures = {}
+local j = 0
+while j < 3 do
+    j = j + 1
+    local copy = j
+    closures[j] = function()
+        return copy
+    end
+end
+print("while locals", closures[1](), closures[2](), closures[3]())
+
+for i = 3, 1, -1 do
+    print("down", i)
+end
+for i = 1, 0 do
+    print("never")
+end
+print("done")
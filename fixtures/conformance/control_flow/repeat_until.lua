@@ -0,0 +1,36 @@
+-- repeat runs its body before testing, and the test sees the body's locals
+
+local runs = 0
+repeat
+    runs = runs + 1
+until true
+print("runs once", runs)
+
+local i = 0
+repeat
+    i = i + 1
+    local done = i >= 3
+until done
+print("body local", i)
+
+-- break skips the condition, which would raise an error here
+local broke = 0
+repeat
+    broke = broke + 1
+    break
+until error("condition evaluated")
+print("break skips until", broke)
+
+local outer = 0
+repeat
+    outer = outer + 1
+    local inner = 0
+    repeat
+        inner = inner + 1
+        if inner == 2 then
+            break
+        end
+    until false
+    print("inner stopped at", inner)
+until outer == 2
+print("outer", outer)
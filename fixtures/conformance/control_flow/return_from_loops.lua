@@ -0,0 +1,70 @@
+-- return leaves the function from any depth of loops and blocks
+
+local function find(list, wanted)
+    for i, v in ipairs(list) do
+        if v == wanted then
+            return i
+        end
+    end
+    return nil
+end
+print("find", find({"x", "y", "z"}, "y"), find({"x"}, "q"))
+
+local function first_pair(limit)
+    for a = 1, limit do
+        for b = a + 1, limit do
+            if a * b == 6 then
+                return a, b
+            end
+        end
+    end
+end
+print("nested for", first_pair(5))
+
+local function count_until(stop)
+    local n = 0
+    while true do
+        n = n + 1
+        repeat
+            if n == stop then
+                return "stopped", n
+            end
+        until true
+    end
+end
+print("while repeat", count_until(4))
+
+local function from_do()
+    do
+        do
+            return "deep"
+        end
+    end
+end
+print("do blocks", from_do())
+
+-- returning from a function called in a loop does not stop the loop
+local function check(k)
+    if k % 2 == 0 then
+        return "even"
+    end
+    return "odd"
+end
+for k = 1, 3 do
+    print("loop continues", k, check(k))
+end
+
+-- nor does breaking inside one
+local function first_big(list)
+    local found
+    for _, v in ipairs(list) do
+        if v > 10 then
+            found = v
+            break
+        end
+    end
+    return found
+end
+for _, list in ipairs({{1, 20, 30}, {5}}) do
+    print("first_big", first_big(list))
+end
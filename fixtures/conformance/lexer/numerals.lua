@@ -0,0 +1,9 @@
+-- decimal and hexadecimal numerals, with fractions and exponents
+
+print("decimal", 10, 007, 2.5, .5, 2E-2, 1.5e1 == 15, 3. == 3)
+print("exponent", 1e15 == 10 ^ 15, 1e15 == 1000000000000000, 5e-1)
+print("hex", 0x10, 0xff, 0XA, 0xA.8p0, 0x.8, 0x1p4 == 16)
+print("wraps", 0xffffffffffffffff == -1)
+print("concat", 1 .. 2, "x" .. 3)
+local t = {10, 20}
+print("index", t[1]..t[2], #t..".")
@@ -0,0 +1,13 @@
+-- short strings with escapes, and long strings taken as written
+
+print("quotes", "a\"b", 'it\'s', "single ' in double", 'double " in single')
+print("escapes", "tab\tend", "\65\x41\u{41}", "\\", #"\0", "\z
+      joined")
+print("line\
+break")
+print([[long
+with "quotes" and \n kept]])
+print([==[
+level ]] and ]=] inside]==])
+print(#[[
+]], #"\r\n")
@@ -0,0 +1,170 @@
+-- Syntax and control structures, in the style of constructs.lua from the
+-- Lua 5.4 test suite. Written for muscm rather than copied from PUC-Lua;
+-- see tests/lua_suite.rs.
+
+print "testing syntax"
+
+-- operator precedence
+assert(2^3^2 == 2^(3^2))
+assert(2^3*4 == (2^3)*4)
+assert(2.0^-2 == 1/4 and -2^- -2 == - - -4)
+assert(not nil and 2 and not(2>3 or 3<2))
+assert(-3-1-5 == 0+0-9)
+assert(-2^2 == -4 and (-2)^2 == 4 and 2*2-3-1 == 0)
+assert(-3%5 == 2 and -3+5 == 2)
+assert(2*1+3/3 == 3 and 1+2 .. 3*1 == "33")
+assert(not(2+1 > 3*1) and "a".."b" > "a")
+assert(1 .. 2 == "12" and "a" .. 1.5 == "a1.5")
+assert(0xF0 | 0xCC ~ 0xAA & 0xFD == 0xF4)
+assert(0xFD & 0xAA ~ 0xCC | 0xF0 == 0xF4)
+assert(0xF0 & 0x0F + 1 == 0x10)
+assert(3^4//2^3//5 == 2)
+assert(-3+4*5//2^3^2//9+4%10/3 == (-3)+(((4*5)//(2^(3^2)))//9)+((4%10)/3))
+assert(not ((true or false) and nil))
+assert(true or false and nil)
+assert((((1 or false) and true) or false) == true)
+assert((((nil and true) or false) and true) == false)
+
+local a, b = 1, nil
+assert(-(1 or 2) == -1 and (1 and 2)+(-1.25 or -4) == 0.75)
+local x = ((b or a)+1 == 2 and (10 or a)+1 == 11); assert(x)
+x = (((2<3) or 1) == true and (2<3 and 4) == 4); assert(x)
+
+-- integer and float arithmetic
+assert(7 // 2 == 3 and -7 // 2 == -4 and 7 // -2 == -4)
+assert(7 % 3 == 1 and -7 % 3 == 2 and 7 % -3 == -2)
+assert(7.5 % 2 == 1.5 and -7.5 % 2 == 0.5)
+assert(1 / 2 == 0.5 and 3 / 1 == 3.0)
+assert(1 << 4 == 16 and 256 >> 4 == 16 and ~0 == -1)
+assert(5 ~ 3 == 6 and 5 & 3 == 1 and 5 | 3 == 7)
+assert("10" + 1 == 11 and 10 .. "" == "10")
+assert(1 == 1.0 and -0.0 == 0.0)
+
+-- comparison
+assert(1 < 2 and 2 <= 2 and not (2 < 2) and 3 > 2 and 3 >= 3)
+assert("a" < "b" and "abc" < "abd" and "" < "a" and not ("b" < "a"))
+assert("Z" < "a" and "10" < "9")
+assert(1 ~= "1" and nil ~= false)
+local t1, t2 = {}, {}
+assert(t1 == t1 and t1 ~= t2)
+
+print "testing control structures"
+
+-- if
+local function sign(n)
+  if n < 0 then return -1 elseif n == 0 then return 0 else return 1 end
+end
+assert(sign(-5) == -1 and sign(0) == 0 and sign(3) == 1)
+
+-- while and break
+local i = 0
+while i < 10 do
+  i = i + 1
+  if i == 5 then break end
+end
+assert(i == 5)
+
+-- repeat sees its body's locals in the condition
+local n = 0
+repeat local done = n >= 3; n = n + 1 until done
+assert(n == 4)
+
+-- numeric for
+local sum = 0
+for k = 1, 10 do sum = sum + k end
+assert(sum == 55)
+sum = 0
+for k = 10, 1, -2 do sum = sum + k end
+assert(sum == 30)
+local count = 0
+for _ = 1, 0 do count = count + 1 end
+assert(count == 0)
+count = 0
+for _ = 1, 2, 0.5 do count = count + 1 end
+assert(count == 3)
+-- the loop variable is a fresh local each time
+local fs = {}
+for k = 1, 3 do fs[k] = function () return k end end
+assert(fs[1]() == 1 and fs[3]() == 3)
+
+-- generic for
+local keys = {}
+for k, v in ipairs({10, 20, 30}) do keys[k] = v end
+assert(#keys == 3 and keys[2] == 20)
+local total = 0
+for _, v in pairs({a = 1, b = 2, c = 3}) do total = total + v end
+assert(total == 6)
+
+-- nested break only leaves the inner loop
+local outer = 0
+for _ = 1, 3 do
+  for j = 1, 10 do
+    if j > 2 then break end
+    outer = outer + 1
+  end
+end
+assert(outer == 6)
+
+-- blocks and shadowing
+local v = 1
+do local v = 2; assert(v == 2) end
+assert(v == 1)
+
+-- multiple assignment evaluates all expressions first
+local p, q = 1, 2
+p, q = q, p
+assert(p == 2 and q == 1)
+local t = {}
+local idx = 1
+idx, t[idx] = idx + 1, 20
+assert(idx == 2 and t[1] == 20)
+
+-- closures share upvalues
+local function counter()
+  local c = 0
+  return function () c = c + 1; return c end, function () return c end
+end
+local inc, get = counter()
+inc(); inc()
+assert(get() == 2)
+
+-- recursion through a local function
+local function fact(m) if m <= 1 then return 1 end return m * fact(m - 1) end
+assert(fact(10) == 3628800)
+
+-- methods
+local obj = {value = 3}
+function obj:add(d) self.value = self.value + d; return self end
+assert(obj:add(2):add(1).value == 6)
+
+-- tables
+local tt = {1, 2, 3, x = "x", ["y"] = "y"; 4}
+assert(#tt == 4 and tt.x == "x" and tt.y == "y")
+tt = {[10] = 10, [1 + 1] = 2}
+assert(tt[10] == 10 and tt[2] == 2 and tt[1] == nil)
+assert(#{} == 0 and #{nil} == 0)
+local nested = {a = {b = {c = 1}}}
+assert(nested.a.b.c == 1)
+
+-- an iterator with no state still gets the control value
+local function range(m)
+  return function (_, last)
+    if last < m then return last + 1 end
+  end, nil, 0
+end
+total = 0
+for v in range(4) do total = total + v end
+assert(total == 10)
+
+-- hexadecimal strings convert too
+assert("0x10" * 2 == 32)
+
+-- goto
+do
+  local k = 1
+  ::top::
+  if k < 3 then k = k + 1; goto top end
+  assert(k == 3)
+end
+
+print "OK"
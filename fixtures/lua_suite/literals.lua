@@ -0,0 +1,65 @@
+-- Lexical elements, in the style of literals.lua from the Lua 5.4 test
+-- suite. Written for muscm rather than copied from PUC-Lua; see
+-- tests/lua_suite.rs.
+
+print "testing scanner"
+
+-- escape sequences
+assert("\n\"'\\" == [[
+
+"'\]])
+assert("\a\b\f\n\r\t\v" == "\7\8\12\10\13\9\11")
+assert("\65\066\0067" == "AB\0067" and #"\0067" == 2)
+assert("\x41\x42" == "AB" and "\x7a" == "z")
+assert("\z
+        abc" == "abc")
+assert("a\z   b" == "ab")
+assert("\u{41}\u{3bb}" == "A\xce\xbb")
+assert("\u{7FFFFFFF}" == "\xFD\xBF\xBF\xBF\xBF\xBF")
+assert(#"\0\0\0" == 3)
+assert('\'' == "'" and "\"" == '"')
+
+-- long strings
+assert([[abc]] == "abc")
+assert([==[a]]b]=]c]==] == "a]]b]=]c")
+assert([[
+first line]] == "first line")
+assert([[a
+b]] == "a\nb")
+assert([=[]]]=] == "]]")
+
+-- comments
+local x = 1 -- line comment
+--[[ long
+comment ]] x = x + 1
+--[==[ another ]] ]=] ]==] x = x + 1
+assert(x == 3)
+
+-- numerals
+assert(0x10 == 16 and 0xff == 255 and 0XA == 10)
+assert(1e2 == 100 and 1E-2 == 0.01 and .5 == 0.5 and 3. == 3)
+assert(0x.8 == 0.5 and 0x1p4 == 16 and 0xA.8p1 == 21)
+assert(2^53 == 9007199254740992)
+assert(tonumber("0x10") == 16 and tonumber("  10  ") == 10)
+assert(tonumber("1e1") == 10 and tonumber("0x1p-1") == 0.5)
+assert(tonumber("") == nil and tonumber("1 2") == nil and tonumber("1a") == nil)
+assert(tonumber("10", 2) == 2 and tonumber("ff", 16) == 255 and tonumber("zz", 36) == 1295)
+assert(tostring(10) == "10" and tostring(-1.5) == "-1.5")
+
+-- names
+local _a1, __ = 1, 2
+assert(_a1 + __ == 3)
+local a_b_c = "ok"
+assert(a_b_c == "ok")
+
+-- semicolons and empty statements
+;;; local y = 1; ; y = y + 1;
+assert(y == 2)
+
+-- chunks through load
+assert(load("return 1 + 1")() == 2)
+assert(load("syntax error here") == nil)
+local f = load("local a, b = ... return a * b")
+assert(f(6, 7) == 42)
+
+print "OK"
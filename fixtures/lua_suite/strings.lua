@@ -0,0 +1,111 @@
+-- The string library, in the style of strings.lua from the Lua 5.4 test
+-- suite. Written for muscm rather than copied from PUC-Lua; see
+-- tests/lua_suite.rs.
+
+print "testing strings and string library"
+
+-- comparison
+assert("alo" < "alo1")
+assert("" < "a")
+assert("alo\0alo" < "alo\0b")
+assert("alo\0alo\0\0" > "alo\0alo\0")
+assert("alo" < "alo\0")
+assert("alo\0" > "alo")
+assert("\0" < "\1")
+assert("\0\0" < "\0\1")
+assert("\1\0a\0a" <= "\1\0a\0a")
+assert(not ("\1\0a\0b" <= "\1\0a\0a"))
+assert("\0\0\0" < "\0\0\0\0")
+assert(not ("\0\0\0\0" < "\0\0\0"))
+assert("\0\0\0" <= "\0\0\0\0")
+assert("\0\0\0" <= "\0\0\0")
+assert("\0\0\0" >= "\0\0\0")
+assert(not ("\0\0b" < "\0\0a\0"))
+
+-- string.sub
+assert(string.sub("123456789", 2, 4) == "234")
+assert(string.sub("123456789", 7) == "789")
+assert(string.sub("123456789", 7, 6) == "")
+assert(string.sub("123456789", 7, 7) == "7")
+assert(string.sub("123456789", 0, 0) == "")
+assert(string.sub("123456789", -10, 10) == "123456789")
+assert(string.sub("123456789", 1, 9) == "123456789")
+assert(string.sub("123456789", -10, -20) == "")
+assert(string.sub("123456789", -1) == "9")
+assert(string.sub("123456789", -4) == "6789")
+assert(string.sub("123456789", -6, -4) == "456")
+assert(string.sub("\000123456789", 3, 5) == "234")
+assert(("\000123456789"):sub(8) == "789")
+
+-- string.len and #
+assert(string.len("") == 0)
+assert(string.len("\0\0\0") == 3)
+assert(string.len("1234567890") == 10)
+assert(#"" == 0)
+assert(#"\0\0\0" == 3)
+assert(#"1234567890" == 10)
+assert(#"\xff\xfe" == 2 and #"\u{3bb}" == 2)
+
+-- upper and lower
+assert(string.upper("ab\0c") == "AB\0C")
+assert(string.lower("\0ABCc%$") == "\0abcc%$")
+assert(("MiXeD"):lower() == "mixed" and ("MiXeD"):upper() == "MIXED")
+
+-- tostring and concatenation
+assert(tostring(-1203) == "-1203")
+assert(tostring(1203.125) == "1203.125")
+assert(tostring(-0.5) == "-0.5")
+assert(tostring(-32767) == "-32767")
+assert(tostring(12) == "12" and tostring(12.0) == "12.0")
+assert(type(tostring(nil)) == "string" and tostring(true) == "true")
+assert("a" .. "b" .. "c" == "abc" and "" .. "" == "")
+assert(1 .. "" == "1" and 1.5 .. "" == "1.5")
+local x = '"\237lo"\n\\'
+assert(#x == 7)
+
+-- string.format
+assert(string.format("%%") == "%")
+assert(string.format("%d", 10) == "10")
+assert(string.format("%5d|%-5d|", 3, 4) == "    3|4    |")
+assert(string.format("%05d", -3) == "-0003")
+assert(string.format("%x %X", 255, 255) == "ff FF")
+assert(string.format("%o", 8) == "10")
+assert(string.format("%c", 65) == "A")
+assert(string.format("%s %s", "a", 1) == "a 1")
+assert(string.format("%10s|%-10s|", "r", "l") == "         r|l         |")
+assert(string.format("%.3s", "abcdef") == "abc")
+assert(string.format("%.2f", 3.14159) == "3.14")
+assert(string.format("%5.1f", 2.25) == "  2.2")
+assert(string.format("%e", 1000) == "1.000000e+03")
+assert(string.format("%g %g", 1e20, 0.1) == "1e+20 0.1")
+assert(string.format("%q", "a\nb\"c\0") == '"a\\\nb\\"c\\0"')
+assert(string.format("%s\0%s", "x", "y") == "x\0y")
+assert(string.format("-%s-", "\0") == "-\0-")
+assert(#string.format("%99d", 1) == 99)
+assert(not pcall(string.format, "%d", 1.5))
+assert(not pcall(string.format, "%d"))
+
+-- the string metatable
+assert(("abc"):len() == 3)
+assert(getmetatable("").__index == string)
+
+-- the rest of the library
+assert(string.sub("123456789", math.mininteger, -4) == "123456")
+assert(string.sub("123456789", math.mininteger, math.maxinteger) == "123456789")
+assert(string.rep("ab", 3) == "ababab")
+assert(string.rep("ab", 3, ",") == "ab,ab,ab")
+assert(string.rep("x", 0) == "")
+assert(string.reverse("abc") == "cba" and string.reverse("") == "")
+assert(string.byte("A") == 65 and string.byte("\255") == 255)
+local b1, b2 = string.byte("hi", 1, -1)
+assert(b1 == 104 and b2 == 105)
+assert(string.char(72, 105) == "Hi" and string.char() == "")
+assert(string.find("hello", "ll") == 3)
+assert(string.find("a.b", ".", 1, true) == 2)
+assert(string.match("key=val", "(%w+)=(%w+)") == "key")
+assert(string.gsub("hello world", "o", "0") == "hell0 w0rld")
+local words = {}
+for w in string.gmatch("one two three", "%a+") do words[#words + 1] = w end
+assert(#words == 3 and words[3] == "three")
+
+print "OK"
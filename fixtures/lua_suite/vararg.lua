@@ -0,0 +1,96 @@
+-- Variable numbers of arguments and results, in the style of vararg.lua
+-- from the Lua 5.4 test suite. Written for muscm rather than copied from
+-- PUC-Lua; see tests/lua_suite.rs.
+
+print "testing vararg"
+
+local function f(a, ...)
+  local x = {n = select('#', ...), ...}
+  for i = 1, x.n do assert(a[i] == x[i]) end
+  return x.n
+end
+
+local function c12(...)
+  assert(arg == _G.arg)   -- no local 'arg'
+  local x = {...}; x.n = #x
+  local res = (x.n == 2 and x[1] == 1 and x[2] == 2)
+  if res then res = 55 end
+  return res, 2
+end
+
+local function vararg(...) return {n = select('#', ...), ...} end
+
+assert(f() == 0)
+assert(f({1, 2, 3}, 1, 2, 3) == 3)
+assert(f({"alo", nil, 45, f, nil}, "alo", nil, 45, f, nil) == 5)
+
+assert(vararg().n == 0)
+assert(vararg(nil, nil).n == 2)
+
+assert(c12(1, 2) == 55)
+local a, b = assert(c12(1, 2))
+assert(a == 55 and b == 2)
+
+-- select
+assert(select('#') == 0 and select('#', nil, nil) == 2)
+assert(select(2, "a", "b", "c") == "b")
+assert(select(-1, "a", "b", "c") == "c")
+local s1, s2 = select(2, "a", "b", "c")
+assert(s1 == "b" and s2 == "c")
+
+-- table.pack and table.unpack
+local p = table.pack(1, nil, 3)
+assert(p.n == 3 and p[1] == 1 and p[2] == nil and p[3] == 3)
+local u1, u2, u3 = table.unpack({1, 2, 3})
+assert(u1 == 1 and u2 == 2 and u3 == 3)
+assert(select('#', table.unpack({1, 2, 3}, 2)) == 2)
+assert(select('#', table.unpack({}, 1, 3)) == 3)
+
+-- only the last expression in a list expands
+local function three() return 1, 2, 3 end
+local t = {three(), three()}
+assert(#t == 4 and t[1] == 1 and t[4] == 3)
+t = {three(), (three())}
+assert(#t == 2)
+local x1, x2, x3, x4 = three(), 10
+assert(x1 == 1 and x2 == 10 and x3 == nil and x4 == nil)
+assert(select('#', three(), three()) == 4)
+assert(select('#', (three())) == 1)
+
+-- varargs passed along
+local function pass(...) return ... end
+assert(select('#', pass()) == 0)
+assert(select('#', pass(nil, nil, nil)) == 3)
+local q1, q2 = pass(three())
+assert(q1 == 1 and q2 == 2)
+
+-- varargs in a nested function call and in returns
+local function sum(...)
+  local total = 0
+  for _, v in ipairs({...}) do total = total + v end
+  return total
+end
+assert(sum() == 0 and sum(1, 2, 3, 4) == 10)
+local function call(g, ...) return g(...) end
+assert(call(sum, 5, 5) == 10)
+assert(call(select, '#', 1, 2) == 2)
+
+-- a fixed parameter list drops extra arguments and fills missing ones
+local function two(a1, a2) return a1, a2 end
+local r1, r2 = two(1)
+assert(r1 == 1 and r2 == nil)
+r1, r2 = two(1, 2, 3)
+assert(r1 == 1 and r2 == 2)
+
+-- many arguments
+local big = {}
+for i = 1, 200 do big[i] = i end
+assert(select('#', table.unpack(big)) == 200)
+assert(sum(table.unpack(big)) == 20100)
+
+-- vararg in the main chunk
+local chunk = load("return select('#', ...), ...")
+local n, first = chunk(7, 8, 9)
+assert(n == 3 and first == 7)
+
+print "OK"
@@ -0,0 +1,3 @@
+local pkg = {}
+pkg.name = "pkg"
+return pkg
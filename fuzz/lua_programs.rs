@@ -0,0 +1,200 @@
+//! Property-based fuzzing of the Lua front end and executors
+//!
+//! Generates random Lua programs, mostly valid but with arbitrary operand
+//! types, and checks that:
+//!
+//! - the tokenizer and parser return errors rather than panicking, even on
+//!   arbitrary text,
+//! - formatting a parsed program and parsing it again gives the same tree,
+//!   and formatting is idempotent,
+//! - running a program on the VM and on the tree-walker never panics; Lua
+//!   errors such as adding a table to a string are fine.
+//!
+//! Generated programs always terminate: loops are numeric `for`s with small
+//! bounds, and functions can only call functions defined before them.
+//!
+//! Run with `cargo test --test fuzz`; set `PROPTEST_CASES` for a longer run.
+//! Failing inputs are shrunk and saved under `fuzz/lua_programs.proptest-regressions`.
+
+use muscm::executor::Executor;
+use muscm::format::{format_block, FormatOptions};
+use muscm::lua_interpreter::LuaInterpreter;
+use muscm::lua_parser::{parse, parse_source, tokenize, Chunk, TokenSlice};
+use muscm::resolver::resolve;
+use muscm::vm::execute_chunk;
+use proptest::prelude::*;
+use proptest::test_runner::FileFailurePersistence;
+
+/// Variables the generated programs read and assign
+const VARIABLES: &[&str] = &["a", "b", "c", "t", "s"];
+
+/// Library functions called with generated arguments
+const CALLS: &[&str] = &[
+    "tostring",
+    "tonumber",
+    "type",
+    "select",
+    "rawlen",
+    "math.floor",
+    "math.abs",
+    "math.max",
+    "string.sub",
+    "string.len",
+    "string.rep",
+    "string.upper",
+    "string.byte",
+    "string.format",
+    "table.concat",
+    "table.insert",
+    "utf8.char",
+    "utf8.codepoint",
+    "utf8.len",
+];
+
+/// Functions every program defines, callable from its statements
+const LOCAL_CALLS: &[&str] = &["f", "g"];
+
+const BINARY_OPS: &[&str] = &[
+    "+", "-", "*", "/", "//", "%", "^", "..", "==", "~=", "<", "<=", ">", ">=", "and", "or",
+];
+
+const UNARY_OPS: &[&str] = &["- ", "not ", "#"];
+
+fn variable() -> impl Strategy<Value = String> {
+    prop::sample::select(VARIABLES).prop_map(str::to_string)
+}
+
+fn literal() -> impl Strategy<Value = String> {
+    prop_oneof![
+        (-5i64..300).prop_map(|n| n.to_string()),
+        (0u32..1000).prop_map(|n| format!("{}.{}", n / 10, n % 10)),
+        "[a-z %dé中]{0,6}".prop_map(|s| format!("'{}'", s)),
+        Just("nil".to_string()),
+        Just("true".to_string()),
+        Just("false".to_string()),
+    ]
+}
+
+/// An expression calling library functions, and `f` and `g` when `locals`
+fn expression_calling(locals: bool) -> impl Strategy<Value = String> {
+    let calls: Vec<&str> = if locals {
+        CALLS.iter().chain(LOCAL_CALLS).copied().collect()
+    } else {
+        CALLS.to_vec()
+    };
+    let leaf = prop_oneof![literal(), variable()];
+    leaf.prop_recursive(4, 32, 4, move |inner| {
+        prop_oneof![
+            (
+                inner.clone(),
+                prop::sample::select(BINARY_OPS),
+                inner.clone()
+            )
+                .prop_map(|(l, op, r)| format!("{} {} {}", l, op, r)),
+            (prop::sample::select(UNARY_OPS), inner.clone())
+                .prop_map(|(op, e)| format!("{}{}", op, e)),
+            inner.clone().prop_map(|e| format!("({})", e)),
+            (
+                prop::sample::select(calls.clone()),
+                prop::collection::vec(inner.clone(), 0..3)
+            )
+                .prop_map(|(f, args)| format!("{}({})", f, args.join(", "))),
+            prop::collection::vec(inner.clone(), 0..3)
+                .prop_map(|items| format!("{{{}}}", items.join(", "))),
+            (variable(), inner.clone()).prop_map(|(k, v)| format!("{{{} = {}}}", k, v)),
+            (variable(), inner.clone()).prop_map(|(t, k)| format!("{}[{}]", t, k)),
+            (variable(), variable()).prop_map(|(t, k)| format!("{}.{}", t, k)),
+        ]
+    })
+}
+
+fn expression() -> impl Strategy<Value = String> {
+    expression_calling(true)
+}
+
+fn statement() -> impl Strategy<Value = String> {
+    let simple = prop_oneof![
+        (variable(), expression()).prop_map(|(v, e)| format!("local {} = {}", v, e)),
+        (variable(), expression()).prop_map(|(v, e)| format!("{} = {}", v, e)),
+        (variable(), expression(), expression())
+            .prop_map(|(t, k, v)| format!("{}[{}] = {}", t, k, v)),
+        (prop::sample::select(LOCAL_CALLS), expression())
+            .prop_map(|(f, e)| format!("{}({})", f, e)),
+        expression().prop_map(|e| format!("local _ = pcall(function() return {} end)", e)),
+    ];
+    simple.prop_recursive(3, 24, 4, |inner| {
+        let block = prop::collection::vec(inner, 0..4).prop_map(|body| body.join("\n"));
+        prop_oneof![
+            (expression(), block.clone(), block.clone())
+                .prop_map(|(c, t, e)| format!("if {} then\n{}\nelse\n{}\nend", c, t, e)),
+            (1i64..4, block.clone())
+                .prop_map(|(n, body)| format!("for i = 1, {} do\n{}\nend", n, body)),
+            (variable(), block.clone()).prop_map(|(v, body)| {
+                format!("for k, {} in pairs({{1, 2, x = 3}}) do\n{}\nend", v, body)
+            }),
+            block.clone().prop_map(|body| format!("do\n{}\nend", body)),
+        ]
+    })
+}
+
+/// A chunk defining `f` and `g`, then running statements and returning
+fn program() -> impl Strategy<Value = String> {
+    (
+        expression_calling(false),
+        expression_calling(false),
+        prop::collection::vec(statement(), 0..8),
+        expression(),
+    )
+        .prop_map(|(f, g, body, result)| {
+            format!(
+                "local function f(x, y) return {}, x end\n\
+                 local function g(...) local n = select('#', ...) return f(n, {}), ... end\n\
+                 {}\n\
+                 return {}",
+                f,
+                g,
+                body.join("\n"),
+                result
+            )
+        })
+}
+
+/// Parse without locations, so trees compare equal across layouts
+fn parse_plain(code: &str) -> Result<Chunk, String> {
+    let tokens = tokenize(code)?;
+    let (_, block) = parse(TokenSlice::from(tokens.as_slice())).map_err(|e| format!("{:?}", e))?;
+    Ok(block)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig {
+        failure_persistence: Some(Box::new(FileFailurePersistence::Direct(
+            "fuzz/lua_programs.proptest-regressions",
+        ))),
+        ..ProptestConfig::default()
+    })]
+
+    #[test]
+    fn front_end_never_panics(code in "\\PC{0,64}") {
+        let _ = parse_plain(&code);
+        let _ = parse_source(&code);
+    }
+
+    #[test]
+    fn format_round_trips(code in program()) {
+        let block = parse_plain(&code).map_err(|e| TestCaseError::fail(format!("{}\n{}", e, code)))?;
+        let options = FormatOptions::default();
+        let formatted = format_block(&block, &options);
+        let reparsed = parse_plain(&formatted)
+            .map_err(|e| TestCaseError::fail(format!("{}\n{}", e, formatted)))?;
+        prop_assert_eq!(&reparsed, &block, "formatted as:\n{}", formatted);
+        prop_assert_eq!(format_block(&reparsed, &options), formatted);
+    }
+
+    #[test]
+    fn executors_never_panic(code in program()) {
+        let block = parse_source(&code).map_err(|e| TestCaseError::fail(format!("{}\n{}", e, code)))?;
+        let _ = execute_chunk(&block, &mut LuaInterpreter::new());
+        let _ = Executor::new().execute_block(&resolve(&block), &mut LuaInterpreter::new());
+    }
+}
@@ -0,0 +1,74 @@
+/*
+ * C API for embedding the muscm Lua interpreter
+ *
+ * Build the shared library with `cargo build --release --features ffi`
+ * and link against libmuscm. Values cross the boundary as JSON text:
+ * tables become arrays or objects, nil becomes null, and functions cannot
+ * cross. Strings returned by the library belong to the caller and are
+ * released with muscm_free_string. A handle must be used from one thread
+ * at a time.
+ */
+#ifndef MUSCM_H
+#define MUSCM_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* An interpreter */
+typedef struct Muscm muscm;
+
+/* One call of a registered host function */
+typedef struct MuscmCall muscm_call;
+
+/*
+ * A host function: gets the arguments as a JSON array, answers through
+ * muscm_return or muscm_error, and returns 0 on success
+ */
+typedef int (*muscm_fn)(void *data, muscm_call *call, const char *args);
+
+/* Create an interpreter with the standard library loaded */
+muscm *muscm_new(void);
+
+/* Free an interpreter; NULL is ignored */
+void muscm_free(muscm *vm);
+
+/* Free a string returned by the library; NULL is ignored */
+void muscm_free_string(char *text);
+
+/*
+ * The message of the last failed call on vm, or NULL; valid until the
+ * next call
+ */
+const char *muscm_last_error(const muscm *vm);
+
+/*
+ * Run a Lua chunk, or evaluate a bare expression, and store its first
+ * value as JSON in *result unless result is NULL. Returns 0 on success
+ * and -1 on failure.
+ */
+int muscm_eval(muscm *vm, const char *source, char **result);
+
+/*
+ * A global as JSON, "null" when unset; NULL when it cannot be converted,
+ * such as a function
+ */
+char *muscm_get_global(muscm *vm, const char *name);
+
+/*
+ * Make function callable from Lua as the global name; data is passed back
+ * to it on every call and must outlive vm. Returns 0 on success.
+ */
+int muscm_register_fn(muscm *vm, const char *name, muscm_fn function, void *data);
+
+/* Set the value a host function returns, from JSON; 0 on success */
+int muscm_return(muscm_call *call, const char *json);
+
+/* Make a host function raise message as a Lua error once it returns */
+void muscm_error(muscm_call *call, const char *message);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MUSCM_H */
@@ -1,8 +1,11 @@
+use crate::scheme_number;
+use num_bigint::BigInt;
+use num_rational::BigRational;
 use std::fmt;
 
 pub type NodeId = usize;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Arena {
     nodes: Vec<SExpr>,
 }
@@ -25,6 +28,28 @@ impl Arena {
     pub fn get_mut(&mut self, id: NodeId) -> Option<&mut SExpr> {
         self.nodes.get_mut(id)
     }
+
+    /// Copy the tree rooted at `id` in `from` into this arena, returning
+    /// the id of the copy, or None if `from` has no such node
+    pub fn copy_from(&mut self, from: &Arena, id: NodeId) -> Option<NodeId> {
+        let copy = match from.get(id)? {
+            SExpr::List(ids) => SExpr::List(self.copy_all(from, ids)?),
+            SExpr::DottedList(ids, tail) => {
+                SExpr::DottedList(self.copy_all(from, ids)?, self.copy_from(from, *tail)?)
+            }
+            SExpr::Vector(ids) => SExpr::Vector(self.copy_all(from, ids)?),
+            SExpr::Quote(id) => SExpr::Quote(self.copy_from(from, *id)?),
+            SExpr::QuasiQuote(id) => SExpr::QuasiQuote(self.copy_from(from, *id)?),
+            SExpr::Unquote(id) => SExpr::Unquote(self.copy_from(from, *id)?),
+            SExpr::UnquoteSplicing(id) => SExpr::UnquoteSplicing(self.copy_from(from, *id)?),
+            leaf => leaf.clone(),
+        };
+        Some(self.alloc(copy))
+    }
+
+    fn copy_all(&mut self, from: &Arena, ids: &[NodeId]) -> Option<Vec<NodeId>> {
+        ids.iter().map(|id| self.copy_from(from, *id)).collect()
+    }
 }
 
 impl Default for Arena {
@@ -36,11 +61,22 @@ impl Default for Arena {
 #[derive(Debug, Clone, PartialEq)]
 pub enum SExpr {
     Atom(String),
+    /// Inexact real literal
     Number(f64),
+    /// Exact integer literal
+    Integer(i64),
+    /// Exact integer literal beyond the i64 range
+    BigInt(BigInt),
+    /// Exact rational literal such as `1/3`
+    Rational(BigRational),
     String(String),
     Bool(bool),
     Char(char),
     List(Vec<NodeId>),
+    /// Improper list `(a b . c)`: the items before the dot and the tail
+    /// after it. The parser folds a list tail into the items, so the tail
+    /// is never a list
+    DottedList(Vec<NodeId>, NodeId),
     Quote(NodeId),
     QuasiQuote(NodeId),
     Unquote(NodeId),
@@ -52,13 +88,10 @@ impl SExpr {
     pub fn display_with_arena(&self, arena: &Arena, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             SExpr::Atom(s) => write!(f, "{}", s),
-            SExpr::Number(n) => {
-                if n.fract() == 0.0 {
-                    write!(f, "{}", *n as i64)
-                } else {
-                    write!(f, "{}", n)
-                }
-            }
+            SExpr::Number(n) => write!(f, "{}", scheme_number::format_real(*n)),
+            SExpr::Integer(n) => write!(f, "{}", n),
+            SExpr::BigInt(n) => write!(f, "{}", n),
+            SExpr::Rational(r) => write!(f, "{}", r),
             SExpr::String(s) => write!(f, "\"{}\"", s),
             SExpr::Bool(b) => write!(f, "#{}", if *b { 't' } else { 'f' }),
             SExpr::Char(c) => write!(f, "#\\{}", c),
@@ -76,6 +109,24 @@ impl SExpr {
                 }
                 write!(f, ")")
             }
+            SExpr::DottedList(ids, tail) => {
+                write!(f, "(")?;
+                for id in ids {
+                    if let Some(item) = arena.get(*id) {
+                        item.display_with_arena(arena, f)?;
+                    } else {
+                        write!(f, "#<invalid>")?;
+                    }
+                    write!(f, " ")?;
+                }
+                write!(f, ". ")?;
+                if let Some(item) = arena.get(*tail) {
+                    item.display_with_arena(arena, f)?;
+                } else {
+                    write!(f, "#<invalid>")?;
+                }
+                write!(f, ")")
+            }
             SExpr::Quote(id) => {
                 write!(f, "'")?;
                 if let Some(node) = arena.get(*id) {
@@ -130,17 +181,14 @@ impl fmt::Display for SExpr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             SExpr::Atom(s) => write!(f, "{}", s),
-            SExpr::Number(n) => {
-                if n.fract() == 0.0 {
-                    write!(f, "{}", *n as i64)
-                } else {
-                    write!(f, "{}", n)
-                }
-            }
+            SExpr::Number(n) => write!(f, "{}", scheme_number::format_real(*n)),
+            SExpr::Integer(n) => write!(f, "{}", n),
+            SExpr::BigInt(n) => write!(f, "{}", n),
+            SExpr::Rational(r) => write!(f, "{}", r),
             SExpr::String(s) => write!(f, "\"{}\"", s),
             SExpr::Bool(b) => write!(f, "#{}", if *b { 't' } else { 'f' }),
             SExpr::Char(c) => write!(f, "#\\{}", c),
-            SExpr::List(_) => {
+            SExpr::List(_) | SExpr::DottedList(..) => {
                 write!(f, "#<node-list>")
             }
             SExpr::Quote(_)
@@ -0,0 +1,187 @@
+/// Machine-readable syntax tree dumps for editors and other tools
+///
+/// The Lua AST serializes directly through serde: each enum variant becomes
+/// an object keyed by the variant name (`{"LocalVars": {...}}`), and unit
+/// variants such as operators become plain strings. A Lua chunk is written
+/// as its arena and the id of its top-level block, so it reads back into
+/// the same chunk. The Scheme arena is flattened into nested objects, since
+/// its node ids mean nothing outside the arena that produced them.
+///
+/// Either JSON tree can also be written as a compact S-expression.
+use crate::ast::{Arena, NodeId, SExpr};
+use crate::lua_parser::Chunk;
+use serde_json::{json, Map, Value};
+
+/// The Lua AST as JSON
+pub fn lua_json(chunk: &Chunk) -> Value {
+    serde_json::to_value(chunk).expect("Lua AST always serializes")
+}
+
+/// Rebuild a Lua AST from `lua_json` output
+pub fn lua_from_json(value: Value) -> Result<Chunk, String> {
+    serde_json::from_value(value).map_err(|e| e.to_string())
+}
+
+/// A Scheme program's top-level forms as a JSON array
+pub fn scheme_json(arena: &Arena, nodes: &[NodeId]) -> Value {
+    Value::Array(nodes.iter().map(|id| scheme_node(arena, *id)).collect())
+}
+
+fn scheme_node(arena: &Arena, id: NodeId) -> Value {
+    let children = |ids: &[NodeId]| -> Value {
+        Value::Array(ids.iter().map(|id| scheme_node(arena, *id)).collect())
+    };
+    match arena.get(id) {
+        None => json!({"type": "invalid", "id": id}),
+        Some(SExpr::Atom(name)) => json!({"type": "symbol", "value": name}),
+        Some(SExpr::Integer(n)) => json!({"type": "integer", "value": n}),
+        Some(SExpr::Number(n)) => json!({"type": "real", "value": n}),
+        // Arbitrary-precision values travel as text to keep every digit
+        Some(SExpr::BigInt(n)) => json!({"type": "integer", "value": n.to_string()}),
+        Some(SExpr::Rational(r)) => json!({"type": "rational", "value": r.to_string()}),
+        Some(SExpr::String(s)) => json!({"type": "string", "value": s}),
+        Some(SExpr::Bool(b)) => json!({"type": "boolean", "value": b}),
+        Some(SExpr::Char(c)) => json!({"type": "char", "value": c.to_string()}),
+        Some(SExpr::List(ids)) => json!({"type": "list", "items": children(ids)}),
+        Some(SExpr::Vector(ids)) => json!({"type": "vector", "items": children(ids)}),
+        Some(SExpr::DottedList(ids, tail)) => {
+            json!({"type": "dotted-list", "items": children(ids), "tail": scheme_node(arena, *tail)})
+        }
+        Some(SExpr::Quote(id)) => json!({"type": "quote", "datum": scheme_node(arena, *id)}),
+        Some(SExpr::QuasiQuote(id)) => {
+            json!({"type": "quasiquote", "datum": scheme_node(arena, *id)})
+        }
+        Some(SExpr::Unquote(id)) => json!({"type": "unquote", "datum": scheme_node(arena, *id)}),
+        Some(SExpr::UnquoteSplicing(id)) => {
+            json!({"type": "unquote-splicing", "datum": scheme_node(arena, *id)})
+        }
+    }
+}
+
+/// Write a JSON tree as an S-expression
+///
+/// Arrays become lists, objects become lists of `(key value)` pairs, and an
+/// object with a single key (an enum variant) becomes `(Key fields...)`.
+pub fn sexp(value: &Value) -> String {
+    let mut out = String::new();
+    write_sexp(value, &mut out);
+    out
+}
+
+fn write_sexp(value: &Value, out: &mut String) {
+    match value {
+        Value::Null => out.push_str("nil"),
+        Value::Bool(b) => out.push_str(if *b { "#t" } else { "#f" }),
+        Value::Number(n) => out.push_str(&n.to_string()),
+        Value::String(s) => out.push_str(&Value::String(s.clone()).to_string()),
+        Value::Array(items) => {
+            out.push('(');
+            write_items(items.iter(), out);
+            out.push(')');
+        }
+        Value::Object(map) if map.len() == 1 => {
+            let (key, inner) = map.iter().next().expect("one entry");
+            out.push('(');
+            out.push_str(key);
+            match inner {
+                // Struct variant: splice its fields
+                Value::Object(fields) if fields.len() > 1 => {
+                    out.push(' ');
+                    write_fields(fields, out);
+                }
+                Value::Array(items) if !items.is_empty() => {
+                    out.push(' ');
+                    write_items(items.iter(), out);
+                }
+                Value::Array(_) => {}
+                other => {
+                    out.push(' ');
+                    write_sexp(other, out);
+                }
+            }
+            out.push(')');
+        }
+        Value::Object(fields) => {
+            out.push('(');
+            write_fields(fields, out);
+            out.push(')');
+        }
+    }
+}
+
+fn write_items<'a>(items: impl Iterator<Item = &'a Value>, out: &mut String) {
+    for (i, item) in items.enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        write_sexp(item, out);
+    }
+}
+
+fn write_fields(fields: &Map<String, Value>, out: &mut String) {
+    for (i, (key, value)) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        out.push('(');
+        out.push_str(key);
+        out.push(' ');
+        write_sexp(value, out);
+        out.push(')');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua_parser::{parse, tokenize, TokenSlice};
+
+    fn lua_chunk(code: &str) -> Chunk {
+        let tokens = tokenize(code).unwrap();
+        parse(TokenSlice::from(tokens.as_slice())).unwrap().1
+    }
+
+    #[test]
+    fn test_lua_json_round_trips() {
+        let chunk = lua_chunk("local t = {a = 1} function t:get(x, ...) return self.a + x end");
+        let value = lua_json(&chunk);
+        let root = &value["arena"]["blocks"][chunk.root];
+        assert_eq!(root["statements"][0]["LocalVars"]["names"], json!(["t"]));
+        assert_eq!(
+            root["statements"][1]["FunctionDecl"]["name"],
+            json!({"path": ["t", "get"], "method": true})
+        );
+        assert_eq!(
+            value["arena"]["functions"][0]["params"],
+            json!(["self", "x"])
+        );
+        assert_eq!(lua_from_json(value).unwrap(), chunk);
+    }
+
+    #[test]
+    fn test_lua_sexp() {
+        let chunk = lua_chunk("x = -y");
+        assert_eq!(
+            sexp(&lua_json(&chunk)),
+            "((arena ((blocks (((statements ((Assignment (variables ((Identifier \"x\"))) \
+             (values ((UnaryOp (op \"Minus\") (operand (Identifier \"y\")))))))) \
+             (return_statement nil)))) (functions ()))) (root 0))"
+        );
+    }
+
+    #[test]
+    fn test_scheme_json_nests_arena_nodes() {
+        let (arena, nodes) = crate::parser::parse("(define x '(1 \"s\"))").unwrap();
+        assert_eq!(
+            scheme_json(&arena, &nodes),
+            json!([{"type": "list", "items": [
+                {"type": "symbol", "value": "define"},
+                {"type": "symbol", "value": "x"},
+                {"type": "quote", "datum": {"type": "list", "items": [
+                    {"type": "integer", "value": 1},
+                    {"type": "string", "value": "s"},
+                ]}},
+            ]}])
+        );
+    }
+}
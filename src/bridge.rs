@@ -0,0 +1,256 @@
+//! Value bridge between the Scheme and Lua interpreters
+//!
+//! Numbers, strings, booleans and nil map directly. Scheme lists and vectors
+//! become sequence tables, and sequence tables come back as lists; other
+//! tables become association lists of `(key value)` pairs. Procedures cross
+//! in both directions as callable proxies that convert their arguments and
+//! results on every call.
+
+use crate::ast::Arena;
+use crate::error_types::{LuaError, LuaResult};
+use crate::executor::{ControlFlow, Executor};
+use crate::interpreter::{Environment, ForeignProc, Interpreter, SVal};
+use crate::lua_interpreter::LuaInterpreter;
+use crate::lua_parser;
+use crate::lua_value::{LuaFunction, LuaTable, LuaValue, TableData};
+use crate::macro_expander::expand_program;
+use crate::parser;
+use crate::resolver;
+use crate::scheme_number;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Maximum nesting of lists/tables converted in one go (guards against cycles)
+const MAX_DEPTH: usize = 100;
+
+/// Convert a Scheme value to Lua. Procedures keep `env` and `arena` so the
+/// resulting Lua function can call back into the Scheme interpreter.
+pub fn sval_to_lua(value: &SVal, env: &Environment, arena: &Arena) -> Result<LuaValue, String> {
+    sval_to_lua_at(value, env, arena, 0)
+}
+
+fn sval_to_lua_at(
+    value: &SVal,
+    env: &Environment,
+    arena: &Arena,
+    depth: usize,
+) -> Result<LuaValue, String> {
+    if depth > MAX_DEPTH {
+        return Err("Value is nested too deeply to convert to Lua".to_string());
+    }
+    match value {
+        SVal::Nil => Ok(LuaValue::Nil),
+        SVal::Bool(b) => Ok(LuaValue::Boolean(*b)),
+        SVal::String(s) | SVal::Atom(s) => Ok(LuaValue::String(s.as_str().into())),
+        SVal::Char(c) => Ok(LuaValue::String(c.to_string().into())),
+        SVal::MutableString(s) => Ok(LuaValue::String(s.borrow().as_str().into())),
+        SVal::List(items) | SVal::Vector(items) => {
+            let mut data = TableData::new();
+            for (i, item) in items.iter().enumerate() {
+                data.insert(
+                    LuaValue::Number((i + 1) as f64),
+                    sval_to_lua_at(item, env, arena, depth + 1)?,
+                );
+            }
+            Ok(LuaValue::Table(Rc::new(RefCell::new(LuaTable {
+                data,
+                metatable: None,
+                frozen: false,
+                removed: None,
+            }))))
+        }
+        SVal::BuiltinProc { .. } | SVal::UserProc { .. } | SVal::Foreign(_) => Ok(
+            scheme_proc_to_lua(value.clone(), env.clone(), arena.clone()),
+        ),
+        SVal::Number(_) | SVal::Integer(_) | SVal::BigInt(_) | SVal::Rational(_) => {
+            scheme_number::to_f64(value)
+                .map(LuaValue::Number)
+                .ok_or_else(|| format!("Cannot convert {} to a Lua number", value))
+        }
+        SVal::DottedList(..) | SVal::Port(_) | SVal::Eof | SVal::Continuation(_) => {
+            Err(format!("Cannot pass {} to Lua", value))
+        }
+    }
+}
+
+/// Wrap a Scheme procedure as a Lua builtin
+fn scheme_proc_to_lua(proc: SVal, env: Environment, arena: Arena) -> LuaValue {
+    let func = move |args: Vec<LuaValue>| -> LuaResult<LuaValue> {
+        let args = args
+            .iter()
+            .map(lua_to_sval)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(LuaError::value)?;
+        let mut env = env.clone();
+        let result = Interpreter::call_function(proc.clone(), args, &mut env, &arena)
+            .map_err(|e| LuaError::runtime(e, "scheme procedure"))?;
+        sval_to_lua(&result, &env, &arena).map_err(LuaError::value)
+    };
+    LuaValue::Function(Rc::new(LuaFunction::Builtin(Rc::new(func))))
+}
+
+/// Convert a Lua value to Scheme
+pub fn lua_to_sval(value: &LuaValue) -> Result<SVal, String> {
+    lua_to_sval_at(value, 0)
+}
+
+fn lua_to_sval_at(value: &LuaValue, depth: usize) -> Result<SVal, String> {
+    if depth > MAX_DEPTH {
+        return Err("Table is nested too deeply to convert to Scheme".to_string());
+    }
+    match value {
+        LuaValue::Nil => Ok(SVal::Nil),
+        LuaValue::Boolean(b) => Ok(SVal::Bool(*b)),
+        // Integral Lua numbers come back as exact Scheme integers
+        LuaValue::Number(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => {
+            Ok(SVal::Integer(*n as i64))
+        }
+        LuaValue::Number(n) => Ok(SVal::Number(*n)),
+        LuaValue::String(s) => Ok(SVal::String(s.to_string())),
+        LuaValue::Table(table) => {
+            let table = table.borrow();
+            let len = table.data.len();
+            let sequence: Option<Vec<&LuaValue>> = (1..=len)
+                .map(|i| table.data.get(&LuaValue::Number(i as f64)))
+                .collect();
+            if let Some(items) = sequence {
+                return items
+                    .into_iter()
+                    .map(|item| lua_to_sval_at(item, depth + 1))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(SVal::List);
+            }
+
+            let mut pairs = table
+                .data
+                .iter()
+                .map(|(k, v)| {
+                    Ok(SVal::List(vec![
+                        lua_to_sval_at(k, depth + 1)?,
+                        lua_to_sval_at(v, depth + 1)?,
+                    ]))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            // Hash order is arbitrary; sort so conversions are deterministic
+            pairs.sort_by_key(|pair| pair.to_string());
+            Ok(SVal::List(pairs))
+        }
+        LuaValue::Function(func) => Ok(lua_function_to_scheme(func.clone())),
+        LuaValue::UserData(_) => Err("Cannot pass Lua userdata to Scheme".to_string()),
+        LuaValue::Thread(_) => Err("Cannot pass a Lua coroutine to Scheme".to_string()),
+    }
+}
+
+/// Wrap a Lua function as a Scheme procedure
+fn lua_function_to_scheme(func: Rc<LuaFunction>) -> SVal {
+    let call = move |args: Vec<SVal>, env: &Environment, arena: &Arena| {
+        let args = args
+            .iter()
+            .map(|arg| sval_to_lua(arg, env, arena))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut interp = LuaInterpreter::new();
+        let result = Executor::new()
+            .call_function(LuaValue::Function(func.clone()), args, &mut interp)
+            .map_err(|e| e.to_string())?;
+        lua_to_sval(&result)
+    };
+    SVal::Foreign(ForeignProc {
+        name: "lua-function".to_string(),
+        func: Rc::new(call),
+    })
+}
+
+/// Run a Lua chunk in a fresh interpreter and return its first return value
+pub fn lua_eval(code: &str) -> Result<SVal, String> {
+    let block = lua_parser::parse_source(code).map_err(|e| format!("Lua: {}", e))?;
+
+    let mut interp = LuaInterpreter::new();
+    match Executor::new()
+        .execute_block(&resolver::resolve(&block), &mut interp)
+        .map_err(|e| e.to_string())?
+    {
+        ControlFlow::Return(values) => lua_to_sval(values.first().unwrap_or(&LuaValue::Nil)),
+        _ => Ok(SVal::Nil),
+    }
+}
+
+/// Evaluate Scheme source in a fresh environment and return the last value
+pub fn scheme_eval(code: &str) -> Result<LuaValue, String> {
+    let (mut arena, nodes) = parser::parse(code).map_err(|e| e.to_string())?;
+    let nodes = expand_program(&mut arena, &nodes)?;
+
+    let mut env = Environment::new();
+    let mut result = SVal::Nil;
+    for node in nodes {
+        let expr = arena.get(node).ok_or("Invalid node reference")?;
+        result = Interpreter::eval(expr, &mut env, &arena)?;
+    }
+    sval_to_lua(&result, &env, &arena)
+}
+
+/// Create the `scheme` table exposed to Lua code
+pub fn create_scheme_table() -> LuaValue {
+    let eval: Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> =
+        Rc::new(|args| match args.first() {
+            Some(LuaValue::String(code)) => {
+                scheme_eval(&code.to_str_lossy()).map_err(|e| LuaError::runtime(e, "scheme.eval"))
+            }
+            Some(other) => Err(LuaError::type_error(
+                "string",
+                other.type_name(),
+                "scheme.eval",
+            )),
+            None => Err(LuaError::arg_count("scheme.eval", 1, 0)),
+        });
+
+    let mut scheme_table = TableData::new();
+    scheme_table.insert(
+        LuaValue::String("eval".into()),
+        LuaValue::Function(Rc::new(LuaFunction::Builtin(eval))),
+    );
+    LuaValue::Table(Rc::new(RefCell::new(LuaTable {
+        data: scheme_table,
+        metatable: None,
+        frozen: false,
+        removed: None,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scalar_roundtrip() {
+        let env = Environment::new();
+        let arena = Arena::new();
+        for value in [
+            SVal::Integer(42),
+            SVal::Number(1.5),
+            SVal::Bool(true),
+            SVal::String("hi".to_string()),
+        ] {
+            let lua = sval_to_lua(&value, &env, &arena).unwrap();
+            assert_eq!(lua_to_sval(&lua).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_list_becomes_sequence_table() {
+        let env = Environment::new();
+        let arena = Arena::new();
+        let list = SVal::List(vec![SVal::Integer(1), SVal::String("two".to_string())]);
+        let lua = sval_to_lua(&list, &env, &arena).unwrap();
+        match &lua {
+            LuaValue::Table(t) => assert_eq!(t.borrow().data.len(), 2),
+            other => panic!("Expected table, got {:?}", other),
+        }
+        assert_eq!(lua_to_sval(&lua).unwrap().to_string(), "(1 \"two\")");
+    }
+
+    #[test]
+    fn test_ports_do_not_cross() {
+        let env = Environment::new();
+        assert!(sval_to_lua(&SVal::Eof, &env, &Arena::new()).is_err());
+    }
+}
@@ -0,0 +1,253 @@
+//! Parsed chunks kept by a hash of their source
+//!
+//! Tokenizing and parsing a large script tree is most of the startup time of
+//! a run. A `ChunkCache` remembers the chunk parsed from each source text it
+//! has seen, so identical sources are parsed once. Given a directory it also
+//! writes every chunk it parses there as JSON, in a file named by a hash of
+//! the text, and reads it back on a later run. A file is trusted only if it
+//! holds the very same source and was written by a build with the same
+//! parser; the hash only picks the file. The disk cache is best effort: a
+//! file that cannot be read or written is treated as missing.
+
+use crate::lua_parser::{parse_source, Chunk};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Bumped whenever the layout of a cache file changes
+const FORMAT: u32 = 3;
+
+/// Hash of the parser's sources, from `build.rs`; chunks parsed by any
+/// other parser may differ even for the same source
+const PARSER: &str = env!("MUSCM_PARSER_ID");
+
+/// Stable across runs and platforms, unlike `std`'s hashers: FNV-1a over
+/// the bytes of the source
+pub fn content_hash(source: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in source.as_bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// What a cache file holds
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    format: u32,
+    /// The parser that wrote the file
+    parser: String,
+    /// The whole source, since different sources can share a hash
+    source: String,
+    chunk: Chunk,
+}
+
+/// How the chunks asked for were found
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub memory_hits: usize,
+    pub disk_hits: usize,
+    /// Chunks that had to be parsed
+    pub misses: usize,
+}
+
+/// Chunks parsed so far, and the directory they are also kept in
+#[derive(Debug, Default)]
+pub struct ChunkCache {
+    enabled: bool,
+    chunks: HashMap<String, Chunk>,
+    dir: Option<PathBuf>,
+    stats: CacheStats,
+}
+
+impl ChunkCache {
+    /// A cache that keeps nothing; every chunk is parsed again
+    pub fn disabled() -> Self {
+        ChunkCache::default()
+    }
+
+    /// A cache that keeps chunks in memory for as long as it lives
+    pub fn in_memory() -> Self {
+        ChunkCache {
+            enabled: true,
+            ..ChunkCache::default()
+        }
+    }
+
+    /// A cache that also keeps chunks as files in `dir`, created when the
+    /// first one is written
+    pub fn with_dir(dir: impl Into<PathBuf>) -> Self {
+        ChunkCache {
+            dir: Some(dir.into()),
+            ..ChunkCache::in_memory()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Forget the chunks kept in memory; files on disk stay
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+    }
+
+    /// The chunk `source` parses to, from the cache when it has been seen
+    /// before; errors are those of `parse_source` and are not cached.
+    /// Chunks from the cache share their arena.
+    pub fn parse(&mut self, source: &str) -> Result<Chunk, String> {
+        if !self.enabled {
+            self.stats.misses += 1;
+            return parse_source(source);
+        }
+        if let Some(chunk) = self.chunks.get(source) {
+            self.stats.memory_hits += 1;
+            return Ok(chunk.clone());
+        }
+        let chunk = match self.read(source) {
+            Some(chunk) => {
+                self.stats.disk_hits += 1;
+                chunk
+            }
+            None => {
+                self.stats.misses += 1;
+                let chunk = parse_source(source)?;
+                self.write(source, &chunk);
+                chunk
+            }
+        };
+        self.chunks.insert(source.to_string(), chunk.clone());
+        Ok(chunk)
+    }
+
+    /// Named for the parser too, so builds sharing a directory keep
+    /// separate files
+    fn path(&self, source: &str) -> Option<PathBuf> {
+        self.dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}-{:016x}.json", PARSER, content_hash(source))))
+    }
+
+    fn read(&self, source: &str) -> Option<Chunk> {
+        let text = std::fs::read_to_string(self.path(source)?).ok()?;
+        let entry: Entry = serde_json::from_str(&text).ok()?;
+        (entry.format == FORMAT && entry.parser == PARSER && entry.source == source)
+            .then_some(entry.chunk)
+    }
+
+    /// Written to a temporary file first, so a run reading the cache at
+    /// the same time never sees half a file
+    fn write(&self, source: &str, chunk: &Chunk) {
+        let (Some(dir), Some(path)) = (&self.dir, self.path(source)) else {
+            return;
+        };
+        let entry = Entry {
+            format: FORMAT,
+            parser: PARSER.to_string(),
+            source: source.to_string(),
+            chunk: chunk.clone(),
+        };
+        let Ok(json) = serde_json::to_string(&entry) else {
+            return;
+        };
+        let partial = path.with_extension(format!("{}.tmp", std::process::id()));
+        let written = std::fs::create_dir_all(dir)
+            .and_then(|_| std::fs::write(&partial, json))
+            .and_then(|_| std::fs::rename(&partial, &path));
+        if written.is_err() {
+            let _ = std::fs::remove_file(&partial);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    const SOURCE: &str = "local function f(x) return x * 2 end\nprint(f(21), 1.5, 'a\\n')";
+
+    #[test]
+    fn test_hash_is_stable() {
+        assert_eq!(content_hash(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(content_hash("a"), 0xaf63_dc4c_8601_ec8c);
+        assert_ne!(content_hash("x = 1"), content_hash("x = 2"));
+    }
+
+    #[test]
+    fn test_memory_cache_parses_once() {
+        let mut cache = ChunkCache::in_memory();
+        let first = cache.parse(SOURCE).unwrap();
+        let second = cache.parse(SOURCE).unwrap();
+        assert!(Rc::ptr_eq(&first.arena, &second.arena));
+        assert_eq!(first, parse_source(SOURCE).unwrap());
+        assert!(cache.parse("x = = 1").is_err());
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                memory_hits: 1,
+                disk_hits: 0,
+                misses: 2
+            }
+        );
+
+        let mut off = ChunkCache::disabled();
+        let first = off.parse(SOURCE).unwrap();
+        assert!(!Rc::ptr_eq(&first.arena, &off.parse(SOURCE).unwrap().arena));
+        assert_eq!(off.stats().misses, 2);
+    }
+
+    #[test]
+    fn test_disk_cache_survives_the_cache() {
+        let dir = std::env::temp_dir().join(format!("muscm_chunks_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut cache = ChunkCache::with_dir(&dir);
+        let parsed = cache.parse(SOURCE).unwrap();
+        let file = cache.path(SOURCE).unwrap();
+        assert!(file.exists());
+
+        let mut later = ChunkCache::with_dir(&dir);
+        assert_eq!(later.parse(SOURCE).unwrap(), parsed);
+        assert_eq!(later.stats().disk_hits, 1);
+
+        // A damaged file is parsed over and replaced
+        std::fs::write(&file, "{").unwrap();
+        let mut again = ChunkCache::with_dir(&dir);
+        assert_eq!(again.parse(SOURCE).unwrap(), parsed);
+        assert_eq!(again.stats().misses, 1);
+        let mut last = ChunkCache::with_dir(&dir);
+        last.parse(SOURCE).unwrap();
+        assert_eq!(last.stats().disk_hits, 1);
+
+        // A file for another source with the same hash is not used
+        let text = std::fs::read_to_string(&file).unwrap();
+        let other = "return 'not the source'";
+        let mut entry: serde_json::Value = serde_json::from_str(&text).unwrap();
+        entry["source"] = other.into();
+        std::fs::write(&file, entry.to_string()).unwrap();
+        let mut collided = ChunkCache::with_dir(&dir);
+        assert_eq!(collided.parse(SOURCE).unwrap(), parsed);
+        assert_eq!(collided.stats().misses, 1);
+
+        // Nor is one written by another parser
+        let mut entry: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+        entry["parser"] = "0000000000000000".into();
+        std::fs::write(&file, entry.to_string()).unwrap();
+        let mut rebuilt = ChunkCache::with_dir(&dir);
+        rebuilt.parse(SOURCE).unwrap();
+        assert_eq!(rebuilt.stats().misses, 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,674 @@
+/// Command-line parsing for the `muscm` binary
+///
+/// ```text
+/// muscm [run] [--lang lua|scheme] [--no-optimize] [--profile] [--coverage FILE] [--cache-dir DIR] [--inspect-result] [--max-depth N] [--strict-globals] [--loop] [--no-vm] (FILE | - | -e CODE) [-- ARGS...]
+/// muscm parse [--ast-dump | --json | --sexp] [--lang lua|scheme] (FILE | - | -e CODE)
+/// muscm tokenize [--lang lua|scheme] (FILE | - | -e CODE)
+/// muscm check [--lang lua|scheme] (FILE | - | -e CODE)
+/// muscm fmt [--indent N] [--quotes double|single] [--width N] (FILE | - | -e CODE)
+/// muscm watch [--keep-globals] [--cache-dir DIR] FILE [-- ARGS...]
+/// muscm debug FILE [-- ARGS...]
+/// muscm test [PATH...]
+/// muscm repl [--lang lua|scheme]
+/// muscm lsp
+/// muscm dap
+/// ```
+///
+/// `check` reports syntax errors for Scheme and also runs the `lint`
+/// pass for Lua. `run` passes Lua chunks through the `optimize` pass
+/// unless `--no-optimize` is given; `--profile` runs a Lua chunk on the
+/// tree-walker with profiling on and prints a timing report, and
+/// `--coverage FILE` writes the lines it ran as an lcov report. `watch` runs a Lua script again each
+/// time it or a module it required changes. With `--cache-dir DIR`, `run`
+/// and `watch` keep the Lua chunks they parse in DIR, keyed by a hash of
+/// their source, and read them back instead of parsing the same text again.
+/// `--inspect-result` prints what a Lua chunk returns, one value per line,
+/// as `inspect` shows it. `--loop` keeps running after a Lua chunk
+/// finishes, firing the timers it queued with `timer.after` and
+/// `timer.every` until none are left. Lua chunks and the functions
+/// they call run on the bytecode VM where the `compiler` covers them, and
+/// on the tree-walker otherwise; `--no-vm` keeps everything on the
+/// tree-walker. `debug` runs a Lua script
+/// under the step debugger, taking commands on stdin. `test` runs the Lua tests in
+/// `*_test.lua` and `spec/*.lua` files under each PATH (default `.`). `lsp` serves the Language Server Protocol on stdio and
+/// needs the `lsp` feature; `dap` serves the Debug Adapter Protocol on
+/// stdio and needs the `dap` feature.
+///
+/// The language comes from `--lang`, else from the file extension, else
+/// defaults to Scheme. A FILE of `-` reads the script from stdin. Arguments
+/// after `--` are handed to the script. Both languages skip a `#!` first
+/// line, so scripts starting with `#!/usr/bin/env muscm` can be run directly.
+use crate::format::{FormatOptions, QuoteStyle};
+use std::path::{Path, PathBuf};
+
+/// Exit code for a script that failed to parse or raised an error
+pub const EXIT_SCRIPT_ERROR: i32 = 1;
+/// Exit code for a malformed command line
+pub const EXIT_USAGE: i32 = 2;
+
+/// Source language of a script
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    Lua,
+    Scheme,
+}
+
+impl Lang {
+    /// Parse a `--lang` value
+    pub fn from_name(name: &str) -> Option<Lang> {
+        match name.to_ascii_lowercase().as_str() {
+            "lua" => Some(Lang::Lua),
+            "scheme" | "scm" => Some(Lang::Scheme),
+            _ => None,
+        }
+    }
+
+    /// Guess the language from a file extension
+    pub fn from_path(path: &Path) -> Option<Lang> {
+        match path.extension()?.to_str()? {
+            "lua" => Some(Lang::Lua),
+            "scm" | "ss" | "sls" | "sps" => Some(Lang::Scheme),
+            _ => None,
+        }
+    }
+}
+
+/// How `parse` prints the syntax tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseOutput {
+    /// Statement count for Lua, the re-printed forms for Scheme
+    Summary,
+    /// Indented tree for reading (`--ast-dump`)
+    Tree,
+    /// JSON for tools (`--json`)
+    Json,
+    /// Compact S-expression (`--sexp`)
+    Sexp,
+}
+
+/// What the binary was asked to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Run,
+    Parse(ParseOutput),
+    Tokenize,
+    Check,
+    /// Print Lua source in canonical layout
+    Fmt(FormatOptions),
+    Repl,
+    /// Run a Lua script again whenever it or its modules change; with
+    /// `keep_globals` each run starts from the globals the last one left
+    Watch {
+        keep_globals: bool,
+    },
+    /// Run a Lua script under the step debugger
+    Debug,
+    /// Run the Lua test files found under `paths`, or under the current
+    /// directory when there are none
+    Test {
+        paths: Vec<PathBuf>,
+    },
+    /// Serve the Language Server Protocol on stdio
+    Lsp,
+    /// Serve the Debug Adapter Protocol on stdio
+    Dap,
+}
+
+/// Where the program text comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    File(PathBuf),
+    Inline(String),
+    /// `-`: the script is read from standard input
+    Stdin,
+}
+
+impl Source {
+    /// Name the script is known by: its path, `-e` for inline code or
+    /// `stdin`
+    pub fn name(&self) -> String {
+        match self {
+            Source::File(path) => path.display().to_string(),
+            Source::Inline(_) => "-e".to_string(),
+            Source::Stdin => "stdin".to_string(),
+        }
+    }
+
+    /// Name of the chunk as Lua's `debug.getinfo` reports it
+    pub fn chunk_name(&self) -> String {
+        match self {
+            Source::File(path) => format!("@{}", path.display()),
+            Source::Inline(_) => "=(command line)".to_string(),
+            Source::Stdin => "=stdin".to_string(),
+        }
+    }
+
+    /// Read the program text
+    pub fn read(&self) -> Result<String, String> {
+        match self {
+            Source::File(path) => std::fs::read_to_string(path)
+                .map_err(|e| format!("Error reading file '{}': {}", path.display(), e)),
+            Source::Inline(code) => Ok(code.clone()),
+            Source::Stdin => std::io::read_to_string(std::io::stdin())
+                .map_err(|e| format!("Error reading stdin: {}", e)),
+        }
+    }
+}
+
+/// A fully parsed command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub command: Command,
+    pub lang: Lang,
+    /// Always `Some` except for `repl`
+    pub source: Option<Source>,
+    /// Arguments after `--`, passed to the script
+    pub script_args: Vec<String>,
+    /// The subcommand and options before `--`, without the script itself;
+    /// Lua sees them, after the program name, at negative `arg` indices
+    pub interpreter_args: Vec<String>,
+    /// Optimize Lua chunks before running them; off with `--no-optimize`
+    pub optimize: bool,
+    /// Print a timing report for a Lua chunk after `run`
+    pub profile: bool,
+    /// Where to write an lcov report of the lines a Lua chunk ran
+    pub coverage: Option<PathBuf>,
+    /// Where to keep parsed Lua chunks between runs
+    pub cache_dir: Option<PathBuf>,
+    /// Print the values a Lua chunk returns after `run`
+    pub inspect_result: bool,
+    /// Most nested Lua calls before a "stack overflow" error
+    pub max_depth: Option<usize>,
+    /// Make reading an undefined Lua global an error instead of nil
+    pub strict_globals: bool,
+    /// Run a Lua chunk's timers after it finishes, until none are left
+    pub run_loop: bool,
+    /// Run Lua code on the bytecode VM where the compiler covers it; off
+    /// with `--no-vm`
+    pub vm: bool,
+}
+
+/// Usage text printed by `--help` and on command-line errors
+pub fn usage(program: &str) -> String {
+    format!(
+        "Usage:
+  {0} [run] [--lang lua|scheme] [--no-optimize] [--profile] [--coverage FILE] [--cache-dir DIR] [--inspect-result] [--max-depth N] [--strict-globals] [--loop] [--no-vm] (FILE | - | -e CODE) [-- ARGS...]
+  {0} parse [--ast-dump | --json | --sexp] [--lang lua|scheme] (FILE | - | -e CODE)
+  {0} tokenize [--lang lua|scheme] (FILE | - | -e CODE)
+  {0} check [--lang lua|scheme] (FILE | - | -e CODE)
+  {0} fmt [--indent N] [--quotes double|single] [--width N] (FILE | - | -e CODE)
+  {0} watch [--keep-globals] [--cache-dir DIR] FILE [-- ARGS...]
+  {0} debug FILE [-- ARGS...]
+  {0} test [PATH...]
+  {0} repl [--lang lua|scheme]
+  {0} lsp
+  {0} dap
+
+The language is taken from --lang, then the file extension (.lua, .scm),
+and defaults to scheme. A FILE of - reads the script from stdin, and a
+first line starting with #! is skipped. Arguments after -- are available
+to the script as the `arg` table in Lua and through (command-line) in
+Scheme.",
+        program
+    )
+}
+
+/// Parse the arguments that follow the program name
+///
+/// Returns `Ok(None)` when help was requested.
+pub fn parse_args(args: &[String]) -> Result<Option<Options>, String> {
+    let mut lang = None;
+    let (mut command, rest) = match args.first().map(String::as_str) {
+        None => (Command::Repl, args),
+        Some("run") => (Command::Run, &args[1..]),
+        Some("parse") => (Command::Parse(ParseOutput::Summary), &args[1..]),
+        Some("tokenize") => (Command::Tokenize, &args[1..]),
+        Some("check") => (Command::Check, &args[1..]),
+        // Only Lua has a formatter, so inline code defaults to it
+        Some("fmt") => {
+            lang = Some(Lang::Lua);
+            (Command::Fmt(FormatOptions::default()), &args[1..])
+        }
+        Some("repl") => (Command::Repl, &args[1..]),
+        // Only Lua scripts can be watched
+        Some("watch") => {
+            lang = Some(Lang::Lua);
+            (
+                Command::Watch {
+                    keep_globals: false,
+                },
+                &args[1..],
+            )
+        }
+        // Only Lua scripts can be debugged
+        Some("debug") => {
+            lang = Some(Lang::Lua);
+            (Command::Debug, &args[1..])
+        }
+        // Test files are Lua scripts
+        Some("test") => {
+            lang = Some(Lang::Lua);
+            (Command::Test { paths: Vec::new() }, &args[1..])
+        }
+        Some("lsp") => (Command::Lsp, &args[1..]),
+        Some("dap") => (Command::Dap, &args[1..]),
+        // `muscm lua FILE` from before subcommands existed
+        Some("lua") => {
+            lang = Some(Lang::Lua);
+            (Command::Run, &args[1..])
+        }
+        // No subcommand: `muscm FILE` runs the file
+        Some(_) => (Command::Run, args),
+    };
+
+    let mut source = None;
+    let mut script_args = Vec::new();
+    let mut interpreter_args = args[..args.len() - rest.len()].to_vec();
+    let mut optimize = true;
+    let mut profile = false;
+    let mut coverage = None;
+    let mut cache_dir = None;
+    let mut inspect_result = false;
+    let mut max_depth = None;
+    let mut strict_globals = false;
+    let mut run_loop = false;
+    let mut vm = true;
+    let mut iter = rest.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--" => {
+                script_args.extend(iter.by_ref().cloned());
+            }
+            "--lang" => {
+                let name = iter.next().ok_or("--lang needs a value")?;
+                interpreter_args.extend([arg.clone(), name.clone()]);
+                lang = Some(Lang::from_name(name).ok_or_else(|| {
+                    format!("unknown language '{}' (expected lua or scheme)", name)
+                })?);
+            }
+            "--no-optimize" => {
+                if command != Command::Run {
+                    return Err("--no-optimize is only valid with run".to_string());
+                }
+                optimize = false;
+                interpreter_args.push(arg.clone());
+            }
+            "--profile" => {
+                if command != Command::Run {
+                    return Err("--profile is only valid with run".to_string());
+                }
+                profile = true;
+                interpreter_args.push(arg.clone());
+            }
+            "--coverage" => {
+                if command != Command::Run {
+                    return Err("--coverage is only valid with run".to_string());
+                }
+                let path = iter.next().ok_or("--coverage needs a report file")?;
+                interpreter_args.extend([arg.clone(), path.clone()]);
+                coverage = Some(PathBuf::from(path));
+            }
+            "--inspect-result" => {
+                if command != Command::Run {
+                    return Err("--inspect-result is only valid with run".to_string());
+                }
+                inspect_result = true;
+                interpreter_args.push(arg.clone());
+            }
+            "--max-depth" => {
+                if command != Command::Run {
+                    return Err("--max-depth is only valid with run".to_string());
+                }
+                let value = iter.next().ok_or("--max-depth needs a value")?;
+                let n = value
+                    .parse()
+                    .map_err(|_| format!("--max-depth needs a number, got '{}'", value))?;
+                interpreter_args.extend([arg.clone(), value.clone()]);
+                max_depth = Some(n);
+            }
+            "--strict-globals" => {
+                if command != Command::Run {
+                    return Err("--strict-globals is only valid with run".to_string());
+                }
+                strict_globals = true;
+                interpreter_args.push(arg.clone());
+            }
+            "--loop" => {
+                if command != Command::Run {
+                    return Err("--loop is only valid with run".to_string());
+                }
+                run_loop = true;
+                interpreter_args.push(arg.clone());
+            }
+            "--no-vm" => {
+                if command != Command::Run {
+                    return Err("--no-vm is only valid with run".to_string());
+                }
+                vm = false;
+                interpreter_args.push(arg.clone());
+            }
+            "--cache-dir" => {
+                if !matches!(command, Command::Run | Command::Watch { .. }) {
+                    return Err("--cache-dir is only valid with run and watch".to_string());
+                }
+                let dir = iter.next().ok_or("--cache-dir needs a directory")?;
+                interpreter_args.extend([arg.clone(), dir.clone()]);
+                cache_dir = Some(PathBuf::from(dir));
+            }
+            "--keep-globals" => match &mut command {
+                Command::Watch { keep_globals } => {
+                    *keep_globals = true;
+                    interpreter_args.push(arg.clone());
+                }
+                _ => return Err("--keep-globals is only valid with watch".to_string()),
+            },
+            "--ast-dump" | "--json" | "--sexp" => match &mut command {
+                Command::Parse(output) => {
+                    *output = match arg.as_str() {
+                        "--ast-dump" => ParseOutput::Tree,
+                        "--json" => ParseOutput::Json,
+                        _ => ParseOutput::Sexp,
+                    };
+                    interpreter_args.push(arg.clone());
+                }
+                _ => return Err(format!("{} is only valid with parse", arg)),
+            },
+            "--indent" | "--quotes" | "--width" => {
+                let Command::Fmt(format) = &mut command else {
+                    return Err(format!("{} is only valid with fmt", arg));
+                };
+                let value = iter
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value", arg))?;
+                interpreter_args.extend([arg.clone(), value.clone()]);
+                match arg.as_str() {
+                    "--quotes" => {
+                        format.quote = match value.as_str() {
+                            "double" => QuoteStyle::Double,
+                            "single" => QuoteStyle::Single,
+                            _ => return Err(format!("unknown quote style '{}'", value)),
+                        }
+                    }
+                    flag => {
+                        let n = value
+                            .parse()
+                            .map_err(|_| format!("{} needs a number, got '{}'", flag, value))?;
+                        if flag == "--indent" {
+                            format.indent = n;
+                        } else {
+                            format.max_width = n;
+                        }
+                    }
+                }
+            }
+            "-e" if matches!(command, Command::Test { .. }) => {
+                return Err("test takes test files or directories, not -e".to_string());
+            }
+            "-e" => {
+                let code = iter.next().ok_or("-e needs a code argument")?;
+                set_source(&mut source, Source::Inline(code.clone()))?;
+            }
+            "-" if matches!(command, Command::Test { .. }) => {
+                return Err("test takes test files or directories, not -".to_string());
+            }
+            "-" => set_source(&mut source, Source::Stdin)?,
+            flag if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(format!("unknown option '{}'", flag));
+            }
+            path => match &mut command {
+                Command::Test { paths } => paths.push(PathBuf::from(path)),
+                _ => set_source(&mut source, Source::File(PathBuf::from(path)))?,
+            },
+        }
+    }
+
+    match (&command, &source) {
+        (Command::Repl, Some(_)) => return Err("repl does not take a script".to_string()),
+        (Command::Repl, None) => {}
+        (Command::Lsp, Some(_)) => return Err("lsp does not take a script".to_string()),
+        (Command::Lsp, None) => {}
+        (Command::Dap, Some(_)) => return Err("dap does not take a script".to_string()),
+        (Command::Dap, None) => {}
+        (Command::Test { .. }, _) => {}
+        (Command::Watch { .. }, Some(Source::Inline(_) | Source::Stdin)) => {
+            return Err("watch needs a script file".to_string())
+        }
+        (Command::Debug, Some(Source::Inline(_) | Source::Stdin)) => {
+            return Err("debug needs a script file".to_string())
+        }
+        (_, None) => return Err("no script given (pass a FILE, - or -e CODE)".to_string()),
+        _ => {}
+    }
+    if !script_args.is_empty()
+        && !matches!(
+            command,
+            Command::Run | Command::Repl | Command::Watch { .. } | Command::Debug
+        )
+    {
+        return Err("script arguments are only valid with run, watch, debug and repl".to_string());
+    }
+
+    let lang = lang
+        .or(match &source {
+            Some(Source::File(path)) => Lang::from_path(path),
+            _ => None,
+        })
+        .unwrap_or(Lang::Scheme);
+
+    Ok(Some(Options {
+        command,
+        lang,
+        source,
+        script_args,
+        interpreter_args,
+        optimize,
+        profile,
+        coverage,
+        cache_dir,
+        inspect_result,
+        max_depth,
+        strict_globals,
+        run_loop,
+        vm,
+    }))
+}
+
+fn set_source(slot: &mut Option<Source>, source: Source) -> Result<(), String> {
+    if slot.is_some() {
+        return Err("only one script may be given".to_string());
+    }
+    *slot = Some(source);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Options>, String> {
+        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        parse_args(&args)
+    }
+
+    #[test]
+    fn test_bare_file_runs_with_detected_lang() {
+        let opts = parse(&["script.lua", "--", "a", "--b"]).unwrap().unwrap();
+        assert_eq!(opts.command, Command::Run);
+        assert_eq!(opts.lang, Lang::Lua);
+        assert_eq!(opts.source, Some(Source::File("script.lua".into())));
+        assert_eq!(opts.script_args, vec!["a", "--b"]);
+        assert!(opts.interpreter_args.is_empty());
+        assert!(opts.optimize);
+    }
+
+    #[test]
+    fn test_dash_reads_the_script_from_stdin() {
+        let opts = parse(&["-", "--lang", "lua"]).unwrap().unwrap();
+        assert_eq!(opts.command, Command::Run);
+        assert_eq!(opts.lang, Lang::Lua);
+        assert_eq!(opts.source, Some(Source::Stdin));
+        assert_eq!(Source::Stdin.name(), "stdin");
+
+        let opts = parse(&["check", "-"]).unwrap().unwrap();
+        assert_eq!(opts.lang, Lang::Scheme);
+        assert!(parse(&["watch", "-"]).is_err());
+        assert!(parse(&["debug", "-"]).is_err());
+        assert!(parse(&["test", "-"]).is_err());
+        assert!(parse(&["run", "-", "a.lua"]).is_err());
+    }
+
+    #[test]
+    fn test_subcommands_and_flags() {
+        let opts = parse(&["parse", "--ast-dump", "x.scm"]).unwrap().unwrap();
+        assert_eq!(opts.command, Command::Parse(ParseOutput::Tree));
+        assert_eq!(opts.lang, Lang::Scheme);
+
+        let opts = parse(&["tokenize", "--lang", "lua", "-e", "x = 1"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(opts.command, Command::Tokenize);
+        assert_eq!(opts.lang, Lang::Lua);
+        assert_eq!(opts.source, Some(Source::Inline("x = 1".into())));
+
+        let opts = parse(&["fmt", "--quotes", "single", "--indent", "2", "-e", "x=1"])
+            .unwrap()
+            .unwrap();
+        let expected = FormatOptions {
+            indent: 2,
+            quote: QuoteStyle::Single,
+            ..FormatOptions::default()
+        };
+        assert_eq!(opts.command, Command::Fmt(expected));
+        assert_eq!(opts.lang, Lang::Lua);
+
+        let opts = parse(&["run", "--no-optimize", "-e", "x = 1"])
+            .unwrap()
+            .unwrap();
+        assert!(!opts.optimize);
+        assert!(!opts.profile);
+        assert!(!opts.inspect_result);
+
+        let opts = parse(&["run", "--inspect-result", "-e", "return 1"])
+            .unwrap()
+            .unwrap();
+        assert!(opts.inspect_result);
+        assert_eq!(opts.max_depth, None);
+
+        let opts = parse(&["run", "--max-depth", "50", "a.lua"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(opts.max_depth, Some(50));
+        assert!(parse(&["run", "--max-depth", "deep", "a.lua"]).is_err());
+        assert!(parse(&["check", "--max-depth", "50", "a.lua"]).is_err());
+        assert!(!opts.strict_globals);
+
+        let opts = parse(&["run", "--strict-globals", "a.lua"])
+            .unwrap()
+            .unwrap();
+        assert!(opts.strict_globals);
+        assert_eq!(opts.interpreter_args, vec!["run", "--strict-globals"]);
+        assert!(parse(&["check", "--strict-globals", "a.lua"]).is_err());
+        assert!(!opts.run_loop);
+
+        let opts = parse(&["run", "--loop", "a.lua"]).unwrap().unwrap();
+        assert!(opts.run_loop);
+        assert_eq!(opts.interpreter_args, vec!["run", "--loop"]);
+        assert!(parse(&["watch", "--loop", "a.lua"]).is_err());
+        assert!(opts.vm);
+
+        let opts = parse(&["run", "--no-vm", "a.lua"]).unwrap().unwrap();
+        assert!(!opts.vm);
+        assert_eq!(opts.interpreter_args, vec!["run", "--no-vm"]);
+        assert!(parse(&["repl", "--no-vm"]).is_err());
+
+        let opts = parse(&["run", "--profile", "a.lua"]).unwrap().unwrap();
+        assert!(opts.profile);
+        assert_eq!(opts.interpreter_args, vec!["run", "--profile"]);
+        assert_eq!(opts.coverage, None);
+
+        let opts = parse(&["run", "--coverage", "lcov.info", "a.lua"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(opts.coverage, Some(PathBuf::from("lcov.info")));
+        assert_eq!(opts.cache_dir, None);
+        assert_eq!(
+            opts.interpreter_args,
+            vec!["run", "--coverage", "lcov.info"]
+        );
+
+        let opts = parse(&["watch", "--cache-dir", "cache", "a.lua"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(opts.cache_dir, Some(PathBuf::from("cache")));
+        assert_eq!(opts.interpreter_args, vec!["watch", "--cache-dir", "cache"]);
+
+        let opts = parse(&["run", "--lang", "lua", "x.txt", "--no-optimize", "--", "a"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            opts.interpreter_args,
+            vec!["run", "--lang", "lua", "--no-optimize"]
+        );
+
+        let opts = parse(&["lua", "old.txt"]).unwrap().unwrap();
+        assert_eq!((opts.command, opts.lang), (Command::Run, Lang::Lua));
+
+        let opts = parse(&[]).unwrap().unwrap();
+        assert_eq!((opts.command, opts.lang), (Command::Repl, Lang::Scheme));
+        let opts = parse(&["lsp"]).unwrap().unwrap();
+        assert_eq!((opts.command, opts.source), (Command::Lsp, None));
+        let opts = parse(&["dap"]).unwrap().unwrap();
+        assert_eq!((opts.command, opts.source), (Command::Dap, None));
+        assert_eq!(parse(&["run", "--help"]), Ok(None));
+
+        let opts = parse(&["watch", "--keep-globals", "app.txt", "--", "x"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(opts.command, Command::Watch { keep_globals: true });
+        assert_eq!(opts.lang, Lang::Lua);
+        assert_eq!(opts.script_args, vec!["x"]);
+
+        let opts = parse(&["debug", "app.txt", "--", "x"]).unwrap().unwrap();
+        assert_eq!((opts.command, opts.lang), (Command::Debug, Lang::Lua));
+        assert_eq!(opts.script_args, vec!["x"]);
+
+        let opts = parse(&["test"]).unwrap().unwrap();
+        assert_eq!(opts.command, Command::Test { paths: Vec::new() });
+        assert_eq!((opts.lang, opts.source), (Lang::Lua, None));
+        let opts = parse(&["test", "spec", "a_test.lua"]).unwrap().unwrap();
+        assert_eq!(
+            opts.command,
+            Command::Test {
+                paths: vec![PathBuf::from("spec"), PathBuf::from("a_test.lua")]
+            }
+        );
+    }
+
+    #[test]
+    fn test_rejects_bad_command_lines() {
+        assert!(parse(&["run"]).is_err());
+        assert!(parse(&["run", "a.lua", "b.lua"]).is_err());
+        assert!(parse(&["run", "--ast-dump", "a.lua"]).is_err());
+        assert!(parse(&["check", "--json", "a.lua"]).is_err());
+        assert!(parse(&["check", "a.lua", "--", "x"]).is_err());
+        assert!(parse(&["lsp", "a.lua"]).is_err());
+        assert!(parse(&["dap", "a.lua"]).is_err());
+        assert!(parse(&["--lang", "cobol", "-e", "1"]).is_err());
+        assert!(parse(&["repl", "a.lua"]).is_err());
+        assert!(parse(&["run", "--indent", "2", "a.lua"]).is_err());
+        assert!(parse(&["fmt", "--width", "wide", "a.lua"]).is_err());
+        assert!(parse(&["check", "--no-optimize", "a.lua"]).is_err());
+        assert!(parse(&["watch", "--profile", "a.lua"]).is_err());
+        assert!(parse(&["check", "--coverage", "lcov.info", "a.lua"]).is_err());
+        assert!(parse(&["run", "a.lua", "--coverage"]).is_err());
+        assert!(parse(&["check", "--cache-dir", "cache", "a.lua"]).is_err());
+        assert!(parse(&["run", "a.lua", "--cache-dir"]).is_err());
+        assert!(parse(&["watch", "--inspect-result", "a.lua"]).is_err());
+        assert!(parse(&["watch", "-e", "x = 1"]).is_err());
+        assert!(parse(&["debug", "-e", "x = 1"]).is_err());
+        assert!(parse(&["run", "--keep-globals", "a.lua"]).is_err());
+        assert!(parse(&["test", "-e", "x = 1"]).is_err());
+        assert!(parse(&["test", "a_test.lua", "--", "x"]).is_err());
+    }
+}
@@ -0,0 +1,959 @@
+/// Bytecode compiler for the Lua executor
+///
+/// Lowers a parsed chunk, or the body of a resolved function, into a flat
+/// list of stack-machine instructions that the `vm` module runs. Locals
+/// live in numbered slots instead of scope hash maps, and control flow
+/// becomes jumps, so hot loops avoid re-walking the AST.
+///
+/// `goto` and labels are not compiled; code that uses them runs on the
+/// tree-walking `Executor` instead (see `vm::execute_chunk`).
+use crate::executor::Executor;
+use crate::lua_parser::{
+    self, BinaryOp, BlockId, Expression, FieldKey, FuncName, FunctionId, FunctionRef, LuaArena,
+    Statement, UnaryOp,
+};
+use crate::lua_value::LuaValue;
+use crate::stack::with_headroom;
+use crate::traceback::CallName;
+use crate::upvalues::find_free_variables;
+use std::rc::Rc;
+
+/// A single VM instruction. Jump targets are absolute instruction indices.
+///
+/// Calls and `...` can leave a variable number of values. Those are "open":
+/// the instruction right after, marked `spread`, takes them all.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr {
+    /// Push `constants[index]`
+    LoadConst(usize),
+    /// Push the value of a local slot
+    GetLocal(usize),
+    /// Pop a value into a local slot
+    SetLocal(usize),
+    /// Pop a value into a local slot as a new `local`, which closures made
+    /// before do not see
+    DeclareLocal(usize),
+    /// `DeclareLocal` for a local in a chunk's outermost scope, which
+    /// later chunks run on the same interpreter see as `names[name]`
+    DeclareNamed { slot: usize, name: usize },
+    /// Push an upvalue of the running function
+    GetUpvalue(usize),
+    /// Pop a value into an upvalue of the running function
+    SetUpvalue(usize),
+    /// Push the variable named `names[index]`: a local of the interpreter's
+    /// scopes, else a global
+    GetName(usize),
+    /// Pop a value into the variable named `names[index]`
+    SetName(usize),
+    /// Push the global `names[index]`
+    GetGlobal(usize),
+    /// Pop a value into the global `names[index]`
+    SetGlobal(usize),
+    /// Pop two operands and push the result
+    Binary(BinaryOp),
+    /// Pop one operand and push the result
+    Unary(UnaryOp),
+    /// Pop a key and a table, push `table[key]`
+    Index,
+    /// Pop a value, a key and a table, and store `table[key] = value`
+    SetIndex,
+    /// Pop the values of a multiple assignment and store them, first to
+    /// last, in `assignments[index]`; the tables and keys of its `Field`
+    /// targets are below the values
+    Assign(usize),
+    /// Push a new table
+    NewTable,
+    /// Pop a value and a key into the table below them
+    TableSet,
+    /// Pop a value into the table below it, at position `index`
+    TableAppend(usize),
+    /// Pop the open values into the table below them, from position
+    /// `index` on
+    TableAppendOpen(usize),
+    /// Push a closure of `closures[index]`
+    Closure(usize),
+    /// Pop the closure of `function declarations[decl]() ... end` and store
+    /// it; `slot` holds the name's first part when that is a local
+    DeclareFunction { decl: usize, slot: Option<usize> },
+    /// Pop an object, push its method named `constants[index]` and then
+    /// the object again, as the first argument
+    Method(usize),
+    /// Pop `argc` arguments, and the open values after them when `spread`
+    /// is set, then the function below them. Push `results` of its values,
+    /// or all of them, open, for `None`. The name is how the call site
+    /// named the function, for tracebacks.
+    Call {
+        argc: usize,
+        spread: bool,
+        results: Option<usize>,
+        name: Option<CallName>,
+    },
+    /// Push `...`, adjusted like a call's values
+    Varargs(Option<usize>),
+    /// Discard the top of the stack
+    Pop,
+    /// Unconditional jump
+    Jump(usize),
+    /// Pop the condition and jump if it is falsy
+    JumpIfFalse(usize),
+    /// Jump if the top is falsy, keeping it; otherwise pop it (`and`)
+    JumpIfFalseOrPop(usize),
+    /// Jump if the top is truthy, keeping it; otherwise pop it (`or`)
+    JumpIfTrueOrPop(usize),
+    /// Pop step, limit and start of a numeric `for` and store its first
+    /// value in the loop variable's `slot`, or jump to `exit` if it never runs
+    ForPrep { slot: usize, exit: usize },
+    /// Advance a numeric `for` and jump back to `body` while it continues
+    ForLoop { slot: usize, body: usize },
+    /// Pop the `count` values of a generic `for`'s expression list, and
+    /// the open values after them when `spread` is set, as the iterables of
+    /// its iterator number `iter`
+    GenericPrep {
+        iter: usize,
+        count: usize,
+        spread: bool,
+    },
+    /// Store the next values of iterator `iter` in the slots
+    /// `slot_lists[vars]`, or jump to `exit` when it is done
+    GenericLoop {
+        iter: usize,
+        vars: usize,
+        exit: usize,
+    },
+    /// Return the top `count` values, and the open values after them when
+    /// `spread` is set
+    Return { count: usize, spread: bool },
+}
+
+/// Where one value of a multiple assignment goes
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Local(usize),
+    Upvalue(usize),
+    /// `names[index]`, as `SetName` stores it
+    Name(usize),
+    /// The global `names[index]`
+    Global(usize),
+    /// A table and key evaluated before the values
+    Field,
+}
+
+/// A function definition and the variables its closures capture by name
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClosureSpec {
+    pub function: FunctionId,
+    /// Each name the body refers to that is not its own parameter, with
+    /// the slot holding it when it is a local of the chunk; other names are
+    /// looked up in the interpreter's scopes when the closure is made
+    pub captures: Vec<(String, Option<usize>)>,
+}
+
+/// Compiled form of a chunk or function body
+#[derive(Debug, Clone, Default)]
+pub struct Chunk {
+    pub code: Vec<Instr>,
+    pub constants: Vec<LuaValue>,
+    /// Names of non-local variables referenced by name
+    pub names: Vec<String>,
+    /// Number of local slots the code needs
+    pub slot_count: usize,
+    /// Source line of the statement each instruction belongs to, parallel
+    /// to `code`; 0 when the chunk was parsed without locations
+    pub lines: Vec<usize>,
+    /// Targets of each multiple assignment, for `Assign`
+    pub assignments: Vec<Vec<Target>>,
+    /// Function definitions, for `Closure`
+    pub closures: Vec<ClosureSpec>,
+    /// Names of `function a.b()` declarations, for `DeclareFunction`
+    pub declarations: Vec<FuncName>,
+    /// Variable slots of each generic `for`, for `GenericLoop`
+    pub slot_lists: Vec<Vec<usize>>,
+    /// Number of generic `for` loops, each with an iterator of its own
+    pub iterators: usize,
+    /// Arena of a main chunk, holding the bodies its closures run; `None`
+    /// for a function body, whose closure knows its arena
+    pub arena: Option<Rc<LuaArena>>,
+}
+
+/// Compile a chunk, or describe the first construct the VM does not support
+///
+/// Functions defined in the chunk are compiled separately, when they are
+/// first called, and only once the resolver has given them a frame layout.
+pub fn compile(source: &lua_parser::Chunk) -> Result<Chunk, String> {
+    let mut compiler = Compiler {
+        arena: Rc::clone(&source.arena),
+        main: true,
+        ..Compiler::default()
+    };
+    compiler.block(source.root)?;
+    compiler.emit(Instr::Return {
+        count: 0,
+        spread: false,
+    });
+    compiler.chunk.arena = Some(Rc::clone(&source.arena));
+    Ok(compiler.chunk)
+}
+
+/// Compile the body of a resolved function, whose locals are already slots
+pub(crate) fn compile_function(body: &FunctionRef) -> Result<Chunk, String> {
+    let layout = body.layout.as_ref().ok_or("unresolved function")?;
+    let mut compiler = Compiler {
+        arena: Rc::clone(&body.arena),
+        ..Compiler::default()
+    };
+    compiler.chunk.slot_count = layout.slot_count;
+    compiler.block(body.block)?;
+    compiler.emit(Instr::Return {
+        count: 0,
+        spread: false,
+    });
+    Ok(compiler.chunk)
+}
+
+/// Whether an expression can produce several values
+fn is_multi(expr: &Expression) -> bool {
+    matches!(
+        expr,
+        Expression::FunctionCall { .. } | Expression::MethodCall { .. } | Expression::Varargs
+    )
+}
+
+#[derive(Default)]
+struct Compiler {
+    chunk: Chunk,
+    /// Arena of the code being compiled
+    arena: Rc<LuaArena>,
+    /// Compiling a main chunk, whose locals are named, rather than a
+    /// resolved function body
+    main: bool,
+    /// Lexical scopes of `(name, slot)` bindings, innermost last
+    scopes: Vec<Vec<(String, usize)>>,
+    next_slot: usize,
+    /// Pending `break` jumps for each enclosing loop
+    breaks: Vec<Vec<usize>>,
+    /// Line of the statement being compiled
+    line: usize,
+}
+
+impl Compiler {
+    fn emit(&mut self, instr: Instr) -> usize {
+        self.chunk.code.push(instr);
+        self.chunk.lines.push(self.line);
+        self.chunk.code.len() - 1
+    }
+
+    fn here(&self) -> usize {
+        self.chunk.code.len()
+    }
+
+    /// Point the jump at `at` to the next instruction
+    fn patch(&mut self, at: usize) {
+        let target = self.here();
+        match &mut self.chunk.code[at] {
+            Instr::Jump(t)
+            | Instr::JumpIfFalse(t)
+            | Instr::JumpIfFalseOrPop(t)
+            | Instr::JumpIfTrueOrPop(t)
+            | Instr::ForPrep { exit: t, .. }
+            | Instr::GenericLoop { exit: t, .. } => *t = target,
+            other => unreachable!("cannot patch {:?}", other),
+        }
+    }
+
+    fn constant(&mut self, value: LuaValue) -> usize {
+        if let Some(i) = self.chunk.constants.iter().position(|c| c == &value) {
+            return i;
+        }
+        self.chunk.constants.push(value);
+        self.chunk.constants.len() - 1
+    }
+
+    fn name(&mut self, name: &str) -> usize {
+        if let Some(i) = self.chunk.names.iter().position(|n| n == name) {
+            return i;
+        }
+        self.chunk.names.push(name.to_string());
+        self.chunk.names.len() - 1
+    }
+
+    /// Give `name` a slot in the innermost scope
+    fn declare(&mut self, name: &str) -> Result<usize, String> {
+        // A resolved body's slots are numbered by the resolver
+        if !self.main {
+            return Err("named locals in a resolved function".to_string());
+        }
+        let slot = self.next_slot;
+        self.next_slot += 1;
+        self.chunk.slot_count = self.chunk.slot_count.max(self.next_slot);
+        self.scopes
+            .last_mut()
+            .expect("locals are declared inside a scope")
+            .push((name.to_string(), slot));
+        Ok(slot)
+    }
+
+    /// Pop the value of the local `name` just declared in `slot`
+    fn emit_declare(&mut self, name: &str, slot: usize) {
+        if self.scopes.len() == 1 {
+            let name = self.name(name);
+            self.emit(Instr::DeclareNamed { slot, name });
+        } else {
+            self.emit(Instr::DeclareLocal(slot));
+        }
+    }
+
+    fn resolve(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(n, _)| n == name)
+            .map(|(_, slot)| *slot)
+    }
+
+    /// Compile `block` in a new lexical scope
+    fn block(&mut self, block: BlockId) -> Result<(), String> {
+        self.scoped(|c| c.block_body(block))
+    }
+
+    fn scoped(&mut self, f: impl FnOnce(&mut Self) -> Result<(), String>) -> Result<(), String> {
+        let saved_slot = self.next_slot;
+        self.scopes.push(Vec::new());
+        let result = f(self);
+        self.scopes.pop();
+        self.next_slot = saved_slot;
+        result
+    }
+
+    fn block_body(&mut self, block: BlockId) -> Result<(), String> {
+        let arena = Rc::clone(&self.arena);
+        let block = arena.block(block);
+        // What follows the block belongs to the statement around it
+        let outer_line = self.line;
+        for (i, statement) in block.statements.iter().enumerate() {
+            self.line = block.lines.get(i).copied().unwrap_or(0);
+            self.statement(statement)?;
+        }
+        if let Some(ret) = &block.return_statement {
+            self.line = block
+                .lines
+                .get(block.statements.len())
+                .copied()
+                .unwrap_or(0);
+            let (count, spread) = self.expression_list(&ret.expression_list)?;
+            self.emit(Instr::Return { count, spread });
+        }
+        self.line = outer_line;
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), String> {
+        with_headroom(|| self.statement_node(statement))
+    }
+
+    fn statement_node(&mut self, statement: &Statement) -> Result<(), String> {
+        match statement {
+            Statement::Empty => Ok(()),
+            Statement::LocalVars { names, values } => {
+                let values = values.as_deref().unwrap_or(&[]);
+                self.expressions_exact(values, names.len())?;
+                // Declare after evaluating so `local x = x` reads the outer x
+                let mut slots = Vec::with_capacity(names.len());
+                for name in names {
+                    slots.push(self.declare(name)?);
+                }
+                for (name, slot) in names.iter().zip(slots).rev() {
+                    self.emit_declare(name, slot);
+                }
+                Ok(())
+            }
+            Statement::LocalSlots { slots, values } => {
+                let values = values.as_deref().unwrap_or(&[]);
+                self.expressions_exact(values, slots.len())?;
+                for slot in slots.iter().rev() {
+                    self.emit(Instr::DeclareLocal(*slot));
+                }
+                Ok(())
+            }
+            Statement::Assignment { variables, values } => self.assignment(variables, values),
+            Statement::FunctionCall(call) => self.call(call, Some(0)),
+            Statement::Do(body) => self.block(*body),
+            Statement::While { condition, body } => {
+                let start = self.here();
+                self.expression(condition)?;
+                let exit = self.emit(Instr::JumpIfFalse(0));
+                self.loop_body(|c| c.block(*body))?;
+                self.emit(Instr::Jump(start));
+                self.patch(exit);
+                self.patch_breaks();
+                Ok(())
+            }
+            Statement::Repeat { body, condition } => {
+                let start = self.here();
+                // The condition can see locals declared in the body
+                self.loop_body(|c| {
+                    c.scoped(|c| {
+                        c.block_body(*body)?;
+                        c.expression(condition)
+                    })
+                })?;
+                self.emit(Instr::JumpIfFalse(start));
+                self.patch_breaks();
+                Ok(())
+            }
+            Statement::If {
+                condition,
+                then_block,
+                elseif_parts,
+                else_block,
+            } => {
+                let mut end_jumps = Vec::new();
+                let branches = std::iter::once((condition, *then_block))
+                    .chain(elseif_parts.iter().map(|(c, b)| (c, *b)));
+                for (cond, block) in branches {
+                    self.expression(cond)?;
+                    let next = self.emit(Instr::JumpIfFalse(0));
+                    self.block(block)?;
+                    end_jumps.push(self.emit(Instr::Jump(0)));
+                    self.patch(next);
+                }
+                if let Some(block) = else_block {
+                    self.block(*block)?;
+                }
+                for jump in end_jumps {
+                    self.patch(jump);
+                }
+                Ok(())
+            }
+            Statement::ForNumeric {
+                var,
+                start,
+                end,
+                step,
+                body,
+            } => self.scoped(|c| {
+                // The VM keeps the counter; the body only sees the variable
+                let slot = c.next_slot;
+                c.numeric_for(slot, start, end, step.as_ref(), |c| {
+                    let declared = c.declare(var)?;
+                    debug_assert_eq!(declared, slot);
+                    c.block_body(*body)
+                })
+            }),
+            Statement::ForNumericSlot {
+                slot,
+                start,
+                end,
+                step,
+                body,
+            } => self.numeric_for(*slot, start, end, step.as_ref(), |c| c.block_body(*body)),
+            Statement::ForGeneric {
+                vars,
+                iterables,
+                body,
+            } => self.scoped(|c| {
+                let first = c.next_slot;
+                let slots: Vec<usize> = (first..first + vars.len()).collect();
+                c.generic_for(slots, iterables, |c| {
+                    for var in vars {
+                        c.declare(var)?;
+                    }
+                    c.block_body(*body)
+                })
+            }),
+            Statement::ForGenericSlots {
+                slots,
+                iterables,
+                body,
+            } => self.generic_for(slots.clone(), iterables, |c| c.block_body(*body)),
+            Statement::Break => {
+                let jump = self.emit(Instr::Jump(0));
+                self.breaks
+                    .last_mut()
+                    .ok_or("break outside a loop")?
+                    .push(jump);
+                Ok(())
+            }
+            Statement::Label(_) | Statement::Goto(_) => Err("goto".to_string()),
+            Statement::FunctionDecl { name, body } => {
+                self.closure(*body);
+                let slot = self.resolve(name.base());
+                let decl = self.chunk.declarations.len();
+                self.chunk.declarations.push(name.clone());
+                self.emit(Instr::DeclareFunction { decl, slot });
+                Ok(())
+            }
+            Statement::LocalFunction { name, body } => {
+                // Bind the name first so the body captures it and can recurse
+                self.load(LuaValue::Nil);
+                let slot = self.declare(name)?;
+                self.emit_declare(name, slot);
+                self.closure(*body);
+                self.emit(Instr::SetLocal(slot));
+                Ok(())
+            }
+        }
+    }
+
+    /// A numeric `for` whose variable is `slot`; `body` compiles the loop
+    /// body in a scope of its own
+    fn numeric_for(
+        &mut self,
+        slot: usize,
+        start: &Expression,
+        end: &Expression,
+        step: Option<&Expression>,
+        body: impl FnOnce(&mut Self) -> Result<(), String>,
+    ) -> Result<(), String> {
+        self.expression(start)?;
+        self.expression(end)?;
+        match step {
+            Some(step) => self.expression(step)?,
+            None => self.load(LuaValue::Number(1.0)),
+        }
+        let prep = self.emit(Instr::ForPrep { slot, exit: 0 });
+        let body_start = self.here();
+        self.loop_body(|c| c.scoped(body))?;
+        self.emit(Instr::ForLoop {
+            slot,
+            body: body_start,
+        });
+        self.patch(prep);
+        self.patch_breaks();
+        Ok(())
+    }
+
+    /// A generic `for` binding `slots` on each pass; `body` compiles the
+    /// loop body in a scope of its own
+    fn generic_for(
+        &mut self,
+        slots: Vec<usize>,
+        iterables: &[Expression],
+        body: impl FnOnce(&mut Self) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let (count, spread) = self.expression_list(iterables)?;
+        let iter = self.chunk.iterators;
+        self.chunk.iterators += 1;
+        self.emit(Instr::GenericPrep {
+            iter,
+            count,
+            spread,
+        });
+        let vars = self.chunk.slot_lists.len();
+        self.chunk.slot_lists.push(slots);
+        let next = self.emit(Instr::GenericLoop {
+            iter,
+            vars,
+            exit: 0,
+        });
+        self.loop_body(|c| c.scoped(body))?;
+        self.emit(Instr::Jump(next));
+        self.patch(next);
+        self.patch_breaks();
+        Ok(())
+    }
+
+    fn loop_body(&mut self, f: impl FnOnce(&mut Self) -> Result<(), String>) -> Result<(), String> {
+        self.breaks.push(Vec::new());
+        f(self)
+    }
+
+    /// Patch the breaks of the innermost loop to jump here
+    fn patch_breaks(&mut self) {
+        for jump in self.breaks.pop().unwrap_or_default() {
+            self.patch(jump);
+        }
+    }
+
+    /// Tables and keys on the left first, then the values, then the
+    /// stores, as the tree-walker does
+    fn assignment(
+        &mut self,
+        variables: &[Expression],
+        values: &[Expression],
+    ) -> Result<(), String> {
+        if let [target] = variables {
+            let store = match target {
+                Expression::TableIndexing { object, index } => {
+                    self.expression(object)?;
+                    self.expression(index)?;
+                    Instr::SetIndex
+                }
+                Expression::FieldAccess { object, field } => {
+                    self.expression(object)?;
+                    self.load(LuaValue::String(field.clone().into()));
+                    Instr::SetIndex
+                }
+                _ => match self.target(target)? {
+                    Target::Local(slot) => Instr::SetLocal(slot),
+                    Target::Upvalue(index) => Instr::SetUpvalue(index),
+                    Target::Name(index) => Instr::SetName(index),
+                    Target::Global(index) => Instr::SetGlobal(index),
+                    Target::Field => unreachable!("fields are handled above"),
+                },
+            };
+            self.expressions_exact(values, 1)?;
+            self.emit(store);
+            return Ok(());
+        }
+
+        let mut targets = Vec::with_capacity(variables.len());
+        for target in variables {
+            match target {
+                Expression::TableIndexing { object, index } => {
+                    self.expression(object)?;
+                    self.expression(index)?;
+                    targets.push(Target::Field);
+                }
+                Expression::FieldAccess { object, field } => {
+                    self.expression(object)?;
+                    self.load(LuaValue::String(field.clone().into()));
+                    targets.push(Target::Field);
+                }
+                _ => targets.push(self.target(target)?),
+            }
+        }
+        self.expressions_exact(values, variables.len())?;
+        let index = self.chunk.assignments.len();
+        self.chunk.assignments.push(targets);
+        self.emit(Instr::Assign(index));
+        Ok(())
+    }
+
+    /// Where an assignment to a variable stores its value
+    fn target(&mut self, target: &Expression) -> Result<Target, String> {
+        Ok(match target {
+            Expression::Identifier(name) => match self.resolve(name) {
+                Some(slot) => Target::Local(slot),
+                None => Target::Name(self.name(name)),
+            },
+            Expression::Local { slot, .. } => Target::Local(*slot),
+            Expression::Upvalue { index, .. } => Target::Upvalue(*index),
+            Expression::Global(name) => Target::Global(self.name(name)),
+            _ => return Err("invalid assignment target".to_string()),
+        })
+    }
+
+    /// Push the values of an expression list, the last expression's all
+    /// left open; returns how many are pushed before those and whether
+    /// there are open ones
+    fn expression_list(&mut self, exprs: &[Expression]) -> Result<(usize, bool), String> {
+        let Some((last, init)) = exprs.split_last() else {
+            return Ok((0, false));
+        };
+        for expr in init {
+            self.expression(expr)?;
+        }
+        if is_multi(last) {
+            self.call(last, None)?;
+            Ok((init.len(), true))
+        } else {
+            self.expression(last)?;
+            Ok((exprs.len(), false))
+        }
+    }
+
+    /// Push exactly `count` values, padding with nil or dropping extras
+    fn expressions_exact(&mut self, exprs: &[Expression], count: usize) -> Result<(), String> {
+        let Some((last, init)) = exprs.split_last() else {
+            for _ in 0..count {
+                self.load(LuaValue::Nil);
+            }
+            return Ok(());
+        };
+        for expr in init {
+            self.expression(expr)?;
+        }
+        if is_multi(last) {
+            // The last expression fills whatever the others leave
+            self.call(last, Some(count.saturating_sub(init.len())))?;
+            for _ in count..init.len() {
+                self.emit(Instr::Pop);
+            }
+            return Ok(());
+        }
+        self.expression(last)?;
+        for _ in count..exprs.len() {
+            self.emit(Instr::Pop);
+        }
+        for _ in exprs.len()..count {
+            self.load(LuaValue::Nil);
+        }
+        Ok(())
+    }
+
+    /// A call, method call or `...`, adjusted to `results` values, or left
+    /// open for `None`
+    fn call(&mut self, expr: &Expression, results: Option<usize>) -> Result<(), String> {
+        match expr {
+            Expression::FunctionCall { function, args } => {
+                self.expression(function)?;
+                let (argc, spread) = self.expression_list(args)?;
+                self.emit(Instr::Call {
+                    argc,
+                    spread,
+                    results,
+                    name: Executor::call_name(function),
+                });
+            }
+            Expression::MethodCall {
+                object,
+                method,
+                args,
+            } => {
+                self.expression(object)?;
+                let key = self.constant(LuaValue::String(method.clone().into()));
+                self.emit(Instr::Method(key));
+                let (argc, spread) = self.expression_list(args)?;
+                self.emit(Instr::Call {
+                    argc: argc + 1,
+                    spread,
+                    results,
+                    name: Some(CallName::Method(method.clone())),
+                });
+            }
+            Expression::Varargs => {
+                self.emit(Instr::Varargs(results));
+            }
+            _ => unreachable!("only calls and `...` have several values"),
+        }
+        Ok(())
+    }
+
+    /// Push a closure of the function `body`
+    fn closure(&mut self, body: FunctionId) {
+        let captures = find_free_variables(&self.arena, self.arena.function(body))
+            .into_iter()
+            .map(|name| {
+                let slot = self.resolve(&name);
+                (name, slot)
+            })
+            .collect();
+        let index = self.chunk.closures.len();
+        self.chunk.closures.push(ClosureSpec {
+            function: body,
+            captures,
+        });
+        self.emit(Instr::Closure(index));
+    }
+
+    fn expression(&mut self, expr: &Expression) -> Result<(), String> {
+        with_headroom(|| self.expression_node(expr))
+    }
+
+    fn expression_node(&mut self, expr: &Expression) -> Result<(), String> {
+        match expr {
+            Expression::Nil => self.load(LuaValue::Nil),
+            Expression::Boolean(b) => self.load(LuaValue::Boolean(*b)),
+            Expression::Number(n) => self.load(LuaValue::Number(n.to_f64())),
+            Expression::String(s) => self.load(LuaValue::String(s.clone())),
+            Expression::Identifier(name) => {
+                match self.resolve(name) {
+                    Some(slot) => self.emit(Instr::GetLocal(slot)),
+                    None => {
+                        let index = self.name(name);
+                        self.emit(Instr::GetName(index))
+                    }
+                };
+            }
+            Expression::Local { slot, .. } => {
+                self.emit(Instr::GetLocal(*slot));
+            }
+            Expression::Upvalue { index, .. } => {
+                self.emit(Instr::GetUpvalue(*index));
+            }
+            Expression::Global(name) => {
+                let index = self.name(name);
+                self.emit(Instr::GetGlobal(index));
+            }
+            Expression::BinaryOp { left, op, right } => {
+                self.expression(left)?;
+                match op {
+                    BinaryOp::And | BinaryOp::Or => {
+                        let jump = if *op == BinaryOp::And {
+                            self.emit(Instr::JumpIfFalseOrPop(0))
+                        } else {
+                            self.emit(Instr::JumpIfTrueOrPop(0))
+                        };
+                        self.expression(right)?;
+                        self.patch(jump);
+                    }
+                    _ => {
+                        self.expression(right)?;
+                        self.emit(Instr::Binary(op.clone()));
+                    }
+                }
+            }
+            Expression::UnaryOp { op, operand } => {
+                self.expression(operand)?;
+                self.emit(Instr::Unary(op.clone()));
+            }
+            Expression::TableIndexing { object, index } => {
+                self.expression(object)?;
+                self.expression(index)?;
+                self.emit(Instr::Index);
+            }
+            Expression::FieldAccess { object, field } => {
+                self.expression(object)?;
+                self.load(LuaValue::String(field.clone().into()));
+                self.emit(Instr::Index);
+            }
+            Expression::FunctionCall { .. }
+            | Expression::MethodCall { .. }
+            | Expression::Varargs => self.call(expr, Some(1))?,
+            // Calls and `...` are already adjusted to one value here
+            Expression::Paren(inner) => self.expression(inner)?,
+            Expression::TableConstructor { fields } => {
+                self.emit(Instr::NewTable);
+                for (i, field) in fields.iter().enumerate() {
+                    match &field.key {
+                        FieldKey::Bracket(key) => {
+                            self.expression(key)?;
+                            self.expression(&field.value)?;
+                            self.emit(Instr::TableSet);
+                        }
+                        FieldKey::Identifier(name) => {
+                            self.load(LuaValue::String(name.clone().into()));
+                            self.expression(&field.value)?;
+                            self.emit(Instr::TableSet);
+                        }
+                        // A trailing call or `...` fills the rest of the array
+                        FieldKey::Index(index)
+                            if i + 1 == fields.len() && is_multi(&field.value) =>
+                        {
+                            self.call(&field.value, None)?;
+                            self.emit(Instr::TableAppendOpen(*index));
+                        }
+                        FieldKey::Index(index) => {
+                            self.expression(&field.value)?;
+                            self.emit(Instr::TableAppend(*index));
+                        }
+                    }
+                }
+            }
+            Expression::FunctionDef(body) => self.closure(*body),
+        }
+        Ok(())
+    }
+
+    fn load(&mut self, value: LuaValue) {
+        let index = self.constant(value);
+        self.emit(Instr::LoadConst(index));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua_parser::{parse, tokenize, TokenSlice};
+    use crate::resolver::resolve;
+
+    fn compile_source(code: &str) -> Result<Chunk, String> {
+        let tokens = tokenize(code)?;
+        let (_, block) =
+            parse(TokenSlice::from(tokens.as_slice())).map_err(|e| format!("{:?}", e))?;
+        compile(&block)
+    }
+
+    #[test]
+    fn test_locals_use_slots() {
+        let chunk = compile_source("do local x = 1 local y = x + 2 return y end").unwrap();
+        assert_eq!(chunk.slot_count, 2);
+        assert!(chunk.code.contains(&Instr::GetLocal(0)));
+        assert!(chunk.code.contains(&Instr::DeclareLocal(1)));
+        assert!(chunk.names.is_empty());
+    }
+
+    #[test]
+    fn test_outermost_locals_keep_their_names() {
+        // Later chunks of a REPL see them by name
+        let chunk = compile_source("local x = 1").unwrap();
+        assert!(chunk
+            .code
+            .contains(&Instr::DeclareNamed { slot: 0, name: 0 }));
+        assert_eq!(chunk.names, vec!["x".to_string()]);
+    }
+
+    #[test]
+    fn test_unknown_names_are_globals() {
+        let chunk = compile_source("total = total + 1").unwrap();
+        assert_eq!(chunk.names, vec!["total".to_string()]);
+    }
+
+    #[test]
+    fn test_numeric_for_needs_only_its_variable_slot() {
+        let chunk = compile_source("local s = 0 for i = 1, 10 do s = s + i end return s").unwrap();
+        assert_eq!(chunk.slot_count, 2);
+        assert!(matches!(
+            chunk
+                .code
+                .iter()
+                .find(|i| matches!(i, Instr::ForPrep { .. })),
+            Some(Instr::ForPrep { slot: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_trailing_calls_keep_all_results() {
+        let chunk = compile_source("print(1, f())").unwrap();
+        assert!(chunk.code.contains(&Instr::Call {
+            argc: 0,
+            spread: false,
+            results: None,
+            name: Some(CallName::Global("f".into())),
+        }));
+        assert!(chunk.code.contains(&Instr::Call {
+            argc: 1,
+            spread: true,
+            results: Some(0),
+            name: Some(CallName::Global("print".into())),
+        }));
+        // A call in the middle of a list, or in parentheses, gives one value
+        let chunk = compile_source("local a, b = f(), (g())").unwrap();
+        let results: Vec<_> = chunk
+            .code
+            .iter()
+            .filter_map(|i| match i {
+                Instr::Call { results, .. } => Some(*results),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(results, vec![Some(1), Some(1)]);
+    }
+
+    #[test]
+    fn test_functions_compile_from_their_slots() {
+        let tokens = tokenize(
+            "local function count(t) local n = 0 for _, v in pairs(t) do n = n + v end return n end",
+        )
+        .unwrap();
+        let (_, block) = parse(TokenSlice::from(tokens.as_slice())).unwrap();
+        let resolved = resolve(&block);
+        let chunk = compile(&resolved).unwrap();
+        let Some(ClosureSpec { function, .. }) = chunk.closures.first() else {
+            panic!("expected a closure");
+        };
+        let body = FunctionRef {
+            arena: Rc::clone(&resolved.arena),
+            id: *function,
+        };
+        let code = compile_function(&body).unwrap();
+        assert_eq!(code.slot_count, body.layout.as_ref().unwrap().slot_count);
+        assert_eq!(code.iterators, 1);
+        assert!(code.names.iter().any(|n| n == "pairs"));
+
+        // Only resolved bodies have slots to compile to
+        let body = FunctionRef {
+            arena: Rc::clone(&block.arena),
+            id: *function,
+        };
+        assert!(compile_function(&body).is_err());
+    }
+
+    #[test]
+    fn test_unsupported_constructs() {
+        assert!(compile_source("goto done ::done::").is_err());
+        assert!(compile_source("break").is_err());
+        assert!(compile_source("local t = {} t.x = 1 for k, v in pairs(t) do end").is_ok());
+    }
+}
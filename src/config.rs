@@ -0,0 +1,248 @@
+//! Loading Lua files as configuration
+//!
+//! `load_config` runs a file the way editors and window managers run their
+//! Lua configs, but as data: the chunk sees only the pure parts of the
+//! standard library (no `io`, `os`, `require`, `debug` or `print`) and is
+//! stopped if it runs for longer than the time limit. The file either
+//! returns its configuration or assigns it to globals:
+//!
+//! ```lua
+//! -- return { width = 80, theme = "dark" } works the same
+//! width = 80
+//! theme = "dark"
+//! ```
+//!
+//! `get` and `get_or` then read a setting by its dotted path into a Rust
+//! type through the `convert` module.
+
+use crate::convert::from_lua;
+use crate::error_types::{LuaError, LuaResult};
+use crate::executor::ControlFlow;
+use crate::lua_interpreter::LuaInterpreter;
+use crate::lua_parser::parse_source;
+use crate::lua_value::{LuaTable, LuaValue};
+use crate::vm::execute_chunk;
+use serde::de::DeserializeOwned;
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+/// The globals a config file can use; nothing here reaches outside the
+/// interpreter
+const CONFIG_GLOBALS: &[&str] = &[
+    "_G",
+    "assert",
+    "error",
+    "getmetatable",
+    "ipairs",
+    "json",
+    "math",
+    "next",
+    "pairs",
+    "pcall",
+    "rawequal",
+    "rawget",
+    "rawlen",
+    "rawset",
+    "select",
+    "setmetatable",
+    "string",
+    "table",
+    "tonumber",
+    "tostring",
+    "type",
+    "unpack",
+    "utf8",
+    "xpcall",
+];
+
+/// Settings for `load_config_with` and `eval_config`
+#[derive(Debug, Clone)]
+pub struct ConfigOptions {
+    /// How long the file may run before it is stopped
+    pub time_limit: Duration,
+}
+
+impl Default for ConfigOptions {
+    fn default() -> Self {
+        ConfigOptions {
+            time_limit: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Run a Lua config file with the default options and return its settings
+pub fn load_config(path: impl AsRef<Path>) -> LuaResult<LuaValue> {
+    load_config_with(path, &ConfigOptions::default())
+}
+
+pub fn load_config_with(path: impl AsRef<Path>, options: &ConfigOptions) -> LuaResult<LuaValue> {
+    let path = path.as_ref();
+    let name = path.display().to_string();
+    let source =
+        std::fs::read_to_string(path).map_err(|e| LuaError::file(name.as_str(), e.to_string()))?;
+    eval_config(&source, &name, options)
+}
+
+/// Run config source, naming it `name` in errors, and return its settings
+///
+/// The settings are the chunk's first return value when it returns one,
+/// and otherwise a table of the globals it assigned.
+pub fn eval_config(source: &str, name: &str, options: &ConfigOptions) -> LuaResult<LuaValue> {
+    let block = parse_source(source).map_err(|e| LuaError::file(name, e))?;
+    let mut interp = LuaInterpreter::new().sandbox(CONFIG_GLOBALS);
+
+    // The watchdog cancels the chunk at the limit, and gives up waiting as
+    // soon as the chunk is done and `finished` is dropped
+    let token = interp.cancellation_token();
+    let (finished, done) = mpsc::channel::<()>();
+    let limit = options.time_limit;
+    let watchdog = thread::spawn(move || {
+        if done.recv_timeout(limit) == Err(RecvTimeoutError::Timeout) {
+            token.cancel();
+        }
+    });
+    let result = execute_chunk(&block, &mut interp);
+    drop(finished);
+    let _ = watchdog.join();
+
+    match result {
+        Ok(ControlFlow::Return(values)) if !values.is_empty() => {
+            Ok(values.into_iter().next().unwrap_or(LuaValue::Nil))
+        }
+        Ok(_) => Ok(assigned_globals(&interp)),
+        Err(LuaError::Cancelled) => Err(LuaError::file(
+            name,
+            format!("config did not finish within {:?}", limit),
+        )),
+        Err(e) => Err(LuaError::file(name, e.to_string())),
+    }
+}
+
+/// The globals a config chunk added to its sandbox, as a table
+fn assigned_globals(interp: &LuaInterpreter) -> LuaValue {
+    let globals = interp.globals.borrow();
+    let data = globals
+        .data
+        .iter()
+        .filter(|(key, _)| {
+            !matches!(key, LuaValue::String(name) if name.to_str().is_some_and(|name| CONFIG_GLOBALS.contains(&name)))
+        })
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    LuaValue::Table(Rc::new(RefCell::new(LuaTable {
+        data,
+        metatable: None,
+        frozen: false,
+        removed: None,
+    })))
+}
+
+/// Read the setting at a dotted `path`, such as `"window.width"`, or `None`
+/// when it or a table on the way to it is missing
+///
+/// A setting of the wrong type is an error naming its path.
+pub fn get<T: DeserializeOwned>(config: &LuaValue, path: &str) -> LuaResult<Option<T>> {
+    let mut value = config.clone();
+    for key in path.split('.') {
+        let next = match &value {
+            LuaValue::Table(table) => table.borrow().get_str(key).cloned(),
+            _ => None,
+        };
+        match next {
+            Some(next) => value = next,
+            None => return Ok(None),
+        }
+    }
+    match from_lua(&value) {
+        Ok(setting) => Ok(Some(setting)),
+        Err(e) => Err(LuaError::value(format!("setting '{}': {}", path, e))),
+    }
+}
+
+/// Read the setting at a dotted `path`, or `default` when it is missing
+pub fn get_or<T: DeserializeOwned>(config: &LuaValue, path: &str, default: T) -> LuaResult<T> {
+    Ok(get(config, path)?.unwrap_or(default))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str) -> LuaResult<LuaValue> {
+        eval_config(source, "test.lua", &ConfigOptions::default())
+    }
+
+    #[test]
+    fn test_settings_from_globals_or_the_returned_value() {
+        let config = eval(
+            "local base = 40\nwidth = base * 2\ntheme = 'dark'\n\
+             keys = { quit = 'q', save = string.upper('s') }\nfonts = {'mono', 'serif'}",
+        )
+        .unwrap();
+        assert_eq!(get::<u32>(&config, "width").unwrap(), Some(80));
+        assert_eq!(
+            get::<String>(&config, "keys.save").unwrap(),
+            Some("S".into())
+        );
+        assert_eq!(
+            get::<Vec<String>>(&config, "fonts").unwrap(),
+            Some(vec!["mono".to_string(), "serif".to_string()])
+        );
+        // Locals and the library are not settings
+        assert_eq!(get::<u32>(&config, "base").unwrap(), None);
+        assert_eq!(get::<String>(&config, "string").unwrap(), None);
+        assert_eq!(get::<String>(&config, "keys.quit.x").unwrap(), None);
+        assert_eq!(get_or(&config, "height", 24).unwrap(), 24);
+        let err = get::<u32>(&config, "theme").unwrap_err().to_string();
+        assert!(err.contains("setting 'theme'"), "{}", err);
+
+        let config = eval("ignored = 1\nreturn { width = 100 }").unwrap();
+        assert_eq!(get::<u32>(&config, "width").unwrap(), Some(100));
+        assert_eq!(get::<u32>(&config, "ignored").unwrap(), None);
+    }
+
+    #[test]
+    fn test_config_cannot_reach_outside_the_interpreter() {
+        for code in [
+            "io.open('x', 'w')",
+            "os.exit(1)",
+            "require('x')",
+            "print('x')",
+        ] {
+            let err = eval(code).unwrap_err().to_string();
+            assert!(err.contains("test.lua"), "{}: {}", code, err);
+        }
+        assert!(eval("x = = 1")
+            .unwrap_err()
+            .to_string()
+            .contains("Parse error"));
+    }
+
+    #[test]
+    fn test_config_is_stopped_at_the_time_limit() {
+        let options = ConfigOptions {
+            time_limit: Duration::from_millis(50),
+        };
+        let err = eval_config("while true do end", "slow.lua", &options)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("did not finish within 50ms"), "{}", err);
+    }
+
+    #[test]
+    fn test_load_config_reads_the_file() {
+        let path = std::env::temp_dir().join(format!("muscm_config_{}.lua", std::process::id()));
+        std::fs::write(&path, "name = 'muscm'").unwrap();
+        let config = load_config(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            get::<String>(&config.unwrap(), "name").unwrap(),
+            Some("muscm".into())
+        );
+        assert!(load_config(&path).is_err());
+    }
+}
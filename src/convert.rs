@@ -0,0 +1,709 @@
+//! Converting between Rust types and script values with serde
+//!
+//! `to_lua` turns anything `Serialize` into plain Lua data: structs and maps
+//! become tables with string keys, sequences become tables keyed 1..n and
+//! `None` or `()` become `nil` (so a `None` field is simply absent). `from_lua`
+//! reads a value back into anything `Deserialize`. Typed targets say what they
+//! expect, so an empty table reads as an empty `Vec` or an empty struct alike;
+//! `Options` decides between array and object only where the target accepts
+//! either, as `serde_json::Value` does.
+//!
+//! The Scheme side goes through the same conversions. Lists and vectors are
+//! sequences and association lists of `(key value)` pairs are objects, the
+//! shape the `bridge` module already uses for Lua tables.
+
+use crate::bridge;
+use crate::error_types::{LuaError, LuaResult};
+use crate::interpreter::SVal;
+use crate::lua_value::{LuaTable, LuaValue, TableData};
+use crate::scheme_number;
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use serde::{forward_to_deserialize_any, Serialize};
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+/// Tables nested deeper than this are assumed to contain themselves
+const MAX_DEPTH: usize = 100;
+
+/// How a table reads when the target type accepts either an array or an object
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Arrays {
+    /// Tables whose keys are exactly 1..n are arrays, like `json.encode`
+    #[default]
+    Sequence,
+    /// Every table is an object
+    Never,
+}
+
+/// Options for `from_lua_with` and `from_scheme_with`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+    pub arrays: Arrays,
+    /// Read `{}` as `[]` instead of `{}`
+    pub empty_as_array: bool,
+}
+
+/// Convert a Rust value into Lua data
+pub fn to_lua<T: Serialize + ?Sized>(value: &T) -> LuaResult<LuaValue> {
+    let json = serde_json::to_value(value)
+        .map_err(|e| LuaError::value(format!("cannot convert to Lua: {}", e)))?;
+    Ok(json_to_lua(json))
+}
+
+/// Read Lua data into a Rust value with the default options
+pub fn from_lua<T: DeserializeOwned>(value: &LuaValue) -> LuaResult<T> {
+    from_lua_with(value, &Options::default())
+}
+
+pub fn from_lua_with<T: DeserializeOwned>(value: &LuaValue, options: &Options) -> LuaResult<T> {
+    T::deserialize(Deserializer::new(value.clone(), options, 0))
+        .map_err(|e| LuaError::value(format!("cannot convert from Lua: {}", e.0)))
+}
+
+/// Convert a Rust value into Scheme data
+pub fn to_scheme<T: Serialize + ?Sized>(value: &T) -> Result<SVal, String> {
+    let lua = to_lua(value).map_err(|e| e.to_string())?;
+    bridge::lua_to_sval(&lua)
+}
+
+/// Read Scheme data into a Rust value with the default options
+pub fn from_scheme<T: DeserializeOwned>(value: &SVal) -> Result<T, String> {
+    from_scheme_with(value, &Options::default())
+}
+
+pub fn from_scheme_with<T: DeserializeOwned>(value: &SVal, options: &Options) -> Result<T, String> {
+    let lua = sval_to_data(value, 0)?;
+    from_lua_with(&lua, options).map_err(|e| e.to_string())
+}
+
+fn json_to_lua(value: serde_json::Value) -> LuaValue {
+    use serde_json::Value;
+    match value {
+        Value::Null => LuaValue::Nil,
+        Value::Bool(b) => LuaValue::Boolean(b),
+        Value::Number(n) => LuaValue::Number(n.as_f64().unwrap_or(f64::NAN)),
+        Value::String(s) => LuaValue::String(s.into()),
+        Value::Array(items) => new_table(
+            items
+                .into_iter()
+                .enumerate()
+                .map(|(i, item)| (LuaValue::Number((i + 1) as f64), json_to_lua(item))),
+        ),
+        Value::Object(fields) => new_table(
+            fields
+                .into_iter()
+                .map(|(key, item)| (LuaValue::String(key.into()), json_to_lua(item))),
+        ),
+    }
+}
+
+/// A table of the given entries; `nil` values leave their key out, as
+/// assigning `nil` would
+fn new_table(entries: impl Iterator<Item = (LuaValue, LuaValue)>) -> LuaValue {
+    let data: TableData = entries
+        .filter(|(_, value)| !matches!(value, LuaValue::Nil))
+        .collect();
+    LuaValue::Table(Rc::new(RefCell::new(LuaTable {
+        data,
+        metatable: None,
+        frozen: false,
+        removed: None,
+    })))
+}
+
+/// The key and value of an association list entry, either `(key value)`
+/// or `(key . value)`, with a string or symbol key
+fn alist_entry(item: &SVal) -> Option<(&SVal, &SVal)> {
+    let (key, value) = match item {
+        SVal::List(pair) if pair.len() == 2 => (&pair[0], &pair[1]),
+        SVal::DottedList(pair, tail) if pair.len() == 1 => (&pair[0], tail.as_ref()),
+        _ => return None,
+    };
+    matches!(key, SVal::String(_) | SVal::Atom(_)).then_some((key, value))
+}
+
+/// Whether a Scheme list is an association list with string or symbol keys
+fn is_alist(items: &[SVal]) -> bool {
+    !items.is_empty() && items.iter().all(|item| alist_entry(item).is_some())
+}
+
+/// Scheme data as the Lua data `from_lua` reads
+fn sval_to_data(value: &SVal, depth: usize) -> Result<LuaValue, String> {
+    if depth > MAX_DEPTH {
+        return Err("value is nested too deeply to convert".to_string());
+    }
+    Ok(match value {
+        SVal::Nil => LuaValue::Nil,
+        SVal::Bool(b) => LuaValue::Boolean(*b),
+        SVal::String(s) | SVal::Atom(s) => LuaValue::String(s.as_str().into()),
+        SVal::Char(c) => LuaValue::String(c.to_string().into()),
+        SVal::MutableString(s) => LuaValue::String(s.borrow().as_str().into()),
+        SVal::List(items) if is_alist(items) => {
+            let mut entries = Vec::with_capacity(items.len());
+            for (key, value) in items.iter().filter_map(alist_entry) {
+                entries.push((
+                    sval_to_data(key, depth + 1)?,
+                    sval_to_data(value, depth + 1)?,
+                ));
+            }
+            new_table(entries.into_iter())
+        }
+        SVal::List(items) | SVal::Vector(items) => {
+            let mut entries = Vec::with_capacity(items.len());
+            for (i, item) in items.iter().enumerate() {
+                entries.push((
+                    LuaValue::Number((i + 1) as f64),
+                    sval_to_data(item, depth + 1)?,
+                ));
+            }
+            new_table(entries.into_iter())
+        }
+        number => match scheme_number::to_f64(number) {
+            Some(n) => LuaValue::Number(n),
+            None => return Err(format!("cannot convert {} to Rust data", number)),
+        },
+    })
+}
+
+/// Errors raised while reading a value; turned into the caller's error type
+#[derive(Debug)]
+struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+/// Reads one Lua value
+struct Deserializer<'a> {
+    value: LuaValue,
+    options: &'a Options,
+    depth: usize,
+}
+
+impl<'a> Deserializer<'a> {
+    fn new(value: LuaValue, options: &'a Options, depth: usize) -> Self {
+        Deserializer {
+            value,
+            options,
+            depth,
+        }
+    }
+
+    fn child(&self, value: LuaValue) -> Result<Self, Error> {
+        if self.depth >= MAX_DEPTH {
+            return Err(Error("table is nested too deeply to convert".to_string()));
+        }
+        Ok(Deserializer::new(value, self.options, self.depth + 1))
+    }
+
+    /// Values 1..n when the table's keys are exactly that sequence
+    fn sequence(table: &LuaTable) -> Option<Vec<LuaValue>> {
+        (1..=table.data.len())
+            .map(|i| table.data.get(&LuaValue::Number(i as f64)).cloned())
+            .collect()
+    }
+
+    fn visit_seq<'de, V: Visitor<'de>>(
+        &self,
+        items: Vec<LuaValue>,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let items = items
+            .into_iter()
+            .map(|item| self.child(item))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut access = Seq(items.into_iter());
+        let result = visitor.visit_seq(&mut access)?;
+        match access.0.len() {
+            0 => Ok(result),
+            left => Err(de::Error::invalid_length(left, &"fewer elements")),
+        }
+    }
+
+    fn visit_map<'de, V: Visitor<'de>>(
+        &self,
+        table: &Rc<RefCell<LuaTable>>,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let entries = table
+            .borrow()
+            .data
+            .iter()
+            .map(|(key, value)| Ok((self.child(key.clone())?, self.child(value.clone())?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        visitor.visit_map(Map {
+            entries: entries.into_iter(),
+            value: None,
+        })
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'_> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match &self.value {
+            LuaValue::Nil => visitor.visit_unit(),
+            LuaValue::Boolean(b) => visitor.visit_bool(*b),
+            LuaValue::Number(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => {
+                visitor.visit_i64(*n as i64)
+            }
+            LuaValue::Number(n) => visitor.visit_f64(*n),
+            LuaValue::String(s) => match s.to_str() {
+                Some(text) => visitor.visit_str(text),
+                None => visitor.visit_bytes(s),
+            },
+            LuaValue::Table(table) => {
+                let is_array = if table.borrow().data.is_empty() {
+                    self.options.empty_as_array
+                } else {
+                    self.options.arrays == Arrays::Sequence
+                };
+                match is_array.then(|| Self::sequence(&table.borrow())).flatten() {
+                    Some(items) => self.visit_seq(items, visitor),
+                    None => self.visit_map(table, visitor),
+                }
+            }
+            other => Err(Error(format!(
+                "cannot convert a {} value",
+                other.type_name()
+            ))),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.value {
+            LuaValue::Nil => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let LuaValue::Table(table) = &self.value else {
+            return self.deserialize_any(visitor);
+        };
+        let items = Self::sequence(&table.borrow());
+        match items {
+            Some(items) => self.visit_seq(items, visitor),
+            None => Err(Error(
+                "expected a table with keys 1..n for a sequence".to_string(),
+            )),
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match &self.value {
+            LuaValue::Table(table) => self.visit_map(table, visitor),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Unit variants are strings; other variants are tables with the
+    /// variant name as their only key, as `to_lua` writes them
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match &self.value {
+            LuaValue::String(s) => visitor.visit_enum(s.to_string().into_deserializer()),
+            LuaValue::Table(table) => {
+                let entry = {
+                    let table = table.borrow();
+                    match table.data.first() {
+                        Some((LuaValue::String(name), value)) if table.data.len() == 1 => {
+                            Some((name.to_string(), value.clone()))
+                        }
+                        _ => None,
+                    }
+                };
+                match entry {
+                    Some((name, value)) => visitor.visit_enum(Variant {
+                        name,
+                        value: self.child(value)?,
+                    }),
+                    None => Err(Error(
+                        "expected a table with one string key for an enum".to_string(),
+                    )),
+                }
+            }
+            other => Err(Error(format!(
+                "expected a string or table for an enum, got {}",
+                other.type_name()
+            ))),
+        }
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match &self.value {
+            LuaValue::String(s) => visitor.visit_bytes(s.as_bytes()),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        unit unit_struct identifier
+    }
+}
+
+/// Reads a table key; number keys also read as strings, since JSON-like
+/// targets only accept string keys
+struct Key<'a>(Deserializer<'a>);
+
+macro_rules! forward_to_value {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                self.0.deserialize_any(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Key<'_> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match &self.0.value {
+            LuaValue::Number(_) => visitor.visit_string(self.0.value.to_string()),
+            _ => self.0.deserialize_any(visitor),
+        }
+    }
+
+    forward_to_value! {
+        deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64 deserialize_i128
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_u128
+        deserialize_f32 deserialize_f64
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.0.deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bool char str string bytes byte_buf unit unit_struct seq tuple tuple_struct
+        map struct identifier ignored_any
+    }
+}
+
+struct Seq<'a>(std::vec::IntoIter<Deserializer<'a>>);
+
+impl<'de> SeqAccess<'de> for Seq<'_> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        self.0.next().map(|item| seed.deserialize(item)).transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+struct Map<'a> {
+    entries: std::vec::IntoIter<(Deserializer<'a>, Deserializer<'a>)>,
+    /// Value of the key just read
+    value: Option<Deserializer<'a>>,
+}
+
+impl<'de> MapAccess<'de> for Map<'_> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        match self.entries.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(Key(key)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        match self.value.take() {
+            Some(value) => seed.deserialize(value),
+            None => Err(Error("map value read before its key".to_string())),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+struct Variant<'a> {
+    name: String,
+    value: Deserializer<'a>,
+}
+
+impl<'de, 'a> EnumAccess<'de> for Variant<'a> {
+    type Error = Error;
+    type Variant = Deserializer<'a>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Deserializer<'a>), Error> {
+        let name = seed.deserialize(self.name.into_deserializer())?;
+        Ok((name, self.value))
+    }
+}
+
+impl<'de> VariantAccess<'de> for Deserializer<'_> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        de::Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::{ControlFlow, Executor};
+    use crate::lua_interpreter::LuaInterpreter;
+    use crate::lua_parser::{parse, tokenize, TokenSlice};
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Mode {
+        Fast,
+        Limited(u32),
+        Custom { name: String },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Config {
+        name: String,
+        port: u16,
+        ratio: f64,
+        tags: Vec<String>,
+        retries: Option<u8>,
+        modes: Vec<Mode>,
+        limits: BTreeMap<String, i64>,
+    }
+
+    fn config() -> Config {
+        Config {
+            name: "server".to_string(),
+            port: 8080,
+            ratio: 0.5,
+            tags: vec!["a".to_string(), "b".to_string()],
+            retries: None,
+            modes: vec![
+                Mode::Fast,
+                Mode::Limited(3),
+                Mode::Custom {
+                    name: "x".to_string(),
+                },
+            ],
+            limits: BTreeMap::from([("cpu".to_string(), 2)]),
+        }
+    }
+
+    fn run(interp: &mut LuaInterpreter, code: &str) -> Vec<LuaValue> {
+        let tokens = tokenize(code).unwrap();
+        let (_, block) = parse(TokenSlice::from(tokens.as_slice())).unwrap();
+        match Executor::new().execute_block(&block, interp).unwrap() {
+            ControlFlow::Return(values) => values,
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_struct_round_trips_through_a_script() {
+        let mut interp = LuaInterpreter::new();
+        interp.set_global("config", to_lua(&config()).unwrap());
+        let result = run(
+            &mut interp,
+            "config.port = config.port + 1
+             table.insert(config.tags, 'c')
+             return config.name, config.retries, config.modes[1], config.modes[2].Limited, config",
+        );
+        assert_eq!(result[0], LuaValue::String("server".into()));
+        assert_eq!(result[1], LuaValue::Nil);
+        assert_eq!(result[2], LuaValue::String("Fast".into()));
+        assert_eq!(result[3], LuaValue::Number(3.0));
+
+        let mut expected = config();
+        expected.port = 8081;
+        expected.tags.push("c".to_string());
+        assert_eq!(from_lua::<Config>(&result[4]).unwrap(), expected);
+        assert_eq!(
+            from_lua::<Config>(&interp.get_global("config")).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_array_detection_is_configurable() {
+        let mut interp = LuaInterpreter::new();
+        let result = run(&mut interp, "return {10, 20}, {}, {[1] = 'a', [3] = 'c'}");
+        let read = |value: &LuaValue, options: &Options| {
+            from_lua_with::<serde_json::Value>(value, options)
+                .unwrap()
+                .to_string()
+        };
+        let default = Options::default();
+        assert_eq!(read(&result[0], &default), "[10,20]");
+        assert_eq!(read(&result[1], &default), "{}");
+        assert_eq!(read(&result[2], &default), r#"{"1":"a","3":"c"}"#);
+
+        let objects = Options {
+            arrays: Arrays::Never,
+            empty_as_array: true,
+        };
+        assert_eq!(read(&result[0], &objects), r#"{"1":10,"2":20}"#);
+        assert_eq!(read(&result[1], &objects), "[]");
+
+        // Typed targets decide for themselves
+        assert_eq!(from_lua::<Vec<u8>>(&result[1]).unwrap(), Vec::<u8>::new());
+        assert_eq!(
+            from_lua::<BTreeMap<u8, String>>(&result[2]).unwrap(),
+            BTreeMap::from([(1, "a".to_string()), (3, "c".to_string())])
+        );
+        assert!(from_lua::<Vec<String>>(&result[2]).is_err());
+    }
+
+    #[test]
+    fn test_bad_values_are_errors() {
+        let mut interp = LuaInterpreter::new();
+        let result = run(
+            &mut interp,
+            "local t = {} t.self = t return print, -1, 2.5, t",
+        );
+        assert!(from_lua::<serde_json::Value>(&result[0]).is_err());
+        assert!(from_lua::<u8>(&result[1]).is_err());
+        assert!(from_lua::<i32>(&result[2]).is_err());
+        assert_eq!(from_lua::<f32>(&result[2]).unwrap(), 2.5);
+        let err = from_lua::<serde_json::Value>(&result[3]).unwrap_err();
+        assert!(err.to_string().contains("nested too deeply"), "{}", err);
+    }
+
+    #[test]
+    fn test_scheme_values() {
+        let value = to_scheme(&config()).unwrap();
+        let SVal::List(fields) = &value else {
+            panic!("expected an association list, got {}", value);
+        };
+        assert!(fields
+            .iter()
+            .any(|field| field.to_string() == "(\"port\" 8080)"));
+        assert_eq!(from_scheme::<Config>(&value).unwrap(), config());
+
+        let list = SVal::List(vec![SVal::Integer(1), SVal::Number(2.5)]);
+        assert_eq!(from_scheme::<Vec<f64>>(&list).unwrap(), vec![1.0, 2.5]);
+        assert_eq!(
+            from_scheme::<Vec<u8>>(&SVal::List(Vec::new())).unwrap(),
+            Vec::<u8>::new()
+        );
+        assert!(from_scheme::<String>(&SVal::Eof).is_err());
+
+        let pairs = SVal::List(vec![SVal::dotted(
+            vec![SVal::Atom("port".to_string())],
+            SVal::Integer(8080),
+        )]);
+        assert_eq!(
+            from_scheme::<BTreeMap<String, u16>>(&pairs).unwrap(),
+            BTreeMap::from([("port".to_string(), 8080)])
+        );
+    }
+}
@@ -1,171 +1,731 @@
-use crate::lua_parser::Statement;
-/// Coroutine support for cooperative multitasking
-/// Enables yield/resume patterns for generator-like behavior
-use crate::lua_value::LuaValue;
+//! Coroutines, and scripts that wait on the host
+//!
+//! A coroutine runs its function on a stack of its own, so it can yield
+//! from any depth of Lua calls, metamethods, iterators or `pcall`s, and the
+//! executors on that stack carry on where they stopped when it is resumed.
+//! While it is suspended, the coroutine also keeps the interpreter's
+//! per-call state (scopes, slot frames and the call trace), which is
+//! swapped in whenever it runs.
+//!
+//! The host waits on scripts the same way. `Executor::start` runs a chunk
+//! as a task, a coroutine scripts cannot yield, and a builtin that calls
+//! `await_host` suspends every coroutine up to that task, handing the call
+//! to the host as a `Step::Pending`. `Executor::resume_with` passes the
+//! host's answer back down as the builtin's result.
+//!
+//! A coroutine holds on to the interpreter it first ran with, which must
+//! not move while it is suspended; resuming it from another interpreter is
+//! an error. WebAssembly has only the one stack, so there coroutines cannot
+//! be created.
+
+use crate::error_types::{LuaError, LuaResult};
+use crate::executor::{ControlFlow, Executor};
+use crate::lua_interpreter::{CallFrame, LuaInterpreter, SlotFrame, ValueStack};
+use crate::lua_parser::Chunk;
+use crate::lua_value::{LuaFunction, LuaValue};
+use crate::scope_manager::ScopeManager;
+use crate::traceback::CallTrace;
+use crate::upvalues::UpvalueCell;
+use fiber::{Fiber, Yielder};
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Coroutines that may be resumed inside one another; one more is a
+/// "C stack overflow", as in Lua
+const MAX_NESTING: usize = 200;
+
+/// Stack each coroutine starts with; it grows like any other (see `stack`)
+#[cfg(not(target_family = "wasm"))]
+const STACK: usize = 1024 * 1024;
 
 /// State of a coroutine
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CoroutineStatus {
-    /// Coroutine has been created but never resumed
+    /// Created but not yet resumed, or stopped in a yield
     Suspended,
-    /// Coroutine is currently running
+    /// Currently running
     Running,
-    /// Coroutine has finished execution
+    /// Running another coroutine it resumed
+    Normal,
+    /// Returned or raised an error
     Dead,
 }
 
-impl ToString for CoroutineStatus {
-    fn to_string(&self) -> String {
-        match self {
-            CoroutineStatus::Suspended => "suspended".to_string(),
-            CoroutineStatus::Running => "running".to_string(),
-            CoroutineStatus::Dead => "dead".to_string(),
-        }
+impl std::fmt::Display for CoroutineStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            CoroutineStatus::Suspended => "suspended",
+            CoroutineStatus::Running => "running",
+            CoroutineStatus::Normal => "normal",
+            CoroutineStatus::Dead => "dead",
+        };
+        write!(f, "{}", status)
     }
 }
-
-/// A Lua coroutine that can be suspended and resumed
-#[derive(Debug, Clone)]
-pub struct Coroutine {
-    /// Unique identifier for this coroutine
-    pub id: usize,
-    /// Current status
-    pub status: CoroutineStatus,
-    /// Saved program counter (instruction index)
-    pub pc: usize,
-    /// Parameters for the function
-    pub params: Vec<String>,
-    /// Function body to execute
-    pub body: Vec<Statement>,
-    /// Local variables at current suspension point
-    pub locals: HashMap<String, LuaValue>,
-    /// Values from the last yield or arguments to resume
-    pub yield_values: Vec<LuaValue>,
-    /// Execution stack at suspension point
-    pub stack: Vec<LuaValue>,
+
+/// A call a task is waiting on the host for
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pending {
+    /// Name the builtin gave `await_host`
+    pub function: String,
+    pub args: Vec<LuaValue>,
+}
+
+/// Where a task stopped
+#[derive(Debug)]
+pub enum Step {
+    /// Waiting for `Executor::resume_with` or `Executor::fail`
+    Pending(Pending),
+    /// Returned these values or failed
+    Finished(LuaResult<Vec<LuaValue>>),
+}
+
+/// What a coroutine hands its resumer when it stops
+enum Suspend {
+    /// `coroutine.yield` with these values
+    Yield(Vec<LuaValue>),
+    /// A call for the host, on its way up to the task
+    Pending(Pending),
+}
+
+/// What resuming hands a coroutine: the interpreter to run with, and the
+/// arguments to `coroutine.resume` or the host's answer
+type Input = (*mut LuaInterpreter, LuaResult<Vec<LuaValue>>);
+
+/// What a coroutine's function returned or raised
+type Output = LuaResult<Vec<LuaValue>>;
+
+/// How a coroutine came back to its resumer
+enum Switch {
+    Suspended(Suspend),
+    Returned(Output),
 }
 
-impl Coroutine {
-    /// Create a new coroutine with a function body
-    pub fn new(id: usize, params: Vec<String>, body: Vec<Statement>) -> Self {
-        Coroutine {
-            id,
-            status: CoroutineStatus::Suspended,
-            pc: 0,
-            params,
-            body,
-            locals: HashMap::new(),
-            yield_values: Vec::new(),
-            stack: Vec::new(),
+#[cfg(not(target_family = "wasm"))]
+mod fiber {
+    use super::{Input, Output, Suspend, Switch, STACK};
+    use corosensei::stack::{DefaultStack, Stack};
+    use corosensei::{Coroutine, CoroutineResult};
+
+    pub type Yielder = corosensei::Yielder<Input, Suspend>;
+
+    /// A function running on a stack of its own
+    pub struct Fiber(Coroutine<Input, Suspend, Output, DefaultStack>);
+
+    impl Fiber {
+        pub fn new(
+            body: impl FnOnce(&Yielder, Input) -> Output + 'static,
+        ) -> std::io::Result<Self> {
+            let stack = DefaultStack::new(STACK)?;
+            let limit = stack.limit().get();
+            Ok(Fiber(Coroutine::with_stack(
+                stack,
+                move |yielder, input| {
+                    crate::stack::enter(limit);
+                    body(yielder, input)
+                },
+            )))
+        }
+
+        pub fn resume(&mut self, input: Input) -> Switch {
+            crate::stack::switching(|| match self.0.resume(input) {
+                CoroutineResult::Yield(suspend) => Switch::Suspended(suspend),
+                CoroutineResult::Return(output) => Switch::Returned(output),
+            })
         }
     }
 
-    /// Resume the coroutine with given arguments
-    pub fn resume(&mut self, args: Vec<LuaValue>) -> (bool, Vec<LuaValue>) {
-        if self.status == CoroutineStatus::Dead {
-            return (
-                false,
-                vec![LuaValue::String("cannot resume dead coroutine".to_string())],
-            );
+    /// Unwinds a suspended body, dropping what its stack holds
+    impl Drop for Fiber {
+        fn drop(&mut self) {
+            crate::stack::switching(|| self.0.force_unwind());
         }
+    }
+
+    pub fn suspend(yielder: &Yielder, suspend: Suspend) -> Input {
+        crate::stack::switching(|| yielder.suspend(suspend))
+    }
+}
 
-        if self.status == CoroutineStatus::Suspended {
-            // Set the arguments as the values for this resume
-            self.yield_values = args;
-            self.status = CoroutineStatus::Running;
-            (true, vec![])
-        } else {
-            (
-                false,
-                vec![LuaValue::String(
-                    "cannot resume running coroutine".to_string(),
-                )],
-            )
+#[cfg(target_family = "wasm")]
+mod fiber {
+    use super::{Input, Output, Suspend, Switch};
+
+    pub enum Yielder {}
+
+    pub enum Fiber {}
+
+    impl Fiber {
+        pub fn new(
+            _body: impl FnOnce(&Yielder, Input) -> Output + 'static,
+        ) -> std::io::Result<Self> {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "coroutines need native stacks",
+            ))
         }
+
+        pub fn resume(&mut self, _input: Input) -> Switch {
+            match *self {}
+        }
+    }
+
+    pub fn suspend(yielder: &Yielder, _suspend: Suspend) -> Input {
+        match *yielder {}
+    }
+}
+
+/// The interpreter's state for one line of execution, kept by a coroutine
+/// while it is not running
+#[derive(Default)]
+struct Stacks {
+    scope_stack: Vec<HashMap<String, UpvalueCell>>,
+    scope_manager: ScopeManager,
+    frames: Vec<SlotFrame>,
+    call_stack: Vec<CallFrame>,
+    value_stack: ValueStack,
+    trace: CallTrace,
+}
+
+impl Stacks {
+    /// Trade these for the interpreter's
+    fn swap(&mut self, interp: &mut LuaInterpreter) {
+        std::mem::swap(&mut self.scope_stack, &mut interp.scope_stack);
+        std::mem::swap(&mut self.scope_manager, &mut interp.scope_manager);
+        std::mem::swap(&mut self.frames, &mut interp.frames);
+        std::mem::swap(&mut self.call_stack, &mut interp.call_stack);
+        std::mem::swap(&mut self.value_stack, &mut interp.value_stack);
+        std::mem::swap(&mut self.trace, &mut interp.trace.borrow_mut());
+    }
+}
+
+thread_local! {
+    /// Coroutines running now, each resumed by the one before it
+    static ACTIVE: RefCell<Vec<Rc<LuaThread>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A coroutine: a Lua function, or a host's task, on a stack of its own
+pub struct LuaThread {
+    status: Cell<CoroutineStatus>,
+    /// The body, out of the cell while it runs
+    fiber: RefCell<Option<Fiber>>,
+    /// How the body suspends, once it has started
+    yielder: Cell<*const Yielder>,
+    /// The interpreter it runs with, once it has started
+    interp: Cell<*const LuaInterpreter>,
+    /// Its state of the interpreter while it is not running, and its
+    /// resumer's while it is
+    stacks: RefCell<Stacks>,
+    /// Whether scripts may yield it; a task only stops for the host
+    yieldable: bool,
+}
+
+impl LuaThread {
+    /// A coroutine that calls `function` with the arguments of its first
+    /// resume
+    pub fn new(function: LuaValue) -> LuaResult<Rc<Self>> {
+        Self::with_body(true, move |interp, args| {
+            Executor::new().call_function_multi(function, args, interp)
+        })
+    }
+
+    /// A task running `chunk`, for `Executor::start`
+    fn task(chunk: Chunk) -> LuaResult<Rc<Self>> {
+        Self::with_body(false, move |interp, _| {
+            match crate::vm::execute_chunk(&chunk, interp)? {
+                ControlFlow::Return(values) => Ok(values),
+                _ => Ok(Vec::new()),
+            }
+        })
+    }
+
+    fn with_body(
+        yieldable: bool,
+        body: impl FnOnce(&mut LuaInterpreter, Vec<LuaValue>) -> Output + 'static,
+    ) -> LuaResult<Rc<Self>> {
+        let fiber = Fiber::new(move |yielder, (interp, args)| {
+            ACTIVE.with(|active| {
+                if let Some(thread) = active.borrow().last() {
+                    thread.yielder.set(yielder);
+                }
+            });
+            // SAFETY: `take` checks every resume passes this same
+            // interpreter, and the resumer does not touch it until the
+            // body suspends or returns
+            let interp = unsafe { &mut *interp };
+            body(interp, args?)
+        })
+        .map_err(|e| LuaError::runtime(format!("cannot create a coroutine: {}", e), "coroutine"))?;
+        Ok(Rc::new(LuaThread {
+            status: Cell::new(CoroutineStatus::Suspended),
+            fiber: RefCell::new(Some(fiber)),
+            yielder: Cell::new(std::ptr::null()),
+            interp: Cell::new(std::ptr::null()),
+            stacks: RefCell::default(),
+            yieldable,
+        }))
     }
 
-    /// Suspend the coroutine at the current point
-    pub fn yield_values(&mut self, values: Vec<LuaValue>) {
-        self.status = CoroutineStatus::Suspended;
-        self.yield_values = values;
+    pub fn status(&self) -> CoroutineStatus {
+        self.status.get()
     }
 
-    /// Mark the coroutine as finished
-    pub fn finish(&mut self, return_values: Vec<LuaValue>) -> Vec<LuaValue> {
-        self.status = CoroutineStatus::Dead;
-        self.yield_values = return_values;
-        self.yield_values.clone()
+    /// The body, to resume with `interp`
+    fn take(&self, interp: &LuaInterpreter) -> LuaResult<Fiber> {
+        match self.status.get() {
+            CoroutineStatus::Suspended => {}
+            CoroutineStatus::Dead => {
+                return Err(LuaError::runtime(
+                    "cannot resume dead coroutine",
+                    "coroutine",
+                ))
+            }
+            CoroutineStatus::Running | CoroutineStatus::Normal => {
+                return Err(LuaError::runtime(
+                    "cannot resume non-suspended coroutine",
+                    "coroutine",
+                ))
+            }
+        }
+        let here: *const LuaInterpreter = interp;
+        if self.interp.get().is_null() {
+            self.interp.set(here);
+        } else if self.interp.get() != here {
+            return Err(LuaError::runtime(
+                "cannot resume a coroutine in another interpreter than the one it started in",
+                "coroutine",
+            ));
+        }
+        if ACTIVE.with(|active| active.borrow().len()) >= MAX_NESTING {
+            return Err(LuaError::runtime(
+                format!(
+                    "C stack overflow (more than {} nested coroutines)",
+                    MAX_NESTING
+                ),
+                "coroutine",
+            ));
+        }
+        self.fiber
+            .borrow_mut()
+            .take()
+            .ok_or_else(|| LuaError::runtime("cannot resume dead coroutine", "coroutine"))
     }
 
-    /// Get the status as a Lua value
-    pub fn status_value(&self) -> LuaValue {
-        LuaValue::String(self.status.to_string())
+    /// Run the body until it next stops, handing it `input`
+    fn switch(
+        self: &Rc<Self>,
+        fiber: &mut Fiber,
+        input: LuaResult<Vec<LuaValue>>,
+        interp: &mut LuaInterpreter,
+    ) -> Switch {
+        let caller = ACTIVE.with(|active| active.borrow().last().cloned());
+        if let Some(caller) = &caller {
+            caller.status.set(CoroutineStatus::Normal);
+        }
+        self.status.set(CoroutineStatus::Running);
+        ACTIVE.with(|active| active.borrow_mut().push(Rc::clone(self)));
+        self.stacks.borrow_mut().swap(interp);
+
+        let switch = fiber.resume((interp, input));
+
+        self.stacks.borrow_mut().swap(interp);
+        ACTIVE.with(|active| active.borrow_mut().pop());
+        if let Some(caller) = &caller {
+            caller.status.set(CoroutineStatus::Running);
+        }
+        match &switch {
+            Switch::Suspended(_) => {}
+            Switch::Returned(_) => self.status.set(CoroutineStatus::Dead),
+        }
+        switch
     }
 
-    /// Check if coroutine can be resumed
-    pub fn is_resumable(&self) -> bool {
-        self.status == CoroutineStatus::Suspended
+    /// Keep the body, which stopped in a yield
+    fn suspended(&self, fiber: Fiber) {
+        self.status.set(CoroutineStatus::Suspended);
+        *self.fiber.borrow_mut() = Some(fiber);
     }
 }
 
-/// Registry to manage all active coroutines
-#[derive(Debug, Clone)]
-pub struct CoroutineRegistry {
-    /// Map of coroutine ID to coroutine
-    coroutines: HashMap<usize, Coroutine>,
-    /// Next ID to assign
-    next_id: usize,
-    /// Currently active coroutine ID
-    active_id: Option<usize>,
+/// Suspend the coroutine running now, handing its resumer `suspend`, and
+/// return what it is resumed with
+fn suspend(suspend: Suspend) -> LuaResult<Vec<LuaValue>> {
+    let yielder = ACTIVE.with(|active| active.borrow().last().map(|thread| thread.yielder.get()));
+    match yielder {
+        // SAFETY: the yielder lives at the bottom of the running
+        // coroutine's stack, which is the one this code runs on
+        Some(yielder) if !yielder.is_null() => fiber::suspend(unsafe { &*yielder }, suspend).1,
+        _ => Err(LuaError::runtime(
+            "attempt to yield from outside a coroutine",
+            "coroutine",
+        )),
+    }
 }
 
-impl CoroutineRegistry {
-    pub fn new() -> Self {
-        CoroutineRegistry {
-            coroutines: HashMap::new(),
-            next_id: 1,
-            active_id: None,
+/// Resume `thread` with `args`: what it yielded or returned, or the error
+/// it raised
+///
+/// Calls a coroutine makes for the host go on up to the task, and the
+/// host's answers back down, without the resumer seeing them.
+pub(crate) fn resume(
+    thread: &Rc<LuaThread>,
+    args: Vec<LuaValue>,
+    interp: &mut LuaInterpreter,
+) -> LuaResult<Vec<LuaValue>> {
+    let mut fiber = thread.take(interp)?;
+    let mut input = Ok(args);
+    loop {
+        match thread.switch(&mut fiber, input, interp) {
+            Switch::Suspended(Suspend::Yield(values)) => {
+                thread.suspended(fiber);
+                return Ok(values);
+            }
+            Switch::Suspended(Suspend::Pending(call)) => {
+                input = suspend(Suspend::Pending(call));
+            }
+            Switch::Returned(output) => return output,
         }
     }
+}
 
-    /// Create a new coroutine and return its ID
-    pub fn create(&mut self, params: Vec<String>, body: Vec<Statement>) -> usize {
-        let id = self.next_id;
-        self.next_id += 1;
-        let co = Coroutine::new(id, params, body);
-        self.coroutines.insert(id, co);
-        id
+/// `coroutine.yield(...)`: suspend the running coroutine, which its
+/// resumer sees return these values, and return what it is resumed with
+pub fn yield_values(values: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    let yieldable = ACTIVE.with(|active| {
+        active
+            .borrow()
+            .last()
+            .is_some_and(|thread| thread.yieldable)
+    });
+    if !yieldable {
+        return Err(LuaError::runtime(
+            "attempt to yield from outside a coroutine",
+            "coroutine.yield",
+        ));
     }
+    suspend(Suspend::Yield(values))
+}
+
+/// The running coroutine; `None` in the main chunk or a task
+pub fn running() -> Option<Rc<LuaThread>> {
+    ACTIVE
+        .with(|active| active.borrow().last().cloned())
+        .filter(|thread| thread.yieldable)
+}
 
-    /// Get a mutable reference to a coroutine by ID
-    pub fn get_mut(&mut self, id: usize) -> Option<&mut Coroutine> {
-        self.coroutines.get_mut(&id)
+/// `coroutine.close(co)`: unwind a suspended coroutine, so it is dead
+pub fn close(thread: &LuaThread) -> LuaResult<()> {
+    match thread.status.get() {
+        CoroutineStatus::Suspended => {
+            let fiber = thread.fiber.borrow_mut().take();
+            thread.status.set(CoroutineStatus::Dead);
+            drop(fiber);
+            Ok(())
+        }
+        CoroutineStatus::Dead => Ok(()),
+        status => Err(LuaError::runtime(
+            format!("cannot close a {} coroutine", status),
+            "coroutine.close",
+        )),
     }
+}
 
-    /// Get a coroutine by ID
-    pub fn get(&self, id: usize) -> Option<&Coroutine> {
-        self.coroutines.get(&id)
+/// Wait for the host to answer a call to `function` with `args`: suspends
+/// the task the script runs in, handing the host a `Step::Pending`, and
+/// returns the value it resumes with, or raises the message it fails with
+///
+/// Builtins that do their work in the host's event loop call this; it is
+/// an error outside a task (see `Executor::start`).
+pub fn await_host(function: impl Into<String>, args: Vec<LuaValue>) -> LuaResult<LuaValue> {
+    let function = function.into();
+    let in_task = ACTIVE.with(|active| active.borrow().iter().any(|thread| !thread.yieldable));
+    if !in_task {
+        return Err(LuaError::runtime(
+            "the host can only be waited on from a task",
+            function,
+        ));
     }
+    let values = suspend(Suspend::Pending(Pending { function, args }))?;
+    Ok(values.into_iter().next().unwrap_or(LuaValue::Nil))
+}
+
+/// A builtin that waits for the host to answer every call, named `name`
+/// in its `Step::Pending`s
+pub fn pending_function(name: impl Into<String>) -> LuaValue {
+    let name = name.into();
+    LuaValue::Function(Rc::new(LuaFunction::Builtin(Rc::new(move |args| {
+        await_host(name.clone(), args)
+    }))))
+}
 
-    /// Set the currently active coroutine
-    pub fn set_active(&mut self, id: usize) {
-        self.active_id = Some(id);
+/// A chunk `Executor::start` runs, while it waits on the host
+pub(crate) struct Task {
+    thread: Rc<LuaThread>,
+    /// The builtin it waits in, which raises the host's failures
+    function: String,
+}
+
+impl Task {
+    /// Run `chunk` until it waits on the host or finishes
+    pub(crate) fn start(chunk: &Chunk, interp: &mut LuaInterpreter) -> (Option<Task>, Step) {
+        match LuaThread::task(chunk.clone()) {
+            Ok(thread) => Self::run(thread, Ok(Vec::new()), interp),
+            Err(e) => (None, Step::Finished(Err(e))),
+        }
     }
 
-    /// Get the active coroutine ID
-    pub fn get_active(&self) -> Option<usize> {
-        self.active_id
+    /// Answer the call the task waits in and run it to its next step
+    pub(crate) fn answer(
+        self,
+        answer: Result<LuaValue, String>,
+        interp: &mut LuaInterpreter,
+    ) -> (Option<Task>, Step) {
+        let input = match answer {
+            Ok(value) => Ok(vec![value]),
+            Err(message) => Err(LuaError::runtime(message, self.function)),
+        };
+        Self::run(self.thread, input, interp)
     }
 
-    /// Clear active coroutine
-    pub fn clear_active(&mut self) {
-        self.active_id = None;
+    fn run(
+        thread: Rc<LuaThread>,
+        input: LuaResult<Vec<LuaValue>>,
+        interp: &mut LuaInterpreter,
+    ) -> (Option<Task>, Step) {
+        let mut fiber = match thread.take(interp) {
+            Ok(fiber) => fiber,
+            Err(e) => return (None, Step::Finished(Err(e))),
+        };
+        match thread.switch(&mut fiber, input, interp) {
+            Switch::Suspended(Suspend::Pending(call)) => {
+                thread.suspended(fiber);
+                let function = call.function.clone();
+                (Some(Task { thread, function }), Step::Pending(call))
+            }
+            // `yield_values` does not suspend tasks
+            Switch::Suspended(Suspend::Yield(_)) => (
+                None,
+                Step::Finished(Err(LuaError::runtime(
+                    "attempt to yield from outside a coroutine",
+                    "coroutine.yield",
+                ))),
+            ),
+            Switch::Returned(output) => (None, Step::Finished(output)),
+        }
     }
 }
 
-impl Default for CoroutineRegistry {
-    fn default() -> Self {
-        Self::new()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua_parser::parse_source;
+
+    fn run(code: &str) -> LuaResult<Vec<LuaValue>> {
+        let mut interp = LuaInterpreter::new();
+        let chunk = parse_source(code).unwrap();
+        match crate::vm::execute_chunk(&chunk, &mut interp)? {
+            ControlFlow::Return(values) => Ok(values),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn strings(values: &[&str]) -> Vec<LuaValue> {
+        values
+            .iter()
+            .map(|s| LuaValue::String((*s).into()))
+            .collect()
+    }
+
+    #[test]
+    fn test_yield_and_resume_pass_values() {
+        let result = run(r#"
+            local co = coroutine.create(function(a, b)
+                local c = coroutine.yield(a + b)
+                local d, e = coroutine.yield(c * 2)
+                return d .. e
+            end)
+            local log = {}
+            local ok, v = coroutine.resume(co, 1, 2)
+            log[#log + 1] = tostring(ok) .. " " .. v .. " " .. coroutine.status(co)
+            ok, v = coroutine.resume(co, 10)
+            log[#log + 1] = tostring(ok) .. " " .. v
+            ok, v = coroutine.resume(co, "x", "y")
+            log[#log + 1] = tostring(ok) .. " " .. v .. " " .. coroutine.status(co)
+            ok, v = coroutine.resume(co)
+            log[#log + 1] = tostring(ok) .. " " .. v
+            return table.unpack(log)
+        "#)
+        .unwrap();
+        assert_eq!(
+            result[..3],
+            strings(&["true 3 suspended", "true 20", "true xy dead"])[..]
+        );
+        assert!(result[3].to_string().starts_with("false "), "{:?}", result);
+        assert!(result[3]
+            .to_string()
+            .contains("cannot resume dead coroutine"));
+    }
+
+    #[test]
+    fn test_yield_from_nested_calls_and_iterators() {
+        let result = run(r#"
+            local function walk(t)
+                for _, v in ipairs(t) do
+                    if type(v) == "table" then walk(v) else coroutine.yield(v) end
+                end
+            end
+            local out = ""
+            for v in coroutine.wrap(function() walk({1, {2, {3}}, 4}) end) do
+                out = out .. v
+            end
+            local caught = coroutine.wrap(function()
+                local ok, err = pcall(function()
+                    coroutine.yield("inside pcall")
+                    error("after yield")
+                end)
+                coroutine.yield(ok)
+                return err
+            end)
+            return out, caught(), caught(), caught()
+        "#)
+        .unwrap();
+        assert_eq!(result[0], LuaValue::String("1234".into()));
+        assert_eq!(result[1], LuaValue::String("inside pcall".into()));
+        assert_eq!(result[2], LuaValue::Boolean(false));
+        assert!(result[3].to_string().contains("after yield"));
+    }
+
+    #[test]
+    fn test_statuses_and_running() {
+        let result = run(r#"
+            local outer
+            local inner = coroutine.create(function()
+                return coroutine.status(outer), coroutine.isyieldable()
+            end)
+            outer = coroutine.create(function()
+                local co, main = coroutine.running()
+                local _, status, yieldable = coroutine.resume(inner)
+                return co == outer, main, coroutine.status(co), status, yieldable
+            end)
+            local main_co, is_main = coroutine.running()
+            return type(outer), is_main, main_co, coroutine.isyieldable(),
+                select(2, coroutine.resume(outer))
+        "#)
+        .unwrap();
+        assert_eq!(result[0], LuaValue::String("thread".into()));
+        assert_eq!(result[1], LuaValue::Boolean(true));
+        assert_eq!(result[2], LuaValue::Nil);
+        assert_eq!(result[3], LuaValue::Boolean(false));
+        assert_eq!(
+            result[4..],
+            [
+                LuaValue::Boolean(true),
+                LuaValue::Boolean(false),
+                LuaValue::String("running".into()),
+                LuaValue::String("normal".into()),
+                LuaValue::Boolean(true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = run("coroutine.yield(1)").unwrap_err();
+        assert!(err.to_string().contains("outside a coroutine"), "{}", err);
+
+        let result = run(r#"
+            local co = coroutine.create(function() error("boom") end)
+            local ok, err = coroutine.resume(co)
+            local self_resume = coroutine.wrap(function()
+                return coroutine.resume(coroutine.running())
+            end)
+            local _, again = self_resume()
+            return ok, err, coroutine.status(co), again
+        "#)
+        .unwrap();
+        assert_eq!(result[0], LuaValue::Boolean(false));
+        assert!(result[1].to_string().contains("boom"));
+        assert_eq!(result[2], LuaValue::String("dead".into()));
+        assert!(result[3].to_string().contains("non-suspended"));
+
+        let err = run("coroutine.wrap(function() error('wrapped') end)()").unwrap_err();
+        assert!(err.to_string().contains("wrapped"), "{}", err);
+        let err = run("coroutine.create(1)").unwrap_err();
+        assert!(err.to_string().contains("function"), "{}", err);
+    }
+
+    #[test]
+    fn test_close_unwinds_a_suspended_coroutine() {
+        let result = run(r#"
+            local co = coroutine.create(function()
+                local t = {}
+                coroutine.yield(t)
+            end)
+            coroutine.resume(co)
+            local closed = coroutine.close(co)
+            return closed, coroutine.status(co), coroutine.resume(co)
+        "#)
+        .unwrap();
+        assert_eq!(result[0], LuaValue::Boolean(true));
+        assert_eq!(result[1], LuaValue::String("dead".into()));
+        assert_eq!(result[2], LuaValue::Boolean(false));
+    }
+
+    #[test]
+    fn test_deep_recursion_inside_a_coroutine() {
+        let result = run(r#"
+            local function depth(n)
+                if n == 0 then return coroutine.yield(0) end
+                return 1 + depth(n - 1)
+            end
+            local co = coroutine.wrap(function() return depth(900) end)
+            return co(), co(5)
+        "#)
+        .unwrap();
+        assert_eq!(result, vec![LuaValue::Number(0.0), LuaValue::Number(905.0)]);
+    }
+
+    #[test]
+    fn test_nesting_is_limited() {
+        let err = run(r#"
+            local function nest()
+                local ok, err = coroutine.resume(coroutine.create(nest))
+                if not ok then error(err) end
+            end
+            nest()
+        "#)
+        .unwrap_err();
+        assert!(err.to_string().contains("C stack overflow"), "{}", err);
+    }
+
+    #[test]
+    fn test_coroutines_stay_with_their_interpreter() {
+        let chunk = parse_source(
+            "co = coroutine.create(function() coroutine.yield() end) coroutine.resume(co)",
+        )
+        .unwrap();
+        let mut first = LuaInterpreter::new();
+        crate::vm::execute_chunk(&chunk, &mut first).unwrap();
+        let co = first.lookup("co").unwrap();
+
+        let mut second = LuaInterpreter::new();
+        second.set_global("co", co);
+        let chunk = parse_source("return coroutine.resume(co)").unwrap();
+        let values = match crate::vm::execute_chunk(&chunk, &mut second).unwrap() {
+            ControlFlow::Return(values) => values,
+            other => panic!("expected a return, got {:?}", other),
+        };
+        assert_eq!(values[0], LuaValue::Boolean(false));
+        assert!(values[1].to_string().contains("another interpreter"));
+    }
+
+    #[test]
+    fn test_host_calls_outside_a_task_fail() {
+        let mut interp = LuaInterpreter::new();
+        interp.set_global("fetch", pending_function("fetch"));
+        let chunk = parse_source("return fetch('x')").unwrap();
+        let err = crate::vm::execute_chunk(&chunk, &mut interp).unwrap_err();
+        assert!(err.to_string().contains("from a task"), "{}", err);
     }
 }
@@ -0,0 +1,195 @@
+/// Line coverage for Lua scripts
+///
+/// Coverage is off until a host calls `Coverage::enable` (`muscm run
+/// --coverage` does). From then on every chunk the executor is handed
+/// through `enter_chunk` (the main script, then each module `require`
+/// loads) has its statement lines registered as executable, and every
+/// statement that runs adds a hit to its line. A line is attributed to the
+/// file of the innermost running Lua function, or of the running chunk for
+/// top-level code. Only chunks parsed with locations have lines to record.
+///
+/// Scripts reach the same state through the `coverage` library:
+/// `coverage.stop()` and `coverage.start()` pause and resume recording,
+/// and `coverage.report()` returns the report in lcov's tracefile format.
+use crate::lua_parser::Chunk;
+use crate::lua_value::LuaValue;
+use crate::source_map::{self, SourceMap};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::rc::Rc;
+
+/// Executable lines of one file and how often each ran
+pub type LineHits = BTreeMap<usize, u64>;
+
+/// Which lines ran, per file
+#[derive(Debug, Default)]
+pub struct Coverage {
+    enabled: bool,
+    recording: bool,
+    files: BTreeMap<Rc<str>, LineHits>,
+    sources: SourceMap,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register chunks from now on and start recording
+    pub fn enable(&mut self) {
+        self.enabled = true;
+        self.recording = true;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    /// Resume recording; false when coverage was never enabled
+    pub fn start(&mut self) -> bool {
+        self.recording = self.enabled;
+        self.enabled
+    }
+
+    /// Pause recording; chunks loaded meanwhile are still registered
+    pub fn stop(&mut self) {
+        self.recording = false;
+    }
+
+    /// `chunk` is about to run as the chunk loaded from `file`
+    pub fn enter_chunk(&mut self, file: &str, chunk: &Chunk) {
+        let file: Rc<str> = file.into();
+        if self.enabled {
+            let lines = self.files.entry(Rc::clone(&file)).or_default();
+            source_map::walk_chunk(chunk, &mut |block| {
+                for line in block.lines.iter().filter(|line| **line > 0) {
+                    lines.entry(*line).or_insert(0);
+                }
+            });
+            self.sources.register(&file, chunk);
+        }
+        self.sources.enter_chunk(file);
+    }
+
+    /// The chunk from the matching `enter_chunk` has finished
+    pub fn leave_chunk(&mut self) {
+        self.sources.leave_chunk();
+    }
+
+    /// A statement on `line` is running inside `function`, or in the
+    /// chunk's top-level code when that is `None` or a builtin
+    pub fn hit(&mut self, function: Option<&LuaValue>, line: usize) {
+        if !self.recording || line == 0 {
+            return;
+        }
+        // Functions loaded before coverage was enabled have no file
+        let Some(file) = self.sources.file_of(function) else {
+            return;
+        };
+        *self.files.entry(file).or_default().entry(line).or_insert(0) += 1;
+    }
+
+    /// Executable lines and their hits, per file in name order
+    pub fn files(&self) -> impl Iterator<Item = (&str, &LineHits)> {
+        self.files
+            .iter()
+            .map(|(file, lines)| (file.as_ref(), lines))
+    }
+
+    /// The report in lcov's tracefile format
+    pub fn lcov(&self) -> String {
+        let mut out = String::new();
+        for (file, lines) in self.files() {
+            let _ = writeln!(out, "TN:\nSF:{}", file);
+            for (line, hits) in lines {
+                let _ = writeln!(out, "DA:{},{}", line, hits);
+            }
+            let hit = lines.values().filter(|hits| **hits > 0).count();
+            let _ = writeln!(out, "LF:{}\nLH:{}\nend_of_record", lines.len(), hit);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::Executor;
+    use crate::lua_interpreter::LuaInterpreter;
+    use crate::lua_parser::parse_source;
+    use crate::resolver::resolve;
+
+    fn covered(code: &str) -> (LuaInterpreter, String) {
+        let block = resolve(&parse_source(code).unwrap());
+        let mut interp = LuaInterpreter::new();
+        interp.coverage.borrow_mut().enable();
+        interp.coverage.borrow_mut().enter_chunk("main.lua", &block);
+        Executor::new().execute_block(&block, &mut interp).unwrap();
+        interp.coverage.borrow_mut().leave_chunk();
+        let report = interp.coverage.borrow().lcov();
+        (interp, report)
+    }
+
+    #[test]
+    fn test_lines_hit_and_missed() {
+        let (_, report) = covered(
+            "local function pick(x)
+               if x then
+                 return 1
+               end
+               return 2
+             end
+             for i = 1, 3 do
+               pick(true)
+             end",
+        );
+        assert_eq!(
+            report,
+            "TN:\nSF:main.lua\nDA:1,1\nDA:2,3\nDA:3,3\nDA:5,0\nDA:7,1\nDA:8,3\n\
+             LF:6\nLH:5\nend_of_record\n"
+        );
+    }
+
+    #[test]
+    fn test_scripts_pause_recording() {
+        let (interp, report) = covered(
+            "local n = 0
+             coverage.stop()
+             n = n + 1
+             started = coverage.start()
+             n = n + 2
+             report = coverage.report()",
+        );
+        assert!(report.contains("DA:3,0\n"), "{}", report);
+        assert!(report.contains("DA:5,1\n"), "{}", report);
+        // The report a script asks for already counts the statement asking
+        let LuaValue::String(seen) = interp.get_global("report") else {
+            panic!("report should be a string");
+        };
+        assert!(seen.to_string().contains("DA:5,1\n"), "{}", seen);
+        assert!(seen.to_string().contains("DA:6,1\n"), "{}", seen);
+        assert_eq!(interp.get_global("started"), LuaValue::Boolean(true));
+    }
+
+    #[test]
+    fn test_disabled_coverage_records_nothing() {
+        let block = resolve(&parse_source("x = 1").unwrap());
+        let mut interp = LuaInterpreter::new();
+        interp.coverage.borrow_mut().enter_chunk("main.lua", &block);
+        Executor::new().execute_block(&block, &mut interp).unwrap();
+        assert_eq!(interp.coverage.borrow().lcov(), "");
+        assert!(!interp.coverage.borrow_mut().start());
+
+        let block = resolve(&parse_source("ok, message = coverage.start()").unwrap());
+        Executor::new().execute_block(&block, &mut interp).unwrap();
+        assert_eq!(interp.get_global("ok"), LuaValue::Nil);
+        assert_eq!(
+            interp.get_global("message"),
+            LuaValue::String("coverage is not enabled (run the script with --coverage)".into())
+        );
+    }
+}
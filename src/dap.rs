@@ -0,0 +1,676 @@
+//! Debug Adapter Protocol mode, run by `muscm dap`
+//!
+//! Speaks DAP over stdio with the same `Content-Length` framing as the
+//! language server, so editors can debug Lua scripts on the tree-walker's
+//! step debugger. A `launch` request names the script in `program`, with
+//! optional `args` and `stopOnEntry`; the script starts once the client
+//! sends `configurationDone`, and what it prints is sent as `output`
+//! events. It reads no input, since stdin carries the protocol.
+//!
+//! Requests are only read while the script is stopped: breakpoints set
+//! while it runs take effect at the next stop, and `pause` is not
+//! supported. There is one thread. Variables cover the locals of the
+//! innermost call and the globals, and tables in them can be expanded.
+//! `evaluate` and `setVariable` take Lua expressions, which see globals
+//! and top-level locals; evaluating a bare name also finds the locals of
+//! the running function, which is what hovers ask for.
+use crate::debugger::{DebugEvent, Resume, StopReason};
+use crate::error_types::{LuaError, LuaResult};
+use crate::executor::Executor;
+use crate::framing::{read_message, write_message};
+use crate::lua_interpreter::LuaInterpreter;
+use crate::lua_parser::parse_source;
+use crate::lua_value::LuaValue;
+use crate::resolver::resolve;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{self, Path};
+use std::rc::Rc;
+
+/// The one thread scripts run on
+const THREAD_ID: u64 = 1;
+
+// Variable references of the two scopes; tables take the numbers after
+const LOCALS: u64 = 1;
+const GLOBALS: u64 = 2;
+
+/// What `launch` asked to run
+struct Launch {
+    program: String,
+    args: Vec<String>,
+    stop_on_entry: bool,
+}
+
+/// State of one debug session, shared with the debug handler while the
+/// script runs
+struct Session {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    seq: u64,
+    launch: Option<Launch>,
+    configured: bool,
+    /// The next stop is the one `stopOnEntry` asked for
+    entry: bool,
+    /// Breakpoint lines by source path, as the client last set them
+    breakpoints: BTreeMap<String, Vec<usize>>,
+    /// Tables the client can expand at this stop, by variable reference
+    /// less `GLOBALS + 1`
+    tables: Vec<LuaValue>,
+    disconnected: bool,
+    /// An I/O error met while the script was stopped, which ends the
+    /// session once the script has been cancelled
+    error: Option<io::Error>,
+}
+
+type Shared = Rc<RefCell<Session>>;
+
+impl Session {
+    fn read(&mut self) -> io::Result<Option<Value>> {
+        read_message(&mut self.input)
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = self.seq.into();
+        write_message(&mut self.output, &message)
+    }
+
+    fn respond(&mut self, request: &Value, body: Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "success": true,
+            "command": request["command"],
+            "body": body,
+        }))
+    }
+
+    fn fail(&mut self, request: &Value, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "success": false,
+            "command": request["command"],
+            "message": message,
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    /// Send script output; a client that has gone away is noticed at the
+    /// next request instead
+    fn output(&mut self, category: &str, text: &str) {
+        let _ = self.event("output", json!({ "category": category, "output": text }));
+    }
+
+    /// Handle the requests that are answered the same whether or not the
+    /// script is stopped; false for any other
+    fn handle_common(&mut self, request: &Value) -> io::Result<bool> {
+        let arguments = &request["arguments"];
+        match request["command"].as_str().unwrap_or_default() {
+            "initialize" => {
+                self.respond(
+                    request,
+                    json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsSetVariable": true,
+                        "supportsEvaluateForHovers": true,
+                    }),
+                )?;
+                self.event("initialized", json!({}))?;
+            }
+            "launch" => match arguments["program"].as_str() {
+                Some(program) => {
+                    let args = arguments["args"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|arg| arg.as_str().map(String::from))
+                        .collect();
+                    self.launch = Some(Launch {
+                        program: program.to_string(),
+                        args,
+                        stop_on_entry: arguments["stopOnEntry"].as_bool().unwrap_or(false),
+                    });
+                    self.respond(request, Value::Null)?;
+                }
+                None => self.fail(request, "launch needs a program")?,
+            },
+            "configurationDone" => {
+                self.configured = true;
+                self.respond(request, Value::Null)?;
+            }
+            "setBreakpoints" => {
+                let path = arguments["source"]["path"].as_str().unwrap_or_default();
+                let lines: Vec<usize> = arguments["breakpoints"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|bp| bp["line"].as_u64().map(|line| line as usize))
+                    .collect();
+                let verified: Vec<Value> = lines
+                    .iter()
+                    .map(|line| json!({ "verified": true, "line": line }))
+                    .collect();
+                self.breakpoints.insert(path.to_string(), lines);
+                self.respond(request, json!({ "breakpoints": verified }))?;
+            }
+            "threads" => self.respond(
+                request,
+                json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] }),
+            )?,
+            "disconnect" | "terminate" => {
+                self.disconnected = true;
+                self.respond(request, Value::Null)?;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// The variable reference for `value`: a new one for tables, so the
+    /// client can expand them, and 0 for everything else
+    fn reference(&mut self, value: &LuaValue) -> u64 {
+        match value {
+            LuaValue::Table(_) => {
+                self.tables.push(value.clone());
+                GLOBALS + self.tables.len() as u64
+            }
+            _ => 0,
+        }
+    }
+
+    fn variable(&mut self, name: &str, value: &LuaValue) -> Value {
+        json!({
+            "name": name,
+            "value": show(value),
+            "type": value.type_name(),
+            "variablesReference": self.reference(value),
+        })
+    }
+}
+
+/// Serve one client over `input` and `output` until it disconnects or
+/// hangs up
+pub fn run(input: impl BufRead + 'static, output: impl Write + 'static) -> io::Result<()> {
+    let session = Rc::new(RefCell::new(Session {
+        input: Box::new(input),
+        output: Box::new(output),
+        seq: 0,
+        launch: None,
+        configured: false,
+        entry: false,
+        breakpoints: BTreeMap::new(),
+        tables: Vec::new(),
+        disconnected: false,
+        error: None,
+    }));
+    loop {
+        let mut s = session.borrow_mut();
+        let Some(request) = s.read()? else {
+            return Ok(());
+        };
+        if !s.handle_common(&request)? {
+            s.fail(&request, "the script is not stopped")?;
+        }
+        if s.disconnected {
+            return Ok(());
+        }
+        if s.configured && s.launch.is_some() {
+            let launch = s.launch.take().expect("checked above");
+            drop(s);
+            debug(&session, launch)?;
+            if session.borrow().disconnected {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Run the launched script to its end, then tell the client it exited
+fn debug(session: &Shared, launch: Launch) -> io::Result<()> {
+    // Breakpoints arrive with absolute paths, which the running file must
+    // end with
+    let program = path::absolute(&launch.program)
+        .map_or(launch.program.clone(), |path| path.display().to_string());
+    let mut interpreter = LuaInterpreter::new();
+    interpreter.set_script_args(&program, &[], &launch.args);
+    if let Some(dir) = Path::new(&program).parent() {
+        interpreter.add_module_search_path(dir.to_path_buf());
+    }
+    interpreter.input.set_text("");
+    let events = Rc::clone(session);
+    interpreter
+        .output
+        .set_callback(move |text| events.borrow_mut().output("stdout", text));
+
+    let mut executor = Executor::new();
+    for (file, lines) in &session.borrow().breakpoints {
+        for line in lines {
+            executor.set_breakpoint(file, *line);
+        }
+    }
+    if launch.stop_on_entry {
+        executor.pause();
+        session.borrow_mut().entry = true;
+    }
+    let handler = Rc::clone(session);
+    executor.set_debug_handler(move |event| stop(&handler, event));
+
+    let result = fs::read_to_string(&program)
+        .map_err(|e| format!("cannot read {}: {}", program, e))
+        .and_then(|code| parse_source(&code))
+        .and_then(|block| {
+            match executor.execute_file(&program, &resolve(&block), &mut interpreter) {
+                Ok(_) | Err(LuaError::Cancelled) => Ok(()),
+                Err(e) => Err(match interpreter.error_traceback(&e) {
+                    Some(traceback) => format!("{}\n{}", e, traceback),
+                    None => e.to_string(),
+                }),
+            }
+        });
+
+    let mut s = session.borrow_mut();
+    if let Some(error) = s.error.take() {
+        return Err(error);
+    }
+    if s.disconnected {
+        return Ok(());
+    }
+    if let Err(message) = &result {
+        s.output("stderr", &format!("{}\n", message));
+    }
+    s.event("exited", json!({ "exitCode": i32::from(result.is_err()) }))?;
+    s.event("terminated", json!({}))
+}
+
+/// Tell the client the script stopped and answer its requests until one
+/// resumes it; disconnecting stops the script
+fn stop(session: &Shared, event: &mut DebugEvent<'_>) -> LuaResult<Resume> {
+    let stopped = {
+        let mut s = session.borrow_mut();
+        let reason = match event.reason {
+            _ if s.entry => "entry",
+            StopReason::Breakpoint => "breakpoint",
+            StopReason::Step => "step",
+        };
+        s.entry = false;
+        s.tables.clear();
+        s.event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+        )
+    };
+    let answer = stopped.and_then(|_| loop {
+        let request = session.borrow_mut().read()?;
+        let Some(request) = request else {
+            session.borrow_mut().disconnected = true;
+            break Ok(None);
+        };
+        let resume = handle_stopped(session, event, &request)?;
+        if resume.is_some() || session.borrow().disconnected {
+            break Ok(resume);
+        }
+    });
+    match answer {
+        Ok(Some(resume)) => Ok(resume),
+        Ok(None) => Err(LuaError::Cancelled),
+        Err(error) => {
+            session.borrow_mut().error = Some(error);
+            Err(LuaError::Cancelled)
+        }
+    }
+}
+
+/// Answer one request at a stop, returning how to resume when it asks to
+fn handle_stopped(
+    session: &Shared,
+    event: &mut DebugEvent<'_>,
+    request: &Value,
+) -> io::Result<Option<Resume>> {
+    let arguments = &request["arguments"];
+    let command = request["command"].as_str().unwrap_or_default();
+    let resume = match command {
+        "continue" => Some(Resume::Continue),
+        "next" => Some(Resume::StepOver),
+        "stepIn" => Some(Resume::StepInto),
+        "stepOut" => Some(Resume::StepOut),
+        _ => None,
+    };
+    if let Some(resume) = resume {
+        let body = match resume {
+            Resume::Continue => json!({ "allThreadsContinued": true }),
+            _ => Value::Null,
+        };
+        session.borrow_mut().respond(request, body)?;
+        return Ok(Some(resume));
+    }
+
+    match command {
+        "setBreakpoints" => {
+            let path = arguments["source"]["path"].as_str().unwrap_or_default();
+            let old: Vec<usize> = event
+                .breakpoints()
+                .filter(|(file, _)| *file == path)
+                .map(|(_, line)| line)
+                .collect();
+            for line in old {
+                event.clear_breakpoint(path, line);
+            }
+            let mut s = session.borrow_mut();
+            s.handle_common(request)?;
+            for line in s.breakpoints.get(path).into_iter().flatten() {
+                event.set_breakpoint(path, *line);
+            }
+        }
+        "stackTrace" => {
+            let mut stack = event.stack();
+            if let Some(top) = stack.first_mut() {
+                top.line = event.line;
+            }
+            let frames: Vec<Value> = stack
+                .iter()
+                .enumerate()
+                .map(|(id, entry)| {
+                    let name = entry
+                        .name
+                        .as_ref()
+                        .map_or("main chunk".to_string(), |name| name.name().to_string());
+                    let mut frame =
+                        json!({ "id": id, "name": name, "line": entry.line, "column": 1 });
+                    if let Some(file) = &entry.file {
+                        let short = Path::new(file.as_ref())
+                            .file_name()
+                            .map_or(file.to_string(), |name| name.to_string_lossy().into_owned());
+                        frame["source"] = json!({ "name": short, "path": file.as_ref() });
+                    }
+                    frame
+                })
+                .collect();
+            session.borrow_mut().respond(
+                request,
+                json!({ "stackFrames": frames, "totalFrames": frames.len() }),
+            )?;
+        }
+        "scopes" => {
+            let globals =
+                json!({ "name": "Globals", "variablesReference": GLOBALS, "expensive": true });
+            // Only the innermost call's locals can be read
+            let scopes = if arguments["frameId"].as_u64().unwrap_or(0) == 0 {
+                json!([{ "name": "Locals", "variablesReference": LOCALS }, globals])
+            } else {
+                json!([globals])
+            };
+            session
+                .borrow_mut()
+                .respond(request, json!({ "scopes": scopes }))?;
+        }
+        "variables" => {
+            let reference = arguments["variablesReference"].as_u64().unwrap_or(0);
+            let named: Vec<(String, LuaValue)> = match reference {
+                LOCALS => event
+                    .locals()
+                    .into_iter()
+                    .map(|(name, value)| (name.to_string(), value))
+                    .collect(),
+                GLOBALS => {
+                    let interp = event.interpreter();
+                    let sorted: BTreeMap<String, LuaValue> = interp
+                        .global_names()
+                        .into_iter()
+                        .map(|name| {
+                            let value = interp.get_global(&name);
+                            (name, value)
+                        })
+                        .collect();
+                    sorted.into_iter().collect()
+                }
+                _ => table_entries(&session.borrow(), reference),
+            };
+            let mut s = session.borrow_mut();
+            let variables: Vec<Value> = named
+                .iter()
+                .map(|(name, value)| s.variable(name, value))
+                .collect();
+            s.respond(request, json!({ "variables": variables }))?;
+        }
+        "setVariable" => {
+            let reference = arguments["variablesReference"].as_u64().unwrap_or(0);
+            let name = arguments["name"].as_str().unwrap_or_default();
+            let value = arguments["value"].as_str().unwrap_or_default();
+            let result = event.evaluate(value).and_then(|value| {
+                let set = match reference {
+                    LOCALS => event.set_local(name, value.clone()),
+                    GLOBALS => {
+                        event.interpreter().set_global(name, value.clone());
+                        true
+                    }
+                    _ => false,
+                };
+                if set {
+                    Ok(value)
+                } else {
+                    Err(format!("cannot set {}", name))
+                }
+            });
+            let mut s = session.borrow_mut();
+            match result {
+                Ok(value) => {
+                    let variable = s.variable(name, &value);
+                    s.respond(request, variable)?;
+                }
+                Err(message) => s.fail(request, &message)?,
+            }
+        }
+        "evaluate" => {
+            let expression = arguments["expression"].as_str().unwrap_or_default();
+            let result = match event.local(expression.trim()) {
+                Some(value) => Ok(value),
+                None => event.evaluate(expression),
+            };
+            let mut s = session.borrow_mut();
+            match result {
+                Ok(value) => {
+                    let reference = s.reference(&value);
+                    s.respond(
+                        request,
+                        json!({ "result": show(&value), "variablesReference": reference }),
+                    )?;
+                }
+                Err(message) => s.fail(request, &message)?,
+            }
+        }
+        _ => {
+            let mut s = session.borrow_mut();
+            if !s.handle_common(request)? {
+                s.fail(request, &format!("unsupported request {}", command))?;
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// The entries of the table behind a variable reference, with keys named
+/// as Lua code would index them
+fn table_entries(session: &Session, reference: u64) -> Vec<(String, LuaValue)> {
+    let index = reference.checked_sub(GLOBALS + 1).map(|i| i as usize);
+    let Some(LuaValue::Table(table)) = index.and_then(|i| session.tables.get(i)) else {
+        return Vec::new();
+    };
+    let table = table.borrow();
+    table
+        .data
+        .iter()
+        .map(|(key, value)| {
+            let name = match key {
+                LuaValue::String(key) => key.to_string(),
+                key => format!("[{}]", show(key)),
+            };
+            (name, value.clone())
+        })
+        .collect()
+}
+
+/// A value as the client shows it, with strings quoted
+fn show(value: &LuaValue) -> String {
+    match value {
+        LuaValue::String(s) => format!("{:?}", s.to_str_lossy()),
+        value => value.to_string_value(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Output shared with the test after the session takes it
+    #[derive(Clone, Default)]
+    struct Sink(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Run a session on `requests` and return what the adapter sent
+    fn session(requests: &[Value]) -> Vec<Value> {
+        let mut input = Vec::new();
+        for (seq, request) in requests.iter().enumerate() {
+            let mut request = request.clone();
+            request["seq"] = (seq + 1).into();
+            request["type"] = "request".into();
+            write_message(&mut input, &request).unwrap();
+        }
+        let sink = Sink::default();
+        run(io::Cursor::new(input), sink.clone()).unwrap();
+        let bytes = sink.0.borrow().clone();
+        let mut bytes = &bytes[..];
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut bytes).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    fn script(name: &str, code: &str) -> String {
+        let path = std::env::temp_dir().join(format!("muscm_dap_{}_{}", std::process::id(), name));
+        fs::write(&path, code).unwrap();
+        path.display().to_string()
+    }
+
+    fn response<'a>(messages: &'a [Value], command: &str) -> Vec<&'a Value> {
+        messages
+            .iter()
+            .filter(|m| m["type"] == "response" && m["command"] == command)
+            .collect()
+    }
+
+    fn events<'a>(messages: &'a [Value], event: &str) -> Vec<&'a Value> {
+        messages.iter().filter(|m| m["event"] == event).collect()
+    }
+
+    #[test]
+    fn test_breakpoint_stop_inspect_and_step() {
+        let program = script(
+            "stop.lua",
+            "local function double(n)\n  local twice = n * 2\n  return twice\nend\nprint(double(4))\nprint(double(5))\n",
+        );
+        let messages = session(&[
+            json!({ "command": "initialize", "arguments": {} }),
+            json!({ "command": "launch", "arguments": { "program": program } }),
+            json!({ "command": "setBreakpoints", "arguments": {
+                "source": { "path": program }, "breakpoints": [{ "line": 3 }] } }),
+            json!({ "command": "configurationDone" }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "variables", "arguments": { "variablesReference": LOCALS } }),
+            json!({ "command": "setVariable", "arguments": {
+                "variablesReference": LOCALS, "name": "twice", "value": "100" } }),
+            json!({ "command": "evaluate", "arguments": { "expression": "n" } }),
+            json!({ "command": "setBreakpoints", "arguments": {
+                "source": { "path": program }, "breakpoints": [] } }),
+            json!({ "command": "stepOut", "arguments": { "threadId": 1 } }),
+            json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "command": "disconnect" }),
+        ]);
+        assert!(
+            messages.iter().all(|m| m["success"] != false),
+            "{:?}",
+            messages
+        );
+        assert_eq!(events(&messages, "initialized").len(), 1);
+        let stopped = events(&messages, "stopped");
+        assert_eq!(stopped.len(), 2);
+        assert_eq!(stopped[0]["body"]["reason"], "breakpoint");
+        assert_eq!(stopped[1]["body"]["reason"], "step");
+
+        let frames = &response(&messages, "stackTrace")[0]["body"]["stackFrames"];
+        assert_eq!(frames[0]["name"], "double");
+        assert_eq!(frames[0]["line"], 3);
+        assert_eq!(frames[0]["source"]["path"], program.as_str());
+        assert_eq!(frames[1]["name"], "main chunk");
+        assert_eq!(frames[1]["line"], 5);
+
+        let locals = &response(&messages, "variables")[0]["body"]["variables"];
+        assert_eq!(locals[0]["name"], "n");
+        assert_eq!(locals[1]["value"], "8");
+        assert_eq!(response(&messages, "evaluate")[0]["body"]["result"], "4");
+
+        let output: String = events(&messages, "output")
+            .iter()
+            .map(|m| m["body"]["output"].as_str().unwrap())
+            .collect();
+        assert_eq!(output, "100\n10\n");
+        assert_eq!(events(&messages, "exited")[0]["body"]["exitCode"], 0);
+        assert_eq!(events(&messages, "terminated").len(), 1);
+    }
+
+    #[test]
+    fn test_stop_on_entry_tables_and_errors() {
+        let program = script(
+            "entry.lua",
+            "local t = {1, name = \"x\"}\nerror(\"boom\")\n",
+        );
+        let messages = session(&[
+            json!({ "command": "initialize", "arguments": {} }),
+            json!({ "command": "launch", "arguments": { "program": program, "stopOnEntry": true } }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "configurationDone" }),
+            json!({ "command": "next", "arguments": { "threadId": 1 } }),
+            json!({ "command": "evaluate", "arguments": { "expression": "t" } }),
+            json!({ "command": "variables", "arguments": { "variablesReference": GLOBALS + 1 } }),
+            json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "command": "disconnect" }),
+        ]);
+        // Before the script starts there is no stack to show
+        assert_eq!(response(&messages, "stackTrace")[0]["success"], false);
+        let stopped = events(&messages, "stopped");
+        assert_eq!(stopped[0]["body"]["reason"], "entry");
+        assert_eq!(stopped[1]["body"]["reason"], "step");
+
+        let evaluated = &response(&messages, "evaluate")[0]["body"];
+        assert_eq!(evaluated["variablesReference"], GLOBALS + 1);
+        let fields = &response(&messages, "variables")[0]["body"]["variables"];
+        assert_eq!(fields[0]["name"], "[1]");
+        assert_eq!(fields[1]["name"], "name");
+        assert_eq!(fields[1]["value"], "\"x\"");
+
+        let stderr = events(&messages, "output");
+        assert_eq!(stderr[0]["body"]["category"], "stderr");
+        assert!(stderr[0]["body"]["output"]
+            .as_str()
+            .unwrap()
+            .contains("boom"));
+        assert_eq!(events(&messages, "exited")[0]["body"]["exitCode"], 1);
+    }
+}
@@ -0,0 +1,377 @@
+/// Step debugging for Lua scripts run on the tree-walker
+///
+/// An executor with a debug handler (`Executor::set_debug_handler`) calls
+/// it before a statement that starts a new line, when that line has a
+/// breakpoint (`Executor::set_breakpoint`) or the last stop asked to step.
+/// The handler gets a `DebugEvent` for reading and changing the locals of
+/// the running function, and returns how to go on: `Resume::Continue`
+/// runs to the next breakpoint, `Resume::StepInto` stops at the next line
+/// wherever it is, and `Resume::StepOver` at the next line of the same
+/// function or one of its callers, and `Resume::StepOut` at the next line
+/// of a caller. Returning an error stops the script with it.
+///
+/// Breakpoints name the file a chunk was run from with
+/// `Executor::execute_file`, which is the path `require` found for a
+/// module; a breakpoint's file matches any path ending with it. Code run
+/// by another executor, such as a coroutine body, does not stop.
+use crate::error_types::LuaResult;
+use crate::executor::Executor;
+use crate::lua_interpreter::LuaInterpreter;
+use crate::lua_parser::{parse_source, Chunk};
+use crate::lua_value::{LuaFunction, LuaValue};
+use crate::source_map::SourceMap;
+use crate::traceback::CallName;
+use crate::upvalues::UpvalueCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::rc::Rc;
+
+/// How a script goes on after a debug stop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    /// Run until a breakpoint
+    Continue,
+    /// Stop at the next line, entering any function it calls
+    StepInto,
+    /// Stop at the next line without entering functions
+    StepOver,
+    /// Stop at the next line after the running function returns; runs
+    /// to a breakpoint from a chunk's top-level code
+    StepOut,
+}
+
+/// Why the script stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Breakpoint,
+    Step,
+}
+
+/// One call on the stack at a debug stop
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackEntry {
+    /// How the caller named the function; `None` for a chunk's top-level
+    /// code and unnamed calls
+    pub name: Option<CallName>,
+    pub file: Option<Rc<str>>,
+    /// Line running in this call, the call's own line in callers
+    pub line: usize,
+}
+
+/// Called at every debug stop
+pub type DebugHandler = Box<dyn FnMut(&mut DebugEvent<'_>) -> LuaResult<Resume>>;
+
+/// A script stopped before a statement
+pub struct DebugEvent<'a> {
+    /// File of the statement; `None` when its chunk was run without one
+    pub file: Option<Rc<str>>,
+    pub line: usize,
+    pub reason: StopReason,
+    /// Calls running, 0 in a chunk's top-level code
+    pub depth: usize,
+    interp: &'a mut LuaInterpreter,
+    breakpoints: &'a mut BTreeSet<(String, usize)>,
+    sources: &'a SourceMap,
+}
+
+impl DebugEvent<'_> {
+    /// Locals of the running function with their values, in declaration
+    /// order for resolved functions and by name for top-level code
+    ///
+    /// Locals that have not been declared yet read as `nil`, and a slot
+    /// that sibling blocks share goes by the first local declared in it,
+    /// as `debug.getlocal` reports them.
+    pub fn locals(&self) -> Vec<(Rc<str>, LuaValue)> {
+        self.cells()
+            .into_iter()
+            .map(|(name, cell)| (name, cell.borrow().clone()))
+            .collect()
+    }
+
+    /// The value of the innermost local called `name`
+    pub fn local(&self, name: &str) -> Option<LuaValue> {
+        self.find(name).map(|cell| cell.borrow().clone())
+    }
+
+    /// Assign the innermost local called `name`; false when there is none
+    pub fn set_local(&mut self, name: &str, value: LuaValue) -> bool {
+        match self.find(name) {
+            Some(cell) => {
+                *cell.borrow_mut() = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Calls of Lua functions running, innermost first, ending with the
+    /// top-level code of the chunk that is running
+    pub fn stack(&self) -> Vec<StackEntry> {
+        let trace = self.interp.trace.borrow();
+        let mut stack: Vec<StackEntry> = trace
+            .frames()
+            .iter()
+            .rev()
+            .filter(|frame| !frame.is_builtin())
+            .map(|frame| StackEntry {
+                name: frame.name.clone(),
+                file: self.sources.file_of(Some(&frame.function)),
+                line: frame.line,
+            })
+            .collect();
+        stack.push(StackEntry {
+            name: None,
+            file: self.sources.file_of(None),
+            line: trace.chunk_line(),
+        });
+        stack
+    }
+
+    /// Evaluate `expr` as a Lua expression; it sees the locals `locals`
+    /// lists, and globals
+    pub fn evaluate(&mut self, expr: &str) -> Result<LuaValue, String> {
+        let block = parse_source(&format!("return {}", expr))?;
+        let expr = block
+            .return_statement
+            .as_ref()
+            .and_then(|ret| ret.expression_list.first())
+            .ok_or("expected an expression")?;
+        let cells = self.cells();
+        self.interp.push_scope();
+        for (name, cell) in cells {
+            self.interp.define_cell(name.to_string(), cell);
+        }
+        let result = Executor::new().eval_expression(expr, self.interp);
+        self.interp.pop_scope();
+        result.map_err(|e| e.to_string())
+    }
+
+    /// The interpreter, for globals and the call trace
+    pub fn interpreter(&mut self) -> &mut LuaInterpreter {
+        self.interp
+    }
+
+    /// Stop before the statement on `line` of `file` from now on
+    pub fn set_breakpoint(&mut self, file: &str, line: usize) {
+        self.breakpoints.insert((file.to_string(), line));
+    }
+
+    /// Remove a breakpoint; false when there was none
+    pub fn clear_breakpoint(&mut self, file: &str, line: usize) -> bool {
+        self.breakpoints.remove(&(file.to_string(), line))
+    }
+
+    /// Breakpoints in file and line order
+    pub fn breakpoints(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.breakpoints
+            .iter()
+            .map(|(file, line)| (file.as_str(), *line))
+    }
+
+    fn find(&self, name: &str) -> Option<UpvalueCell> {
+        self.cells()
+            .into_iter()
+            .rev()
+            .find(|(local, _)| local.as_ref() == name)
+            .map(|(_, cell)| cell)
+    }
+
+    /// Cells of the running function's locals, innermost last
+    fn cells(&self) -> Vec<(Rc<str>, UpvalueCell)> {
+        let trace = self.interp.trace.borrow();
+        if let Some(frame) = trace.frames().last() {
+            if let LuaValue::Function(f) = &frame.function {
+                if let LuaFunction::User { body, .. } = f.as_ref() {
+                    if let (Some(layout), Some(index)) = (&body.layout, frame.slot_frame) {
+                        let slots = self.interp.frames.get(index).map_or(&[][..], |f| &f.slots);
+                        return layout
+                            .slot_names
+                            .iter()
+                            .cloned()
+                            .zip(slots.iter().cloned())
+                            .collect();
+                    }
+                }
+            }
+        }
+        // Top-level code and functions without slots keep their locals in
+        // name-keyed scopes, where inner ones shadow outer ones
+        let mut cells = BTreeMap::new();
+        for scope in &self.interp.scope_stack {
+            for (name, cell) in scope {
+                cells.insert(Rc::from(name.as_str()), Rc::clone(cell));
+            }
+        }
+        cells.into_iter().collect()
+    }
+}
+
+/// Breakpoints, stepping state and the handler of one executor
+#[derive(Default)]
+pub(crate) struct Debugger {
+    pub(crate) handler: Option<DebugHandler>,
+    pub(crate) breakpoints: BTreeSet<(String, usize)>,
+    sources: SourceMap,
+    /// Stop at the next line running at most this many calls deep;
+    /// `None` runs to a breakpoint
+    step: Option<usize>,
+}
+
+impl Debugger {
+    /// Stop at the next line, as if the last stop had asked to step into
+    pub(crate) fn pause(&mut self) {
+        self.step = Some(usize::MAX);
+    }
+
+    pub(crate) fn enter_chunk(&mut self, file: &str, chunk: &Chunk) {
+        let file: Rc<str> = file.into();
+        if self.handler.is_some() {
+            self.sources.register(&file, chunk);
+        }
+        self.sources.enter_chunk(file);
+    }
+
+    pub(crate) fn leave_chunk(&mut self) {
+        self.sources.leave_chunk();
+    }
+
+    /// Call the handler if the statement on `line` should stop
+    pub(crate) fn before_line(
+        &mut self,
+        line: usize,
+        interp: &mut LuaInterpreter,
+    ) -> LuaResult<()> {
+        let Some(handler) = self.handler.as_mut() else {
+            return Ok(());
+        };
+        let (depth, file) = {
+            let trace = interp.trace.borrow();
+            let function = trace.frames().last().map(|frame| &frame.function);
+            (trace.depth(), self.sources.file_of(function))
+        };
+        let reason = if self.step.is_some_and(|step| depth <= step) {
+            StopReason::Step
+        } else if file
+            .as_ref()
+            .is_some_and(|file| has_breakpoint(&self.breakpoints, file, line))
+        {
+            StopReason::Breakpoint
+        } else {
+            return Ok(());
+        };
+        let mut event = DebugEvent {
+            file,
+            line,
+            reason,
+            depth,
+            interp,
+            breakpoints: &mut self.breakpoints,
+            sources: &self.sources,
+        };
+        self.step = match handler(&mut event)? {
+            Resume::Continue => None,
+            Resume::StepInto => Some(usize::MAX),
+            Resume::StepOver => Some(depth),
+            Resume::StepOut => depth.checked_sub(1),
+        };
+        Ok(())
+    }
+}
+
+fn has_breakpoint(breakpoints: &BTreeSet<(String, usize)>, file: &str, line: usize) -> bool {
+    breakpoints
+        .iter()
+        .any(|(bp_file, bp_line)| *bp_line == line && Path::new(file).ends_with(bp_file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::resolve;
+    use std::cell::RefCell;
+
+    const SCRIPT: &str = "local function double(n)
+  local twice = n * 2
+  return twice
+end
+local x = 1
+local y = double(x)
+result = y";
+
+    /// Run `SCRIPT` as main.lua, answering each stop with the next of
+    /// `answers` and recording where it stopped
+    fn debug(
+        breakpoints: &[usize],
+        answers: Vec<Resume>,
+    ) -> (Vec<(usize, StopReason, usize)>, LuaInterpreter) {
+        let block = resolve(&parse_source(SCRIPT).unwrap());
+        let stops = Rc::new(RefCell::new(Vec::new()));
+        let mut answers = answers.into_iter();
+        let seen = Rc::clone(&stops);
+        let mut executor = Executor::new();
+        executor.set_debug_handler(move |event| {
+            seen.borrow_mut()
+                .push((event.line, event.reason, event.depth));
+            Ok(answers.next().unwrap_or(Resume::Continue))
+        });
+        for line in breakpoints {
+            executor.set_breakpoint("main.lua", *line);
+        }
+        let mut interp = LuaInterpreter::new();
+        executor
+            .execute_file("main.lua", &block, &mut interp)
+            .unwrap();
+        let stops = stops.borrow().clone();
+        (stops, interp)
+    }
+
+    #[test]
+    fn test_breakpoints_and_stepping() {
+        let (stops, _) = debug(&[6], vec![Resume::StepInto, Resume::StepOver]);
+        assert_eq!(
+            stops,
+            [
+                (6, StopReason::Breakpoint, 0),
+                (2, StopReason::Step, 1),
+                (3, StopReason::Step, 1),
+            ]
+        );
+
+        let (stops, _) = debug(&[5], vec![Resume::StepOver, Resume::StepOver]);
+        let lines: Vec<usize> = stops.iter().map(|(line, _, _)| *line).collect();
+        assert_eq!(lines, [5, 6, 7]);
+
+        let (stops, _) = debug(&[2], vec![Resume::StepOut]);
+        let lines: Vec<usize> = stops.iter().map(|(line, _, _)| *line).collect();
+        assert_eq!(lines, [2, 7]);
+    }
+
+    #[test]
+    fn test_handler_reads_and_writes_locals() {
+        let block = resolve(&parse_source(SCRIPT).unwrap());
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let locals = Rc::clone(&seen);
+        let mut executor = Executor::new();
+        executor.set_breakpoint("main.lua", 3);
+        executor.set_debug_handler(move |event| {
+            locals.borrow_mut().extend(event.locals());
+            let stack = event.stack();
+            let lines: Vec<usize> = stack.iter().map(|entry| entry.line).collect();
+            assert_eq!(lines, [3, 6]);
+            assert_eq!(stack[0].name, Some(CallName::Global("double".into())));
+            assert_eq!(stack[1].file.as_deref(), Some("main.lua"));
+            assert_eq!(event.evaluate("n + 1"), Ok(LuaValue::Number(2.0)));
+            assert!(event.set_local("twice", LuaValue::Number(40.0)));
+            assert!(!event.set_local("missing", LuaValue::Nil));
+            Ok(Resume::Continue)
+        });
+        let mut interp = LuaInterpreter::new();
+        executor
+            .execute_file("main.lua", &block, &mut interp)
+            .unwrap();
+        let names: Vec<String> = seen.borrow().iter().map(|(n, _)| n.to_string()).collect();
+        assert_eq!(names, ["n", "twice"]);
+        assert_eq!(seen.borrow()[1].1, LuaValue::Number(2.0));
+        assert_eq!(interp.get_global("result"), LuaValue::Number(40.0));
+    }
+}
@@ -51,6 +51,8 @@ pub enum LuaError {
     },
     /// Attempt to call non-callable
     CallError { value_type: String },
+    /// The host cancelled the script through its `CancellationToken`
+    Cancelled,
 }
 
 impl LuaError {
@@ -164,6 +166,7 @@ impl LuaError {
             LuaError::DivisionByZero => "arithmetic",
             LuaError::IndexError { .. } => "index",
             LuaError::CallError { .. } => "call",
+            LuaError::Cancelled => "cancelled",
         }
     }
 
@@ -215,6 +218,7 @@ impl LuaError {
             LuaError::CallError { value_type } => {
                 format!("Attempt to call {} (not a function)", value_type)
             }
+            LuaError::Cancelled => "script cancelled".to_string(),
         }
     }
 }
@@ -0,0 +1,373 @@
+//! Evaluating source text in one call
+//!
+//! `eval_lua` and `eval_scheme` parse, prepare and run a chunk in a fresh
+//! interpreter and hand back its value, for hosts that only need a result.
+//! A `Session` keeps both interpreters between calls, so definitions made by
+//! one evaluation are seen by the next, as at the REPL.
+//!
+//! A Lua chunk that is a bare expression is evaluated as if it started with
+//! `return`, and its first return value is the result. A Scheme chunk's
+//! result is the value of its last form.
+//!
+//! `Session::execute_streaming` runs a Lua chunk while it is still being
+//! read, one top-level statement at a time, for generated scripts too large
+//! to hold whole alongside their syntax tree.
+
+use crate::error_types::{LuaError, LuaResult};
+use crate::executor::ControlFlow;
+use crate::interpreter::{Environment, Interpreter, SVal};
+use crate::lua_interpreter::LuaInterpreter;
+use crate::lua_parser::{
+    parse_located, parse_located_statement, tokenize_with_location, Chunk, Location,
+    TokenWithLocation,
+};
+use crate::lua_value::LuaValue;
+use crate::macro_expander::expand_program;
+use crate::parser::parse;
+use crate::vm::execute_chunk;
+use std::io::{BufRead, BufReader, Read};
+
+/// Run a Lua chunk in a fresh interpreter and return its first value
+pub fn eval_lua(source: &str) -> LuaResult<LuaValue> {
+    Session::new().eval_lua(source)
+}
+
+/// Run Scheme source in a fresh environment and return its last value
+pub fn eval_scheme(source: &str) -> Result<SVal, String> {
+    Session::new().eval_scheme(source)
+}
+
+/// Interpreter state kept between evaluations
+pub struct Session {
+    lua: LuaInterpreter,
+    scheme: Environment,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session {
+            lua: LuaInterpreter::new(),
+            scheme: Environment::new(),
+        }
+    }
+
+    /// The Lua interpreter, to set globals or search paths directly
+    pub fn lua(&mut self) -> &mut LuaInterpreter {
+        &mut self.lua
+    }
+
+    /// The Scheme environment, to define names directly
+    pub fn scheme(&mut self) -> &mut Environment {
+        &mut self.scheme
+    }
+
+    /// Run a Lua chunk in this session and return its first value, or nil
+    pub fn eval_lua(&mut self, source: &str) -> LuaResult<LuaValue> {
+        let chunk = parse_lua(&format!("return {}", source)).or_else(|_| parse_lua(source))?;
+        match execute_chunk(&chunk, &mut self.lua)? {
+            ControlFlow::Return(values) => Ok(values.into_iter().next().unwrap_or(LuaValue::Nil)),
+            _ => Ok(LuaValue::Nil),
+        }
+    }
+
+    /// Run the Lua chunk read from `reader` in this session, executing each
+    /// top-level statement as soon as it has been read, and return the
+    /// chunk's first return value, or nil
+    ///
+    /// Only the source of statements not yet run is held, so memory follows
+    /// the largest statement rather than the whole chunk. Statements run
+    /// before the rest of the chunk is parsed, so a syntax error is only
+    /// reported once the statements before it have run, and `goto` cannot
+    /// jump from one top-level statement to another.
+    pub fn execute_streaming(&mut self, reader: impl Read) -> LuaResult<LuaValue> {
+        let mut reader = BufReader::new(reader);
+        let mut pending = String::new();
+        // Where `pending` starts in the whole chunk
+        let mut origin = Location::start();
+        // How much to hold before parsing again; doubled while a statement
+        // is unfinished, so a long one is not tokenized again every line
+        let mut wanted = 0;
+        let mut eof = false;
+        loop {
+            while !eof {
+                let read = reader
+                    .read_line(&mut pending)
+                    .map_err(|e| LuaError::file("<stream>", e.to_string()))?;
+                eof = read == 0;
+                if pending.len() >= wanted {
+                    break;
+                }
+            }
+
+            // An unfinished string or comment fails to tokenize until the
+            // rest of it has been read
+            let relative = match tokenize_with_location(&pending) {
+                Ok(located) => located,
+                Err(_) if !eof => {
+                    wanted = pending.len() * 2;
+                    continue;
+                }
+                Err(e) => {
+                    return Err(LuaError::value(format!(
+                        "Tokenize error in the chunk from line {}: {}",
+                        origin.line, e
+                    )))
+                }
+            };
+            let located: Vec<TokenWithLocation> = relative
+                .iter()
+                .map(|t| TokenWithLocation::new(t.token.clone(), shift(origin, t.location)))
+                .collect();
+
+            let mut done = 0;
+            while done < located.len() {
+                match parse_located_statement(&located[done..]) {
+                    // The next line may carry the statement on
+                    Ok((_, used)) if done + used == located.len() && !eof => break,
+                    Ok((chunk, used)) => {
+                        done += used;
+                        if let ControlFlow::Return(values) = execute_chunk(&chunk, &mut self.lua)? {
+                            return Ok(values.into_iter().next().unwrap_or(LuaValue::Nil));
+                        }
+                    }
+                    Err(error) if error.index.is_none() && !eof => break,
+                    Err(error) => {
+                        let location = match error.index {
+                            Some(i) => located[done + i].location,
+                            None => located.last().map_or(origin, |t| t.end()),
+                        };
+                        return Err(LuaError::parse(
+                            error.to_string(),
+                            location.line,
+                            location.column,
+                        ));
+                    }
+                }
+            }
+            if eof && done == located.len() {
+                return Ok(LuaValue::Nil);
+            }
+
+            let cut = match relative.get(done) {
+                Some(t) => byte_offset(&pending, t.location),
+                None => pending.len(),
+            };
+            origin = shift(origin, end_of(&pending[..cut]));
+            pending.drain(..cut);
+            wanted = pending.len() * 2;
+        }
+    }
+
+    /// Run Scheme source in this session and return the value of its last
+    /// form, or nil when there are none
+    pub fn eval_scheme(&mut self, source: &str) -> Result<SVal, String> {
+        let (mut arena, nodes) = parse(source).map_err(|e| format!("Parse error: {}", e))?;
+        let nodes = expand_program(&mut arena, &nodes)
+            .map_err(|e| format!("Macro expansion error: {}", e))?;
+        let mut last = SVal::Nil;
+        for node in nodes {
+            let expr = arena.get(node).ok_or("Invalid node reference")?;
+            last = Interpreter::eval(expr, &mut self.scheme, &arena)?;
+        }
+        Ok(last)
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parse a Lua chunk, placing a syntax error at the token it broke at, or
+/// after the last token when the chunk ended too early
+fn parse_lua(source: &str) -> LuaResult<Chunk> {
+    let located = tokenize_with_location(source)
+        .map_err(|e| LuaError::value(format!("Tokenize error: {}", e)))?;
+    parse_located(&located).map_err(|error| {
+        let location = match error.index {
+            Some(i) => located[i].location,
+            None => located.last().map_or(Location::start(), |t| t.end()),
+        };
+        LuaError::parse(error.to_string(), location.line, location.column)
+    })
+}
+
+/// `at`, counted from the start of a text, as a location in a chunk where
+/// that text starts at `origin`
+fn shift(origin: Location, at: Location) -> Location {
+    if at.line == 1 {
+        Location::new(origin.line, origin.column + at.column)
+    } else {
+        Location::new(origin.line + at.line - 1, at.column)
+    }
+}
+
+/// Where `text` ends, counted from its start
+fn end_of(text: &str) -> Location {
+    match text.rfind('\n') {
+        Some(newline) => Location::new(
+            1 + text.matches('\n').count(),
+            text[newline + 1..].chars().count(),
+        ),
+        None => Location::new(1, text.chars().count()),
+    }
+}
+
+/// Byte offset of `at` in `text`; columns count characters
+fn byte_offset(text: &str, at: Location) -> usize {
+    let line_start = match at.line {
+        1 => 0,
+        line => text
+            .match_indices('\n')
+            .nth(line - 2)
+            .map_or(text.len(), |(i, _)| i + 1),
+    };
+    text[line_start..]
+        .char_indices()
+        .nth(at.column)
+        .map_or(text.len(), |(i, _)| line_start + i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_one_shot_evaluation() {
+        assert_eq!(eval_lua("1 + 2").unwrap(), LuaValue::Number(3.0));
+        assert_eq!(
+            eval_lua("local t = {} for i = 1, 3 do t[i] = i end return #t, 'more'").unwrap(),
+            LuaValue::Number(3.0)
+        );
+        assert_eq!(eval_lua("x = 1").unwrap(), LuaValue::Nil);
+        assert_eq!(
+            eval_scheme("(define (sq x) (* x x)) (sq 7)").unwrap(),
+            SVal::Integer(49)
+        );
+        assert_eq!(eval_scheme("").unwrap(), SVal::Nil);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            eval_lua("x = = 1").unwrap_err(),
+            LuaError::parse("unexpected `=`, expected an expression", 1, 4)
+        );
+        assert!(matches!(
+            eval_lua("if x then").unwrap_err(),
+            LuaError::ParseError { line: 1, .. }
+        ));
+        assert!(eval_lua("error('boom')")
+            .unwrap_err()
+            .to_string()
+            .contains("boom"));
+        assert!(eval_scheme("(car '())").is_err());
+        assert!(eval_scheme("(1 2").unwrap_err().starts_with("Parse error"));
+    }
+
+    #[test]
+    fn test_streaming_runs_statements_as_they_arrive() {
+        let source = "local t = {}\n\
+            for i = 1, 3 do t[i] = i end s = 'two\\\n\
+            lines' -- a comment\n\
+            n = #t\n\
+            x = 1\n\
+            + 2; return n, s";
+        let mut session = Session::new();
+        assert_eq!(
+            session.execute_streaming(source.as_bytes()).unwrap(),
+            LuaValue::Number(3.0)
+        );
+        assert_eq!(
+            session.eval_lua("s .. x").unwrap(),
+            LuaValue::String("two\nlines3".into())
+        );
+        assert_eq!(
+            session.execute_streaming("".as_bytes()).unwrap(),
+            LuaValue::Nil
+        );
+
+        // The first statement has run by the time reading fails
+        struct Broken;
+        impl Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("gone"))
+            }
+        }
+        let mut session = Session::new();
+        let err = session
+            .execute_streaming("a = 1\nb = 2\n".as_bytes().chain(Broken))
+            .unwrap_err();
+        assert!(err.to_string().contains("gone"), "{}", err);
+        assert_eq!(session.eval_lua("a").unwrap(), LuaValue::Number(1.0));
+        assert_eq!(session.lua().get_global("b"), LuaValue::Nil);
+    }
+
+    #[test]
+    fn test_streaming_errors_are_placed_in_the_chunk() {
+        let mut session = Session::new();
+        assert_eq!(
+            session
+                .execute_streaming("a = 1\nb = 2 c = = 3".as_bytes())
+                .unwrap_err(),
+            LuaError::parse("unexpected `=`, expected an expression", 2, 10)
+        );
+        assert_eq!(session.eval_lua("b").unwrap(), LuaValue::Number(2.0));
+        assert!(matches!(
+            session.execute_streaming("if a then\n".as_bytes()),
+            Err(LuaError::ParseError { line: 1, .. })
+        ));
+        assert!(session.execute_streaming("s = 'open\n".as_bytes()).is_err());
+        assert!(session
+            .execute_streaming("error('boom')".as_bytes())
+            .unwrap_err()
+            .to_string()
+            .contains("boom"));
+    }
+
+    #[test]
+    fn test_closures_see_earlier_chunk_locals() {
+        for source in [
+            "local a = 1\nlocal function f() return a end\nreturn f()",
+            "local x = 0; local function f() return x + 1 end; return f()",
+            "local n = 0\nlocal function bump() n = n + 1 end\nbump() bump()\nreturn n",
+        ] {
+            let expected = eval_lua(source).unwrap();
+            assert_ne!(expected, LuaValue::Nil);
+            assert_eq!(
+                Session::new().execute_streaming(source.as_bytes()).unwrap(),
+                expected,
+                "{}",
+                source
+            );
+        }
+
+        let mut session = Session::new();
+        session.eval_lua("local y = 5").unwrap();
+        session.eval_lua("function get() return y end").unwrap();
+        session
+            .eval_lua("local function set(v) y = v end set(6)")
+            .unwrap();
+        assert_eq!(session.eval_lua("get()").unwrap(), LuaValue::Number(6.0));
+        assert_eq!(session.lua().get_global("y"), LuaValue::Nil);
+    }
+
+    #[test]
+    fn test_session_keeps_state() {
+        let mut session = Session::new();
+        session.eval_lua("count = 0").unwrap();
+        session.eval_lua("count = count + 1").unwrap();
+        session.lua().set_global("step", LuaValue::Number(10.0));
+        assert_eq!(
+            session.eval_lua("count + step").unwrap(),
+            LuaValue::Number(11.0)
+        );
+
+        session.eval_scheme("(define total 5)").unwrap();
+        assert_eq!(
+            session.eval_scheme("(+ total 1)").unwrap(),
+            SVal::Integer(6)
+        );
+    }
+}
@@ -0,0 +1,369 @@
+//! C API for embedding the Lua interpreter, behind the `ffi` feature
+//!
+//! `include/muscm.h` declares these functions for C and C++ hosts, and
+//! Python can load the same shared library with ctypes. A `muscm` handle
+//! owns one interpreter session; use it from one thread at a time.
+//!
+//! Values cross the boundary as JSON text through the serde conversions in
+//! `convert`: tables become arrays or objects, nil becomes `null`, and
+//! functions cannot cross at all. Strings the library hands out belong to
+//! the caller, who releases them with `muscm_free_string`. When a call
+//! fails, `muscm_last_error` holds its message until the next call on the
+//! same handle.
+
+use crate::convert::{from_lua, to_lua};
+use crate::error_types::{LuaError, LuaResult};
+use crate::eval::Session;
+use crate::lua_value::{LuaFunction, LuaValue};
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::ptr;
+use std::rc::Rc;
+
+/// An interpreter and the message of its last failure
+pub struct Muscm {
+    session: Session,
+    error: Option<CString>,
+}
+
+/// One call of a registered host function, where it leaves its result
+pub struct MuscmCall {
+    result: LuaValue,
+    error: Option<String>,
+}
+
+/// A host function: gets the arguments as a JSON array, answers through
+/// `muscm_return` or `muscm_error`, and returns 0 on success
+pub type MuscmFn =
+    unsafe extern "C" fn(data: *mut c_void, call: *mut MuscmCall, args: *const c_char) -> c_int;
+
+impl Muscm {
+    /// Remember `message` for `muscm_last_error` and return the failure code
+    fn fail(&mut self, message: impl Into<String>) -> c_int {
+        self.error = Some(c_string(message.into()));
+        -1
+    }
+}
+
+/// A C string of `text`, with any NUL bytes in it dropped
+fn c_string(text: String) -> CString {
+    CString::new(text).unwrap_or_else(|e| {
+        let mut bytes = e.into_vec();
+        bytes.retain(|&b| b != 0);
+        CString::new(bytes).unwrap_or_default()
+    })
+}
+
+/// `text` as UTF-8, or a message naming `what` it was
+unsafe fn read_str<'a>(text: *const c_char, what: &str) -> Result<&'a str, String> {
+    if text.is_null() {
+        return Err(format!("{} is NULL", what));
+    }
+    CStr::from_ptr(text)
+        .to_str()
+        .map_err(|_| format!("{} is not valid UTF-8", what))
+}
+
+/// A value as JSON text the caller owns
+fn to_json(value: &LuaValue) -> LuaResult<*mut c_char> {
+    let json: serde_json::Value = from_lua(value)?;
+    Ok(c_string(json.to_string()).into_raw())
+}
+
+/// Create an interpreter with the standard library loaded
+#[no_mangle]
+pub extern "C" fn muscm_new() -> *mut Muscm {
+    Box::into_raw(Box::new(Muscm {
+        session: Session::new(),
+        error: None,
+    }))
+}
+
+/// Free an interpreter; NULL is ignored
+///
+/// # Safety
+///
+/// `vm` must come from `muscm_new` and not have been freed.
+#[no_mangle]
+pub unsafe extern "C" fn muscm_free(vm: *mut Muscm) {
+    if !vm.is_null() {
+        drop(Box::from_raw(vm));
+    }
+}
+
+/// Free a string returned by the library; NULL is ignored
+///
+/// # Safety
+///
+/// `text` must come from this library and not have been freed.
+#[no_mangle]
+pub unsafe extern "C" fn muscm_free_string(text: *mut c_char) {
+    if !text.is_null() {
+        drop(CString::from_raw(text));
+    }
+}
+
+/// The message of the last failed call on `vm`, or NULL
+///
+/// # Safety
+///
+/// `vm` must be a live handle; the message is valid until the next call.
+#[no_mangle]
+pub unsafe extern "C" fn muscm_last_error(vm: *const Muscm) -> *const c_char {
+    match vm.as_ref().and_then(|vm| vm.error.as_ref()) {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// Run a Lua chunk, or evaluate a bare expression, and store its first
+/// value as JSON in `*result` unless `result` is NULL; 0 on success
+///
+/// # Safety
+///
+/// `vm` must be a live handle, `source` a NUL-terminated string and
+/// `result` NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn muscm_eval(
+    vm: *mut Muscm,
+    source: *const c_char,
+    result: *mut *mut c_char,
+) -> c_int {
+    let Some(vm) = vm.as_mut() else {
+        return -1;
+    };
+    vm.error = None;
+    let source = match read_str(source, "source") {
+        Ok(source) => source,
+        Err(message) => return vm.fail(message),
+    };
+    let value = match vm.session.eval_lua(source) {
+        Ok(value) => value,
+        Err(e) => return vm.fail(e.to_string()),
+    };
+    if !result.is_null() {
+        match to_json(&value) {
+            Ok(json) => *result = json,
+            Err(e) => return vm.fail(format!("cannot return the result: {}", e)),
+        }
+    }
+    0
+}
+
+/// A global as JSON, `null` when unset; NULL when it cannot be converted
+///
+/// # Safety
+///
+/// `vm` must be a live handle and `name` a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn muscm_get_global(vm: *mut Muscm, name: *const c_char) -> *mut c_char {
+    let Some(vm) = vm.as_mut() else {
+        return ptr::null_mut();
+    };
+    vm.error = None;
+    let json = read_str(name, "name")
+        .and_then(|name| to_json(&vm.session.lua().get_global(name)).map_err(|e| e.to_string()));
+    json.unwrap_or_else(|message| {
+        vm.fail(message);
+        ptr::null_mut()
+    })
+}
+
+/// Make `function` callable from Lua as the global `name`; `data` is
+/// passed back to it on every call. 0 on success
+///
+/// # Safety
+///
+/// `vm` must be a live handle and `name` a NUL-terminated string;
+/// `function` must stay callable with `data` for as long as `vm` lives.
+#[no_mangle]
+pub unsafe extern "C" fn muscm_register_fn(
+    vm: *mut Muscm,
+    name: *const c_char,
+    function: Option<MuscmFn>,
+    data: *mut c_void,
+) -> c_int {
+    let Some(vm) = vm.as_mut() else {
+        return -1;
+    };
+    vm.error = None;
+    let name = match read_str(name, "name") {
+        Ok(name) => name.to_string(),
+        Err(message) => return vm.fail(message),
+    };
+    let Some(function) = function else {
+        return vm.fail("function is NULL");
+    };
+    let context = name.clone();
+    let call = move |args: Vec<LuaValue>| -> LuaResult<LuaValue> {
+        let args = args
+            .iter()
+            .map(from_lua)
+            .collect::<LuaResult<Vec<serde_json::Value>>>()?;
+        let args = c_string(serde_json::Value::Array(args).to_string());
+        let mut call = MuscmCall {
+            result: LuaValue::Nil,
+            error: None,
+        };
+        let status = function(data, &mut call, args.as_ptr());
+        match (status, call.error) {
+            (0, None) => Ok(call.result),
+            (_, Some(message)) => Err(LuaError::runtime(message, context.as_str())),
+            (_, None) => Err(LuaError::runtime(
+                format!("{} failed", context),
+                context.as_str(),
+            )),
+        }
+    };
+    vm.session.lua().set_global(
+        name,
+        LuaValue::Function(Rc::new(LuaFunction::Builtin(Rc::new(call)))),
+    );
+    0
+}
+
+/// Set the value a host function returns, from JSON; 0 on success
+///
+/// # Safety
+///
+/// `call` must be the one the host function was given, and `json` a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn muscm_return(call: *mut MuscmCall, json: *const c_char) -> c_int {
+    let Some(call) = call.as_mut() else {
+        return -1;
+    };
+    let value = read_str(json, "result").and_then(|json| {
+        let json: serde_json::Value =
+            serde_json::from_str(json).map_err(|e| format!("result is not JSON: {}", e))?;
+        to_lua(&json).map_err(|e| e.to_string())
+    });
+    match value {
+        Ok(value) => {
+            call.result = value;
+            0
+        }
+        Err(message) => {
+            call.error = Some(message);
+            -1
+        }
+    }
+}
+
+/// Make a host function raise `message` as a Lua error once it returns
+///
+/// # Safety
+///
+/// `call` must be the one the host function was given, and `message` a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn muscm_error(call: *mut MuscmCall, message: *const c_char) {
+    if let Some(call) = call.as_mut() {
+        call.error = Some(match read_str(message, "message") {
+            Ok(message) => message.to_string(),
+            Err(problem) => problem,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Take a string from the library, freeing it
+    unsafe fn take(text: *mut c_char) -> String {
+        assert!(!text.is_null());
+        let owned = CStr::from_ptr(text).to_str().unwrap().to_string();
+        muscm_free_string(text);
+        owned
+    }
+
+    unsafe extern "C" fn add(
+        data: *mut c_void,
+        call: *mut MuscmCall,
+        args: *const c_char,
+    ) -> c_int {
+        let calls = &mut *(data as *mut u32);
+        *calls += 1;
+        let args: Vec<f64> = serde_json::from_str(CStr::from_ptr(args).to_str().unwrap()).unwrap();
+        if args.len() != 2 {
+            muscm_error(call, c"add takes two numbers".as_ptr());
+            return 1;
+        }
+        let sum = c_string((args[0] + args[1]).to_string());
+        muscm_return(call, sum.as_ptr())
+    }
+
+    #[test]
+    fn test_eval_and_globals() {
+        unsafe {
+            let vm = muscm_new();
+            let mut result = ptr::null_mut();
+            assert_eq!(
+                muscm_eval(vm, c"t = {1, 2, x = 'y'}".as_ptr(), &mut result),
+                0
+            );
+            assert_eq!(take(result), "null");
+            assert_eq!(muscm_eval(vm, c"#t + 0.5".as_ptr(), &mut result), 0);
+            assert_eq!(take(result), "2.5");
+            assert_eq!(
+                take(muscm_get_global(vm, c"t".as_ptr())),
+                r#"{"1":1,"2":2,"x":"y"}"#
+            );
+            assert_eq!(take(muscm_get_global(vm, c"missing".as_ptr())), "null");
+            assert!(muscm_last_error(vm).is_null());
+
+            assert!(muscm_get_global(vm, c"print".as_ptr()).is_null());
+            assert!(!muscm_last_error(vm).is_null());
+            assert_eq!(
+                muscm_eval(vm, c"error('boom')".as_ptr(), ptr::null_mut()),
+                -1
+            );
+            let message = CStr::from_ptr(muscm_last_error(vm)).to_str().unwrap();
+            assert!(message.contains("boom"), "{}", message);
+            muscm_free(vm);
+            muscm_free(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_host_functions() {
+        unsafe {
+            let vm = muscm_new();
+            let mut calls = 0u32;
+            let data = &mut calls as *mut u32 as *mut c_void;
+            assert_eq!(muscm_register_fn(vm, c"add".as_ptr(), Some(add), data), 0);
+            let mut result = ptr::null_mut();
+            assert_eq!(
+                muscm_eval(vm, c"add(2, add(3, 4))".as_ptr(), &mut result),
+                0
+            );
+            assert_eq!(take(result), "9");
+            assert_eq!(muscm_eval(vm, c"add(1)".as_ptr(), ptr::null_mut()), -1);
+            let message = CStr::from_ptr(muscm_last_error(vm)).to_str().unwrap();
+            assert!(message.contains("add takes two numbers"), "{}", message);
+            assert_eq!(muscm_register_fn(vm, c"f".as_ptr(), None, data), -1);
+            muscm_free(vm);
+            assert_eq!(calls, 3);
+        }
+    }
+
+    #[test]
+    fn test_header_declares_every_function() {
+        let header = include_str!("../include/muscm.h");
+        let source = include_str!("ffi.rs");
+        let exported: Vec<&str> = source
+            .split("extern \"C\" fn ")
+            .skip(1)
+            .filter_map(|rest| rest.split('(').next())
+            .filter(|name| name.starts_with("muscm_"))
+            .collect();
+        assert!(exported.len() >= 9, "{:?}", exported);
+        for name in exported {
+            assert!(
+                header.contains(&format!("{}(", name)),
+                "{} is not declared",
+                name
+            );
+        }
+    }
+}
@@ -1,338 +1,1090 @@
 //! Phase 8: File I/O & System Integration
 //!
 //! This module provides Lua file I/O and system interaction functions:
-//! - File operations: io.open, file:read, file:write, file:close, file:lines
+//! - Default streams: io.stdin/stdout/stderr, io.input/io.output redirecting
+//!   io.read, io.write and io.lines
+//! - File operations: io.open, io.type, io.close, and file handles with read, write, lines,
+//!   seek, flush, setvbuf and close methods
 //! - System functions: os.execute, os.exit, os.getenv, os.setenv, os.time, os.date
-//! - Path operations: io.popen (command execution)
+//! - Processes: io.popen, whose handles read a command's output or feed its input
 //! - File metadata: io.stat (file information)
 
 use crate::error_types::{LuaError, LuaResult};
-use crate::lua_value::{LuaTable, LuaValue};
+use crate::host_io::{InputSource, OutputSink};
+use crate::lua_parser::Numeral;
+use crate::lua_value::{format_number, LuaTable, LuaValue, TableData};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fs::{self, File, OpenOptions};
-use std::io::{self, BufRead, BufReader, Read, Write};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, ExitStatus, Stdio};
 use std::rc::Rc;
+use std::time::Duration;
+#[cfg(not(all(target_family = "wasm", feature = "wasm")))]
 use std::time::{SystemTime, UNIX_EPOCH};
 
-// File handle wrapper - stored as UserData in LuaValue
-pub struct FileHandle {
-    file: Option<Box<dyn FileOperations>>,
+/// A browser has no process to exit and no environment to change; the
+/// functions that would need one raise an error there instead
+const IN_BROWSER: bool = cfg!(all(target_arch = "wasm32", target_os = "unknown"));
+
+/// Time since the Unix epoch, read from JavaScript's `Date` in a browser,
+/// where `std` has no clock
+pub fn unix_time() -> Duration {
+    #[cfg(all(target_family = "wasm", feature = "wasm"))]
+    {
+        Duration::from_secs_f64(js_sys::Date::now() / 1000.0)
+    }
+    #[cfg(not(all(target_family = "wasm", feature = "wasm")))]
+    {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+}
+
+/// How a file was opened, parsed from an `io.open` mode string
+///
+/// Accepts the same strings as C `fopen` in Lua: `r`, `w` or `a`, an
+/// optional `+` for update, then any number of `b` flags (ignored).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenMode {
+    pub read: bool,
+    pub write: bool,
+    pub append: bool,
+    pub truncate: bool,
+}
+
+impl OpenMode {
+    /// Parse a mode string, returning None if it is not a valid mode
+    pub fn parse(mode: &str) -> Option<OpenMode> {
+        let kind = mode.chars().next()?;
+        let rest = &mode[kind.len_utf8()..];
+        let (update, rest) = match rest.strip_prefix('+') {
+            Some(rest) => (true, rest),
+            None => (false, rest),
+        };
+        if !rest.chars().all(|c| c == 'b') {
+            return None;
+        }
+        match kind {
+            'r' => Some(OpenMode {
+                read: true,
+                write: update,
+                append: false,
+                truncate: false,
+            }),
+            'w' => Some(OpenMode {
+                read: update,
+                write: true,
+                append: false,
+                truncate: true,
+            }),
+            'a' => Some(OpenMode {
+                read: update,
+                write: true,
+                append: true,
+                truncate: false,
+            }),
+            _ => None,
+        }
+    }
+
+    fn options(&self) -> OpenOptions {
+        let mut options = OpenOptions::new();
+        options
+            .read(self.read)
+            .write(self.write && !self.append)
+            .append(self.append)
+            .truncate(self.truncate)
+            .create(self.write && (self.truncate || self.append));
+        options
+    }
 }
 
-trait FileOperations: std::any::Any {
-    fn read_line(&mut self) -> io::Result<String>;
-    fn read_all(&mut self) -> io::Result<String>;
-    fn write(&mut self, data: &str) -> io::Result<()>;
+/// Write buffering policy, set by `file:setvbuf`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BufferMode {
+    No,
+    Line,
+    Full,
+}
+
+const DEFAULT_BUFFER_SIZE: usize = 8192;
+
+/// Longest numeral `read("n")` takes, as in PUC-Lua
+const MAX_NUMERAL: usize = 200;
+
+/// Where a FileHandle's bytes come from and go to
+enum Stream {
+    File(BufReader<File>),
+    Stdin(InputSource),
+    Stdout(OutputSink),
+    Stderr,
+    Pipe(Box<Pipe>),
 }
 
-struct ReadFileHandle {
-    reader: BufReader<File>,
+/// A child process started by `io.popen`, with the end of the pipe the
+/// script uses
+struct Pipe {
+    child: Child,
+    stdout: Option<BufReader<ChildStdout>>,
+    stdin: Option<ChildStdin>,
 }
 
-impl FileOperations for ReadFileHandle {
-    fn read_line(&mut self) -> io::Result<String> {
-        let mut line = String::new();
-        self.reader.read_line(&mut line)?;
-        Ok(line)
+/// A `Command` running `command` through the system shell, like C `system`
+fn shell_command(command: &str) -> Command {
+    #[cfg(unix)]
+    {
+        let mut shell = Command::new("/bin/sh");
+        shell.arg("-c").arg(command);
+        shell
     }
 
-    fn read_all(&mut self) -> io::Result<String> {
-        let mut content = String::new();
-        self.reader.read_to_string(&mut content)?;
-        Ok(content)
+    #[cfg(not(unix))]
+    {
+        let mut shell = Command::new("cmd");
+        shell.args(["/C", command]);
+        shell
     }
+}
 
-    fn write(&mut self, _data: &str) -> io::Result<()> {
-        Err(io::Error::new(
-            io::ErrorKind::PermissionDenied,
-            "File opened in read mode",
-        ))
+/// Lua's results for a finished command, shared by `os.execute` and closing
+/// an `io.popen` handle: `true` or nil, then `"exit"` and the exit code, or
+/// `"signal"` and the signal that killed it
+pub fn exit_status_values(status: ExitStatus) -> Vec<LuaValue> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return vec![
+                LuaValue::Nil,
+                LuaValue::String("signal".into()),
+                LuaValue::Number(signal as f64),
+            ];
+        }
     }
+    let success = if status.success() {
+        LuaValue::Boolean(true)
+    } else {
+        LuaValue::Nil
+    };
+    vec![
+        success,
+        LuaValue::String("exit".into()),
+        LuaValue::Number(status.code().unwrap_or(1) as f64),
+    ]
 }
 
-struct WriteFileHandle {
-    file: File,
+/// An open Lua file, stored as UserData behind the handle table
+///
+/// Reads go through a `BufReader`; writes collect in `pending` until the
+/// buffering policy or an explicit flush, seek, read or close sends them
+/// to the file.
+pub struct FileHandle {
+    stream: Option<Stream>,
+    mode: OpenMode,
+    buffering: BufferMode,
+    buffer_size: usize,
+    pending: Vec<u8>,
 }
 
-impl FileOperations for WriteFileHandle {
-    fn read_line(&mut self) -> io::Result<String> {
-        Err(io::Error::new(
-            io::ErrorKind::PermissionDenied,
-            "File opened in write mode",
-        ))
+fn closed_file() -> io::Error {
+    io::Error::other("attempt to use a closed file")
+}
+
+fn standard_stream() -> io::Error {
+    io::Error::other("not supported on a standard stream")
+}
+
+/// The errno of a failure: the operating system's own code when there is
+/// one, otherwise the usual POSIX code for its kind
+fn errno(error: &io::Error) -> i32 {
+    if let Some(code) = error.raw_os_error() {
+        return code;
+    }
+    match error.kind() {
+        io::ErrorKind::NotFound => 2,
+        io::ErrorKind::Interrupted => 4,
+        io::ErrorKind::WouldBlock => 11,
+        io::ErrorKind::PermissionDenied => 13,
+        io::ErrorKind::AlreadyExists => 17,
+        io::ErrorKind::NotADirectory => 20,
+        io::ErrorKind::IsADirectory => 21,
+        io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => 22,
+        io::ErrorKind::StorageFull => 28,
+        io::ErrorKind::ReadOnlyFilesystem => 30,
+        io::ErrorKind::BrokenPipe => 32,
+        io::ErrorKind::DirectoryNotEmpty => 39,
+        _ => 5,
+    }
+}
+
+/// What Lua's io and os functions return when the operating system refuses:
+/// `nil`, the message (after `subject`, usually the file name, as C Lua
+/// does) and the errno
+///
+/// Failures like these are part of normal use, so they come back as values
+/// a script can test; misuse such as a bad mode or a closed file still
+/// raises an error.
+pub fn io_failure(subject: Option<&str>, error: &io::Error) -> Vec<LuaValue> {
+    let text = error.to_string();
+    // Rust appends the code, which Lua returns on its own
+    let text = match text.rfind(" (os error ") {
+        Some(at) if text.ends_with(')') => &text[..at],
+        _ => &text,
+    };
+    let message = match subject {
+        Some(subject) => format!("{}: {}", subject, text),
+        None => text.to_string(),
+    };
+    vec![
+        LuaValue::Nil,
+        LuaValue::String(message.into()),
+        LuaValue::Number(errno(error) as f64),
+    ]
+}
+
+impl FileHandle {
+    pub fn new(file: File, mode: OpenMode) -> Self {
+        Self::with_stream(Stream::File(BufReader::new(file)), mode, BufferMode::Full)
+    }
+
+    fn with_stream(stream: Stream, mode: OpenMode, buffering: BufferMode) -> Self {
+        FileHandle {
+            stream: Some(stream),
+            mode,
+            buffering,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            pending: Vec::new(),
+        }
     }
 
-    fn read_all(&mut self) -> io::Result<String> {
-        Err(io::Error::new(
-            io::ErrorKind::PermissionDenied,
-            "File opened in write mode",
+    /// Standard input, read from `source`
+    pub fn stdin(source: InputSource) -> Self {
+        let mode = OpenMode::parse("r").expect("valid mode");
+        Self::with_stream(Stream::Stdin(source), mode, BufferMode::No)
+    }
+
+    /// Standard output, written to `sink` unbuffered so it interleaves
+    /// with `print`
+    pub fn stdout(sink: OutputSink) -> Self {
+        let mode = OpenMode::parse("w").expect("valid mode");
+        Self::with_stream(Stream::Stdout(sink), mode, BufferMode::No)
+    }
+
+    /// The process's standard error
+    pub fn stderr() -> Self {
+        let mode = OpenMode::parse("w").expect("valid mode");
+        Self::with_stream(Stream::Stderr, mode, BufferMode::No)
+    }
+
+    /// Start `command` in the shell, reading its output (mode "r") or
+    /// writing to its input (mode "w")
+    pub fn popen(command: &str, mode: &str) -> LuaResult<Self> {
+        let (mode, stdin, stdout) = match mode {
+            "r" => ("r", Stdio::inherit(), Stdio::piped()),
+            "w" => ("w", Stdio::piped(), Stdio::inherit()),
+            _ => {
+                return Err(LuaError::value(format!(
+                    "io.popen() invalid mode: {}",
+                    mode
+                )))
+            }
+        };
+        let mut child = shell_command(command)
+            .stdin(stdin)
+            .stdout(stdout)
+            .spawn()
+            .map_err(|e| LuaError::runtime(format!("io.popen() failed: {}", e), "system call"))?;
+        let pipe = Pipe {
+            stdout: child.stdout.take().map(BufReader::new),
+            stdin: child.stdin.take(),
+            child,
+        };
+        let mode = OpenMode::parse(mode).expect("valid mode");
+        Ok(Self::with_stream(
+            Stream::Pipe(Box::new(pipe)),
+            mode,
+            BufferMode::Full,
         ))
     }
 
-    fn write(&mut self, data: &str) -> io::Result<()> {
-        self.file.write_all(data.as_bytes())
+    /// Open `path` with a Lua mode string
+    pub fn open(path: &str, mode: &str) -> LuaResult<Self> {
+        Self::open_with(path, Self::parse_mode(mode)?)
+            .map_err(|e| LuaError::file(path, format!("io.open() failed to open: {}", e)))
+    }
+
+    /// The mode of an `io.open` call; an invalid one is an error
+    fn parse_mode(mode: &str) -> LuaResult<OpenMode> {
+        OpenMode::parse(mode)
+            .ok_or_else(|| LuaError::value(format!("io.open() invalid mode: {}", mode)))
+    }
+
+    fn open_with(path: &str, mode: OpenMode) -> io::Result<Self> {
+        mode.options()
+            .open(path)
+            .map(|file| FileHandle::new(file, mode))
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.stream.is_none()
+    }
+
+    /// Run `op` on the readable stream, sending any pending writes first
+    fn with_reader<T>(
+        &mut self,
+        op: impl FnOnce(&mut dyn BufRead) -> io::Result<T>,
+    ) -> io::Result<T> {
+        if !self.mode.read {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "file not opened for reading",
+            ));
+        }
+        self.flush()?;
+        match self.stream.as_mut().ok_or_else(closed_file)? {
+            Stream::File(reader) => op(reader),
+            Stream::Stdin(source) => source.with_reader(op),
+            Stream::Pipe(pipe) => op(pipe.stdout.as_mut().ok_or_else(closed_file)?),
+            Stream::Stdout(_) | Stream::Stderr => Err(standard_stream()),
+        }
+    }
+
+    fn read_line(&mut self, keep_newline: bool) -> io::Result<Option<Vec<u8>>> {
+        let mut line = Vec::new();
+        if self.with_reader(|reader| reader.read_until(b'\n', &mut line))? == 0 {
+            return Ok(None);
+        }
+        if !keep_newline && line.last() == Some(&b'\n') {
+            line.pop();
+        }
+        Ok(Some(line))
+    }
+
+    fn read_all(&mut self) -> io::Result<Vec<u8>> {
+        let mut content = Vec::new();
+        self.with_reader(|reader| reader.read_to_end(&mut content))?;
+        Ok(content)
+    }
+
+    fn read_count(&mut self, count: usize) -> io::Result<Option<Vec<u8>>> {
+        self.with_reader(|reader| {
+            if count == 0 {
+                // read(0) tests for end of file
+                return Ok((!reader.fill_buf()?.is_empty()).then(Vec::new));
+            }
+            let mut bytes = Vec::new();
+            reader.take(count as u64).read_to_end(&mut bytes)?;
+            if bytes.is_empty() {
+                return Ok(None);
+            }
+            Ok(Some(bytes))
+        })
+    }
+
+    /// Read the longest prefix that can start a numeral, as Lua does: an
+    /// optional sign, a `0x` prefix, digits with a point and an exponent.
+    /// Whatever follows stays unread, and a prefix that is not a whole
+    /// numeral (such as `1e`) reads as `None`.
+    fn read_number(&mut self) -> io::Result<Option<f64>> {
+        let text = self.with_reader(|reader| {
+            let mut text = String::new();
+            // Take the next byte when it is one of `wanted`
+            let mut accept = |reader: &mut dyn BufRead, wanted: &dyn Fn(u8) -> bool| match reader
+                .fill_buf()?
+                .first()
+            {
+                Some(&byte) if text.len() < MAX_NUMERAL && wanted(byte) => {
+                    text.push(byte as char);
+                    reader.consume(1);
+                    Ok::<_, io::Error>(true)
+                }
+                _ => Ok(false),
+            };
+            while let Some(&byte) = reader.fill_buf()?.first() {
+                if !byte.is_ascii_whitespace() {
+                    break;
+                }
+                reader.consume(1);
+            }
+            accept(reader, &|b| b == b'+' || b == b'-')?;
+            let mut hex = false;
+            if accept(reader, &|b| b == b'0')? {
+                hex = accept(reader, &|b| b == b'x' || b == b'X')?;
+            }
+            let digit = move |b: u8| {
+                if hex {
+                    b.is_ascii_hexdigit()
+                } else {
+                    b.is_ascii_digit()
+                }
+            };
+            while accept(reader, &digit)? {}
+            if accept(reader, &|b| b == b'.')? {
+                while accept(reader, &digit)? {}
+            }
+            let exponent: &[u8] = if hex { b"pP" } else { b"eE" };
+            if accept(reader, &|b| exponent.contains(&b))? {
+                accept(reader, &|b| b == b'+' || b == b'-')?;
+                while accept(reader, &|b| b.is_ascii_digit())? {}
+            }
+            Ok(text)
+        })?;
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text.strip_prefix('+').unwrap_or(&text)),
+        };
+        let value = Numeral::parse(digits).map(|n| n.to_f64());
+        Ok(value.map(|n| if negative { -n } else { n }))
+    }
+
+    pub(crate) fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        if self.is_closed() {
+            return Err(closed_file());
+        }
+        if !self.mode.write {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "file not opened for writing",
+            ));
+        }
+        self.pending.extend_from_slice(data);
+        let flush = match self.buffering {
+            BufferMode::No => true,
+            BufferMode::Line => data.contains(&b'\n'),
+            BufferMode::Full => self.pending.len() >= self.buffer_size,
+        };
+        if flush {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let stream = self.stream.as_mut().ok_or_else(closed_file)?;
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let pending = std::mem::take(&mut self.pending);
+        match stream {
+            Stream::File(reader) => {
+                // Re-seek to the logical position so read-ahead doesn't shift the write
+                let position = reader.stream_position()?;
+                reader.seek(SeekFrom::Start(position))?;
+                reader.get_mut().write_all(&pending)?;
+                reader.get_mut().flush()
+            }
+            Stream::Stdout(sink) => sink.write_bytes(&pending),
+            Stream::Stderr => io::stderr().lock().write_all(&pending),
+            Stream::Pipe(pipe) => {
+                let stdin = pipe.stdin.as_mut().ok_or_else(closed_file)?;
+                stdin.write_all(&pending)?;
+                stdin.flush()
+            }
+            Stream::Stdin(_) => Err(standard_stream()),
+        }
+    }
+
+    fn seek(&mut self, whence: SeekFrom) -> io::Result<u64> {
+        self.flush()?;
+        match self.stream.as_mut().ok_or_else(closed_file)? {
+            Stream::File(reader) => reader.seek(whence),
+            _ => Err(standard_stream()),
+        }
+    }
+
+    fn set_buffering(&mut self, mode: BufferMode, size: Option<usize>) -> io::Result<()> {
+        self.flush()?;
+        self.buffering = mode;
+        self.buffer_size = size.unwrap_or(DEFAULT_BUFFER_SIZE);
+        Ok(())
+    }
+
+    /// Close the handle; for a pipe, also wait for the command and return
+    /// how it finished
+    pub(crate) fn close(&mut self) -> io::Result<Option<ExitStatus>> {
+        if !matches!(self.stream, Some(Stream::File(_) | Stream::Pipe(_)) | None) {
+            return Err(io::Error::other("cannot close standard file"));
+        }
+        let result = self.flush();
+        match self.stream.take() {
+            Some(Stream::Pipe(mut pipe)) => {
+                // Dropping our end first lets the command see end of input
+                drop(pipe.stdin.take());
+                drop(pipe.stdout.take());
+                let status = pipe.child.wait()?;
+                result.map(|()| Some(status))
+            }
+            _ => result.map(|()| None),
+        }
     }
 }
 
-struct AppendFileHandle {
-    file: File,
+impl std::fmt::Debug for FileHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileHandle")
+            .field("mode", &self.mode)
+            .field("closed", &self.is_closed())
+            .finish()
+    }
 }
 
-impl FileOperations for AppendFileHandle {
-    fn read_line(&mut self) -> io::Result<String> {
-        Err(io::Error::new(
-            io::ErrorKind::PermissionDenied,
-            "File opened in append mode",
-        ))
+impl Drop for FileHandle {
+    fn drop(&mut self) {
+        if matches!(self.stream, Some(Stream::Pipe(_))) {
+            // Reap the command rather than leave it running unattended
+            let _ = self.close();
+        } else if !self.is_closed() {
+            let _ = self.flush();
+        }
     }
+}
 
-    fn read_all(&mut self) -> io::Result<String> {
-        Err(io::Error::new(
-            io::ErrorKind::PermissionDenied,
-            "File opened in append mode",
-        ))
+/// The standard streams and the current default input and output
+///
+/// Owned by the interpreter and shared with the io table, so
+/// `io.input(file)` and `io.output(file)` redirect later `io.read`,
+/// `io.lines` and `io.write` calls.
+pub struct IoStreams {
+    pub stdin: LuaValue,
+    pub stdout: LuaValue,
+    pub stderr: LuaValue,
+    pub input: LuaValue,
+    pub output: LuaValue,
+}
+
+impl IoStreams {
+    /// Streams whose stdin and stdout go through the host's `input` and `output`
+    pub fn new(input: &InputSource, output: &OutputSink) -> Self {
+        let stdin = create_file_value(FileHandle::stdin(input.clone()));
+        let stdout = create_file_value(FileHandle::stdout(output.clone()));
+        IoStreams {
+            input: stdin.clone(),
+            output: stdout.clone(),
+            stderr: create_file_value(FileHandle::stderr()),
+            stdin,
+            stdout,
+        }
     }
+}
 
-    fn write(&mut self, data: &str) -> io::Result<()> {
-        self.file.write_all(data.as_bytes())
+impl Default for IoStreams {
+    fn default() -> Self {
+        Self::new(&InputSource::stdin(), &OutputSink::stdout())
     }
 }
 
+/// Metatable key holding a handle table's FileHandle userdata
+const FILE_KEY: &str = "__file";
+
+thread_local! {
+    static FILE_METHODS: LuaValue = create_file_methods();
+}
+
+/// Wrap a FileHandle as a Lua file: a table whose metatable carries the
+/// handle and indexes the shared method table
+pub fn create_file_value(handle: FileHandle) -> LuaValue {
+    let userdata: Rc<RefCell<Box<dyn std::any::Any>>> = Rc::new(RefCell::new(Box::new(handle)));
+    let mut metatable = HashMap::new();
+    metatable.insert(FILE_KEY.to_string(), LuaValue::UserData(userdata));
+    metatable.insert(
+        "__index".to_string(),
+        FILE_METHODS.with(|methods| methods.clone()),
+    );
+    metatable.insert("__name".to_string(), LuaValue::String("FILE*".into()));
+    LuaValue::Table(Rc::new(RefCell::new(LuaTable {
+        data: TableData::new(),
+        metatable: Some(Box::new(metatable.into())),
+        frozen: false,
+        removed: None,
+    })))
+}
+
+/// The userdata behind a file value, if it is one
+fn file_userdata(value: &LuaValue) -> Option<Rc<RefCell<Box<dyn std::any::Any>>>> {
+    let userdata = match value {
+        LuaValue::UserData(ud) => ud.clone(),
+        LuaValue::Table(t) => match t.borrow().metatable.as_ref()?.get(FILE_KEY)? {
+            LuaValue::UserData(ud) => ud.clone(),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let is_file = userdata.borrow().is::<FileHandle>();
+    is_file.then_some(userdata)
+}
+
+/// Run `op` against the FileHandle behind `args[0]`
+fn with_file<T>(
+    name: &str,
+    args: &[LuaValue],
+    op: impl FnOnce(&mut FileHandle) -> io::Result<T>,
+) -> LuaResult<T> {
+    let file = args.first().unwrap_or(&LuaValue::Nil);
+    let userdata =
+        file_userdata(file).ok_or_else(|| LuaError::type_error("file", file.type_name(), name))?;
+    let mut borrow = userdata.borrow_mut();
+    let handle = borrow
+        .downcast_mut::<FileHandle>()
+        .expect("file_userdata checked the type");
+    op(handle).map_err(|e| LuaError::runtime(format!("{}() error: {}", name, e), "io"))
+}
+
+/// Like `with_file`, but a failure of `op` comes back as `nil, message,
+/// errno`; using a closed file is still an error
+fn try_with_file(
+    name: &str,
+    args: &[LuaValue],
+    op: impl FnOnce(&mut FileHandle) -> io::Result<Vec<LuaValue>>,
+) -> LuaResult<Vec<LuaValue>> {
+    with_file(name, args, |fh| {
+        if fh.is_closed() {
+            return Err(closed_file());
+        }
+        Ok(op(fh).unwrap_or_else(|e| io_failure(None, &e)))
+    })
+}
+
+fn builtin(f: Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>>) -> LuaValue {
+    LuaValue::Function(Rc::new(crate::lua_value::LuaFunction::Builtin(f)))
+}
+
+fn create_file_methods() -> LuaValue {
+    let methods = [
+        ("lines", create_file_lines()),
+        ("seek", create_file_seek()),
+        ("flush", create_file_flush()),
+        ("setvbuf", create_file_setvbuf()),
+    ];
+    let mut data: TableData = methods
+        .into_iter()
+        .map(|(name, f)| (LuaValue::String(name.into()), builtin(f)))
+        .collect();
+    for (name, f) in [
+        ("read", create_file_read()),
+        ("write", create_file_write()),
+        ("close", create_file_close()),
+    ] {
+        data.insert(
+            LuaValue::String(name.into()),
+            LuaValue::Function(Rc::new(crate::lua_value::LuaFunction::MultiBuiltin(f))),
+        );
+    }
+    LuaValue::Table(Rc::new(RefCell::new(LuaTable {
+        data,
+        metatable: None,
+        frozen: false,
+        removed: None,
+    })))
+}
+
+/// Create io.popen(command [, mode]) function
+/// Runs command in the shell and returns a file handle reading its output
+/// (mode "r", the default) or writing to its input (mode "w")
+pub fn create_io_popen() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|args| {
+        if args.is_empty() {
+            return Err(LuaError::arg_count("io.popen", 1, args.len()));
+        }
+
+        let command = match &args[0] {
+            LuaValue::String(s) => s.to_string(),
+            _ => {
+                return Err(LuaError::type_error(
+                    "string",
+                    args[0].type_name(),
+                    "io.popen",
+                ))
+            }
+        };
+
+        let mode = match args.get(1) {
+            Some(LuaValue::String(s)) => s.to_string(),
+            _ => "r".to_string(),
+        };
+
+        FileHandle::popen(&command, &mode).map(create_file_value)
+    })
+}
+
 /// Create io.open(filename, mode) function
-/// Opens a file and returns a file handle
-/// Modes: "r" (read), "w" (write), "a" (append), "rb"/"wb"/"ab" (binary)
-pub fn create_io_open() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+/// Opens a file and returns a file handle, or nil, a message and the errno
+/// when it cannot be opened
+/// Modes: "r", "w", "a", "r+", "w+", "a+", each optionally followed by "b"
+pub fn create_io_open() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
     Rc::new(|args| {
-        if args.len() < 1 {
+        if args.is_empty() {
             return Err(LuaError::arg_count("io.open", 1, args.len()));
         }
 
         let filename = match &args[0] {
-            LuaValue::String(s) => s.clone(),
-            _ => return Err(LuaError::type_error("string", args[0].type_name(), "io.open")),
+            LuaValue::String(s) => s.to_string(),
+            _ => {
+                return Err(LuaError::type_error(
+                    "string",
+                    args[0].type_name(),
+                    "io.open",
+                ))
+            }
         };
 
-        let mode = if args.len() >= 2 {
-            match &args[1] {
-                LuaValue::String(s) => s.clone(),
-                _ => "r".to_string(),
-            }
-        } else {
-            "r".to_string()
+        let mode = match args.get(1) {
+            Some(LuaValue::String(s)) => s.to_string(),
+            _ => "r".to_string(),
         };
 
-        match mode.as_str() {
-            "r" => match File::open(&filename) {
-                Ok(file) => {
-                    let reader = BufReader::new(file);
-                    let fh = FileHandle {
-                        file: Some(Box::new(ReadFileHandle { reader })),
-                    };
+        match FileHandle::open_with(&filename, FileHandle::parse_mode(&mode)?) {
+            Ok(handle) => Ok(vec![create_file_value(handle)]),
+            Err(e) => Ok(io_failure(Some(&filename), &e)),
+        }
+    })
+}
 
-                    let userdata = Rc::new(RefCell::new(Box::new(fh) as Box<dyn std::any::Any>));
-                    Ok(LuaValue::UserData(userdata))
-                }
-                Err(e) => Err(LuaError::file(&filename, format!("io.open() failed to open: {}", e))),
-            },
-            "w" => match File::create(&filename) {
-                Ok(file) => {
-                    let fh = FileHandle {
-                        file: Some(Box::new(WriteFileHandle { file })),
-                    };
-
-                    let userdata = Rc::new(RefCell::new(Box::new(fh) as Box<dyn std::any::Any>));
-                    Ok(LuaValue::UserData(userdata))
-                }
-                Err(e) => Err(LuaError::file(&filename, format!("io.open() failed to create: {}", e))),
-            },
-            "a" => match OpenOptions::new().append(true).create(true).open(&filename) {
-                Ok(file) => {
-                    let fh = FileHandle {
-                        file: Some(Box::new(AppendFileHandle { file })),
-                    };
-
-                    let userdata = Rc::new(RefCell::new(Box::new(fh) as Box<dyn std::any::Any>));
-                    Ok(LuaValue::UserData(userdata))
+/// A `file:read` format
+enum ReadFormat {
+    Line { keep_newline: bool },
+    All,
+    Number,
+    Count(usize),
+}
+
+impl ReadFormat {
+    fn from_lua(value: Option<&LuaValue>) -> LuaResult<Self> {
+        match value {
+            None | Some(LuaValue::Nil) => Ok(ReadFormat::Line {
+                keep_newline: false,
+            }),
+            Some(LuaValue::Number(n)) => Ok(ReadFormat::Count(n.max(0.0) as usize)),
+            Some(LuaValue::String(s)) => {
+                // Lua 5.1 spelled the formats with a leading '*'
+                match s.iter().find(|&&b| b != b'*') {
+                    Some(b'l') => Ok(ReadFormat::Line {
+                        keep_newline: false,
+                    }),
+                    Some(b'L') => Ok(ReadFormat::Line { keep_newline: true }),
+                    Some(b'a') => Ok(ReadFormat::All),
+                    Some(b'n') => Ok(ReadFormat::Number),
+                    _ => Err(LuaError::value(format!(
+                        "file:read() unsupported format: {}",
+                        s
+                    ))),
                 }
-                Err(e) => Err(LuaError::file(&filename, format!("io.open() failed to open: {}", e))),
-            },
-            _ => Err(LuaError::value(format!("io.open() unsupported mode: {}", mode))),
+            }
+            Some(other) => Err(LuaError::type_error(
+                "string or number",
+                other.type_name(),
+                "file:read",
+            )),
         }
-    })
+    }
+
+    /// Read one value; nil marks end of file
+    fn read(&self, fh: &mut FileHandle) -> io::Result<LuaValue> {
+        let text = |s: Option<Vec<u8>>| s.map_or(LuaValue::Nil, |s| LuaValue::String(s.into()));
+        Ok(match self {
+            ReadFormat::Line { keep_newline } => text(fh.read_line(*keep_newline)?),
+            ReadFormat::All => LuaValue::String(fh.read_all()?.into()),
+            ReadFormat::Number => fh.read_number()?.map_or(LuaValue::Nil, LuaValue::Number),
+            ReadFormat::Count(n) => text(fh.read_count(*n)?),
+        })
+    }
 }
 
 /// Create file:read(...) function
-/// Reads from a file handle with various formats
-pub fn create_file_read() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+/// Formats: "l" (line), "L" (line with newline), "a" (rest of file),
+/// "n" (number) or a byte count, "l" when none is given; returns one value
+/// per format, stopping at the first nil for end of file, and nil, a
+/// message and the errno when reading fails
+pub fn create_file_read() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
     Rc::new(|args| {
         if args.is_empty() {
             return Err(LuaError::arg_count("file:read", 1, 0));
         }
-
-        // Extract format string (default "l" for line)
-        let format = if args.len() >= 2 {
-            match &args[1] {
-                LuaValue::String(s) => s.clone(),
-                LuaValue::Number(n) => format!("{}", *n as i64),
-                _ => "l".to_string(),
-            }
+        let formats = if args.len() == 1 {
+            vec![ReadFormat::from_lua(None)?]
         } else {
-            "l".to_string()
+            args[1..]
+                .iter()
+                .map(|arg| ReadFormat::from_lua(Some(arg)))
+                .collect::<LuaResult<Vec<_>>>()?
         };
-
-        match &args[0] {
-            LuaValue::UserData(ud) => {
-                let mut ud_borrow = ud.borrow_mut();
-                if let Some(fh) = ud_borrow.downcast_mut::<FileHandle>() {
-                    match format.as_str() {
-                        "l" | "L" => {
-                            // Read line
-                            match fh.file.as_mut().unwrap().read_line() {
-                                Ok(line) => {
-                                    if format == "L" {
-                                        Ok(LuaValue::String(line))
-                                    } else {
-                                        // Remove trailing newline for "l" format
-                                        Ok(LuaValue::String(
-                                            line.trim_end_matches('\n').to_string(),
-                                        ))
-                                    }
-                                }
-                                Err(e) => Err(LuaError::runtime(format!("file:read() error: {}", e), "io")),
-                            }
-                        }
-                        "a" => {
-                            // Read all
-                            match fh.file.as_mut().unwrap().read_all() {
-                                Ok(content) => Ok(LuaValue::String(content)),
-                                Err(e) => Err(LuaError::runtime(format!("file:read() error: {}", e), "io")),
-                            }
-                        }
-                        _ => Err(LuaError::value(format!("file:read() unsupported format: {}", format))),
-                    }
-                } else {
-                    Err(LuaError::value("Invalid file handle"))
+        try_with_file("file:read", &args, |fh| {
+            let mut values = Vec::with_capacity(formats.len());
+            for format in &formats {
+                let value = format.read(fh)?;
+                let end = value == LuaValue::Nil;
+                values.push(value);
+                if end {
+                    break;
                 }
             }
-            _ => Err(LuaError::type_error("userdata", args[0].type_name(), "file:read")),
+            Ok(values)
+        })
+    })
+}
+
+/// Create file:lines([format]) function
+/// Returns an iterator that reads with `format` until end of file
+pub fn create_file_lines() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|args| {
+        if args.is_empty() {
+            return Err(LuaError::arg_count("file:lines", 1, 0));
         }
+        let format = Rc::new(ReadFormat::from_lua(args.get(1))?);
+        let file = args[0].clone();
+        with_file("file:lines", &args, |_| Ok(()))?;
+        Ok(builtin(Rc::new(move |_| {
+            with_file("file:lines", std::slice::from_ref(&file), |fh| {
+                format.read(fh)
+            })
+        })))
     })
 }
 
 /// Create file:write(...) function
-/// Writes data to a file handle
-pub fn create_file_write() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+/// Writes strings and numbers to a file handle and returns the handle, or
+/// nil, a message and the errno when writing fails; any other argument is
+/// an error, and nothing is written
+pub fn create_file_write() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
     Rc::new(|args| {
         if args.is_empty() {
             return Err(LuaError::arg_count("file:write", 1, 0));
         }
-
-        match &args[0] {
-            LuaValue::UserData(ud) => {
-                let mut ud_borrow = ud.borrow_mut();
-                if let Some(fh) = ud_borrow.downcast_mut::<FileHandle>() {
-                    let mut total_written = 0;
-
-                    for arg in &args[1..] {
-                        let data = match arg {
-                            LuaValue::String(s) => s.clone(),
-                            LuaValue::Number(n) => {
-                                if n.fract() == 0.0 && !n.is_infinite() {
-                                    format!("{}", *n as i64)
-                                } else {
-                                    n.to_string()
-                                }
-                            }
-                            _ => arg.to_string(),
-                        };
-
-                        match fh.file.as_mut().unwrap().write(&data) {
-                            Ok(_) => total_written += data.len(),
-                            Err(e) => return Err(LuaError::runtime(format!("file:write() error: {}", e), "io")),
-                        }
-                    }
-
-                    Ok(LuaValue::Number(total_written as f64))
-                } else {
-                    Err(LuaError::value("Invalid file handle"))
+        let mut data = Vec::with_capacity(args.len() - 1);
+        for (i, arg) in args[1..].iter().enumerate() {
+            data.push(match arg {
+                LuaValue::String(s) => s.clone(),
+                LuaValue::Number(n) => format_number(*n).into(),
+                other => {
+                    return Err(LuaError::value(format!(
+                        "bad argument #{} to 'write' (string expected, got {})",
+                        i + 1,
+                        other.type_name()
+                    )))
                 }
-            }
-            _ => Err(LuaError::type_error("userdata", args[0].type_name(), "file:write")),
+            });
         }
+
+        try_with_file("file:write", &args, |fh| {
+            for text in &data {
+                fh.write(text)?;
+            }
+            Ok(vec![args[0].clone()])
+        })
+    })
+}
+
+/// Create file:seek([whence [, offset]]) function
+/// Whence is "set", "cur" (default) or "end"; returns the new position
+pub fn create_file_seek() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|args| {
+        let whence = match args.get(1) {
+            None | Some(LuaValue::Nil) => "cur".to_string(),
+            Some(LuaValue::String(s)) => s.to_string(),
+            Some(other) => {
+                return Err(LuaError::type_error(
+                    "string",
+                    other.type_name(),
+                    "file:seek",
+                ))
+            }
+        };
+        let offset = match args.get(2) {
+            None | Some(LuaValue::Nil) => 0,
+            Some(LuaValue::Number(n)) => *n as i64,
+            Some(other) => {
+                return Err(LuaError::type_error(
+                    "number",
+                    other.type_name(),
+                    "file:seek",
+                ))
+            }
+        };
+        let from = match whence.as_str() {
+            "set" if offset < 0 => {
+                return Err(LuaError::value("file:seek() negative position"));
+            }
+            "set" => SeekFrom::Start(offset as u64),
+            "cur" => SeekFrom::Current(offset),
+            "end" => SeekFrom::End(offset),
+            _ => {
+                return Err(LuaError::value(format!(
+                    "file:seek() invalid option: {}",
+                    whence
+                )))
+            }
+        };
+        let position = with_file("file:seek", &args, |fh| fh.seek(from))?;
+        Ok(LuaValue::Number(position as f64))
+    })
+}
+
+/// Create file:flush() function
+/// Writes any buffered data and returns the handle
+pub fn create_file_flush() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|args| {
+        with_file("file:flush", &args, FileHandle::flush)?;
+        Ok(args[0].clone())
+    })
+}
+
+/// Create file:setvbuf(mode [, size]) function
+/// Mode is "no", "line" or "full"; size is the "full" buffer size in bytes
+pub fn create_file_setvbuf() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|args| {
+        let mode = match args.get(1) {
+            Some(LuaValue::String(s)) => match s.as_bytes() {
+                b"no" => BufferMode::No,
+                b"line" => BufferMode::Line,
+                b"full" => BufferMode::Full,
+                _ => {
+                    return Err(LuaError::value(format!(
+                        "file:setvbuf() invalid mode: {}",
+                        s
+                    )))
+                }
+            },
+            _ => return Err(LuaError::arg_count("file:setvbuf", 2, args.len())),
+        };
+        let size = match args.get(2) {
+            Some(LuaValue::Number(n)) if *n >= 1.0 => Some(*n as usize),
+            _ => None,
+        };
+        with_file("file:setvbuf", &args, |fh| fh.set_buffering(mode, size))?;
+        Ok(LuaValue::Boolean(true))
     })
 }
 
 /// Create file:close() function
-/// Closes a file handle
-pub fn create_file_close() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+/// Flushes and closes a file handle; later operations on it fail
+/// Returns true, or for an `io.popen` handle the command's status as
+/// `os.execute` reports it
+pub fn create_file_close() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
     Rc::new(|args| {
         if args.is_empty() {
             return Err(LuaError::arg_count("file:close", 1, 0));
         }
-
-        match &args[0] {
-            LuaValue::UserData(_ud) => {
-                // File will be closed when UserData is dropped (RAII)
-                Ok(LuaValue::Nil)
-            }
-            _ => Err(LuaError::type_error("userdata", args[0].type_name(), "file:close")),
+        match with_file("file:close", &args, FileHandle::close)? {
+            Some(status) => Ok(exit_status_values(status)),
+            None => Ok(vec![LuaValue::Boolean(true)]),
         }
     })
 }
 
-/// Create io.input([filename]) function
-/// Sets or gets the current input file
-pub fn create_io_input() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+/// Create io.type(value) function
+/// Returns "file", "closed file", or nil for anything that is not a file
+pub fn create_io_type() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
     Rc::new(|args| {
-        if args.is_empty() {
-            // Get current input file (stdin placeholder)
-            Ok(LuaValue::String("<stdin>".to_string()))
-        } else {
-            // Set input file - would need interpreter context to fully implement
-            match &args[0] {
-                LuaValue::String(filename) => match File::open(filename) {
-                    Ok(file) => {
-                        let reader = BufReader::new(file);
-                        let fh = FileHandle {
-                            file: Some(Box::new(ReadFileHandle { reader })),
-                        };
-                        let userdata =
-                            Rc::new(RefCell::new(Box::new(fh) as Box<dyn std::any::Any>));
-                        Ok(LuaValue::UserData(userdata))
-                    }
-                    Err(e) => Err(LuaError::file(filename, format!("io.input() failed: {}", e))),
-                },
-                _ => Err(LuaError::type_error("string", args[0].type_name(), "io.input")),
-            }
+        let Some(userdata) = args.first().and_then(file_userdata) else {
+            return Ok(LuaValue::Nil);
+        };
+        let closed = userdata
+            .borrow()
+            .downcast_ref::<FileHandle>()
+            .is_some_and(FileHandle::is_closed);
+        let kind = if closed { "closed file" } else { "file" };
+        Ok(LuaValue::String(kind.into()))
+    })
+}
+
+/// Resolve an io.input/io.output argument: a filename opened with `mode`,
+/// or an existing file
+fn default_stream_arg(name: &str, value: &LuaValue, mode: &str) -> LuaResult<LuaValue> {
+    match value {
+        LuaValue::String(filename) => {
+            FileHandle::open(&filename.to_str_lossy(), mode).map(create_file_value)
+        }
+        other if file_userdata(other).is_some() => Ok(other.clone()),
+        other => Err(LuaError::type_error(
+            "string or file",
+            other.type_name(),
+            name,
+        )),
+    }
+}
+
+/// Create io.input([file]) function
+/// With a filename or file, makes it the default input; returns the
+/// current default input
+pub fn create_io_input(
+    streams: Rc<RefCell<IoStreams>>,
+) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(move |args| {
+        if let Some(arg) = args.first().filter(|arg| !matches!(arg, LuaValue::Nil)) {
+            streams.borrow_mut().input = default_stream_arg("io.input", arg, "r")?;
         }
+        Ok(streams.borrow().input.clone())
     })
 }
 
-/// Create io.output([filename]) function
-/// Sets or gets the current output file
-pub fn create_io_output() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
-    Rc::new(|args| {
+/// Create io.output([file]) function
+/// With a filename (truncated) or file, makes it the default output;
+/// returns the current default output
+pub fn create_io_output(
+    streams: Rc<RefCell<IoStreams>>,
+) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(move |args| {
+        if let Some(arg) = args.first().filter(|arg| !matches!(arg, LuaValue::Nil)) {
+            streams.borrow_mut().output = default_stream_arg("io.output", arg, "w")?;
+        }
+        Ok(streams.borrow().output.clone())
+    })
+}
+
+/// Create io.write(...) function
+/// Equivalent to io.output():write(...)
+pub fn create_io_write(
+    streams: Rc<RefCell<IoStreams>>,
+) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
+    let write = create_file_write();
+    Rc::new(move |args| {
+        let output = streams.borrow().output.clone();
+        write(std::iter::once(output).chain(args).collect())
+    })
+}
+
+/// Create io.read([format]) function
+/// Equivalent to io.input():read(format)
+pub fn create_io_read(
+    streams: Rc<RefCell<IoStreams>>,
+) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
+    let read = create_file_read();
+    Rc::new(move |args| {
+        let input = streams.borrow().input.clone();
+        read(std::iter::once(input).chain(args).collect())
+    })
+}
+
+/// Create io.lines([filename [, format]]) function
+/// Iterates over the named file, or the default input when no name is given
+pub fn create_io_lines(
+    streams: Rc<RefCell<IoStreams>>,
+) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    let lines = create_file_lines();
+    Rc::new(move |mut args| {
+        let file = match args.first() {
+            None | Some(LuaValue::Nil) => streams.borrow().input.clone(),
+            Some(arg) => default_stream_arg("io.lines", arg, "r")?,
+        };
         if args.is_empty() {
-            // Get current output file (stdout placeholder)
-            Ok(LuaValue::String("<stdout>".to_string()))
+            args.push(file);
         } else {
-            // Set output file
-            match &args[0] {
-                LuaValue::String(filename) => match File::create(filename) {
-                    Ok(file) => {
-                        let fh = FileHandle {
-                            file: Some(Box::new(WriteFileHandle { file })),
-                        };
-                        let userdata =
-                            Rc::new(RefCell::new(Box::new(fh) as Box<dyn std::any::Any>));
-                        Ok(LuaValue::UserData(userdata))
-                    }
-                    Err(e) => Err(LuaError::file(filename, format!("io.output() failed: {}", e))),
-                },
-                _ => Err(LuaError::type_error("string", args[0].type_name(), "io.output")),
-            }
+            args[0] = file;
         }
+        lines(args)
+    })
+}
+
+/// Create io.close([file]) function
+/// Closes `file`, or the default output when none is given
+pub fn create_io_close(
+    streams: Rc<RefCell<IoStreams>>,
+) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
+    let close = create_file_close();
+    Rc::new(move |args| match args.first() {
+        None | Some(LuaValue::Nil) => close(vec![streams.borrow().output.clone()]),
+        Some(_) => close(args),
     })
 }
 
@@ -340,41 +1092,35 @@ pub fn create_io_output() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
 // OS FUNCTIONS
 // ============================================================================
 
-/// Create os.execute(command) function
-/// Executes a system command
-pub fn create_os_execute() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+/// Create os.execute([command]) function
+/// Runs command in the shell and returns true or nil, then "exit" and the
+/// exit code or "signal" and the signal number. Without a command, returns
+/// whether a shell is available.
+pub fn create_os_execute() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
     Rc::new(|args| {
-        if args.is_empty() {
-            return Err(LuaError::arg_count("os.execute", 1, 0));
-        }
-
-        let command = match &args[0] {
-            LuaValue::String(s) => s.clone(),
-            _ => return Err(LuaError::type_error("string", args[0].type_name(), "os.execute")),
-        };
-
-        #[cfg(unix)]
-        {
-            use std::process::Command;
-            match Command::new("bash").arg("-c").arg(&command).status() {
-                Ok(status) => {
-                    let exit_code = status.code().unwrap_or(1) as f64;
-                    Ok(LuaValue::Number(exit_code))
-                }
-                Err(e) => Err(LuaError::runtime(format!("os.execute() failed: {}", e), "system call")),
+        let command = match args.first() {
+            None | Some(LuaValue::Nil) => {
+                let available = shell_command("exit 0")
+                    .status()
+                    .is_ok_and(|status| status.success());
+                return Ok(vec![LuaValue::Boolean(available)]);
             }
-        }
-
-        #[cfg(not(unix))]
-        {
-            use std::process::Command;
-            match Command::new("cmd").args(&["/C", &command]).output() {
-                Ok(output) => {
-                    let exit_code = output.status.code().unwrap_or(1) as f64;
-                    Ok(LuaValue::Number(exit_code))
-                }
-                Err(e) => Err(LuaError::runtime(format!("os.execute() failed: {}", e), "system call")),
+            Some(LuaValue::String(s)) => s.to_string(),
+            Some(other) => {
+                return Err(LuaError::type_error(
+                    "string",
+                    other.type_name(),
+                    "os.execute",
+                ))
             }
+        };
+
+        match shell_command(&command).status() {
+            Ok(status) => Ok(exit_status_values(status)),
+            Err(e) => Err(LuaError::runtime(
+                format!("os.execute() failed: {}", e),
+                "system call",
+            )),
         }
     })
 }
@@ -392,12 +1138,20 @@ pub fn create_os_exit() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
             0
         };
 
+        if IN_BROWSER {
+            return Err(LuaError::runtime(
+                "os.exit is not available in the browser",
+                "os.exit",
+            ));
+        }
         std::process::exit(code);
     })
 }
 
 /// Create os.getenv(name) function
-/// Gets an environment variable
+/// Gets an environment variable: `nil` when it is unset, the empty string
+/// when it is set but empty. Values that are not valid UTF-8 come back with
+/// replacement characters rather than as `nil`.
 pub fn create_os_getenv() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
     Rc::new(|args| {
         if args.is_empty() {
@@ -405,17 +1159,56 @@ pub fn create_os_getenv() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
         }
 
         let var_name = match &args[0] {
-            LuaValue::String(s) => s.clone(),
-            _ => return Err(LuaError::type_error("string", args[0].type_name(), "os.getenv")),
+            LuaValue::String(s) => s.to_string(),
+            _ => {
+                return Err(LuaError::type_error(
+                    "string",
+                    args[0].type_name(),
+                    "os.getenv",
+                ))
+            }
         };
 
-        match std::env::var(&var_name) {
-            Ok(value) => Ok(LuaValue::String(value)),
-            Err(_) => Ok(LuaValue::Nil),
+        match std::env::var_os(&var_name) {
+            Some(value) => Ok(LuaValue::String(value.to_string_lossy().into())),
+            None => Ok(LuaValue::Nil),
         }
     })
 }
 
+/// Create os.environ() function
+/// Returns a new table mapping every environment variable to its value,
+/// in name order so `pairs` visits them predictably. Later changes to the
+/// environment do not show up in the table.
+pub fn create_os_environ() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|_args| {
+        let mut vars: Vec<(String, String)> = std::env::vars_os()
+            .map(|(name, value)| {
+                (
+                    name.to_string_lossy().into_owned(),
+                    value.to_string_lossy().into_owned(),
+                )
+            })
+            .collect();
+        vars.sort();
+        let data = vars
+            .into_iter()
+            .map(|(name, value)| {
+                (
+                    LuaValue::String(name.into()),
+                    LuaValue::String(value.into()),
+                )
+            })
+            .collect();
+        Ok(LuaValue::Table(Rc::new(RefCell::new(LuaTable {
+            data,
+            metatable: None,
+            frozen: false,
+            removed: None,
+        }))))
+    })
+}
+
 /// Create os.setenv(name, value) function
 /// Sets an environment variable
 pub fn create_os_setenv() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
@@ -425,15 +1218,33 @@ pub fn create_os_setenv() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
         }
 
         let var_name = match &args[0] {
-            LuaValue::String(s) => s.clone(),
-            _ => return Err(LuaError::type_error("string", args[0].type_name(), "os.setenv")),
+            LuaValue::String(s) => s.to_string(),
+            _ => {
+                return Err(LuaError::type_error(
+                    "string",
+                    args[0].type_name(),
+                    "os.setenv",
+                ))
+            }
         };
 
         let var_value = match &args[1] {
-            LuaValue::String(s) => s.clone(),
-            _ => return Err(LuaError::type_error("string", args[1].type_name(), "os.setenv")),
+            LuaValue::String(s) => s.to_string(),
+            _ => {
+                return Err(LuaError::type_error(
+                    "string",
+                    args[1].type_name(),
+                    "os.setenv",
+                ))
+            }
         };
 
+        if IN_BROWSER {
+            return Err(LuaError::runtime(
+                "os.setenv is not available in the browser",
+                "os.setenv",
+            ));
+        }
         std::env::set_var(&var_name, &var_value);
         Ok(LuaValue::Nil)
     })
@@ -443,10 +1254,7 @@ pub fn create_os_setenv() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
 /// Returns the current time in seconds since epoch
 /// If table is provided, returns time for that date
 pub fn create_os_time() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
-    Rc::new(|_args| match SystemTime::now().duration_since(UNIX_EPOCH) {
-        Ok(duration) => Ok(LuaValue::Number(duration.as_secs() as f64)),
-        Err(_) => Err(LuaError::runtime("os.time() failed to get system time", "system")),
-    })
+    Rc::new(|_args| Ok(LuaValue::Number(unix_time().as_secs() as f64)))
 }
 
 /// Create os.clock() function
@@ -460,46 +1268,64 @@ pub fn create_os_clock() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
 }
 
 /// Create os.remove(filename) function
-/// Deletes a file
-pub fn create_os_remove() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+/// Deletes a file; returns true, or nil, a message and the errno
+pub fn create_os_remove() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
     Rc::new(|args| {
         if args.is_empty() {
             return Err(LuaError::arg_count("os.remove", 1, 0));
         }
 
         let filename = match &args[0] {
-            LuaValue::String(s) => s.clone(),
-            _ => return Err(LuaError::type_error("string", args[0].type_name(), "os.remove")),
+            LuaValue::String(s) => s.to_string(),
+            _ => {
+                return Err(LuaError::type_error(
+                    "string",
+                    args[0].type_name(),
+                    "os.remove",
+                ))
+            }
         };
 
         match fs::remove_file(&filename) {
-            Ok(_) => Ok(LuaValue::Nil),
-            Err(e) => Err(LuaError::file(&filename, format!("os.remove() failed: {}", e))),
+            Ok(_) => Ok(vec![LuaValue::Boolean(true)]),
+            Err(e) => Ok(io_failure(Some(&filename), &e)),
         }
     })
 }
 
 /// Create os.rename(oldname, newname) function
-/// Renames or moves a file
-pub fn create_os_rename() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+/// Renames or moves a file; returns true, or nil, a message and the errno
+pub fn create_os_rename() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
     Rc::new(|args| {
         if args.len() < 2 {
             return Err(LuaError::arg_count("os.rename", 2, args.len()));
         }
 
         let oldname = match &args[0] {
-            LuaValue::String(s) => s.clone(),
-            _ => return Err(LuaError::type_error("string", args[0].type_name(), "os.rename")),
+            LuaValue::String(s) => s.to_string(),
+            _ => {
+                return Err(LuaError::type_error(
+                    "string",
+                    args[0].type_name(),
+                    "os.rename",
+                ))
+            }
         };
 
         let newname = match &args[1] {
-            LuaValue::String(s) => s.clone(),
-            _ => return Err(LuaError::type_error("string", args[1].type_name(), "os.rename")),
+            LuaValue::String(s) => s.to_string(),
+            _ => {
+                return Err(LuaError::type_error(
+                    "string",
+                    args[1].type_name(),
+                    "os.rename",
+                ))
+            }
         };
 
         match fs::rename(&oldname, &newname) {
-            Ok(_) => Ok(LuaValue::Nil),
-            Err(e) => Err(LuaError::file(&oldname, format!("os.rename() failed: {}", e))),
+            Ok(_) => Ok(vec![LuaValue::Boolean(true)]),
+            Err(e) => Ok(io_failure(Some(&oldname), &e)),
         }
     })
 }
@@ -509,15 +1335,9 @@ pub fn create_os_rename() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
 pub fn create_os_tmpname() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
     Rc::new(|_args| {
         let tmp_dir = std::env::temp_dir();
-        let filename = format!(
-            "lua_{}",
-            std::time::SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_nanos()
-        );
+        let filename = format!("lua_{}", unix_time().as_nanos());
         let path = tmp_dir.join(filename);
-        Ok(LuaValue::String(path.to_string_lossy().to_string()))
+        Ok(LuaValue::String(path.to_string_lossy().into()))
     })
 }
 
@@ -531,12 +1351,24 @@ pub fn create_os_difftime() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>>
 
         let t2 = match &args[0] {
             LuaValue::Number(n) => *n,
-            _ => return Err(LuaError::type_error("number", args[0].type_name(), "os.difftime")),
+            _ => {
+                return Err(LuaError::type_error(
+                    "number",
+                    args[0].type_name(),
+                    "os.difftime",
+                ))
+            }
         };
 
         let t1 = match &args[1] {
             LuaValue::Number(n) => *n,
-            _ => return Err(LuaError::type_error("number", args[1].type_name(), "os.difftime")),
+            _ => {
+                return Err(LuaError::type_error(
+                    "number",
+                    args[1].type_name(),
+                    "os.difftime",
+                ))
+            }
         };
 
         Ok(LuaValue::Number(t2 - t1))
@@ -547,103 +1379,128 @@ pub fn create_os_difftime() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>>
 pub fn create_os_table() -> LuaValue {
     use crate::lua_value::LuaFunction;
 
-    let mut os_table = HashMap::new();
+    let mut os_table = TableData::new();
 
     os_table.insert(
-        LuaValue::String("execute".to_string()),
-        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_os_execute()))),
+        LuaValue::String("execute".into()),
+        LuaValue::Function(Rc::new(LuaFunction::MultiBuiltin(create_os_execute()))),
     );
     os_table.insert(
-        LuaValue::String("exit".to_string()),
+        LuaValue::String("exit".into()),
         LuaValue::Function(Rc::new(LuaFunction::Builtin(create_os_exit()))),
     );
     os_table.insert(
-        LuaValue::String("getenv".to_string()),
+        LuaValue::String("getenv".into()),
         LuaValue::Function(Rc::new(LuaFunction::Builtin(create_os_getenv()))),
     );
     os_table.insert(
-        LuaValue::String("setenv".to_string()),
+        LuaValue::String("environ".into()),
+        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_os_environ()))),
+    );
+    os_table.insert(
+        LuaValue::String("setenv".into()),
         LuaValue::Function(Rc::new(LuaFunction::Builtin(create_os_setenv()))),
     );
     os_table.insert(
-        LuaValue::String("time".to_string()),
+        LuaValue::String("time".into()),
         LuaValue::Function(Rc::new(LuaFunction::Builtin(create_os_time()))),
     );
     os_table.insert(
-        LuaValue::String("clock".to_string()),
+        LuaValue::String("clock".into()),
         LuaValue::Function(Rc::new(LuaFunction::Builtin(create_os_clock()))),
     );
     os_table.insert(
-        LuaValue::String("remove".to_string()),
-        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_os_remove()))),
+        LuaValue::String("remove".into()),
+        LuaValue::Function(Rc::new(LuaFunction::MultiBuiltin(create_os_remove()))),
     );
     os_table.insert(
-        LuaValue::String("rename".to_string()),
-        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_os_rename()))),
+        LuaValue::String("rename".into()),
+        LuaValue::Function(Rc::new(LuaFunction::MultiBuiltin(create_os_rename()))),
     );
     os_table.insert(
-        LuaValue::String("tmpname".to_string()),
+        LuaValue::String("tmpname".into()),
         LuaValue::Function(Rc::new(LuaFunction::Builtin(create_os_tmpname()))),
     );
     os_table.insert(
-        LuaValue::String("difftime".to_string()),
+        LuaValue::String("difftime".into()),
         LuaValue::Function(Rc::new(LuaFunction::Builtin(create_os_difftime()))),
     );
 
     LuaValue::Table(Rc::new(RefCell::new(LuaTable {
         data: os_table,
         metatable: None,
+        frozen: false,
+        removed: None,
     })))
 }
 
 /// Enhance io table with file I/O functions
-pub fn create_enhanced_io_table() -> LuaValue {
+///
+/// `io.read`, `io.write` and `io.lines` go through the default streams in
+/// `streams`, which `io.input` and `io.output` replace.
+pub fn create_enhanced_io_table(streams: Rc<RefCell<IoStreams>>) -> LuaValue {
     use crate::lua_value::LuaFunction;
 
-    let mut io_table = HashMap::new();
+    let mut io_table = TableData::new();
 
     io_table.insert(
-        LuaValue::String("open".to_string()),
-        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_io_open()))),
+        LuaValue::String("open".into()),
+        LuaValue::Function(Rc::new(LuaFunction::MultiBuiltin(create_io_open()))),
     );
     io_table.insert(
-        LuaValue::String("input".to_string()),
-        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_io_input()))),
+        LuaValue::String("type".into()),
+        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_io_type()))),
     );
     io_table.insert(
-        LuaValue::String("output".to_string()),
-        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_io_output()))),
+        LuaValue::String("input".into()),
+        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_io_input(
+            streams.clone(),
+        )))),
     );
     io_table.insert(
-        LuaValue::String("write".to_string()),
-        LuaValue::Function(Rc::new(LuaFunction::Builtin(Rc::new(|args| {
-            let output = args
-                .iter()
-                .map(|v| match v {
-                    LuaValue::String(s) => s.clone(),
-                    _ => v.to_string(),
-                })
-                .collect::<Vec<_>>()
-                .join("");
-
-            print!("{}", output);
-            Ok(LuaValue::Nil)
-        })))),
+        LuaValue::String("output".into()),
+        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_io_output(
+            streams.clone(),
+        )))),
     );
     io_table.insert(
-        LuaValue::String("read".to_string()),
-        LuaValue::Function(Rc::new(LuaFunction::Builtin(Rc::new(|_args| {
-            use crate::error_types::LuaError;
-            let mut line = String::new();
-            match io::stdin().read_line(&mut line) {
-                Ok(_) => Ok(LuaValue::String(line.trim_end_matches('\n').to_string())),
-                Err(e) => Err(LuaError::file("stdin", format!("io.read() error: {}", e))),
-            }
-        })))),
+        LuaValue::String("write".into()),
+        LuaValue::Function(Rc::new(LuaFunction::MultiBuiltin(create_io_write(
+            streams.clone(),
+        )))),
     );
+    io_table.insert(
+        LuaValue::String("read".into()),
+        LuaValue::Function(Rc::new(LuaFunction::MultiBuiltin(create_io_read(
+            streams.clone(),
+        )))),
+    );
+    io_table.insert(
+        LuaValue::String("lines".into()),
+        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_io_lines(
+            streams.clone(),
+        )))),
+    );
+    io_table.insert(
+        LuaValue::String("close".into()),
+        LuaValue::Function(Rc::new(LuaFunction::MultiBuiltin(create_io_close(
+            streams.clone(),
+        )))),
+    );
+    io_table.insert(
+        LuaValue::String("popen".into()),
+        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_io_popen()))),
+    );
+
+    let streams = streams.borrow();
+    io_table.insert(LuaValue::String("stdin".into()), streams.stdin.clone());
+    io_table.insert(LuaValue::String("stdout".into()), streams.stdout.clone());
+    io_table.insert(LuaValue::String("stderr".into()), streams.stderr.clone());
 
     LuaValue::Table(Rc::new(RefCell::new(LuaTable {
         data: io_table,
         metatable: None,
+        frozen: false,
+        removed: None,
     })))
 }
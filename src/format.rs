@@ -0,0 +1,760 @@
+use crate::lua_parser::{
+    BinaryOp, Block, BlockId, Chunk, Expression, Field, FieldKey, FunctionId, LuaArena, Statement,
+    Trivia, UnaryOp,
+};
+/// Lua source formatter
+///
+/// Prints a parsed `Chunk` back out as canonical Lua: one statement per
+/// line, nested blocks indented, operators spaced, and parentheses only
+/// where precedence needs them. Comments a block was parsed with, through
+/// `ParseOptions::comments`, are printed where they were attached: on
+/// their own lines before a statement or the end of the block, or after
+/// the statement they trailed. Blank lines and the comments the parser
+/// dropped do not survive formatting.
+///
+/// Formatting parses back to the same AST, which makes it a convenient
+/// round-trip check for the parser.
+use crate::lua_string::LuaString;
+
+/// Which quote character string literals are written with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuoteStyle {
+    Double,
+    Single,
+}
+
+impl QuoteStyle {
+    fn char(self) -> char {
+        match self {
+            QuoteStyle::Double => '"',
+            QuoteStyle::Single => '\'',
+        }
+    }
+
+    fn other(self) -> QuoteStyle {
+        match self {
+            QuoteStyle::Double => QuoteStyle::Single,
+            QuoteStyle::Single => QuoteStyle::Double,
+        }
+    }
+}
+
+/// Layout settings for `format_block`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatOptions {
+    /// Spaces per indentation level
+    pub indent: usize,
+    /// Preferred quote; the other one is used when it avoids an escape
+    pub quote: QuoteStyle,
+    /// Table constructors and argument lists that would run past this
+    /// column are split one item per line
+    pub max_width: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            indent: 4,
+            quote: QuoteStyle::Double,
+            max_width: 100,
+        }
+    }
+}
+
+/// Format a chunk as Lua source, ending in a newline unless it is empty
+pub fn format_block(chunk: &Chunk, options: &FormatOptions) -> String {
+    let mut printer = Printer {
+        options,
+        arena: &chunk.arena,
+        out: String::new(),
+        level: 0,
+    };
+    printer.block(chunk);
+    printer.out
+}
+
+/// Format a single expression as if it started a line at column 0; the
+/// functions it defines are found in `arena`
+pub fn format_expression(expr: &Expression, arena: &LuaArena, options: &FormatOptions) -> String {
+    let mut printer = Printer {
+        options,
+        arena,
+        out: String::new(),
+        level: 0,
+    };
+    printer.expression(expr)
+}
+
+fn binary_symbol(op: &BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Subtract => "-",
+        BinaryOp::Multiply => "*",
+        BinaryOp::Divide => "/",
+        BinaryOp::FloorDivide => "//",
+        BinaryOp::Modulo => "%",
+        BinaryOp::Power => "^",
+        BinaryOp::Concat => "..",
+        BinaryOp::BitAnd => "&",
+        BinaryOp::BitOr => "|",
+        BinaryOp::BitXor => "~",
+        BinaryOp::LeftShift => "<<",
+        BinaryOp::RightShift => ">>",
+        BinaryOp::Lt => "<",
+        BinaryOp::Lte => "<=",
+        BinaryOp::Gt => ">",
+        BinaryOp::Gte => ">=",
+        BinaryOp::Eq => "==",
+        BinaryOp::Neq => "~=",
+        BinaryOp::And => "and",
+        BinaryOp::Or => "or",
+    }
+}
+
+/// Precedence of an expression as an operand; atoms bind tightest
+fn expression_precedence(expr: &Expression) -> u8 {
+    match expr {
+        Expression::BinaryOp { op, .. } => op.precedence(),
+        Expression::UnaryOp { .. } => UnaryOp::PRECEDENCE,
+        _ => u8::MAX,
+    }
+}
+
+/// Whether `expr` can be called or indexed without parentheses
+fn is_prefix_expression(expr: &Expression) -> bool {
+    matches!(
+        expr,
+        Expression::Identifier(_)
+            | Expression::Local { .. }
+            | Expression::Upvalue { .. }
+            | Expression::Global(_)
+            | Expression::FieldAccess { .. }
+            | Expression::TableIndexing { .. }
+            | Expression::FunctionCall { .. }
+            | Expression::MethodCall { .. }
+            | Expression::Paren(_)
+    )
+}
+
+/// Name printed for a resolved slot, which no longer carries its source name
+fn slot_name(slot: usize) -> String {
+    format!("_slot{}", slot)
+}
+
+struct Printer<'a> {
+    options: &'a FormatOptions,
+    arena: &'a LuaArena,
+    out: String,
+    level: usize,
+}
+
+impl Printer<'_> {
+    fn indent(&self) -> String {
+        " ".repeat(self.options.indent * self.level)
+    }
+
+    /// Column the next character on the current output line lands in
+    fn column(&self) -> usize {
+        self.out.len() - self.out.rfind('\n').map_or(0, |i| i + 1)
+    }
+
+    fn line(&mut self, text: &str) {
+        self.out.push_str(&self.indent());
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn nested(&mut self, block: BlockId) {
+        self.level += 1;
+        self.block(self.arena.block(block));
+        self.level -= 1;
+    }
+
+    fn block(&mut self, block: &Block) {
+        let mut trivia = block.trivia.iter();
+        for statement in &block.statements {
+            let comments = trivia.next();
+            self.leading(comments);
+            let start = self.out.len();
+            self.statement(statement);
+            self.trailing(comments, start);
+        }
+        if let Some(ret) = &block.return_statement {
+            let comments = trivia.next();
+            self.leading(comments);
+            let start = self.out.len();
+            self.out.push_str(&self.indent());
+            self.out.push_str("return");
+            if !ret.expression_list.is_empty() {
+                self.out.push(' ');
+                let list = self.expression_list(&ret.expression_list);
+                self.out.push_str(&list);
+            }
+            self.out.push('\n');
+            self.trailing(comments, start);
+        }
+        self.leading(trivia.next());
+    }
+
+    /// Comments on their own lines before a statement or the block's end
+    fn leading(&mut self, trivia: Option<&Trivia>) {
+        for comment in trivia.map_or(&[][..], |t| &t.leading) {
+            self.line(&format!("--{}", comment.text));
+        }
+    }
+
+    /// Comments after the statement printed from `start`, on its last line
+    fn trailing(&mut self, trivia: Option<&Trivia>, start: usize) {
+        let comments = trivia.map_or(&[][..], |t| &t.trailing);
+        if comments.is_empty() {
+            return;
+        }
+        let text: Vec<String> = comments.iter().map(|c| format!("--{}", c.text)).collect();
+        // A statement that prints nothing, like `;`, leaves no line to end
+        if self.out.len() == start {
+            self.line(&text.join(" "));
+        } else {
+            self.out.pop();
+            self.out.push(' ');
+            self.out.push_str(&text.join(" "));
+            self.out.push('\n');
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Empty => {}
+            Statement::Assignment { variables, values } => {
+                self.out.push_str(&self.indent());
+                let targets = self.expression_list(variables);
+                self.out.push_str(&targets);
+                self.out.push_str(" = ");
+                let values = self.expression_list(values);
+                self.out.push_str(&values);
+                self.out.push('\n');
+            }
+            Statement::FunctionCall(expr) => {
+                self.out.push_str(&self.indent());
+                let call = self.expression(expr);
+                self.out.push_str(&call);
+                self.out.push('\n');
+            }
+            Statement::Break => self.line("break"),
+            Statement::Label(name) => self.line(&format!("::{}::", name)),
+            Statement::Goto(name) => self.line(&format!("goto {}", name)),
+            Statement::Do(body) => {
+                self.line("do");
+                self.nested(*body);
+                self.line("end");
+            }
+            Statement::While { condition, body } => {
+                let condition = self.expression(condition);
+                self.line(&format!("while {} do", condition));
+                self.nested(*body);
+                self.line("end");
+            }
+            Statement::Repeat { body, condition } => {
+                self.line("repeat");
+                self.nested(*body);
+                let condition = self.expression(condition);
+                self.line(&format!("until {}", condition));
+            }
+            Statement::If {
+                condition,
+                then_block,
+                elseif_parts,
+                else_block,
+            } => {
+                let condition = self.expression(condition);
+                self.line(&format!("if {} then", condition));
+                self.nested(*then_block);
+                for (condition, block) in elseif_parts {
+                    let condition = self.expression(condition);
+                    self.line(&format!("elseif {} then", condition));
+                    self.nested(*block);
+                }
+                if let Some(block) = else_block {
+                    self.line("else");
+                    self.nested(*block);
+                }
+                self.line("end");
+            }
+            Statement::ForNumeric {
+                var,
+                start,
+                end,
+                step,
+                body,
+            } => self.for_numeric(var, start, end, step.as_ref(), *body),
+            Statement::ForNumericSlot {
+                slot,
+                start,
+                end,
+                step,
+                body,
+            } => self.for_numeric(&slot_name(*slot), start, end, step.as_ref(), *body),
+            Statement::ForGeneric {
+                vars,
+                iterables,
+                body,
+            } => self.for_generic(vars, iterables, *body),
+            Statement::ForGenericSlots {
+                slots,
+                iterables,
+                body,
+            } => {
+                let vars: Vec<String> = slots.iter().map(|s| slot_name(*s)).collect();
+                self.for_generic(&vars, iterables, *body)
+            }
+            Statement::FunctionDecl { name, body } => {
+                self.out.push_str(&self.indent());
+                self.out.push_str(&format!("function {}", name));
+                // A method's `self` is implied by the `:` in its name
+                self.function_body(*body, usize::from(name.method));
+                self.out.push('\n');
+            }
+            Statement::LocalFunction { name, body } => {
+                self.out.push_str(&self.indent());
+                self.out.push_str(&format!("local function {}", name));
+                self.function_body(*body, 0);
+                self.out.push('\n');
+            }
+            Statement::LocalVars { names, values } => self.local(names, values.as_ref()),
+            Statement::LocalSlots { slots, values } => {
+                let names: Vec<String> = slots.iter().map(|s| slot_name(*s)).collect();
+                self.local(&names, values.as_ref())
+            }
+        }
+    }
+
+    fn local(&mut self, names: &[String], values: Option<&Vec<Expression>>) {
+        self.out.push_str(&self.indent());
+        self.out.push_str("local ");
+        self.out.push_str(&names.join(", "));
+        if let Some(values) = values {
+            self.out.push_str(" = ");
+            let values = self.expression_list(values);
+            self.out.push_str(&values);
+        }
+        self.out.push('\n');
+    }
+
+    fn for_numeric(
+        &mut self,
+        var: &str,
+        start: &Expression,
+        end: &Expression,
+        step: Option<&Expression>,
+        body: BlockId,
+    ) {
+        let mut header = format!(
+            "for {} = {}, {}",
+            var,
+            self.expression(start),
+            self.expression(end)
+        );
+        if let Some(step) = step {
+            header.push_str(", ");
+            header.push_str(&self.expression(step));
+        }
+        header.push_str(" do");
+        self.line(&header);
+        self.nested(body);
+        self.line("end");
+    }
+
+    fn for_generic(&mut self, vars: &[String], iterables: &[Expression], body: BlockId) {
+        let iterables = self.expression_list(iterables);
+        self.line(&format!("for {} in {} do", vars.join(", "), iterables));
+        self.nested(body);
+        self.line("end");
+    }
+
+    /// Append `(params)` without the first `implicit` ones, the body and
+    /// `end`, leaving the cursor after `end`
+    fn function_body(&mut self, body: FunctionId, implicit: usize) {
+        let body = self.arena.function(body);
+        let mut params = body.params[implicit..].to_vec();
+        if body.varargs {
+            params.push("...".to_string());
+        }
+        self.out.push('(');
+        self.out.push_str(&params.join(", "));
+        self.out.push_str(")\n");
+        self.nested(body.block);
+        self.out.push_str(&self.indent());
+        self.out.push_str("end");
+    }
+
+    fn expression_list(&mut self, exprs: &[Expression]) -> String {
+        exprs
+            .iter()
+            .map(|e| self.expression(e))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Render an operand, parenthesized if it binds looser than `min`
+    fn operand(&mut self, expr: &Expression, min: u8) -> String {
+        let text = self.expression(expr);
+        if expression_precedence(expr) < min {
+            format!("({})", text)
+        } else {
+            text
+        }
+    }
+
+    /// Render the object of a call, index or method call
+    fn prefix(&mut self, expr: &Expression) -> String {
+        let text = self.expression(expr);
+        if is_prefix_expression(expr) {
+            text
+        } else {
+            format!("({})", text)
+        }
+    }
+
+    fn expression(&mut self, expr: &Expression) -> String {
+        match expr {
+            Expression::Nil => "nil".to_string(),
+            Expression::Boolean(b) => b.to_string(),
+            Expression::Number(n) => n.to_string(),
+            Expression::String(s) => self.string(s),
+            Expression::Varargs => "...".to_string(),
+            Expression::Identifier(name)
+            | Expression::Local { name, .. }
+            | Expression::Upvalue { name, .. }
+            | Expression::Global(name) => name.to_string(),
+            Expression::BinaryOp { left, op, right } => {
+                let prec = op.precedence();
+                // The side that does not associate needs a strictly
+                // tighter operand
+                let (left_min, right_min) = if op.is_right_associative() {
+                    (prec + 1, prec)
+                } else {
+                    (prec, prec + 1)
+                };
+                let left = self.operand(left, left_min);
+                let right = self.operand(right, right_min);
+                format!("{} {} {}", left, binary_symbol(op), right)
+            }
+            Expression::UnaryOp { op, operand } => {
+                let operand_text = self.operand(operand, UnaryOp::PRECEDENCE);
+                match op {
+                    UnaryOp::Not => format!("not {}", operand_text),
+                    UnaryOp::Minus if operand_text.starts_with('-') => {
+                        // `--x` would start a comment
+                        format!("-({})", operand_text)
+                    }
+                    UnaryOp::Minus => format!("-{}", operand_text),
+                    UnaryOp::BitNot => format!("~{}", operand_text),
+                    UnaryOp::Length => format!("#{}", operand_text),
+                }
+            }
+            Expression::TableIndexing { object, index } => {
+                let object = self.prefix(object);
+                let index = self.expression(index);
+                format!("{}[{}]", object, index)
+            }
+            Expression::FieldAccess { object, field } => {
+                format!("{}.{}", self.prefix(object), field)
+            }
+            Expression::FunctionCall { function, args } => {
+                let head = self.prefix(function);
+                let args = self.arguments(&head, args);
+                format!("{}{}", head, args)
+            }
+            Expression::MethodCall {
+                object,
+                method,
+                args,
+            } => {
+                let head = format!("{}:{}", self.prefix(object), method);
+                let args = self.arguments(&head, args);
+                format!("{}{}", head, args)
+            }
+            Expression::TableConstructor { fields } => self.table(fields),
+            Expression::Paren(inner) => format!("({})", self.expression(inner)),
+            Expression::FunctionDef(body) => {
+                // Render into a scratch buffer so the body lines pick up
+                // the current indentation
+                let saved = std::mem::replace(&mut self.out, "function".to_string());
+                self.function_body(*body, 0);
+                std::mem::replace(&mut self.out, saved)
+            }
+        }
+    }
+
+    /// Whether `text` fits on the current line after `before` more columns
+    fn fits(&self, before: usize, text: &str) -> bool {
+        !text.contains('\n') && self.column() + before + text.len() <= self.options.max_width
+    }
+
+    fn arguments(&mut self, head: &str, args: &[Expression]) -> String {
+        let items: Vec<String> = args.iter().map(|a| self.expression(a)).collect();
+        let flat = format!("({})", items.join(", "));
+        if items.is_empty() || self.fits(head.len(), &flat) || flat.contains('\n') {
+            return flat;
+        }
+        self.split_items('(', ')', args, |p, arg| p.expression(arg))
+    }
+
+    fn table(&mut self, fields: &[Field]) -> String {
+        if fields.is_empty() {
+            return "{}".to_string();
+        }
+        let items: Vec<String> = fields.iter().map(|f| self.field(f)).collect();
+        let flat = format!("{{{}}}", items.join(", "));
+        if self.fits(0, &flat) {
+            return flat;
+        }
+        self.split_items('{', '}', fields, |p, field| p.field(field))
+    }
+
+    /// Lay out `items` one per line between `open` and `close`, rendering
+    /// each at the deeper indentation
+    fn split_items<T>(
+        &mut self,
+        open: char,
+        close: char,
+        items: &[T],
+        mut render: impl FnMut(&mut Self, &T) -> String,
+    ) -> String {
+        self.level += 1;
+        let indent = self.indent();
+        let lines: Vec<String> = items
+            .iter()
+            .map(|item| format!("{}{},\n", indent, render(self, item)))
+            .collect();
+        self.level -= 1;
+        let mut text = format!("{}\n{}", open, lines.concat());
+        if close == ')' {
+            // Lua has no trailing comma in argument lists
+            text.truncate(text.len() - 2);
+            text.push('\n');
+        }
+        text.push_str(&self.indent());
+        text.push(close);
+        text
+    }
+
+    fn field(&mut self, field: &Field) -> String {
+        let value = self.expression(&field.value);
+        match &field.key {
+            FieldKey::Index(_) => value,
+            FieldKey::Identifier(name) => format!("{} = {}", name, value),
+            FieldKey::Bracket(key) => {
+                let key = self.expression(key);
+                // `[[` would open a long string
+                let key = if key.starts_with('[') {
+                    format!(" {} ", key)
+                } else {
+                    key
+                };
+                format!("[{}] = {}", key, value)
+            }
+        }
+    }
+
+    /// A string literal for the bytes of `s`; bytes that are not part of
+    /// valid UTF-8 are written as decimal escapes
+    fn string(&self, s: &LuaString) -> String {
+        let preferred = self.options.quote;
+        let (mine, other) = (preferred.char() as u8, preferred.other().char() as u8);
+        let quote = if s.contains(&mine) && !s.contains(&other) {
+            preferred.other()
+        } else {
+            preferred
+        }
+        .char();
+
+        let mut out = String::with_capacity(s.len() + 2);
+        out.push(quote);
+        for c in s.chars_or_bytes() {
+            let c = match c {
+                Ok(c) => c,
+                Err(byte) => {
+                    out.push_str(&format!("\\{:03}", byte));
+                    continue;
+                }
+            };
+            match c {
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\t' => out.push_str("\\t"),
+                '\r' => out.push_str("\\r"),
+                c if c == quote => {
+                    out.push('\\');
+                    out.push(c);
+                }
+                c if c.is_ascii_control() => out.push_str(&format!("\\{:03}", c as u32)),
+                c => out.push(c),
+            }
+        }
+        out.push(quote);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua_parser::{parse, parse_source_with, tokenize, ParseOptions, TokenSlice};
+
+    fn parse_code(code: &str) -> Chunk {
+        let tokens = tokenize(code).unwrap();
+        let (rest, block) = parse(TokenSlice::from(tokens.as_slice())).unwrap();
+        assert!(
+            nom::Input::input_len(&rest) == 0,
+            "unparsed input in {:?}",
+            code
+        );
+        block
+    }
+
+    /// Format, then check the output parses to the same AST and is stable
+    fn round_trip(code: &str, options: &FormatOptions) -> String {
+        let block = parse_code(code);
+        let formatted = format_block(&block, options);
+        assert_eq!(parse_code(&formatted), block, "reparse of:\n{}", formatted);
+        assert_eq!(format_block(&parse_code(&formatted), options), formatted);
+        formatted
+    }
+
+    #[test]
+    fn test_canonical_layout() {
+        let code = "local x=1 if x>0 then print('pos') elseif x<0 then print(\"neg\") else x=-x end
+            function obj.a:m(a,...) return a..'!' end while x<3 do x=x+1 end";
+        let expected = "\
+local x = 1
+if x > 0 then
+    print(\"pos\")
+elseif x < 0 then
+    print(\"neg\")
+else
+    x = -x
+end
+function obj.a:m(a, ...)
+    return a .. \"!\"
+end
+while x < 3 do
+    x = x + 1
+end
+";
+        assert_eq!(round_trip(code, &FormatOptions::default()), expected);
+    }
+
+    #[test]
+    fn test_parentheses_follow_precedence() {
+        let options = FormatOptions::default();
+        let cases = [
+            ("x = (1 + 2) * 3", "x = (1 + 2) * 3\n"),
+            ("x = 1 + 2 * 3", "x = 1 + 2 * 3\n"),
+            ("x = (1 - 2) - 3", "x = 1 - 2 - 3\n"),
+            ("x = 1 - (2 - 3)", "x = 1 - (2 - 3)\n"),
+            ("x = 2 ^ 3 ^ 2", "x = 2 ^ 3 ^ 2\n"),
+            ("x = not (a and b)", "x = not (a and b)\n"),
+            ("x = -(-y)", "x = -(-y)\n"),
+            ("x = ('s'):upper()", "x = (\"s\"):upper()\n"),
+        ];
+        for (code, expected) in cases {
+            assert_eq!(round_trip(code, &options), expected);
+        }
+    }
+
+    #[test]
+    fn test_options_control_quotes_indent_and_width() {
+        let options = FormatOptions {
+            indent: 2,
+            quote: QuoteStyle::Single,
+            max_width: 20,
+        };
+        let code = "do t = {first = 'a', second = \"it's\", 3} end";
+        let expected = "\
+do
+  t = {
+    first = 'a',
+    second = \"it's\",
+    3,
+  }
+end
+";
+        assert_eq!(round_trip(code, &options), expected);
+    }
+
+    #[test]
+    fn test_function_values_follow_enclosing_indent() {
+        let code = "do table.insert(t, function(a) if a then return 1 end end) end";
+        let expected = "\
+do
+    table.insert(t, function(a)
+        if a then
+            return 1
+        end
+    end)
+end
+";
+        assert_eq!(round_trip(code, &FormatOptions::default()), expected);
+    }
+
+    #[test]
+    fn test_comments_are_kept() {
+        let options = ParseOptions {
+            comments: true,
+            ..ParseOptions::default()
+        };
+        let format = |code: &str| {
+            let block = parse_source_with(code, &options).unwrap();
+            format_block(&block, &FormatOptions::default())
+        };
+        let code = "-- header
+local x=1 -- one
+--[[ before
+  f ]] function f(a) -- params
+  -- inside
+  return a --[==[ ret ]==]
+  -- tail of f
+end ; -- empty
+if x then x=2 else
+-- only
+end
+-- eof";
+        let expected = "\
+-- header
+local x = 1 -- one
+--[[ before
+  f ]]
+function f(a)
+    -- params
+    -- inside
+    return a --[==[ ret ]==]
+    -- tail of f
+end
+-- empty
+if x then
+    x = 2
+else
+    -- only
+end
+-- eof
+";
+        let formatted = format(code);
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted), formatted);
+        // Comments change nothing else
+        let plain = format_block(&parse_code(code), &FormatOptions::default());
+        assert_eq!(parse_code(&formatted), parse_code(&plain));
+    }
+
+    #[test]
+    fn test_fixtures_round_trip() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/lua");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|e| e == "lua") {
+                let code = std::fs::read_to_string(&path).unwrap();
+                round_trip(&code, &FormatOptions::default());
+            }
+        }
+    }
+}
@@ -0,0 +1,56 @@
+//! `Content-Length` framing for the JSON messages of the language server
+//! and the debug adapter, which both speak over stdio
+use serde_json::Value;
+use std::io::{self, BufRead, Write};
+
+/// Read one `Content-Length` framed message, or `None` at end of input
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Write one message with its `Content-Length` header
+pub fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_framing_round_trip() {
+        let mut buffer = Vec::new();
+        let message = json!({ "jsonrpc": "2.0", "method": "exit" });
+        write_message(&mut buffer, &message).unwrap();
+        let header = format!("Content-Length: {}\r\n\r\n", message.to_string().len());
+        assert!(buffer.starts_with(header.as_bytes()));
+        let mut input = &buffer[..];
+        assert_eq!(read_message(&mut input).unwrap(), Some(message));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+}
@@ -0,0 +1,429 @@
+/// Cycle collection for Lua tables
+///
+/// Values are reference counted, so tables are freed as soon as nothing
+/// refers to them, except when they refer to each other: `t.self = t` or two
+/// tables pointing at one another keep themselves alive forever. The
+/// collector finds such groups by trial deletion. For each table it tracks,
+/// it subtracts the references held by other tracked tables from the
+/// table's reference count; whatever is left comes from somewhere else (a
+/// variable, a closure, the Rust stack) and makes the table a root. Tables
+/// no root reaches are held only by each other, and are emptied so the
+/// reference counts can free them.
+///
+/// Only tables made by `LuaInterpreter::create_table`, which is every table
+/// constructor in Lua code, are tracked. Anything else that refers to a
+/// tracked table, including closures and untracked tables, counts as a
+/// root, so the collector may miss a cycle but never frees a live table.
+/// Metatables made in Rust are the exception: the collector looks through
+/// them as it does tracked tables, but never frees them itself.
+///
+/// A table whose metatable has `__mode` containing `k` or `v` holds its keys
+/// or values weakly. Collection removes the entries whose weak key or value
+/// is otherwise unreachable: a tracked table nothing live reaches, or a
+/// function, userdata or untracked table the entry alone holds. Strings,
+/// numbers and booleans are never removed. A weak-keyed table is an
+/// ephemeron table, so a value that refers back to its own key does not
+/// keep the entry alive.
+use crate::lua_value::{LuaTable, LuaValue};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+type TableRef = Rc<RefCell<LuaTable>>;
+
+/// Collect automatically once at least this many tables are tracked
+const MIN_THRESHOLD: usize = 1024;
+
+/// The tables a collector watches and when it next runs by itself
+#[derive(Debug)]
+pub struct CycleCollector {
+    tables: Vec<Weak<RefCell<LuaTable>>>,
+    /// Collect when a new table brings `tables` to this length
+    threshold: usize,
+    /// Whether new tables trigger collections; `collectgarbage("stop")`
+    /// turns this off
+    pub automatic: bool,
+    /// Collections run so far
+    pub collections: usize,
+    /// Tables freed by all collections so far
+    pub freed: usize,
+}
+
+impl Default for CycleCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CycleCollector {
+    pub fn new() -> Self {
+        CycleCollector {
+            tables: Vec::new(),
+            threshold: MIN_THRESHOLD,
+            automatic: true,
+            collections: 0,
+            freed: 0,
+        }
+    }
+
+    /// Watch a new table, collecting first if enough have been created
+    /// since the last collection
+    pub fn track(&mut self, table: &TableRef) {
+        if self.automatic && self.tables.len() >= self.threshold {
+            self.collect();
+        }
+        self.tables.push(Rc::downgrade(table));
+    }
+
+    /// Number of tracked tables still alive
+    pub fn live(&mut self) -> usize {
+        self.tables.retain(|table| table.strong_count() > 0);
+        self.tables.len()
+    }
+
+    /// Rough size in bytes of the tracked tables and their entries
+    pub fn estimated_bytes(&mut self) -> usize {
+        self.live();
+        self.tables
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|table| {
+                let entries = table.try_borrow().map_or(0, |t| t.data.len());
+                std::mem::size_of::<LuaTable>()
+                    + entries * std::mem::size_of::<(LuaValue, LuaValue)>()
+            })
+            .sum()
+    }
+
+    /// Free every group of tracked tables that only refer to each other,
+    /// and clear dead entries from weak tables, returning how many tables
+    /// were freed
+    pub fn collect(&mut self) -> usize {
+        let mut nodes: Vec<TableRef> = self.tables.iter().filter_map(Weak::upgrade).collect();
+        let tracked_count = nodes.len();
+        // Metatables made in Rust are not tracked, but hold references like
+        // any table; they join the graph after the tracked tables, to be
+        // seen through but never freed
+        let mut index: HashMap<*const RefCell<LuaTable>, usize> = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, table)| (Rc::as_ptr(table), i))
+            .collect();
+        let mut i = 0;
+        while i < nodes.len() {
+            let meta = match nodes[i].try_borrow() {
+                Ok(table) => table.metatable.as_ref().map(|meta| meta.table()),
+                Err(_) => None,
+            };
+            if let Some(meta) = meta {
+                index.entry(Rc::as_ptr(&meta)).or_insert_with(|| {
+                    nodes.push(meta);
+                    nodes.len() - 1
+                });
+            }
+            i += 1;
+        }
+        let tracked = |value: &LuaValue| match value {
+            LuaValue::Table(table) => index.get(&Rc::as_ptr(table)).copied(),
+            _ => None,
+        };
+
+        // References each table gets from other tracked tables, and the
+        // tracked tables each one keeps alive. Weak slots count as
+        // references but keep nothing alive; the value of a weak-keyed
+        // entry is kept alive only while its key is.
+        let mut internal = vec![0; nodes.len()];
+        let mut edges = vec![Vec::new(); nodes.len()];
+        let mut ephemerons = Vec::new();
+        let mut roots = Vec::new();
+        for (i, table) in nodes.iter().enumerate() {
+            // A table someone is modifying is in use
+            let Ok(table) = table.try_borrow() else {
+                roots.push(i);
+                continue;
+            };
+            let mode = WeakMode::of(&table);
+            for (key, value) in &table.data {
+                let key = tracked(key);
+                let value = tracked(value);
+                for j in key.iter().chain(&value) {
+                    internal[*j] += 1;
+                }
+                match (key, value) {
+                    (Some(k), _) if !mode.keys => edges[i].push(k),
+                    _ => {}
+                }
+                match (key, value) {
+                    (_, Some(_)) if mode.values => {}
+                    (Some(k), Some(v)) if mode.keys => ephemerons.push((k, v)),
+                    (_, Some(v)) => edges[i].push(v),
+                    _ => {}
+                }
+            }
+            if let Some(meta) = &table.metatable {
+                if let Some(j) = tracked(&LuaValue::Table(meta.table())) {
+                    internal[j] += 1;
+                    edges[i].push(j);
+                }
+            }
+        }
+        // `nodes` holds one reference to each; any beyond that and the
+        // internal ones come from outside
+        for (i, table) in nodes.iter().enumerate() {
+            if Rc::strong_count(table) - 1 > internal[i] {
+                roots.push(i);
+            }
+        }
+
+        let mut reachable = vec![false; nodes.len()];
+        loop {
+            while let Some(i) = roots.pop() {
+                if !std::mem::replace(&mut reachable[i], true) {
+                    roots.extend(edges[i].iter().copied().filter(|&j| !reachable[j]));
+                }
+            }
+            roots.extend(
+                ephemerons
+                    .iter()
+                    .filter(|(k, v)| reachable[*k] && !reachable[*v])
+                    .map(|(_, v)| *v),
+            );
+            if roots.is_empty() {
+                break;
+            }
+        }
+
+        // Take dead entries out of weak tables and empty the unreachable
+        // tables, dropping what was taken only once no table is borrowed
+        let dead = |value: &LuaValue| match tracked(value) {
+            Some(j) => !reachable[j],
+            None => match value {
+                LuaValue::Table(table) => Rc::strong_count(table) == 1,
+                LuaValue::Function(function) => Rc::strong_count(function) == 1,
+                LuaValue::UserData(data) => Rc::strong_count(data) == 1,
+                _ => false,
+            },
+        };
+        let mut contents = Vec::new();
+        for (table, reachable) in nodes[..tracked_count].iter().zip(&reachable) {
+            let Ok(mut table) = table.try_borrow_mut() else {
+                continue;
+            };
+            if !*reachable {
+                contents.push(LuaTable {
+                    data: std::mem::take(&mut table.data),
+                    metatable: table.metatable.take(),
+                    frozen: false,
+                    removed: None,
+                });
+                continue;
+            }
+            let mode = WeakMode::of(&table);
+            if mode.keys || mode.values {
+                let (live, cleared) =
+                    std::mem::take(&mut table.data)
+                        .into_iter()
+                        .partition(|(key, value)| {
+                            !(mode.keys && dead(key) || mode.values && dead(value))
+                        });
+                table.data = live;
+                contents.push(LuaTable {
+                    data: cleared,
+                    metatable: None,
+                    frozen: false,
+                    removed: None,
+                });
+            }
+        }
+        let freed = reachable[..tracked_count].iter().filter(|r| !**r).count();
+        drop(contents);
+        drop(nodes);
+
+        let live = self.live();
+        self.threshold = MIN_THRESHOLD.max(live * 2);
+        self.collections += 1;
+        self.freed += freed;
+        freed
+    }
+}
+
+/// Which parts of a table's entries are weak, from its `__mode`
+#[derive(Debug, Clone, Copy, Default)]
+struct WeakMode {
+    keys: bool,
+    values: bool,
+}
+
+impl WeakMode {
+    fn of(table: &LuaTable) -> Self {
+        match table.metatable.as_ref().and_then(|meta| meta.get("__mode")) {
+            Some(LuaValue::String(mode)) => WeakMode {
+                keys: mode.contains(&b'k'),
+                values: mode.contains(&b'v'),
+            },
+            _ => WeakMode::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua_value::{Metatable, TableData};
+
+    fn new_table(gc: &mut CycleCollector) -> TableRef {
+        let table = Rc::new(RefCell::new(LuaTable {
+            data: TableData::new(),
+            metatable: None,
+            frozen: false,
+            removed: None,
+        }));
+        gc.track(&table);
+        table
+    }
+
+    fn set(table: &TableRef, key: &str, value: &TableRef) {
+        table
+            .borrow_mut()
+            .data
+            .insert(LuaValue::String(key.into()), LuaValue::Table(value.clone()));
+    }
+
+    #[test]
+    fn test_frees_self_reference() {
+        let mut gc = CycleCollector::new();
+        let t = new_table(&mut gc);
+        set(&t, "self", &t);
+        let weak = Rc::downgrade(&t);
+        drop(t);
+        assert_eq!(gc.live(), 1);
+
+        assert_eq!(gc.collect(), 1);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(gc.live(), 0);
+    }
+
+    #[test]
+    fn test_keeps_cycles_held_from_outside() {
+        let mut gc = CycleCollector::new();
+        let a = new_table(&mut gc);
+        let b = new_table(&mut gc);
+        set(&a, "next", &b);
+        set(&b, "next", &a);
+
+        assert_eq!(gc.collect(), 0);
+        assert_eq!(b.borrow().data.len(), 1);
+
+        drop(b);
+        assert_eq!(gc.collect(), 0);
+        drop(a);
+        assert_eq!(gc.collect(), 2);
+        assert_eq!(gc.live(), 0);
+    }
+
+    #[test]
+    fn test_metatable_references_count() {
+        let mut gc = CycleCollector::new();
+        let t = new_table(&mut gc);
+        let mut meta = HashMap::new();
+        meta.insert("__index".to_string(), LuaValue::Table(t.clone()));
+        t.borrow_mut().metatable = Some(Box::new(meta.into()));
+        drop(t);
+
+        assert_eq!(gc.collect(), 1);
+    }
+
+    #[test]
+    fn test_metatable_tables_count() {
+        let mut gc = CycleCollector::new();
+        let t = new_table(&mut gc);
+        let mt = new_table(&mut gc);
+        set(&mt, "__index", &t);
+        t.borrow_mut().metatable = Some(Box::new(Metatable::new(mt.clone())));
+        drop(mt);
+        assert_eq!(gc.collect(), 0);
+
+        drop(t);
+        assert_eq!(gc.collect(), 2);
+    }
+
+    fn make_weak(table: &TableRef, mode: &str) {
+        let mut meta = HashMap::new();
+        meta.insert("__mode".to_string(), LuaValue::String(mode.into()));
+        table.borrow_mut().metatable = Some(Box::new(meta.into()));
+    }
+
+    #[test]
+    fn test_weak_values_drop_unreachable_entries() {
+        let mut gc = CycleCollector::new();
+        let cache = new_table(&mut gc);
+        make_weak(&cache, "v");
+        let kept = new_table(&mut gc);
+        let dropped = new_table(&mut gc);
+        set(&cache, "kept", &kept);
+        set(&cache, "dropped", &dropped);
+        cache
+            .borrow_mut()
+            .data
+            .insert(LuaValue::String("n".into()), LuaValue::Number(1.0));
+        drop(dropped);
+
+        assert_eq!(gc.collect(), 1);
+        let cache = cache.borrow();
+        assert_eq!(cache.data.len(), 2);
+        assert!(cache.data.contains_key(&LuaValue::String("kept".into())));
+        assert!(cache.data.contains_key(&LuaValue::String("n".into())));
+    }
+
+    #[test]
+    fn test_mode_set_after_setmetatable_is_seen() {
+        let mut gc = CycleCollector::new();
+        let cache = new_table(&mut gc);
+        let mt = new_table(&mut gc);
+        cache.borrow_mut().metatable = Some(Box::new(Metatable::new(mt.clone())));
+        mt.borrow_mut().set(
+            LuaValue::String("__mode".into()),
+            LuaValue::String("v".into()),
+        );
+        set(&cache, "dropped", &new_table(&mut gc));
+
+        assert_eq!(gc.collect(), 1);
+        assert!(cache.borrow().data.is_empty());
+    }
+
+    #[test]
+    fn test_weak_keys_are_ephemerons() {
+        let mut gc = CycleCollector::new();
+        let cache = new_table(&mut gc);
+        make_weak(&cache, "k");
+        let key = new_table(&mut gc);
+        let value = new_table(&mut gc);
+        // The value refers back to its key, which must not keep either alive
+        set(&value, "key", &key);
+        cache
+            .borrow_mut()
+            .data
+            .insert(LuaValue::Table(key.clone()), LuaValue::Table(value.clone()));
+        drop(value);
+
+        assert_eq!(gc.collect(), 0);
+        assert_eq!(cache.borrow().data.len(), 1);
+
+        drop(key);
+        assert_eq!(gc.collect(), 2);
+        assert!(cache.borrow().data.is_empty());
+        assert_eq!(gc.live(), 1);
+    }
+
+    #[test]
+    fn test_collects_automatically() {
+        let mut gc = CycleCollector::new();
+        for _ in 0..MIN_THRESHOLD + 1 {
+            let t = new_table(&mut gc);
+            set(&t, "self", &t);
+        }
+        assert_eq!(gc.collections, 1);
+        assert_eq!(gc.freed, MIN_THRESHOLD);
+        assert_eq!(gc.live(), 1);
+    }
+}
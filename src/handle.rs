@@ -0,0 +1,219 @@
+//! Running a Lua interpreter on its own thread
+//!
+//! Values are `Rc`-based, so a `LuaInterpreter` has to stay on the thread
+//! that created it. `InterpreterHandle` keeps one on a dedicated thread and
+//! sends it work over a channel; the handle itself is `Send` and `Sync`, so
+//! worker threads and async tasks can share it. Jobs run one at a time in
+//! the order they were sent. Values cross the thread boundary as plain data
+//! through the serde conversions in `convert`, or as whatever a closure
+//! passed to `with` returns.
+
+use crate::convert;
+use crate::error_types::{LuaError, LuaResult};
+use crate::executor::ControlFlow;
+use crate::hooks::CancellationToken;
+use crate::lua_interpreter::LuaInterpreter;
+use crate::lua_parser::parse_source;
+use crate::lua_value::LuaValue;
+use crate::vm::execute_chunk;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce(&mut LuaInterpreter) + Send>;
+
+/// A `LuaInterpreter` running on its own thread
+pub struct InterpreterHandle {
+    jobs: Option<mpsc::Sender<Job>>,
+    thread: Option<JoinHandle<()>>,
+    cancel: CancellationToken,
+}
+
+impl InterpreterHandle {
+    /// Start a fresh interpreter on a new thread
+    pub fn spawn() -> io::Result<Self> {
+        Self::spawn_with(LuaInterpreter::new)
+    }
+
+    /// Start the interpreter `setup` builds, on the new thread, so it can
+    /// add search paths, preload modules or sandbox the globals first
+    pub fn spawn_with(setup: impl FnOnce() -> LuaInterpreter + Send + 'static) -> io::Result<Self> {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let (token_tx, token_rx) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("muscm-lua".to_string())
+            .spawn(move || {
+                let mut interp = setup();
+                if token_tx.send(interp.cancellation_token()).is_err() {
+                    return;
+                }
+                for job in queue {
+                    job(&mut interp);
+                }
+            })?;
+        let cancel = token_rx
+            .recv()
+            .map_err(|_| io::Error::other("the interpreter thread panicked while starting"))?;
+        Ok(InterpreterHandle {
+            jobs: Some(jobs),
+            thread: Some(thread),
+            cancel,
+        })
+    }
+
+    /// Run `f` with the interpreter and wait for its result
+    pub fn with<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut LuaInterpreter) -> R + Send + 'static,
+    ) -> LuaResult<R> {
+        let (reply, result) = mpsc::sync_channel(1);
+        let job: Job = Box::new(move |interp| {
+            let _ = reply.send(f(interp));
+        });
+        self.jobs
+            .as_ref()
+            .and_then(|jobs| jobs.send(job).ok())
+            .ok_or_else(stopped)?;
+        result.recv().map_err(|_| stopped())
+    }
+
+    /// Run a chunk for its side effects
+    pub fn exec(&self, code: impl Into<String>) -> LuaResult<()> {
+        let code = code.into();
+        self.with(move |interp| run(interp, &code).map(|_| ()))?
+    }
+
+    /// Run a chunk and convert the first value it returns
+    pub fn eval<T: DeserializeOwned + Send + 'static>(
+        &self,
+        code: impl Into<String>,
+    ) -> LuaResult<T> {
+        let code = code.into();
+        self.with(move |interp| {
+            let values = run(interp, &code)?;
+            convert::from_lua(values.first().unwrap_or(&LuaValue::Nil))
+        })?
+    }
+
+    /// Define a global holding `value` converted to Lua data
+    pub fn set_global<T: Serialize + ?Sized>(&self, name: &str, value: &T) -> LuaResult<()> {
+        let name = name.to_string();
+        let value = serde_json::to_value(value)
+            .map_err(|e| LuaError::value(format!("cannot convert to Lua: {}", e)))?;
+        self.with(move |interp| {
+            let value = convert::to_lua(&value)?;
+            interp.set_global(name, value);
+            Ok(())
+        })?
+    }
+
+    /// Convert the value of a global
+    pub fn get_global<T: DeserializeOwned + Send + 'static>(&self, name: &str) -> LuaResult<T> {
+        let name = name.to_string();
+        self.with(move |interp| convert::from_lua(&interp.get_global(&name)))?
+    }
+
+    /// The interpreter's cancellation token; cancelling it stops the running
+    /// job, and the jobs after it until the token is reset
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Finish the jobs already sent, then stop the thread
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        self.jobs = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for InterpreterHandle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn stopped() -> LuaError {
+    LuaError::runtime("the interpreter thread has stopped", "interpreter handle")
+}
+
+fn run(interp: &mut LuaInterpreter, code: &str) -> LuaResult<Vec<LuaValue>> {
+    let block = parse_source(code).map_err(LuaError::value)?;
+    match execute_chunk(&block, interp)? {
+        ControlFlow::Return(values) => Ok(values),
+        _ => Ok(Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_handle_is_shared_between_threads() {
+        assert_send_sync::<InterpreterHandle>();
+        let handle = Arc::new(InterpreterHandle::spawn().unwrap());
+        handle.exec("count = 0").unwrap();
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let handle = Arc::clone(&handle);
+                thread::spawn(move || handle.exec("count = count + 1").unwrap())
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert_eq!(handle.get_global::<u32>("count").unwrap(), 4);
+    }
+
+    #[test]
+    fn test_values_cross_as_data() {
+        let handle = InterpreterHandle::spawn().unwrap();
+        handle.set_global("names", &["a", "b"]).unwrap();
+        let joined: String = handle.eval("return names[1] .. names[2]").unwrap();
+        assert_eq!(joined, "ab");
+        let pair: (f64, bool) = handle.eval("return {1.5, true}").unwrap();
+        assert_eq!(pair, (1.5, true));
+        assert!(handle
+            .with(|interp| interp.get_global("names") != LuaValue::Nil)
+            .unwrap());
+
+        let err = handle.exec("error('boom')").unwrap_err();
+        assert!(err.to_string().contains("boom"), "{}", err);
+        assert!(handle.exec("return").is_ok());
+    }
+
+    #[test]
+    fn test_setup_and_cancellation() {
+        let handle = InterpreterHandle::spawn_with(|| {
+            let mut interp = LuaInterpreter::new();
+            interp.set_global("answer", LuaValue::Number(42.0));
+            interp
+        })
+        .unwrap();
+        assert_eq!(handle.eval::<i64>("return answer").unwrap(), 42);
+
+        let token = handle.cancellation_token();
+        let canceller = thread::spawn(move || {
+            thread::sleep(std::time::Duration::from_millis(20));
+            token.cancel();
+        });
+        let err = handle.exec("while true do end").unwrap_err();
+        assert!(matches!(err, LuaError::Cancelled), "{:?}", err);
+        canceller.join().unwrap();
+
+        handle.cancellation_token().reset();
+        assert_eq!(handle.eval::<i64>("return answer + 1").unwrap(), 43);
+        handle.shutdown();
+    }
+}
@@ -0,0 +1,159 @@
+/// Cancellation and debug hooks for running Lua scripts
+///
+/// A host stops a script by calling `CancellationToken::cancel` from any
+/// thread; the executor checks the token before every statement and every
+/// VM instruction and fails with `LuaError::Cancelled`. Hooks are the
+/// `debug.sethook` mechanism: a callback run on function calls and returns,
+/// when execution reaches a new line, or after every `count` statements.
+/// The host can install a Rust callback for watchdogs and profilers, and
+/// scripts can install a Lua function.
+use crate::error_types::LuaResult;
+use crate::lua_value::LuaValue;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A flag shared between the interpreter and whoever may stop it
+///
+/// Clones share the flag and can be sent to other threads.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask the running script to stop at its next statement
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Clear the flag so the interpreter can run scripts again
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+/// What a hook is being called for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    /// A function is about to run
+    Call,
+    /// A function has returned
+    Return,
+    /// A statement on a new line is about to run (0 when lines are unknown)
+    Line(usize),
+    /// `count` more statements have run
+    Count,
+}
+
+impl HookEvent {
+    /// The event name passed to Lua hook functions
+    pub fn name(&self) -> &'static str {
+        match self {
+            HookEvent::Call => "call",
+            HookEvent::Return => "return",
+            HookEvent::Line(_) => "line",
+            HookEvent::Count => "count",
+        }
+    }
+}
+
+/// Which events a hook wants, as in the `"crl"` mask of `debug.sethook`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HookMask {
+    pub call: bool,
+    pub ret: bool,
+    pub line: bool,
+}
+
+impl HookMask {
+    /// Parse a `debug.sethook` mask; unknown characters are ignored
+    pub fn parse(mask: &str) -> Self {
+        HookMask {
+            call: mask.contains('c'),
+            ret: mask.contains('r'),
+            line: mask.contains('l'),
+        }
+    }
+
+    /// The mask as `debug.gethook` reports it
+    pub fn as_string(&self) -> String {
+        let mut mask = String::new();
+        for (set, c) in [(self.call, 'c'), (self.ret, 'r'), (self.line, 'l')] {
+            if set {
+                mask.push(c);
+            }
+        }
+        mask
+    }
+}
+
+/// The code a hook runs
+#[derive(Clone)]
+pub enum HookFunction {
+    /// A host callback; returning an error aborts the script with it
+    Host(Rc<dyn Fn(HookEvent) -> LuaResult<()>>),
+    /// A Lua function, called with the event name and the line number
+    Lua(LuaValue),
+}
+
+impl fmt::Debug for HookFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HookFunction::Host(_) => write!(f, "HookFunction::Host"),
+            HookFunction::Lua(value) => write!(f, "HookFunction::Lua({:?})", value),
+        }
+    }
+}
+
+/// An installed hook
+#[derive(Debug, Clone)]
+pub struct Hook {
+    pub function: HookFunction,
+    pub mask: HookMask,
+    /// Fire a `Count` event every this many statements; 0 disables it
+    pub count: u64,
+    /// Statements run since the last `Count` event
+    pub(crate) counted: u64,
+}
+
+impl Hook {
+    pub fn new(function: HookFunction, mask: HookMask, count: u64) -> Self {
+        Hook {
+            function,
+            mask,
+            count,
+            counted: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancellation_token_is_shared_across_threads() {
+        let token = CancellationToken::new();
+        let remote = token.clone();
+        std::thread::spawn(move || remote.cancel()).join().unwrap();
+        assert!(token.is_cancelled());
+        token.reset();
+        assert!(!token.is_cancelled());
+    }
+
+    #[test]
+    fn test_hook_mask_round_trip() {
+        let mask = HookMask::parse("lc");
+        assert!(mask.call && mask.line && !mask.ret);
+        assert_eq!(mask.as_string(), "cl");
+        assert_eq!(HookMask::parse("").as_string(), "");
+    }
+}
@@ -0,0 +1,292 @@
+//! Scripts that wait on the host
+//!
+//! An async host wants a script call like `http_get(url)` to hand control
+//! back so it can await the real request. A `ScriptTask` runs its script as
+//! a task (see `Executor::start`), in which each of its pending functions
+//! suspends the script, coroutines it is called from included, and hands
+//! the host its arguments as a `Step::Pending`. The host answers with
+//! `resume_with` or `fail`, which run the script on to its next step.
+//! Arguments, results and return values cross as JSON-like data through
+//! `convert`.
+//!
+//! The script runs on the thread driving the task, only inside these
+//! calls, so a task is not `Send`; an async runtime keeps it on one thread,
+//! e.g. in a local set.
+
+use crate::convert;
+use crate::coroutines::{self, pending_function};
+use crate::error_types::{LuaError, LuaResult};
+use crate::executor::Executor;
+use crate::hooks::CancellationToken;
+use crate::lua_interpreter::LuaInterpreter;
+use crate::lua_parser::parse_source;
+use crate::lua_value::LuaValue;
+use serde_json::Value;
+
+/// A call the script is waiting on
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pending {
+    /// Name the function was registered under
+    pub function: String,
+    pub args: Vec<Value>,
+}
+
+/// Where the script stopped
+#[derive(Debug)]
+pub enum Step {
+    /// Waiting for `resume_with` or `fail`
+    Pending(Pending),
+    /// Returned these values or failed
+    Finished(LuaResult<Vec<Value>>),
+}
+
+/// How far the script has got
+enum State {
+    /// Not started; the code to run
+    Ready(String),
+    /// Stopped in a pending call
+    Waiting,
+    Finished,
+}
+
+/// A script that stops at every pending call
+pub struct ScriptTask {
+    /// Holds the script while it waits, so it goes before the interpreter
+    executor: Executor,
+    /// Boxed, as the waiting script must find it where it left it
+    interp: Box<LuaInterpreter>,
+    state: State,
+}
+
+impl ScriptTask {
+    /// Prepare `code` in a fresh interpreter where each name in `pending`
+    /// is a global function that waits on the host
+    pub fn spawn(code: impl Into<String>, pending: &[&str]) -> Self {
+        Self::spawn_with(code, pending, LuaInterpreter::new)
+    }
+
+    /// Like `spawn`, in the interpreter `setup` builds
+    pub fn spawn_with(
+        code: impl Into<String>,
+        pending: &[&str],
+        setup: impl FnOnce() -> LuaInterpreter,
+    ) -> Self {
+        let mut interp = Box::new(setup());
+        for name in pending {
+            interp.set_global(*name, pending_function(*name));
+        }
+        ScriptTask {
+            executor: Executor::new(),
+            interp,
+            state: State::Ready(code.into()),
+        }
+    }
+
+    /// Run the script until it makes a pending call or finishes
+    pub fn wait(&mut self) -> Step {
+        match std::mem::replace(&mut self.state, State::Finished) {
+            State::Ready(code) => match parse_source(&code) {
+                Ok(chunk) => {
+                    let step = self.executor.start(&chunk, &mut self.interp);
+                    self.step(step)
+                }
+                Err(e) => Step::Finished(Err(LuaError::value(e))),
+            },
+            State::Waiting => {
+                self.state = State::Waiting;
+                Step::Finished(Err(LuaError::runtime(
+                    "the script is waiting for resume_with or fail",
+                    "script task",
+                )))
+            }
+            State::Finished => Step::Finished(Err(LuaError::runtime(
+                "the script has already finished",
+                "script task",
+            ))),
+        }
+    }
+
+    /// Answer the pending call with `value` and run to the next step
+    pub fn resume_with(&mut self, value: Value) -> Step {
+        if !matches!(self.state, State::Waiting) {
+            return Self::not_waiting();
+        }
+        let step = match convert::to_lua(&value) {
+            Ok(value) => self.executor.resume_with(value, &mut self.interp),
+            Err(e) => self.executor.fail(e.to_string(), &mut self.interp),
+        };
+        self.step(step)
+    }
+
+    /// Make the pending call raise `message` in the script and run to the
+    /// next step
+    pub fn fail(&mut self, message: impl Into<String>) -> Step {
+        if !matches!(self.state, State::Waiting) {
+            return Self::not_waiting();
+        }
+        let step = self.executor.fail(message, &mut self.interp);
+        self.step(step)
+    }
+
+    /// The script's cancellation token; cancelling it stops the script the
+    /// next time it runs
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.interp.cancellation_token()
+    }
+
+    fn not_waiting() -> Step {
+        Step::Finished(Err(LuaError::runtime(
+            "the script is not waiting on a pending call",
+            "script task",
+        )))
+    }
+
+    /// Hand the host the script's step as JSON; a call whose arguments
+    /// have no JSON form fails in the script
+    fn step(&mut self, mut step: coroutines::Step) -> Step {
+        loop {
+            match step {
+                coroutines::Step::Pending(call) => {
+                    match call.args.iter().map(convert::from_lua::<Value>).collect() {
+                        Ok(args) => {
+                            self.state = State::Waiting;
+                            return Step::Pending(Pending {
+                                function: call.function,
+                                args,
+                            });
+                        }
+                        Err(e) => step = self.executor.fail(e.to_string(), &mut self.interp),
+                    }
+                }
+                coroutines::Step::Finished(result) => {
+                    self.state = State::Finished;
+                    return Step::Finished(result.and_then(|values| json(&values)));
+                }
+            }
+        }
+    }
+}
+
+fn json(values: &[LuaValue]) -> LuaResult<Vec<Value>> {
+    values.iter().map(convert::from_lua::<Value>).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn pending(step: Step) -> Pending {
+        match step {
+            Step::Pending(pending) => pending,
+            Step::Finished(result) => panic!("expected a pending call, got {:?}", result),
+        }
+    }
+
+    fn finished(step: Step) -> LuaResult<Vec<Value>> {
+        match step {
+            Step::Finished(result) => result,
+            Step::Pending(pending) => panic!("expected the script to finish, got {:?}", pending),
+        }
+    }
+
+    #[test]
+    fn test_pending_calls_wait_for_the_host() {
+        let mut task = ScriptTask::spawn(
+            "local a = fetch('a', {n = 1})
+             local b = fetch('b')
+             return a.body .. b.body, a.status",
+            &["fetch"],
+        );
+
+        let call = pending(task.wait());
+        assert_eq!(call.function, "fetch");
+        assert_eq!(call.args, vec![json!("a"), json!({"n": 1})]);
+        let call = pending(task.resume_with(json!({"body": "x", "status": 200})));
+        assert_eq!(call.args, vec![json!("b")]);
+
+        let result = finished(task.resume_with(json!({"body": "y"}))).unwrap();
+        assert_eq!(result, vec![json!("xy"), json!(200)]);
+        assert!(finished(task.wait()).is_err());
+    }
+
+    #[test]
+    fn test_failed_calls_raise_in_the_script() {
+        let mut task = ScriptTask::spawn("sleep(1) return 'unreachable'", &["sleep"]);
+        pending(task.wait());
+        let err = finished(task.fail("timed out")).unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{}", err);
+
+        // Answering out of turn is an error rather than a hang
+        let mut task = ScriptTask::spawn("return 1", &[]);
+        assert!(finished(task.resume_with(json!(null))).is_err());
+        assert_eq!(finished(task.wait()).unwrap(), vec![json!(1)]);
+    }
+
+    #[test]
+    fn test_dropping_a_waiting_task_stops_the_script() {
+        let mut task = ScriptTask::spawn("while true do sleep() end", &["sleep"]);
+        pending(task.wait());
+        drop(task);
+
+        let mut task = ScriptTask::spawn("while true do end", &[]);
+        task.cancellation_token().cancel();
+        assert!(matches!(finished(task.wait()), Err(LuaError::Cancelled)));
+    }
+
+    #[test]
+    fn test_pending_calls_suspend_the_coroutines_they_are_in() {
+        let mut task = ScriptTask::spawn(
+            "local gen = coroutine.wrap(function()
+                 for i = 1, 2 do coroutine.yield(fetch(i)) end
+             end)
+             local co = coroutine.create(function() return pcall(fetch, 'inner') end)
+             local _, ok, failed = coroutine.resume(co)
+             return gen() .. gen(), ok, failed",
+            &["fetch"],
+        );
+        assert_eq!(pending(task.wait()).args, vec![json!("inner")]);
+        assert_eq!(pending(task.fail("refused")).args, vec![json!(1)]);
+        assert_eq!(pending(task.resume_with(json!("a"))).args, vec![json!(2)]);
+        let result = finished(task.resume_with(json!("b"))).unwrap();
+        assert_eq!(result[..2], [json!("ab"), json!(false)]);
+        assert!(result[2].as_str().unwrap().contains("refused"));
+
+        // A call with no JSON form fails in the script
+        let mut task = ScriptTask::spawn("return pcall(fetch, print)", &["fetch"]);
+        let result = finished(task.wait()).unwrap();
+        assert_eq!(result[0], json!(false));
+
+        // Dropping the task unwinds the coroutines waiting inside it
+        let mut task = ScriptTask::spawn(
+            "coroutine.wrap(function() coroutine.wrap(fetch)() end)()",
+            &["fetch"],
+        );
+        pending(task.wait());
+        drop(task);
+    }
+
+    #[test]
+    fn test_executor_steps_through_host_calls() {
+        let mut interp = LuaInterpreter::new();
+        interp.set_global("ask", pending_function("ask"));
+        let chunk = parse_source("return ask(1) + ask(2)").unwrap();
+        let mut executor = Executor::new();
+
+        let coroutines::Step::Pending(call) = executor.start(&chunk, &mut interp) else {
+            panic!("expected a pending call");
+        };
+        assert_eq!(call.function, "ask");
+        assert_eq!(call.args, vec![LuaValue::Number(1.0)]);
+        let step = executor.resume_with(LuaValue::Number(10.0), &mut interp);
+        assert!(matches!(step, coroutines::Step::Pending(_)));
+        match executor.resume_with(LuaValue::Number(5.0), &mut interp) {
+            coroutines::Step::Finished(result) => {
+                assert_eq!(result.unwrap(), vec![LuaValue::Number(15.0)])
+            }
+            other => panic!("expected the chunk to finish, got {:?}", other),
+        }
+        let step = executor.resume_with(LuaValue::Nil, &mut interp);
+        assert!(matches!(step, coroutines::Step::Finished(Err(_))));
+    }
+}
@@ -0,0 +1,165 @@
+/// Replaceable standard output and input for embedding hosts
+///
+/// Both interpreters write script output (`print`, `io.write`, `display`)
+/// through an `OutputSink` and Lua reads `io.stdin` through an
+/// `InputSource`. Clones share one slot, so installing a writer or reader
+/// after the interpreter is built redirects every builtin holding a clone.
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+/// Where script output goes: the process's stdout unless a host installs
+/// a writer
+#[derive(Clone, Default)]
+pub struct OutputSink(Rc<RefCell<Option<Box<dyn Write>>>>);
+
+impl OutputSink {
+    /// A sink writing to the process's stdout
+    pub fn stdout() -> Self {
+        Self::default()
+    }
+
+    /// Send all later output to `writer`
+    pub fn set_writer(&self, writer: impl Write + 'static) {
+        *self.0.borrow_mut() = Some(Box::new(writer));
+    }
+
+    /// Send all later output to `callback`, one write at a time
+    pub fn set_callback(&self, callback: impl FnMut(&str) + 'static) {
+        self.set_writer(CallbackWriter(callback));
+    }
+
+    /// Collect all later output into the returned buffer
+    pub fn capture(&self) -> Rc<RefCell<String>> {
+        let buffer = Rc::new(RefCell::new(String::new()));
+        let target = Rc::clone(&buffer);
+        self.set_callback(move |text| target.borrow_mut().push_str(text));
+        buffer
+    }
+
+    /// Go back to writing to the process's stdout
+    pub fn reset(&self) {
+        *self.0.borrow_mut() = None;
+    }
+
+    /// Write `bytes` and flush them through
+    pub fn write_bytes(&self, bytes: &[u8]) -> io::Result<()> {
+        match &mut *self.0.borrow_mut() {
+            Some(writer) => {
+                writer.write_all(bytes)?;
+                writer.flush()
+            }
+            None => {
+                let mut out = io::stdout().lock();
+                out.write_all(bytes)?;
+                out.flush()
+            }
+        }
+    }
+
+    pub fn write_str(&self, text: &str) -> io::Result<()> {
+        self.write_bytes(text.as_bytes())
+    }
+}
+
+impl fmt::Debug for OutputSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let target = if self.0.borrow().is_some() {
+            "host"
+        } else {
+            "stdout"
+        };
+        write!(f, "OutputSink({})", target)
+    }
+}
+
+/// Adapts a text callback to `Write`; invalid UTF-8 is replaced
+struct CallbackWriter<F>(F);
+
+impl<F: FnMut(&str)> Write for CallbackWriter<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (self.0)(&String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Where scripts read standard input from: the process's stdin unless a
+/// host installs a reader
+#[derive(Clone, Default)]
+pub struct InputSource(Rc<RefCell<Option<Box<dyn BufRead>>>>);
+
+impl InputSource {
+    /// A source reading from the process's stdin
+    pub fn stdin() -> Self {
+        Self::default()
+    }
+
+    /// Read all later input from `reader`
+    pub fn set_reader(&self, reader: impl BufRead + 'static) {
+        *self.0.borrow_mut() = Some(Box::new(reader));
+    }
+
+    /// Read all later input from `text`
+    pub fn set_text(&self, text: impl Into<String>) {
+        self.set_reader(io::Cursor::new(text.into().into_bytes()));
+    }
+
+    /// Go back to reading the process's stdin
+    pub fn reset(&self) {
+        *self.0.borrow_mut() = None;
+    }
+
+    /// Run `op` on the current reader
+    pub fn with_reader<T>(
+        &self,
+        op: impl FnOnce(&mut dyn BufRead) -> io::Result<T>,
+    ) -> io::Result<T> {
+        match &mut *self.0.borrow_mut() {
+            Some(reader) => op(reader),
+            None => op(&mut io::stdin().lock()),
+        }
+    }
+}
+
+impl fmt::Debug for InputSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = if self.0.borrow().is_some() {
+            "host"
+        } else {
+            "stdin"
+        };
+        write!(f, "InputSource({})", source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clones_share_the_installed_writer() {
+        let sink = OutputSink::stdout();
+        let clone = sink.clone();
+        let captured = sink.capture();
+        clone.write_str("hello ").unwrap();
+        sink.write_str("world").unwrap();
+        assert_eq!(*captured.borrow(), "hello world");
+    }
+
+    #[test]
+    fn test_input_from_text() {
+        let source = InputSource::stdin();
+        source.set_text("line one\nline two\n");
+        let mut line = String::new();
+        source
+            .clone()
+            .with_reader(|reader| reader.read_line(&mut line))
+            .unwrap();
+        assert_eq!(line, "line one\n");
+    }
+}
@@ -0,0 +1,65 @@
+/// String interning for the Lua front end and runtime
+///
+/// Identifiers, string literals and field names are interned as they are
+/// tokenized, so every occurrence of the same text shares one `Rc<str>`.
+/// Cloning one is a reference-count bump, and comparing two interned strings
+/// takes `Rc`'s pointer-equality fast path before falling back to bytes.
+/// String literals become `LuaString`s sharing the interned allocation.
+/// Strings built at run time (concatenation, `string.*` results) are not
+/// added to the table.
+use crate::lua_string::LuaString;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+thread_local! {
+    static INTERNER: RefCell<HashSet<Rc<str>>> = RefCell::new(HashSet::new());
+}
+
+/// Return the shared copy of `s`, adding it to the table on first use
+pub fn intern(s: &str) -> Rc<str> {
+    INTERNER.with(|table| {
+        let mut table = table.borrow_mut();
+        if let Some(existing) = table.get(s) {
+            return existing.clone();
+        }
+        let interned: Rc<str> = Rc::from(s);
+        table.insert(interned.clone());
+        interned
+    })
+}
+
+/// The string a literal with these bytes makes, shared with every other
+/// use of the same text; only valid UTF-8 is interned
+pub fn intern_bytes(bytes: Vec<u8>) -> LuaString {
+    match String::from_utf8(bytes) {
+        Ok(text) => intern(&text).into(),
+        Err(invalid) => invalid.into_bytes().into(),
+    }
+}
+
+/// Number of distinct strings interned so far on this thread
+pub fn interned_count() -> usize {
+    INTERNER.with(|table| table.borrow().len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_text_shares_allocation() {
+        let a = intern("counter");
+        let b = intern(&String::from("counter"));
+        assert!(Rc::ptr_eq(&a, &b));
+        assert!(!Rc::ptr_eq(&a, &intern("other")));
+    }
+
+    #[test]
+    fn test_table_grows_once_per_string() {
+        let before = interned_count();
+        intern("interned_once_marker");
+        intern("interned_once_marker");
+        assert_eq!(interned_count(), before + 1);
+    }
+}
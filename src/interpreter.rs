@@ -1,22 +1,102 @@
 use crate::ast::{Arena, NodeId, SExpr};
+use crate::bridge;
+use crate::file_io::{FileHandle, OpenMode};
+use crate::host_io::OutputSink;
+use crate::parser;
+use crate::scheme_library::{self, Libraries};
+use crate::scheme_number::{self, IntDiv, Op, Rounding};
 use crate::scheme_stdlib;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::fmt;
+use std::rc::Rc;
+
+/// Port used by the reader and writer builtins
+///
+/// Input files are read whole when opened and behave like string input
+/// ports; output files write through a buffered file handle.
+#[derive(Debug)]
+pub enum Port {
+    /// Input port reading from `text`, starting at byte offset `pos`
+    Input { text: String, pos: usize },
+    /// Output port accumulating everything written to it
+    Output(String),
+    /// Output port writing to a file
+    File(FileHandle),
+    /// Port shut by `close-port`
+    Closed,
+}
+
+/// State shared by an escape continuation and the `call/cc` that captured it
+#[derive(Debug, Default)]
+pub struct Escape {
+    /// True while the capturing `call/cc` is still on the stack
+    active: bool,
+    /// Value passed to the continuation while the stack unwinds
+    value: Option<SVal>,
+}
+
+/// Body of a user procedure, copied out of the arena it was parsed into so
+/// the procedure can still be called once that arena is gone, e.g. after
+/// `load` returns or on a later REPL line
+#[derive(Debug)]
+pub struct ProcBody {
+    /// Name the procedure was defined under, for printing
+    name: Option<String>,
+    arena: Arena,
+    exprs: Vec<NodeId>,
+}
+
+/// Signature of procedures implemented outside the Scheme interpreter
+pub type ForeignFn = Rc<dyn Fn(Vec<SVal>, &Environment, &Arena) -> Result<SVal, String>>;
+
+/// Procedure implemented outside the Scheme interpreter, such as a Lua function
+#[derive(Clone)]
+pub struct ForeignProc {
+    pub name: String,
+    pub func: ForeignFn,
+}
+
+impl fmt::Debug for ForeignProc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ForeignProc({})", self.name)
+    }
+}
 
 /// Runtime value representation for Scheme
 #[derive(Debug, Clone)]
 pub enum SVal {
-    /// Numeric values (integers and floats)
+    /// Inexact real numbers
     Number(f64),
-    /// String values
+    /// Exact integers that fit in an i64
+    Integer(i64),
+    /// Exact integers beyond the i64 range
+    BigInt(BigInt),
+    /// Exact non-integer rationals
+    Rational(BigRational),
+    /// String values; literals and the results of most string procedures,
+    /// which cannot be changed in place
     String(String),
+    /// String made by `make-string` or `string-copy`, the only strings
+    /// `string-set!` and `string-fill!` change. Every binding, list and
+    /// vector holding it shares one buffer, so a change made through one is
+    /// seen through all; procedures that only read strings see its
+    /// contents at the time of the call
+    MutableString(Rc<RefCell<String>>),
     /// Boolean values
     Bool(bool),
     /// Symbols/atoms (quoted or identifiers)
     Atom(String),
     /// Character values
     Char(char),
-    /// Proper and improper lists
+    /// Proper lists
     List(Vec<SVal>),
+    /// Improper list `(a b . c)`: at least one item followed by a tail
+    /// that is not itself a list
+    DottedList(Vec<SVal>, Box<SVal>),
     /// Vector type
     Vector(Vec<SVal>),
     /// Nil/void value
@@ -29,80 +109,273 @@ pub enum SVal {
     /// User-defined procedure
     UserProc {
         params: Vec<String>,
-        body: Box<SExpr>,
+        /// Name bound to the list of arguments after `params`, for dotted
+        /// formals `(a . rest)` or a single symbol `args`
+        rest: Option<String>,
+        /// Body expressions, evaluated in order (implicit begin); shared
+        /// by copies of the procedure, which gives it an identity for `eq?`
+        body: Rc<ProcBody>,
+        /// Scope the procedure was created in
+        scope: Rc<RefCell<Scope>>,
     },
+    /// Input or output port, shared between all references to it
+    Port(Rc<RefCell<Port>>),
+    /// End-of-file object returned by `read` on an exhausted port
+    Eof,
+    /// One-shot escape continuation captured by `call/cc`
+    Continuation(Rc<RefCell<Escape>>),
+    /// Procedure provided by the host, e.g. a Lua function proxy
+    Foreign(ForeignProc),
 }
 
-impl fmt::Display for SVal {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl SVal {
+    /// The value with a mutable string replaced by a copy of its current
+    /// contents, for procedures that only read strings
+    pub fn contents(self) -> SVal {
         match self {
-            SVal::Number(n) => {
-                if n.fract() == 0.0 {
-                    write!(f, "{}", *n as i64)
-                } else {
-                    write!(f, "{}", n)
-                }
+            SVal::MutableString(s) => SVal::String(s.borrow().clone()),
+            other => other,
+        }
+    }
+
+    /// Build the list of `items` followed by `tail`, as `cons` does: a list
+    /// tail is spliced in, so only a non-list tail gives a dotted list
+    pub fn dotted(mut items: Vec<SVal>, tail: SVal) -> SVal {
+        if items.is_empty() {
+            return tail;
+        }
+        match tail {
+            SVal::Nil => SVal::List(items),
+            SVal::List(rest) => {
+                items.extend(rest);
+                SVal::List(items)
             }
-            SVal::String(s) => write!(f, "\"{}\"", s),
-            SVal::Bool(b) => write!(f, "#{}", if *b { 't' } else { 'f' }),
-            SVal::Atom(a) => write!(f, "{}", a),
-            SVal::Char(c) => write!(f, "#\\{}", c),
-            SVal::List(items) => {
-                write!(f, "(")?;
-                for (i, item) in items.iter().enumerate() {
-                    if i > 0 {
-                        write!(f, " ")?;
-                    }
-                    write!(f, "{}", item)?;
+            SVal::DottedList(rest, tail) => {
+                items.extend(rest);
+                SVal::DottedList(items, tail)
+            }
+            tail => SVal::DottedList(items, Box::new(tail)),
+        }
+    }
+
+    /// Render the value the way `display` prints it: strings and
+    /// characters appear as their raw contents
+    pub fn display_string(&self) -> String {
+        struct Displayed<'a>(&'a SVal);
+
+        impl fmt::Display for Displayed<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt_with(f, false)
+            }
+        }
+
+        Displayed(self).to_string()
+    }
+
+    /// Shared printer for `write` (machine-readable) and `display` output
+    ///
+    /// Lists and vectors are walked with an explicit stack, so printing a
+    /// deeply nested structure cannot overflow the Rust stack. They hold
+    /// their items by value rather than through shared cells, so no value
+    /// can contain itself and the printer needs no datum labels.
+    fn fmt_with(&self, f: &mut fmt::Formatter<'_>, write: bool) -> fmt::Result {
+        enum Piece<'a> {
+            Value(&'a SVal),
+            Text(&'static str),
+        }
+
+        let mut pending = vec![Piece::Value(self)];
+        while let Some(piece) = pending.pop() {
+            let value = match piece {
+                Piece::Text(text) => {
+                    f.write_str(text)?;
+                    continue;
+                }
+                Piece::Value(value) => value,
+            };
+            // Pieces are pushed in reverse so they pop in print order
+            let (open, items, tail) = match value {
+                SVal::List(items) => ("(", items, None),
+                SVal::DottedList(items, tail) => ("(", items, Some(&**tail)),
+                SVal::Vector(items) => ("#(", items, None),
+                atom => {
+                    atom.fmt_atom(f, write)?;
+                    continue;
+                }
+            };
+            f.write_str(open)?;
+            pending.push(Piece::Text(")"));
+            if let Some(tail) = tail {
+                pending.push(Piece::Value(tail));
+                pending.push(Piece::Text(" . "));
+            }
+            for (i, item) in items.iter().enumerate().rev() {
+                pending.push(Piece::Value(item));
+                if i > 0 {
+                    pending.push(Piece::Text(" "));
                 }
-                write!(f, ")")
             }
-            SVal::Vector(items) => {
-                write!(f, "#(")?;
-                for (i, item) in items.iter().enumerate() {
-                    if i > 0 {
-                        write!(f, " ")?;
+        }
+        Ok(())
+    }
+
+    /// Print a value that holds no other values
+    fn fmt_atom(&self, f: &mut fmt::Formatter<'_>, write: bool) -> fmt::Result {
+        match self {
+            SVal::Number(n) => write!(f, "{}", scheme_number::format_real(*n)),
+            SVal::Integer(n) => write!(f, "{}", n),
+            SVal::BigInt(n) => write!(f, "{}", n),
+            SVal::Rational(r) => write!(f, "{}", r),
+            SVal::String(s) if write => {
+                write!(f, "\"")?;
+                for c in s.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\t' => write!(f, "\\t")?,
+                        '\r' => write!(f, "\\r")?,
+                        c => write!(f, "{}", c)?,
                     }
-                    write!(f, "{}", item)?;
                 }
-                write!(f, ")")
+                write!(f, "\"")
+            }
+            SVal::String(s) => write!(f, "{}", s),
+            SVal::MutableString(s) => SVal::String(s.borrow().clone()).fmt_atom(f, write),
+            SVal::Bool(b) => write!(f, "#{}", if *b { 't' } else { 'f' }),
+            SVal::Atom(a) => write!(f, "{}", a),
+            SVal::Char(c) if write => match c {
+                ' ' => write!(f, "#\\space"),
+                '\n' => write!(f, "#\\newline"),
+                '\t' => write!(f, "#\\tab"),
+                '\r' => write!(f, "#\\return"),
+                '\0' => write!(f, "#\\null"),
+                '\x07' => write!(f, "#\\alarm"),
+                '\x08' => write!(f, "#\\backspace"),
+                '\x7f' => write!(f, "#\\delete"),
+                '\x1b' => write!(f, "#\\escape"),
+                c => write!(f, "#\\{}", c),
+            },
+            SVal::Char(c) => write!(f, "{}", c),
+            SVal::Nil => write!(f, "()"),
+            SVal::BuiltinProc { name, arity } => match arity {
+                Some(arity) => write!(f, "#<builtin:{}/{}>", name, arity),
+                None => write!(f, "#<builtin:{}/*>", name),
+            },
+            SVal::UserProc {
+                params, rest, body, ..
+            } => {
+                write!(f, "#<procedure")?;
+                if let Some(name) = &body.name {
+                    write!(f, ":{}", name)?;
+                }
+                write!(f, "/{}", params.len())?;
+                if rest.is_some() {
+                    write!(f, "+")?;
+                }
+                write!(f, ">")
+            }
+            SVal::Port(port) => match &*port.borrow() {
+                Port::Input { .. } => write!(f, "#<input-port>"),
+                Port::Output(_) | Port::File(_) => write!(f, "#<output-port>"),
+                Port::Closed => write!(f, "#<closed-port>"),
+            },
+            SVal::Eof => write!(f, "#<eof>"),
+            SVal::Continuation(_) => write!(f, "#<continuation>"),
+            SVal::Foreign(proc) => write!(f, "#<foreign:{}>", proc.name),
+            SVal::List(_) | SVal::DottedList(..) | SVal::Vector(_) => {
+                unreachable!("containers are printed by fmt_with")
             }
-            SVal::Nil => write!(f, "'()"),
-            SVal::BuiltinProc { name, .. } => write!(f, "#<builtin:{}>", name),
-            SVal::UserProc { .. } => write!(f, "#<procedure>"),
         }
     }
 }
 
+/// Values print in their machine-readable `write` form
+impl fmt::Display for SVal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with(f, true)
+    }
+}
+
 impl PartialEq for SVal {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (SVal::Number(a), SVal::Number(b)) => a == b,
+            (SVal::Integer(a), SVal::Integer(b)) => a == b,
+            (SVal::BigInt(a), SVal::BigInt(b)) => a == b,
+            (SVal::Rational(a), SVal::Rational(b)) => a == b,
             (SVal::String(a), SVal::String(b)) => a == b,
             (SVal::Bool(a), SVal::Bool(b)) => a == b,
             (SVal::Atom(a), SVal::Atom(b)) => a == b,
             (SVal::Char(a), SVal::Char(b)) => a == b,
             (SVal::Nil, SVal::Nil) => true,
+            (SVal::MutableString(a), SVal::MutableString(b)) => Rc::ptr_eq(a, b),
+            (SVal::Port(a), SVal::Port(b)) => Rc::ptr_eq(a, b),
+            (SVal::Eof, SVal::Eof) => true,
+            (SVal::Continuation(a), SVal::Continuation(b)) => Rc::ptr_eq(a, b),
+            (SVal::Foreign(a), SVal::Foreign(b)) => Rc::ptr_eq(&a.func, &b.func),
             _ => false,
         }
     }
 }
 
+/// Bindings of one scope, shared by every environment and closure that
+/// sees it so `set!` and `define` are visible through all of them
+#[derive(Default)]
+pub struct Scope {
+    bindings: Vec<(String, SVal)>,
+    parent: Option<Rc<RefCell<Scope>>>,
+}
+
+/// Closures capture the scope they were created in, which may hold the
+/// closure itself, so only the names are printed
+impl fmt::Debug for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scope")
+            .field(
+                "bindings",
+                &self.bindings.iter().map(|(n, _)| n).collect::<Vec<_>>(),
+            )
+            .field("has_parent", &self.parent.is_some())
+            .finish()
+    }
+}
+
 /// Environment for variable bindings and nested scopes
 #[derive(Debug, Clone)]
 pub struct Environment {
-    /// Current scope's variable bindings
+    /// Innermost scope; clones of the environment share it
+    scope: Rc<RefCell<Scope>>,
+    /// Port that output builtins write to, or `sink` when unset
+    output: Option<Rc<RefCell<Port>>>,
+    /// Destination for unredirected output, shared with every child scope
+    sink: OutputSink,
+    /// Libraries defined so far, shared by every environment of a program
+    libraries: Rc<RefCell<Libraries>>,
+    /// What the sandbox this environment was made in allows; `None`
+    /// outside a sandbox
+    allowed: Option<Rc<Allowed>>,
+}
+
+/// The names a sandbox was given, shared by every environment made in it
+#[derive(Debug)]
+struct Allowed {
+    /// Every allowed name, including special forms such as `import`
+    names: Vec<String>,
+    /// The allowed bindings, as they were when the sandbox was made; the
+    /// top level of each library starts with these
     bindings: Vec<(String, SVal)>,
-    /// Reference to parent environment for nested scopes
-    parent: Option<Box<Environment>>,
 }
 
 impl Environment {
     /// Create a new root environment with built-in functions
     pub fn new() -> Self {
         let mut env = Environment {
-            bindings: Vec::new(),
-            parent: None,
+            scope: Rc::new(RefCell::new(Scope::default())),
+            output: None,
+            sink: OutputSink::stdout(),
+            libraries: Rc::new(RefCell::new(Libraries::new())),
+            allowed: None,
         };
 
         // Register all builtins via stdlib module
@@ -113,56 +386,186 @@ impl Environment {
 
     /// Create a new child environment with a parent reference
     pub fn child(&self) -> Self {
+        self.enclosed_by(&self.scope)
+    }
+
+    /// Create an environment with a fresh scope nested in `parent`, keeping
+    /// this environment's output redirection
+    fn enclosed_by(&self, parent: &Rc<RefCell<Scope>>) -> Self {
         Environment {
-            bindings: Vec::new(),
-            parent: Some(Box::new(self.clone())),
+            scope: Rc::new(RefCell::new(Scope {
+                bindings: Vec::new(),
+                parent: Some(parent.clone()),
+            })),
+            output: self.output.clone(),
+            sink: self.sink.clone(),
+            libraries: self.libraries.clone(),
+            allowed: self.allowed.clone(),
+        }
+    }
+
+    /// Create a root environment that only sees the named bindings
+    ///
+    /// Mirrors `LuaInterpreter::sandbox`: leaving out `open-input-file`
+    /// and `open-output-file` keeps a script away from the file system, and
+    /// leaving out `getenv` and the `get-environment-variable` procedures
+    /// hides the process environment, as leaving out `os` does for Lua.
+    /// `define-library` and `import` only work when they are named too, and
+    /// library bodies then see the same bindings as the sandbox. Unknown
+    /// names are ignored.
+    pub fn sandbox(&self, allowed: &[&str]) -> Self {
+        let names: Vec<String> = allowed
+            .iter()
+            .filter(|name| self.allows(name))
+            .map(|name| name.to_string())
+            .collect();
+        let bindings: Vec<(String, SVal)> = names
+            .iter()
+            .filter_map(|name| Some((name.clone(), self.lookup(name)?)))
+            .collect();
+        self.root(bindings.clone(), Some(Rc::new(Allowed { names, bindings })))
+    }
+
+    /// A top-level environment holding `bindings`, sharing this one's
+    /// output and libraries
+    fn root(&self, bindings: Vec<(String, SVal)>, allowed: Option<Rc<Allowed>>) -> Self {
+        Environment {
+            scope: Rc::new(RefCell::new(Scope {
+                bindings,
+                parent: None,
+            })),
+            output: self.output.clone(),
+            sink: self.sink.clone(),
+            libraries: self.libraries.clone(),
+            allowed,
+        }
+    }
+
+    /// Whether the sandbox this environment is in, if any, allows `name`
+    pub(crate) fn allows(&self, name: &str) -> bool {
+        self.allowed
+            .as_ref()
+            .is_none_or(|allowed| allowed.names.iter().any(|n| n == name))
+    }
+
+    /// Bind `(command-line)` to return the script name followed by its
+    /// arguments, as R7RS and Lua's `arg` table describe them
+    pub fn set_command_line(&mut self, script: &str, script_args: &[String]) {
+        let mut line = vec![SVal::String(script.to_string())];
+        line.extend(script_args.iter().cloned().map(SVal::String));
+        self.define(
+            "command-line".to_string(),
+            SVal::Foreign(ForeignProc {
+                name: "command-line".to_string(),
+                func: Rc::new(move |_, _, _| Ok(SVal::List(line.clone()))),
+            }),
+        );
+    }
+
+    /// A fresh top-level environment for the body of a library, sharing
+    /// this one's output and libraries: the builtins, or in a sandbox only
+    /// what the sandbox allows
+    pub(crate) fn library_root(&self) -> Self {
+        match &self.allowed {
+            Some(allowed) => self.root(allowed.bindings.clone(), Some(allowed.clone())),
+            None => {
+                let mut env = self.root(Vec::new(), None);
+                scheme_stdlib::register_stdlib(&mut env);
+                env
+            }
         }
     }
 
+    pub(crate) fn libraries(&self) -> &Rc<RefCell<Libraries>> {
+        &self.libraries
+    }
+
+    /// The `;`-separated templates `import` searches for library files
+    pub fn library_path(&self) -> String {
+        self.libraries.borrow().path.clone()
+    }
+
+    /// Replace the templates `import` searches for library files
+    pub fn set_library_path(&self, path: &str) {
+        self.libraries.borrow_mut().path = path.to_string();
+    }
+
+    /// Where `display`, `write` and `newline` send output when no port is
+    /// given; install a writer on it to capture a program's output
+    pub fn output_sink(&self) -> &OutputSink {
+        &self.sink
+    }
+
     /// Define a variable in the current scope
     pub fn define(&mut self, name: String, value: SVal) {
+        let mut scope = self.scope.borrow_mut();
         // Check if variable already exists in current scope
-        for (n, v) in &mut self.bindings {
+        for (n, v) in &mut scope.bindings {
             if n == &name {
                 *v = value;
                 return;
             }
         }
         // If not found, add new binding
-        self.bindings.push((name, value));
+        scope.bindings.push((name, value));
     }
 
-    /// Look up a variable's value, checking parent scopes recursively
+    /// Look up a variable's value, checking parent scopes from the innermost out
     pub fn lookup(&self, name: &str) -> Option<SVal> {
-        // Check current scope
-        for (n, v) in &self.bindings {
-            if n == name {
-                return Some(v.clone());
-            }
+        let mut scope = self.scope.clone();
+        loop {
+            let parent = {
+                let current = scope.borrow();
+                if let Some((_, v)) = current.bindings.iter().find(|(n, _)| n == name) {
+                    return Some(v.clone());
+                }
+                current.parent.clone()?
+            };
+            scope = parent;
         }
-        // Check parent scope recursively
-        if let Some(parent) = &self.parent {
-            parent.lookup(name)
-        } else {
-            None
+    }
+
+    /// Every name visible from this environment, innermost scope first and
+    /// without duplicates
+    pub fn names(&self) -> Vec<String> {
+        let mut seen = HashSet::new();
+        let mut names = Vec::new();
+        let mut scope = Some(self.scope.clone());
+        while let Some(rc) = scope {
+            let current = rc.borrow();
+            for (name, _) in &current.bindings {
+                if seen.insert(name.clone()) {
+                    names.push(name.clone());
+                }
+            }
+            scope = current.parent.clone();
         }
+        names
     }
 
     /// Update an existing variable (must exist in current or parent scope)
     pub fn set(&mut self, name: &str, value: SVal) -> Result<(), String> {
-        // Check current scope
-        for (n, v) in &mut self.bindings {
-            if n == name {
-                *v = value;
-                return Ok(());
+        let mut scope = self.scope.clone();
+        loop {
+            let parent = {
+                let mut current = scope.borrow_mut();
+                if let Some((_, v)) = current.bindings.iter_mut().find(|(n, _)| n == name) {
+                    *v = value;
+                    return Ok(());
+                }
+                current.parent.clone()
+            };
+            match parent {
+                Some(parent) => scope = parent,
+                None => return Err(format!("Unbound variable: {}", name)),
             }
         }
-        // Check parent scope recursively
-        if let Some(parent) = &mut self.parent {
-            parent.set(name, value)
-        } else {
-            Err(format!("Unbound variable: {}", name))
-        }
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -173,6 +576,9 @@ impl Interpreter {
     fn sexpr_to_sval(expr: &SExpr, arena: &Arena) -> SVal {
         match expr {
             SExpr::Number(n) => SVal::Number(*n),
+            SExpr::Integer(n) => SVal::Integer(*n),
+            SExpr::BigInt(n) => SVal::BigInt(n.clone()),
+            SExpr::Rational(r) => SVal::Rational(r.clone()),
             SExpr::String(s) => SVal::String(s.clone()),
             SExpr::Bool(b) => SVal::Bool(*b),
             SExpr::Char(c) => SVal::Char(*c),
@@ -201,7 +607,30 @@ impl Interpreter {
                     .collect();
                 SVal::Vector(items)
             }
-            _ => SVal::Nil, // Unquote, QuasiQuote, etc. become nil in simple implementation
+            SExpr::DottedList(ids, tail) => {
+                let items: Vec<SVal> = ids
+                    .iter()
+                    .filter_map(|id| arena.get(*id).map(|e| Self::sexpr_to_sval(e, arena)))
+                    .collect();
+                match arena.get(*tail) {
+                    Some(tail) => SVal::dotted(items, Self::sexpr_to_sval(tail, arena)),
+                    None => SVal::List(items),
+                }
+            }
+            SExpr::QuasiQuote(id) => Self::wrap_sval("quasiquote", *id, arena),
+            SExpr::Unquote(id) => Self::wrap_sval("unquote", *id, arena),
+            SExpr::UnquoteSplicing(id) => Self::wrap_sval("unquote-splicing", *id, arena),
+        }
+    }
+
+    /// Build the long form `(keyword datum)` of a reader abbreviation
+    fn wrap_sval(keyword: &str, id: NodeId, arena: &Arena) -> SVal {
+        match arena.get(id) {
+            Some(node) => SVal::List(vec![
+                SVal::Atom(keyword.to_string()),
+                Self::sexpr_to_sval(node, arena),
+            ]),
+            None => SVal::Nil,
         }
     }
 
@@ -222,6 +651,146 @@ impl Interpreter {
         }
     }
 
+    /// Evaluate quasiquote special form: (quasiquote template)
+    fn eval_quasiquote_form(
+        ids: &[NodeId],
+        env: &mut Environment,
+        arena: &Arena,
+    ) -> Result<SVal, String> {
+        if ids.len() != 2 {
+            return Err("quasiquote expects exactly 1 argument".to_string());
+        }
+        let template = arena.get(ids[1]).ok_or("Invalid quasiquote reference")?;
+        Self::eval_quasiquote(template, 1, env, arena)
+    }
+
+    /// Match the long form `(keyword datum)` and return the datum's id
+    fn long_form(ids: &[NodeId], arena: &Arena, keyword: &str) -> Option<NodeId> {
+        if ids.len() == 2 && Self::is_symbol(ids[0], arena, keyword) {
+            Some(ids[1])
+        } else {
+            None
+        }
+    }
+
+    /// Expand a quasiquote template at the given nesting depth.
+    ///
+    /// Unquotes at depth 1 are evaluated; deeper ones are rebuilt as data with
+    /// the depth lowered, and nested quasiquotes raise it.
+    fn eval_quasiquote(
+        expr: &SExpr,
+        depth: usize,
+        env: &mut Environment,
+        arena: &Arena,
+    ) -> Result<SVal, String> {
+        match expr {
+            SExpr::QuasiQuote(id) => {
+                Self::quasiquote_nested("quasiquote", *id, depth + 1, env, arena)
+            }
+            SExpr::Unquote(id) => Self::eval_unquote(*id, depth, env, arena),
+            SExpr::UnquoteSplicing(_) => {
+                Err("unquote-splicing is only valid inside a list".to_string())
+            }
+            SExpr::List(ids) => {
+                if let Some(id) = Self::long_form(ids, arena, "quasiquote") {
+                    return Self::quasiquote_nested("quasiquote", id, depth + 1, env, arena);
+                }
+                if let Some(id) = Self::long_form(ids, arena, "unquote") {
+                    return Self::eval_unquote(id, depth, env, arena);
+                }
+                Ok(SVal::List(Self::quasiquote_items(ids, depth, env, arena)?))
+            }
+            SExpr::DottedList(ids, tail) => {
+                let items = Self::quasiquote_items(ids, depth, env, arena)?;
+                let tail = arena
+                    .get(*tail)
+                    .ok_or("Invalid quasiquote tail reference")?;
+                Ok(SVal::dotted(
+                    items,
+                    Self::eval_quasiquote(tail, depth, env, arena)?,
+                ))
+            }
+            SExpr::Vector(ids) => Ok(SVal::Vector(Self::quasiquote_items(
+                ids, depth, env, arena,
+            )?)),
+            _ => Ok(Self::sexpr_to_sval(expr, arena)),
+        }
+    }
+
+    /// Handle `,x` inside a quasiquote template
+    fn eval_unquote(
+        id: NodeId,
+        depth: usize,
+        env: &mut Environment,
+        arena: &Arena,
+    ) -> Result<SVal, String> {
+        if depth == 1 {
+            let expr = arena.get(id).ok_or("Invalid unquote reference")?;
+            Self::eval(expr, env, arena)
+        } else {
+            Self::quasiquote_nested("unquote", id, depth - 1, env, arena)
+        }
+    }
+
+    /// Rebuild `(keyword datum)` with the datum expanded at a new depth
+    fn quasiquote_nested(
+        keyword: &str,
+        id: NodeId,
+        depth: usize,
+        env: &mut Environment,
+        arena: &Arena,
+    ) -> Result<SVal, String> {
+        let expr = arena.get(id).ok_or("Invalid quasiquote reference")?;
+        Ok(SVal::List(vec![
+            SVal::Atom(keyword.to_string()),
+            Self::eval_quasiquote(expr, depth, env, arena)?,
+        ]))
+    }
+
+    /// Expand the elements of a list or vector template, splicing `,@x` forms
+    fn quasiquote_items(
+        ids: &[NodeId],
+        depth: usize,
+        env: &mut Environment,
+        arena: &Arena,
+    ) -> Result<Vec<SVal>, String> {
+        let mut items = Vec::new();
+        for id in ids {
+            let item = arena
+                .get(*id)
+                .ok_or("Invalid quasiquote element reference")?;
+            let splice = match item {
+                SExpr::UnquoteSplicing(inner) => Some(*inner),
+                SExpr::List(inner) => Self::long_form(inner, arena, "unquote-splicing"),
+                _ => None,
+            };
+
+            match splice {
+                Some(inner) if depth == 1 => {
+                    let expr = arena
+                        .get(inner)
+                        .ok_or("Invalid unquote-splicing reference")?;
+                    match Self::eval(expr, env, arena)? {
+                        SVal::List(values) => items.extend(values),
+                        SVal::Nil => {}
+                        other => {
+                            return Err(format!("unquote-splicing expects a list, got {}", other))
+                        }
+                    }
+                }
+                Some(inner) => items.push(Self::quasiquote_nested(
+                    "unquote-splicing",
+                    inner,
+                    depth - 1,
+                    env,
+                    arena,
+                )?),
+                None => items.push(Self::eval_quasiquote(item, depth, env, arena)?),
+            }
+        }
+        Ok(items)
+    }
+
     /// Evaluate if special form: (if condition consequent alternative?)
     fn eval_if(ids: &[NodeId], env: &mut Environment, arena: &Arena) -> Result<SVal, String> {
         if ids.len() < 3 || ids.len() > 4 {
@@ -251,6 +820,153 @@ impl Interpreter {
         Ok(result)
     }
 
+    /// Evaluate a sequence of expressions, returning the value of the last one
+    fn eval_sequence(ids: &[NodeId], env: &mut Environment, arena: &Arena) -> Result<SVal, String> {
+        let mut result = SVal::Nil;
+        for id in ids {
+            let expr = arena.get(*id).ok_or("Invalid expression reference")?;
+            result = Self::eval(expr, env, arena)?;
+        }
+        Ok(result)
+    }
+
+    /// Check whether a node is the given symbol
+    fn is_symbol(id: NodeId, arena: &Arena, symbol: &str) -> bool {
+        matches!(arena.get(id), Some(SExpr::Atom(name)) if name == symbol)
+    }
+
+    /// Evaluate cond special form: (cond (test expr...) (test => proc) (else expr...))
+    fn eval_cond(ids: &[NodeId], env: &mut Environment, arena: &Arena) -> Result<SVal, String> {
+        for (i, clause_id) in ids[1..].iter().enumerate() {
+            let clause = match arena.get(*clause_id) {
+                Some(SExpr::List(clause)) if !clause.is_empty() => clause,
+                _ => return Err("cond clause must be a non-empty list".to_string()),
+            };
+
+            if Self::is_symbol(clause[0], arena, "else") {
+                if i != ids.len() - 2 {
+                    return Err("else clause must be the last cond clause".to_string());
+                }
+                return Self::eval_sequence(&clause[1..], env, arena);
+            }
+
+            let test_expr = arena.get(clause[0]).ok_or("Invalid cond test reference")?;
+            let test = Self::eval(test_expr, env, arena)?;
+            if !Self::is_truthy(&test) {
+                continue;
+            }
+
+            if clause.len() > 1 && Self::is_symbol(clause[1], arena, "=>") {
+                if clause.len() != 3 {
+                    return Err("cond => clause expects exactly one receiver".to_string());
+                }
+                let receiver_expr = arena
+                    .get(clause[2])
+                    .ok_or("Invalid cond receiver reference")?;
+                let receiver = Self::eval(receiver_expr, env, arena)?;
+                return Self::call_function(receiver, vec![test], env, arena);
+            }
+
+            if clause.len() == 1 {
+                return Ok(test);
+            }
+            return Self::eval_sequence(&clause[1..], env, arena);
+        }
+        Ok(SVal::Nil)
+    }
+
+    /// Evaluate case special form: (case key ((datum...) expr...) (else expr...))
+    fn eval_case(ids: &[NodeId], env: &mut Environment, arena: &Arena) -> Result<SVal, String> {
+        if ids.len() < 2 {
+            return Err("case expects a key expression".to_string());
+        }
+        let key_expr = arena.get(ids[1]).ok_or("Invalid case key reference")?;
+        let key = Self::eval(key_expr, env, arena)?;
+
+        for (i, clause_id) in ids[2..].iter().enumerate() {
+            let clause = match arena.get(*clause_id) {
+                Some(SExpr::List(clause)) if !clause.is_empty() => clause,
+                _ => return Err("case clause must be a non-empty list".to_string()),
+            };
+
+            let matched = if Self::is_symbol(clause[0], arena, "else") {
+                if i != ids.len() - 3 {
+                    return Err("else clause must be the last case clause".to_string());
+                }
+                true
+            } else {
+                match arena.get(clause[0]) {
+                    Some(SExpr::List(data)) => data
+                        .iter()
+                        .filter_map(|id| arena.get(*id))
+                        .any(|datum| Self::sexpr_to_sval(datum, arena) == key),
+                    _ => return Err("case clause must start with a list of data".to_string()),
+                }
+            };
+
+            if matched {
+                if clause.len() > 1 && Self::is_symbol(clause[1], arena, "=>") {
+                    if clause.len() != 3 {
+                        return Err("case => clause expects exactly one receiver".to_string());
+                    }
+                    let receiver_expr = arena
+                        .get(clause[2])
+                        .ok_or("Invalid case receiver reference")?;
+                    let receiver = Self::eval(receiver_expr, env, arena)?;
+                    return Self::call_function(receiver, vec![key], env, arena);
+                }
+                return Self::eval_sequence(&clause[1..], env, arena);
+            }
+        }
+        Ok(SVal::Nil)
+    }
+
+    /// Evaluate when/unless special forms: (when test expr...) / (unless test expr...)
+    fn eval_when(
+        ids: &[NodeId],
+        env: &mut Environment,
+        arena: &Arena,
+        expected: bool,
+    ) -> Result<SVal, String> {
+        if ids.len() < 2 {
+            let form = if expected { "when" } else { "unless" };
+            return Err(format!("{} expects a test expression", form));
+        }
+        let test_expr = arena.get(ids[1]).ok_or("Invalid test reference")?;
+        let test = Self::eval(test_expr, env, arena)?;
+        if Self::is_truthy(&test) == expected {
+            Self::eval_sequence(&ids[2..], env, arena)
+        } else {
+            Ok(SVal::Nil)
+        }
+    }
+
+    /// Evaluate and special form: (and expr...), short-circuiting on the first #f
+    fn eval_and(ids: &[NodeId], env: &mut Environment, arena: &Arena) -> Result<SVal, String> {
+        let mut result = SVal::Bool(true);
+        for id in &ids[1..] {
+            let expr = arena.get(*id).ok_or("Invalid and operand reference")?;
+            result = Self::eval(expr, env, arena)?;
+            if !Self::is_truthy(&result) {
+                break;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Evaluate or special form: (or expr...), short-circuiting on the first true value
+    fn eval_or(ids: &[NodeId], env: &mut Environment, arena: &Arena) -> Result<SVal, String> {
+        let mut result = SVal::Bool(false);
+        for id in &ids[1..] {
+            let expr = arena.get(*id).ok_or("Invalid or operand reference")?;
+            result = Self::eval(expr, env, arena)?;
+            if Self::is_truthy(&result) {
+                break;
+            }
+        }
+        Ok(result)
+    }
+
     /// Evaluate define special form: (define name value) or (define (name params...) body)
     fn eval_define(ids: &[NodeId], env: &mut Environment, arena: &Arena) -> Result<SVal, String> {
         if ids.len() < 3 {
@@ -262,46 +978,40 @@ impl Interpreter {
             // Simple variable definition: (define x 42)
             SExpr::Atom(name) => {
                 let value_expr = arena.get(ids[2]).ok_or("Invalid define value reference")?;
-                let value = Self::eval(value_expr, env, arena)?;
+                // A lambda bound here takes the name it is defined under
+                let value = match value_expr {
+                    SExpr::List(lambda_ids)
+                        if matches!(
+                            lambda_ids.first().and_then(|id| arena.get(*id)),
+                            Some(SExpr::Atom(head)) if head == "lambda"
+                        ) =>
+                    {
+                        Self::eval_lambda(Some(name), lambda_ids, env, arena)?
+                    }
+                    _ => Self::eval(value_expr, env, arena)?,
+                };
                 env.define(name.clone(), value);
                 Ok(SVal::Nil)
             }
-            // Function definition: (define (name params...) body...)
-            SExpr::List(sig_ids) if !sig_ids.is_empty() => {
+            // Function definition: (define (name params...) body...), with
+            // `(define (name params... . rest) body...)` for variadic ones
+            SExpr::List(sig_ids) | SExpr::DottedList(sig_ids, _) if !sig_ids.is_empty() => {
                 let func_expr = arena
                     .get(sig_ids[0])
                     .ok_or("Invalid function name reference")?;
                 match func_expr {
                     SExpr::Atom(func_name) => {
-                        let params: Result<Vec<String>, String> = sig_ids[1..]
-                            .iter()
-                            .filter_map(|id| arena.get(*id))
-                            .map(|p| {
-                                if let SExpr::Atom(s) = p {
-                                    Ok(s.clone())
-                                } else {
-                                    Err("Invalid parameter".to_string())
-                                }
-                            })
-                            .collect();
-                        let params = params?;
-
-                        // Combine remaining items as body (implicit begin)
-                        let body = if ids.len() == 3 {
-                            arena.get(ids[2]).ok_or("Invalid body reference")?.clone()
-                        } else {
-                            // Create a begin form with body expressions
-                            // This is tricky - we need to create new SExpr nodes in the arena
-                            let mut body_ids = vec![];
-                            for body_id in &ids[2..] {
-                                body_ids.push(*body_id);
-                            }
-                            SExpr::List(body_ids)
+                        let tail = match name_expr {
+                            SExpr::DottedList(_, tail) => Some(*tail),
+                            _ => None,
                         };
+                        let (params, rest) = Self::formals(&sig_ids[1..], tail, arena)?;
 
                         let func = SVal::UserProc {
                             params,
-                            body: Box::new(body),
+                            rest,
+                            body: Self::body_exprs(Some(func_name), &ids[2..], arena)?,
+                            scope: env.scope.clone(),
                         };
                         env.define(func_name.clone(), func);
                         Ok(SVal::Nil)
@@ -313,142 +1023,419 @@ impl Interpreter {
         }
     }
 
+    /// Evaluate set! special form: (set! name value), updating the nearest
+    /// existing binding of `name`
+    fn eval_set(ids: &[NodeId], env: &mut Environment, arena: &Arena) -> Result<SVal, String> {
+        if ids.len() != 3 {
+            return Err("set! expects exactly 2 arguments".to_string());
+        }
+        let name = match arena.get(ids[1]) {
+            Some(SExpr::Atom(name)) => name,
+            _ => return Err("set! expects a variable name".to_string()),
+        };
+        let value_expr = arena.get(ids[2]).ok_or("Invalid set! value reference")?;
+        let value = Self::eval(value_expr, env, arena)?;
+        env.set(name, value)?;
+        Ok(SVal::Nil)
+    }
+
     /// Evaluate lambda special form: (lambda (params...) body...)
-    fn eval_lambda(ids: &[NodeId], arena: &Arena) -> Result<SVal, String> {
+    fn eval_lambda(
+        name: Option<&String>,
+        ids: &[NodeId],
+        env: &Environment,
+        arena: &Arena,
+    ) -> Result<SVal, String> {
         if ids.len() < 3 {
             return Err("lambda expects at least 2 arguments".to_string());
         }
         let params_expr = arena.get(ids[1]).ok_or("Invalid lambda params reference")?;
-        let params = match params_expr {
-            SExpr::List(ps_ids) => ps_ids
-                .iter()
-                .filter_map(|id| arena.get(*id))
-                .map(|p| {
-                    if let SExpr::Atom(s) = p {
-                        Ok(s.clone())
-                    } else {
-                        Err("Invalid parameter".to_string())
-                    }
-                })
-                .collect::<Result<Vec<String>, String>>()?,
+        let (params, rest) = match params_expr {
+            SExpr::List(ps_ids) => Self::formals(ps_ids, None, arena)?,
+            SExpr::DottedList(ps_ids, tail) => Self::formals(ps_ids, Some(*tail), arena)?,
+            // (lambda args ...) takes every argument as one list
+            SExpr::Atom(_) => Self::formals(&[], Some(ids[1]), arena)?,
             _ => return Err("lambda expects a parameter list".to_string()),
         };
 
-        // Combine remaining items as body (implicit begin)
-        let body = if ids.len() == 3 {
-            arena
-                .get(ids[2])
-                .ok_or("Invalid lambda body reference")?
-                .clone()
-        } else {
-            // Create list of body ids
-            let mut body_ids = vec![];
-            for body_id in &ids[2..] {
-                body_ids.push(*body_id);
-            }
-            SExpr::List(body_ids)
-        };
-
         Ok(SVal::UserProc {
             params,
-            body: Box::new(body),
+            rest,
+            body: Self::body_exprs(name, &ids[2..], arena)?,
+            scope: env.scope.clone(),
         })
     }
 
+    /// Names of the required parameters and of the rest parameter, if the
+    /// formals have a dotted `tail`; every name must be a distinct symbol
+    fn formals(
+        ids: &[NodeId],
+        tail: Option<NodeId>,
+        arena: &Arena,
+    ) -> Result<(Vec<String>, Option<String>), String> {
+        let name = |id: NodeId| match arena.get(id) {
+            Some(SExpr::Atom(s)) => Ok(s.clone()),
+            _ => Err("Invalid parameter".to_string()),
+        };
+        let params = ids
+            .iter()
+            .map(|id| name(*id))
+            .collect::<Result<Vec<_>, _>>()?;
+        let rest = tail.map(name).transpose()?;
+        let mut names: Vec<&String> = params.iter().chain(&rest).collect();
+        names.sort();
+        if let Some(pair) = names.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(format!("Duplicate parameter: {}", pair[0]));
+        }
+        Ok((params, rest))
+    }
+
+    /// Copy the expressions of a procedure body out of the arena
+    fn body_exprs(
+        name: Option<&String>,
+        ids: &[NodeId],
+        arena: &Arena,
+    ) -> Result<Rc<ProcBody>, String> {
+        let mut body = Arena::new();
+        let exprs = ids
+            .iter()
+            .map(|id| body.copy_from(arena, *id))
+            .collect::<Option<Vec<_>>>()
+            .ok_or("Invalid body reference")?;
+        Ok(Rc::new(ProcBody {
+            name: name.cloned(),
+            arena: body,
+            exprs,
+        }))
+    }
+
     /// Call a function value with arguments
-    fn call_function(
+    pub fn call_function(
         func: SVal,
         args: Vec<SVal>,
         env: &mut Environment,
         arena: &Arena,
     ) -> Result<SVal, String> {
         match func {
-            SVal::BuiltinProc { name: fname, .. } => Self::apply_builtin(&fname, args, env),
-            SVal::UserProc { params, body } => {
-                if params.len() != args.len() {
+            // Needs the arena to call back into the thunk
+            SVal::BuiltinProc { name: fname, .. } if fname == "with-output-to-string" => {
+                Self::with_output_to_string(args, env, arena)
+            }
+            SVal::BuiltinProc { name: fname, .. }
+                if matches!(
+                    fname.as_str(),
+                    "call/cc"
+                        | "call-with-current-continuation"
+                        | "call/ec"
+                        | "call-with-escape-continuation"
+                ) =>
+            {
+                Self::call_with_escape(args, env, arena)
+            }
+            SVal::BuiltinProc { name: fname, .. } => Self::apply_builtin(&fname, args, env, arena),
+            SVal::Foreign(proc) => (proc.func)(args, env, arena),
+            SVal::Continuation(escape) => {
+                let value = match <[SVal; 1]>::try_from(args) {
+                    Ok([value]) => value,
+                    Err(args) if args.is_empty() => SVal::Nil,
+                    Err(_) => return Err("Continuation expects at most 1 argument".to_string()),
+                };
+                let mut escape = escape.borrow_mut();
+                if !escape.active {
+                    return Err("Continuation invoked outside its dynamic extent".to_string());
+                }
+                // Unwind as an error until the capturing call/cc picks the value up
+                escape.value = Some(value);
+                Err("Escape continuation invoked".to_string())
+            }
+            SVal::UserProc {
+                params,
+                rest,
+                body,
+                scope,
+            } => {
+                if rest.is_none() && params.len() != args.len() {
                     return Err(format!(
                         "Function expects {} arguments, got {}",
                         params.len(),
                         args.len()
                     ));
                 }
+                if args.len() < params.len() {
+                    return Err(format!(
+                        "Function expects at least {} arguments, got {}",
+                        params.len(),
+                        args.len()
+                    ));
+                }
 
-                // Create new environment for function call
-                let mut call_env = env.child();
-                for (param, arg) in params.iter().zip(args.iter()) {
-                    call_env.define(param.clone(), arg.clone());
+                // Parameters live in a new scope inside the one the
+                // procedure was created in
+                let mut call_env = env.enclosed_by(&scope);
+                let mut args = args.into_iter();
+                for (param, arg) in params.into_iter().zip(args.by_ref()) {
+                    call_env.define(param, arg);
+                }
+                if let Some(rest) = rest {
+                    call_env.define(rest, Self::list_from(args.collect()));
                 }
 
-                Self::eval(&body, &mut call_env, arena)
+                Self::eval_sequence(&body.exprs, &mut call_env, &body.arena)
             }
             _ => Err(format!("Cannot call non-function value: {}", func)),
         }
     }
 
+    /// Call a procedure with an escape continuation for the current call site.
+    /// Continuations are one-shot and only valid until `call/cc` returns, so
+    /// they can exit early from a computation but cannot re-enter it.
+    fn call_with_escape(
+        args: Vec<SVal>,
+        env: &mut Environment,
+        arena: &Arena,
+    ) -> Result<SVal, String> {
+        let receiver = match <[SVal; 1]>::try_from(args) {
+            Ok([receiver]) => receiver,
+            Err(_) => return Err("call/cc expects exactly 1 argument".to_string()),
+        };
+        let escape = Rc::new(RefCell::new(Escape {
+            active: true,
+            value: None,
+        }));
+        let continuation = SVal::Continuation(escape.clone());
+        let result = Self::call_function(receiver, vec![continuation], env, arena);
+
+        let mut escape = escape.borrow_mut();
+        escape.active = false;
+        match (result, escape.value.take()) {
+            (Err(_), Some(value)) => Ok(value),
+            (result, _) => result,
+        }
+    }
+
+    /// Call a thunk with output redirected into a fresh string port
+    fn with_output_to_string(
+        args: Vec<SVal>,
+        env: &mut Environment,
+        arena: &Arena,
+    ) -> Result<SVal, String> {
+        let thunk = match <[SVal; 1]>::try_from(args) {
+            Ok([thunk]) => thunk,
+            Err(_) => return Err("with-output-to-string expects exactly 1 argument".to_string()),
+        };
+        let port = Rc::new(RefCell::new(Port::Output(String::new())));
+        let previous = env.output.replace(port.clone());
+        let result = Self::call_function(thunk, vec![], env, arena);
+        env.output = previous;
+        result?;
+
+        let text = match &*port.borrow() {
+            Port::Output(buffer) => buffer.clone(),
+            _ => String::new(),
+        };
+        Ok(SVal::String(text))
+    }
+
+    /// Write text to an explicit port, the redirected output, or the sink
+    fn emit(text: &str, port: Option<&SVal>, env: &Environment) -> Result<SVal, String> {
+        let target = match port {
+            Some(SVal::Port(port)) => Some(port.clone()),
+            Some(other) => return Err(format!("Expected an output port, got {}", other)),
+            None => env.output.clone(),
+        };
+        match target {
+            Some(port) => match &mut *port.borrow_mut() {
+                Port::Output(buffer) => {
+                    buffer.push_str(text);
+                    Ok(SVal::Nil)
+                }
+                Port::File(file) => {
+                    file.write(text.as_bytes()).map_err(|e| e.to_string())?;
+                    Ok(SVal::Nil)
+                }
+                Port::Input { .. } => Err("Cannot write to an input port".to_string()),
+                Port::Closed => Err("Cannot write to a closed port".to_string()),
+            },
+            None => {
+                env.sink.write_str(text).map_err(|e| e.to_string())?;
+                Ok(SVal::Nil)
+            }
+        }
+    }
+
+    /// Read the next datum from an input port, or the eof object once it is exhausted
+    fn read_datum(port: &SVal) -> Result<SVal, String> {
+        let port = match port {
+            SVal::Port(port) => port,
+            other => return Err(format!("read expects an input port, got {}", other)),
+        };
+        match &mut *port.borrow_mut() {
+            Port::Input { text, pos } => match parser::parse_datum(&text[*pos..]) {
+                Ok(Some((arena, id, end))) => {
+                    *pos += end;
+                    let datum = arena.get(id).ok_or("Invalid datum reference")?;
+                    Ok(Self::sexpr_to_sval(datum, &arena))
+                }
+                Ok(None) => {
+                    *pos = text.len();
+                    Ok(SVal::Eof)
+                }
+                Err(e) => Err(e.to_string()),
+            },
+            _ => Err("read expects an input port".to_string()),
+        }
+    }
+
+    /// Read from a text input port with `take`, which is handed the unread
+    /// text and returns the value read and how many bytes it consumed
+    fn read_text(
+        name: &str,
+        port: &SVal,
+        take: impl FnOnce(&str) -> (SVal, usize),
+    ) -> Result<SVal, String> {
+        let port = match port {
+            SVal::Port(port) => port,
+            other => return Err(format!("{} expects an input port, got {}", name, other)),
+        };
+        match &mut *port.borrow_mut() {
+            Port::Input { text, pos } if *pos >= text.len() => Ok(SVal::Eof),
+            Port::Input { text, pos } => {
+                let (value, consumed) = take(&text[*pos..]);
+                *pos += consumed;
+                Ok(value)
+            }
+            _ => Err(format!("{} expects an input port", name)),
+        }
+    }
+
+    /// Borrow the elements of each list argument of a list builtin
+    fn list_args<'a>(name: &str, args: &'a [SVal]) -> Result<Vec<&'a [SVal]>, String> {
+        args.iter()
+            .map(|arg| match arg {
+                SVal::List(items) => Ok(items.as_slice()),
+                SVal::Nil => Ok(&[][..]),
+                other => Err(format!("{} expects a list, got {}", name, other)),
+            })
+            .collect()
+    }
+
+    /// Group the i-th elements of every list, stopping at the shortest one
+    fn zip_lists(lists: &[&[SVal]]) -> Vec<Vec<SVal>> {
+        let len = lists.iter().map(|l| l.len()).min().unwrap_or(0);
+        (0..len)
+            .map(|i| lists.iter().map(|l| l[i].clone()).collect())
+            .collect()
+    }
+
+    /// Build a list value, using the empty list when there are no items
+    fn list_from(items: Vec<SVal>) -> SVal {
+        if items.is_empty() {
+            SVal::Nil
+        } else {
+            SVal::List(items)
+        }
+    }
+
+    /// Extract the single numeric argument of a float-valued builtin
+    fn float_arg(name: &str, args: &[SVal]) -> Result<f64, String> {
+        if args.len() != 1 {
+            return Err(format!("{} expects exactly 1 argument", name));
+        }
+        scheme_number::to_f64(&args[0]).ok_or_else(|| format!("{} expects a number", name))
+    }
+
+    /// Builtins that only read their string arguments, so a mutable string
+    /// can be handed to them as its contents
+    fn reads_strings(name: &str) -> bool {
+        match name {
+            "string-set!" | "string-fill!" => false,
+            "substring"
+            | "open-input-string"
+            | "open-input-file"
+            | "open-output-file"
+            | "write-string"
+            | "load"
+            | "getenv"
+            | "get-environment-variable"
+            | "lua-eval" => true,
+            _ => name.starts_with("string"),
+        }
+    }
+
+    /// The error for changing a string that is not mutable
+    fn immutable(name: &str) -> String {
+        format!(
+            "{} expects a mutable string (from make-string or string-copy)",
+            name
+        )
+    }
+
+    /// Optional start and end character indices into `s`, defaulting to
+    /// the whole string
+    fn char_range(name: &str, s: &str, range: &[SVal]) -> Result<(usize, usize), String> {
+        let length = s.chars().count();
+        let index = |i: usize, default: usize| match range.get(i) {
+            Some(value) => scheme_number::to_index(value)
+                .ok_or_else(|| format!("{} expects non-negative indices", name)),
+            None => Ok(default),
+        };
+        let (start, end) = (index(0, 0)?, index(1, length)?);
+        if start > end || end > length {
+            return Err(format!("{} indices out of range", name));
+        }
+        Ok((start, end))
+    }
+
     /// Apply a built-in function
-    fn apply_builtin(name: &str, args: Vec<SVal>, _env: &mut Environment) -> Result<SVal, String> {
+    fn apply_builtin(
+        name: &str,
+        args: Vec<SVal>,
+        env: &mut Environment,
+        arena: &Arena,
+    ) -> Result<SVal, String> {
+        let args = if Self::reads_strings(name) {
+            args.into_iter().map(SVal::contents).collect()
+        } else {
+            args
+        };
         match name {
             // Arithmetic
-            "+" => {
-                let mut sum = 0.0;
-                for arg in args {
-                    match arg {
-                        SVal::Number(n) => sum += n,
-                        _ => return Err("+ expects numbers".to_string()),
+            "+" => args.iter().try_fold(SVal::Integer(0), |acc, arg| {
+                scheme_number::arith(Op::Add, &acc, arg)
+            }),
+            "*" => args.iter().try_fold(SVal::Integer(1), |acc, arg| {
+                scheme_number::arith(Op::Mul, &acc, arg)
+            }),
+            "-" | "/" => {
+                let op = if name == "-" { Op::Sub } else { Op::Div };
+                match args.len() {
+                    0 => Err(format!("{} expects at least one argument", name)),
+                    // (- x) negates and (/ x) takes the reciprocal
+                    1 => {
+                        let identity = SVal::Integer(if name == "-" { 0 } else { 1 });
+                        scheme_number::arith(op, &identity, &args[0])
                     }
+                    _ => args[1..].iter().try_fold(args[0].clone(), |acc, arg| {
+                        scheme_number::arith(op, &acc, arg)
+                    }),
                 }
-                Ok(SVal::Number(sum))
             }
-            "-" => {
-                if args.is_empty() {
-                    return Err("- expects at least one argument".to_string());
-                }
-                match args[0] {
-                    SVal::Number(first) => {
-                        let mut result = first;
-                        for arg in &args[1..] {
-                            match arg {
-                                SVal::Number(n) => result -= n,
-                                _ => return Err("- expects numbers".to_string()),
-                            }
-                        }
-                        Ok(SVal::Number(result))
-                    }
-                    _ => Err("- expects numbers".to_string()),
-                }
-            }
-            "*" => {
-                let mut product = 1.0;
-                for arg in args {
-                    match arg {
-                        SVal::Number(n) => product *= n,
-                        _ => return Err("* expects numbers".to_string()),
-                    }
+            "quotient" | "remainder" | "modulo" => {
+                if args.len() != 2 {
+                    return Err(format!("{} expects exactly 2 arguments", name));
                 }
-                Ok(SVal::Number(product))
+                let op = match name {
+                    "quotient" => IntDiv::Quotient,
+                    "remainder" => IntDiv::Remainder,
+                    _ => IntDiv::Modulo,
+                };
+                scheme_number::int_div(op, &args[0], &args[1], name)
             }
-            "/" => {
-                if args.is_empty() {
-                    return Err("/ expects at least one argument".to_string());
-                }
-                match args[0] {
-                    SVal::Number(first) => {
-                        let mut result = first;
-                        for arg in &args[1..] {
-                            match arg {
-                                SVal::Number(n) => {
-                                    if *n == 0.0 {
-                                        return Err("Division by zero".to_string());
-                                    }
-                                    result /= n;
-                                }
-                                _ => return Err("/ expects numbers".to_string()),
-                            }
-                        }
-                        Ok(SVal::Number(result))
-                    }
-                    _ => Err("/ expects numbers".to_string()),
+            "expt" => {
+                if args.len() != 2 {
+                    return Err("expt expects exactly 2 arguments".to_string());
                 }
+                scheme_number::expt(&args[0], &args[1])
             }
 
             // Comparison
@@ -456,54 +1443,77 @@ impl Interpreter {
                 if args.len() != 2 {
                     return Err("= expects exactly 2 arguments".to_string());
                 }
-                match (&args[0], &args[1]) {
-                    (SVal::Number(a), SVal::Number(b)) => Ok(SVal::Bool(a == b)),
-                    (a, b) => Ok(SVal::Bool(a == b)),
+                if scheme_number::is_number(&args[0]) && scheme_number::is_number(&args[1]) {
+                    let ord = scheme_number::compare(&args[0], &args[1], name)?;
+                    Ok(SVal::Bool(ord == Some(Ordering::Equal)))
+                } else {
+                    Ok(SVal::Bool(args[0] == args[1]))
                 }
             }
-            "<" => {
+            "<" | ">" | "<=" | ">=" => {
                 if args.len() != 2 {
-                    return Err("< expects exactly 2 arguments".to_string());
-                }
-                match (&args[0], &args[1]) {
-                    (SVal::Number(a), SVal::Number(b)) => Ok(SVal::Bool(a < b)),
-                    _ => Err("< expects numbers".to_string()),
-                }
+                    return Err(format!("{} expects exactly 2 arguments", name));
+                }
+                let ord = scheme_number::compare(&args[0], &args[1], name)?;
+                let result = match (name, ord) {
+                    (_, None) => false,
+                    ("<", Some(o)) => o == Ordering::Less,
+                    (">", Some(o)) => o == Ordering::Greater,
+                    ("<=", Some(o)) => o != Ordering::Greater,
+                    (_, Some(o)) => o != Ordering::Less,
+                };
+                Ok(SVal::Bool(result))
             }
-            ">" => {
-                if args.len() != 2 {
-                    return Err("> expects exactly 2 arguments".to_string());
+
+            // Exactness
+            "exact?" | "inexact?" => {
+                if args.len() != 1 {
+                    return Err(format!("{} expects exactly 1 argument", name));
                 }
-                match (&args[0], &args[1]) {
-                    (SVal::Number(a), SVal::Number(b)) => Ok(SVal::Bool(a > b)),
-                    _ => Err("> expects numbers".to_string()),
+                if !scheme_number::is_number(&args[0]) {
+                    return Err(format!("{} expects a number", name));
                 }
+                let exact = scheme_number::is_exact(&args[0]);
+                Ok(SVal::Bool(if name == "exact?" { exact } else { !exact }))
             }
-            "<=" => {
-                if args.len() != 2 {
-                    return Err("<= expects exactly 2 arguments".to_string());
-                }
-                match (&args[0], &args[1]) {
-                    (SVal::Number(a), SVal::Number(b)) => Ok(SVal::Bool(a <= b)),
-                    _ => Err("<= expects numbers".to_string()),
+            "exact->inexact" | "inexact" => {
+                if args.len() != 1 {
+                    return Err(format!("{} expects exactly 1 argument", name));
                 }
+                scheme_number::to_inexact(&args[0])
             }
-            ">=" => {
-                if args.len() != 2 {
-                    return Err(">= expects exactly 2 arguments".to_string());
+            "inexact->exact" | "exact" => {
+                if args.len() != 1 {
+                    return Err(format!("{} expects exactly 1 argument", name));
                 }
-                match (&args[0], &args[1]) {
-                    (SVal::Number(a), SVal::Number(b)) => Ok(SVal::Bool(a >= b)),
-                    _ => Err(">= expects numbers".to_string()),
+                scheme_number::to_exact(&args[0])
+            }
+            "numerator" | "denominator" => {
+                if args.len() != 1 {
+                    return Err(format!("{} expects exactly 1 argument", name));
                 }
+                scheme_number::rational_part(&args[0], name == "numerator")
             }
 
             // Type predicates
-            "number?" => {
+            "number?" | "real?" => {
                 if args.len() != 1 {
-                    return Err("number? expects exactly 1 argument".to_string());
+                    return Err(format!("{} expects exactly 1 argument", name));
                 }
-                Ok(SVal::Bool(matches!(args[0], SVal::Number(_))))
+                Ok(SVal::Bool(scheme_number::is_number(&args[0])))
+            }
+            "rational?" => {
+                if args.len() != 1 {
+                    return Err("rational? expects exactly 1 argument".to_string());
+                }
+                let finite = scheme_number::to_f64(&args[0]).is_some_and(f64::is_finite);
+                Ok(SVal::Bool(scheme_number::is_exact(&args[0]) || finite))
+            }
+            "integer?" => {
+                if args.len() != 1 {
+                    return Err("integer? expects exactly 1 argument".to_string());
+                }
+                Ok(SVal::Bool(scheme_number::is_integer(&args[0])))
             }
             "symbol?" => {
                 if args.len() != 1 {
@@ -517,6 +1527,7 @@ impl Interpreter {
                 }
                 match &args[0] {
                     SVal::List(items) => Ok(SVal::Bool(!items.is_empty())),
+                    SVal::DottedList(..) => Ok(SVal::Bool(true)),
                     _ => Ok(SVal::Bool(false)),
                 }
             }
@@ -545,14 +1556,28 @@ impl Interpreter {
                 }
             }
 
+            "eq?" | "eqv?" | "equal?" => {
+                if args.len() != 2 {
+                    return Err(format!("{} expects exactly 2 arguments", name));
+                }
+                let same = match name {
+                    "eq?" => scheme_stdlib::is_eq(&args[0], &args[1]),
+                    "eqv?" => scheme_stdlib::is_eqv(&args[0], &args[1]),
+                    _ => scheme_stdlib::is_equal(&args[0], &args[1]),
+                };
+                Ok(SVal::Bool(same))
+            }
+
             // List operations
             "car" => {
                 if args.len() != 1 {
                     return Err("car expects exactly 1 argument".to_string());
                 }
                 match &args[0] {
-                    SVal::List(items) if !items.is_empty() => Ok(items[0].clone()),
-                    _ => Err("car expects a non-empty list".to_string()),
+                    SVal::List(items) | SVal::DottedList(items, _) if !items.is_empty() => {
+                        Ok(items[0].clone())
+                    }
+                    _ => Err("car expects a pair".to_string()),
                 }
             }
             "cdr" => {
@@ -567,22 +1592,19 @@ impl Interpreter {
                             Ok(SVal::List(items[1..].to_vec()))
                         }
                     }
-                    _ => Err("cdr expects a non-empty list".to_string()),
+                    SVal::DottedList(items, tail) => {
+                        Ok(SVal::dotted(items[1..].to_vec(), (**tail).clone()))
+                    }
+                    _ => Err("cdr expects a pair".to_string()),
                 }
             }
             "cons" => {
                 if args.len() != 2 {
                     return Err("cons expects exactly 2 arguments".to_string());
                 }
-                match &args[1] {
-                    SVal::List(items) => {
-                        let mut new_list = vec![args[0].clone()];
-                        new_list.extend(items.clone());
-                        Ok(SVal::List(new_list))
-                    }
-                    SVal::Nil => Ok(SVal::List(vec![args[0].clone()])),
-                    _ => Err("cons expects a list as second argument".to_string()),
-                }
+                let mut args = args.into_iter();
+                let (car, cdr) = (args.next().unwrap(), args.next().unwrap());
+                Ok(SVal::dotted(vec![car], cdr))
             }
             "list" => Ok(SVal::List(args)),
             "length" => {
@@ -590,8 +1612,9 @@ impl Interpreter {
                     return Err("length expects exactly 1 argument".to_string());
                 }
                 match &args[0] {
-                    SVal::List(items) => Ok(SVal::Number(items.len() as f64)),
-                    SVal::Nil => Ok(SVal::Number(0.0)),
+                    SVal::List(items) => Ok(SVal::Integer(items.len() as i64)),
+                    SVal::Nil => Ok(SVal::Integer(0)),
+                    SVal::DottedList(..) => Err("length expects a proper list".to_string()),
                     _ => Err("length expects a list".to_string()),
                 }
             }
@@ -599,6 +1622,7 @@ impl Interpreter {
                 // append: concatenate multiple lists
                 // (append '(1 2) '(3 4)) -> (1 2 3 4)
                 // (append '() '(1)) -> (1)
+                // (append '(1) 2) -> (1 . 2)
                 if args.is_empty() {
                     return Ok(SVal::Nil);
                 }
@@ -613,13 +1637,10 @@ impl Interpreter {
                             // nil contributes nothing to append
                         }
                         _ => {
-                            // Last argument can be non-list for improper lists
+                            // The last argument becomes the tail, so a
+                            // non-list gives an improper list
                             if i == args.len() - 1 {
-                                if !result.is_empty() {
-                                    // This would create improper list, not standard
-                                    return Err("append: improper list not supported".to_string());
-                                }
-                                return Ok(arg.clone());
+                                return Ok(SVal::dotted(result, arg.clone()));
                             }
                             return Err("append expects lists as arguments".to_string());
                         }
@@ -633,167 +1654,265 @@ impl Interpreter {
                 }
             }
 
-            // I/O
-            "display" => {
-                for arg in args {
-                    print!("{}", arg);
+            "memq" | "memv" | "member" | "assq" | "assv" | "assoc" => {
+                if args.len() != 2 {
+                    return Err(format!("{} expects exactly 2 arguments", name));
+                }
+                if name.starts_with("mem") {
+                    scheme_stdlib::member(name, &args[0], &args[1])
+                } else {
+                    scheme_stdlib::assoc(name, &args[0], &args[1])
                 }
-                Ok(SVal::Nil)
-            }
-            "newline" => {
-                println!();
-                Ok(SVal::Nil)
             }
 
-            // Mathematical functions
-            "abs" => {
-                if args.len() != 1 {
-                    return Err("abs expects exactly 1 argument".to_string());
-                }
-                match args[0] {
-                    SVal::Number(n) => Ok(SVal::Number(n.abs())),
-                    _ => Err("abs expects a number".to_string()),
+            // Higher-order procedures call back into the evaluator
+            "map" | "for-each" => {
+                if args.len() < 2 {
+                    return Err(format!("{} expects a procedure and at least 1 list", name));
+                }
+                let lists = Self::list_args(name, &args[1..])?;
+                let mut results = Vec::new();
+                for row in Self::zip_lists(&lists) {
+                    let value = Self::call_function(args[0].clone(), row, env, arena)?;
+                    if name == "map" {
+                        results.push(value);
+                    }
                 }
+                Ok(Self::list_from(results))
             }
-            "floor" => {
-                if args.len() != 1 {
-                    return Err("floor expects exactly 1 argument".to_string());
-                }
-                match args[0] {
-                    SVal::Number(n) => Ok(SVal::Number(n.floor())),
-                    _ => Err("floor expects a number".to_string()),
+            "filter" => {
+                if args.len() != 2 {
+                    return Err("filter expects exactly 2 arguments".to_string());
+                }
+                let items = Self::list_args(name, &args[1..])?[0];
+                let mut kept = Vec::new();
+                for item in items {
+                    let test =
+                        Self::call_function(args[0].clone(), vec![item.clone()], env, arena)?;
+                    if Self::is_truthy(&test) {
+                        kept.push(item.clone());
+                    }
                 }
+                Ok(Self::list_from(kept))
             }
-            "ceiling" => {
-                if args.len() != 1 {
-                    return Err("ceiling expects exactly 1 argument".to_string());
-                }
-                match args[0] {
-                    SVal::Number(n) => Ok(SVal::Number(n.ceil())),
-                    _ => Err("ceiling expects a number".to_string()),
+            "fold-left" | "fold-right" => {
+                if args.len() < 3 {
+                    return Err(format!(
+                        "{} expects a procedure, an initial value and at least 1 list",
+                        name
+                    ));
                 }
-            }
-            "round" => {
-                if args.len() != 1 {
-                    return Err("round expects exactly 1 argument".to_string());
+                let lists = Self::list_args(name, &args[2..])?;
+                let mut rows = Self::zip_lists(&lists);
+                if name == "fold-right" {
+                    rows.reverse();
                 }
-                match args[0] {
-                    SVal::Number(n) => Ok(SVal::Number(n.round())),
-                    _ => Err("round expects a number".to_string()),
+                let mut acc = args[1].clone();
+                for mut row in rows {
+                    // fold-left passes the accumulator first, fold-right last
+                    if name == "fold-left" {
+                        row.insert(0, acc);
+                    } else {
+                        row.push(acc);
+                    }
+                    acc = Self::call_function(args[0].clone(), row, env, arena)?;
                 }
+                Ok(acc)
             }
-            "truncate" => {
-                if args.len() != 1 {
-                    return Err("truncate expects exactly 1 argument".to_string());
+            "apply" => {
+                // (apply f a b '(c d)) calls (f a b c d)
+                let mut args = args.into_iter();
+                let func = args
+                    .next()
+                    .ok_or("apply expects a procedure and a list of arguments")?;
+                let mut call_args: Vec<SVal> = args.collect();
+                match call_args.pop() {
+                    Some(SVal::List(rest)) => call_args.extend(rest),
+                    Some(SVal::Nil) => {}
+                    Some(other) => {
+                        return Err(format!(
+                            "apply expects a list as last argument, got {}",
+                            other
+                        ))
+                    }
+                    None => return Err("apply expects a list of arguments".to_string()),
                 }
-                match args[0] {
-                    SVal::Number(n) => Ok(SVal::Number(n.trunc())),
-                    _ => Err("truncate expects a number".to_string()),
+                Self::call_function(func, call_args, env, arena)
+            }
+
+            // I/O
+            "display" | "write" => {
+                if args.is_empty() || args.len() > 2 {
+                    return Err(format!("{} expects 1 or 2 arguments", name));
                 }
+                let text = if name == "display" {
+                    args[0].display_string()
+                } else {
+                    args[0].to_string()
+                };
+                Self::emit(&text, args.get(1), env)
             }
-            "sqrt" => {
-                if args.len() != 1 {
-                    return Err("sqrt expects exactly 1 argument".to_string());
+            "newline" => {
+                if args.len() > 1 {
+                    return Err("newline expects at most 1 argument".to_string());
                 }
-                match args[0] {
-                    SVal::Number(n) => {
-                        if n < 0.0 {
-                            return Err("sqrt expects a non-negative number".to_string());
-                        }
-                        Ok(SVal::Number(n.sqrt()))
+                Self::emit("\n", args.first(), env)
+            }
+            "open-input-string" => match args.as_slice() {
+                [SVal::String(text)] => Ok(SVal::Port(Rc::new(RefCell::new(Port::Input {
+                    text: text.clone(),
+                    pos: 0,
+                })))),
+                _ => Err("open-input-string expects a string".to_string()),
+            },
+            "open-output-string" => {
+                if !args.is_empty() {
+                    return Err("open-output-string expects no arguments".to_string());
+                }
+                Ok(SVal::Port(Rc::new(RefCell::new(Port::Output(
+                    String::new(),
+                )))))
+            }
+            "get-output-string" => match args.as_slice() {
+                [SVal::Port(port)] => match &*port.borrow() {
+                    Port::Output(buffer) => Ok(SVal::String(buffer.clone())),
+                    _ => Err("get-output-string expects an output port".to_string()),
+                },
+                _ => Err("get-output-string expects an output port".to_string()),
+            },
+            "read" => match args.as_slice() {
+                [port] => Self::read_datum(port),
+                _ => Err("read expects exactly 1 argument".to_string()),
+            },
+            "open-input-file" => match args.as_slice() {
+                [SVal::String(path)] => {
+                    let text = std::fs::read_to_string(path)
+                        .map_err(|e| format!("open-input-file: cannot open {}: {}", path, e))?;
+                    Ok(SVal::Port(Rc::new(RefCell::new(Port::Input {
+                        text,
+                        pos: 0,
+                    }))))
+                }
+                _ => Err("open-input-file expects a file name".to_string()),
+            },
+            "open-output-file" => match args.as_slice() {
+                [SVal::String(path)] => {
+                    let file = std::fs::File::create(path)
+                        .map_err(|e| format!("open-output-file: cannot open {}: {}", path, e))?;
+                    let mode = OpenMode::parse("w").expect("valid mode");
+                    Ok(SVal::Port(Rc::new(RefCell::new(Port::File(
+                        FileHandle::new(file, mode),
+                    )))))
+                }
+                _ => Err("open-output-file expects a file name".to_string()),
+            },
+            "read-line" => match args.as_slice() {
+                [port] => Self::read_text(name, port, |rest| {
+                    let (line, consumed) = match rest.find('\n') {
+                        Some(end) => (&rest[..end], end + 1),
+                        None => (rest, rest.len()),
+                    };
+                    let line = line.strip_suffix('\r').unwrap_or(line);
+                    (SVal::String(line.to_string()), consumed)
+                }),
+                _ => Err("read-line expects exactly 1 argument".to_string()),
+            },
+            "read-char" => match args.as_slice() {
+                [port] => Self::read_text(name, port, |rest| {
+                    let c = rest.chars().next().expect("port has unread text");
+                    (SVal::Char(c), c.len_utf8())
+                }),
+                _ => Err("read-char expects exactly 1 argument".to_string()),
+            },
+            "write-string" => match args.as_slice() {
+                [SVal::String(text)] => Self::emit(text, None, env),
+                [SVal::String(text), port] => Self::emit(text, Some(port), env),
+                _ => Err("write-string expects a string and an optional port".to_string()),
+            },
+            "close-port" => match args.as_slice() {
+                [SVal::Port(port)] => {
+                    let previous = std::mem::replace(&mut *port.borrow_mut(), Port::Closed);
+                    if let Port::File(mut file) = previous {
+                        file.close().map_err(|e| format!("close-port: {}", e))?;
                     }
-                    _ => Err("sqrt expects a number".to_string()),
+                    Ok(SVal::Nil)
                 }
-            }
-            "sin" => {
+                _ => Err("close-port expects a port".to_string()),
+            },
+            "load" => match args.as_slice() {
+                [SVal::String(path)] => scheme_library::load(path, env),
+                _ => Err("load expects a file name".to_string()),
+            },
+            "eof-object?" => {
                 if args.len() != 1 {
-                    return Err("sin expects exactly 1 argument".to_string());
-                }
-                match args[0] {
-                    SVal::Number(n) => Ok(SVal::Number(n.sin())),
-                    _ => Err("sin expects a number".to_string()),
+                    return Err("eof-object? expects exactly 1 argument".to_string());
                 }
+                Ok(SVal::Bool(matches!(args[0], SVal::Eof)))
             }
-            "cos" => {
+
+            // Mathematical functions
+            "abs" => {
                 if args.len() != 1 {
-                    return Err("cos expects exactly 1 argument".to_string());
-                }
-                match args[0] {
-                    SVal::Number(n) => Ok(SVal::Number(n.cos())),
-                    _ => Err("cos expects a number".to_string()),
+                    return Err("abs expects exactly 1 argument".to_string());
                 }
+                scheme_number::abs(&args[0])
             }
-            "tan" => {
+            "floor" | "ceiling" | "round" | "truncate" => {
                 if args.len() != 1 {
-                    return Err("tan expects exactly 1 argument".to_string());
-                }
-                match args[0] {
-                    SVal::Number(n) => Ok(SVal::Number(n.tan())),
-                    _ => Err("tan expects a number".to_string()),
+                    return Err(format!("{} expects exactly 1 argument", name));
                 }
+                let mode = match name {
+                    "floor" => Rounding::Floor,
+                    "ceiling" => Rounding::Ceiling,
+                    "round" => Rounding::Round,
+                    _ => Rounding::Truncate,
+                };
+                scheme_number::round_with(&args[0], mode, name)
             }
-            "log" => {
-                if args.len() != 1 {
-                    return Err("log expects exactly 1 argument".to_string());
-                }
-                match args[0] {
-                    SVal::Number(n) => {
-                        if n <= 0.0 {
-                            return Err("log expects a positive number".to_string());
-                        }
-                        Ok(SVal::Number(n.ln()))
-                    }
-                    _ => Err("log expects a number".to_string()),
+            "sqrt" => {
+                let n = Self::float_arg(name, &args)?;
+                if n < 0.0 {
+                    return Err("sqrt expects a non-negative number".to_string());
                 }
+                Ok(SVal::Number(n.sqrt()))
             }
-            "exp" => {
-                if args.len() != 1 {
-                    return Err("exp expects exactly 1 argument".to_string());
-                }
-                match args[0] {
-                    SVal::Number(n) => Ok(SVal::Number(n.exp())),
-                    _ => Err("exp expects a number".to_string()),
+            "sin" => Ok(SVal::Number(Self::float_arg(name, &args)?.sin())),
+            "cos" => Ok(SVal::Number(Self::float_arg(name, &args)?.cos())),
+            "tan" => Ok(SVal::Number(Self::float_arg(name, &args)?.tan())),
+            "log" => {
+                let n = Self::float_arg(name, &args)?;
+                if n <= 0.0 {
+                    return Err("log expects a positive number".to_string());
                 }
+                Ok(SVal::Number(n.ln()))
             }
-            "min" => {
+            "exp" => Ok(SVal::Number(Self::float_arg(name, &args)?.exp())),
+            "min" | "max" => {
                 if args.is_empty() {
-                    return Err("min expects at least 1 argument".to_string());
+                    return Err(format!("{} expects at least 1 argument", name));
                 }
-                let mut result = match args[0] {
-                    SVal::Number(n) => n,
-                    _ => return Err("min expects numbers".to_string()),
+                let wanted = if name == "min" {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
                 };
-                for arg in &args[1..] {
-                    match arg {
-                        SVal::Number(n) => {
-                            if *n < result {
-                                result = *n;
-                            }
-                        }
-                        _ => return Err("min expects numbers".to_string()),
+                let mut result = args[0].clone();
+                let mut inexact = false;
+                for arg in &args {
+                    if !scheme_number::is_number(arg) {
+                        return Err(format!("{} expects numbers", name));
                     }
-                }
-                Ok(SVal::Number(result))
-            }
-            "max" => {
-                if args.is_empty() {
-                    return Err("max expects at least 1 argument".to_string());
-                }
-                let mut result = match args[0] {
-                    SVal::Number(n) => n,
-                    _ => return Err("max expects numbers".to_string()),
-                };
-                for arg in &args[1..] {
-                    match arg {
-                        SVal::Number(n) => {
-                            if *n > result {
-                                result = *n;
-                            }
-                        }
-                        _ => return Err("max expects numbers".to_string()),
+                    inexact |= !scheme_number::is_exact(arg);
+                    if scheme_number::compare(arg, &result, name)? == Some(wanted) {
+                        result = arg.clone();
                     }
                 }
-                Ok(SVal::Number(result))
+                // Any inexact argument makes the result inexact
+                if inexact {
+                    scheme_number::to_inexact(&result)
+                } else {
+                    Ok(result)
+                }
             }
 
             // String functions
@@ -808,26 +1927,27 @@ impl Interpreter {
                     return Err("string-length expects exactly 1 argument".to_string());
                 }
                 match &args[0] {
-                    SVal::String(s) => Ok(SVal::Number(s.len() as f64)),
+                    SVal::String(s) => Ok(SVal::Integer(s.chars().count() as i64)),
                     _ => Err("string-length expects a string".to_string()),
                 }
             }
-            "substring" => {
-                if args.len() != 3 {
-                    return Err("substring expects exactly 3 arguments".to_string());
-                }
-                match (&args[0], &args[1], &args[2]) {
-                    (SVal::String(s), SVal::Number(start), SVal::Number(end)) => {
-                        let start = *start as usize;
-                        let end = *end as usize;
-                        if start > end || end > s.len() {
-                            return Err("substring indices out of range".to_string());
-                        }
-                        Ok(SVal::String(s[start..end].to_string()))
-                    }
-                    _ => Err("substring expects (string, number, number)".to_string()),
-                }
-            }
+            "substring" => match args.as_slice() {
+                [SVal::String(s), range @ ..] if range.len() == 2 => {
+                    let (start, end) = Self::char_range(name, s, range)?;
+                    Ok(SVal::String(
+                        s.chars().skip(start).take(end - start).collect(),
+                    ))
+                }
+                [_, _, _] => Err("substring expects (string, number, number)".to_string()),
+                _ => Err("substring expects exactly 3 arguments".to_string()),
+            },
+            "string-ref" => match args.as_slice() {
+                [SVal::String(s), index] => scheme_number::to_index(index)
+                    .and_then(|i| s.chars().nth(i))
+                    .map(SVal::Char)
+                    .ok_or_else(|| "string-ref index out of range".to_string()),
+                _ => Err("string-ref expects a string and an index".to_string()),
+            },
             "string-upcase" => {
                 if args.len() != 1 {
                     return Err("string-upcase expects exactly 1 argument".to_string());
@@ -857,35 +1977,222 @@ impl Interpreter {
                 Ok(SVal::String(result))
             }
             "string->number" => {
-                if args.len() != 1 {
-                    return Err("string->number expects exactly 1 argument".to_string());
-                }
+                let radix = match args.as_slice() {
+                    [_] => 10,
+                    [_, radix] => scheme_number::to_radix(radix, "string->number")?,
+                    _ => return Err("string->number expects 1 or 2 arguments".to_string()),
+                };
                 match &args[0] {
                     SVal::String(s) => {
-                        match s.trim().parse::<f64>() {
-                            Ok(n) => Ok(SVal::Number(n)),
-                            Err(_) => Ok(SVal::Bool(false)), // Return #f on parse failure (Scheme convention)
+                        match scheme_number::parse_prefixed(s.trim(), radix) {
+                            Some(literal) => Ok(Self::sexpr_to_sval(&literal, &Arena::new())),
+                            None => Ok(SVal::Bool(false)), // Return #f on parse failure (Scheme convention)
                         }
                     }
                     _ => Err("string->number expects a string".to_string()),
                 }
             }
             "number->string" => {
-                if args.len() != 1 {
-                    return Err("number->string expects exactly 1 argument".to_string());
-                }
-                match args[0] {
-                    SVal::Number(n) => {
-                        if n.fract() == 0.0 {
-                            Ok(SVal::String(format!("{}", n as i64)))
-                        } else {
-                            Ok(SVal::String(format!("{}", n)))
-                        }
+                let radix = match args.as_slice() {
+                    [_] => 10,
+                    [_, radix] => scheme_number::to_radix(radix, "number->string")?,
+                    _ => return Err("number->string expects 1 or 2 arguments".to_string()),
+                };
+                scheme_number::format_radix(&args[0], radix).map(SVal::String)
+            }
+
+            "make-string" => {
+                let (length, fill) = match args.as_slice() {
+                    [length] => (length, ' '),
+                    [length, SVal::Char(c)] => (length, *c),
+                    _ => {
+                        return Err(
+                            "make-string expects a length and an optional character".to_string()
+                        )
+                    }
+                };
+                let length = scheme_number::to_index(length)
+                    .ok_or("make-string expects a non-negative length")?;
+                Ok(SVal::MutableString(Rc::new(RefCell::new(
+                    std::iter::repeat_n(fill, length).collect(),
+                ))))
+            }
+            "string-copy" => {
+                let (s, range) = match args.as_slice() {
+                    [SVal::String(s), range @ ..] if range.len() <= 2 => (s, range),
+                    _ => {
+                        return Err(
+                            "string-copy expects a string and optional start and end".to_string()
+                        )
+                    }
+                };
+                let (start, end) = Self::char_range(name, s, range)?;
+                let copy = s.chars().skip(start).take(end - start).collect();
+                Ok(SVal::MutableString(Rc::new(RefCell::new(copy))))
+            }
+            "string-set!" => match args.as_slice() {
+                [SVal::MutableString(s), index, SVal::Char(c)] => {
+                    let index = scheme_number::to_index(index);
+                    let mut s = s.borrow_mut();
+                    let mut chars: Vec<char> = s.chars().collect();
+                    match index.and_then(|i| chars.get_mut(i)) {
+                        Some(slot) => *slot = *c,
+                        None => return Err("string-set! index out of range".to_string()),
                     }
-                    _ => Err("number->string expects a number".to_string()),
+                    *s = chars.into_iter().collect();
+                    Ok(SVal::Nil)
+                }
+                [SVal::String(_), _, _] => Err(Self::immutable(name)),
+                _ => Err("string-set! expects a string, an index and a character".to_string()),
+            },
+            "string-fill!" => match args.as_slice() {
+                [SVal::MutableString(s), SVal::Char(c)] => {
+                    let mut s = s.borrow_mut();
+                    *s = std::iter::repeat_n(*c, s.chars().count()).collect();
+                    Ok(SVal::Nil)
                 }
+                [SVal::String(_), _] => Err(Self::immutable(name)),
+                _ => Err("string-fill! expects a string and a character".to_string()),
+            },
+            "string-join" => {
+                let (items, separator) = match args.as_slice() {
+                    [items] => (items, " "),
+                    [items, SVal::String(separator)] => (items, separator.as_str()),
+                    _ => {
+                        return Err(
+                            "string-join expects a list of strings and an optional separator"
+                                .to_string(),
+                        )
+                    }
+                };
+                let parts = match items {
+                    SVal::Nil => Vec::new(),
+                    SVal::List(items) => items
+                        .iter()
+                        .map(|item| match item.clone().contents() {
+                            SVal::String(s) => Ok(s),
+                            _ => Err("string-join expects a list of strings".to_string()),
+                        })
+                        .collect::<Result<Vec<String>, String>>()?,
+                    _ => return Err("string-join expects a list of strings".to_string()),
+                };
+                Ok(SVal::String(parts.join(separator)))
             }
 
+            // Characters
+            "char?" => match args.as_slice() {
+                [value] => Ok(SVal::Bool(matches!(value, SVal::Char(_)))),
+                _ => Err("char? expects exactly 1 argument".to_string()),
+            },
+            "char->integer" => match args.as_slice() {
+                [SVal::Char(c)] => Ok(SVal::Integer(*c as i64)),
+                _ => Err("char->integer expects a character".to_string()),
+            },
+            "integer->char" => match args.as_slice() {
+                [SVal::Integer(n)] => u32::try_from(*n)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .map(SVal::Char)
+                    .ok_or_else(|| format!("integer->char: {} is not a Unicode scalar value", n)),
+                _ => Err("integer->char expects an exact integer".to_string()),
+            },
+            "char-upcase" | "char-downcase" => match args.as_slice() {
+                [SVal::Char(c)] => {
+                    // Keep characters whose other case is more than one character
+                    let mapped: Vec<char> = if name == "char-upcase" {
+                        c.to_uppercase().collect()
+                    } else {
+                        c.to_lowercase().collect()
+                    };
+                    Ok(SVal::Char(match mapped.as_slice() {
+                        [single] => *single,
+                        _ => *c,
+                    }))
+                }
+                _ => Err(format!("{} expects a character", name)),
+            },
+            "char-alphabetic?" | "char-numeric?" | "char-whitespace?" | "char-upper-case?"
+            | "char-lower-case?" => match args.as_slice() {
+                [SVal::Char(c)] => Ok(SVal::Bool(match name {
+                    "char-alphabetic?" => c.is_alphabetic(),
+                    "char-numeric?" => c.is_numeric(),
+                    "char-whitespace?" => c.is_whitespace(),
+                    "char-upper-case?" => c.is_uppercase(),
+                    _ => c.is_lowercase(),
+                })),
+                _ => Err(format!("{} expects a character", name)),
+            },
+            "char=?" | "char<?" | "char>?" | "char<=?" | "char>=?" => {
+                let chars = args
+                    .iter()
+                    .map(|arg| match arg {
+                        SVal::Char(c) => Ok(*c),
+                        _ => Err(format!("{} expects characters", name)),
+                    })
+                    .collect::<Result<Vec<char>, String>>()?;
+                if chars.len() < 2 {
+                    return Err(format!("{} expects at least 2 arguments", name));
+                }
+                Ok(SVal::Bool(chars.windows(2).all(|pair| match name {
+                    "char=?" => pair[0] == pair[1],
+                    "char<?" => pair[0] < pair[1],
+                    "char>?" => pair[0] > pair[1],
+                    "char<=?" => pair[0] <= pair[1],
+                    _ => pair[0] >= pair[1],
+                })))
+            }
+            "string->list" => match args.as_slice() {
+                [SVal::String(s)] if s.is_empty() => Ok(SVal::Nil),
+                [SVal::String(s)] => Ok(SVal::List(s.chars().map(SVal::Char).collect())),
+                _ => Err("string->list expects a string".to_string()),
+            },
+            "list->string" => match args.as_slice() {
+                [SVal::Nil] => Ok(SVal::String(String::new())),
+                [SVal::List(items)] => items
+                    .iter()
+                    .map(|item| match item {
+                        SVal::Char(c) => Ok(*c),
+                        _ => Err("list->string expects a list of characters".to_string()),
+                    })
+                    .collect::<Result<String, String>>()
+                    .map(SVal::String),
+                _ => Err("list->string expects a list of characters".to_string()),
+            },
+
+            // Process environment: `#f` for an unset variable, like `os.getenv`
+            // returning nil
+            "getenv" | "get-environment-variable" => match args.as_slice() {
+                [SVal::String(var)] => Ok(match std::env::var_os(var) {
+                    Some(value) => SVal::String(value.to_string_lossy().into_owned()),
+                    None => SVal::Bool(false),
+                }),
+                _ => Err(format!("{} expects a variable name", name)),
+            },
+            "get-environment-variables" => {
+                let mut vars: Vec<(String, String)> = std::env::vars_os()
+                    .map(|(var, value)| {
+                        (
+                            var.to_string_lossy().into_owned(),
+                            value.to_string_lossy().into_owned(),
+                        )
+                    })
+                    .collect();
+                vars.sort();
+                Ok(SVal::List(
+                    vars.into_iter()
+                        .map(|(var, value)| {
+                            SVal::List(vec![SVal::String(var), SVal::String(value)])
+                        })
+                        .collect(),
+                ))
+            }
+
+            // Interop
+            "lua-eval" => match args.as_slice() {
+                [SVal::String(code)] => bridge::lua_eval(code),
+                _ => Err("lua-eval expects a string of Lua code".to_string()),
+            },
+
             _ => Err(format!("Unknown function: {}", name)),
         }
     }
@@ -895,6 +2202,9 @@ impl Interpreter {
         match expr {
             // Literals evaluate to themselves
             SExpr::Number(n) => Ok(SVal::Number(*n)),
+            SExpr::Integer(n) => Ok(SVal::Integer(*n)),
+            SExpr::BigInt(n) => Ok(SVal::BigInt(n.clone())),
+            SExpr::Rational(r) => Ok(SVal::Rational(r.clone())),
             SExpr::Bool(b) => Ok(SVal::Bool(*b)),
             SExpr::String(s) => Ok(SVal::String(s.clone())),
             SExpr::Char(c) => Ok(SVal::Char(*c)),
@@ -923,11 +2233,27 @@ impl Interpreter {
                     SExpr::Atom(name) => {
                         // Special forms
                         match name.as_str() {
-                            "quote" => Self::eval_quote(&ids, arena),
-                            "if" => Self::eval_if(&ids, env, arena),
-                            "define" => Self::eval_define(&ids, env, arena),
-                            "begin" => Self::eval_begin(&ids, env, arena),
-                            "lambda" => Self::eval_lambda(&ids, arena),
+                            "quote" => Self::eval_quote(ids, arena),
+                            "quasiquote" => Self::eval_quasiquote_form(ids, env, arena),
+                            "define-syntax" => {
+                                Err("define-syntax must be expanded before evaluation".to_string())
+                            }
+                            "unquote" | "unquote-splicing" => {
+                                Err(format!("{} not in quasiquote context", name))
+                            }
+                            "if" => Self::eval_if(ids, env, arena),
+                            "define" => Self::eval_define(ids, env, arena),
+                            "begin" => Self::eval_begin(ids, env, arena),
+                            "lambda" => Self::eval_lambda(None, ids, env, arena),
+                            "set!" => Self::eval_set(ids, env, arena),
+                            "define-library" => scheme_library::define_library(ids, env, arena),
+                            "import" => scheme_library::import(ids, env, arena),
+                            "cond" => Self::eval_cond(ids, env, arena),
+                            "case" => Self::eval_case(ids, env, arena),
+                            "when" => Self::eval_when(ids, env, arena, true),
+                            "unless" => Self::eval_when(ids, env, arena, false),
+                            "and" => Self::eval_and(ids, env, arena),
+                            "or" => Self::eval_or(ids, env, arena),
 
                             // Regular function call
                             _ => {
@@ -958,11 +2284,19 @@ impl Interpreter {
                 }
             }
 
+            // Quasi-quote: expand the template, evaluating unquoted parts
+            SExpr::QuasiQuote(id) => {
+                let template = arena.get(*id).ok_or("Invalid quasiquote reference")?;
+                Self::eval_quasiquote(template, 1, env, arena)
+            }
+
             // Not yet supported
             SExpr::Vector(_) => Err("Vectors not yet supported".to_string()),
-            SExpr::QuasiQuote(_) => Err("Quasi-quote not yet supported".to_string()),
-            SExpr::Unquote(_) => Err("Unquote not in quote context".to_string()),
-            SExpr::UnquoteSplicing(_) => Err("Unquote-splicing not in quote context".to_string()),
+            SExpr::DottedList(..) => Err("Cannot evaluate a dotted list".to_string()),
+            SExpr::Unquote(_) => Err("Unquote not in quasiquote context".to_string()),
+            SExpr::UnquoteSplicing(_) => {
+                Err("Unquote-splicing not in quasiquote context".to_string())
+            }
         }
     }
 }
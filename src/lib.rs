@@ -1,22 +1,81 @@
 pub mod ast;
+pub mod ast_dump;
+pub mod bridge;
+pub mod chunk_cache;
+pub mod cli;
+pub mod compiler;
+#[cfg(not(target_family = "wasm"))]
+pub mod config;
+pub mod convert;
 pub mod coroutines;
+pub mod coverage;
+#[cfg(feature = "dap")]
+pub mod dap;
+pub mod debugger;
 pub mod error_types;
 pub mod errors;
+pub mod eval;
 pub mod executor;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 pub mod file_io;
+pub mod format;
+#[cfg(any(feature = "lsp", feature = "dap"))]
+pub mod framing;
+pub mod gc;
+pub mod handle;
+pub mod hooks;
+pub mod host_async;
+pub mod host_io;
+pub mod intern;
 pub mod interpreter;
+pub mod lint;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+pub mod lua_arith;
 pub mod lua_interpreter;
 pub mod lua_parser;
-pub mod lua_parser_types;
+pub mod lua_string;
 pub mod lua_value;
+pub mod macro_expander;
 pub mod module_loader;
+#[cfg(feature = "net")]
+pub mod net;
 pub mod nom_parser;
+pub mod optimize;
 pub mod parser;
+pub mod perf;
+pub mod profile;
+#[cfg(not(target_family = "wasm"))]
+pub mod repl;
+pub mod resolver;
+pub mod scheme_library;
+pub mod scheme_number;
 pub mod scheme_stdlib;
 pub mod scope_manager;
+pub mod snapshot;
+pub mod source_map;
+pub mod stack;
 pub mod stdlib;
+pub mod testing;
+pub mod timers;
 pub mod tokenizer;
+pub mod traceback;
 pub mod upvalues;
+pub mod vm;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod watch;
+
+// AST types used to live in a top-level module; keep the old path working
+pub use lua_parser::types as lua_parser_types;
 
 // Re-export commonly used error types
 pub use error_types::{LuaError, LuaResult};
+
+// One-call evaluation for library users
+pub use eval::{eval_lua, eval_scheme, Session};
+
+// Lua files as configuration data
+#[cfg(not(target_family = "wasm"))]
+pub use config::load_config;
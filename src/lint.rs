@@ -0,0 +1,585 @@
+/// Static checks for Lua chunks
+///
+/// Walks a parsed (unresolved) AST and reports likely mistakes without
+/// running anything: reads of globals that are never assigned, locals that
+/// are never read, statements that follow a `return`, `break` or `goto`
+/// on every path, repeated keys in a table constructor, and locals that
+/// hide another variable of the same name.
+///
+/// Locals and loop variables whose name starts with `_` are exempt from
+/// the unused check, as are function parameters.
+///
+/// Each finding points at the statement it comes from: the declaration for
+/// unused and shadowing locals, the statement that reads an undefined
+/// global or builds the table, and the first unreachable statement. Chunks
+/// parsed without locations report line 0.
+use crate::lua_interpreter::LuaInterpreter;
+use crate::lua_parser::{
+    Block, BlockId, Chunk, Expression, FieldKey, FunctionId, Location, LuaArena, Statement,
+};
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+
+/// Category of a diagnostic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LintKind {
+    UndefinedGlobal,
+    UnusedLocal,
+    UnreachableCode,
+    DuplicateKey,
+    ShadowedVariable,
+}
+
+impl fmt::Display for LintKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LintKind::UndefinedGlobal => "undefined-global",
+            LintKind::UnusedLocal => "unused-local",
+            LintKind::UnreachableCode => "unreachable-code",
+            LintKind::DuplicateKey => "duplicate-key",
+            LintKind::ShadowedVariable => "shadowed-variable",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A single finding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub kind: LintKind,
+    /// The variable or key concerned; for unreachable code, the statement
+    /// that ends the flow (`return`, `break` or `goto`)
+    pub name: String,
+    /// Enclosing function, `None` at chunk level
+    pub function: Option<String>,
+    /// Start of the statement the finding is about
+    pub location: Location,
+}
+
+impl Diagnostic {
+    /// Human-readable description, without the kind tag
+    pub fn message(&self) -> String {
+        match self.kind {
+            LintKind::UndefinedGlobal => format!("undefined global '{}'", self.name),
+            LintKind::UnusedLocal => format!("unused local '{}'", self.name),
+            LintKind::UnreachableCode => format!("unreachable code after '{}'", self.name),
+            LintKind::DuplicateKey => format!("duplicate table key '{}'", self.name),
+            LintKind::ShadowedVariable => {
+                format!("local '{}' shadows an earlier variable", self.name)
+            }
+        }
+    }
+}
+
+/// `line:column: [kind] message`, with the column counted from 1 as
+/// editors do
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: [{}] {}",
+            self.location.line,
+            self.location.column + 1,
+            self.kind,
+            self.message()
+        )?;
+        if let Some(function) = &self.function {
+            write!(f, " in function '{}'", function)?;
+        }
+        Ok(())
+    }
+}
+
+/// Globals a fresh interpreter defines, plus the CLI's `arg` table
+pub fn builtin_globals() -> HashSet<String> {
+    let mut names: HashSet<String> = LuaInterpreter::new().global_names().into_iter().collect();
+    names.insert("arg".to_string());
+    names
+}
+
+/// Check a chunk, treating `known_globals` as always defined
+pub fn lint(chunk: &Chunk, known_globals: &HashSet<String>) -> Vec<Diagnostic> {
+    let mut linter = Linter {
+        arena: &chunk.arena,
+        scopes: vec![Vec::new()],
+        functions: Vec::new(),
+        location: Location::new(0, 0),
+        global_reads: Vec::new(),
+        global_writes: BTreeSet::new(),
+        diagnostics: Vec::new(),
+    };
+    linter.block(chunk);
+    linter.close_scope();
+
+    // A global is fine if anything in the chunk assigns it, even later
+    let mut reported = HashSet::new();
+    for (name, function, location) in std::mem::take(&mut linter.global_reads) {
+        if known_globals.contains(&name)
+            || linter.global_writes.contains(&name)
+            || !reported.insert(name.clone())
+        {
+            continue;
+        }
+        linter.diagnostics.push(Diagnostic {
+            kind: LintKind::UndefinedGlobal,
+            name,
+            function,
+            location,
+        });
+    }
+    linter.diagnostics
+}
+
+struct Binding {
+    name: String,
+    used: bool,
+    /// Parameters may go unused without a warning
+    param: bool,
+    function: Option<String>,
+    location: Location,
+}
+
+struct Linter<'a> {
+    /// Where the chunk's blocks and functions are
+    arena: &'a LuaArena,
+    /// Lexical scopes across all enclosing functions, innermost last
+    scopes: Vec<Vec<Binding>>,
+    /// Names of the functions being checked, innermost last
+    functions: Vec<String>,
+    /// Start of the statement being checked
+    location: Location,
+    global_reads: Vec<(String, Option<String>, Location)>,
+    global_writes: BTreeSet<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Linter<'_> {
+    fn function_name(&self) -> Option<String> {
+        self.functions.last().cloned()
+    }
+
+    fn report(&mut self, kind: LintKind, name: impl Into<String>) {
+        let function = self.function_name();
+        self.diagnostics.push(Diagnostic {
+            kind,
+            name: name.into(),
+            function,
+            location: self.location,
+        });
+    }
+
+    fn lookup(&mut self, name: &str) -> Option<&mut Binding> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .flat_map(|scope| scope.iter_mut().rev())
+            .find(|b| b.name == name)
+    }
+
+    fn declare(&mut self, name: &str, param: bool) {
+        if !name.starts_with('_') && name != "self" && self.lookup(name).is_some() {
+            self.report(LintKind::ShadowedVariable, name);
+        }
+        let function = self.function_name();
+        self.scopes
+            .last_mut()
+            .expect("linter always has a scope")
+            .push(Binding {
+                name: name.to_string(),
+                used: false,
+                param,
+                function,
+                location: self.location,
+            });
+    }
+
+    fn close_scope(&mut self) {
+        let scope = self.scopes.pop().expect("linter always has a scope");
+        for binding in scope {
+            if !binding.used && !binding.param && !binding.name.starts_with('_') {
+                self.diagnostics.push(Diagnostic {
+                    kind: LintKind::UnusedLocal,
+                    name: binding.name,
+                    function: binding.function,
+                    location: binding.location,
+                });
+            }
+        }
+    }
+
+    fn read(&mut self, name: &str) {
+        match self.lookup(name) {
+            Some(binding) => binding.used = true,
+            None => {
+                let function = self.function_name();
+                self.global_reads
+                    .push((name.to_string(), function, self.location));
+            }
+        }
+    }
+
+    fn write(&mut self, name: &str) {
+        if self.lookup(name).is_none() {
+            self.global_writes.insert(name.to_string());
+        }
+    }
+
+    fn scoped_block(&mut self, block: BlockId) {
+        self.scopes.push(Vec::new());
+        self.block(self.arena.block(block));
+        self.close_scope();
+    }
+
+    fn block(&mut self, block: &Block) {
+        let outer = self.location;
+        let mut exit = None;
+        for (i, statement) in block.statements.iter().enumerate() {
+            self.locate(block, i);
+            // A label can be jumped to, so code after it runs again
+            if let Some(after) = exit {
+                if !matches!(statement, Statement::Label(_)) {
+                    self.report(LintKind::UnreachableCode, after);
+                }
+            }
+            self.statement(statement);
+            exit = exits(self.arena, statement);
+        }
+        if let Some(ret) = &block.return_statement {
+            self.locate(block, block.statements.len());
+            if let Some(after) = exit {
+                self.report(LintKind::UnreachableCode, after);
+            }
+            for expr in &ret.expression_list {
+                self.expression(expr);
+            }
+        }
+        self.location = outer;
+    }
+
+    /// Move to the `i`th statement of `block`, or its return statement
+    fn locate(&mut self, block: &Block, i: usize) {
+        let line = block.lines.get(i).copied().unwrap_or(0);
+        let column = block.columns.get(i).copied().unwrap_or(0);
+        self.location = Location::new(line, column);
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Empty | Statement::Break | Statement::Label(_) | Statement::Goto(_) => {}
+            Statement::Assignment { variables, values } => {
+                for expr in values {
+                    self.expression(expr);
+                }
+                for target in variables {
+                    match target {
+                        Expression::Identifier(name) => self.write(name),
+                        other => self.expression(other),
+                    }
+                }
+            }
+            Statement::FunctionCall(expr) => self.expression(expr),
+            Statement::Do(body) => self.scoped_block(*body),
+            Statement::While { condition, body } => {
+                self.expression(condition);
+                self.scoped_block(*body);
+            }
+            Statement::Repeat { body, condition } => {
+                // The condition can see the body's locals
+                self.scopes.push(Vec::new());
+                self.block(self.arena.block(*body));
+                self.expression(condition);
+                self.close_scope();
+            }
+            Statement::If {
+                condition,
+                then_block,
+                elseif_parts,
+                else_block,
+            } => {
+                self.expression(condition);
+                self.scoped_block(*then_block);
+                for (cond, block) in elseif_parts {
+                    self.expression(cond);
+                    self.scoped_block(*block);
+                }
+                if let Some(block) = else_block {
+                    self.scoped_block(*block);
+                }
+            }
+            Statement::ForNumeric {
+                var,
+                start,
+                end,
+                step,
+                body,
+            } => {
+                self.expression(start);
+                self.expression(end);
+                if let Some(step) = step {
+                    self.expression(step);
+                }
+                self.scopes.push(Vec::new());
+                self.declare(var, false);
+                self.scoped_block(*body);
+                self.close_scope();
+            }
+            Statement::ForGeneric {
+                vars,
+                iterables,
+                body,
+            } => {
+                for expr in iterables {
+                    self.expression(expr);
+                }
+                self.scopes.push(Vec::new());
+                for var in vars {
+                    self.declare(var, false);
+                }
+                self.scoped_block(*body);
+                self.close_scope();
+            }
+            Statement::FunctionDecl { name, body } => {
+                if name.is_simple() {
+                    self.write(name.base());
+                } else {
+                    self.read(name.base());
+                }
+                self.function(&name.to_string(), *body);
+            }
+            Statement::LocalFunction { name, body } => {
+                // Declared first so the body can call itself
+                self.declare(name, false);
+                self.function(name, *body);
+            }
+            Statement::LocalVars { names, values } => {
+                for expr in values.iter().flatten() {
+                    self.expression(expr);
+                }
+                for name in names {
+                    self.declare(name, false);
+                }
+            }
+            // The linter runs before resolution
+            Statement::LocalSlots { .. }
+            | Statement::ForNumericSlot { .. }
+            | Statement::ForGenericSlots { .. } => {}
+        }
+    }
+
+    fn function(&mut self, name: &str, body: FunctionId) {
+        let body = self.arena.function(body);
+        self.functions.push(name.to_string());
+        self.scopes.push(Vec::new());
+        for param in &body.params {
+            self.declare(param, true);
+        }
+        self.block(self.arena.block(body.block));
+        self.close_scope();
+        self.functions.pop();
+    }
+
+    fn expression(&mut self, expr: &Expression) {
+        match expr {
+            Expression::Nil
+            | Expression::Boolean(_)
+            | Expression::Number(_)
+            | Expression::String(_)
+            | Expression::Varargs
+            | Expression::Local { .. }
+            | Expression::Upvalue { .. } => {}
+            Expression::Identifier(name) | Expression::Global(name) => self.read(name),
+            Expression::BinaryOp { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            Expression::UnaryOp { operand, .. } | Expression::Paren(operand) => {
+                self.expression(operand)
+            }
+            Expression::TableIndexing { object, index } => {
+                self.expression(object);
+                self.expression(index);
+            }
+            Expression::FieldAccess { object, .. } => self.expression(object),
+            Expression::FunctionCall { function, args } => {
+                self.expression(function);
+                for arg in args {
+                    self.expression(arg);
+                }
+            }
+            Expression::MethodCall { object, args, .. } => {
+                self.expression(object);
+                for arg in args {
+                    self.expression(arg);
+                }
+            }
+            Expression::TableConstructor { fields } => {
+                let mut seen = HashSet::new();
+                for field in fields {
+                    let key = match &field.key {
+                        FieldKey::Identifier(name) => Some(name.to_string()),
+                        FieldKey::Bracket(key) => {
+                            self.expression(key);
+                            constant_key(key)
+                        }
+                        FieldKey::Index(index) => Some(index.to_string()),
+                    };
+                    if let Some(key) = key {
+                        if !seen.insert(key.clone()) {
+                            self.report(LintKind::DuplicateKey, key);
+                        }
+                    }
+                    self.expression(&field.value);
+                }
+            }
+            Expression::FunctionDef(body) => self.function("<anonymous>", *body),
+        }
+    }
+}
+
+/// The key a constant bracket expression stands for, in the same spelling
+/// as identifier and positional keys
+fn constant_key(expr: &Expression) -> Option<String> {
+    match expr {
+        Expression::String(s) => Some(s.to_string()),
+        Expression::Number(n) => Some(n.to_f64().to_string()),
+        _ => None,
+    }
+}
+
+/// The statement ending control flow if `statement` never falls through
+fn exits(arena: &LuaArena, statement: &Statement) -> Option<&'static str> {
+    match statement {
+        Statement::Break => Some("break"),
+        Statement::Goto(_) => Some("goto"),
+        Statement::Do(body) => block_exits(arena, arena.block(*body)),
+        Statement::If {
+            then_block,
+            elseif_parts,
+            else_block: Some(else_block),
+            ..
+        } => {
+            // Only when every branch leaves
+            let mut branches = std::iter::once(*then_block)
+                .chain(elseif_parts.iter().map(|(_, b)| *b))
+                .chain(std::iter::once(*else_block))
+                .map(|b| arena.block(b));
+            let first = block_exits(arena, branches.next()?)?;
+            branches
+                .all(|b| block_exits(arena, b).is_some())
+                .then_some(first)
+        }
+        _ => None,
+    }
+}
+
+fn block_exits(arena: &LuaArena, block: &Block) -> Option<&'static str> {
+    if block.return_statement.is_some() {
+        return Some("return");
+    }
+    block
+        .statements
+        .iter()
+        .rev()
+        .find_map(|statement| exits(arena, statement))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua_parser::{parse, tokenize, TokenSlice};
+
+    fn check(code: &str) -> Vec<(LintKind, String)> {
+        let tokens = tokenize(code).unwrap();
+        let (_, block) = parse(TokenSlice::from(tokens.as_slice())).unwrap();
+        lint(&block, &builtin_globals())
+            .into_iter()
+            .map(|d| (d.kind, d.name))
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_chunk_has_no_diagnostics() {
+        let code = "
+            local count = 0
+            function bump(n) count = count + n end
+            local obj = {}
+            function obj:get() return self end
+            for _, v in ipairs({1, 2}) do bump(v) end
+            print(count, obj:get(), bump)";
+        assert_eq!(check(code), vec![]);
+    }
+
+    #[test]
+    fn test_undefined_and_unused() {
+        let code = "
+            local unused = 1
+            local function helper(x) return missing + x end
+            print(helper(2), later)
+            later = 3";
+        assert_eq!(
+            check(code),
+            vec![
+                (LintKind::UnusedLocal, "unused".to_string()),
+                (LintKind::UndefinedGlobal, "missing".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unreachable_after_exit() {
+        let code = "
+            local function f(x)
+                if x then return 1 else return 2 end
+                print(x)
+            end
+            while true do break print(1) end
+            f(1)";
+        assert_eq!(
+            check(code),
+            vec![
+                (LintKind::UnreachableCode, "return".to_string()),
+                (LintKind::UnreachableCode, "break".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_keys_and_shadowing() {
+        let code = "
+            local t = {1, 2, a = 1, ['a'] = 2, [2] = 3}
+            local x = 1
+            do local x = 2 print(x) end
+            print(t, x)";
+        assert_eq!(
+            check(code),
+            vec![
+                (LintKind::DuplicateKey, "a".to_string()),
+                (LintKind::DuplicateKey, "2".to_string()),
+                (LintKind::ShadowedVariable, "x".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_findings_point_at_their_statement() {
+        let code = "local unused = 1\n\
+            local function f(x)\n  do return x end\n  print(x)\nend\n\
+            f(1) print(missing)\n\
+            local t = {a = 1,\n  a = 2}\n\
+            return t";
+        let chunk = crate::lua_parser::parse_source(code).unwrap();
+        let found: Vec<String> = lint(&chunk, &builtin_globals())
+            .iter()
+            .map(Diagnostic::to_string)
+            .collect();
+        assert_eq!(
+            found,
+            [
+                "4:3: [unreachable-code] unreachable code after 'return' in function 'f'",
+                "7:1: [duplicate-key] duplicate table key 'a'",
+                "1:1: [unused-local] unused local 'unused'",
+                "6:6: [undefined-global] undefined global 'missing'",
+            ]
+        );
+    }
+}
@@ -0,0 +1,899 @@
+//! Language Server Protocol mode, run by `muscm lsp`
+//!
+//! Speaks JSON-RPC over stdio with `Content-Length` framing. Documents are
+//! synced in full; after every change the server publishes the document's
+//! parse errors and, for Lua, the findings of `lint`. It answers
+//! `textDocument/documentSymbol` with the functions and variables a
+//! document declares, and `textDocument/hover` with the signature of the
+//! standard library function under the cursor.
+//!
+//! Documents opened with the `scheme` language id, or whose URI ends in
+//! `.scm`, are Scheme; everything else is Lua. Positions count characters
+//! rather than UTF-16 code units, which only matters past the BMP.
+//!
+//! Lint findings do not record where they occur, so their range is the
+//! first matching token: the variable for undefined globals and unused
+//! locals, its second appearance for duplicate keys and shadowing, and the
+//! first `return`, `break` or `goto` for unreachable code.
+
+use crate::interpreter::{Environment, SVal};
+use crate::lint;
+use crate::lua_parser::{
+    self, Block, FunctionBody, Location, LuaArena, Statement, Token, TokenWithLocation, KEYWORDS,
+    SYMBOLS,
+};
+use crate::macro_expander::expand_program;
+use crate::parser;
+use crate::tokenizer::{Token as SchemeToken, TokenType, Tokenizer};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+pub use crate::framing::{read_message, write_message};
+
+// Symbol kinds and diagnostic severities, as numbered by the protocol
+const SYMBOL_METHOD: u32 = 6;
+const SYMBOL_FUNCTION: u32 = 12;
+const SYMBOL_VARIABLE: u32 = 13;
+const SEVERITY_ERROR: u32 = 1;
+const SEVERITY_WARNING: u32 = 2;
+
+/// JSON-RPC error code for requests the server does not implement
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Signatures shown on hover for Lua's standard library
+const LUA_DOCS: &[(&str, &str)] = &[
+    ("assert", "assert(v [, message]) -> v, ..."),
+    (
+        "collectgarbage",
+        "collectgarbage([opt]) -> number | boolean",
+    ),
+    ("error", "error(message [, level])"),
+    ("getmetatable", "getmetatable(object) -> table | nil"),
+    ("inspect", "inspect(value [, options]) -> string"),
+    ("ipairs", "ipairs(t) -> iterator, t, 0"),
+    (
+        "load",
+        "load(chunk [, chunkname [, mode [, env]]]) -> function | nil, err",
+    ),
+    ("next", "next(table [, index]) -> key, value"),
+    ("pairs", "pairs(t) -> next, t, nil"),
+    ("pcall", "pcall(f, ...) -> ok, ..."),
+    ("print", "print(...)"),
+    ("rawequal", "rawequal(a, b) -> boolean"),
+    ("rawget", "rawget(table, key) -> value"),
+    ("rawlen", "rawlen(v) -> integer"),
+    ("rawset", "rawset(table, key, value) -> table"),
+    ("require", "require(modname) -> module"),
+    ("select", "select(n | '#', ...) -> ..."),
+    ("setmetatable", "setmetatable(table, metatable) -> table"),
+    ("tonumber", "tonumber(e [, base]) -> number | nil"),
+    ("tostring", "tostring(v) -> string"),
+    ("type", "type(v) -> string"),
+    ("xpcall", "xpcall(f, handler, ...) -> ok, ..."),
+    ("coroutine.close", "coroutine.close(co) -> boolean"),
+    ("coroutine.create", "coroutine.create(f) -> thread"),
+    (
+        "coroutine.isyieldable",
+        "coroutine.isyieldable() -> boolean",
+    ),
+    ("coroutine.resume", "coroutine.resume(co, ...) -> ok, ..."),
+    (
+        "coroutine.running",
+        "coroutine.running() -> thread | nil, boolean",
+    ),
+    ("coroutine.status", "coroutine.status(co) -> string"),
+    ("coroutine.wrap", "coroutine.wrap(f) -> function"),
+    ("coroutine.yield", "coroutine.yield(...) -> ..."),
+    ("coverage.report", "coverage.report() -> string"),
+    ("coverage.start", "coverage.start() -> true | nil, message"),
+    ("coverage.stop", "coverage.stop()"),
+    (
+        "debug.traceback",
+        "debug.traceback([message [, level]]) -> string",
+    ),
+    (
+        "debug.getinfo",
+        "debug.getinfo(f | level [, what]) -> table",
+    ),
+    ("io.open", "io.open(filename [, mode]) -> file | nil, err"),
+    ("io.read", "io.read(...) -> ..."),
+    ("io.write", "io.write(...) -> file"),
+    ("io.lines", "io.lines([filename, ...]) -> iterator"),
+    ("math.abs", "math.abs(x) -> number"),
+    ("math.ceil", "math.ceil(x) -> integer"),
+    ("math.floor", "math.floor(x) -> integer"),
+    ("math.max", "math.max(x, ...) -> number"),
+    ("math.min", "math.min(x, ...) -> number"),
+    ("math.random", "math.random([m [, n]]) -> number"),
+    ("math.sqrt", "math.sqrt(x) -> number"),
+    ("math.tointeger", "math.tointeger(x) -> integer | nil"),
+    (
+        "net.connect",
+        "net.connect(host, port [, timeout]) -> connection",
+    ),
+    (
+        "net.http_get",
+        "net.http_get(url [, timeout]) -> body, status, headers",
+    ),
+    (
+        "net.http_request",
+        "net.http_request{url, method, headers, body, timeout} -> response",
+    ),
+    ("os.clock", "os.clock() -> number"),
+    ("os.date", "os.date([format [, time]]) -> string | table"),
+    ("os.getenv", "os.getenv(varname) -> string | nil"),
+    ("os.time", "os.time([table]) -> integer"),
+    ("string.byte", "string.byte(s [, i [, j]]) -> ..."),
+    ("string.char", "string.char(...) -> string"),
+    (
+        "string.find",
+        "string.find(s, pattern [, init [, plain]]) -> start, end, ...",
+    ),
+    (
+        "string.format",
+        "string.format(formatstring, ...) -> string",
+    ),
+    ("string.gmatch", "string.gmatch(s, pattern) -> iterator"),
+    (
+        "string.gsub",
+        "string.gsub(s, pattern, repl [, n]) -> string, count",
+    ),
+    ("string.len", "string.len(s) -> integer"),
+    ("string.lower", "string.lower(s) -> string"),
+    ("string.match", "string.match(s, pattern [, init]) -> ..."),
+    ("string.rep", "string.rep(s, n [, sep]) -> string"),
+    ("string.reverse", "string.reverse(s) -> string"),
+    ("string.sub", "string.sub(s, i [, j]) -> string"),
+    ("string.upper", "string.upper(s) -> string"),
+    (
+        "table.concat",
+        "table.concat(list [, sep [, i [, j]]]) -> string",
+    ),
+    ("table.deepcopy", "table.deepcopy(t) -> table"),
+    ("table.freeze", "table.freeze(t) -> t"),
+    ("table.insert", "table.insert(list, [pos,] value)"),
+    ("table.isfrozen", "table.isfrozen(t) -> boolean"),
+    ("table.move", "table.move(a1, f, e, t [, a2]) -> a2"),
+    ("table.pack", "table.pack(...) -> table"),
+    ("table.remove", "table.remove(list [, pos]) -> value"),
+    ("table.sort", "table.sort(list [, comp])"),
+    ("table.unpack", "table.unpack(list [, i [, j]]) -> ..."),
+    ("timer.after", "timer.after(seconds, fn) -> id"),
+    ("timer.cancel", "timer.cancel(id) -> boolean"),
+    ("timer.every", "timer.every(seconds, fn) -> id"),
+    ("timer.sleep", "timer.sleep(seconds)"),
+    ("utf8.char", "utf8.char(...) -> string"),
+    (
+        "utf8.codepoint",
+        "utf8.codepoint(s [, i [, j [, lax]]]) -> ...",
+    ),
+    (
+        "utf8.len",
+        "utf8.len(s [, i [, j [, lax]]]) -> integer | nil, position",
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dialect {
+    Lua,
+    Scheme,
+}
+
+struct Document {
+    text: String,
+    dialect: Dialect,
+}
+
+/// A range within one line: 0-based line and character columns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Span {
+    line: usize,
+    start: usize,
+    end: usize,
+}
+
+impl Span {
+    fn json(self) -> Value {
+        range(self.line, self.start, self.line, self.end)
+    }
+}
+
+fn range(start_line: usize, start: usize, end_line: usize, end: usize) -> Value {
+    json!({
+        "start": { "line": start_line, "character": start },
+        "end": { "line": end_line, "character": end },
+    })
+}
+
+/// State of one language server session
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, Document>,
+    exit: bool,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// True once the client has sent `exit`
+    pub fn exited(&self) -> bool {
+        self.exit
+    }
+
+    /// Handle one message from the client, returning the responses and
+    /// notifications to send back
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message.get("method").and_then(Value::as_str);
+        let id = message.get("id").cloned();
+        let params = message.get("params").unwrap_or(&Value::Null);
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+
+        match (method, id) {
+            (Some("initialize"), Some(id)) => vec![response(
+                id,
+                json!({
+                    "capabilities": {
+                        "textDocumentSync": 1,
+                        "documentSymbolProvider": true,
+                        "hoverProvider": true,
+                    },
+                    "serverInfo": { "name": "muscm", "version": env!("CARGO_PKG_VERSION") },
+                }),
+            )],
+            (Some("shutdown"), Some(id)) => vec![response(id, Value::Null)],
+            (Some("exit"), _) => {
+                self.exit = true;
+                Vec::new()
+            }
+            (Some("textDocument/didOpen"), None) => {
+                let document = &params["textDocument"];
+                let dialect = if document["languageId"] == "scheme" || uri.ends_with(".scm") {
+                    Dialect::Scheme
+                } else {
+                    Dialect::Lua
+                };
+                let text = document["text"].as_str().unwrap_or_default().to_string();
+                self.documents
+                    .insert(uri.to_string(), Document { text, dialect });
+                vec![self.publish(uri)]
+            }
+            (Some("textDocument/didChange"), None) => {
+                // Full sync: the last change holds the whole text
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str());
+                match (self.documents.get_mut(uri), text) {
+                    (Some(document), Some(text)) => {
+                        document.text = text.to_string();
+                        vec![self.publish(uri)]
+                    }
+                    _ => Vec::new(),
+                }
+            }
+            (Some("textDocument/didClose"), None) => {
+                self.documents.remove(uri);
+                vec![publish_diagnostics(uri, Vec::new())]
+            }
+            (Some("textDocument/documentSymbol"), Some(id)) => {
+                let symbols = self
+                    .documents
+                    .get(uri)
+                    .map(|document| match document.dialect {
+                        Dialect::Lua => lua_symbols(&document.text),
+                        Dialect::Scheme => scheme_symbols(&document.text),
+                    });
+                vec![response(id, symbols.map_or(Value::Null, Value::from))]
+            }
+            (Some("textDocument/hover"), Some(id)) => {
+                let line = params["position"]["line"].as_u64().unwrap_or(0) as usize;
+                let character = params["position"]["character"].as_u64().unwrap_or(0) as usize;
+                let hover = self
+                    .documents
+                    .get(uri)
+                    .and_then(|document| match document.dialect {
+                        Dialect::Lua => lua_hover(&document.text, line, character),
+                        Dialect::Scheme => scheme_hover(&document.text, line, character),
+                    });
+                let result = hover.map_or(Value::Null, |(span, text)| {
+                    json!({
+                        "contents": { "kind": "markdown", "value": text },
+                        "range": span.json(),
+                    })
+                });
+                vec![response(id, result)]
+            }
+            (Some(method), Some(id)) => vec![json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {
+                    "code": METHOD_NOT_FOUND,
+                    "message": format!("unhandled method {}", method),
+                },
+            })],
+            // Other notifications, and responses to requests we never send
+            _ => Vec::new(),
+        }
+    }
+
+    fn publish(&self, uri: &str) -> Value {
+        let diagnostics = match self.documents.get(uri) {
+            Some(Document {
+                text,
+                dialect: Dialect::Lua,
+            }) => lua_diagnostics(text),
+            Some(Document {
+                text,
+                dialect: Dialect::Scheme,
+            }) => scheme_diagnostics(text),
+            None => Vec::new(),
+        };
+        publish_diagnostics(uri, diagnostics)
+    }
+}
+
+/// Serve one client over `input` and `output` until it exits or hangs up
+pub fn run(mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut server = Server::new();
+    while let Some(message) = read_message(&mut input)? {
+        for reply in server.handle(&message) {
+            write_message(&mut output, &reply)?;
+        }
+        if server.exited() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn response(id: Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+fn diagnostic(span: Span, severity: u32, code: Option<String>, message: String) -> Value {
+    let mut diagnostic = json!({
+        "range": span.json(),
+        "severity": severity,
+        "source": "muscm",
+        "message": message,
+    });
+    if let Some(code) = code {
+        diagnostic["code"] = Value::from(code);
+    }
+    diagnostic
+}
+
+/// Span of the whole of 0-based line `line`
+fn line_span(text: &str, line: usize) -> Span {
+    let width = text.lines().nth(line).map_or(0, |l| l.chars().count());
+    Span {
+        line,
+        start: 0,
+        end: width,
+    }
+}
+
+// Lua
+
+/// Number of characters a Lua token takes in the source
+fn token_width(token: &Token) -> usize {
+    match token {
+        Token::Identifier(name) => name.chars().count(),
+        Token::Number(text) => text.chars().count(),
+        // Assume plain quotes; long brackets and escapes make it longer
+        Token::StringLit(text) => text.to_str_lossy().chars().count() + 2,
+        other => KEYWORDS
+            .entries()
+            .chain(SYMBOLS.entries())
+            .find(|(_, t)| *t == other)
+            .map_or(1, |(text, _)| text.len()),
+    }
+}
+
+fn token_span(token: &TokenWithLocation) -> Span {
+    let line = token.location.line.saturating_sub(1);
+    let start = token.location.column;
+    Span {
+        line,
+        start,
+        end: start + token_width(&token.token),
+    }
+}
+
+fn lua_diagnostics(text: &str) -> Vec<Value> {
+    let tokens = match lua_parser::tokenize_with_location(text) {
+        Ok(tokens) => tokens,
+        Err(e) => return vec![diagnostic(line_span(text, 0), SEVERITY_ERROR, None, e)],
+    };
+    let block = match lua_parser::parse_located(&tokens) {
+        Ok(block) => block,
+        Err(error) => {
+            let span = match error.index.map(|i| &tokens[i]) {
+                Some(token) => token_span(token),
+                None => line_span(text, text.lines().count().saturating_sub(1)),
+            };
+            return vec![diagnostic(span, SEVERITY_ERROR, None, error.to_string())];
+        }
+    };
+
+    lint::lint(&block, &lint::builtin_globals())
+        .into_iter()
+        .map(|finding| {
+            let span = statement_span(text, finding.location);
+            let message = match &finding.function {
+                Some(function) => format!("{} in function '{}'", finding.message(), function),
+                None => finding.message(),
+            };
+            diagnostic(
+                span,
+                SEVERITY_WARNING,
+                Some(finding.kind.to_string()),
+                message,
+            )
+        })
+        .collect()
+}
+
+/// Span from `start`, where a statement begins, to the end of its line
+fn statement_span(text: &str, start: Location) -> Span {
+    let line = line_span(text, start.line.saturating_sub(1));
+    Span {
+        start: start.column.min(line.end),
+        ..line
+    }
+}
+
+fn lua_symbols(text: &str) -> Vec<Value> {
+    let Ok(tokens) = lua_parser::tokenize_with_location(text) else {
+        return Vec::new();
+    };
+    match lua_parser::parse_located(&tokens) {
+        Ok(chunk) => block_symbols(&chunk.arena, &chunk, &tokens),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Symbols declared in `block`, with each function's own declarations as
+/// its children
+fn block_symbols(arena: &LuaArena, block: &Block, tokens: &[TokenWithLocation]) -> Vec<Value> {
+    let mut symbols = Vec::new();
+    for (i, statement) in block.statements.iter().enumerate() {
+        let line = block.lines.get(i).copied().unwrap_or(0);
+        match statement {
+            Statement::FunctionDecl { name, body } => {
+                let last = name.path.last().map(String::as_str).unwrap_or_default();
+                let kind = if name.method {
+                    SYMBOL_METHOD
+                } else {
+                    SYMBOL_FUNCTION
+                };
+                symbols.push(function_symbol(
+                    arena,
+                    name.to_string(),
+                    kind,
+                    last,
+                    line,
+                    arena.function(*body),
+                    tokens,
+                ));
+            }
+            Statement::LocalFunction { name, body } => {
+                symbols.push(function_symbol(
+                    arena,
+                    name.clone(),
+                    SYMBOL_FUNCTION,
+                    name,
+                    line,
+                    arena.function(*body),
+                    tokens,
+                ));
+            }
+            Statement::LocalVars { names, .. } => {
+                for name in names {
+                    if let Some(span) = name_span(tokens, line, name) {
+                        symbols.push(json!({
+                            "name": name,
+                            "kind": SYMBOL_VARIABLE,
+                            "range": span.json(),
+                            "selectionRange": span.json(),
+                        }));
+                    }
+                }
+            }
+            Statement::Do(body)
+            | Statement::While { body, .. }
+            | Statement::Repeat { body, .. }
+            | Statement::ForNumeric { body, .. }
+            | Statement::ForGeneric { body, .. } => {
+                symbols.extend(block_symbols(arena, arena.block(*body), tokens));
+            }
+            Statement::If {
+                then_block,
+                elseif_parts,
+                else_block,
+                ..
+            } => {
+                symbols.extend(block_symbols(arena, arena.block(*then_block), tokens));
+                for (_, block) in elseif_parts {
+                    symbols.extend(block_symbols(arena, arena.block(*block), tokens));
+                }
+                if let Some(block) = else_block {
+                    symbols.extend(block_symbols(arena, arena.block(*block), tokens));
+                }
+            }
+            _ => {}
+        }
+    }
+    symbols
+}
+
+fn function_symbol(
+    arena: &LuaArena,
+    display: String,
+    kind: u32,
+    name: &str,
+    line: usize,
+    body: &FunctionBody,
+    tokens: &[TokenWithLocation],
+) -> Value {
+    let selection = name_span(tokens, line, name).unwrap_or(Span {
+        line: line.saturating_sub(1),
+        start: 0,
+        end: 0,
+    });
+    // From the start of the declaring line to the end of the closing `end`
+    let end_line = body.lines.map_or(line, |(_, last)| last);
+    let end = tokens
+        .iter()
+        .rev()
+        .find(|t| t.location.line == end_line && t.token == Token::End)
+        .map_or(selection, token_span);
+    json!({
+        "name": display,
+        "kind": kind,
+        "range": range(selection.line, 0, end.line, end.end),
+        "selectionRange": selection.json(),
+        "children": block_symbols(arena, arena.block(body.block), tokens),
+    })
+}
+
+/// Span of the first identifier `name` on 1-based `line`
+fn name_span(tokens: &[TokenWithLocation], line: usize, name: &str) -> Option<Span> {
+    tokens
+        .iter()
+        .find(|t| {
+            t.location.line == line && matches!(&t.token, Token::Identifier(id) if &**id == name)
+        })
+        .map(token_span)
+}
+
+fn lua_hover(text: &str, line: usize, character: usize) -> Option<(Span, String)> {
+    let tokens = lua_parser::tokenize_with_location(text).ok()?;
+    let index = tokens.iter().position(|t| {
+        let span = token_span(t);
+        span.line == line && span.start <= character && character < span.end
+    })?;
+    let Token::Identifier(name) = &tokens[index].token else {
+        return None;
+    };
+    // `string.format` is documented under its library's name
+    let qualified = match index
+        .checked_sub(2)
+        .map(|i| (&tokens[i].token, &tokens[i + 1].token))
+    {
+        Some((Token::Identifier(library), Token::Dot)) => format!("{}.{}", library, name),
+        _ => name.to_string(),
+    };
+    let (_, signature) = LUA_DOCS.iter().find(|(n, _)| *n == qualified)?;
+    Some((
+        token_span(&tokens[index]),
+        format!("```lua\n{}\n```\nLua standard library", signature),
+    ))
+}
+
+// Scheme
+
+/// Converts byte offsets in `text` to line and character positions
+struct LineIndex<'a> {
+    text: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(text: &'a str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        LineIndex { text, starts }
+    }
+
+    /// 0-based line and character of byte offset `offset`
+    fn position(&self, offset: usize) -> (usize, usize) {
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        let character = self.text[self.starts[line]..offset].chars().count();
+        (line, character)
+    }
+
+    fn span(&self, token: &SchemeToken) -> Span {
+        let (line, start) = self.position(token.start);
+        Span {
+            line,
+            start,
+            end: start + self.text[token.start..token.end].chars().count(),
+        }
+    }
+}
+
+fn scheme_diagnostics(text: &str) -> Vec<Value> {
+    match parser::parse(text) {
+        Err(e) => {
+            let line = e.line.saturating_sub(1);
+            vec![diagnostic(
+                line_span(text, line),
+                SEVERITY_ERROR,
+                None,
+                e.message,
+            )]
+        }
+        Ok((mut arena, nodes)) => match expand_program(&mut arena, &nodes) {
+            Ok(_) => Vec::new(),
+            Err(e) => vec![diagnostic(
+                line_span(text, 0),
+                SEVERITY_ERROR,
+                None,
+                format!("macro expansion: {}", e),
+            )],
+        },
+    }
+}
+
+/// Top-level `(define name ...)` and `(define (name args...) ...)` forms
+fn scheme_symbols(text: &str) -> Vec<Value> {
+    let tokens: Vec<SchemeToken> = Tokenizer::new(text).collect();
+    let index = LineIndex::new(text);
+    let is = |i: usize, kind: TokenType| tokens.get(i).is_some_and(|t| t.token_type == kind);
+
+    let mut symbols = Vec::new();
+    let mut depth = 0usize;
+    for (i, token) in tokens.iter().enumerate() {
+        match token.token_type {
+            TokenType::LParen | TokenType::Vec => {
+                let defines = depth == 0
+                    && tokens
+                        .get(i + 1)
+                        .is_some_and(|t| t.token_type == TokenType::Atom && t.literal == "define");
+                depth += 1;
+                if !defines {
+                    continue;
+                }
+                let (name, kind) = if is(i + 2, TokenType::Atom) {
+                    (&tokens[i + 2], SYMBOL_VARIABLE)
+                } else if is(i + 2, TokenType::LParen) && is(i + 3, TokenType::Atom) {
+                    (&tokens[i + 3], SYMBOL_FUNCTION)
+                } else {
+                    continue;
+                };
+                let close = matching_paren(&tokens[i..]).map_or(token, |j| &tokens[i + j]);
+                let (start_line, start) = index.position(token.start);
+                let (end_line, end) = index.position(close.end);
+                symbols.push(json!({
+                    "name": name.literal,
+                    "kind": kind,
+                    "range": range(start_line, start, end_line, end),
+                    "selectionRange": index.span(name).json(),
+                }));
+            }
+            TokenType::RParen => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    symbols
+}
+
+/// Index of the parenthesis closing the one `tokens` starts with
+fn matching_paren(tokens: &[SchemeToken]) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, token) in tokens.iter().enumerate() {
+        match token.token_type {
+            TokenType::LParen | TokenType::Vec => depth += 1,
+            TokenType::RParen => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn scheme_hover(text: &str, line: usize, character: usize) -> Option<(Span, String)> {
+    let index = LineIndex::new(text);
+    let token = Tokenizer::new(text).find(|t| {
+        let span = index.span(t);
+        t.token_type == TokenType::Atom
+            && span.line == line
+            && span.start <= character
+            && character < span.end
+    })?;
+    let SVal::BuiltinProc { name, arity } = Environment::new().lookup(&token.literal)? else {
+        return None;
+    };
+    let arguments = match arity {
+        Some(1) => "1 argument".to_string(),
+        Some(n) => format!("{} arguments", n),
+        None => "any number of arguments".to_string(),
+    };
+    Some((
+        index.span(&token),
+        format!(
+            "```scheme\n({} ...)\n```\nBuiltin procedure taking {}",
+            name, arguments
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(server: &mut Server, uri: &str, text: &str) -> Vec<Value> {
+        server.handle(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": uri, "languageId": "", "version": 1, "text": text } },
+        }))
+    }
+
+    fn request(server: &mut Server, method: &str, params: Value) -> Value {
+        let replies = server
+            .handle(&json!({ "jsonrpc": "2.0", "id": 7, "method": method, "params": params }));
+        assert_eq!(replies.len(), 1);
+        replies[0]["result"].clone()
+    }
+
+    #[test]
+    fn test_lua_diagnostics_on_open() {
+        let mut server = Server::new();
+        let replies = open(&mut server, "file:///a.lua", "local x = 1\nprint(y)\n");
+        let diagnostics = &replies[0]["params"]["diagnostics"];
+        let messages: Vec<_> = diagnostics
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| {
+                (
+                    d["code"].as_str().unwrap(),
+                    d["range"]["start"]["line"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert!(messages.contains(&("unused-local", 0)), "{:?}", messages);
+        assert!(
+            messages.contains(&("undefined-global", 1)),
+            "{:?}",
+            messages
+        );
+
+        // The finding points at the statement that reads the global, not
+        // at an earlier token of the same name
+        let replies = open(
+            &mut server,
+            "file:///c.lua",
+            "t = {y = 1}\nt.x = 2 print(y)\n",
+        );
+        let warning = &replies[0]["params"]["diagnostics"][0];
+        assert_eq!(warning["code"], "undefined-global");
+        assert_eq!(warning["range"], range(1, 8, 1, 16));
+
+        let replies = open(&mut server, "file:///b.lua", "x = 1\n= 2");
+        let error = &replies[0]["params"]["diagnostics"][0];
+        assert_eq!(error["severity"], SEVERITY_ERROR);
+        assert_eq!(error["range"], range(1, 0, 1, 1));
+    }
+
+    #[test]
+    fn test_lua_document_symbols() {
+        let mut server = Server::new();
+        let code = "local t = {}\nfunction t.m:run(a)\n  local inner = a\n  return inner\nend\n";
+        open(&mut server, "file:///s.lua", code);
+        let symbols = request(
+            &mut server,
+            "textDocument/documentSymbol",
+            json!({ "textDocument": { "uri": "file:///s.lua" } }),
+        );
+        assert_eq!(symbols[0]["name"], "t");
+        assert_eq!(symbols[1]["name"], "t.m:run");
+        assert_eq!(symbols[1]["kind"], SYMBOL_METHOD);
+        assert_eq!(symbols[1]["range"], range(1, 0, 4, 3));
+        assert_eq!(symbols[1]["selectionRange"], range(1, 13, 1, 16));
+        assert_eq!(symbols[1]["children"][0]["name"], "inner");
+    }
+
+    #[test]
+    fn test_hover_shows_stdlib_signatures() {
+        let mut server = Server::new();
+        open(
+            &mut server,
+            "file:///h.lua",
+            "print(string.format('%d', 1))",
+        );
+        let hover = |server: &mut Server, character: usize| {
+            request(
+                server,
+                "textDocument/hover",
+                json!({
+                    "textDocument": { "uri": "file:///h.lua" },
+                    "position": { "line": 0, "character": character },
+                }),
+            )
+        };
+        let format = hover(&mut server, 15);
+        assert!(format["contents"]["value"]
+            .as_str()
+            .unwrap()
+            .contains("string.format(formatstring, ...)"));
+        assert_eq!(format["range"], range(0, 13, 0, 19));
+        assert_eq!(hover(&mut server, 21), Value::Null);
+    }
+
+    #[test]
+    fn test_scheme_documents() {
+        let mut server = Server::new();
+        let replies = open(
+            &mut server,
+            "file:///p.scm",
+            "(define (sq x)\n  (* x x))\n(define n 2)",
+        );
+        assert_eq!(replies[0]["params"]["diagnostics"], json!([]));
+        let symbols = request(
+            &mut server,
+            "textDocument/documentSymbol",
+            json!({ "textDocument": { "uri": "file:///p.scm" } }),
+        );
+        assert_eq!(symbols[0]["name"], "sq");
+        assert_eq!(symbols[0]["kind"], SYMBOL_FUNCTION);
+        assert_eq!(symbols[0]["range"], range(0, 0, 1, 10));
+        assert_eq!(symbols[1]["name"], "n");
+        let hover = request(
+            &mut server,
+            "textDocument/hover",
+            json!({ "textDocument": { "uri": "file:///p.scm" }, "position": { "line": 1, "character": 3 } }),
+        );
+        assert!(hover["contents"]["value"]
+            .as_str()
+            .unwrap()
+            .contains("any number"));
+
+        let replies = open(&mut server, "file:///q.scm", "(define (f)\n  (g");
+        assert_eq!(
+            replies[0]["params"]["diagnostics"][0]["severity"],
+            SEVERITY_ERROR
+        );
+    }
+
+    #[test]
+    fn test_unknown_requests_and_exit() {
+        let mut server = Server::new();
+        let replies =
+            server.handle(&json!({ "jsonrpc": "2.0", "id": 1, "method": "textDocument/rename" }));
+        assert_eq!(replies[0]["error"]["code"], METHOD_NOT_FOUND);
+        assert!(server
+            .handle(&json!({ "jsonrpc": "2.0", "method": "initialized" }))
+            .is_empty());
+        server.handle(&json!({ "jsonrpc": "2.0", "method": "exit" }));
+        assert!(server.exited());
+    }
+}
@@ -0,0 +1,72 @@
+//! Lua 5.4 floor division and modulo
+//!
+//! Both round the quotient toward negative infinity, so the remainder takes
+//! the sign of the divisor: `-5 % 3` is `1` and `-7 // 2` is `-4`, where
+//! Rust's `%` and `/` truncate toward zero. These follow `luai_numidiv`
+//! and `luai_nummod` from the reference implementation, so infinities come
+//! out the same too. Every Lua number here is a float, so dividing by zero
+//! gives `inf`, `-inf` or `nan` rather than an error.
+
+/// `a // b` for floats: the floor of the true quotient, following IEEE 754
+/// when `b` is zero
+pub fn float_floor_div(a: f64, b: f64) -> f64 {
+    (a / b).floor()
+}
+
+/// `a % b` for floats: `a - floor(a / b) * b`, computed from the exact
+/// remainder so large operands lose no precision
+pub fn float_mod(a: f64, b: f64) -> f64 {
+    // `%` truncates like C's `fmod`; a remainder whose sign differs from
+    // the divisor's came from a quotient that was rounded up
+    let m = a % b;
+    if (m > 0.0 && b < 0.0) || (m < 0.0 && b > 0.0) {
+        m + b
+    } else {
+        m
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every sign combination, checked against Lua 5.4
+    const MATRIX: [(i64, i64, i64, i64); 8] = [
+        // (a, b, a // b, a % b)
+        (7, 2, 3, 1),
+        (-7, 2, -4, 1),
+        (7, -2, -4, -1),
+        (-7, -2, 3, -1),
+        (5, 3, 1, 2),
+        (-5, 3, -2, 1),
+        (5, -3, -2, -1),
+        (-6, 3, -2, 0),
+    ];
+
+    #[test]
+    fn test_float_matrix() {
+        for (a, b, div, rem) in MATRIX {
+            let (a, b) = (a as f64, b as f64);
+            assert_eq!(float_floor_div(a, b), div as f64, "{} // {}", a, b);
+            assert_eq!(float_mod(a, b), rem as f64, "{} % {}", a, b);
+        }
+        assert_eq!(float_mod(5.5, 2.0), 1.5);
+        assert_eq!(float_mod(-5.5, 2.0), 0.5);
+        assert_eq!(float_mod(5.5, -2.0), -0.5);
+        assert_eq!(float_floor_div(-5.5, 2.0), -3.0);
+    }
+
+    #[test]
+    fn test_float_edge_cases() {
+        let inf = f64::INFINITY;
+        assert_eq!(float_floor_div(1.0, 0.0), inf);
+        assert_eq!(float_floor_div(-1.0, 0.0), -inf);
+        assert!(float_floor_div(0.0, 0.0).is_nan());
+        assert!(float_mod(1.0, 0.0).is_nan());
+        assert!(float_mod(inf, 2.0).is_nan());
+        assert_eq!(float_mod(5.0, inf), 5.0);
+        assert_eq!(float_mod(-5.0, inf), inf);
+        assert_eq!(float_mod(5.0, -inf), -inf);
+        assert_eq!(float_mod(-5.0, -inf), -5.0);
+    }
+}
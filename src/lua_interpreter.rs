@@ -1,8 +1,20 @@
-use crate::lua_value::{LuaTable, LuaValue};
+use crate::chunk_cache::ChunkCache;
+use crate::coverage::Coverage;
+use crate::error_types::{LuaError, LuaResult};
+use crate::file_io::IoStreams;
+use crate::gc::CycleCollector;
+use crate::hooks::{CancellationToken, Hook, HookEvent, HookFunction, HookMask};
+use crate::host_io::{InputSource, OutputSink};
+use crate::lua_value::{LuaFunction, LuaTable, LuaValue, Metatable, TableData};
 use crate::module_loader::ModuleLoader;
 use crate::scope_manager::ScopeManager;
+use crate::stdlib::Random;
+use crate::timers::Timers;
+use crate::traceback::CallTrace;
+use crate::upvalues::UpvalueCell;
+use crate::vm::CodeCache;
 use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::rc::Rc;
 
@@ -82,24 +94,97 @@ impl Default for ValueStack {
     }
 }
 
+/// Slots of one running call to a resolved function
+#[derive(Debug, Clone)]
+pub struct SlotFrame {
+    /// Local variables, indexed by `Expression::Local` slot
+    pub slots: Vec<UpvalueCell>,
+    /// Captured cells, indexed by `Expression::Upvalue` index
+    pub upvalues: Vec<UpvalueCell>,
+}
+
 /// The Lua interpreter with global state and execution context
 pub struct LuaInterpreter {
-    /// Global variables
-    pub globals: HashMap<String, LuaValue>,
-    /// Stack of local scopes (managed via ScopeManager)
-    pub scope_stack: Vec<HashMap<String, LuaValue>>,
+    /// Global variables, as the entries of the `_G` table; its metatable's
+    /// `__index` and `__newindex` see reads of undefined globals and
+    /// assignments to new ones
+    pub globals: Rc<RefCell<LuaTable>>,
+    /// Stack of local scopes (managed via ScopeManager). Locals live in
+    /// shared cells so closures can capture them by reference.
+    pub scope_stack: Vec<HashMap<String, UpvalueCell>>,
     /// Scope manager for encapsulated scope operations
     pub scope_manager: ScopeManager,
+    /// Frames of the resolved functions currently running, innermost last
+    pub frames: Vec<SlotFrame>,
     /// Call stack for function calls
     pub call_stack: Vec<CallFrame>,
     /// Value stack for temporary computation
     pub value_stack: ValueStack,
-    /// Set of reachable objects for garbage collection (mark-and-sweep)
-    pub reachable_objects: HashSet<usize>,
-    /// Maximum recursion depth to prevent stack overflow
+    /// Frees tables that only keep each other alive; shared with
+    /// `collectgarbage`
+    pub gc: Rc<RefCell<CycleCollector>>,
+    /// Most Lua calls that may be nested; one more is a "stack overflow"
+    /// error instead of unbounded growth
     pub max_call_depth: usize,
     /// Module loader for require() functionality
     pub module_loader: Rc<RefCell<ModuleLoader>>,
+    /// Standard streams and the default input/output used by io.read/io.write
+    pub io_streams: Rc<RefCell<IoStreams>>,
+    /// Where print and io.stdout write; hosts can install a writer or capture it
+    pub output: OutputSink,
+    /// Where io.stdin reads from; hosts can install a reader
+    pub input: InputSource,
+    /// Generator behind math.random, seeded from the clock; see
+    /// `set_random_seed` for reproducible runs
+    pub rng: Rc<RefCell<Random>>,
+    /// Checked before every statement; cancelling it stops the script
+    pub cancel: CancellationToken,
+    /// The hook installed by the host or by `debug.sethook`
+    pub hook: Rc<RefCell<Option<Hook>>>,
+    /// Calls the executor is running, for `debug.traceback` and error reports
+    pub trace: Rc<RefCell<CallTrace>>,
+    /// Lines that ran, once a host enables coverage; shared with the
+    /// `coverage` library
+    pub coverage: Rc<RefCell<Coverage>>,
+    /// Blocks parsed from the main chunk and required modules; keeps
+    /// nothing until a host calls `enable_chunk_cache`
+    pub chunk_cache: Rc<RefCell<ChunkCache>>,
+    /// Bytecode of the functions the VM has compiled, shared with child
+    /// interpreters
+    pub(crate) code_cache: Rc<RefCell<CodeCache>>,
+    /// Metatable every string value shares; its `__index` is the `string`
+    /// table, which is how `s:upper()` and `s.len` find the library
+    pub string_metatable: Rc<RefCell<LuaTable>>,
+    /// Callbacks queued by `timer.after` and `timer.every`; shared with the
+    /// `timer` library
+    pub timers: Rc<RefCell<Timers>>,
+    /// Switch for the `net` library; turning it off stops every network
+    /// call, in this interpreter and the children sharing it
+    #[cfg(feature = "net")]
+    pub network: crate::net::NetworkAccess,
+    /// Reading an undefined global that `_G`'s `__index` does not handle
+    /// is an error naming the variable and its line, not nil
+    pub strict_globals: bool,
+    /// Run chunks and functions on the bytecode VM when the compiler
+    /// covers them, and on the tree-walker otherwise; on by default (see
+    /// `vm::execute_chunk`)
+    pub use_vm: bool,
+    /// `debug.getlocal`, which the executor runs itself for stack levels
+    pub(crate) debug_getlocal: Rc<LuaFunction>,
+    /// `timer.sleep`, which the executor runs itself so due timers fire
+    /// while it waits
+    pub(crate) timer_sleep: Rc<LuaFunction>,
+    /// `pcall` and `xpcall`, which the executor runs itself so they can
+    /// call Lua functions and catch their errors
+    pub(crate) pcall: Rc<LuaFunction>,
+    pub(crate) xpcall: Rc<LuaFunction>,
+    /// `coroutine.resume`, which the executor runs itself so the coroutine
+    /// runs with this interpreter
+    pub(crate) coroutine_resume: Rc<LuaFunction>,
+    /// `print` and `tostring`, whose table arguments the executor first
+    /// passes through `__tostring`
+    pub(crate) print: Rc<LuaFunction>,
+    pub(crate) tostring: Rc<LuaFunction>,
 }
 
 impl LuaInterpreter {
@@ -111,16 +196,59 @@ impl LuaInterpreter {
     /// Create a new interpreter with custom max recursion depth
     pub fn with_max_depth(max_depth: usize) -> Self {
         let module_loader = ModuleLoader::new();
+        let output = OutputSink::stdout();
+        let input = InputSource::stdin();
+        let print = Rc::new(LuaFunction::Builtin(crate::stdlib::create_print(
+            output.clone(),
+        )));
 
         let mut interpreter = LuaInterpreter {
-            globals: HashMap::new(),
+            globals: Rc::new(RefCell::new(LuaTable {
+                data: TableData::new(),
+                metatable: None,
+                frozen: false,
+                removed: None,
+            })),
             scope_stack: Vec::new(),
             scope_manager: ScopeManager::new(),
+            frames: Vec::new(),
             call_stack: Vec::new(),
             value_stack: ValueStack::new(),
-            reachable_objects: HashSet::new(),
+            gc: Rc::new(RefCell::new(CycleCollector::new())),
             max_call_depth: max_depth,
             module_loader: Rc::new(RefCell::new(module_loader)),
+            io_streams: Rc::new(RefCell::new(IoStreams::new(&input, &output))),
+            output,
+            input,
+            rng: Rc::new(RefCell::new(Random::from_time())),
+            cancel: CancellationToken::new(),
+            hook: Rc::new(RefCell::new(None)),
+            trace: Rc::new(RefCell::new(CallTrace::new())),
+            coverage: Rc::new(RefCell::new(Coverage::new())),
+            chunk_cache: Rc::new(RefCell::new(ChunkCache::disabled())),
+            code_cache: Rc::new(RefCell::new(CodeCache::default())),
+            string_metatable: Rc::new(RefCell::new(LuaTable {
+                data: TableData::new(),
+                metatable: None,
+                frozen: false,
+                removed: None,
+            })),
+            timers: Rc::new(RefCell::new(Timers::new())),
+            #[cfg(feature = "net")]
+            network: crate::net::NetworkAccess::new(),
+            strict_globals: false,
+            use_vm: true,
+            debug_getlocal: Rc::new(LuaFunction::MultiBuiltin(
+                crate::stdlib::create_debug_getlocal(),
+            )),
+            timer_sleep: Rc::new(LuaFunction::Builtin(crate::stdlib::create_timer_sleep())),
+            pcall: Rc::new(LuaFunction::Builtin(crate::stdlib::create_pcall())),
+            xpcall: Rc::new(LuaFunction::Builtin(crate::stdlib::create_xpcall())),
+            coroutine_resume: Rc::new(LuaFunction::MultiBuiltin(
+                crate::stdlib::create_coroutine_resume(),
+            )),
+            print,
+            tostring: Rc::new(LuaFunction::Builtin(crate::stdlib::create_tostring())),
         };
 
         // Initialize standard library
@@ -134,107 +262,392 @@ impl LuaInterpreter {
         self.module_loader.borrow_mut().add_search_path(path);
     }
 
+    /// Keep parsed chunks by the hash of their source, in memory and, given
+    /// a directory, on disk for later runs
+    pub fn enable_chunk_cache(&mut self, dir: Option<PathBuf>) {
+        *self.chunk_cache.borrow_mut() = match dir {
+            Some(dir) => ChunkCache::with_dir(dir),
+            None => ChunkCache::in_memory(),
+        };
+    }
+
+    /// Register a host function as `package.preload[name]`, so `require(name)`
+    /// returns its result without searching `package.path`
+    pub fn preload_module(
+        &mut self,
+        name: &str,
+        loader: Rc<dyn Fn(Vec<LuaValue>) -> crate::error_types::LuaResult<LuaValue>>,
+    ) {
+        self.module_loader.borrow().preload(name, loader);
+    }
+
+    /// Define the global `arg` table the way the standalone `lua` does
+    ///
+    /// `script` is at index 0 and `script_args` from 1. `interpreter_args`
+    /// (the program name, then the options that came before the script) go
+    /// at negative indices, ending at -1, so `arg[-1]` is the option
+    /// closest to the script.
+    pub fn set_script_args(
+        &mut self,
+        script: &str,
+        interpreter_args: &[String],
+        script_args: &[String],
+    ) {
+        let mut table = LuaTable {
+            data: TableData::new(),
+            metatable: None,
+            frozen: false,
+            removed: None,
+        };
+        let first = -(interpreter_args.len() as i64);
+        let before = interpreter_args.iter().map(String::as_str);
+        let after = script_args.iter().map(String::as_str);
+        let all = before.chain(std::iter::once(script)).chain(after);
+        for (i, value) in (first..).zip(all) {
+            table.set_int(i, LuaValue::String(value.into()));
+        }
+        self.set_global("arg", LuaValue::Table(Rc::new(RefCell::new(table))));
+    }
+
+    /// Define a global, as a script assigning to it would
+    pub fn set_global(&mut self, name: impl Into<String>, value: LuaValue) {
+        self.globals.borrow_mut().set_str(&name.into(), value);
+    }
+
+    /// The value of a global, `Nil` when it is not defined
+    pub fn get_global(&self, name: &str) -> LuaValue {
+        let globals = self.globals.borrow();
+        globals.get_str(name).cloned().unwrap_or(LuaValue::Nil)
+    }
+
+    /// Names of the defined globals, in the order they were first defined
+    pub fn global_names(&self) -> Vec<String> {
+        let globals = self.globals.borrow();
+        globals
+            .data
+            .keys()
+            .filter_map(|key| match key {
+                LuaValue::String(name) => Some(name.to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Write the globals to `path` so `load_snapshot` can bring them back
+    ///
+    /// See `snapshot` for what is saved and what is saved only by name.
+    pub fn save_snapshot(&self, path: impl AsRef<std::path::Path>) -> LuaResult<()> {
+        let path = path.as_ref();
+        let json = crate::snapshot::Snapshot::take(self)?.to_json()?;
+        std::fs::write(path, json)
+            .map_err(|e| LuaError::file(path.display().to_string(), e.to_string()))
+    }
+
+    /// Define the globals saved by `save_snapshot`, replacing globals of the
+    /// same name
+    pub fn load_snapshot(&mut self, path: impl AsRef<std::path::Path>) -> LuaResult<()> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| LuaError::file(path.display().to_string(), e.to_string()))?;
+        crate::snapshot::Snapshot::from_json(&json)?.restore(self)
+    }
+
+    /// Seed math.random so every run produces the same sequence
+    ///
+    /// Same as `math.randomseed(seed)` from a script.
+    pub fn set_random_seed(&self, seed: u64) {
+        *self.rng.borrow_mut() = Random::with_seed(seed);
+    }
+
+    /// A token that stops running scripts when cancelled, from any thread
+    ///
+    /// Cancelled scripts fail with `LuaError::Cancelled`; reset the token
+    /// before running the next one.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Install a host hook, replacing any hook a script installed
+    ///
+    /// `mask` selects call, return and line events; a nonzero `count` also
+    /// fires `HookEvent::Count` every `count` statements. An error returned
+    /// by `callback` aborts the script.
+    pub fn set_hook(
+        &self,
+        callback: impl Fn(HookEvent) -> crate::error_types::LuaResult<()> + 'static,
+        mask: HookMask,
+        count: u64,
+    ) {
+        let function = HookFunction::Host(Rc::new(callback));
+        *self.hook.borrow_mut() = Some(Hook::new(function, mask, count));
+    }
+
+    /// Remove the installed hook
+    pub fn clear_hook(&self) {
+        *self.hook.borrow_mut() = None;
+    }
+
+    /// The stack traceback from where `error` was raised
+    ///
+    /// Only the latest error raised by the tree-walking executor has one,
+    /// and it can be taken once.
+    pub fn error_traceback(&self, error: &crate::error_types::LuaError) -> Option<String> {
+        self.trace.borrow_mut().take_error_traceback(error)
+    }
+
+    /// Create a child interpreter for running a script in isolation
+    ///
+    /// The child starts with a copy of this interpreter's global bindings,
+    /// and of `_G`'s metatable, so globals it assigns or removes never reach
+    /// the parent. Values are copied by reference: tables such as `string`
+    /// are still shared, as are the module loader, the io streams, the
+    /// random generator, the cancellation token, the hook, the call stack,
+    /// the timers, the network switch and the host's input and output.
+    pub fn fork_env(&self) -> Self {
+        let globals = self.globals.borrow();
+        let metatable = globals.metatable.as_ref().map(|meta| {
+            let table = meta.table();
+            let table = table.borrow();
+            Box::new(Metatable::new(Rc::new(RefCell::new(LuaTable {
+                data: table.data.clone(),
+                metatable: table.metatable.clone(),
+                frozen: false,
+                removed: None,
+            }))))
+        });
+        self.with_globals(LuaTable {
+            data: globals.data.clone(),
+            metatable,
+            frozen: false,
+            removed: None,
+        })
+    }
+
+    /// Create a child interpreter that only sees the named globals
+    ///
+    /// Like `setfenv` on a fresh chunk: everything not listed (`os`, `io`,
+    /// `require`, ...) is simply absent. Unknown names are ignored.
+    pub fn sandbox(&self, allowed: &[&str]) -> Self {
+        let globals = self.globals.borrow();
+        let data = allowed
+            .iter()
+            .filter_map(|name| {
+                let value = globals.get_str(name)?.clone();
+                Some((LuaValue::String((*name).into()), value))
+            })
+            .collect();
+        self.with_globals(LuaTable {
+            data,
+            metatable: None,
+            frozen: false,
+            removed: None,
+        })
+    }
+
+    /// A child interpreter sharing this one's host plumbing, with `globals`;
+    /// a `_G` among them becomes the child's own globals table
+    fn with_globals(&self, globals: LuaTable) -> Self {
+        let globals = Rc::new(RefCell::new(globals));
+        if let Some(LuaValue::Table(table)) = globals
+            .borrow_mut()
+            .data
+            .get_mut(&LuaValue::String("_G".into()))
+        {
+            if Rc::ptr_eq(table, &self.globals) {
+                *table = Rc::clone(&globals);
+            }
+        }
+        LuaInterpreter {
+            globals,
+            scope_stack: Vec::new(),
+            scope_manager: ScopeManager::new(),
+            frames: Vec::new(),
+            call_stack: Vec::new(),
+            value_stack: ValueStack::new(),
+            gc: Rc::clone(&self.gc),
+            max_call_depth: self.max_call_depth,
+            module_loader: Rc::clone(&self.module_loader),
+            io_streams: Rc::clone(&self.io_streams),
+            output: self.output.clone(),
+            input: self.input.clone(),
+            rng: Rc::clone(&self.rng),
+            cancel: self.cancel.clone(),
+            hook: Rc::clone(&self.hook),
+            trace: Rc::clone(&self.trace),
+            coverage: Rc::clone(&self.coverage),
+            chunk_cache: Rc::clone(&self.chunk_cache),
+            code_cache: Rc::clone(&self.code_cache),
+            string_metatable: Rc::clone(&self.string_metatable),
+            timers: Rc::clone(&self.timers),
+            #[cfg(feature = "net")]
+            network: self.network.clone(),
+            strict_globals: self.strict_globals,
+            use_vm: self.use_vm,
+            debug_getlocal: Rc::clone(&self.debug_getlocal),
+            timer_sleep: Rc::clone(&self.timer_sleep),
+            pcall: Rc::clone(&self.pcall),
+            xpcall: Rc::clone(&self.xpcall),
+            coroutine_resume: Rc::clone(&self.coroutine_resume),
+            print: Rc::clone(&self.print),
+            tostring: Rc::clone(&self.tostring),
+        }
+    }
+
     /// Initialize standard library functions
     fn init_stdlib(&mut self) {
-        use crate::lua_value::LuaFunction;
         use crate::stdlib;
 
+        let globals = LuaValue::Table(Rc::clone(&self.globals));
+        self.set_global("_G", globals);
+
         // Global I/O functions
-        self.globals.insert(
-            "print".to_string(),
-            LuaValue::Function(Rc::new(LuaFunction::Builtin(stdlib::create_print()))),
-        );
+        self.set_global("print", LuaValue::Function(Rc::clone(&self.print)));
 
         // Global type functions
-        self.globals.insert(
-            "type".to_string(),
+        self.set_global(
+            "type",
             LuaValue::Function(Rc::new(LuaFunction::Builtin(stdlib::create_type()))),
         );
 
-        self.globals.insert(
-            "tonumber".to_string(),
+        self.set_global(
+            "tonumber",
             LuaValue::Function(Rc::new(LuaFunction::Builtin(stdlib::create_tonumber()))),
         );
 
-        self.globals.insert(
-            "tostring".to_string(),
-            LuaValue::Function(Rc::new(LuaFunction::Builtin(stdlib::create_tostring()))),
-        );
+        self.set_global("tostring", LuaValue::Function(Rc::clone(&self.tostring)));
 
         // Global iteration functions
-        self.globals.insert(
-            "pairs".to_string(),
+        self.set_global(
+            "pairs",
             LuaValue::Function(Rc::new(LuaFunction::Builtin(stdlib::create_pairs()))),
         );
 
-        self.globals.insert(
-            "ipairs".to_string(),
+        self.set_global(
+            "ipairs",
             LuaValue::Function(Rc::new(LuaFunction::Builtin(stdlib::create_ipairs()))),
         );
 
-        self.globals.insert(
-            "next".to_string(),
-            LuaValue::Function(Rc::new(LuaFunction::Builtin(stdlib::create_next()))),
+        self.set_global(
+            "next",
+            LuaValue::Function(Rc::new(LuaFunction::MultiBuiltin(stdlib::create_next()))),
         );
 
-        // String table
-        self.globals
-            .insert("string".to_string(), stdlib::create_string_table());
+        // Base library: assert, select, unpack and raw table access
+        for (name, function) in [
+            ("assert", LuaFunction::MultiBuiltin(stdlib::create_assert())),
+            ("select", LuaFunction::MultiBuiltin(stdlib::create_select())),
+            ("unpack", LuaFunction::MultiBuiltin(stdlib::create_unpack())),
+            ("rawget", LuaFunction::Builtin(stdlib::create_rawget())),
+            ("rawset", LuaFunction::Builtin(stdlib::create_rawset())),
+            ("rawequal", LuaFunction::Builtin(stdlib::create_rawequal())),
+            ("rawlen", LuaFunction::Builtin(stdlib::create_rawlen())),
+        ] {
+            self.set_global(name, LuaValue::Function(Rc::new(function)));
+        }
+
+        self.set_global(
+            "collectgarbage",
+            LuaValue::Function(Rc::new(LuaFunction::MultiBuiltin(
+                stdlib::create_collectgarbage(Rc::clone(&self.gc)),
+            ))),
+        );
+
+        // Debug table, sharing the interpreter's hook slot and call stack
+        self.set_global(
+            "debug",
+            stdlib::create_debug_table(
+                Rc::clone(&self.hook),
+                Rc::clone(&self.trace),
+                Rc::clone(&self.debug_getlocal),
+            ),
+        );
+
+        self.set_global(
+            "coverage",
+            stdlib::create_coverage_table(Rc::clone(&self.coverage)),
+        );
+
+        // Timer table, queueing callbacks in the interpreter's timers
+        self.set_global(
+            "timer",
+            stdlib::create_timer_table(Rc::clone(&self.timers), Rc::clone(&self.timer_sleep)),
+        );
+
+        // Net table, only with the net feature
+        #[cfg(feature = "net")]
+        self.set_global("net", crate::net::create_net_table(self.network.clone()));
+
+        // String table, also the __index of the string metatable
+        let string_table = stdlib::create_string_table();
+        self.string_metatable
+            .borrow_mut()
+            .data
+            .insert(LuaValue::String("__index".into()), string_table.clone());
+        self.set_global("string", string_table);
+
+        // UTF-8 table
+        self.set_global("utf8", stdlib::create_utf8_table());
 
         // Math table
-        self.globals
-            .insert("math".to_string(), stdlib::create_math_table());
+        self.set_global("math", stdlib::create_math_table(Rc::clone(&self.rng)));
 
         // Table table
-        self.globals
-            .insert("table".to_string(), stdlib::create_table_table());
+        self.set_global("table", stdlib::create_table_table());
 
         // I/O table
-        self.globals
-            .insert("io".to_string(), stdlib::create_io_table());
+        self.set_global("io", stdlib::create_io_table(Rc::clone(&self.io_streams)));
+
+        // JSON table
+        self.set_global("json", stdlib::create_json_table());
+
+        self.set_global(
+            "inspect",
+            LuaValue::Function(Rc::new(LuaFunction::Builtin(stdlib::create_inspect()))),
+        );
 
         // Phase 7: Metatables
-        self.globals.insert(
-            "setmetatable".to_string(),
+        self.set_global(
+            "setmetatable",
             LuaValue::Function(Rc::new(LuaFunction::Builtin(stdlib::create_setmetatable()))),
         );
 
-        self.globals.insert(
-            "getmetatable".to_string(),
-            LuaValue::Function(Rc::new(LuaFunction::Builtin(stdlib::create_getmetatable()))),
+        self.set_global(
+            "getmetatable",
+            LuaValue::Function(Rc::new(LuaFunction::Builtin(stdlib::create_getmetatable(
+                Rc::clone(&self.string_metatable),
+            )))),
         );
 
         // Phase 7: Error Handling
-        self.globals.insert(
-            "pcall".to_string(),
-            LuaValue::Function(Rc::new(LuaFunction::Builtin(stdlib::create_pcall()))),
-        );
-
-        self.globals.insert(
-            "xpcall".to_string(),
-            LuaValue::Function(Rc::new(LuaFunction::Builtin(stdlib::create_xpcall()))),
-        );
+        self.set_global("pcall", LuaValue::Function(Rc::clone(&self.pcall)));
+        self.set_global("xpcall", LuaValue::Function(Rc::clone(&self.xpcall)));
 
-        self.globals.insert(
-            "error".to_string(),
+        self.set_global(
+            "error",
             LuaValue::Function(Rc::new(LuaFunction::Builtin(stdlib::create_error()))),
         );
 
         // Phase 7: Coroutines
-        self.globals
-            .insert("coroutine".to_string(), stdlib::create_coroutine_table());
+        self.set_global(
+            "coroutine",
+            stdlib::create_coroutine_table(Rc::clone(&self.coroutine_resume)),
+        );
 
         // Phase 8: File I/O & System Integration
-        self.globals
-            .insert("os".to_string(), stdlib::create_os_table());
+        self.set_global("os", stdlib::create_os_table());
 
         // Phase 9: Module System
-        self.globals.insert(
-            "require".to_string(),
+        self.set_global(
+            "require",
             LuaValue::Function(Rc::new(LuaFunction::Builtin(stdlib::create_require(
                 Rc::clone(&self.module_loader),
             )))),
         );
+        let package = self.module_loader.borrow().package_value();
+        self.set_global("package", package);
+
+        // Scheme interop
+        self.set_global("scheme", crate::bridge::create_scheme_table());
     }
 
     /// Push a new scope for block statements or function calls
@@ -325,10 +738,13 @@ impl LuaInterpreter {
 
     /// Define or update a variable in the current scope
     pub fn define(&mut self, name: String, value: LuaValue) {
+        // A local declared outside any scope belongs to the chunk, which
+        // gets a scope of its own rather than writing into `_G`
+        if self.scope_stack.is_empty() {
+            self.push_scope();
+        }
         if let Some(scope) = self.scope_stack.last_mut() {
-            scope.insert(name, value);
-        } else {
-            self.globals.insert(name, value);
+            scope.insert(name, Rc::new(RefCell::new(value)));
         }
     }
 
@@ -336,102 +752,133 @@ impl LuaInterpreter {
     pub fn lookup(&self, name: &str) -> Option<LuaValue> {
         // Check scopes from innermost to outermost
         for scope in self.scope_stack.iter().rev() {
-            if let Some(value) = scope.get(name) {
-                return Some(value.clone());
+            if let Some(cell) = scope.get(name) {
+                return Some(cell.borrow().clone());
             }
         }
         // Check globals
-        self.globals.get(name).cloned()
+        self.globals.borrow().get_str(name).cloned()
+    }
+
+    /// Names of the locals in the interpreter's scopes, such as those the
+    /// earlier lines of a REPL declared
+    pub fn local_names(&self) -> Vec<String> {
+        self.scope_stack
+            .iter()
+            .flat_map(|scope| scope.keys().cloned())
+            .collect()
+    }
+
+    /// Bind an existing cell in the current scope, sharing it with whoever
+    /// else holds it (used to install a closure's upvalues)
+    pub fn define_cell(&mut self, name: String, cell: UpvalueCell) {
+        if self.scope_stack.is_empty() {
+            self.push_scope();
+        }
+        if let Some(scope) = self.scope_stack.last_mut() {
+            scope.insert(name, cell);
+        }
+    }
+
+    /// Find the cell backing a local variable. Globals have no cell.
+    pub fn lookup_cell(&self, name: &str) -> Option<UpvalueCell> {
+        self.scope_stack
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).cloned())
     }
 
     /// Update an existing variable, searching scopes from innermost to outermost, then globals
     pub fn update(&mut self, name: &str, value: LuaValue) -> Result<(), String> {
         // Check scopes from innermost to outermost
-        for scope in self.scope_stack.iter_mut().rev() {
-            if scope.contains_key(name) {
-                scope.insert(name.to_string(), value);
+        for scope in self.scope_stack.iter().rev() {
+            if let Some(cell) = scope.get(name) {
+                *cell.borrow_mut() = value;
                 return Ok(());
             }
         }
         // Check globals
-        if self.globals.contains_key(name) {
-            self.globals.insert(name.to_string(), value);
+        let mut globals = self.globals.borrow_mut();
+        if globals.get_str(name).is_some() {
+            if globals.frozen {
+                return Err("attempt to modify a frozen table".to_string());
+            }
+            globals.set_str(name, value);
             Ok(())
         } else {
             Err(format!("Undefined variable: {}", name))
         }
     }
 
-    /// Create a new empty table
-    pub fn create_table(&self) -> LuaValue {
-        LuaValue::Table(Rc::new(RefCell::new(LuaTable {
-            data: HashMap::new(),
-            metatable: None,
-        })))
+    fn frame(&self) -> &SlotFrame {
+        self.frames
+            .last()
+            .expect("resolved code only runs inside a frame")
     }
 
-    /// Get the current call depth (for debugging/recursion limits)
-    pub fn call_depth(&self) -> usize {
-        self.call_stack.len()
+    /// Cell backing a local slot of the running resolved function
+    pub fn slot_cell(&self, slot: usize) -> UpvalueCell {
+        self.frame().slots[slot].clone()
     }
 
-    /// Mark a table as reachable (for garbage collection)
-    pub fn mark_reachable_table(&mut self, table: &LuaValue) {
-        if let LuaValue::Table(t) = table {
-            self.reachable_objects.insert(t.as_ptr() as usize);
-        }
+    /// Read a local slot of the running resolved function
+    pub fn get_slot(&self, slot: usize) -> LuaValue {
+        self.frame().slots[slot].borrow().clone()
+    }
+
+    /// Write a local slot, shared with any closure that captured it
+    pub fn set_slot(&mut self, slot: usize, value: LuaValue) {
+        *self.frame().slots[slot].borrow_mut() = value;
     }
 
-    /// Mark all values in a scope as reachable
-    pub fn mark_scope_reachable(&mut self, scope: &HashMap<String, LuaValue>) {
-        for value in scope.values() {
-            if let LuaValue::Table(t) = value {
-                self.reachable_objects.insert(t.as_ptr() as usize);
+    /// Bind a slot to a fresh cell, as a new `local` declaration does.
+    /// A cell nothing else holds is reused, since no closure can tell.
+    pub fn declare_slot(&mut self, slot: usize, value: LuaValue) {
+        if let Some(frame) = self.frames.last_mut() {
+            let cell = &mut frame.slots[slot];
+            match Rc::get_mut(cell) {
+                Some(unshared) => *unshared.get_mut() = value,
+                None => *cell = Rc::new(RefCell::new(value)),
             }
         }
     }
 
-    /// Perform garbage collection (mark-and-sweep style)
-    /// This is a simplified GC that marks all currently reachable objects
-    pub fn collect_garbage(&mut self) {
-        self.reachable_objects.clear();
+    /// Cell of an upvalue of the running resolved function
+    pub fn upvalue_cell(&self, index: usize) -> UpvalueCell {
+        self.frame().upvalues[index].clone()
+    }
 
-        // Mark global values
-        for value in self.globals.values() {
-            if let LuaValue::Table(t) = value {
-                self.reachable_objects.insert(t.as_ptr() as usize);
-            }
-        }
+    /// Read an upvalue of the running resolved function
+    pub fn get_upvalue(&self, index: usize) -> LuaValue {
+        self.frame().upvalues[index].borrow().clone()
+    }
 
-        // Mark values in all scopes
-        for scope in &self.scope_stack {
-            for value in scope.values() {
-                if let LuaValue::Table(t) = value {
-                    self.reachable_objects.insert(t.as_ptr() as usize);
-                }
-            }
-        }
+    /// Write an upvalue of the running resolved function
+    pub fn set_upvalue(&mut self, index: usize, value: LuaValue) {
+        *self.frame().upvalues[index].borrow_mut() = value;
+    }
 
-        // Mark values in call frames
-        for frame in &self.call_stack {
-            for value in frame.locals.values() {
-                if let LuaValue::Table(t) = value {
-                    self.reachable_objects.insert(t.as_ptr() as usize);
-                }
-            }
-            for value in &frame.return_values {
-                if let LuaValue::Table(t) = value {
-                    self.reachable_objects.insert(t.as_ptr() as usize);
-                }
-            }
-        }
+    /// Create a new empty table, tracked by the cycle collector
+    pub fn create_table(&self) -> LuaValue {
+        let table = Rc::new(RefCell::new(LuaTable {
+            data: TableData::new(),
+            metatable: None,
+            frozen: false,
+            removed: None,
+        }));
+        self.gc.borrow_mut().track(&table);
+        LuaValue::Table(table)
+    }
 
-        // Mark values in value stack
-        for value in &self.value_stack.values {
-            if let LuaValue::Table(t) = value {
-                self.reachable_objects.insert(t.as_ptr() as usize);
-            }
-        }
+    /// Get the current call depth (for debugging/recursion limits)
+    pub fn call_depth(&self) -> usize {
+        self.call_stack.len()
+    }
+
+    /// Free tables that are only reachable from each other, returning how
+    /// many were freed
+    pub fn collect_garbage(&mut self) -> usize {
+        self.gc.borrow_mut().collect()
     }
 
     /// Get current memory usage estimate
@@ -439,12 +886,13 @@ impl LuaInterpreter {
         let mut size = std::mem::size_of::<Self>();
 
         // Approximate size of globals
-        size +=
-            self.globals.len() * (std::mem::size_of::<String>() + std::mem::size_of::<LuaValue>());
+        size += self.globals.borrow().data.len()
+            * (std::mem::size_of::<String>() + std::mem::size_of::<LuaValue>());
 
         // Approximate size of scopes
         for scope in &self.scope_stack {
-            size += scope.len() * (std::mem::size_of::<String>() + std::mem::size_of::<LuaValue>());
+            size +=
+                scope.len() * (std::mem::size_of::<String>() + std::mem::size_of::<UpvalueCell>());
         }
 
         // Size of call stack
@@ -463,6 +911,15 @@ impl Default for LuaInterpreter {
     }
 }
 
+/// `_G` is one of its own entries, so the globals would never be freed;
+/// they are emptied when the interpreter goes
+impl Drop for LuaInterpreter {
+    fn drop(&mut self) {
+        let data = std::mem::take(&mut self.globals.borrow_mut().data);
+        drop(data);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -474,15 +931,44 @@ mod tests {
         // Plus library tables: string, math, table, io
         // Phase 7 adds: setmetatable, getmetatable, pcall, xpcall, error, coroutine
         // Phase 8 adds: os
-        // Phase 9 adds: require
-        // Total: 7 functions + 4 tables + 5 functions + 1 table + 1 table + 1 function = 19 globals
-        assert_eq!(interp.globals.len(), 19);
+        // Phase 9 adds: require, package
+        // Plus the scheme and json tables, and inspect
+        // Base library: assert, select, unpack, rawget, rawset, rawequal, rawlen
+        // Plus the debug, utf8, coverage and timer tables, and _G
+        // Total: 7 functions + 4 tables + 5 functions + 1 table + 1 table + 1 function
+        //        + 1 table + 2 tables + 1 function + 7 functions + 5 tables
+        // And the net table with the net feature
+        assert_eq!(
+            interp.global_names().len(),
+            36 + cfg!(feature = "net") as usize
+        );
         assert!(interp.scope_stack.is_empty());
         assert!(interp.call_stack.is_empty());
         assert!(interp.value_stack.is_empty());
         assert_eq!(interp.max_call_depth, 1000);
     }
 
+    #[test]
+    fn test_script_args_use_negative_indices() {
+        let mut interp = LuaInterpreter::new();
+        let strings = |items: &[&str]| items.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        interp.set_script_args(
+            "main.lua",
+            &strings(&["muscm", "run", "--no-optimize"]),
+            &strings(&["a", "b"]),
+        );
+        let Some(LuaValue::Table(arg)) = interp.lookup("arg") else {
+            panic!("arg should be a table");
+        };
+        let arg = arg.borrow();
+        let expected = ["muscm", "run", "--no-optimize", "main.lua", "a", "b"];
+        for (i, value) in (-3..).zip(expected) {
+            assert_eq!(arg.get_int(i), LuaValue::String(value.into()), "arg[{}]", i);
+        }
+        assert_eq!(arg.get_int(-4), LuaValue::Nil);
+        assert_eq!(arg.border(), 2);
+    }
+
     #[test]
     fn test_global_variable_definition() {
         let mut interp = LuaInterpreter::new();
@@ -572,10 +1058,21 @@ mod tests {
         let table = interp.create_table();
 
         interp.define("my_table".to_string(), table.clone());
-        interp.collect_garbage();
+        let LuaValue::Table(cycle) = interp.create_table() else {
+            unreachable!()
+        };
+        cycle.borrow_mut().data.insert(
+            LuaValue::String("self".into()),
+            LuaValue::Table(cycle.clone()),
+        );
+        drop(cycle);
 
-        // Table should be marked as reachable
-        assert!(!interp.reachable_objects.is_empty());
+        // Only the table that refers to itself is freed
+        assert_eq!(interp.collect_garbage(), 1);
+        let LuaValue::Table(table) = table else {
+            unreachable!()
+        };
+        assert_eq!(Rc::strong_count(&table), 2);
     }
 
     #[test]
@@ -588,4 +1085,71 @@ mod tests {
 
         assert!(after_define > initial);
     }
+
+    fn run(interp: &mut LuaInterpreter, code: &str) -> Result<(), String> {
+        use crate::lua_parser::{parse, tokenize, TokenSlice};
+        let tokens = tokenize(code)?;
+        let (_, block) = parse(TokenSlice::from(tokens.as_slice())).map_err(|e| e.to_string())?;
+        crate::executor::Executor::new()
+            .execute_block(&block, interp)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_fork_env_keeps_globals_out_of_parent() {
+        let mut parent = LuaInterpreter::new();
+        run(&mut parent, "shared = 1").unwrap();
+
+        let mut first = parent.fork_env();
+        run(&mut first, "leaked = shared + 1 shared = 10 print = nil").unwrap();
+        assert_eq!(first.lookup("leaked"), Some(LuaValue::Number(2.0)));
+
+        let mut second = parent.fork_env();
+        run(&mut second, "copy = shared").unwrap();
+        assert_eq!(second.lookup("copy"), Some(LuaValue::Number(1.0)));
+        assert_eq!(second.lookup("leaked"), None);
+
+        assert_eq!(parent.lookup("leaked"), None);
+        assert_eq!(parent.lookup("shared"), Some(LuaValue::Number(1.0)));
+        assert!(parent.lookup("print").is_some());
+
+        // `_G` in the child is the child's own globals table
+        run(&mut first, "_G.through_g = 1").unwrap();
+        assert_eq!(first.get_global("through_g"), LuaValue::Number(1.0));
+        assert_eq!(parent.get_global("through_g"), LuaValue::Nil);
+    }
+
+    #[test]
+    fn test_sandbox_exposes_only_allowed_globals() {
+        let parent = LuaInterpreter::new();
+        let mut sandbox = parent.sandbox(&["string", "tostring", "missing"]);
+
+        assert_eq!(sandbox.global_names(), vec!["string", "tostring"]);
+        run(&mut sandbox, "result = string.upper(tostring(1))").unwrap();
+        assert_eq!(sandbox.lookup("result"), Some(LuaValue::String("1".into())));
+        assert!(run(&mut sandbox, "os.remove('x')").is_err());
+        assert!(run(&mut sandbox, "require('simple')").is_err());
+    }
+
+    #[test]
+    fn test_random_seed_makes_runs_reproducible() {
+        let draws = |interp: &mut LuaInterpreter| {
+            run(interp, "a, b = math.random(1000), math.random()").unwrap();
+            (interp.lookup("a"), interp.lookup("b"))
+        };
+
+        let mut first = LuaInterpreter::new();
+        first.set_random_seed(7);
+        let mut second = LuaInterpreter::new();
+        second.set_random_seed(7);
+        assert_eq!(draws(&mut first), draws(&mut second));
+
+        // The host seed and math.randomseed agree
+        let mut seeded = LuaInterpreter::new();
+        run(&mut seeded, "math.randomseed(7)").unwrap();
+        let mut hosted = LuaInterpreter::new();
+        hosted.set_random_seed(7);
+        assert_eq!(draws(&mut seeded), draws(&mut hosted));
+    }
 }
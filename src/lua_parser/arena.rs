@@ -0,0 +1,187 @@
+//! The arena a parsed Lua chunk lives in
+//!
+//! Like the Scheme side's `ast::Arena`, blocks and function bodies are
+//! stored once in a `LuaArena` and referred to by index. A statement holds
+//! the `BlockId` of each block it runs and a function definition the
+//! `FunctionId` of its body, so copying either copies an index, and a
+//! closure is the arena it was parsed into plus the id of its body.
+//!
+//! The parser's functions return plain values, so the chunk being parsed
+//! collects its blocks and bodies in an arena kept on this thread.
+
+use super::types::{Block, FunctionBody};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::ops::Deref;
+use std::rc::Rc;
+
+pub type BlockId = usize;
+pub type FunctionId = usize;
+
+/// Every block and function body of a chunk
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LuaArena {
+    blocks: Vec<Block>,
+    functions: Vec<FunctionBody>,
+    /// Name of the chunk as Lua writes it, `@path` for a file or `=name`
+    /// otherwise; `debug.getinfo` reports it for the chunk's functions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source: Option<Rc<str>>,
+}
+
+impl LuaArena {
+    pub fn new() -> Self {
+        LuaArena::default()
+    }
+
+    pub fn alloc_block(&mut self, block: Block) -> BlockId {
+        let id = self.blocks.len();
+        self.blocks.push(block);
+        id
+    }
+
+    pub fn alloc_function(&mut self, body: FunctionBody) -> FunctionId {
+        let id = self.functions.len();
+        self.functions.push(body);
+        id
+    }
+
+    pub fn source(&self) -> Option<&Rc<str>> {
+        self.source.as_ref()
+    }
+
+    /// The block `id`; ids come from this arena, so one past its end is a bug
+    pub fn block(&self, id: BlockId) -> &Block {
+        &self.blocks[id]
+    }
+
+    pub fn block_mut(&mut self, id: BlockId) -> &mut Block {
+        &mut self.blocks[id]
+    }
+
+    /// The function body `id`, like `block`
+    pub fn function(&self, id: FunctionId) -> &FunctionBody {
+        &self.functions[id]
+    }
+
+    pub fn function_mut(&mut self, id: FunctionId) -> &mut FunctionBody {
+        &mut self.functions[id]
+    }
+
+    /// How many function bodies there are; their ids run up to this
+    pub fn function_count(&self) -> usize {
+        self.functions.len()
+    }
+}
+
+/// A parsed chunk: the arena it was parsed into and its top-level block
+///
+/// Cloning a chunk shares the arena. It dereferences to the top-level
+/// block, whose nested blocks and functions are found in `arena`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Chunk {
+    pub arena: Rc<LuaArena>,
+    pub root: BlockId,
+}
+
+impl Chunk {
+    /// The chunk whose top-level block is `root` in `arena`
+    pub fn new(arena: LuaArena, root: BlockId) -> Self {
+        Chunk {
+            arena: Rc::new(arena),
+            root,
+        }
+    }
+
+    /// The chunk under the name `source` (see `LuaArena::source`); its
+    /// arena is copied first if other chunks share it
+    pub fn with_source(mut self, source: &str) -> Self {
+        Rc::make_mut(&mut self.arena).source = Some(source.into());
+        self
+    }
+
+    pub fn block(&self, id: BlockId) -> &Block {
+        self.arena.block(id)
+    }
+
+    pub fn function(&self, id: FunctionId) -> &FunctionBody {
+        self.arena.function(id)
+    }
+
+    /// The function body `id` together with the arena it is in
+    pub fn function_ref(&self, id: FunctionId) -> FunctionRef {
+        FunctionRef {
+            arena: Rc::clone(&self.arena),
+            id,
+        }
+    }
+}
+
+impl Deref for Chunk {
+    type Target = Block;
+
+    fn deref(&self) -> &Block {
+        self.arena.block(self.root)
+    }
+}
+
+/// A function body and the arena it is in: what every closure made from
+/// the definition holds
+///
+/// It dereferences to the body; the body's blocks are found in `arena`.
+#[derive(Debug, Clone)]
+pub struct FunctionRef {
+    pub arena: Rc<LuaArena>,
+    pub id: FunctionId,
+}
+
+impl FunctionRef {
+    /// The block the function runs
+    pub fn block(&self) -> &Block {
+        self.arena.block(self.block)
+    }
+
+    /// Tells definitions apart, for keying maps by the definition a
+    /// closure was made from: the arena's address and the body's id
+    pub fn key(&self) -> (usize, FunctionId) {
+        (Rc::as_ptr(&self.arena) as usize, self.id)
+    }
+}
+
+impl Deref for FunctionRef {
+    type Target = FunctionBody;
+
+    fn deref(&self) -> &FunctionBody {
+        self.arena.function(self.id)
+    }
+}
+
+/// The same definition: the same body in the same arena
+impl PartialEq for FunctionRef {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.arena, &other.arena) && self.id == other.id
+    }
+}
+
+thread_local! {
+    /// Where the running parse allocates its blocks and bodies
+    static BUILDING: RefCell<LuaArena> = RefCell::new(LuaArena::new());
+}
+
+/// Store `block` in the arena of the running parse
+pub(super) fn alloc_block(block: Block) -> BlockId {
+    BUILDING.with_borrow_mut(|arena| arena.alloc_block(block))
+}
+
+/// Store `body` in the arena of the running parse
+pub(super) fn alloc_function(body: FunctionBody) -> FunctionId {
+    BUILDING.with_borrow_mut(|arena| arena.alloc_function(body))
+}
+
+/// Run the parse `f` with a fresh arena, returning its result and what it
+/// allocated, and restoring the arena of any parse it interrupted
+pub(super) fn building<R>(f: impl FnOnce() -> R) -> (R, LuaArena) {
+    let outer = BUILDING.take();
+    let result = f();
+    (result, BUILDING.replace(outer))
+}
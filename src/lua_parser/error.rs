@@ -0,0 +1,159 @@
+//! Parse errors that say what the parser expected
+//!
+//! Every parser fails with a `ParseError` holding the tokens it stopped at
+//! and what could have come there. When alternatives all fail, the error
+//! of the one that got furthest wins, and alternatives that stopped at the
+//! same token pool what they expected, so the error names the token the
+//! chunk really breaks at rather than the start of its statement.
+
+use super::{Token, TokenSlice};
+use nom::error::ErrorKind;
+use nom::{IResult, Input};
+use std::fmt;
+
+/// Result of the token parsers
+pub type PResult<'a, O> = IResult<TokenSlice<'a>, O, ParseError<'a>>;
+
+/// Something a parser could have accepted where it failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expected {
+    Token(Token),
+    Name,
+    Expression,
+    Statement,
+    /// `(args)`, a table constructor or a string after a method name
+    Arguments,
+    EndOfInput,
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::Token(token) => write!(f, "`{}`", token),
+            Expected::Name => write!(f, "a name"),
+            Expected::Expression => write!(f, "an expression"),
+            Expected::Statement => write!(f, "a statement"),
+            Expected::Arguments => write!(f, "function arguments"),
+            Expected::EndOfInput => write!(f, "end of input"),
+        }
+    }
+}
+
+/// Where a token parser failed and what it expected there
+#[derive(Debug, Clone)]
+pub struct ParseError<'a> {
+    /// The tokens from the one that could not be parsed on
+    pub input: TokenSlice<'a>,
+    /// In the order the alternatives were tried; empty when the token was
+    /// wrong in itself, such as a malformed number
+    pub expected: Vec<Expected>,
+    /// The token would have started a level of nesting past the limit
+    pub too_deep: bool,
+}
+
+impl<'a> ParseError<'a> {
+    pub fn new(input: TokenSlice<'a>, expected: Vec<Expected>) -> Self {
+        ParseError {
+            input,
+            expected,
+            too_deep: false,
+        }
+    }
+
+    pub fn too_deep(input: TokenSlice<'a>) -> Self {
+        ParseError {
+            too_deep: true,
+            ..ParseError::new(input, Vec::new())
+        }
+    }
+
+    /// Whether this failure got past the first token of `start`
+    fn is_past(&self, start: &TokenSlice<'a>) -> bool {
+        self.input.input_len() < start.input_len()
+    }
+}
+
+impl<'a> nom::error::ParseError<TokenSlice<'a>> for ParseError<'a> {
+    fn from_error_kind(input: TokenSlice<'a>, _kind: ErrorKind) -> Self {
+        ParseError::new(input, Vec::new())
+    }
+
+    fn append(_input: TokenSlice<'a>, _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    /// Keep the failure that got further, or pool both at the same token
+    fn or(mut self, other: Self) -> Self {
+        if other.input.input_len() < self.input.input_len() {
+            return other;
+        }
+        if other.input.input_len() == self.input.input_len() {
+            for expected in other.expected {
+                if !self.expected.contains(&expected) {
+                    self.expected.push(expected);
+                }
+            }
+        }
+        self
+    }
+}
+
+/// Fail at the front of `input`, expecting any of `expected`
+pub fn fail<'a, O>(input: TokenSlice<'a>, expected: Vec<Expected>) -> PResult<'a, O> {
+    Err(nom::Err::Error(ParseError::new(input, expected)))
+}
+
+/// Run `parser` on `t`, reporting a failure at its very first token as
+/// expecting `what` instead of the alternatives it tried
+pub fn expecting<'a, O>(
+    what: Expected,
+    t: TokenSlice<'a>,
+    parser: impl FnOnce(TokenSlice<'a>) -> PResult<'a, O>,
+) -> PResult<'a, O> {
+    parser(t).map_err(|err| match err {
+        nom::Err::Error(e) if !e.is_past(&t) => {
+            nom::Err::Error(ParseError::new(e.input, vec![what]))
+        }
+        err => err,
+    })
+}
+
+/// A chunk that failed to parse: the token it broke at and what could
+/// have come there
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    /// Index of the offending token; `None` when the input ended too early
+    pub index: Option<usize>,
+    pub found: Option<Token>,
+    pub expected: Vec<Expected>,
+    /// The chunk nests deeper than `ParseOptions::max_depth`
+    pub too_deep: bool,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.too_deep {
+            return write!(f, "chunk has too many syntax levels");
+        }
+        match &self.found {
+            Some(token) => write!(f, "unexpected `{}`", token)?,
+            None => write!(f, "unexpected end of input")?,
+        }
+        match self.expected.as_slice() {
+            [] => Ok(()),
+            [one] => write!(f, ", expected {}", one),
+            several => {
+                write!(f, ", expected one of: ")?;
+                for (i, expected) in several.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", expected)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for SyntaxError {}
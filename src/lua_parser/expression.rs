@@ -1,92 +1,86 @@
 //! Expression parsing - binary ops, unary ops, literals, prefix expressions
 
-use nom::{
-    branch::alt,
-    combinator::{map, opt},
-    multi::many0,
-    sequence::pair,
-    IResult, Parser,
-};
+use nom::{branch::alt, combinator::map, sequence::pair, Parser};
 
+use super::arena;
+use super::error::{expecting, fail, Expected, PResult, ParseError};
+use super::nesting::Level;
 use super::{
-    Token, TokenSlice, Expression, BinaryOp, UnaryOp, Field, FieldKey, FunctionBody,
-    token_tag,
+    token_tag, BinaryOp, Expression, Field, FieldKey, FunctionBody, Numeral, Token, TokenSlice,
+    UnaryOp,
 };
+use crate::stack::with_headroom;
 
 /// Parse number literal from token
-pub fn parse_number_literal(input: TokenSlice) -> IResult<TokenSlice, Expression> {
+pub fn parse_number_literal(input: TokenSlice) -> PResult<Expression> {
     if let Some(Token::Number(n)) = input.0.first() {
-        Ok((TokenSlice(&input.0[1..]), Expression::Number(n.clone())))
+        match Numeral::parse(n) {
+            Some(n) => Ok((input.advance(1), Expression::Number(n))),
+            // Not backtracked over, so the error names this token
+            None => Err(nom::Err::Failure(ParseError::new(input, Vec::new()))),
+        }
     } else {
-        Err(nom::Err::Error(nom::error::Error::new(
-            input,
-            nom::error::ErrorKind::Tag,
-        )))
+        fail(input, vec![Expected::Expression])
     }
 }
 
 /// Parse string literal from token
-pub fn parse_string_literal(input: TokenSlice) -> IResult<TokenSlice, Expression> {
+pub fn parse_string_literal(input: TokenSlice) -> PResult<Expression> {
     if let Some(Token::StringLit(s)) = input.0.first() {
-        Ok((TokenSlice(&input.0[1..]), Expression::String(s.clone())))
+        Ok((input.advance(1), Expression::String(s.clone())))
     } else {
-        Err(nom::Err::Error(nom::error::Error::new(
-            input,
-            nom::error::ErrorKind::Tag,
-        )))
+        fail(input, vec![Expected::Expression])
     }
 }
 
 /// Parse identifier
-pub fn parse_identifier(t: TokenSlice) -> IResult<TokenSlice, Expression> {
+pub fn parse_identifier(t: TokenSlice) -> PResult<Expression> {
     if let Some(Token::Identifier(id)) = t.0.first() {
-        Ok((TokenSlice(&t.0[1..]), Expression::Identifier(id.clone())))
+        Ok((t.advance(1), Expression::Identifier(id.clone())))
     } else {
-        Err(nom::Err::Error(nom::error::Error::new(
-            t,
-            nom::error::ErrorKind::Tag,
-        )))
+        fail(t, vec![Expected::Name])
     }
 }
 
 /// Parse table constructor: `{ [fieldlist] }`
-pub fn parse_table_constructor(t: TokenSlice) -> IResult<TokenSlice, Expression> {
+pub fn parse_table_constructor(t: TokenSlice) -> PResult<Expression> {
     let (rest, _) = token_tag(&Token::LBrace)(t)?;
-    let (rest, fields) = opt(|i| parse_fieldlist(i)).parse(rest)?;
+    let (rest, fields) = match rest.0.first() {
+        Some(Token::RBrace) => (rest, Vec::new()),
+        _ => parse_fieldlist(rest)?,
+    };
     let (rest, _) = token_tag(&Token::RBrace)(rest)?;
-    Ok((
-        rest,
-        Expression::TableConstructor {
-            fields: fields.unwrap_or_default(),
-        },
-    ))
+    Ok((rest, Expression::TableConstructor { fields }))
 }
 
 /// Parse field list: `field {fieldsep field} [fieldsep]`
-fn parse_fieldlist(t: TokenSlice) -> IResult<TokenSlice, Vec<Field>> {
-    let (rest, first_field) = parse_field(t)?;
-    let (rest, rest_fields) = many0(|input| {
-        let (rest, _) = parse_fieldsep(input)?;
-        // Check for trailing separator (fieldsep followed by })
+fn parse_fieldlist(t: TokenSlice) -> PResult<Vec<Field>> {
+    let (mut rest, first_field) = parse_field(t)?;
+    let mut result = vec![first_field];
+    while let Ok((r, _)) = parse_fieldsep(rest) {
+        rest = r;
+        // A trailing separator is allowed before the closing brace
         if rest.0.first() == Some(&Token::RBrace) {
-            return Ok((rest, None));
+            break;
         }
-        let (rest, field) = parse_field(rest)?;
-        Ok((rest, Some(field)))
-    })
-    .parse(rest)?;
+        let (r, field) = parse_field(rest)?;
+        result.push(field);
+        rest = r;
+    }
 
-    let mut result = vec![first_field];
-    for field_opt in rest_fields {
-        if let Some(field) = field_opt {
-            result.push(field);
+    // Positional fields are numbered in source order, skipping keyed ones
+    let mut position = 0;
+    for field in &mut result {
+        if let FieldKey::Index(index) = &mut field.key {
+            position += 1;
+            *index = position;
         }
     }
     Ok((rest, result))
 }
 
 /// Parse a single field: `[exp] = exp | name = exp | exp`
-fn parse_field(t: TokenSlice) -> IResult<TokenSlice, Field> {
+fn parse_field(t: TokenSlice) -> PResult<Field> {
     // Try [exp] = exp
     if let Ok((rest, _)) = token_tag(&Token::LBracket)(t) {
         let (rest, key_expr) = parse_expression(rest)?;
@@ -105,7 +99,7 @@ fn parse_field(t: TokenSlice) -> IResult<TokenSlice, Field> {
     // Try name = exp
     if let Some(Token::Identifier(name)) = t.0.first() {
         let name = name.clone();
-        let rest = TokenSlice(&t.0[1..]);
+        let rest = t.advance(1);
         if let Ok((rest, _)) = token_tag(&Token::Equals)(rest) {
             let (rest, value) = parse_expression(rest)?;
             return Ok((
@@ -123,14 +117,14 @@ fn parse_field(t: TokenSlice) -> IResult<TokenSlice, Field> {
     Ok((
         rest,
         Field {
-            key: FieldKey::Index(0), // placeholder
+            key: FieldKey::Index(0), // numbered by parse_fieldlist
             value: expr,
         },
     ))
 }
 
 /// Parse field separator: `,` or `;`
-fn parse_fieldsep(t: TokenSlice) -> IResult<TokenSlice, ()> {
+fn parse_fieldsep(t: TokenSlice) -> PResult<()> {
     alt((
         map(token_tag(&Token::Comma), |_| ()),
         map(token_tag(&Token::Semicolon), |_| ()),
@@ -139,109 +133,82 @@ fn parse_fieldsep(t: TokenSlice) -> IResult<TokenSlice, ()> {
 }
 
 /// Parse function definition: `function funcbody`
-pub fn parse_function_def(t: TokenSlice) -> IResult<TokenSlice, Expression> {
+pub fn parse_function_def(t: TokenSlice) -> PResult<Expression> {
     let (rest, _) = token_tag(&Token::Function)(t)?;
     let (rest, body) = parse_funcbody(rest)?;
-    Ok((rest, Expression::FunctionDef(Box::new(body))))
+    Ok((rest, Expression::FunctionDef(arena::alloc_function(body))))
 }
 
 /// Parse function body: `( [parlist] ) block end`
-pub fn parse_funcbody(t: TokenSlice) -> IResult<TokenSlice, FunctionBody> {
+pub fn parse_funcbody(t: TokenSlice) -> PResult<FunctionBody> {
     let (rest, _) = token_tag(&Token::LParen)(t)?;
-    let (rest, params_info) = opt(|i| parse_parlist(i)).parse(rest)?;
+    let (rest, (params, varargs)) = match rest.0.first() {
+        Some(Token::RParen) => (rest, (Vec::new(), false)),
+        _ => parse_parlist(rest)?,
+    };
     let (rest, _) = token_tag(&Token::RParen)(rest)?;
-    let (rest, block) = super::statement::parse_block(rest)?;
+    let (rest, block) = super::statement::parse_nested_block(rest)?;
+    let last_line = rest.line();
     let (rest, _) = token_tag(&Token::End)(rest)?;
 
-    let (params, varargs) = params_info.unwrap_or_default();
     Ok((
         rest,
         FunctionBody {
             params,
             varargs,
-            block: Box::new(block),
+            block,
+            layout: None,
+            lines: (!t.1.is_empty()).then_some((t.line(), last_line)),
         },
     ))
 }
 
 /// Parse parameter list: `namelist [',' '...'] | '...'`
-fn parse_parlist(t: TokenSlice) -> IResult<TokenSlice, (Vec<String>, bool)> {
-    alt((
-        // Just varargs
-        map(token_tag(&Token::Varargs), |_| (vec![], true)),
-        // Namelist with optional varargs
-        |input| {
-            let (rest, names) = parse_namelist(input)?;
-            let (rest, has_varargs) = opt(|i| {
-                let (r, _) = token_tag(&Token::Comma)(i)?;
-                token_tag(&Token::Varargs)(r)
-            })
-            .parse(rest)?;
-            Ok((rest, (names, has_varargs.is_some())))
-        },
-    ))
-    .parse(t)
-}
-
-/// Parse name list: `name {',' name}`
-fn parse_namelist(t: TokenSlice) -> IResult<TokenSlice, Vec<String>> {
-    let (rest, first_name) = if let Some(Token::Identifier(name)) = t.0.first() {
-        (TokenSlice(&t.0[1..]), name.clone())
-    } else {
-        return Err(nom::Err::Error(nom::error::Error::new(
-            t,
-            nom::error::ErrorKind::Tag,
-        )));
-    };
-
-    let (rest, rest_names) = many0(|input| {
-        let (r, _) = token_tag(&Token::Comma)(input)?;
-        if let Some(Token::Identifier(name)) = r.0.first() {
-            Ok((TokenSlice(&r.0[1..]), name.clone()))
-        } else {
-            Err(nom::Err::Error(nom::error::Error::new(
-                r,
-                nom::error::ErrorKind::Tag,
-            )))
+fn parse_parlist(t: TokenSlice) -> PResult<(Vec<String>, bool)> {
+    let mut names = Vec::new();
+    let mut rest = t;
+    loop {
+        match rest.0.first() {
+            Some(Token::Identifier(name)) => names.push(name.to_string()),
+            Some(Token::Varargs) => return Ok((rest.advance(1), (names, true))),
+            _ => return fail(rest, vec![Expected::Name, Expected::Token(Token::Varargs)]),
         }
-    })
-    .parse(rest)?;
-
-    let mut result = vec![first_name];
-    result.extend(rest_names);
-    Ok((rest, result))
+        match token_tag(&Token::Comma)(rest.advance(1)) {
+            Ok((r, _)) => rest = r,
+            Err(_) => return Ok((rest.advance(1), (names, false))),
+        }
+    }
 }
 
 /// Parse arguments for a function call: `(explist) | tableconstructor | string_literal`
-pub fn parse_args(t: TokenSlice) -> IResult<TokenSlice, Vec<Expression>> {
-    // Try parenthesized argument list first
-    if let Ok((rest, _)) = token_tag(&Token::LParen)(t) {
-        let (rest, exprs) = opt(|i| parse_expression_list(i)).parse(rest)?;
-        let (rest, _) = token_tag(&Token::RParen)(rest)?;
-        return Ok((rest, exprs.unwrap_or_default()));
-    }
-
-    // Try table constructor
-    if let Ok((rest, expr)) = parse_table_constructor(t) {
-        return Ok((rest, vec![expr]));
-    }
-
-    // Try string literal
-    if let Ok((rest, expr)) = parse_string_literal(t) {
-        return Ok((rest, vec![expr]));
+pub fn parse_args(t: TokenSlice) -> PResult<Vec<Expression>> {
+    match t.0.first() {
+        Some(Token::LParen) => {
+            let rest = t.advance(1);
+            let (rest, exprs) = match rest.0.first() {
+                Some(Token::RParen) => (rest, Vec::new()),
+                _ => parse_expression_list(rest)?,
+            };
+            let (rest, _) = token_tag(&Token::RParen)(rest)?;
+            Ok((rest, exprs))
+        }
+        Some(Token::LBrace) => {
+            let (rest, expr) = parse_table_constructor(t)?;
+            Ok((rest, vec![expr]))
+        }
+        Some(Token::StringLit(_)) => {
+            let (rest, expr) = parse_string_literal(t)?;
+            Ok((rest, vec![expr]))
+        }
+        _ => fail(t, vec![Expected::Arguments]),
     }
-
-    Err(nom::Err::Error(nom::error::Error::new(
-        t,
-        nom::error::ErrorKind::Alt,
-    )))
 }
 
 /// Parse a primary/prefix expression, then apply suffix operations (indexing, calls, method calls)
-pub fn parse_prefix_exp(t: TokenSlice) -> IResult<TokenSlice, Expression> {
+pub fn parse_prefix_exp(t: TokenSlice) -> PResult<Expression> {
     let (mut rest, mut expr) = {
         // Try simple literals first
-        if let Ok((r, expr)) = alt((
+        let literal = alt((
             map(token_tag(&Token::Nil), |_| Expression::Nil),
             map(token_tag(&Token::True), |_| Expression::Boolean(true)),
             map(token_tag(&Token::False), |_| Expression::Boolean(false)),
@@ -249,15 +216,22 @@ pub fn parse_prefix_exp(t: TokenSlice) -> IResult<TokenSlice, Expression> {
             parse_number_literal,
             parse_string_literal,
         ))
-        .parse(t)
-        {
+        .parse(t);
+        if let Ok((r, expr)) = literal {
             (r, expr)
+        } else if let Err(nom::Err::Failure(e)) = literal {
+            return Err(nom::Err::Failure(e));
         } else if let Some(Token::LParen) = t.0.first() {
             // Parenthesized expression: ( exp )
             let (r, _) = token_tag(&Token::LParen)(t)?;
             let (r, expr) = parse_expression(r)?;
             let (r, _) = token_tag(&Token::RParen)(r)?;
-            (r, expr)
+            match expr {
+                Expression::FunctionCall { .. }
+                | Expression::MethodCall { .. }
+                | Expression::Varargs => (r, Expression::Paren(Box::new(expr))),
+                _ => (r, expr),
+            }
         } else if let Some(Token::Function) = t.0.first() {
             // Function definition: function funcbody
             parse_function_def(t)?
@@ -268,10 +242,7 @@ pub fn parse_prefix_exp(t: TokenSlice) -> IResult<TokenSlice, Expression> {
             // Identifier
             parse_identifier(t)?
         } else {
-            return Err(nom::Err::Error(nom::error::Error::new(
-                t,
-                nom::error::ErrorKind::Alt,
-            )));
+            return fail(t, vec![Expected::Expression]);
         }
     };
 
@@ -279,7 +250,7 @@ pub fn parse_prefix_exp(t: TokenSlice) -> IResult<TokenSlice, Expression> {
     loop {
         if let Some(Token::LBracket) = rest.0.first() {
             // Table indexing: [exp]
-            let r = TokenSlice(&rest.0[1..]);
+            let r = rest.advance(1);
             let (r, index) = parse_expression(r)?;
             let (r, _) = token_tag(&Token::RBracket)(r)?;
             expr = Expression::TableIndexing {
@@ -289,27 +260,24 @@ pub fn parse_prefix_exp(t: TokenSlice) -> IResult<TokenSlice, Expression> {
             rest = r;
         } else if let Some(Token::Dot) = rest.0.first() {
             // Field access: .name
-            let r = TokenSlice(&rest.0[1..]);
+            let r = rest.advance(1);
             if let Some(Token::Identifier(field)) = r.0.first() {
                 let field = field.clone();
-                let r = TokenSlice(&r.0[1..]);
+                let r = r.advance(1);
                 expr = Expression::FieldAccess {
                     object: Box::new(expr),
                     field,
                 };
                 rest = r;
             } else {
-                return Err(nom::Err::Error(nom::error::Error::new(
-                    r,
-                    nom::error::ErrorKind::Tag,
-                )));
+                return fail(r, vec![Expected::Name]);
             }
         } else if let Some(Token::Colon) = rest.0.first() {
             // Method call: :name args
-            let r = TokenSlice(&rest.0[1..]);
+            let r = rest.advance(1);
             if let Some(Token::Identifier(method)) = r.0.first() {
                 let method = method.clone();
-                let r = TokenSlice(&r.0[1..]);
+                let r = r.advance(1);
                 let (r, args) = parse_args(r)?;
                 expr = Expression::MethodCall {
                     object: Box::new(expr),
@@ -318,10 +286,7 @@ pub fn parse_prefix_exp(t: TokenSlice) -> IResult<TokenSlice, Expression> {
                 };
                 rest = r;
             } else {
-                return Err(nom::Err::Error(nom::error::Error::new(
-                    r,
-                    nom::error::ErrorKind::Tag,
-                )));
+                return fail(r, vec![Expected::Name]);
             }
         } else if matches!(
             rest.0.first(),
@@ -343,7 +308,7 @@ pub fn parse_prefix_exp(t: TokenSlice) -> IResult<TokenSlice, Expression> {
 }
 
 /// Parse unary operators: - | not | # | ~
-fn parse_unary_op(t: TokenSlice) -> IResult<TokenSlice, UnaryOp> {
+fn parse_unary_op(t: TokenSlice) -> PResult<UnaryOp> {
     alt((
         map(token_tag(&Token::Minus), |_| UnaryOp::Minus),
         map(token_tag(&Token::Not), |_| UnaryOp::Not),
@@ -354,215 +319,102 @@ fn parse_unary_op(t: TokenSlice) -> IResult<TokenSlice, UnaryOp> {
 }
 
 /// Parse a unary expression
-fn parse_unary_expr(t: TokenSlice) -> IResult<TokenSlice, Expression> {
-    alt((
-        map(pair(parse_unary_op, parse_unary_expr), |(op, operand)| {
-            Expression::UnaryOp {
-                op,
-                operand: Box::new(operand),
-            }
-        }),
-        parse_prefix_exp,
-    ))
-    .parse(t)
-}
-
-/// Parse expression with binary operators
-/// Lua operator precedence (lowest to highest):
-/// or, and, <, >, <=, >=, ~=, ==, |, ~, &, <<, >>, .., +, -, *, /, //, %, ^, unary
-fn parse_or_expr(t: TokenSlice) -> IResult<TokenSlice, Expression> {
-    let (rest, mut left) = parse_and_expr(t)?;
-    let (rest, ops) = many0(pair(
-        |i| token_tag(&Token::Or)(i).map(|(r, _)| (r, BinaryOp::Or)),
-        parse_and_expr,
-    ))
-    .parse(rest)?;
-    for (op, right) in ops {
-        left = Expression::BinaryOp {
-            left: Box::new(left),
-            op,
-            right: Box::new(right),
-        };
-    }
-    Ok((rest, left))
-}
-
-fn parse_and_expr(t: TokenSlice) -> IResult<TokenSlice, Expression> {
-    let (rest, mut left) = parse_eq_expr(t)?;
-    let (rest, ops) = many0(pair(
-        |i| token_tag(&Token::And)(i).map(|(r, _)| (r, BinaryOp::And)),
-        parse_eq_expr,
-    ))
-    .parse(rest)?;
-    for (op, right) in ops {
-        left = Expression::BinaryOp {
-            left: Box::new(left),
-            op,
-            right: Box::new(right),
-        };
-    }
-    Ok((rest, left))
-}
-
-fn parse_eq_expr(t: TokenSlice) -> IResult<TokenSlice, Expression> {
-    let (rest, mut left) = parse_relational_expr(t)?;
-    let (rest, ops) = many0(pair(parse_eq_op, parse_relational_expr)).parse(rest)?;
-    for (op, right) in ops {
-        left = Expression::BinaryOp {
-            left: Box::new(left),
-            op,
-            right: Box::new(right),
-        };
-    }
-    Ok((rest, left))
-}
-
-fn parse_eq_op(t: TokenSlice) -> IResult<TokenSlice, BinaryOp> {
-    alt((
-        map(token_tag(&Token::Eq), |_| BinaryOp::Eq),
-        map(token_tag(&Token::Neq), |_| BinaryOp::Neq),
-    ))
-    .parse(t)
-}
-
-fn parse_relational_expr(t: TokenSlice) -> IResult<TokenSlice, Expression> {
-    let (rest, mut left) = parse_bitwise_expr(t)?;
-    let (rest, ops) = many0(pair(parse_relational_op, parse_bitwise_expr)).parse(rest)?;
-    for (op, right) in ops {
-        left = Expression::BinaryOp {
-            left: Box::new(left),
-            op,
-            right: Box::new(right),
-        };
-    }
-    Ok((rest, left))
-}
-
-fn parse_relational_op(t: TokenSlice) -> IResult<TokenSlice, BinaryOp> {
-    alt((
-        map(token_tag(&Token::Lt), |_| BinaryOp::Lt),
-        map(token_tag(&Token::Lte), |_| BinaryOp::Lte),
-        map(token_tag(&Token::Gt), |_| BinaryOp::Gt),
-        map(token_tag(&Token::Gte), |_| BinaryOp::Gte),
-    ))
-    .parse(t)
+fn parse_unary_expr(t: TokenSlice) -> PResult<Expression> {
+    expecting(Expected::Expression, t, |t| {
+        alt((
+            // Only `^` binds tighter than a unary operator
+            map(
+                pair(parse_unary_op, |i| {
+                    parse_binary_expr(i, UnaryOp::PRECEDENCE + 1)
+                }),
+                |(op, operand)| Expression::UnaryOp {
+                    op,
+                    operand: Box::new(operand),
+                },
+            ),
+            parse_prefix_exp,
+        ))
+        .parse(t)
+    })
 }
 
-fn parse_bitwise_expr(t: TokenSlice) -> IResult<TokenSlice, Expression> {
-    let (rest, mut left) = parse_concat_expr(t)?;
-    let (rest, ops) = many0(pair(parse_bitwise_op, parse_concat_expr)).parse(rest)?;
-    for (op, right) in ops {
-        left = Expression::BinaryOp {
-            left: Box::new(left),
-            op,
-            right: Box::new(right),
-        };
-    }
-    Ok((rest, left))
+/// The binary operator at the front of `t`, if there is one
+fn parse_binary_op(t: TokenSlice) -> PResult<BinaryOp> {
+    let op = match t.0.first() {
+        Some(Token::Or) => BinaryOp::Or,
+        Some(Token::And) => BinaryOp::And,
+        Some(Token::Eq) => BinaryOp::Eq,
+        Some(Token::Neq) => BinaryOp::Neq,
+        Some(Token::Lt) => BinaryOp::Lt,
+        Some(Token::Lte) => BinaryOp::Lte,
+        Some(Token::Gt) => BinaryOp::Gt,
+        Some(Token::Gte) => BinaryOp::Gte,
+        Some(Token::Pipe) => BinaryOp::BitOr,
+        Some(Token::Tilde) => BinaryOp::BitXor,
+        Some(Token::Ampersand) => BinaryOp::BitAnd,
+        Some(Token::LShift) => BinaryOp::LeftShift,
+        Some(Token::RShift) => BinaryOp::RightShift,
+        Some(Token::Concat) => BinaryOp::Concat,
+        Some(Token::Plus) => BinaryOp::Add,
+        Some(Token::Minus) => BinaryOp::Subtract,
+        Some(Token::Star) => BinaryOp::Multiply,
+        Some(Token::Slash) => BinaryOp::Divide,
+        Some(Token::DoubleSlash) => BinaryOp::FloorDivide,
+        Some(Token::Percent) => BinaryOp::Modulo,
+        Some(Token::Caret) => BinaryOp::Power,
+        _ => return fail(t, Vec::new()),
+    };
+    Ok((t.advance(1), op))
 }
 
-fn parse_bitwise_op(t: TokenSlice) -> IResult<TokenSlice, BinaryOp> {
-    alt((
-        map(token_tag(&Token::Ampersand), |_| BinaryOp::BitAnd),
-        map(token_tag(&Token::Pipe), |_| BinaryOp::BitOr),
-        map(token_tag(&Token::Tilde), |_| BinaryOp::BitXor),
-        map(token_tag(&Token::LShift), |_| BinaryOp::LeftShift),
-        map(token_tag(&Token::RShift), |_| BinaryOp::RightShift),
-    ))
-    .parse(t)
+/// Parse binary operators by precedence climbing: an operand, then every
+/// following operator that binds at least as tightly as `min`, each with a
+/// right-hand side parsed at the next level up (or the same level for the
+/// right-associative `..` and `^`). See `BinaryOp::precedence` for the
+/// levels.
+///
+/// Every nested expression passes through here, so this is where its
+/// nesting is counted against the limit and gets more stack
+fn parse_binary_expr(t: TokenSlice, min: u8) -> PResult<Expression> {
+    let _level = Level::enter(t)?;
+    with_headroom(|| parse_binary_chain(t, min))
 }
 
-fn parse_concat_expr(t: TokenSlice) -> IResult<TokenSlice, Expression> {
-    let (rest, mut left) = parse_additive_expr(t)?;
-    let (rest, ops) = many0(pair(
-        |i| token_tag(&Token::Concat)(i).map(|(r, _)| (r, BinaryOp::Concat)),
-        parse_additive_expr,
-    ))
-    .parse(rest)?;
-    for (op, right) in ops {
-        left = Expression::BinaryOp {
-            left: Box::new(left),
-            op,
-            right: Box::new(right),
-        };
-    }
-    Ok((rest, left))
-}
-
-fn parse_additive_expr(t: TokenSlice) -> IResult<TokenSlice, Expression> {
-    let (rest, mut left) = parse_multiplicative_expr(t)?;
-    let (rest, ops) = many0(pair(parse_additive_op, parse_multiplicative_expr)).parse(rest)?;
-    for (op, right) in ops {
-        left = Expression::BinaryOp {
-            left: Box::new(left),
-            op,
-            right: Box::new(right),
+fn parse_binary_chain(t: TokenSlice, min: u8) -> PResult<Expression> {
+    let (mut rest, mut left) = parse_unary_expr(t)?;
+    while let Ok((r, op)) = parse_binary_op(rest) {
+        let precedence = op.precedence();
+        if precedence < min {
+            break;
+        }
+        let next = if op.is_right_associative() {
+            precedence
+        } else {
+            precedence + 1
         };
-    }
-    Ok((rest, left))
-}
-
-fn parse_additive_op(t: TokenSlice) -> IResult<TokenSlice, BinaryOp> {
-    alt((
-        map(token_tag(&Token::Plus), |_| BinaryOp::Add),
-        map(token_tag(&Token::Minus), |_| BinaryOp::Subtract),
-    ))
-    .parse(t)
-}
-
-fn parse_multiplicative_expr(t: TokenSlice) -> IResult<TokenSlice, Expression> {
-    let (rest, mut left) = parse_power_expr(t)?;
-    let (rest, ops) = many0(pair(parse_multiplicative_op, parse_power_expr)).parse(rest)?;
-    for (op, right) in ops {
+        let (r, right) = parse_binary_expr(r, next)?;
         left = Expression::BinaryOp {
             left: Box::new(left),
             op,
             right: Box::new(right),
         };
+        rest = r;
     }
     Ok((rest, left))
 }
 
-fn parse_multiplicative_op(t: TokenSlice) -> IResult<TokenSlice, BinaryOp> {
-    alt((
-        map(token_tag(&Token::Star), |_| BinaryOp::Multiply),
-        map(token_tag(&Token::Slash), |_| BinaryOp::Divide),
-        map(token_tag(&Token::DoubleSlash), |_| BinaryOp::FloorDivide),
-        map(token_tag(&Token::Percent), |_| BinaryOp::Modulo),
-    ))
-    .parse(t)
-}
-
-fn parse_power_expr(t: TokenSlice) -> IResult<TokenSlice, Expression> {
-    let (rest, left) = parse_unary_expr(t)?;
-    let (rest, op) = opt(token_tag(&Token::Caret)).parse(rest)?;
-    if op.is_some() {
-        let (rest, right) = parse_power_expr(rest)?;
-        Ok((
-            rest,
-            Expression::BinaryOp {
-                left: Box::new(left),
-                op: BinaryOp::Power,
-                right: Box::new(right),
-            },
-        ))
-    } else {
-        Ok((rest, left))
-    }
-}
-
 /// Parse the full expression
-pub fn parse_expression(t: TokenSlice) -> IResult<TokenSlice, Expression> {
-    parse_or_expr(t)
+pub fn parse_expression(t: TokenSlice) -> PResult<Expression> {
+    parse_binary_expr(t, 0)
 }
 
-pub fn parse_expression_list(t: TokenSlice) -> IResult<TokenSlice, Vec<Expression>> {
-    let (rest, first) = parse_expression(t)?;
-    let (rest, rest_exprs) = many0(pair(token_tag(&Token::Comma), parse_expression)).parse(rest)?;
-
+pub fn parse_expression_list(t: TokenSlice) -> PResult<Vec<Expression>> {
+    let (mut rest, first) = parse_expression(t)?;
     let mut result = vec![first];
-    for (_, expr) in rest_exprs {
+    while let Ok((r, _)) = token_tag(&Token::Comma)(rest) {
+        let (r, expr) = parse_expression(r)?;
         result.push(expr);
+        rest = r;
     }
     Ok((rest, result))
 }
@@ -1,16 +1,18 @@
 //! Common parsing helpers and token utilities
 
-use phf::phf_map;
 use nom::{
-    bytes::complete::{tag, take_while, take_while1},
-    character::complete::{char, digit1, satisfy},
-    combinator::{opt, recognize},
-    sequence::{pair, preceded},
+    bytes::complete::{tag, take_while},
+    character::complete::satisfy,
+    combinator::recognize,
+    sequence::pair,
     IResult, Parser,
 };
+use phf::phf_map;
 
 use super::Token;
 use super::Token::*;
+use crate::intern::{intern, intern_bytes};
+use crate::lua_string::utf8_encode;
 
 // Keywords and symbols lookup tables
 pub const KEYWORDS: phf::Map<&str, Token> = phf_map! {
@@ -83,71 +85,182 @@ pub fn identifier(input: &str) -> IResult<&str, &str> {
     .parse(input)
 }
 
+/// A numeral: decimal digits with an optional fraction and exponent
+/// (`3`, `.5`, `1e15`), or `0x` and hex digits with an optional fraction
+/// and binary exponent (`0x10`, `0x1p4`)
 pub fn number(input: &str) -> IResult<&str, &str> {
-    recognize(pair(digit1, opt(preceded(char('.'), digit1)))).parse(input)
+    let bytes = input.as_bytes();
+    let hex = bytes.len() > 2 && bytes[0] == b'0' && matches!(bytes[1], b'x' | b'X');
+    let (start, exponent) = if hex { (2, b'p') } else { (0, b'e') };
+    let digits = |from: usize| {
+        from + bytes[from..]
+            .iter()
+            .take_while(|b| {
+                if hex {
+                    b.is_ascii_hexdigit()
+                } else {
+                    b.is_ascii_digit()
+                }
+            })
+            .count()
+    };
+    let mut end = digits(start);
+    let mut seen = end > start;
+    // A second dot makes `..`, which concatenates
+    if bytes.get(end) == Some(&b'.') && bytes.get(end + 1) != Some(&b'.') {
+        let after = digits(end + 1);
+        seen |= after > end + 1;
+        end = after;
+    }
+    if !seen {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Digit,
+        )));
+    }
+    if bytes.get(end).map(u8::to_ascii_lowercase) == Some(exponent) {
+        let mut at = end + 1;
+        if matches!(bytes.get(at), Some(b'+' | b'-')) {
+            at += 1;
+        }
+        let after = at
+            + bytes[at..]
+                .iter()
+                .take_while(|b| b.is_ascii_digit())
+                .count();
+        if after > at {
+            end = after;
+        }
+    }
+    Ok((&input[end..], &input[..end]))
 }
 
-pub fn string_literal(input: &str) -> IResult<&str, String> {
-    if input.starts_with('\'') {
-        let (input, _) = char('\'').parse(input)?;
-        let (input, content) = take_while(|c: char| c != '\'').parse(input)?;
-        let (input, _) = char('\'').parse(input)?;
-        let processed = process_escape_sequences(content);
-        Ok((input, processed))
-    } else {
-        let (input, _) = char('"').parse(input)?;
-        let (input, content) = take_while(|c: char| c != '"').parse(input)?;
-        let (input, _) = char('"').parse(input)?;
-        let processed = process_escape_sequences(content);
-        Ok((input, processed))
+/// A quoted string, with its escapes decoded to bytes
+///
+/// Fails on a string that is not closed on its line and on an invalid
+/// escape. `\ddd` and `\xhh` stand for the byte with that value, which
+/// need not make valid UTF-8; `\u{XXX}` stands for the UTF-8 encoding of
+/// the code point.
+pub fn string_literal(input: &str) -> IResult<&str, Vec<u8>> {
+    let fail = || nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Char));
+    let quote = match input.chars().next() {
+        Some(quote @ ('"' | '\'')) => quote,
+        _ => return Err(fail()),
+    };
+    let mut content = Vec::new();
+    let mut rest = &input[1..];
+    loop {
+        let c = rest.chars().next().ok_or_else(fail)?;
+        rest = &rest[c.len_utf8()..];
+        match c {
+            c if c == quote => return Ok((rest, content)),
+            '\n' | '\r' => return Err(fail()),
+            '\\' => {
+                let (len, decoded) = escape(rest).ok_or_else(fail)?;
+                content.extend(decoded);
+                rest = &rest[len..];
+            }
+            c => content.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
     }
 }
 
-pub fn process_escape_sequences(s: &str) -> String {
-    let mut result = String::new();
-    let mut chars = s.chars().peekable();
-
-    while let Some(ch) = chars.next() {
-        if ch == '\\' {
-            if let Some(&next_ch) = chars.peek() {
-                match next_ch {
-                    'n' => {
-                        result.push('\n');
-                        chars.next();
-                    }
-                    't' => {
-                        result.push('\t');
-                        chars.next();
-                    }
-                    'r' => {
-                        result.push('\r');
-                        chars.next();
-                    }
-                    '\\' => {
-                        result.push('\\');
-                        chars.next();
-                    }
-                    '"' => {
-                        result.push('"');
-                        chars.next();
-                    }
-                    '\'' => {
-                        result.push('\'');
-                        chars.next();
-                    }
-                    _ => {
-                        result.push(ch);
-                    }
-                }
-            } else {
-                result.push(ch);
+/// The escape sequence that starts `input`, just after its backslash: how
+/// many bytes of source it takes and the bytes it stands for (none for
+/// `\z`)
+fn escape(input: &str) -> Option<(usize, Vec<u8>)> {
+    let bytes = input.as_bytes();
+    let simple = match *bytes.first()? {
+        b'a' => b'\x07',
+        b'b' => b'\x08',
+        b'f' => b'\x0c',
+        b'n' => b'\n',
+        b'r' => b'\r',
+        b't' => b'\t',
+        b'v' => b'\x0b',
+        b'\\' => b'\\',
+        b'"' => b'"',
+        b'\'' => b'\'',
+        // An escaped line break, `\r\n` and `\n\r` included, is a newline
+        first @ (b'\n' | b'\r') => {
+            let pair = matches!(bytes.get(1), Some(&next) if next != first && matches!(next, b'\n' | b'\r'));
+            return Some((1 + pair as usize, vec![b'\n']));
+        }
+        b'x' => {
+            let hex = input
+                .get(1..3)
+                .filter(|h| h.bytes().all(|b| b.is_ascii_hexdigit()))?;
+            return Some((3, vec![u8::from_str_radix(hex, 16).ok()?]));
+        }
+        b'z' => {
+            let skipped = input[1..].len() - input[1..].trim_start().len();
+            return Some((1 + skipped, Vec::new()));
+        }
+        b'u' => {
+            let close = input.find('}')?;
+            let hex = input.get(1..close)?.strip_prefix('{')?;
+            if hex.is_empty() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
             }
-        } else {
-            result.push(ch);
+            let code = u32::from_str_radix(hex, 16).ok()?;
+            return Some((close + 1, utf8_encode(code)?));
         }
-    }
+        b'0'..=b'9' => {
+            let len = bytes
+                .iter()
+                .take(3)
+                .take_while(|b| b.is_ascii_digit())
+                .count();
+            let code: u32 = input[..len].parse().ok()?;
+            return Some((len, vec![u8::try_from(code).ok()?]));
+        }
+        _ => return None,
+    };
+    Some((1, vec![simple]))
+}
+
+/// Level of the long bracket opening `input`, such as 2 for `[==[`
+fn long_bracket_level(input: &str) -> Option<usize> {
+    let rest = input.strip_prefix('[')?;
+    let level = rest.len() - rest.trim_start_matches('=').len();
+    rest[level..].starts_with('[').then_some(level)
+}
 
-    result
+/// A long string such as `[[...]]` or `[==[...]==]`, taken as written
+/// except for a line break right after the opening bracket; an opening
+/// bracket that is never closed is a hard failure
+pub fn long_string(input: &str) -> IResult<&str, String> {
+    let error = || nom::error::Error::new(input, nom::error::ErrorKind::Tag);
+    let level = long_bracket_level(input).ok_or_else(|| nom::Err::Error(error()))?;
+    let open = level + 2;
+    let close = format!("]{}]", "=".repeat(level));
+    let end = open
+        + input[open..]
+            .find(&close)
+            .ok_or_else(|| nom::Err::Failure(error()))?;
+    let content = &input[open..end];
+    let content = ["\r\n", "\n\r", "\n", "\r"]
+        .iter()
+        .find_map(|newline| content.strip_prefix(newline))
+        .unwrap_or(content);
+    Ok((&input[end + close.len()..], content.to_string()))
+}
+
+/// Byte length of the comment starting `input`, which begins with `--`
+///
+/// A line comment runs up to the newline; a long comment such as
+/// `--[==[ ... ]==]` runs through its closing bracket, or is unfinished
+/// (`None`) when the input ends first.
+pub fn comment_len(input: &str) -> Option<usize> {
+    match long_bracket_level(&input[2..]) {
+        Some(level) => {
+            let close = format!("]{}]", "=".repeat(level));
+            let open = 2 + level + 2;
+            let end = input[open..].find(&close)?;
+            Some(open + end + close.len())
+        }
+        None => Some(input.find('\n').unwrap_or(input.len())),
+    }
 }
 
 pub fn symbol(input: &str) -> IResult<&str, Token> {
@@ -172,20 +285,26 @@ pub fn symbol(input: &str) -> IResult<&str, Token> {
 }
 
 pub fn tokenize_single(input: &str) -> IResult<&str, Token> {
+    // Before the symbols, which `[` and `.` would match
+    match long_string(input) {
+        Ok((rest, content)) => return Ok((rest, Token::StringLit(intern(&content).into()))),
+        Err(err @ nom::Err::Failure(_)) => return Err(err),
+        Err(_) => {}
+    }
+    if let Ok((rest, num)) = number(input) {
+        return Ok((rest, Token::Number(num.to_string())));
+    }
     if let Ok((rest, token)) = symbol(input) {
         return Ok((rest, token));
     }
     if let Ok((rest, content)) = string_literal(input) {
-        return Ok((rest, Token::StringLit(content)));
-    }
-    if let Ok((rest, num)) = number(input) {
-        return Ok((rest, Token::Number(num.to_string())));
+        return Ok((rest, Token::StringLit(intern_bytes(content))));
     }
 
     let (rest, ident) = identifier(input)?;
     let token = KEYWORDS
         .get(ident)
         .cloned()
-        .unwrap_or_else(|| Token::Identifier(ident.to_string()));
+        .unwrap_or_else(|| Token::Identifier(intern(ident)));
     Ok((rest, token))
 }
@@ -1,6 +1,6 @@
 //! Location tracking for tokens and AST nodes
 
-use super::Token;
+use super::{comment_len, Comment, Token};
 
 /// Source location information (line and column numbers)
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -41,6 +41,17 @@ impl TokenWithLocation {
     pub fn new(token: Token, location: Location) -> Self {
         TokenWithLocation { token, location }
     }
+
+    /// Where the token ends, just past its last character, assuming it is
+    /// written as `Token`'s `Display` shows it
+    pub fn end(&self) -> Location {
+        let mut tracker = LocationTracker {
+            line: self.location.line,
+            column: self.location.column,
+        };
+        tracker.advance_str(&self.token.to_string());
+        tracker.current()
+    }
 }
 
 /// Helper to track location while processing source code
@@ -82,21 +93,39 @@ impl LocationTracker {
 
     /// Skip whitespace and comments, returning the number of characters consumed
     pub fn skip_whitespace_and_comments(&mut self, input: &str) -> usize {
+        self.skip_trivia(input, None)
+    }
+
+    /// Like `skip_whitespace_and_comments`, but appends the comments it
+    /// skips to `comments`
+    pub fn collect_comments(&mut self, input: &str, comments: &mut Vec<Comment>) -> usize {
+        self.skip_trivia(input, Some(comments))
+    }
+
+    fn skip_trivia(&mut self, input: &str, mut comments: Option<&mut Vec<Comment>>) -> usize {
         let mut consumed = 0;
         let mut remaining = input;
 
         loop {
             // Skip comments
             if remaining.starts_with("--") {
-                if let Some(newline_pos) = remaining.find('\n') {
-                    self.advance_str(&remaining[..newline_pos + 1]);
-                    consumed += newline_pos + 1;
-                    remaining = &remaining[newline_pos + 1..];
-                } else {
-                    self.advance_str(remaining);
-                    consumed += remaining.len();
-                    remaining = "";
+                // An unfinished long comment is left for the caller to report
+                let Some(len) = comment_len(remaining) else {
+                    break;
+                };
+                let text = remaining[2..len].trim_end_matches('\r');
+                let (line, column) = (self.line, self.column);
+                self.advance_str(&remaining[..2 + text.len()]);
+                if let Some(comments) = comments.as_deref_mut() {
+                    comments.push(Comment {
+                        text: text.to_string(),
+                        line,
+                        column,
+                        end_column: self.column,
+                    });
                 }
+                consumed += 2 + text.len();
+                remaining = &remaining[2 + text.len()..];
             } else if remaining.chars().next().is_some_and(char::is_whitespace) {
                 let ch = remaining.chars().next().unwrap();
                 self.advance(ch);
@@ -184,6 +213,38 @@ mod tests {
         assert_eq!(tracker.current(), Location::new(2, 0));
     }
 
+    #[test]
+    fn test_location_tracker_collects_comments() {
+        let mut tracker = LocationTracker::new();
+        let mut comments = Vec::new();
+        let input = "  -- one\r\n--two\nhello";
+        let consumed = tracker.collect_comments(input, &mut comments);
+        assert_eq!(consumed, 16);
+        assert_eq!(tracker.current(), Location::new(3, 0));
+        assert_eq!(comments.len(), 2);
+        assert_eq!(comments[0].text, " one");
+        assert_eq!((comments[0].line, comments[0].column), (1, 2));
+        assert_eq!(comments[0].end_column, 8);
+        assert_eq!((comments[1].text.as_str(), comments[1].line), ("two", 2));
+    }
+
+    #[test]
+    fn test_location_tracker_skips_long_comments() {
+        let mut tracker = LocationTracker::new();
+        let mut comments = Vec::new();
+        let input = "--[[ a\n]] --[==[ ]] ]==]--[ line\nx";
+        let consumed = tracker.collect_comments(input, &mut comments);
+        assert_eq!(consumed, input.len() - 1);
+        assert_eq!(tracker.current(), Location::new(3, 0));
+        let texts: Vec<_> = comments.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(texts, ["[[ a\n]]", "[==[ ]] ]==]", "[ line"]);
+        assert_eq!((comments[0].line, comments[0].end_column), (1, 2));
+        assert_eq!((comments[1].line, comments[1].column), (2, 3));
+
+        let mut tracker = LocationTracker::new();
+        assert_eq!(tracker.skip_whitespace_and_comments(" --[[ open"), 1);
+    }
+
     #[test]
     fn test_token_with_location() {
         let tok = TokenWithLocation::new(Token::True, Location::new(5, 10));
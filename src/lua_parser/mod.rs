@@ -21,32 +21,122 @@
 //!
 //! retstat ::= return [explist] [';']
 
-mod helpers;
+pub mod arena;
+pub mod error;
 mod expression;
-mod statement;
+mod helpers;
 pub mod location;
+mod nesting;
+mod statement;
+pub mod types;
 
-pub use helpers::{tokenize_single, KEYWORDS, SYMBOLS};
-pub use expression::{parse_expression, parse_expression_list, parse_prefix_exp};
-pub use statement::parse_block;
+pub use arena::{BlockId, Chunk, FunctionId, FunctionRef, LuaArena};
+pub use helpers::{comment_len, tokenize_single, KEYWORDS, SYMBOLS};
 
-use nom::{IResult, Input, Needed};
+use nom::{Input, Needed};
+
+pub use error::{Expected, PResult, ParseError, SyntaxError};
 
-use crate::lua_parser_types as types;
 pub use location::{Location, LocationTracker, TokenWithLocation};
+pub use nesting::MAX_DEPTH;
 
 // Re-export main AST types
 pub use types::{
-    Block, Expression, Statement, Token, Token::*, ReturnStatement,
-    BinaryOp, UnaryOp, Field, FieldKey, FunctionBody,
+    BinaryOp, Block, Capture, Comment, Expression, Field, FieldKey, FrameLayout, FuncName,
+    FunctionBody, Numeral, ReturnStatement, Statement, Token, Token::*, Trivia, UnaryOp,
 };
 
+/// Tokens being parsed, plus the source location of each token when known
+///
+/// Locations are either empty (parsed from plain `tokenize` output) or run
+/// parallel to the tokens, in which case parsed blocks record where every
+/// statement starts. Comments are likewise either empty or hold the
+/// comments before each token, plus a last entry for those after the
+/// final token, in which case parsed blocks record statement trivia.
 #[derive(Debug, Clone, Copy)]
-pub struct TokenSlice<'a>(&'a [Token]);
+pub struct TokenSlice<'a>(&'a [Token], &'a [Location], &'a [Vec<Comment>]);
 
 impl<'a> From<&'a [Token]> for TokenSlice<'a> {
     fn from(slice: &'a [Token]) -> Self {
-        TokenSlice(slice)
+        TokenSlice(slice, &[], &[])
+    }
+}
+
+impl<'a> TokenSlice<'a> {
+    /// Tokens paired with the location each one starts at
+    pub fn with_locations(tokens: &'a [Token], locations: &'a [Location]) -> Self {
+        assert_eq!(tokens.len(), locations.len(), "one location per token");
+        TokenSlice(tokens, locations, &[])
+    }
+
+    /// Tokens with their locations and the comments before each of them,
+    /// as returned by `tokenize_with_comments`
+    pub fn with_comments(
+        tokens: &'a [Token],
+        locations: &'a [Location],
+        comments: &'a [Vec<Comment>],
+    ) -> Self {
+        assert_eq!(tokens.len(), locations.len(), "one location per token");
+        assert_eq!(tokens.len() + 1, comments.len(), "comments around tokens");
+        TokenSlice(tokens, locations, comments)
+    }
+
+    /// Line of the first token, or 0 when locations are unknown
+    pub fn line(&self) -> usize {
+        self.1.first().map_or(0, |location| location.line)
+    }
+
+    /// Column of the first token, or 0 when locations are unknown
+    pub fn column(&self) -> usize {
+        self.1.first().map_or(0, |location| location.column)
+    }
+
+    /// Comments before the first token, or after the last one when the
+    /// slice is exhausted
+    fn comments(&self) -> &'a [Comment] {
+        self.2.first().map_or(&[], Vec::as_slice)
+    }
+
+    /// The slice without its first `count` tokens
+    fn advance(&self, count: usize) -> Self {
+        let count = count.min(self.0.len());
+        TokenSlice(
+            &self.0[count..],
+            self.1.get(count..).unwrap_or(&[]),
+            self.2.get(count..).unwrap_or(&[]),
+        )
+    }
+
+    /// The first `count` tokens
+    fn prefix(&self, count: usize) -> Self {
+        let count = count.min(self.0.len());
+        TokenSlice(
+            &self.0[..count],
+            self.1.get(..count).unwrap_or(&[]),
+            self.2.get(..=count).unwrap_or(&[]),
+        )
+    }
+}
+
+/// Settings for `parse_source_with`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Keep comments as statement trivia in `Block::trivia`
+    ///
+    /// Comments inside an expression or a statement's header, such as
+    /// between `if` and `then`, belong to no statement and are dropped.
+    pub comments: bool,
+    /// How deeply blocks and expressions may nest, `MAX_DEPTH` by default;
+    /// PUC-Lua allows 200
+    pub max_depth: usize,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            comments: false,
+            max_depth: MAX_DEPTH,
+        }
     }
 }
 
@@ -60,16 +150,15 @@ impl<'a> Input for TokenSlice<'a> {
     }
 
     fn take(&self, index: usize) -> Self {
-        TokenSlice(&self.0[..index.min(self.0.len())])
+        self.prefix(index)
     }
 
     fn take_from(&self, index: usize) -> Self {
-        TokenSlice(&self.0[index.min(self.0.len())..])
+        self.advance(index)
     }
 
     fn take_split(&self, index: usize) -> (Self, Self) {
-        let index = index.min(self.0.len());
-        (TokenSlice(&self.0[index..]), TokenSlice(&self.0[..index]))
+        (self.advance(index), self.prefix(index))
     }
 
     fn position<P>(&self, predicate: P) -> Option<usize>
@@ -99,43 +188,39 @@ impl<'a> Input for TokenSlice<'a> {
 }
 
 /// Helper to match a specific token
-pub fn token_tag(expected: &Token) -> impl Fn(TokenSlice) -> IResult<TokenSlice, &Token> {
+pub fn token_tag(expected: &Token) -> impl Fn(TokenSlice) -> PResult<&Token> {
     let expected = expected.clone();
-    move |input: TokenSlice| {
-        if let Some(tok) = input.0.first() {
-            if tok == &expected {
-                Ok((TokenSlice(&input.0[1..]), tok))
-            } else {
-                Err(nom::Err::Error(nom::error::Error::new(
-                    input,
-                    nom::error::ErrorKind::Tag,
-                )))
-            }
-        } else {
-            Err(nom::Err::Error(nom::error::Error::new(
-                input,
-                nom::error::ErrorKind::Eof,
-            )))
-        }
+    move |input: TokenSlice| match input.0.first() {
+        Some(tok) if tok == &expected => Ok((input.advance(1), tok)),
+        _ => error::fail(input, vec![Expected::Token(expected.clone())]),
+    }
+}
+
+/// The `#!` line a chunk starts with, without its newline, or `""`
+///
+/// The tokenizers skip it, so a script run as an executable through
+/// `#!/usr/bin/env muscm` parses as if the line were not there.
+pub fn shebang_line(input: &str) -> &str {
+    if input.starts_with("#!") {
+        input.lines().next().unwrap_or_default()
+    } else {
+        ""
     }
 }
 
 /// Tokenize Lua source code into a vector of tokens
 pub fn tokenize(input: &str) -> Result<Vec<Token>, String> {
     let mut tokens = Vec::new();
-    let mut remaining = input;
+    let mut remaining = &input[shebang_line(input).len()..];
 
     loop {
         // Skip whitespace and comments
         while !remaining.is_empty() {
             if remaining.starts_with("--") {
-                if let Some(newline) = remaining.find('\n') {
-                    remaining = &remaining[newline + 1..];
-                } else {
-                    remaining = "";
-                }
-            } else if remaining.chars().next().is_some_and(char::is_whitespace) {
-                remaining = &remaining[1..];
+                let len = comment_len(remaining).ok_or("unfinished long comment")?;
+                remaining = &remaining[len..];
+            } else if let Some(ch) = remaining.chars().next().filter(|c| c.is_whitespace()) {
+                remaining = &remaining[ch.len_utf8()..];
             } else {
                 break;
             }
@@ -145,8 +230,8 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, String> {
             break;
         }
 
-        let (rest, tok) = tokenize_single(remaining)
-            .map_err(|e| format!("Tokenization error: {:?}", e))?;
+        let (rest, tok) =
+            tokenize_single(remaining).map_err(|e| format!("Tokenization error: {:?}", e))?;
 
         tokens.push(tok);
         remaining = rest;
@@ -157,18 +242,51 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, String> {
 
 /// Tokenize Lua source code with location tracking
 pub fn tokenize_with_location(input: &str) -> Result<Vec<TokenWithLocation>, String> {
+    tokenize_located(input, None)
+}
+
+/// Tokenize Lua source code with location tracking, keeping comments
+///
+/// The comments come grouped by the token they precede, with one more
+/// group for those after the last token.
+pub fn tokenize_with_comments(
+    input: &str,
+) -> Result<(Vec<TokenWithLocation>, Vec<Vec<Comment>>), String> {
+    let mut comments = Vec::new();
+    let tokens = tokenize_located(input, Some(&mut comments))?;
+    Ok((tokens, comments))
+}
+
+fn tokenize_located(
+    input: &str,
+    mut comments: Option<&mut Vec<Vec<Comment>>>,
+) -> Result<Vec<TokenWithLocation>, String> {
     let mut tokens = Vec::new();
     let mut tracker = LocationTracker::new();
-    let mut remaining = input;
+    let shebang = shebang_line(input);
+    tracker.advance_str(shebang);
+    let mut remaining = &input[shebang.len()..];
 
     loop {
         // Skip whitespace and comments, tracking position
-        let consumed = tracker.skip_whitespace_and_comments(remaining);
+        let consumed = match comments.as_deref_mut() {
+            Some(comments) => {
+                let mut before = Vec::new();
+                let consumed = tracker.collect_comments(remaining, &mut before);
+                comments.push(before);
+                consumed
+            }
+            None => tracker.skip_whitespace_and_comments(remaining),
+        };
         remaining = &remaining[consumed..];
 
         if remaining.is_empty() {
             break;
         }
+        // Trivia stops short of a comment only when it never ends
+        if remaining.starts_with("--") {
+            return Err(format!("unfinished long comment at {}", tracker.current()));
+        }
 
         let token_location = tracker.current();
         let (rest, tok) = tokenize_single(remaining)
@@ -186,8 +304,130 @@ pub fn tokenize_with_location(input: &str) -> Result<Vec<TokenWithLocation>, Str
 }
 
 /// Parse tokenized Lua code into an AST
-pub fn parse(t: TokenSlice) -> IResult<TokenSlice, Block> {
-    parse_block(t)
+pub fn parse(t: TokenSlice) -> PResult<Chunk> {
+    let (parsed, mut arena) = arena::building(|| statement::parse_block(t));
+    let (rest, block) = parsed?;
+    // A chunk must consume every token; leftovers are a block's end with
+    // no block to end
+    if !rest.0.is_empty() {
+        return error::fail(rest, vec![Expected::EndOfInput]);
+    }
+    let root = arena.alloc_block(block);
+    Ok((rest, Chunk::new(arena, root)))
+}
+
+/// Tokenize and parse a whole chunk, recording the line of every statement
+///
+/// Errors name the span of the token that could not be parsed, with what
+/// was expected there.
+pub fn parse_source(input: &str) -> Result<Chunk, String> {
+    parse_source_with(input, &ParseOptions::default())
+}
+
+/// `parse_source` with settings for what else to keep in the tree
+pub fn parse_source_with(input: &str, options: &ParseOptions) -> Result<Chunk, String> {
+    let (located, comments) = if options.comments {
+        tokenize_with_comments(input)
+    } else {
+        tokenize_with_location(input).map(|located| (located, Vec::new()))
+    }
+    .map_err(|e| format!("Tokenize error: {}", e))?;
+    let parsed = nesting::with_max_depth(options.max_depth, || parse_tokens(&located, &comments));
+    parsed.map_err(|error| match error.index.map(|i| &located[i]) {
+        Some(token) => {
+            let start = token.location;
+            let end = token.end();
+            let span = if end.line == start.line && end.column > start.column + 1 {
+                format!("columns {}-{}", start.column, end.column - 1)
+            } else {
+                format!("column {}", start.column)
+            };
+            format!("Parse error at line {}, {}: {}", start.line, span, error)
+        }
+        None => format!("Parse error: {}", error),
+    })
+}
+
+/// Parse a whole chunk of located tokens, recording the line of every
+/// statement
+///
+/// On failure, the error holds the index of the first token that could not
+/// be parsed, or `None` when the input ended too early.
+pub fn parse_located(located: &[TokenWithLocation]) -> Result<Chunk, SyntaxError> {
+    parse_tokens(located, &[])
+}
+
+/// `parse_located`, also attaching `comments` unless they are empty
+fn parse_tokens(
+    located: &[TokenWithLocation],
+    comments: &[Vec<Comment>],
+) -> Result<Chunk, SyntaxError> {
+    let tokens: Vec<Token> = located.iter().map(|t| t.token.clone()).collect();
+    let locations: Vec<Location> = located.iter().map(|t| t.location).collect();
+    let input = if comments.is_empty() {
+        TokenSlice::with_locations(&tokens, &locations)
+    } else {
+        TokenSlice::with_comments(&tokens, &locations, comments)
+    };
+    parse(input)
+        .map(|(_, chunk)| chunk)
+        .map_err(|err| syntax_error(tokens.len(), err))
+}
+
+/// Parse the statement at the front of `located`, for running a chunk one
+/// statement at a time while the rest of its source is still being read
+///
+/// Returns the statement as a block of its own, with its line recorded,
+/// and how many tokens it took. A `return` must end the chunk, so from one
+/// on all the tokens are parsed as the chunk's last block.
+pub fn parse_located_statement(
+    located: &[TokenWithLocation],
+) -> Result<(Chunk, usize), SyntaxError> {
+    let tokens: Vec<Token> = located.iter().map(|t| t.token.clone()).collect();
+    let locations: Vec<Location> = located.iter().map(|t| t.location).collect();
+    let input = TokenSlice::with_locations(&tokens, &locations);
+    let parsed = match tokens.first() {
+        Some(Token::Return) => parse(input),
+        _ => {
+            let (parsed, mut arena) = arena::building(|| {
+                crate::stack::with_headroom(|| statement::parse_statement(input))
+            });
+            parsed.map(|(rest, statement)| {
+                let root = arena.alloc_block(Block {
+                    statements: vec![statement],
+                    return_statement: None,
+                    lines: vec![input.line()],
+                    columns: vec![input.column()],
+                    trivia: Vec::new(),
+                });
+                (rest, Chunk::new(arena, root))
+            })
+        }
+    };
+    parsed
+        .map(|(rest, chunk)| (chunk, tokens.len() - rest.input_len()))
+        .map_err(|err| syntax_error(tokens.len(), err))
+}
+
+/// Where in `len` tokens a parse failed, and why
+fn syntax_error(len: usize, err: nom::Err<ParseError>) -> SyntaxError {
+    match err {
+        nom::Err::Error(e) | nom::Err::Failure(e) => {
+            let failed = len - e.input.input_len();
+            SyntaxError {
+                index: (failed < len).then_some(failed),
+                found: e.input.0.first().cloned(),
+                expected: e.expected,
+                too_deep: e.too_deep,
+            }
+        }
+        nom::Err::Incomplete(_) => SyntaxError {
+            index: None,
+            found: None,
+            expected: Vec::new(),
+            too_deep: false,
+        },
+    }
 }
 
 #[cfg(test)]
@@ -216,6 +456,27 @@ mod tests {
         assert!(rest.0.is_empty());
     }
 
+    #[test]
+    fn test_dotted_method_funcname() {
+        let tokens = tokenize("function obj.a.b:method(x) end").unwrap();
+        let (_, block) = parse(TokenSlice::from(tokens.as_slice())).unwrap();
+
+        match &block.statements[0] {
+            Statement::FunctionDecl { name, body } => {
+                assert_eq!(name.path, vec!["obj", "a", "b", "method"]);
+                assert!(name.method);
+                assert_eq!(name.to_string(), "obj.a.b:method");
+                // The implicit `self` comes first
+                assert_eq!(block.function(*body).params, vec!["self", "x"]);
+            }
+            other => panic!("Expected FunctionDecl, got {:?}", other),
+        }
+
+        // Nothing may follow the method name
+        let tokens = tokenize("function a:b.c() end").unwrap();
+        assert!(parse(TokenSlice::from(tokens.as_slice())).is_err());
+    }
+
     #[test]
     fn test_if_statement() {
         let code = "if x > 0 then print('positive') elseif x < 0 then print('negative') else print('zero') end";
@@ -465,7 +726,10 @@ mod tests {
         let tokens = tokenize_with_location(code).unwrap();
 
         // Find the 'y' token
-        let y_token = tokens.iter().find(|t| matches!(t.token, Token::Identifier(ref s) if s == "y")).unwrap();
+        let y_token = tokens
+            .iter()
+            .find(|t| matches!(t.token, Token::Identifier(ref s) if &**s == "y"))
+            .unwrap();
         assert_eq!(y_token.location.line, 2);
         assert_eq!(y_token.location.column, 0);
     }
@@ -476,7 +740,361 @@ mod tests {
         let tokens = tokenize_with_location(code).unwrap();
 
         // The 'y' token should be on line 2
-        let y_token = tokens.iter().find(|t| matches!(t.token, Token::Identifier(ref s) if s == "y")).unwrap();
+        let y_token = tokens
+            .iter()
+            .find(|t| matches!(t.token, Token::Identifier(ref s) if &**s == "y"))
+            .unwrap();
         assert_eq!(y_token.location.line, 2);
     }
+
+    #[test]
+    fn test_shebang_line_is_skipped() {
+        let code = "#!/usr/bin/env muscm\nx = #t";
+        assert_eq!(tokenize(code).unwrap(), tokenize("x = #t").unwrap());
+
+        let tokens = tokenize_with_location(code).unwrap();
+        assert_eq!(tokens[0].location.line, 2);
+        assert_eq!(parse_source(code).unwrap().lines, vec![2]);
+        // Only the first line can be one
+        assert!(tokenize("x = 1\n#!/bin/sh").is_err());
+    }
+
+    #[test]
+    fn test_repeated_identifiers_share_storage() {
+        let tokens = tokenize("count = count + \"count\"").unwrap();
+        let names: Vec<*const u8> = tokens
+            .iter()
+            .filter_map(|t| match t {
+                Token::Identifier(s) => Some(s.as_ptr()),
+                Token::StringLit(s) => Some(s.as_ptr()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names.len(), 3);
+        assert_eq!(names[0], names[1]);
+        assert_eq!(names[0], names[2]);
+    }
+
+    #[test]
+    fn test_comments_become_statement_trivia() {
+        let code = "-- header\nlocal x = 1 -- one\n-- before f\nfunction f()\n  -- inside\n  return x -- ret\n  -- tail of f\nend\n-- eof";
+        let texts = |comments: &[Comment]| -> Vec<String> {
+            comments.iter().map(|c| c.text.clone()).collect()
+        };
+
+        assert!(parse_source(code).unwrap().trivia.is_empty());
+
+        let options = ParseOptions {
+            comments: true,
+            ..ParseOptions::default()
+        };
+        let block = parse_source_with(code, &options).unwrap();
+        assert_eq!(block.trivia.len(), 3);
+        assert_eq!(texts(&block.trivia[0].leading), [" header"]);
+        assert_eq!(texts(&block.trivia[0].trailing), [" one"]);
+        assert_eq!(texts(&block.trivia[1].leading), [" before f"]);
+        assert!(block.trivia[1].trailing.is_empty());
+        assert_eq!(texts(&block.trivia[2].leading), [" eof"]);
+
+        let one = &block.trivia[0].trailing[0];
+        assert_eq!((one.line, one.column, one.end_column), (2, 12, 18));
+
+        let Statement::FunctionDecl { body, .. } = &block.statements[1] else {
+            panic!("expected a function declaration");
+        };
+        let trivia = &block.block(block.function(*body).block).trivia;
+        assert_eq!(trivia.len(), 2);
+        assert_eq!(texts(&trivia[0].leading), [" inside"]);
+        assert_eq!(texts(&trivia[0].trailing), [" ret"]);
+        assert_eq!(texts(&trivia[1].leading), [" tail of f"]);
+    }
+
+    #[test]
+    fn test_long_comments_span_lines() {
+        let code = "--[[ local x = 1\nlocal y = 2 ]] local z = 3\n--[==[ ]] ]==] return z --[[x]]";
+        assert_eq!(tokenize(code).unwrap().len(), 6);
+        let block = parse_source(code).unwrap();
+        assert_eq!(block.statements.len(), 1);
+        assert_eq!(block.lines, [2, 3]);
+
+        assert_eq!(
+            tokenize("x = 1 --[==[ open ]]").unwrap_err(),
+            "unfinished long comment"
+        );
+        let err = parse_source("x = 1\n--[[ open").unwrap_err();
+        assert!(err.contains("unfinished long comment at 2:0"), "{}", err);
+    }
+
+    #[test]
+    fn test_comparisons_share_a_precedence_level() {
+        let block = parse_source("x = 1 == 2 < 3").unwrap();
+        let Statement::Assignment { values, .. } = &block.statements[0] else {
+            panic!("expected an assignment");
+        };
+        match &values[0] {
+            Expression::BinaryOp { left, op, .. } => {
+                assert_eq!(*op, BinaryOp::Lt);
+                assert!(matches!(
+                    **left,
+                    Expression::BinaryOp {
+                        op: BinaryOp::Eq,
+                        ..
+                    }
+                ));
+            }
+            other => panic!("expected a comparison, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_positional_fields_are_numbered() {
+        let block = parse_source("t = {'a', x = 1, 'b'; [5] = 2, f()}").unwrap();
+        let Statement::Assignment { values, .. } = &block.statements[0] else {
+            panic!("expected an assignment");
+        };
+        let Expression::TableConstructor { fields } = &values[0] else {
+            panic!("expected a table constructor");
+        };
+        let indices: Vec<Option<usize>> = fields
+            .iter()
+            .map(|field| match field.key {
+                FieldKey::Index(index) => Some(index),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(indices, [Some(1), None, Some(2), None, Some(3)]);
+    }
+
+    #[test]
+    fn test_parentheses_keep_calls_to_one_value() {
+        let block = parse_source("local a, b = (f()), (...);\n(f or g)(a)\nx = ((y))").unwrap();
+        let Statement::LocalVars {
+            values: Some(values),
+            ..
+        } = &block.statements[0]
+        else {
+            panic!("expected a local declaration");
+        };
+        assert!(matches!(&values[0], Expression::Paren(inner)
+            if matches!(**inner, Expression::FunctionCall { .. })));
+        assert!(matches!(&values[1], Expression::Paren(inner)
+            if matches!(**inner, Expression::Varargs)));
+
+        let Statement::FunctionCall(Expression::FunctionCall { function, .. }) =
+            &block.statements[2]
+        else {
+            panic!("expected a call statement");
+        };
+        assert!(matches!(**function, Expression::BinaryOp { .. }));
+
+        // Parentheses that cannot change a value are dropped
+        let Statement::Assignment { values, .. } = &block.statements[3] else {
+            panic!("expected an assignment");
+        };
+        assert_eq!(values[0], Expression::Identifier("y".into()));
+
+        assert!(parse_source("(f())").is_err());
+        assert!(parse_source("(f()) = 1").is_err());
+        assert!(parse_source("x, f() = 1, 2").is_err());
+    }
+
+    #[test]
+    fn test_nesting_past_the_limit_fails() {
+        let parens = |depth: usize| format!("x = {}1{}", "(".repeat(depth), ")".repeat(depth));
+        let error = parse_source(&parens(100_000)).unwrap_err();
+        assert!(
+            error.ends_with(": chunk has too many syntax levels"),
+            "{}",
+            error
+        );
+
+        let strict = ParseOptions {
+            max_depth: 200,
+            ..ParseOptions::default()
+        };
+        assert!(parse_source_with(&parens(150), &strict).is_ok());
+        assert!(parse_source_with(&parens(250), &strict).is_err());
+        let blocks = format!("{}x = 1{}", "do ".repeat(250), " end".repeat(250));
+        assert_eq!(
+            parse_source_with(&blocks, &strict).unwrap_err(),
+            "Parse error at line 1, columns 600-601: chunk has too many syntax levels"
+        );
+        // The limit is left as it was, and so is the count of levels
+        assert!(parse_source(&blocks).is_ok());
+    }
+
+    #[test]
+    fn test_errors_name_what_was_expected() {
+        let error = |code: &str| parse_source(code).unwrap_err();
+        assert_eq!(
+            error("if x\n  print(1) end"),
+            "Parse error at line 2, columns 2-6: unexpected `print`, expected `then`"
+        );
+        assert_eq!(
+            error("for i do end"),
+            "Parse error at line 1, columns 6-7: unexpected `do`, expected one of: `=`, `,`, `in`"
+        );
+        assert_eq!(
+            error("f(1, )"),
+            "Parse error at line 1, column 5: unexpected `)`, expected an expression"
+        );
+        assert_eq!(
+            error("local t = {1, 2 +"),
+            "Parse error: unexpected end of input, expected an expression"
+        );
+        assert_eq!(
+            error("x = 1 end"),
+            "Parse error at line 1, columns 6-8: unexpected `end`, expected end of input"
+        );
+        assert_eq!(
+            error("= 5"),
+            "Parse error at line 1, column 0: unexpected `=`, expected a statement"
+        );
+
+        let tokens = tokenize_with_location("function f(a, 'b') end").unwrap();
+        let failed = parse_located(&tokens).unwrap_err();
+        assert_eq!(failed.index, Some(5));
+        assert_eq!(failed.found, Some(Token::StringLit("b".into())));
+        assert_eq!(
+            failed.expected,
+            [Expected::Name, Expected::Token(Token::Varargs)]
+        );
+        assert_eq!(tokens[5].end(), Location::new(1, 17));
+    }
+
+    /// The single expression assigned by `x = <code>`
+    fn assigned(code: &str) -> Expression {
+        let chunk = parse_source(&format!("x = {}", code)).unwrap();
+        let Some(Statement::Assignment { mut values, .. }) = chunk.statements.last().cloned()
+        else {
+            panic!("expected an assignment");
+        };
+        values.remove(0)
+    }
+
+    #[test]
+    fn test_binary_operators_follow_lua_precedence() {
+        // Lua 5.4 manual, section 3.4.8, lowest to highest
+        let levels: [&[&str]; 11] = [
+            &["or"],
+            &["and"],
+            &["<", ">", "<=", ">=", "~=", "=="],
+            &["|"],
+            &["~"],
+            &["&"],
+            &["<<", ">>"],
+            &[".."],
+            &["+", "-"],
+            &["*", "/", "//", "%"],
+            &["^"],
+        ];
+        let ops = levels
+            .iter()
+            .enumerate()
+            .flat_map(|(level, ops)| ops.iter().map(move |op| (level, *op)));
+        for (first_level, first) in ops.clone() {
+            for (second_level, second) in ops.clone() {
+                let code = format!("a {} b {} c", first, second);
+                let left_first = if first_level == second_level {
+                    !matches!(first, ".." | "^")
+                } else {
+                    first_level > second_level
+                };
+                let grouped = if left_first {
+                    format!("(a {} b) {} c", first, second)
+                } else {
+                    format!("a {} (b {} c)", first, second)
+                };
+                assert_eq!(assigned(&code), assigned(&grouped), "{}", code);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unary_operators_bind_below_power() {
+        let cases = [
+            ("-x ^ 2", "-(x ^ 2)"),
+            ("-x * 2", "(-x) * 2"),
+            ("2 ^ -x", "2 ^ (-x)"),
+            ("2 ^ -x ^ 2", "2 ^ (-(x ^ 2))"),
+            ("2 ^ 3 ^ 2", "2 ^ (3 ^ 2)"),
+            ("not a == b", "(not a) == b"),
+            ("not a and b", "(not a) and b"),
+            ("#t + 1", "(#t) + 1"),
+            ("~a & b", "(~a) & b"),
+            ("- -x ^ 2", "-(-(x ^ 2))"),
+            ("a ~ ~b", "a ~ (~b)"),
+            ("a - -b", "a - (-b)"),
+        ];
+        for (code, grouped) in cases {
+            assert_eq!(assigned(code), assigned(grouped), "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_numerals_are_parsed_once() {
+        let cases = [
+            ("10", Numeral::Integer(10)),
+            ("007", Numeral::Integer(7)),
+            ("2.5", Numeral::Float(2.5)),
+            ("3.0", Numeral::Float(3.0)),
+            // Too large for an integer, so a float like in Lua
+            ("99999999999999999999", Numeral::Float(1e20)),
+            ("1e15", Numeral::Float(1e15)),
+            ("2E-2", Numeral::Float(0.02)),
+            (".5", Numeral::Float(0.5)),
+            ("3.", Numeral::Float(3.0)),
+            ("0x10", Numeral::Integer(16)),
+            ("0xff", Numeral::Integer(255)),
+            ("0x1p4", Numeral::Float(16.0)),
+            ("0xA.8p0", Numeral::Float(10.5)),
+        ];
+        // Hex integers wrap around instead of turning into floats
+        assert_eq!(
+            assigned("0xffffffffffffffff"),
+            Expression::Number(Numeral::Integer(-1))
+        );
+        for (code, numeral) in cases {
+            assert_eq!(assigned(code), Expression::Number(numeral), "{}", code);
+            // Printed back, each stays the same kind of number
+            assert_eq!(
+                assigned(&numeral.to_string()),
+                Expression::Number(numeral),
+                "{}",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn test_string_escapes_and_long_strings() {
+        let cases = [
+            (r#""a\"b""#, "a\"b"),
+            (r#"'it\'s'"#, "it's"),
+            (r#""\65\x41\u{41}""#, "AAA"),
+            (r#""tab\tnew\nline""#, "tab\tnew\nline"),
+            ("\"a\\z  \n  b\"", "ab"),
+            ("\"one\\\ntwo\"", "one\ntwo"),
+            ("[[raw \\n]]", "raw \\n"),
+            ("[[\nfirst newline dropped]]", "first newline dropped"),
+            ("[==[ a ]] b ]=] c ]==]", " a ]] b ]=] c "),
+        ];
+        for (code, text) in cases {
+            assert_eq!(assigned(code), Expression::String(text.into()), "{}", code);
+        }
+
+        assert!(tokenize("x = \"open").is_err());
+        assert!(tokenize("x = \"line\nbreak\"").is_err());
+        assert!(tokenize(r#"x = "\q""#).is_err());
+        assert!(tokenize(r#"x = "\300""#).is_err());
+        assert!(tokenize("x = [[open").is_err());
+    }
+
+    #[test]
+    fn test_concatenated_numbers_need_spaces_not_dots() {
+        let tokens = tokenize("x = 1 .. 2").unwrap();
+        assert_eq!(tokens.len(), 5);
+        let tokens = tokenize("x = t[1]..t[2]").unwrap();
+        assert_eq!(tokens.len(), 11);
+    }
 }
@@ -0,0 +1,53 @@
+//! The limit on how deeply blocks and expressions nest
+//!
+//! Every nested block and every operand of an expression is parsed one
+//! native call deeper, so the parser counts the levels it has entered on
+//! this thread and fails with "chunk has too many syntax levels" past the
+//! limit, as PUC-Lua does, instead of parsing until memory runs out.
+
+use super::error::ParseError;
+use super::TokenSlice;
+use std::cell::Cell;
+
+/// Default for `ParseOptions::max_depth`; deep enough for any program a
+/// person writes, with the tree walks given stack as they need it
+pub const MAX_DEPTH: usize = 50_000;
+
+thread_local! {
+    /// Levels the running parse is in, and how many it may enter
+    static LEVELS: Cell<(usize, usize)> = const { Cell::new((0, MAX_DEPTH)) };
+}
+
+/// One level of nesting, left when dropped
+pub(super) struct Level;
+
+impl Level {
+    /// Enter a level at the front of `t`, failing there when that would
+    /// pass the limit
+    pub(super) fn enter(t: TokenSlice) -> Result<Level, nom::Err<ParseError>> {
+        let (depth, max) = LEVELS.get();
+        if depth >= max {
+            return Err(nom::Err::Failure(ParseError::too_deep(t)));
+        }
+        LEVELS.set((depth + 1, max));
+        Ok(Level)
+    }
+}
+
+impl Drop for Level {
+    fn drop(&mut self) {
+        LEVELS.with(|levels| {
+            let (depth, max) = levels.get();
+            levels.set((depth - 1, max));
+        });
+    }
+}
+
+/// Run `f` with the limit set to `max`, restoring the previous one after
+pub(super) fn with_max_depth<R>(max: usize, f: impl FnOnce() -> R) -> R {
+    let (depth, previous) = LEVELS.get();
+    LEVELS.set((depth, max));
+    let result = f();
+    LEVELS.with(|levels| levels.set((levels.get().0, previous)));
+    result
+}
@@ -1,440 +1,415 @@
 //! Statement parsing - assignments, control flow, declarations
 
-use nom::{
-    branch::alt,
-    combinator::opt,
-    multi::many0,
-    IResult, Parser,
-};
+use nom::{branch::alt, Parser};
 
-use super::{Token, TokenSlice, Statement, Expression, Block, ReturnStatement, token_tag};
+use super::arena::{self, BlockId};
+use super::error::{expecting, fail, Expected, PResult};
 use super::expression;
+use super::nesting::Level;
+use super::{
+    token_tag, Block, Comment, Expression, FuncName, ReturnStatement, Statement, Token, TokenSlice,
+    Trivia,
+};
+use crate::stack::with_headroom;
 
 /// Parse a single statement
-pub fn parse_statement(t: TokenSlice) -> IResult<TokenSlice, Statement> {
-    alt((
-        parse_empty_statement,
-        parse_break_statement,
-        parse_label_statement,
-        parse_goto_statement,
-        parse_do_block,
-        parse_while_loop,
-        parse_repeat_until,
-        parse_if_statement,
-        parse_for_loop,
-        parse_function_decl,
-        parse_local_statement,
-        parse_assignment_or_call,
-    ))
-    .parse(t)
+pub fn parse_statement(t: TokenSlice) -> PResult<Statement> {
+    expecting(Expected::Statement, t, |t| {
+        alt((
+            parse_empty_statement,
+            parse_break_statement,
+            parse_label_statement,
+            parse_goto_statement,
+            parse_do_block,
+            parse_while_loop,
+            parse_repeat_until,
+            parse_if_statement,
+            parse_for_loop,
+            parse_function_decl,
+            parse_local_statement,
+            parse_assignment_or_call,
+        ))
+        .parse(t)
+    })
 }
 
-fn parse_empty_statement(t: TokenSlice) -> IResult<TokenSlice, Statement> {
+fn parse_empty_statement(t: TokenSlice) -> PResult<Statement> {
     let (rest, _) = token_tag(&Token::Semicolon)(t)?;
     Ok((rest, Statement::Empty))
 }
 
-fn parse_break_statement(t: TokenSlice) -> IResult<TokenSlice, Statement> {
+fn parse_break_statement(t: TokenSlice) -> PResult<Statement> {
     let (rest, _) = token_tag(&Token::Break)(t)?;
     Ok((rest, Statement::Break))
 }
 
-fn parse_label_statement(t: TokenSlice) -> IResult<TokenSlice, Statement> {
+fn parse_label_statement(t: TokenSlice) -> PResult<Statement> {
     let (rest, _) = token_tag(&Token::DoubleColon)(t)?;
-    if let Some(Token::Identifier(name)) = rest.0.first() {
-        let name = name.clone();
-        let rest = TokenSlice(&rest.0[1..]);
-        let (rest, _) = token_tag(&Token::DoubleColon)(rest)?;
-        Ok((rest, Statement::Label(name)))
-    } else {
-        Err(nom::Err::Error(nom::error::Error::new(
-            rest,
-            nom::error::ErrorKind::Tag,
-        )))
-    }
+    let (rest, name) = ident(rest)?;
+    let (rest, _) = token_tag(&Token::DoubleColon)(rest)?;
+    Ok((rest, Statement::Label(name)))
 }
 
-fn parse_goto_statement(t: TokenSlice) -> IResult<TokenSlice, Statement> {
+fn parse_goto_statement(t: TokenSlice) -> PResult<Statement> {
     let (rest, _) = token_tag(&Token::Goto)(t)?;
-    if let Some(Token::Identifier(name)) = rest.0.first() {
-        let name = name.clone();
-        let rest = TokenSlice(&rest.0[1..]);
-        Ok((rest, Statement::Goto(name)))
-    } else {
-        Err(nom::Err::Error(nom::error::Error::new(
-            rest,
-            nom::error::ErrorKind::Tag,
-        )))
-    }
+    let (rest, name) = ident(rest)?;
+    Ok((rest, Statement::Goto(name)))
 }
 
-fn parse_do_block(t: TokenSlice) -> IResult<TokenSlice, Statement> {
+fn parse_do_block(t: TokenSlice) -> PResult<Statement> {
     let (rest, _) = token_tag(&Token::Do)(t)?;
-    let (rest, block) = parse_block(rest)?;
+    let (rest, block) = parse_nested_block(rest)?;
     let (rest, _) = token_tag(&Token::End)(rest)?;
-    Ok((rest, Statement::Do(Box::new(block))))
+    Ok((rest, Statement::Do(block)))
 }
 
-fn parse_while_loop(t: TokenSlice) -> IResult<TokenSlice, Statement> {
+fn parse_while_loop(t: TokenSlice) -> PResult<Statement> {
     let (rest, _) = token_tag(&Token::While)(t)?;
     let (rest, condition) = expression::parse_expression(rest)?;
     let (rest, _) = token_tag(&Token::Do)(rest)?;
-    let (rest, body) = parse_block(rest)?;
+    let (rest, body) = parse_nested_block(rest)?;
     let (rest, _) = token_tag(&Token::End)(rest)?;
-    Ok((
-        rest,
-        Statement::While {
-            condition,
-            body: Box::new(body),
-        },
-    ))
+    Ok((rest, Statement::While { condition, body }))
 }
 
-fn parse_repeat_until(t: TokenSlice) -> IResult<TokenSlice, Statement> {
+fn parse_repeat_until(t: TokenSlice) -> PResult<Statement> {
     let (rest, _) = token_tag(&Token::Repeat)(t)?;
-    let (rest, body) = parse_block(rest)?;
+    let (rest, body) = parse_nested_block(rest)?;
     let (rest, _) = token_tag(&Token::Until)(rest)?;
     let (rest, condition) = expression::parse_expression(rest)?;
-    Ok((
-        rest,
-        Statement::Repeat {
-            body: Box::new(body),
-            condition,
-        },
-    ))
+    Ok((rest, Statement::Repeat { body, condition }))
 }
 
-fn parse_if_statement(t: TokenSlice) -> IResult<TokenSlice, Statement> {
+fn parse_if_statement(t: TokenSlice) -> PResult<Statement> {
     let (rest, _) = token_tag(&Token::If)(t)?;
     let (rest, condition) = expression::parse_expression(rest)?;
     let (rest, _) = token_tag(&Token::Then)(rest)?;
-    let (rest, then_block) = parse_block(rest)?;
+    let (mut rest, then_block) = parse_nested_block(rest)?;
 
     // Parse elseif parts
-    let (rest, elseif_parts) = many0(|input| {
-        let (r, _) = token_tag(&Token::Elseif)(input)?;
+    let mut elseif_parts = Vec::new();
+    while let Ok((r, _)) = token_tag(&Token::Elseif)(rest) {
         let (r, cond) = expression::parse_expression(r)?;
         let (r, _) = token_tag(&Token::Then)(r)?;
-        let (r, blk) = parse_block(r)?;
-        Ok((r, (cond, blk)))
-    })
-    .parse(rest)?;
+        let (r, blk) = parse_nested_block(r)?;
+        elseif_parts.push((cond, blk));
+        rest = r;
+    }
 
     // Parse optional else block
-    let (rest, else_block) = opt(|input| {
-        let (r, _) = token_tag(&Token::Else)(input)?;
-        parse_block(r).map(|(r, b)| (r, Box::new(b)))
-    })
-    .parse(rest)?;
+    let (rest, else_block) = match token_tag(&Token::Else)(rest) {
+        Ok((r, _)) => {
+            let (r, block) = parse_nested_block(r)?;
+            (r, Some(block))
+        }
+        Err(_) => (rest, None),
+    };
 
-    let (rest, _) = token_tag(&Token::End)(rest)?;
+    let (rest, _) = match else_block {
+        Some(_) => token_tag(&Token::End)(rest)?,
+        None => token_tag(&Token::End)(rest).or_else(|_| {
+            fail(
+                rest,
+                vec![
+                    Expected::Token(Token::Elseif),
+                    Expected::Token(Token::Else),
+                    Expected::Token(Token::End),
+                ],
+            )
+        })?,
+    };
 
     Ok((
         rest,
         Statement::If {
             condition,
-            then_block: Box::new(then_block),
+            then_block,
             elseif_parts,
             else_block,
         },
     ))
 }
 
-fn parse_for_loop(t: TokenSlice) -> IResult<TokenSlice, Statement> {
+fn parse_for_loop(t: TokenSlice) -> PResult<Statement> {
     let (rest, _) = token_tag(&Token::For)(t)?;
+    let (rest, var_name) = ident(rest)?;
 
-    // Parse the first variable name
-    if let Some(Token::Identifier(var_name)) = rest.0.first() {
-        let var_name = var_name.clone();
-        let rest = TokenSlice(&rest.0[1..]);
-
-        // Try numeric for: var = start, end [, step]
-        if let Ok((r, _)) = token_tag(&Token::Equals)(rest) {
-            let (r, start) = expression::parse_expression(r)?;
-            let (r, _) = token_tag(&Token::Comma)(r)?;
-            let (r, end) = expression::parse_expression(r)?;
+    // Numeric for: var = start, end [, step]
+    if let Ok((r, _)) = token_tag(&Token::Equals)(rest) {
+        let (r, start) = expression::parse_expression(r)?;
+        let (r, _) = token_tag(&Token::Comma)(r)?;
+        let (r, end) = expression::parse_expression(r)?;
+
+        // Optional step
+        let (r, step) = match token_tag(&Token::Comma)(r) {
+            Ok((r, _)) => {
+                let (r, step) = expression::parse_expression(r)?;
+                (r, Some(step))
+            }
+            Err(_) => (r, None),
+        };
 
-            // Optional step
-            let (r, step) = opt(|input| {
-                let (r, _) = token_tag(&Token::Comma)(input)?;
-                expression::parse_expression(r)
-            })
-            .parse(r)?;
+        let (r, _) = token_tag(&Token::Do)(r)?;
+        let (r, body) = parse_nested_block(r)?;
+        let (r, _) = token_tag(&Token::End)(r)?;
 
-            let (r, _) = token_tag(&Token::Do)(r)?;
-            let (r, body) = parse_block(r)?;
-            let (r, _) = token_tag(&Token::End)(r)?;
+        return Ok((
+            r,
+            Statement::ForNumeric {
+                var: var_name,
+                start,
+                end,
+                step,
+                body,
+            },
+        ));
+    }
 
-            return Ok((
-                r,
-                Statement::ForNumeric {
-                    var: var_name,
-                    start,
-                    end,
-                    step,
-                    body: Box::new(body),
-                },
-            ));
+    // Generic for: var1 [, var2, ...] in iterables
+    let mut vars = vec![var_name];
+    let rest = match token_tag(&Token::Comma)(rest) {
+        Ok((r, _)) => {
+            let (r, more_vars) = parse_namelist(r)?;
+            vars.extend(more_vars);
+            token_tag(&Token::In)(r)?.0
         }
-
-        // Try generic for: [var1,] var2 [, var3, ...] in iterables
-        let (rest, more_vars) = opt(|input| {
-            let (r, _) = token_tag(&Token::Comma)(input)?;
-            parse_namelist(r)
-        })
-        .parse(rest)?;
-
-        if let Ok((r, _)) = token_tag(&Token::In)(rest) {
-            let (r, iterables) = expression::parse_expression_list(r)?;
-            let (r, _) = token_tag(&Token::Do)(r)?;
-            let (r, body) = parse_block(r)?;
-            let (r, _) = token_tag(&Token::End)(r)?;
-
-            let mut vars = vec![var_name];
-            if let Some(mut extra_vars) = more_vars {
-                vars.append(&mut extra_vars);
-            }
-
-            return Ok((
-                r,
-                Statement::ForGeneric {
-                    vars,
-                    iterables,
-                    body: Box::new(body),
-                },
-            ));
+        Err(_) => {
+            token_tag(&Token::In)(rest)
+                .or_else(|_| {
+                    fail(
+                        rest,
+                        vec![
+                            Expected::Token(Token::Equals),
+                            Expected::Token(Token::Comma),
+                            Expected::Token(Token::In),
+                        ],
+                    )
+                })?
+                .0
         }
-    }
+    };
+    let (r, iterables) = expression::parse_expression_list(rest)?;
+    let (r, _) = token_tag(&Token::Do)(r)?;
+    let (r, body) = parse_nested_block(r)?;
+    let (r, _) = token_tag(&Token::End)(r)?;
 
-    Err(nom::Err::Error(nom::error::Error::new(
-        t,
-        nom::error::ErrorKind::Alt,
-    )))
+    Ok((
+        r,
+        Statement::ForGeneric {
+            vars,
+            iterables,
+            body,
+        },
+    ))
 }
 
-fn parse_function_decl(t: TokenSlice) -> IResult<TokenSlice, Statement> {
+fn parse_function_decl(t: TokenSlice) -> PResult<Statement> {
     let (rest, _) = token_tag(&Token::Function)(t)?;
+    let (rest, name) = parse_funcname(rest)?;
+    let (rest, mut body) = expression::parse_funcbody(rest)?;
+    if name.method {
+        body.params.insert(0, "self".to_string());
+    }
+    let body = arena::alloc_function(body);
+    Ok((rest, Statement::FunctionDecl { name, body }))
+}
 
-    // Parse function name - can be simple (foo) or qualified (M.test, a.b.c, or a:method)
-    if let Some(Token::Identifier(name)) = rest.0.first() {
-        let mut full_name = name.clone();
-        let mut rest = TokenSlice(&rest.0[1..]);
-
-        // Handle qualified names like M.test or a:method
-        loop {
-            if let Some(Token::Dot) = rest.0.first() {
-                rest = TokenSlice(&rest.0[1..]);
-                if let Some(Token::Identifier(member)) = rest.0.first() {
-                    full_name.push('.');
-                    full_name.push_str(member);
-                    rest = TokenSlice(&rest.0[1..]);
-                } else {
-                    return Err(nom::Err::Error(nom::error::Error::new(
-                        rest,
-                        nom::error::ErrorKind::Tag,
-                    )));
-                }
-            } else if let Some(Token::Colon) = rest.0.first() {
-                // Method definition (a:b becomes a.b with self parameter)
-                rest = TokenSlice(&rest.0[1..]);
-                if let Some(Token::Identifier(method)) = rest.0.first() {
-                    full_name.push(':');
-                    full_name.push_str(method);
-                    rest = TokenSlice(&rest.0[1..]);
-                } else {
-                    return Err(nom::Err::Error(nom::error::Error::new(
-                        rest,
-                        nom::error::ErrorKind::Tag,
-                    )));
-                }
-                break;
-            } else {
-                break;
-            }
-        }
+/// funcname ::= Name {'.' Name} [':' Name]
+fn parse_funcname(t: TokenSlice) -> PResult<FuncName> {
+    let (mut rest, first) = ident(t)?;
+    let mut path = vec![first];
+    while let Ok((r, _)) = token_tag(&Token::Dot)(rest) {
+        let (r, field) = ident(r)?;
+        path.push(field);
+        rest = r;
+    }
+    let mut method = false;
+    if let Ok((r, _)) = token_tag(&Token::Colon)(rest) {
+        let (r, name) = ident(r)?;
+        path.push(name);
+        method = true;
+        rest = r;
+    }
+    Ok((rest, FuncName { path, method }))
+}
 
-        let (rest, body) = expression::parse_funcbody(rest)?;
-        Ok((
-            rest,
-            Statement::FunctionDecl {
-                name: full_name,
-                body: Box::new(body),
-            },
-        ))
-    } else {
-        Err(nom::Err::Error(nom::error::Error::new(
-            rest,
-            nom::error::ErrorKind::Tag,
-        )))
+/// Parse a single `Name` token
+fn ident(t: TokenSlice) -> PResult<String> {
+    match t.0.first() {
+        Some(Token::Identifier(name)) => Ok((t.advance(1), name.to_string())),
+        _ => fail(t, vec![Expected::Name]),
     }
 }
 
-fn parse_local_statement(t: TokenSlice) -> IResult<TokenSlice, Statement> {
+fn parse_local_statement(t: TokenSlice) -> PResult<Statement> {
     let (rest, _) = token_tag(&Token::Local)(t)?;
 
     // Check if it's local function
     if let Ok((r, _)) = token_tag(&Token::Function)(rest) {
-        if let Some(Token::Identifier(name)) = r.0.first() {
-            let name = name.clone();
-            let r = TokenSlice(&r.0[1..]);
-            let (r, body) = expression::parse_funcbody(r)?;
-            return Ok((
-                r,
-                Statement::LocalFunction {
-                    name,
-                    body: Box::new(body),
-                },
-            ));
-        }
+        let (r, name) = ident(r)?;
+        let (r, body) = expression::parse_funcbody(r)?;
+        let body = arena::alloc_function(body);
+        return Ok((r, Statement::LocalFunction { name, body }));
     }
 
     // Otherwise it's local vars [= values]
     let (rest, names) = parse_namelist(rest)?;
-    let (rest, values) = opt(|input| {
-        let (r, _) = token_tag(&Token::Equals)(input)?;
-        expression::parse_expression_list(r)
-    })
-    .parse(rest)?;
+    let (rest, values) = match token_tag(&Token::Equals)(rest) {
+        Ok((r, _)) => {
+            let (r, values) = expression::parse_expression_list(r)?;
+            (r, Some(values))
+        }
+        Err(_) => (rest, None),
+    };
 
     Ok((rest, Statement::LocalVars { names, values }))
 }
 
-fn parse_assignment_or_call(t: TokenSlice) -> IResult<TokenSlice, Statement> {
+fn parse_assignment_or_call(t: TokenSlice) -> PResult<Statement> {
     let (rest, first_expr) = expression::parse_prefix_exp(t)?;
 
     // Check if this is an assignment by looking for more variables or =
-    if let Ok((r, _)) = token_tag(&Token::Comma)(rest) {
-        // Multiple variables: var1, var2, ... = ...
-        let (r, rest_vars) = many0(|input| {
-            let (r, expr) = expression::parse_prefix_exp(input)?;
-            let (r, _) = token_tag(&Token::Comma)(r)?;
-            Ok((r, expr))
-        })
-        .parse(r)?;
-
-        // Now we must have = and values
-        let (r, final_expr) = expression::parse_prefix_exp(r)?;
-        let (r, _) = token_tag(&Token::Equals)(r)?;
-        let (r, values) = expression::parse_expression_list(r)?;
-
-        let mut variables = vec![first_expr];
-        variables.extend(rest_vars);
-        variables.push(final_expr);
-
-        return Ok((r, Statement::Assignment { variables, values }));
+    let mut variables = vec![first_expr];
+    let mut rest = rest;
+    while let Ok((r, _)) = token_tag(&Token::Comma)(rest) {
+        let (r, expr) = expression::parse_prefix_exp(r)?;
+        variables.push(expr);
+        rest = r;
     }
 
     // Try assignment: varlist = explist
     if let Ok((r, _)) = token_tag(&Token::Equals)(rest) {
+        // Calls and parenthesized expressions are values, so `=` is the
+        // token that cannot follow them
+        if !variables.iter().all(is_assignable) {
+            return fail(rest, Vec::new());
+        }
         let (r, values) = expression::parse_expression_list(r)?;
-        // Collect first_expr as a variable
-        return Ok((
-            r,
-            Statement::Assignment {
-                variables: vec![first_expr],
-                values,
-            },
-        ));
+        return Ok((r, Statement::Assignment { variables, values }));
     }
 
     // Try function call (prefix expression that is a function call)
-    match &first_expr {
-        Expression::FunctionCall { .. } | Expression::MethodCall { .. } => {
-            Ok((rest, Statement::FunctionCall(first_expr)))
+    match variables.as_slice() {
+        [Expression::FunctionCall { .. } | Expression::MethodCall { .. }] => {
+            let call = variables.pop().expect("one expression");
+            Ok((rest, Statement::FunctionCall(call)))
         }
-        _ => Err(nom::Err::Error(nom::error::Error::new(
-            t,
-            nom::error::ErrorKind::Alt,
-        ))),
+        [_] => fail(
+            rest,
+            vec![Expected::Token(Token::Equals), Expected::Arguments],
+        ),
+        _ => fail(rest, vec![Expected::Token(Token::Equals)]),
     }
 }
 
-pub fn parse_return_statement(t: TokenSlice) -> IResult<TokenSlice, ReturnStatement> {
+/// Whether a prefix expression names a variable or table slot; calls and
+/// parenthesized calls are values only
+fn is_assignable(expr: &Expression) -> bool {
+    matches!(
+        expr,
+        Expression::Identifier(_)
+            | Expression::TableIndexing { .. }
+            | Expression::FieldAccess { .. }
+    )
+}
+
+pub fn parse_return_statement(t: TokenSlice) -> PResult<ReturnStatement> {
     let (rest, _) = token_tag(&Token::Return).parse(t)?;
-    let (rest, list) = opt(expression::parse_expression_list).parse(rest)?;
-    let (rest, _) = opt(token_tag(&Token::Semicolon)).parse(rest)?;
+    // The values are optional; only a block's end or `;` can follow without them
+    let (rest, list) = match rest.0.first() {
+        None | Some(Token::End | Token::Else | Token::Elseif | Token::Until | Token::Semicolon) => {
+            (rest, Vec::new())
+        }
+        Some(_) => expression::parse_expression_list(rest)?,
+    };
+    let rest = token_tag(&Token::Semicolon)(rest).map_or(rest, |(r, _)| r);
     Ok((
         rest,
         ReturnStatement {
-            expression_list: list.unwrap_or_default(),
+            expression_list: list,
         },
     ))
 }
 
 /// Parse name list: `name {',' name}`
-fn parse_namelist(t: TokenSlice) -> IResult<TokenSlice, Vec<String>> {
-    let (rest, first_name) = if let Some(Token::Identifier(name)) = t.0.first() {
-        (TokenSlice(&t.0[1..]), name.clone())
-    } else {
-        return Err(nom::Err::Error(nom::error::Error::new(
-            t,
-            nom::error::ErrorKind::Tag,
-        )));
-    };
-
-    let (rest, rest_names) = many0(|input| {
-        let (r, _) = token_tag(&Token::Comma)(input)?;
-        if let Some(Token::Identifier(name)) = r.0.first() {
-            Ok((TokenSlice(&r.0[1..]), name.clone()))
-        } else {
-            Err(nom::Err::Error(nom::error::Error::new(
-                r,
-                nom::error::ErrorKind::Tag,
-            )))
-        }
-    })
-    .parse(rest)?;
-
+pub(super) fn parse_namelist(t: TokenSlice) -> PResult<Vec<String>> {
+    let (mut rest, first_name) = ident(t)?;
     let mut result = vec![first_name];
-    result.extend(rest_names);
+    while let Ok((r, _)) = token_tag(&Token::Comma)(rest) {
+        let (r, name) = ident(r)?;
+        result.push(name);
+        rest = r;
+    }
     Ok((rest, result))
 }
 
+/// Parse a block inside a statement or function, storing it in the arena
+pub(super) fn parse_nested_block(t: TokenSlice) -> PResult<BlockId> {
+    let (rest, block) = parse_block(t)?;
+    Ok((rest, arena::alloc_block(block)))
+}
+
 /// Parse a block of statements, stopping at block-terminating tokens
 /// Block terminators: 'end', 'else', 'elseif', 'until', EOF
-pub fn parse_block(t: TokenSlice) -> IResult<TokenSlice, Block> {
+pub fn parse_block(t: TokenSlice) -> PResult<Block> {
+    let _level = Level::enter(t)?;
     let mut statements = Vec::new();
+    let mut lines = Vec::new();
+    let mut columns = Vec::new();
+    let mut trivia = Vec::new();
     let mut current = t;
+    // Line the previous statement ended on, for its trailing comment
+    let mut last_line = None;
 
     // Parse statements until we hit a block terminator
     loop {
+        // Comments before the next statement, or before the end of the block
+        if !current.2.is_empty() {
+            attach_comments(&mut trivia, last_line, current.comments());
+        }
+
         // Check if we've hit a block terminator or EOF
         if current.0.is_empty() {
             break;
         }
 
         // Check for block terminating tokens
-        if let Some(token) = current.0.first() {
-            match token {
-                Token::End | Token::Else | Token::Elseif | Token::Until => {
-                    break;
-                }
-                _ => {}
-            }
+        if let Some(Token::End | Token::Else | Token::Elseif | Token::Until) = current.0.first() {
+            break;
         }
 
-        // Try to parse a return statement first (since it can be followed by anything)
-        if let Ok((rest, ret_stmt)) = parse_return_statement(current) {
+        // A return statement must be the last in its block
+        if let Some(Token::Return) = current.0.first() {
+            let (rest, ret_stmt) = parse_return_statement(current)?;
+            if !current.1.is_empty() {
+                lines.push(current.line());
+                columns.push(current.column());
+            }
+            if !current.2.is_empty() {
+                attach_comments(&mut trivia, Some(end_line(current, rest)), rest.comments());
+            }
             return Ok((
                 rest,
                 Block {
                     statements,
                     return_statement: Some(ret_stmt),
+                    lines,
+                    columns,
+                    trivia,
                 },
             ));
         }
 
-        // Try to parse a regular statement
-        match parse_statement(current) {
-            Ok((rest, stmt)) => {
-                statements.push(stmt);
-                current = rest;
-            }
-            Err(_) => {
-                // If we can't parse a statement, we're done with the block
-                break;
-            }
+        // Only the tokens above end a block, so anything else must start
+        // a statement
+        let (rest, stmt) = with_headroom(|| parse_statement(current))?;
+        if !current.1.is_empty() {
+            lines.push(current.line());
+            columns.push(current.column());
+            last_line = Some(end_line(current, rest));
         }
+        statements.push(stmt);
+        current = rest;
     }
 
     Ok((
@@ -442,6 +417,33 @@ pub fn parse_block(t: TokenSlice) -> IResult<TokenSlice, Block> {
         Block {
             statements,
             return_statement: None,
+            lines,
+            columns,
+            trivia,
         },
     ))
 }
+
+/// Line of the last token in `from` before `rest`
+fn end_line(from: TokenSlice, rest: TokenSlice) -> usize {
+    let consumed = from.0.len() - rest.0.len();
+    from.1[consumed.saturating_sub(1)].line
+}
+
+/// Start the trivia of the next statement, or of the end of the block
+///
+/// Comments on `last_line`, where the previous statement ended, trail that
+/// statement; the others lead the next one.
+fn attach_comments(trivia: &mut Vec<Trivia>, last_line: Option<usize>, comments: &[Comment]) {
+    let split = comments
+        .iter()
+        .position(|c| Some(c.line) != last_line)
+        .unwrap_or(comments.len());
+    if let Some(previous) = trivia.last_mut().filter(|_| last_line.is_some()) {
+        previous.trailing.extend_from_slice(&comments[..split]);
+    }
+    trivia.push(Trivia {
+        leading: comments[split..].to_vec(),
+        trailing: Vec::new(),
+    });
+}
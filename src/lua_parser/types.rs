@@ -0,0 +1,616 @@
+//! AST Types for Lua parser
+//!
+//! Names and string literals are `Rc<str>` values interned by the tokenizer
+//! (see `crate::intern`), so cloning them into the AST and into runtime values
+//! does not allocate. Nested blocks and function bodies live in the chunk's
+//! `LuaArena` and are referred to by id (see `super::arena`).
+
+use super::arena::{BlockId, FunctionId};
+use super::helpers::{KEYWORDS, SYMBOLS};
+use crate::lua_string::LuaString;
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    And,
+    Break,
+    Do,
+    Else,
+    Elseif,
+    End,
+    False,
+    For,
+    Function,
+    Goto,
+    If,
+    In,
+    Local,
+    Nil,
+    Not,
+    Or,
+    Repeat,
+    Return,
+    Then,
+    True,
+    Until,
+    While,
+    // Symbols
+    Semicolon,
+    Equals,
+    Comma,
+    Dot,
+    Colon,
+    DoubleColon,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    DoubleSlash,
+    Caret,
+    Percent,
+    Ampersand,
+    Tilde,
+    Pipe,
+    RShift,
+    LShift,
+    Concat,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+    Eq,
+    Neq,
+    Hash,
+    Varargs,
+    // Values
+    Identifier(Rc<str>),
+    Number(String),
+    StringLit(LuaString),
+}
+
+/// The token as it appears in source; string literals are shown quoted
+/// with escapes, however they were written
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Identifier(name) => write!(f, "{}", name),
+            Token::Number(text) => write!(f, "{}", text),
+            Token::StringLit(text) => write!(f, "{:?}", text.to_str_lossy()),
+            other => {
+                let text = KEYWORDS
+                    .entries()
+                    .chain(SYMBOLS.entries())
+                    .find(|(_, token)| *token == other)
+                    .map_or("?", |(text, _)| text);
+                write!(f, "{}", text)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Block {
+    pub statements: Vec<Statement>,
+    pub return_statement: Option<ReturnStatement>,
+    /// Source line of each statement, parallel to `statements`, followed by
+    /// the line of the return statement if there is one; empty when the
+    /// block was parsed without locations
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lines: Vec<usize>,
+    /// Column each statement starts at, parallel to `lines`; kept only by
+    /// the parser, for tools such as the linter that point into the source
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub columns: Vec<usize>,
+    /// Comments around each statement, parallel to `statements` and the
+    /// return statement like `lines`, followed by one more entry whose
+    /// `leading` comments sit before the end of the block; empty unless
+    /// parsed with `ParseOptions::comments`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trivia: Vec<Trivia>,
+}
+
+/// Comments attached to a statement
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Trivia {
+    /// Comments between the previous statement and this one
+    pub leading: Vec<Comment>,
+    /// A comment after the statement, on the line it ends
+    pub trailing: Vec<Comment>,
+}
+
+/// A `--` comment and where it sits in the source
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Comment {
+    /// Text after the `--`: the rest of the line, or the whole bracketed
+    /// `[[ ... ]]` of a long comment
+    pub text: String,
+    /// 1-based line the comment starts on
+    pub line: usize,
+    /// 0-based column of the `--`
+    pub column: usize,
+    /// 0-based column just past the last character, on the line the
+    /// comment ends
+    pub end_column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Statement {
+    Empty,
+    Assignment {
+        variables: Vec<Expression>,
+        values: Vec<Expression>,
+    },
+    FunctionCall(Expression),
+    Break,
+    Label(String),
+    Goto(String),
+    Do(BlockId),
+    While {
+        condition: Expression,
+        body: BlockId,
+    },
+    Repeat {
+        body: BlockId,
+        condition: Expression,
+    },
+    If {
+        condition: Expression,
+        then_block: BlockId,
+        elseif_parts: Vec<(Expression, BlockId)>,
+        else_block: Option<BlockId>,
+    },
+    ForNumeric {
+        var: String,
+        start: Expression,
+        end: Expression,
+        step: Option<Expression>,
+        body: BlockId,
+    },
+    ForGeneric {
+        vars: Vec<String>,
+        iterables: Vec<Expression>,
+        body: BlockId,
+    },
+    FunctionDecl {
+        name: FuncName,
+        body: FunctionId,
+    },
+    LocalFunction {
+        name: String,
+        body: FunctionId,
+    },
+    LocalVars {
+        names: Vec<String>,
+        values: Option<Vec<Expression>>,
+    },
+    // Resolved forms, produced by the resolver and never by the parser
+    LocalSlots {
+        slots: Vec<usize>,
+        values: Option<Vec<Expression>>,
+    },
+    ForNumericSlot {
+        slot: usize,
+        start: Expression,
+        end: Expression,
+        step: Option<Expression>,
+        body: BlockId,
+    },
+    ForGenericSlots {
+        slots: Vec<usize>,
+        iterables: Vec<Expression>,
+        body: BlockId,
+    },
+}
+
+impl Statement {
+    /// Short name of the statement's kind; resolved forms share the name
+    /// of the form they came from
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Statement::Empty => "empty",
+            Statement::Assignment { .. } => "assignment",
+            Statement::FunctionCall(_) => "call",
+            Statement::Break => "break",
+            Statement::Label(_) => "label",
+            Statement::Goto(_) => "goto",
+            Statement::Do(_) => "do",
+            Statement::While { .. } => "while",
+            Statement::Repeat { .. } => "repeat",
+            Statement::If { .. } => "if",
+            Statement::ForNumeric { .. } | Statement::ForNumericSlot { .. } => "numeric for",
+            Statement::ForGeneric { .. } | Statement::ForGenericSlots { .. } => "generic for",
+            Statement::FunctionDecl { .. } => "function",
+            Statement::LocalFunction { .. } => "local function",
+            Statement::LocalVars { .. } | Statement::LocalSlots { .. } => "local",
+        }
+    }
+}
+
+/// Target of `function a.b.c()` or `function a.b:c()`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FuncName {
+    /// `a`, `b`, `c`: the first segment is a variable, the rest are fields
+    pub path: Vec<String>,
+    /// Declared with `:`; the parser puts the implicit `self` first in the
+    /// body's parameters
+    pub method: bool,
+}
+
+impl FuncName {
+    /// The variable the declaration assigns through
+    pub fn base(&self) -> &str {
+        &self.path[0]
+    }
+
+    /// Fields walked from the base; the last one receives the function
+    pub fn fields(&self) -> &[String] {
+        &self.path[1..]
+    }
+
+    /// A plain `function name()` with no fields
+    pub fn is_simple(&self) -> bool {
+        self.path.len() == 1
+    }
+}
+
+impl std::fmt::Display for FuncName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, segment) in self.path.iter().enumerate() {
+            if i > 0 {
+                let sep = if self.method && i == self.path.len() - 1 {
+                    ':'
+                } else {
+                    '.'
+                };
+                write!(f, "{}", sep)?;
+            }
+            write!(f, "{}", segment)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReturnStatement {
+    pub expression_list: Vec<Expression>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Expression {
+    Nil,
+    Boolean(bool),
+    Number(Numeral),
+    String(LuaString),
+    Varargs,
+    Identifier(Rc<str>),
+    BinaryOp {
+        left: Box<Expression>,
+        op: BinaryOp,
+        right: Box<Expression>,
+    },
+    UnaryOp {
+        op: UnaryOp,
+        operand: Box<Expression>,
+    },
+    TableIndexing {
+        object: Box<Expression>,
+        index: Box<Expression>,
+    },
+    FieldAccess {
+        object: Box<Expression>,
+        field: Rc<str>,
+    },
+    FunctionCall {
+        function: Box<Expression>,
+        args: Vec<Expression>,
+    },
+    MethodCall {
+        object: Box<Expression>,
+        method: Rc<str>,
+        args: Vec<Expression>,
+    },
+    TableConstructor {
+        fields: Vec<Field>,
+    },
+    FunctionDef(FunctionId),
+    /// A call or `...` in parentheses, adjusted to exactly one value.
+    /// Parentheses around anything else change nothing and are not kept.
+    Paren(Box<Expression>),
+    // Resolved variable references, produced by the resolver
+    Local {
+        name: Rc<str>,
+        slot: usize,
+    },
+    Upvalue {
+        name: Rc<str>,
+        index: usize,
+    },
+    Global(Rc<str>),
+}
+
+/// Copied one level per native call like `Drop` below, so cloning a deep
+/// nest moves onto a fresh stack segment instead of overflowing
+impl Clone for Expression {
+    fn clone(&self) -> Self {
+        crate::stack::with_headroom(|| match self {
+            Expression::Nil => Expression::Nil,
+            Expression::Boolean(b) => Expression::Boolean(*b),
+            Expression::Number(n) => Expression::Number(*n),
+            Expression::String(s) => Expression::String(s.clone()),
+            Expression::Varargs => Expression::Varargs,
+            Expression::Identifier(name) => Expression::Identifier(Rc::clone(name)),
+            Expression::BinaryOp { left, op, right } => Expression::BinaryOp {
+                left: left.clone(),
+                op: op.clone(),
+                right: right.clone(),
+            },
+            Expression::UnaryOp { op, operand } => Expression::UnaryOp {
+                op: op.clone(),
+                operand: operand.clone(),
+            },
+            Expression::TableIndexing { object, index } => Expression::TableIndexing {
+                object: object.clone(),
+                index: index.clone(),
+            },
+            Expression::FieldAccess { object, field } => Expression::FieldAccess {
+                object: object.clone(),
+                field: Rc::clone(field),
+            },
+            Expression::FunctionCall { function, args } => Expression::FunctionCall {
+                function: function.clone(),
+                args: args.clone(),
+            },
+            Expression::MethodCall {
+                object,
+                method,
+                args,
+            } => Expression::MethodCall {
+                object: object.clone(),
+                method: Rc::clone(method),
+                args: args.clone(),
+            },
+            Expression::TableConstructor { fields } => Expression::TableConstructor {
+                fields: fields.clone(),
+            },
+            Expression::FunctionDef(id) => Expression::FunctionDef(*id),
+            Expression::Paren(inner) => Expression::Paren(inner.clone()),
+            Expression::Local { name, slot } => Expression::Local {
+                name: Rc::clone(name),
+                slot: *slot,
+            },
+            Expression::Upvalue { name, index } => Expression::Upvalue {
+                name: Rc::clone(name),
+                index: *index,
+            },
+            Expression::Global(name) => Expression::Global(Rc::clone(name)),
+        })
+    }
+}
+
+/// Deeply nested expressions are dropped one level per native call, so a
+/// deep enough nest is freed on a fresh stack segment instead of
+/// overflowing
+impl Drop for Expression {
+    fn drop(&mut self) {
+        if crate::stack::running_low() {
+            let expr = std::mem::replace(self, Expression::Nil);
+            crate::stack::with_headroom(move || drop(expr));
+        }
+    }
+}
+
+/// A numeric literal, converted from its source text by the parser so
+/// running it parses nothing
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Numeral {
+    Integer(i64),
+    Float(f64),
+}
+
+impl Numeral {
+    /// The literal's value: a whole number without a fractional part or
+    /// exponent is an integer, unless it is too large for one, and anything
+    /// else a float. `None` when `text` is not a numeral at all.
+    pub fn parse(text: &str) -> Option<Numeral> {
+        if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            return Self::parse_hex(hex);
+        }
+        if let Ok(n) = text.parse::<i64>() {
+            return Some(Numeral::Integer(n));
+        }
+        text.parse::<f64>().ok().map(Numeral::Float)
+    }
+
+    /// A hexadecimal numeral after its `0x`; whole ones wrap around on
+    /// overflow as in Lua, so `0xffffffffffffffff` is -1
+    fn parse_hex(text: &str) -> Option<Numeral> {
+        if !text.is_empty() && text.bytes().all(|b| b.is_ascii_hexdigit()) {
+            let n = text.chars().fold(0u64, |n, c| {
+                n.wrapping_mul(16)
+                    .wrapping_add(u64::from(c.to_digit(16).unwrap_or(0)))
+            });
+            return Some(Numeral::Integer(n as i64));
+        }
+        let (mantissa, exponent) = match text.find(['p', 'P']) {
+            Some(p) => (&text[..p], text[p + 1..].parse::<i32>().ok()?),
+            None => (text, 0),
+        };
+        let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        if whole.is_empty() && fraction.is_empty() {
+            return None;
+        }
+        let mut value = 0.0;
+        for c in whole.chars() {
+            value = value * 16.0 + f64::from(c.to_digit(16)?);
+        }
+        let mut scale = 1.0 / 16.0;
+        for c in fraction.chars() {
+            value += f64::from(c.to_digit(16)?) * scale;
+            scale /= 16.0;
+        }
+        Some(Numeral::Float(value * 2f64.powi(exponent)))
+    }
+
+    pub fn to_f64(self) -> f64 {
+        match self {
+            Numeral::Integer(n) => n as f64,
+            Numeral::Float(n) => n,
+        }
+    }
+}
+
+/// Floats compare by bit pattern, so every literal equals itself and
+/// `Expression` can stay `Eq`
+impl PartialEq for Numeral {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Numeral::Integer(a), Numeral::Integer(b)) => a == b,
+            (Numeral::Float(a), Numeral::Float(b)) => a.to_bits() == b.to_bits(),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Numeral {}
+
+impl std::fmt::Display for Numeral {
+    /// Source text that parses back to the same literal; whole floats keep
+    /// a `.0` so they stay floats
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Numeral::Integer(n) => write!(f, "{}", n),
+            Numeral::Float(n) if n.fract() == 0.0 => write!(f, "{:.1}", n),
+            Numeral::Float(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    FloorDivide,
+    Modulo,
+    Power,
+    Concat,
+    BitAnd,
+    BitOr,
+    BitXor,
+    LeftShift,
+    RightShift,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+    Eq,
+    Neq,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    /// Binding power, from `or` at 1 up to `^` at 12; higher binds tighter
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Lt
+            | BinaryOp::Lte
+            | BinaryOp::Gt
+            | BinaryOp::Gte
+            | BinaryOp::Eq
+            | BinaryOp::Neq => 3,
+            BinaryOp::BitOr => 4,
+            BinaryOp::BitXor => 5,
+            BinaryOp::BitAnd => 6,
+            BinaryOp::LeftShift | BinaryOp::RightShift => 7,
+            BinaryOp::Concat => 8,
+            BinaryOp::Add | BinaryOp::Subtract => 9,
+            BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::FloorDivide | BinaryOp::Modulo => 10,
+            BinaryOp::Power => 12,
+        }
+    }
+
+    /// `..` and `^` group from the right; every other operator from the left
+    pub fn is_right_associative(&self) -> bool {
+        matches!(self, BinaryOp::Concat | BinaryOp::Power)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UnaryOp {
+    Minus,
+    Not,
+    BitNot,
+    Length,
+}
+
+impl UnaryOp {
+    /// Binding power of every unary operator, between `*` and `^`, so
+    /// `-x ^ 2` is `-(x ^ 2)` but `-x * 2` is `(-x) * 2`
+    pub const PRECEDENCE: u8 = 11;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Field {
+    pub key: FieldKey,
+    pub value: Expression,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FieldKey {
+    /// `[exp] = value`
+    Bracket(Box<Expression>),
+    /// `name = value`
+    Identifier(Rc<str>),
+    /// A bare `value`: its 1-based position among the positional fields
+    Index(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FunctionBody {
+    pub params: Vec<String>,
+    pub varargs: bool,
+    pub block: BlockId,
+    /// Slot layout filled in by the resolver; `None` runs the body with
+    /// name-based scopes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<FrameLayout>,
+    /// First and last source line, when parsed with locations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lines: Option<(usize, usize)>,
+}
+
+/// Where a resolved function keeps its variables
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct FrameLayout {
+    /// Number of local slots, parameters first
+    pub slot_count: usize,
+    /// Source of each upvalue, in `Expression::Upvalue` index order
+    pub captures: Vec<Capture>,
+    /// Name of the first local declared in each slot; sibling blocks reuse
+    /// slots, so later locals in a slot go by the first one's name
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub slot_names: Vec<Rc<str>>,
+    /// Name of each upvalue, parallel to `captures`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub capture_names: Vec<Rc<str>>,
+}
+
+/// Where a closure's upvalue comes from in the enclosing function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Capture {
+    /// A local slot of the enclosing function
+    Slot(usize),
+    /// An upvalue of the enclosing function
+    Upvalue(usize),
+}
@@ -0,0 +1,220 @@
+/// Lua strings: immutable, shared byte sequences
+///
+/// A Lua string holds any bytes, not just UTF-8: `"\xff"` is one byte long
+/// and `string.sub` cuts wherever it is asked to. Text is kept as written,
+/// so source files and host strings give valid UTF-8 strings, and the
+/// `utf8` library is the way to work with whole characters. Where a string
+/// has to become Rust text (error messages, JSON, host conversions) it is
+/// decoded lossily, with U+FFFD for invalid sequences; output streams get
+/// the bytes themselves.
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Deref;
+use std::rc::Rc;
+
+/// The bytes of a Lua string, cheap to clone
+#[derive(Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LuaString(Rc<[u8]>);
+
+impl LuaString {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// The string as text when it is valid UTF-8
+    pub fn to_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.0).ok()
+    }
+
+    /// The string as text, with U+FFFD for invalid UTF-8
+    pub fn to_str_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.0)
+    }
+
+    /// The characters of the valid UTF-8 parts, and each byte of the rest
+    /// on its own
+    pub fn chars_or_bytes(&self) -> impl Iterator<Item = Result<char, u8>> + '_ {
+        self.0.utf8_chunks().flat_map(|chunk| {
+            chunk
+                .valid()
+                .chars()
+                .map(Ok)
+                .chain(chunk.invalid().iter().map(|&b| Err(b)))
+        })
+    }
+
+    /// Whether both are the same allocation, as interned strings are
+    pub fn ptr_eq(&self, other: &LuaString) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Deref for LuaString {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for LuaString {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<&str> for LuaString {
+    fn from(s: &str) -> Self {
+        LuaString(Rc::from(s.as_bytes()))
+    }
+}
+
+impl From<String> for LuaString {
+    fn from(s: String) -> Self {
+        LuaString(Rc::from(s.into_bytes()))
+    }
+}
+
+/// Shares the allocation, so interned text stays interned
+impl From<Rc<str>> for LuaString {
+    fn from(s: Rc<str>) -> Self {
+        LuaString(Rc::from(s))
+    }
+}
+
+impl From<Cow<'_, str>> for LuaString {
+    fn from(s: Cow<'_, str>) -> Self {
+        LuaString(Rc::from(s.as_bytes()))
+    }
+}
+
+impl From<&[u8]> for LuaString {
+    fn from(bytes: &[u8]) -> Self {
+        LuaString(Rc::from(bytes))
+    }
+}
+
+impl From<Vec<u8>> for LuaString {
+    fn from(bytes: Vec<u8>) -> Self {
+        LuaString(Rc::from(bytes))
+    }
+}
+
+impl PartialEq<str> for LuaString {
+    fn eq(&self, other: &str) -> bool {
+        *self.0 == *other.as_bytes()
+    }
+}
+
+impl PartialEq<&str> for LuaString {
+    fn eq(&self, other: &&str) -> bool {
+        *self.0 == *other.as_bytes()
+    }
+}
+
+/// The text, with U+FFFD for invalid UTF-8
+impl fmt::Display for LuaString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_str_lossy())
+    }
+}
+
+/// Quoted, with bytes outside printable ASCII escaped the way Lua writes
+/// them
+impl fmt::Debug for LuaString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{}\"", self.0.escape_ascii())
+    }
+}
+
+/// Text when the bytes are UTF-8, an array of bytes otherwise
+impl Serialize for LuaString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.to_str() {
+            Some(text) => serializer.serialize_str(text),
+            None => serializer.collect_seq(self.0.iter()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for LuaString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Text(String),
+            Bytes(Vec<u8>),
+        }
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Text(text) => text.into(),
+            Repr::Bytes(bytes) => bytes.into(),
+        })
+    }
+}
+
+/// The UTF-8 encoding of `code` as Lua extends it: up to six bytes, for
+/// values below 2^31, surrogates included
+pub fn utf8_encode(code: u32) -> Option<Vec<u8>> {
+    if code < 0x80 {
+        return Some(vec![code as u8]);
+    }
+    if code >= 0x8000_0000 {
+        return None;
+    }
+    // Continuation bytes carry six bits each; the first byte gets what is
+    // left, after a marker of as many ones as there are bytes
+    let mut tail = Vec::new();
+    let mut rest = code;
+    let mut first_max = 0x3f;
+    while rest > first_max {
+        tail.push(0x80 | (rest & 0x3f) as u8);
+        rest >>= 6;
+        first_max >>= 1;
+    }
+    let marker = !((first_max << 1) | 1) as u8;
+    let mut out = vec![marker | rest as u8];
+    out.extend(tail.iter().rev());
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_holds_any_bytes() {
+        let s = LuaString::from(vec![b'a', 0xff, 0]);
+        assert_eq!(s.len(), 3);
+        assert_eq!(s.to_str(), None);
+        assert_eq!(s.to_string(), "a\u{fffd}\0");
+        assert_eq!(format!("{:?}", s), "\"a\\xff\\x00\"");
+        assert_eq!(LuaString::from("héllo").len(), 6);
+        assert_eq!(LuaString::from("héllo"), "héllo");
+    }
+
+    #[test]
+    fn test_utf8_encode() {
+        for c in ['a', 'é', 'λ', '€', '😀'] {
+            assert_eq!(utf8_encode(c as u32).unwrap(), c.to_string().into_bytes());
+        }
+        assert_eq!(utf8_encode(0xd800).unwrap(), b"\xed\xa0\x80");
+        assert_eq!(
+            utf8_encode(0x7fff_ffff).unwrap(),
+            b"\xfd\xbf\xbf\xbf\xbf\xbf"
+        );
+        assert_eq!(utf8_encode(0x8000_0000), None);
+    }
+
+    #[test]
+    fn test_serializes_text_or_bytes() {
+        let text = LuaString::from("hi");
+        let bytes = LuaString::from(&b"\xff"[..]);
+        assert_eq!(serde_json::to_string(&text).unwrap(), "\"hi\"");
+        assert_eq!(serde_json::to_string(&bytes).unwrap(), "[255]");
+        for s in [text, bytes] {
+            let json = serde_json::to_string(&s).unwrap();
+            assert_eq!(serde_json::from_str::<LuaString>(&json).unwrap(), s);
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use crate::lua_string::LuaString;
+use indexmap::IndexMap;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt;
@@ -12,21 +14,208 @@ pub enum LuaValue {
     Boolean(bool),
     /// Numeric values (Lua uses only f64)
     Number(f64),
-    /// String values
-    String(String),
+    /// String values: bytes, shared and usually interned (see `intern`)
+    String(LuaString),
     /// Table (hash map with metatable support)
     Table(Rc<RefCell<LuaTable>>),
     /// Function (built-in or user-defined)
     Function(Rc<LuaFunction>),
     /// User data (opaque data for extensions)
     UserData(Rc<RefCell<Box<dyn std::any::Any>>>),
+    /// A coroutine, made by `coroutine.create`
+    Thread(Rc<crate::coroutines::LuaThread>),
 }
 
+/// Entries of a table, kept in insertion order
+///
+/// `pairs`, `next` and the generic `for` visit keys in the order they were
+/// first inserted. Overwriting a key keeps its position; removing one (see
+/// `LuaTable::remove`) keeps the relative order of the rest. A key is never
+/// stored with a `Nil` value: assigning nil removes it.
+pub type TableData = IndexMap<LuaValue, LuaValue>;
+
 /// A Lua table with potential metatable
 #[derive(Debug)]
 pub struct LuaTable {
-    pub data: HashMap<LuaValue, LuaValue>,
-    pub metatable: Option<Box<HashMap<String, LuaValue>>>,
+    pub data: TableData,
+    pub metatable: Option<Box<Metatable>>,
+    /// Set by `table.freeze`: scripts can no longer assign its fields or
+    /// change its metatable
+    pub frozen: bool,
+    /// The key removed last and the position it had, so `next` can go on
+    /// from a key cleared during a traversal
+    pub removed: Option<(LuaValue, usize)>,
+}
+
+/// A table's metatable: the table `setmetatable` was given, which is what
+/// `getmetatable` returns and where metamethods are looked up, so changes
+/// made to it later take effect
+#[derive(Clone)]
+pub struct Metatable {
+    table: Rc<RefCell<LuaTable>>,
+}
+
+impl Metatable {
+    pub fn new(table: Rc<RefCell<LuaTable>>) -> Self {
+        Metatable { table }
+    }
+
+    /// The metatable as a Lua table, the same one every time
+    pub fn table(&self) -> Rc<RefCell<LuaTable>> {
+        Rc::clone(&self.table)
+    }
+
+    /// The field `name` as the metatable holds it now
+    pub fn get(&self, name: &str) -> Option<LuaValue> {
+        self.table.borrow().get_str(name).cloned()
+    }
+}
+
+/// A metatable made in Rust from its fields
+impl From<HashMap<String, LuaValue>> for Metatable {
+    fn from(fields: HashMap<String, LuaValue>) -> Self {
+        let data = fields
+            .into_iter()
+            .map(|(key, value)| (LuaValue::String(key.as_str().into()), value))
+            .collect();
+        Metatable::new(Rc::new(RefCell::new(LuaTable {
+            data,
+            metatable: None,
+            frozen: false,
+            removed: None,
+        })))
+    }
+}
+
+/// Only the address: a metatable may well be its own metatable
+impl fmt::Debug for Metatable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Metatable({:p})", Rc::as_ptr(&self.table))
+    }
+}
+
+impl LuaTable {
+    /// An error when the table is frozen, for code about to change it
+    pub fn check_writable(&self) -> crate::error_types::LuaResult<()> {
+        if self.frozen {
+            Err(crate::error_types::LuaError::runtime(
+                "attempt to modify a frozen table",
+                "assignment",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The value at integer key `i`, `Nil` when there is none
+    pub fn get_int(&self, i: i64) -> LuaValue {
+        self.data
+            .get(&LuaValue::Number(i as f64))
+            .cloned()
+            .unwrap_or(LuaValue::Nil)
+    }
+
+    /// Store `value` at `key`; `Nil` removes the key
+    pub fn set(&mut self, key: LuaValue, value: LuaValue) {
+        match value {
+            LuaValue::Nil => self.remove(&key),
+            value => {
+                self.data.insert(key, value);
+            }
+        }
+    }
+
+    /// Remove `key`, remembering where it was for `next_index`
+    pub fn remove(&mut self, key: &LuaValue) {
+        if let Some((index, key, _)) = self.data.shift_remove_full(key) {
+            self.removed = Some((key, index));
+        }
+    }
+
+    /// Position of the entry after `key` in traversal order, `None` when
+    /// the table never had `key`
+    ///
+    /// A key removed since the traversal reached it is still found, as
+    /// long as it is the one removed last, so loops that clear the current
+    /// field keep going.
+    pub fn next_index(&self, key: &LuaValue) -> Option<usize> {
+        match self.data.get_index_of(key) {
+            Some(index) => Some(index + 1),
+            None => match &self.removed {
+                Some((removed, index)) if removed == key => Some(*index),
+                _ => None,
+            },
+        }
+    }
+
+    /// Store `value` at integer key `i`; `Nil` removes the key
+    pub fn set_int(&mut self, i: i64, value: LuaValue) {
+        self.set(LuaValue::Number(i as f64), value);
+    }
+
+    /// The value at string key `name`, found without building a key value
+    pub fn get_str(&self, name: &str) -> Option<&LuaValue> {
+        self.data.get(&StrKey(name))
+    }
+
+    /// Store `value` at string key `name`; `Nil` removes the key
+    pub fn set_str(&mut self, name: &str, value: LuaValue) {
+        match (self.data.get_mut(&StrKey(name)), value) {
+            (Some(_), LuaValue::Nil) => {
+                if let Some((index, key, _)) = self.data.shift_remove_full(&StrKey(name)) {
+                    self.removed = Some((key, index));
+                }
+            }
+            (Some(slot), value) => *slot = value,
+            (None, LuaValue::Nil) => {}
+            (None, value) => {
+                self.data
+                    .insert(LuaValue::String(crate::intern::intern(name).into()), value);
+            }
+        }
+    }
+
+    /// Length of the sequence part, as `#`, `rawlen` and the table library
+    /// see it: the last `n` with `t[1]` to `t[n]` all non-nil
+    pub fn border(&self) -> usize {
+        (1..)
+            .take_while(|i| !matches!(self.get_int(*i), LuaValue::Nil))
+            .count()
+    }
+}
+
+/// A string key borrowed as `&str`; hashes and compares as the
+/// `LuaValue::String` holding the same bytes would
+struct StrKey<'a>(&'a str);
+
+impl std::hash::Hash for StrKey<'_> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        3.hash(state);
+        self.0.as_bytes().hash(state);
+    }
+}
+
+impl indexmap::Equivalent<LuaValue> for StrKey<'_> {
+    fn equivalent(&self, key: &LuaValue) -> bool {
+        matches!(key, LuaValue::String(s) if *s == self.0)
+    }
+}
+
+/// A long chain of tables each holding the next is freed one level per
+/// native call, so the rest of the chain is dropped on a fresh stack
+/// segment once this one runs low
+impl Drop for LuaTable {
+    fn drop(&mut self) {
+        if crate::stack::running_low() {
+            let table = LuaTable {
+                data: std::mem::take(&mut self.data),
+                metatable: self.metatable.take(),
+                frozen: false,
+                removed: None,
+            };
+            crate::stack::with_headroom(move || drop(table));
+        }
+    }
 }
 
 /// A Lua function (closure with captured variables)
@@ -34,17 +223,19 @@ pub struct LuaTable {
 pub enum LuaFunction {
     /// Built-in function with a closure
     Builtin(Rc<dyn Fn(Vec<LuaValue>) -> crate::error_types::LuaResult<LuaValue>>),
+    /// Built-in function returning any number of values
+    MultiBuiltin(Rc<dyn Fn(Vec<LuaValue>) -> crate::error_types::LuaResult<Vec<LuaValue>>>),
     /// User-defined function with AST and captured variables
     User {
-        /// Function parameters
-        params: Vec<String>,
-        /// Whether function accepts varargs (...)
-        varargs: bool,
-        /// Function body (AST)
-        body: Box<crate::lua_parser::Block>,
-        /// Variables captured from defining scope (shared reference for proper closure semantics)
-        captured: Rc<RefCell<HashMap<String, LuaValue>>>,
+        /// Parameters, body, slot layout and source lines of the definition,
+        /// in the arena it was parsed into and shared with every other
+        /// closure made from it
+        body: crate::lua_parser::FunctionRef,
+        /// Locals from the defining scope, captured by reference
+        captured: crate::upvalues::ClosureState,
     },
+    /// Made by `coroutine.wrap`: calling it resumes the coroutine
+    Coroutine(Rc<crate::coroutines::LuaThread>),
 }
 
 impl fmt::Debug for LuaValue {
@@ -53,34 +244,102 @@ impl fmt::Debug for LuaValue {
             LuaValue::Nil => write!(f, "nil"),
             LuaValue::Boolean(b) => write!(f, "{}", b),
             LuaValue::Number(n) => write!(f, "{}", n),
-            LuaValue::String(s) => write!(f, "\"{}\"", s),
+            LuaValue::String(s) => write!(f, "{:?}", s),
             LuaValue::Table(_) => write!(f, "<table>"),
             LuaValue::Function(_) => write!(f, "<function>"),
             LuaValue::UserData(_) => write!(f, "<userdata>"),
+            LuaValue::Thread(_) => write!(f, "<thread>"),
         }
     }
 }
 
+/// Significant digits numbers are printed with, Lua's `LUAI_NUMFFORMAT`
+/// of `%.14g`
+pub const NUMBER_DIGITS: usize = 14;
+
+/// A number as `tostring`, `print`, `..`, `io.write`, `string.format("%s")`
+/// and error messages show it; every number-to-text conversion goes
+/// through here so they agree
+///
+/// Whole numbers that fit an integer print like one, and everything else
+/// like C's `%.14g`, the format Lua uses for floats: `0.1 + 0.2` is `0.3`
+/// and `2^63` is `9.2233720368548e+18`.
+pub fn format_number(n: f64) -> String {
+    if n.is_nan() {
+        return if n.is_sign_negative() { "-nan" } else { "nan" }.to_string();
+    }
+    if n.is_infinite() {
+        return if n > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+    // -2^63 is the one whole float in range whose negation is not
+    if n.fract() == 0.0 && (-9.223_372_036_854_776e18..9.223_372_036_854_776e18).contains(&n) {
+        return (n as i64).to_string();
+    }
+    format_general(n, NUMBER_DIGITS)
+}
+
+/// A finite number in C's `%.<precision>g`: `precision` significant
+/// digits, in scientific notation only for very large or small exponents,
+/// without trailing zeros
+pub fn format_general(n: f64, precision: usize) -> String {
+    let precision = precision.max(1);
+    // Round to the significant digits first; the exponent of the rounded
+    // value picks fixed or scientific notation
+    let scientific = format!("{:.*e}", precision - 1, n);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    if (-4..precision as i32).contains(&exponent) {
+        let decimals = (precision as i32 - 1 - exponent) as usize;
+        trim_fraction(&format!("{:.*}", decimals, n)).to_string()
+    } else {
+        format!("{}{}", trim_fraction(mantissa), c_exponent(exponent))
+    }
+}
+
+/// An exponent the way C prints it: `e`, a sign and at least two digits
+pub fn c_exponent(exponent: i32) -> String {
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("e{}{:02}", sign, exponent.unsigned_abs())
+}
+
+/// Drop trailing zeros after a decimal point, and the point if nothing is
+/// left after it
+fn trim_fraction(digits: &str) -> &str {
+    if digits.contains('.') {
+        digits.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        digits
+    }
+}
+
 impl fmt::Display for LuaValue {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             LuaValue::Nil => write!(f, "nil"),
             LuaValue::Boolean(b) => write!(f, "{}", b),
-            LuaValue::Number(n) => {
-                if n.fract() == 0.0 && !n.is_infinite() {
-                    write!(f, "{}", *n as i64)
-                } else {
-                    write!(f, "{}", n)
-                }
-            }
+            LuaValue::Number(n) => write!(f, "{}", format_number(*n)),
             LuaValue::String(s) => write!(f, "{}", s),
-            LuaValue::Table(_) => write!(f, "table"),
-            LuaValue::Function(_) => write!(f, "function"),
-            LuaValue::UserData(_) => write!(f, "userdata"),
+            LuaValue::Function(func) if !matches!(func.as_ref(), LuaFunction::User { .. }) => {
+                write!(f, "function: builtin: {:#x}", self.address().unwrap_or(0))
+            }
+            LuaValue::Table(_)
+            | LuaValue::Function(_)
+            | LuaValue::UserData(_)
+            | LuaValue::Thread(_) => {
+                write!(
+                    f,
+                    "{}: {:#x}",
+                    self.type_name(),
+                    self.address().unwrap_or(0)
+                )
+            }
         }
     }
 }
 
+/// Raw equality, as `rawequal` sees it: primitives by value, tables,
+/// functions, userdata and threads by identity, and never across types.
+/// `==` in Lua code also consults `__eq` (see `Executor::values_equal`).
 impl PartialEq for LuaValue {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
@@ -89,8 +348,9 @@ impl PartialEq for LuaValue {
             (LuaValue::Number(a), LuaValue::Number(b)) => a == b,
             (LuaValue::String(a), LuaValue::String(b)) => a == b,
             (LuaValue::Table(a), LuaValue::Table(b)) => Rc::ptr_eq(a, b),
-            (LuaValue::Function(_), LuaValue::Function(_)) => false, // Functions compared by identity
+            (LuaValue::Function(a), LuaValue::Function(b)) => Rc::ptr_eq(a, b),
             (LuaValue::UserData(a), LuaValue::UserData(b)) => Rc::ptr_eq(a, b),
+            (LuaValue::Thread(a), LuaValue::Thread(b)) => Rc::ptr_eq(a, b),
             _ => false,
         }
     }
@@ -126,6 +386,10 @@ impl std::hash::Hash for LuaValue {
                 6.hash(state);
                 (u.as_ptr() as *const () as usize).hash(state);
             }
+            LuaValue::Thread(t) => {
+                7.hash(state);
+                (Rc::as_ptr(t) as usize).hash(state);
+            }
         }
     }
 }
@@ -142,23 +406,50 @@ impl LuaValue {
         match self {
             LuaValue::Number(n) => Ok(*n),
             LuaValue::String(s) => s
-                .trim()
-                .parse::<f64>()
-                .map_err(|_| LuaError::type_error("number", "string", "to_number")),
+                .to_str()
+                .and_then(|s| s.trim().parse::<f64>().ok())
+                .ok_or_else(|| LuaError::type_error("number", "string", "to_number")),
             LuaValue::Boolean(true) => Ok(1.0),
             LuaValue::Boolean(false) => Ok(0.0),
-            _ => Err(LuaError::type_error("number", self.type_name(), "to_number")),
+            _ => Err(LuaError::type_error(
+                "number",
+                self.type_name(),
+                "to_number",
+            )),
         }
     }
 
-    /// Convert value to string
+    /// Convert value to string, as text
+    ///
+    /// Invalid UTF-8 in a string becomes U+FFFD; `to_lua_string` keeps the
+    /// bytes.
     pub fn to_string_value(&self) -> String {
         match self {
-            LuaValue::String(s) => s.clone(),
+            LuaValue::String(s) => s.to_string(),
             _ => self.to_string(),
         }
     }
 
+    /// Convert value to a Lua string, keeping a string's bytes as they are
+    pub fn to_lua_string(&self) -> LuaString {
+        match self {
+            LuaValue::String(s) => s.clone(),
+            _ => self.to_string().into(),
+        }
+    }
+
+    /// Where a table, function, userdata or thread lives, as `%p` and
+    /// `tostring` show it; `None` for values compared by value
+    pub fn address(&self) -> Option<usize> {
+        match self {
+            LuaValue::Table(t) => Some(Rc::as_ptr(t) as usize),
+            LuaValue::Function(f) => Some(Rc::as_ptr(f) as usize),
+            LuaValue::UserData(u) => Some(Rc::as_ptr(u) as usize),
+            LuaValue::Thread(t) => Some(Rc::as_ptr(t) as usize),
+            _ => None,
+        }
+    }
+
     /// Get the type name of the value
     pub fn type_name(&self) -> &'static str {
         match self {
@@ -169,6 +460,7 @@ impl LuaValue {
             LuaValue::Table(_) => "table",
             LuaValue::Function(_) => "function",
             LuaValue::UserData(_) => "userdata",
+            LuaValue::Thread(_) => "thread",
         }
     }
 }
@@ -181,7 +473,7 @@ mod tests {
     fn test_truthy_values() {
         assert!(LuaValue::Number(1.0).is_truthy());
         assert!(LuaValue::Number(0.0).is_truthy());
-        assert!(LuaValue::String("hello".to_string()).is_truthy());
+        assert!(LuaValue::String("hello".into()).is_truthy());
         assert!(LuaValue::Boolean(true).is_truthy());
         assert!(!LuaValue::Boolean(false).is_truthy());
         assert!(!LuaValue::Nil.is_truthy());
@@ -190,10 +482,31 @@ mod tests {
     #[test]
     fn test_to_number() {
         assert_eq!(LuaValue::Number(42.0).to_number(), Ok(42.0));
-        assert_eq!(LuaValue::String("123".to_string()).to_number(), Ok(123.0));
+        assert_eq!(LuaValue::String("123".into()).to_number(), Ok(123.0));
         assert_eq!(LuaValue::Boolean(true).to_number(), Ok(1.0));
         assert_eq!(LuaValue::Boolean(false).to_number(), Ok(0.0));
-        assert!(LuaValue::String("abc".to_string()).to_number().is_err());
+        assert!(LuaValue::String("abc".into()).to_number().is_err());
+    }
+
+    #[test]
+    fn test_format_number() {
+        let cases = [
+            (42.0, "42"),
+            (-7.0, "-7"),
+            (3.5, "3.5"),
+            (0.1 + 0.2, "0.3"),
+            (1.0 / 3.0, "0.33333333333333"),
+            (1e100, "1e+100"),
+            (2f64.powi(63), "9.2233720368548e+18"),
+            (1.5e-7, "1.5e-07"),
+            (0.0001, "0.0001"),
+            (f64::INFINITY, "inf"),
+            (f64::NEG_INFINITY, "-inf"),
+        ];
+        for (n, expected) in cases {
+            assert_eq!(format_number(n), expected, "{:?}", n);
+        }
+        assert!(format_number(f64::NAN).ends_with("nan"));
     }
 
     #[test]
@@ -201,6 +514,6 @@ mod tests {
         assert_eq!(LuaValue::Nil.type_name(), "nil");
         assert_eq!(LuaValue::Boolean(true).type_name(), "boolean");
         assert_eq!(LuaValue::Number(42.0).type_name(), "number");
-        assert_eq!(LuaValue::String("hello".to_string()).type_name(), "string");
+        assert_eq!(LuaValue::String("hello".into()).type_name(), "string");
     }
 }
@@ -0,0 +1,670 @@
+//! Pattern-based macro expansion for Scheme (`define-syntax` / `syntax-rules`)
+//!
+//! Expansion is a pass over the AST arena that runs before evaluation.
+//! Each `define-syntax` form registers its rules and is replaced by `()`;
+//! every later use of the macro is rewritten by instantiating the template
+//! of the first matching pattern. The expander is not hygienic: template
+//! symbols are inserted as written.
+
+use crate::ast::{Arena, NodeId, SExpr};
+use std::collections::HashMap;
+
+/// Upper bound on nested expansions, to stop runaway recursive macros
+const MAX_EXPANSION_DEPTH: usize = 256;
+
+const ELLIPSIS: &str = "...";
+
+/// Value bound to a pattern variable during matching
+#[derive(Debug, Clone)]
+enum Binding {
+    /// A single matched form
+    One(NodeId),
+    /// One binding per repetition of an ellipsis pattern
+    Many(Vec<Binding>),
+}
+
+type Bindings = HashMap<String, Binding>;
+
+/// A macro defined with `syntax-rules`
+#[derive(Debug, Clone)]
+struct SyntaxRules {
+    literals: Vec<String>,
+    /// (pattern, template) pairs, stored in the expander's own arena
+    rules: Vec<(NodeId, NodeId)>,
+}
+
+/// Expands `syntax-rules` macros in parsed Scheme code
+pub struct MacroExpander {
+    /// Private copy of every macro's patterns and templates, so macros
+    /// outlive the arena they were defined in
+    arena: Arena,
+    macros: HashMap<String, SyntaxRules>,
+}
+
+impl MacroExpander {
+    /// Create an expander with no macros defined
+    pub fn new() -> Self {
+        MacroExpander {
+            arena: Arena::new(),
+            macros: HashMap::new(),
+        }
+    }
+
+    /// Check whether a macro with this name has been defined
+    pub fn is_macro(&self, name: &str) -> bool {
+        self.macros.contains_key(name)
+    }
+
+    /// Expand all macro uses in the form `id`, returning the expanded form
+    pub fn expand(&mut self, arena: &mut Arena, id: NodeId) -> Result<NodeId, String> {
+        self.expand_node(arena, id, 0)
+    }
+
+    fn expand_node(
+        &mut self,
+        arena: &mut Arena,
+        id: NodeId,
+        depth: usize,
+    ) -> Result<NodeId, String> {
+        if depth > MAX_EXPANSION_DEPTH {
+            return Err("Macro expansion too deep".to_string());
+        }
+
+        let expr = arena.get(id).ok_or("Invalid node reference")?.clone();
+        match expr {
+            SExpr::List(ids) if !ids.is_empty() => {
+                if let Some(SExpr::Atom(head)) = arena.get(ids[0]) {
+                    let head = head.clone();
+                    match head.as_str() {
+                        "quote" => return Ok(id),
+                        "define-syntax" => {
+                            self.define_syntax(&ids, arena)?;
+                            return Ok(arena.alloc(SExpr::List(vec![])));
+                        }
+                        _ if self.is_macro(&head) => {
+                            let expanded = self.apply_macro(&head, &ids, arena)?;
+                            return self.expand_node(arena, expanded, depth + 1);
+                        }
+                        _ => {}
+                    }
+                }
+                self.expand_list(arena, id, &ids, depth)
+            }
+            SExpr::QuasiQuote(inner) => {
+                let new_inner = self.expand_template(arena, inner, 1, depth)?;
+                if new_inner == inner {
+                    Ok(id)
+                } else {
+                    Ok(arena.alloc(SExpr::QuasiQuote(new_inner)))
+                }
+            }
+            _ => Ok(id),
+        }
+    }
+
+    /// Expand every element of a list, allocating a new node only if something changed
+    fn expand_list(
+        &mut self,
+        arena: &mut Arena,
+        id: NodeId,
+        ids: &[NodeId],
+        depth: usize,
+    ) -> Result<NodeId, String> {
+        let mut new_ids = Vec::with_capacity(ids.len());
+        for child in ids {
+            new_ids.push(self.expand_node(arena, *child, depth)?);
+        }
+        if new_ids == ids {
+            Ok(id)
+        } else {
+            Ok(arena.alloc(SExpr::List(new_ids)))
+        }
+    }
+
+    /// Expand only the unquoted parts of a quasiquote template
+    fn expand_template(
+        &mut self,
+        arena: &mut Arena,
+        id: NodeId,
+        level: usize,
+        depth: usize,
+    ) -> Result<NodeId, String> {
+        let expr = arena.get(id).ok_or("Invalid node reference")?.clone();
+        match expr {
+            SExpr::Unquote(inner) | SExpr::UnquoteSplicing(inner) => {
+                let new_inner = if level == 1 {
+                    self.expand_node(arena, inner, depth)?
+                } else {
+                    self.expand_template(arena, inner, level - 1, depth)?
+                };
+                if new_inner == inner {
+                    return Ok(id);
+                }
+                Ok(arena.alloc(match expr {
+                    SExpr::Unquote(_) => SExpr::Unquote(new_inner),
+                    _ => SExpr::UnquoteSplicing(new_inner),
+                }))
+            }
+            SExpr::QuasiQuote(inner) => {
+                let new_inner = self.expand_template(arena, inner, level + 1, depth)?;
+                if new_inner == inner {
+                    Ok(id)
+                } else {
+                    Ok(arena.alloc(SExpr::QuasiQuote(new_inner)))
+                }
+            }
+            SExpr::List(ref ids) | SExpr::Vector(ref ids) => {
+                let mut new_ids = Vec::with_capacity(ids.len());
+                for child in ids {
+                    new_ids.push(self.expand_template(arena, *child, level, depth)?);
+                }
+                if new_ids == *ids {
+                    return Ok(id);
+                }
+                Ok(arena.alloc(match expr {
+                    SExpr::List(_) => SExpr::List(new_ids),
+                    _ => SExpr::Vector(new_ids),
+                }))
+            }
+            SExpr::DottedList(ids, tail) => {
+                let mut new_ids = Vec::with_capacity(ids.len());
+                for child in &ids {
+                    new_ids.push(self.expand_template(arena, *child, level, depth)?);
+                }
+                let new_tail = self.expand_template(arena, tail, level, depth)?;
+                if new_ids == ids && new_tail == tail {
+                    return Ok(id);
+                }
+                Ok(arena.alloc(SExpr::DottedList(new_ids, new_tail)))
+            }
+            _ => Ok(id),
+        }
+    }
+
+    /// Register a macro: (define-syntax name (syntax-rules (literal...) (pattern template)...))
+    fn define_syntax(&mut self, ids: &[NodeId], arena: &Arena) -> Result<(), String> {
+        if ids.len() != 3 {
+            return Err("define-syntax expects a name and a syntax-rules form".to_string());
+        }
+        let name = match arena.get(ids[1]) {
+            Some(SExpr::Atom(name)) => name.clone(),
+            _ => return Err("define-syntax expects a symbol as macro name".to_string()),
+        };
+
+        let spec = match arena.get(ids[2]) {
+            Some(SExpr::List(spec)) if spec.len() >= 2 => spec.clone(),
+            _ => return Err("define-syntax expects a syntax-rules form".to_string()),
+        };
+        if !matches!(arena.get(spec[0]), Some(SExpr::Atom(s)) if s == "syntax-rules") {
+            return Err("Only syntax-rules macros are supported".to_string());
+        }
+
+        let literals = match arena.get(spec[1]) {
+            Some(SExpr::List(lits)) => lits
+                .iter()
+                .map(|id| match arena.get(*id) {
+                    Some(SExpr::Atom(lit)) => Ok(lit.clone()),
+                    _ => Err("syntax-rules literals must be symbols".to_string()),
+                })
+                .collect::<Result<Vec<String>, String>>()?,
+            _ => return Err("syntax-rules expects a list of literals".to_string()),
+        };
+
+        let mut rules = Vec::new();
+        for rule_id in &spec[2..] {
+            let rule = match arena.get(*rule_id) {
+                Some(SExpr::List(rule)) if rule.len() == 2 => rule.clone(),
+                _ => return Err("syntax-rules clause must be (pattern template)".to_string()),
+            };
+            if !matches!(arena.get(rule[0]), Some(SExpr::List(_))) {
+                return Err("syntax-rules pattern must be a list".to_string());
+            }
+            let pattern = copy_node(arena, rule[0], &mut self.arena)?;
+            let template = copy_node(arena, rule[1], &mut self.arena)?;
+            rules.push((pattern, template));
+        }
+
+        self.macros.insert(name, SyntaxRules { literals, rules });
+        Ok(())
+    }
+
+    /// Rewrite a macro use with the first rule whose pattern matches
+    fn apply_macro(&self, name: &str, ids: &[NodeId], arena: &mut Arena) -> Result<NodeId, String> {
+        let syntax = &self.macros[name];
+        for (pattern, template) in &syntax.rules {
+            let pattern_ids = match self.arena.get(*pattern) {
+                Some(SExpr::List(pattern_ids)) => pattern_ids,
+                _ => continue,
+            };
+
+            // The macro keyword position is ignored when matching
+            let mut bindings = Bindings::new();
+            let pattern_tail = pattern_ids.get(1..).unwrap_or(&[]);
+            if self.match_sequence(syntax, pattern_tail, &ids[1..], arena, &mut bindings) {
+                return self.instantiate(*template, &bindings, arena);
+            }
+        }
+        Err(format!("No syntax-rules pattern matches use of {}", name))
+    }
+
+    /// Match a pattern node (expander arena) against a form node (program arena)
+    fn match_pattern(
+        &self,
+        syntax: &SyntaxRules,
+        pattern: NodeId,
+        form: NodeId,
+        arena: &Arena,
+        bindings: &mut Bindings,
+    ) -> bool {
+        let (Some(pat), Some(expr)) = (self.arena.get(pattern), arena.get(form)) else {
+            return false;
+        };
+
+        match pat {
+            SExpr::Atom(name) if name == "_" => true,
+            SExpr::Atom(name) if syntax.literals.contains(name) => {
+                matches!(expr, SExpr::Atom(s) if s == name)
+            }
+            SExpr::Atom(name) => {
+                bindings.insert(name.clone(), Binding::One(form));
+                true
+            }
+            SExpr::List(pids) => match expr {
+                SExpr::List(fids) => self.match_sequence(syntax, pids, fids, arena, bindings),
+                _ => false,
+            },
+            SExpr::Vector(pids) => match expr {
+                SExpr::Vector(fids) => self.match_sequence(syntax, pids, fids, arena, bindings),
+                _ => false,
+            },
+            SExpr::DottedList(pids, ptail) => match expr {
+                SExpr::DottedList(fids, ftail) => {
+                    self.match_sequence(syntax, pids, fids, arena, bindings)
+                        && self.match_pattern(syntax, *ptail, *ftail, arena, bindings)
+                }
+                _ => false,
+            },
+            SExpr::Quote(p) => {
+                matches!(expr, SExpr::Quote(f) if self.match_pattern(syntax, *p, *f, arena, bindings))
+            }
+            SExpr::QuasiQuote(p) => {
+                matches!(expr, SExpr::QuasiQuote(f) if self.match_pattern(syntax, *p, *f, arena, bindings))
+            }
+            SExpr::Unquote(p) => {
+                matches!(expr, SExpr::Unquote(f) if self.match_pattern(syntax, *p, *f, arena, bindings))
+            }
+            SExpr::UnquoteSplicing(p) => {
+                matches!(expr, SExpr::UnquoteSplicing(f) if self.match_pattern(syntax, *p, *f, arena, bindings))
+            }
+            literal => literal == expr,
+        }
+    }
+
+    /// Match a sequence of patterns, where one element may be followed by `...`
+    fn match_sequence(
+        &self,
+        syntax: &SyntaxRules,
+        pids: &[NodeId],
+        fids: &[NodeId],
+        arena: &Arena,
+        bindings: &mut Bindings,
+    ) -> bool {
+        let ellipsis = pids
+            .iter()
+            .position(|id| matches!(self.arena.get(*id), Some(SExpr::Atom(s)) if s == ELLIPSIS));
+
+        let Some(ellipsis) = ellipsis.filter(|i| *i > 0) else {
+            return pids.len() == fids.len()
+                && pids
+                    .iter()
+                    .zip(fids)
+                    .all(|(p, f)| self.match_pattern(syntax, *p, *f, arena, bindings));
+        };
+
+        let repeated = pids[ellipsis - 1];
+        let before = &pids[..ellipsis - 1];
+        let after = &pids[ellipsis + 1..];
+        if fids.len() < before.len() + after.len() {
+            return false;
+        }
+        let repeat_end = fids.len() - after.len();
+
+        let fixed_match = before
+            .iter()
+            .zip(&fids[..before.len()])
+            .chain(after.iter().zip(&fids[repeat_end..]))
+            .all(|(p, f)| self.match_pattern(syntax, *p, *f, arena, bindings));
+        if !fixed_match {
+            return false;
+        }
+
+        let vars = self.pattern_vars(syntax, repeated);
+        let mut repetitions: Vec<Bindings> = Vec::new();
+        for form in &fids[before.len()..repeat_end] {
+            let mut rep = Bindings::new();
+            if !self.match_pattern(syntax, repeated, *form, arena, &mut rep) {
+                return false;
+            }
+            repetitions.push(rep);
+        }
+
+        for var in vars {
+            let items = repetitions.iter().map(|rep| rep[&var].clone()).collect();
+            bindings.insert(var, Binding::Many(items));
+        }
+        true
+    }
+
+    /// Collect the pattern variables that appear in a pattern
+    fn pattern_vars(&self, syntax: &SyntaxRules, pattern: NodeId) -> Vec<String> {
+        let mut vars = Vec::new();
+        self.collect_atoms(pattern, &mut vars);
+        vars.retain(|v| v != "_" && v != ELLIPSIS && !syntax.literals.contains(v));
+        vars
+    }
+
+    fn collect_atoms(&self, id: NodeId, out: &mut Vec<String>) {
+        match self.arena.get(id) {
+            Some(SExpr::Atom(name)) if !out.contains(name) => out.push(name.clone()),
+            Some(SExpr::List(ids)) | Some(SExpr::Vector(ids)) => {
+                for child in ids {
+                    self.collect_atoms(*child, out);
+                }
+            }
+            Some(SExpr::DottedList(ids, tail)) => {
+                for child in ids.iter().chain([tail]) {
+                    self.collect_atoms(*child, out);
+                }
+            }
+            Some(SExpr::Quote(inner))
+            | Some(SExpr::QuasiQuote(inner))
+            | Some(SExpr::Unquote(inner))
+            | Some(SExpr::UnquoteSplicing(inner)) => self.collect_atoms(*inner, out),
+            _ => {}
+        }
+    }
+
+    /// Build a template (expander arena) into the program arena
+    fn instantiate(
+        &self,
+        template: NodeId,
+        bindings: &Bindings,
+        arena: &mut Arena,
+    ) -> Result<NodeId, String> {
+        let expr = self
+            .arena
+            .get(template)
+            .ok_or("Invalid template reference")?;
+        match expr {
+            SExpr::Atom(name) => match bindings.get(name) {
+                Some(Binding::One(id)) => Ok(*id),
+                Some(Binding::Many(_)) => {
+                    Err(format!("Pattern variable {} used without ellipsis", name))
+                }
+                None => Ok(arena.alloc(expr.clone())),
+            },
+            SExpr::List(ids) => {
+                // (... ...) escapes a literal ellipsis
+                if ids.len() == 2 && ids.iter().all(|id| self.is_ellipsis(*id)) {
+                    return Ok(arena.alloc(SExpr::Atom(ELLIPSIS.to_string())));
+                }
+                let items = self.instantiate_sequence(ids, bindings, arena)?;
+                Ok(arena.alloc(SExpr::List(items)))
+            }
+            SExpr::Vector(ids) => {
+                let items = self.instantiate_sequence(ids, bindings, arena)?;
+                Ok(arena.alloc(SExpr::Vector(items)))
+            }
+            SExpr::DottedList(ids, tail) => {
+                let mut items = self.instantiate_sequence(ids, bindings, arena)?;
+                let tail = self.instantiate(*tail, bindings, arena)?;
+                // A tail bound to a list joins the items, as the parser does
+                let expr = match arena.get(tail) {
+                    Some(SExpr::List(rest)) => {
+                        items.extend(rest);
+                        SExpr::List(items)
+                    }
+                    Some(SExpr::DottedList(rest, inner)) => {
+                        let inner = *inner;
+                        items.extend(rest);
+                        SExpr::DottedList(items, inner)
+                    }
+                    _ => SExpr::DottedList(items, tail),
+                };
+                Ok(arena.alloc(expr))
+            }
+            SExpr::Quote(inner) => {
+                let inner = self.instantiate(*inner, bindings, arena)?;
+                Ok(arena.alloc(SExpr::Quote(inner)))
+            }
+            SExpr::QuasiQuote(inner) => {
+                let inner = self.instantiate(*inner, bindings, arena)?;
+                Ok(arena.alloc(SExpr::QuasiQuote(inner)))
+            }
+            SExpr::Unquote(inner) => {
+                let inner = self.instantiate(*inner, bindings, arena)?;
+                Ok(arena.alloc(SExpr::Unquote(inner)))
+            }
+            SExpr::UnquoteSplicing(inner) => {
+                let inner = self.instantiate(*inner, bindings, arena)?;
+                Ok(arena.alloc(SExpr::UnquoteSplicing(inner)))
+            }
+            literal => Ok(arena.alloc(literal.clone())),
+        }
+    }
+
+    /// Instantiate template elements, repeating any element followed by `...`
+    fn instantiate_sequence(
+        &self,
+        ids: &[NodeId],
+        bindings: &Bindings,
+        arena: &mut Arena,
+    ) -> Result<Vec<NodeId>, String> {
+        let mut items = Vec::new();
+        let mut i = 0;
+        while i < ids.len() {
+            let sub = ids[i];
+            if i + 1 < ids.len() && self.is_ellipsis(ids[i + 1]) {
+                let mut atoms = Vec::new();
+                self.collect_atoms(sub, &mut atoms);
+                let repeated: Vec<(&String, &Vec<Binding>)> = atoms
+                    .iter()
+                    .filter_map(|name| match bindings.get_key_value(name) {
+                        Some((key, Binding::Many(items))) => Some((key, items)),
+                        _ => None,
+                    })
+                    .collect();
+
+                let Some((_, first)) = repeated.first() else {
+                    return Err(
+                        "Ellipsis in template follows no repeated pattern variable".to_string()
+                    );
+                };
+                let count = first.len();
+                if repeated.iter().any(|(_, items)| items.len() != count) {
+                    return Err(
+                        "Pattern variables under one ellipsis matched different lengths"
+                            .to_string(),
+                    );
+                }
+
+                for n in 0..count {
+                    let mut rep = bindings.clone();
+                    for (name, items) in &repeated {
+                        rep.insert((*name).clone(), items[n].clone());
+                    }
+                    items.push(self.instantiate(sub, &rep, arena)?);
+                }
+                i += 2;
+            } else {
+                items.push(self.instantiate(sub, bindings, arena)?);
+                i += 1;
+            }
+        }
+        Ok(items)
+    }
+
+    fn is_ellipsis(&self, id: NodeId) -> bool {
+        matches!(self.arena.get(id), Some(SExpr::Atom(s)) if s == ELLIPSIS)
+    }
+}
+
+impl Default for MacroExpander {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Deep-copy a node and its children from one arena into another
+fn copy_node(src: &Arena, id: NodeId, dst: &mut Arena) -> Result<NodeId, String> {
+    let expr = src.get(id).ok_or("Invalid node reference")?;
+    let copied = match expr {
+        SExpr::List(ids) => SExpr::List(
+            ids.iter()
+                .map(|child| copy_node(src, *child, dst))
+                .collect::<Result<Vec<NodeId>, String>>()?,
+        ),
+        SExpr::Vector(ids) => SExpr::Vector(
+            ids.iter()
+                .map(|child| copy_node(src, *child, dst))
+                .collect::<Result<Vec<NodeId>, String>>()?,
+        ),
+        SExpr::DottedList(ids, tail) => SExpr::DottedList(
+            ids.iter()
+                .map(|child| copy_node(src, *child, dst))
+                .collect::<Result<Vec<NodeId>, String>>()?,
+            copy_node(src, *tail, dst)?,
+        ),
+        SExpr::Quote(inner) => SExpr::Quote(copy_node(src, *inner, dst)?),
+        SExpr::QuasiQuote(inner) => SExpr::QuasiQuote(copy_node(src, *inner, dst)?),
+        SExpr::Unquote(inner) => SExpr::Unquote(copy_node(src, *inner, dst)?),
+        SExpr::UnquoteSplicing(inner) => SExpr::UnquoteSplicing(copy_node(src, *inner, dst)?),
+        literal => literal.clone(),
+    };
+    Ok(dst.alloc(copied))
+}
+
+/// Expand every top-level form of a program in order with a fresh expander
+pub fn expand_program(arena: &mut Arena, nodes: &[NodeId]) -> Result<Vec<NodeId>, String> {
+    let mut expander = MacroExpander::new();
+    nodes.iter().map(|id| expander.expand(arena, *id)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    // Expand a program and render the last top-level form
+    fn expand_str(code: &str) -> Result<String, String> {
+        let (mut arena, nodes) = parse(code).map_err(|e| e.message)?;
+        let expanded = expand_program(&mut arena, &nodes)?;
+        let last = *expanded.last().ok_or("empty program")?;
+        Ok(render(&arena, last))
+    }
+
+    fn render(arena: &Arena, id: NodeId) -> String {
+        match arena.get(id).unwrap() {
+            SExpr::List(ids) => format!(
+                "({})",
+                ids.iter()
+                    .map(|id| render(arena, *id))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            SExpr::Atom(s) => s.clone(),
+            SExpr::Integer(n) => n.to_string(),
+            SExpr::Quote(inner) => format!("'{}", render(arena, *inner)),
+            other => format!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_simple_macro() {
+        let code =
+            "(define-syntax swap-args (syntax-rules () ((_ f a b) (f b a)))) (swap-args - 1 2)";
+        assert_eq!(expand_str(code).unwrap(), "(- 2 1)");
+    }
+
+    #[test]
+    fn test_define_syntax_becomes_empty_list() {
+        let code = "(define-syntax id (syntax-rules () ((_ x) x)))";
+        assert_eq!(expand_str(code).unwrap(), "()");
+    }
+
+    #[test]
+    fn test_ellipsis() {
+        let code =
+            "(define-syntax my-list (syntax-rules () ((_ x ...) (list x ...)))) (my-list 1 2 3)";
+        assert_eq!(expand_str(code).unwrap(), "(list 1 2 3)");
+    }
+
+    #[test]
+    fn test_ellipsis_with_zero_matches() {
+        let code = "(define-syntax my-list (syntax-rules () ((_ x ...) (list x ...)))) (my-list)";
+        assert_eq!(expand_str(code).unwrap(), "(list)");
+    }
+
+    #[test]
+    fn test_nested_ellipsis_pattern() {
+        let code = "(define-syntax my-let (syntax-rules () ((_ ((n v) ...) body ...) ((lambda (n ...) body ...) v ...)))) (my-let ((a 1) (b 2)) (+ a b))";
+        assert_eq!(expand_str(code).unwrap(), "((lambda (a b) (+ a b)) 1 2)");
+    }
+
+    #[test]
+    fn test_literals() {
+        let code = "(define-syntax arrow (syntax-rules (=>) ((_ a => b) (b a)) ((_ a b) (a b)))) (arrow 1 => f)";
+        assert_eq!(expand_str(code).unwrap(), "(f 1)");
+    }
+
+    #[test]
+    fn test_recursive_macro() {
+        let code = "(define-syntax my-or (syntax-rules () ((_) #f) ((_ e) e) ((_ e r ...) (if e e (my-or r ...))))) (my-or a b)";
+        assert_eq!(expand_str(code).unwrap(), "(if a a b)");
+    }
+
+    #[test]
+    fn test_quoted_forms_are_not_expanded() {
+        let code = "(define-syntax id (syntax-rules () ((_ x) x))) '(id 1)";
+        assert_eq!(expand_str(code).unwrap(), "'(id 1)");
+    }
+
+    #[test]
+    fn test_no_matching_rule() {
+        let code = "(define-syntax two (syntax-rules () ((_ a b) a))) (two 1)";
+        assert!(expand_str(code).is_err());
+    }
+
+    #[test]
+    fn test_runaway_expansion_is_error() {
+        let code = "(define-syntax loop (syntax-rules () ((_ x) (loop x)))) (loop 1)";
+        assert!(expand_str(code).is_err());
+    }
+
+    #[test]
+    fn test_expanded_program_evaluates() {
+        use crate::interpreter::{Environment, Interpreter, SVal};
+
+        let code = "(define-syntax my-unless (syntax-rules () ((_ c body ...) (if c #f (begin body ...))))) (define x 1) (my-unless (> x 5) (+ x 10))";
+        let (mut arena, nodes) = parse(code).unwrap();
+        let expanded = expand_program(&mut arena, &nodes).unwrap();
+
+        let mut env = Environment::new();
+        let mut result = SVal::Nil;
+        for id in expanded {
+            result = Interpreter::eval(arena.get(id).unwrap(), &mut env, &arena).unwrap();
+        }
+        assert!(matches!(result, SVal::Integer(11)));
+    }
+
+    #[test]
+    fn test_macros_survive_across_arenas() {
+        let mut expander = MacroExpander::new();
+        let (mut arena, nodes) = parse("(define-syntax id (syntax-rules () ((_ x) x)))").unwrap();
+        expander.expand(&mut arena, nodes[0]).unwrap();
+
+        let (mut arena, nodes) = parse("(id 42)").unwrap();
+        let id = expander.expand(&mut arena, nodes[0]).unwrap();
+        assert_eq!(arena.get(id), Some(&SExpr::Integer(42)));
+    }
+}
@@ -1,176 +1,641 @@
-use muscm::executor::Executor;
-use muscm::interpreter::{Environment, Interpreter};
+use muscm::ast::{Arena, NodeId, SExpr};
+use muscm::ast_dump;
+use muscm::cli::{
+    self, Command, Lang, Options, ParseOutput, Source, EXIT_SCRIPT_ERROR, EXIT_USAGE,
+};
+use muscm::debugger::{DebugEvent, Resume};
+use muscm::error_types::{LuaError, LuaResult};
+use muscm::executor::{ControlFlow, Executor};
+use muscm::interpreter::{Environment, Interpreter, SVal};
+use muscm::lint;
 use muscm::lua_interpreter::LuaInterpreter;
-use muscm::lua_parser::{parse as parse_lua, tokenize, TokenSlice};
+use muscm::lua_parser::{
+    parse_source, parse_source_with, shebang_line, tokenize_with_location, Chunk, ParseOptions,
+};
+use muscm::macro_expander::expand_program;
+use muscm::optimize::optimize;
 use muscm::parser::parse;
+use muscm::repl::{lua_names, Repl};
+use muscm::resolver::resolve;
+use muscm::stdlib::inspect::{inspect, InspectOptions};
+use muscm::testing;
+use muscm::tokenizer::{TokenType, Tokenizer};
+use muscm::vm::execute_chunk;
+use muscm::watch::FileWatch;
 use std::env;
-use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::time::Duration;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+    let program = args.first().map(String::as_str).unwrap_or("muscm");
 
-    if args.len() < 2 {
-        run_scheme_default();
-        return;
+    let options = match cli::parse_args(&args[1..]) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", cli::usage(program));
+            return;
+        }
+        Err(e) => {
+            eprintln!("{}: {}\n\n{}", program, e, cli::usage(program));
+            std::process::exit(EXIT_USAGE);
+        }
+    };
+
+    if let Err(e) = dispatch(program, &options) {
+        eprintln!("{}", e);
+        std::process::exit(EXIT_SCRIPT_ERROR);
     }
+}
 
-    match args[1].as_str() {
-        "lua" => {
-            if args.len() < 3 {
-                eprintln!("Usage: {} lua <file>", args[0]);
-                std::process::exit(1);
+fn dispatch(program: &str, options: &Options) -> Result<(), String> {
+    if options.command == Command::Lsp {
+        return lsp();
+    }
+    if options.command == Command::Dap {
+        return dap();
+    }
+    if let Command::Test { paths } = &options.command {
+        return test_lua(program, paths, options);
+    }
+    let Some(source) = &options.source else {
+        return match options.lang {
+            Lang::Lua => lua_repl(program, options),
+            Lang::Scheme => scheme_repl(&options.script_args),
+        };
+    };
+    let code = source.read()?;
+
+    match (&options.command, options.lang) {
+        (Command::Run, Lang::Lua) => run_lua(program, source, &code, options),
+        (Command::Watch { keep_globals }, Lang::Lua) => {
+            watch_lua(program, source, options, *keep_globals)
+        }
+        (Command::Watch { .. }, Lang::Scheme) => Err("watch only supports Lua scripts".to_string()),
+        (Command::Debug, Lang::Lua) => debug_lua(program, source, &code, options),
+        (Command::Debug, Lang::Scheme) => Err("debug only supports Lua scripts".to_string()),
+        (Command::Run, Lang::Scheme) if options.profile => {
+            Err("--profile only supports Lua scripts".to_string())
+        }
+        (Command::Run, Lang::Scheme) if options.coverage.is_some() => {
+            Err("--coverage only supports Lua scripts".to_string())
+        }
+        (Command::Run, Lang::Scheme) if options.inspect_result => {
+            Err("--inspect-result only supports Lua scripts".to_string())
+        }
+        (Command::Run, Lang::Scheme) if options.cache_dir.is_some() => {
+            Err("--cache-dir only supports Lua scripts".to_string())
+        }
+        (Command::Run, Lang::Scheme) if options.max_depth.is_some() => {
+            Err("--max-depth only supports Lua scripts".to_string())
+        }
+        (Command::Run, Lang::Scheme) if options.strict_globals => {
+            Err("--strict-globals only supports Lua scripts".to_string())
+        }
+        (Command::Run, Lang::Scheme) if options.run_loop => {
+            Err("--loop only supports Lua scripts".to_string())
+        }
+        (Command::Run, Lang::Scheme) if !options.vm => {
+            Err("--no-vm only supports Lua scripts".to_string())
+        }
+        (Command::Run, Lang::Scheme) => run_scheme(source, &code, &options.script_args),
+        (Command::Parse(output), Lang::Lua) => {
+            let block = parse_source(&code)?;
+            match output {
+                ParseOutput::Summary => {
+                    println!("{}: {} statements", source.name(), block.statements.len())
+                }
+                ParseOutput::Tree => println!("{:#?}", block),
+                ParseOutput::Json => println!("{:#}", ast_dump::lua_json(&block)),
+                ParseOutput::Sexp => println!("{}", ast_dump::sexp(&ast_dump::lua_json(&block))),
             }
-            run_lua(&args[2]);
+            Ok(())
         }
-        _ => {
-            run_scheme_default();
+        (Command::Parse(output), Lang::Scheme) => {
+            let (arena, nodes) = parse(&code).map_err(|e| format!("Parse error: {}", e))?;
+            match output {
+                ParseOutput::Json => println!("{:#}", ast_dump::scheme_json(&arena, &nodes)),
+                // Scheme source already is an S-expression
+                ParseOutput::Summary | ParseOutput::Sexp => {
+                    for expr in nodes.iter().filter_map(|node| arena.get(*node)) {
+                        println!("{}", Shown(expr, &arena));
+                    }
+                }
+                ParseOutput::Tree => {
+                    for node in nodes {
+                        dump_sexpr(&arena, node, 0);
+                    }
+                }
+            }
+            Ok(())
+        }
+        (Command::Tokenize, Lang::Lua) => {
+            let tokens =
+                tokenize_with_location(&code).map_err(|e| format!("Tokenize error: {}", e))?;
+            for t in tokens {
+                println!("{}:{}\t{:?}", t.location.line, t.location.column, t.token);
+            }
+            Ok(())
+        }
+        (Command::Tokenize, Lang::Scheme) => {
+            let mut tokenizer = Tokenizer::new(&code);
+            loop {
+                let token = tokenizer.next_token();
+                if token.token_type == TokenType::Eof {
+                    return Ok(());
+                }
+                println!("{}\t{}\t{}", token.line, token.token_type, token.literal);
+            }
+        }
+        (Command::Check, Lang::Lua) => {
+            let block = parse_source(&code)?;
+            let diagnostics = lint::lint(&block, &lint::builtin_globals());
+            for diagnostic in &diagnostics {
+                println!("{}:{}", source.name(), diagnostic);
+            }
+            if diagnostics.is_empty() {
+                println!("{}: ok", source.name());
+                Ok(())
+            } else {
+                Err(format!("{} warning(s)", diagnostics.len()))
+            }
+        }
+        (Command::Check, Lang::Scheme) => {
+            let (mut arena, nodes) = parse(&code).map_err(|e| format!("Parse error: {}", e))?;
+            expand_program(&mut arena, &nodes)
+                .map_err(|e| format!("Macro expansion error: {}", e))?;
+            println!("{}: ok", source.name());
+            Ok(())
+        }
+        (Command::Fmt(format), Lang::Lua) => {
+            let options = ParseOptions {
+                comments: true,
+                ..ParseOptions::default()
+            };
+            let block = parse_source_with(&code, &options)?;
+            let shebang = shebang_line(&code);
+            if !shebang.is_empty() {
+                println!("{}", shebang);
+            }
+            print!("{}", muscm::format::format_block(&block, format));
+            Ok(())
+        }
+        (Command::Fmt(_), Lang::Scheme) => Err("fmt only supports Lua".to_string()),
+        (Command::Repl | Command::Lsp | Command::Dap | Command::Test { .. }, _) => {
+            unreachable!("handled before reading a source")
         }
     }
 }
 
-fn run_scheme_default() {
-    // Test Phase 3: List Operations
-    let input = r#"
-(display "=== Phase 3: List Operations ===")
-(newline)
-
-(display "list? '(1 2 3): ")
-(display (list? '(1 2 3)))
-(newline)
+#[cfg(feature = "lsp")]
+fn lsp() -> Result<(), String> {
+    muscm::lsp::run(io::stdin().lock(), io::stdout().lock()).map_err(|e| format!("lsp: {}", e))
+}
 
-(display "list? '(): ")
-(display (list? '()))
-(newline)
+#[cfg(not(feature = "lsp"))]
+fn lsp() -> Result<(), String> {
+    Err("lsp: this build has no language server; rebuild with --features lsp".to_string())
+}
 
-(display "list? 42: ")
-(display (list? 42))
-(newline)
+#[cfg(feature = "dap")]
+fn dap() -> Result<(), String> {
+    muscm::dap::run(io::stdin().lock(), io::stdout().lock()).map_err(|e| format!("dap: {}", e))
+}
 
-(display "atom? 'hello: ")
-(display (atom? 'hello))
-(newline)
+#[cfg(not(feature = "dap"))]
+fn dap() -> Result<(), String> {
+    Err("dap: this build has no debug adapter; rebuild with --features dap".to_string())
+}
 
-(display "atom? '(1 2): ")
-(display (atom? '(1 2)))
-(newline)
+/// What Lua sees before the script in `arg`: the program name, then the
+/// subcommand and options
+fn interpreter_args(program: &str, options: &Options) -> Vec<String> {
+    let mut args = vec![program.to_string()];
+    args.extend(options.interpreter_args.iter().cloned());
+    args
+}
 
-(display "pair? '(1 2): ")
-(display (pair? '(1 2)))
-(newline)
+/// A Lua interpreter set up to run `source`: `arg` filled in and modules
+/// found next to the script, or in the working directory for inline code
+fn lua_interpreter_for(program: &str, source: &Source, options: &Options) -> LuaInterpreter {
+    let mut interpreter = LuaInterpreter::new();
+    if let Some(dir) = &options.cache_dir {
+        interpreter.enable_chunk_cache(Some(dir.clone()));
+    }
+    if let Some(depth) = options.max_depth {
+        interpreter.max_call_depth = depth;
+    }
+    interpreter.strict_globals = options.strict_globals;
+    interpreter.use_vm = options.vm;
+    interpreter.set_script_args(
+        &source.name(),
+        &interpreter_args(program, options),
+        &options.script_args,
+    );
+    if let Source::File(path) = source {
+        let script_dir = path
+            .canonicalize()
+            .ok()
+            .and_then(|p| p.parent().map(|parent| parent.to_path_buf()))
+            .or_else(|| path.parent().map(std::path::PathBuf::from));
+        if let Some(dir) = script_dir {
+            interpreter.add_module_search_path(dir);
+        }
+    }
+    interpreter
+}
 
-(display "null? '(): ")
-(display (null? '()))
-(newline)
+/// Parse and run a chunk, compiled to bytecode unless `--no-vm` is given
+/// or the VM does not support it
+///
+/// Profiling and coverage measure the code as written, so they skip the
+/// optimizer, which would fold away the branches they report on.
+fn execute_lua(
+    interpreter: &mut LuaInterpreter,
+    source: &Source,
+    code: &str,
+    options: &Options,
+) -> Result<(), String> {
+    let instrumented = options.profile || options.coverage.is_some();
+    let mut chunk = interpreter.chunk_cache.borrow_mut().parse(code)?;
+    if options.optimize && !instrumented {
+        let builtins = interpreter.global_names().into_iter().collect();
+        chunk = optimize(&chunk, &builtins);
+    }
+    let chunk = chunk.with_source(&source.chunk_name());
+    let flow = if instrumented {
+        walk_lua(interpreter, source, &chunk, options)?
+    } else {
+        execute_chunk(&chunk, interpreter).map_err(|e| runtime_error(&e, interpreter))?
+    };
+    if options.run_loop {
+        Executor::new()
+            .run_timers(interpreter)
+            .map_err(|e| runtime_error(&e, interpreter))?;
+    }
+    if let (true, ControlFlow::Return(values)) = (options.inspect_result, flow) {
+        let inspect_options = InspectOptions::default();
+        for value in values {
+            interpreter
+                .output
+                .write_str(&format!("{}\n", inspect(&value, &inspect_options)))
+                .map_err(|e| format!("Cannot write the result: {}", e))?;
+        }
+    }
+    Ok(())
+}
 
-(display "null? '(1): ")
-(display (null? '(1)))
-(newline)
+/// Run a chunk on the tree-walker, which is where profiling and coverage
+/// happen, and write their reports even when the script fails
+fn walk_lua(
+    interpreter: &mut LuaInterpreter,
+    source: &Source,
+    chunk: &Chunk,
+    options: &Options,
+) -> Result<ControlFlow, String> {
+    let chunk = resolve(chunk);
+    let mut executor = Executor::new();
+    if options.profile {
+        executor.enable_profiling();
+    }
+    if options.coverage.is_some() {
+        let mut coverage = interpreter.coverage.borrow_mut();
+        coverage.enable();
+        coverage.enter_chunk(&source.name(), &chunk);
+    }
+    let result = executor.execute_block(&chunk, interpreter);
+    if let Some(profile) = executor.profile() {
+        eprint!("{}", profile);
+    }
+    if let Some(path) = &options.coverage {
+        let mut coverage = interpreter.coverage.borrow_mut();
+        coverage.leave_chunk();
+        std::fs::write(path, coverage.lcov())
+            .map_err(|e| format!("Cannot write coverage report {}: {}", path.display(), e))?;
+    }
+    result.map_err(|e| runtime_error(&e, interpreter))
+}
 
-(display "car '(1 2 3): ")
-(display (car '(1 2 3)))
-(newline)
+fn run_lua(program: &str, source: &Source, code: &str, options: &Options) -> Result<(), String> {
+    let mut interpreter = lua_interpreter_for(program, source, options);
+    execute_lua(&mut interpreter, source, code, options)
+}
 
-(display "cdr '(1 2 3): ")
-(display (cdr '(1 2 3)))
-(newline)
+/// How often `watch` looks at the files
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
 
-(display "cons 0 '(1 2): ")
-(display (cons 0 '(1 2)))
-(newline)
+/// Run a script, then again every time it or a module it required changes
+///
+/// Errors are reported without stopping the loop, so a typo can be fixed
+/// and saved. Each run starts from a fresh interpreter unless
+/// `keep_globals` is set; modules are always loaded again.
+fn watch_lua(
+    program: &str,
+    source: &Source,
+    options: &Options,
+    keep_globals: bool,
+) -> Result<(), String> {
+    let Source::File(script) = source else {
+        return Err("watch needs a script file".to_string());
+    };
+    let mut watch = FileWatch::new();
+    let mut kept: Option<LuaInterpreter> = None;
+    loop {
+        // Stamped before running, so a save during the run triggers another
+        watch.clear();
+        watch.watch(script);
 
-(display "list 1 2 3: ")
-(display (list 1 2 3))
-(newline)
+        let mut interpreter = match kept.take() {
+            Some(interpreter) if keep_globals => interpreter,
+            _ => lua_interpreter_for(program, source, options),
+        };
+        interpreter.module_loader.borrow_mut().clear_cache();
+        match source
+            .read()
+            .and_then(|code| execute_lua(&mut interpreter, source, &code, options))
+        {
+            Ok(()) => eprintln!("[watch] {} finished", source.name()),
+            Err(e) => eprintln!("{}", e),
+        }
+        for module in interpreter.module_loader.borrow().source_files() {
+            watch.watch(module);
+        }
+        kept = Some(interpreter);
 
-(display "length '(a b c d): ")
-(display (length '(a b c d)))
-(newline)
+        eprintln!(
+            "[watch] waiting for changes to {} file(s)",
+            watch.files().count()
+        );
+        let changed = watch.wait(WATCH_INTERVAL);
+        let names: Vec<String> = changed.iter().map(|p| p.display().to_string()).collect();
+        eprintln!("[watch] {} changed, running again", names.join(", "));
+    }
+}
 
-(display "append '(1 2) '(3 4): ")
-(display (append '(1 2) '(3 4)))
-(newline)
+/// Run every test file found under `paths`, each in a fresh interpreter,
+/// and fail when any test did
+fn test_lua(program: &str, paths: &[PathBuf], options: &Options) -> Result<(), String> {
+    let roots = match paths {
+        [] => vec![PathBuf::from(".")],
+        _ => paths.to_vec(),
+    };
+    let files = testing::discover(&roots).map_err(|e| format!("Cannot find tests: {}", e))?;
+    if files.is_empty() {
+        return Err("No test files found (*_test.lua or spec/*.lua)".to_string());
+    }
+    let (mut passed, mut failed) = (0, 0);
+    for file in files {
+        let source = Source::File(file.clone());
+        let mut interpreter = lua_interpreter_for(program, &source, options);
+        let report = testing::run_file(&mut interpreter, &file);
+        print!("{}", report);
+        passed += report.passed();
+        failed += report.failed();
+    }
+    println!("\n{} passed, {} failed", passed, failed);
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("{} test(s) failed", failed)),
+    }
+}
 
-(display "append '() '(5 6): ")
-(display (append '() '(5 6)))
-(newline)
+/// Commands `muscm debug` takes at each stop
+const DEBUG_HELP: &str = "Commands:
+  s, step                stop at the next line, entering calls
+  n, next                stop at the next line, stepping over calls
+  o, out                 stop at the next line of the caller
+  c, continue            run to the next breakpoint
+  b, break [FILE:]LINE   set a breakpoint
+  d, delete [FILE:]LINE  remove a breakpoint
+  breakpoints            list breakpoints
+  l, locals              show the locals of the running function
+  p, print NAME          show a local or global
+  set NAME EXPR          assign a local, or a global when there is none
+  bt, where              show the call stack
+  q, quit                stop the script";
 
-(display "append '(a) '(b) '(c): ")
-(display (append '(a) '(b) '(c)))
-(newline)
-"#;
+/// Run a Lua script on the tree-walker, stopping at its first line and
+/// reading debugger commands from stdin at every stop
+fn debug_lua(program: &str, source: &Source, code: &str, options: &Options) -> Result<(), String> {
+    let mut interpreter = lua_interpreter_for(program, source, options);
+    let block = resolve(&parse_source(code)?.with_source(&source.chunk_name()));
+    let file = source.name();
+    let lines: Vec<String> = code.lines().map(String::from).collect();
+    let mut executor = Executor::new();
+    executor.pause();
+    let main = file.clone();
+    executor.set_debug_handler(move |event| debug_stop(event, &main, &lines));
+    println!("Debugging {}; type help for commands", file);
+    match executor.execute_file(&file, &block, &mut interpreter) {
+        Ok(_) | Err(LuaError::Cancelled) => Ok(()),
+        Err(e) => Err(runtime_error(&e, &interpreter)),
+    }
+}
 
-    match parse(input) {
-        Ok((arena, node_ids)) => {
-            let mut env = Environment::new();
-            for node_id in node_ids {
-                if let Some(expr) = arena.get(node_id) {
-                    match Interpreter::eval(expr, &mut env, &arena) {
-                        Ok(val) => println!("{}", val),
-                        Err(e) => println!("ERROR: {}", e),
+/// Show where the script stopped and run commands until one resumes it;
+/// end of input continues to the end of the script
+fn debug_stop(event: &mut DebugEvent<'_>, main: &str, lines: &[String]) -> LuaResult<Resume> {
+    let file = event.file.as_deref().unwrap_or(main).to_string();
+    let text = event.line.checked_sub(1).and_then(|i| lines.get(i));
+    match text.filter(|_| file == main) {
+        Some(text) => println!("{}:{}: {}", file, event.line, text.trim()),
+        None => println!("{}:{}", file, event.line),
+    }
+    // Bare line numbers name the file that is stopped
+    let location = |arg: &str| -> Option<(String, usize)> {
+        match arg.rsplit_once(':') {
+            Some((file, line)) => Some((file.to_string(), line.parse().ok()?)),
+            None => Some((file.clone(), arg.parse().ok()?)),
+        }
+    };
+    let stdin = io::stdin();
+    loop {
+        print!("(debug) ");
+        io::stdout().flush().ok();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+            println!();
+            return Ok(Resume::Continue);
+        }
+        let (command, arg) = match line.trim().split_once(char::is_whitespace) {
+            Some((command, arg)) => (command, arg.trim()),
+            None => (line.trim(), ""),
+        };
+        match command {
+            "" => {}
+            "s" | "step" => return Ok(Resume::StepInto),
+            "n" | "next" => return Ok(Resume::StepOver),
+            "o" | "out" => return Ok(Resume::StepOut),
+            "c" | "continue" => return Ok(Resume::Continue),
+            "q" | "quit" => return Err(LuaError::Cancelled),
+            "b" | "break" => match location(arg) {
+                Some((file, line)) => {
+                    event.set_breakpoint(&file, line);
+                    println!("Breakpoint at {}:{}", file, line);
+                }
+                None => println!("break needs [FILE:]LINE"),
+            },
+            "d" | "delete" => match location(arg) {
+                Some((file, line)) if event.clear_breakpoint(&file, line) => {
+                    println!("Deleted breakpoint at {}:{}", file, line)
+                }
+                Some((file, line)) => println!("No breakpoint at {}:{}", file, line),
+                None => println!("delete needs [FILE:]LINE"),
+            },
+            "breakpoints" => {
+                for (file, line) in event.breakpoints() {
+                    println!("{}:{}", file, line);
+                }
+            }
+            "l" | "locals" => {
+                for (name, value) in event.locals() {
+                    println!("{} = {}", name, value.to_string_value());
+                }
+            }
+            "p" | "print" => {
+                let value = event
+                    .local(arg)
+                    .unwrap_or_else(|| event.interpreter().get_global(arg));
+                println!("{} = {}", arg, value.to_string_value());
+            }
+            "set" => {
+                let Some((name, expr)) = arg.split_once(char::is_whitespace) else {
+                    println!("set needs NAME EXPR");
+                    continue;
+                };
+                match event.evaluate(expr.trim()) {
+                    Ok(value) => {
+                        if !event.set_local(name, value.clone()) {
+                            event.interpreter().set_global(name, value);
+                        }
                     }
+                    Err(e) => println!("{}", e),
                 }
             }
+            "bt" | "where" => println!("{}", event.interpreter().trace.borrow().traceback(0)),
+            "h" | "help" => println!("{}", DEBUG_HELP),
+            other => println!("unknown command '{}' (type help for commands)", other),
         }
-        Err(e) => println!("Parse error: {}", e),
     }
 }
 
-fn run_lua(file_path: &str) {
-    // Read the Lua file
-    let code = match fs::read_to_string(file_path) {
-        Ok(content) => content,
-        Err(e) => {
-            eprintln!("Error reading file '{}': {}", file_path, e);
-            std::process::exit(1);
-        }
-    };
+/// Report an uncaught Lua error, with the traceback from where it was raised
+fn runtime_error(error: &LuaError, interpreter: &LuaInterpreter) -> String {
+    match interpreter.error_traceback(error) {
+        Some(traceback) => format!("Runtime error: {}\n{}", error, traceback),
+        None => format!("Runtime error: {}", error),
+    }
+}
 
-    // Tokenize the code
-    let tokens = match tokenize(&code) {
-        Ok(tokens) => tokens,
-        Err(e) => {
-            eprintln!("Tokenize error: {}", e);
-            std::process::exit(1);
-        }
-    };
+fn run_scheme(source: &Source, code: &str, script_args: &[String]) -> Result<(), String> {
+    let (mut arena, nodes) = parse(code).map_err(|e| format!("Parse error: {}", e))?;
+    let nodes =
+        expand_program(&mut arena, &nodes).map_err(|e| format!("Macro expansion error: {}", e))?;
 
-    // Parse the code
-    let token_slice = TokenSlice::from(tokens.as_slice());
-    let block = match parse_lua(token_slice) {
-        Ok((_, block)) => block,
-        Err(e) => {
-            eprintln!("Parse error: {:?}", e);
-            std::process::exit(1);
+    let mut env = Environment::new();
+    env.set_command_line(&source.name(), script_args);
+    for node in nodes {
+        let expr = arena.get(node).ok_or("Invalid node reference")?;
+        Interpreter::eval(expr, &mut env, &arena).map_err(|e| format!("Error: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Displays a Scheme expression whose children live in an arena
+struct Shown<'a>(&'a SExpr, &'a Arena);
+
+impl std::fmt::Display for Shown<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.display_with_arena(self.1, f)
+    }
+}
+
+/// Print a Scheme syntax tree, one node per line
+fn dump_sexpr(arena: &Arena, node: NodeId, depth: usize) {
+    let indent = "  ".repeat(depth);
+    let Some(expr) = arena.get(node) else {
+        println!("{}#<invalid {}>", indent, node);
+        return;
+    };
+    let (label, children): (&str, Vec<NodeId>) = match expr {
+        SExpr::List(ids) => ("List", ids.clone()),
+        // The tail is the last child
+        SExpr::DottedList(ids, tail) => ("DottedList", [ids.as_slice(), &[*tail]].concat()),
+        SExpr::Vector(ids) => ("Vector", ids.clone()),
+        SExpr::Quote(id) => ("Quote", vec![*id]),
+        SExpr::QuasiQuote(id) => ("QuasiQuote", vec![*id]),
+        SExpr::Unquote(id) => ("Unquote", vec![*id]),
+        SExpr::UnquoteSplicing(id) => ("UnquoteSplicing", vec![*id]),
+        leaf => {
+            println!("{}{:?}", indent, leaf);
+            return;
         }
     };
+    println!("{}{}", indent, label);
+    for child in children {
+        dump_sexpr(arena, child, depth + 1);
+    }
+}
 
-    // Create a Lua interpreter and executor
+fn lua_repl(program: &str, options: &Options) -> Result<(), String> {
     let mut interpreter = LuaInterpreter::new();
+    interpreter.set_script_args(
+        "repl",
+        &interpreter_args(program, options),
+        &options.script_args,
+    );
+    if let Ok(dir) = env::current_dir() {
+        interpreter.add_module_search_path(dir);
+    }
 
-    // Add the script's directory to the module search paths
-    let script_dir = std::path::Path::new(file_path)
-        .canonicalize()
-        .ok()
-        .and_then(|p| p.parent().map(|parent| parent.to_path_buf()))
-        .or_else(|| {
-            // Fallback: use parent of the path, or current dir if no parent
-            std::path::Path::new(file_path)
-                .parent()
-                .map(|p| std::path::PathBuf::from(p))
+    let mut repl = Repl::new(Lang::Lua)?;
+    while let Some(entry) = repl.read_entry("> ", lua_names(&interpreter), |code| {
+        parse_source(&format!("return {}", code)).is_ok() || parse_source(code).is_ok()
+    }) {
+        // Bare expressions are evaluated and printed, like the reference REPL
+        let block = parse_source(&format!("return {}", entry)).or_else(|_| parse_source(&entry));
+        let result = block.and_then(|block| {
+            execute_chunk(&block, &mut interpreter).map_err(|e| runtime_error(&e, &interpreter))
         });
-
-    if let Some(dir) = script_dir {
-        interpreter.add_module_search_path(dir);
+        match result {
+            Ok(ControlFlow::Return(values)) if !values.is_empty() => {
+                let shown: Vec<String> = values.iter().map(|v| v.to_string_value()).collect();
+                println!("{}", shown.join("\t"));
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("{}", e),
+        }
     }
+    println!();
+    Ok(())
+}
 
-    let mut executor = Executor::new();
+fn scheme_repl(script_args: &[String]) -> Result<(), String> {
+    let mut env = Environment::new();
+    env.set_command_line("repl", script_args);
 
-    // Execute the block
-    match executor.execute_block(&block, &mut interpreter) {
-        Ok(_) => {}
-        Err(e) => {
-            eprintln!("Runtime error: {}", e);
-            std::process::exit(1);
+    let mut repl = Repl::new(Lang::Scheme)?;
+    while let Some(entry) = repl.read_entry("scm> ", env.names(), |code| parse(code).is_ok()) {
+        let result = parse(&entry)
+            .map_err(|e| format!("Parse error: {}", e))
+            .and_then(|(mut arena, nodes)| {
+                let nodes = expand_program(&mut arena, &nodes)
+                    .map_err(|e| format!("Macro expansion error: {}", e))?;
+                let mut last = SVal::Nil;
+                for node in nodes {
+                    let expr = arena.get(node).ok_or("Invalid node reference")?;
+                    last = Interpreter::eval(expr, &mut env, &arena)
+                        .map_err(|e| format!("Error: {}", e))?;
+                }
+                Ok(last)
+            });
+        match result {
+            Ok(SVal::Nil) => {}
+            Ok(value) => println!("{}", value),
+            Err(e) => eprintln!("{}", e),
         }
     }
+    println!();
+    Ok(())
 }
@@ -2,72 +2,200 @@
 ///
 /// This module implements a module loading system for Lua code.
 /// Allows code organization and reuse via `require("<module>")`.
-use crate::lua_value::LuaValue;
-use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+///
+/// The loader's state lives in the Lua `package` table so scripts can read
+/// and change it: `package.path` holds `;`-separated templates where `?`
+/// stands for the module name with dots turned into directory separators,
+/// `package.loaded` caches every loaded module, and `package.preload` maps
+/// names to loader functions consulted before any file is searched.
+use crate::lua_value::{LuaFunction, LuaTable, LuaValue, TableData};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Templates searched when nothing else is configured
+pub const DEFAULT_PATH: &str = "./?.lua;./?/init.lua;./modules/?.lua;./lib/?.lua";
+
+/// Find the first file among `templates`, a `;`-separated list in which
+/// `?` stands for `name`
+///
+/// On failure the error lists every file tried, one `\n\tno file '...'`
+/// line each, ready to append to a message.
+pub fn search_path(templates: &str, name: &str) -> Result<PathBuf, String> {
+    let mut tried = String::new();
+    for template in templates.split(';').filter(|t| !t.is_empty()) {
+        let candidate = PathBuf::from(template.replace('?', name));
+        if candidate.is_file() {
+            return Ok(candidate);
+        }
+        tried.push_str(&format!("\n\tno file '{}'", candidate.display()));
+    }
+    Err(tried)
+}
 
 /// Manages module loading and caching
 pub struct ModuleLoader {
-    /// Search paths for modules (e.g., ['.', 'modules/', 'lib/'])
-    pub search_paths: Vec<PathBuf>,
-    /// Cache of loaded modules
-    pub loaded_modules: HashMap<String, LuaValue>,
+    /// The `package` table: `path`, `loaded` and `preload`
+    pub package: Rc<RefCell<LuaTable>>,
     /// Tracks modules currently being loaded (for circular dependency detection)
     pub loading: HashSet<String>,
+    /// The file each module was loaded from, in load order; `watch` mode
+    /// reloads when one of them changes
+    pub sources: Vec<(String, PathBuf)>,
+}
+
+fn new_table() -> Rc<RefCell<LuaTable>> {
+    Rc::new(RefCell::new(LuaTable {
+        data: TableData::new(),
+        metatable: None,
+        frozen: false,
+        removed: None,
+    }))
+}
+
+fn key(name: &str) -> LuaValue {
+    LuaValue::String(name.into())
 }
 
 impl ModuleLoader {
-    /// Create a new module loader with default search paths
+    /// Create a new module loader searching `DEFAULT_PATH`
     pub fn new() -> Self {
+        let package = new_table();
+        {
+            let mut package = package.borrow_mut();
+            package.data.insert(key("path"), key(DEFAULT_PATH));
+            package
+                .data
+                .insert(key("loaded"), LuaValue::Table(new_table()));
+            package
+                .data
+                .insert(key("preload"), LuaValue::Table(new_table()));
+        }
         ModuleLoader {
-            search_paths: vec![
-                PathBuf::from("."),
-                PathBuf::from("modules"),
-                PathBuf::from("lib"),
-            ],
-            loaded_modules: HashMap::new(),
+            package,
             loading: HashSet::new(),
+            sources: Vec::new(),
+        }
+    }
+
+    /// The `package` table as a Lua value
+    pub fn package_value(&self) -> LuaValue {
+        LuaValue::Table(Rc::clone(&self.package))
+    }
+
+    /// The current `package.path`
+    pub fn path(&self) -> String {
+        match self.package.borrow().data.get(&key("path")) {
+            Some(LuaValue::String(path)) => path.to_string(),
+            _ => String::new(),
         }
     }
 
-    /// Add a search path for module discovery
+    /// Replace `package.path`
+    pub fn set_path(&self, path: &str) {
+        self.package
+            .borrow_mut()
+            .data
+            .insert(key("path"), key(path));
+    }
+
+    /// Add a directory to search, as `dir/?.lua;dir/?/init.lua` templates
     pub fn add_search_path(&mut self, path: PathBuf) {
-        self.search_paths.push(path);
+        let dir = path.display().to_string();
+        let mut templates = self.path();
+        for template in ["?.lua", "?/init.lua"] {
+            if !templates.is_empty() {
+                templates.push(';');
+            }
+            templates.push_str(&Path::new(&dir).join(template).display().to_string());
+        }
+        self.set_path(&templates);
     }
 
-    /// Resolve a module name to a file path
+    /// Resolve a module name to a file path using `package.path`
     ///
-    /// "mymodule" → finds mymodule.lua in search paths
-    /// "config.server" → finds config/server.lua in search paths
+    /// "mymodule" → mymodule.lua or mymodule/init.lua
+    /// "config.server" → config/server.lua or config/server/init.lua
     pub fn resolve_module(&self, module_name: &str) -> Result<PathBuf, String> {
-        // Convert dot notation to path notation
-        let path_part = module_name.replace('.', "/");
-        let filename = format!("{}.lua", path_part);
-
-        for search_path in &self.search_paths {
-            let full_path = search_path.join(&filename);
-            if full_path.exists() && full_path.is_file() {
-                return Ok(full_path);
-            }
+        search_path(&self.path(), &module_name.replace('.', "/"))
+            .map_err(|tried| format!("Module not found: {}{}", module_name, tried))
+    }
+
+    /// One of the tables stored in `package`, if the script hasn't replaced it
+    fn subtable(&self, name: &str) -> Option<Rc<RefCell<LuaTable>>> {
+        match self.package.borrow().data.get(&key(name)) {
+            Some(LuaValue::Table(table)) => Some(Rc::clone(table)),
+            _ => None,
         }
+    }
 
-        Err(format!("Module not found: {}", module_name))
+    /// The cached value of a module, from `package.loaded`
+    pub fn loaded(&self, module_name: &str) -> Option<LuaValue> {
+        let loaded = self.subtable("loaded")?;
+        let value = loaded.borrow().data.get(&key(module_name)).cloned();
+        value.filter(|value| !matches!(value, LuaValue::Nil))
+    }
+
+    /// Record a module's value in `package.loaded`
+    pub fn set_loaded(&self, module_name: &str, value: LuaValue) {
+        if let Some(loaded) = self.subtable("loaded") {
+            loaded.borrow_mut().data.insert(key(module_name), value);
+        }
+    }
+
+    /// The `package.preload` loader registered for a module
+    pub fn preloader(&self, module_name: &str) -> Option<LuaValue> {
+        let preload = self.subtable("preload")?;
+        let value = preload.borrow().data.get(&key(module_name)).cloned();
+        value.filter(|value| !matches!(value, LuaValue::Nil))
+    }
+
+    /// Register a host function as `package.preload[module_name]`
+    ///
+    /// `require` calls it with the module name and caches what it returns.
+    pub fn preload(
+        &self,
+        module_name: &str,
+        loader: Rc<dyn Fn(Vec<LuaValue>) -> crate::error_types::LuaResult<LuaValue>>,
+    ) {
+        if let Some(preload) = self.subtable("preload") {
+            preload.borrow_mut().data.insert(
+                key(module_name),
+                LuaValue::Function(Rc::new(LuaFunction::Builtin(loader))),
+            );
+        }
+    }
+
+    /// Remember that `module_name` was read from `path`
+    pub fn record_source(&mut self, module_name: &str, path: PathBuf) {
+        self.sources.retain(|(name, _)| name != module_name);
+        self.sources.push((module_name.to_string(), path));
+    }
+
+    /// Files of every module loaded from disk since the cache was cleared
+    pub fn source_files(&self) -> Vec<PathBuf> {
+        self.sources.iter().map(|(_, path)| path.clone()).collect()
     }
 
     /// Check if a module is already cached
     pub fn is_cached(&self, module_name: &str) -> bool {
-        self.loaded_modules.contains_key(module_name)
+        self.loaded(module_name).is_some()
     }
 
     /// Clear the module cache
     pub fn clear_cache(&mut self) {
-        self.loaded_modules.clear();
+        if let Some(loaded) = self.subtable("loaded") {
+            loaded.borrow_mut().data.clear();
+        }
         self.loading.clear();
+        self.sources.clear();
     }
 
     /// Get number of cached modules
     pub fn cached_count(&self) -> usize {
-        self.loaded_modules.len()
+        self.subtable("loaded")
+            .map_or(0, |loaded| loaded.borrow().data.len())
     }
 }
 
@@ -84,16 +212,33 @@ mod tests {
     #[test]
     fn test_module_loader_creation() {
         let loader = ModuleLoader::new();
-        assert_eq!(loader.search_paths.len(), 3);
-        assert!(loader.loaded_modules.is_empty());
+        assert_eq!(loader.path(), DEFAULT_PATH);
+        assert_eq!(loader.cached_count(), 0);
         assert!(loader.loading.is_empty());
     }
 
+    #[test]
+    fn test_sources_follow_the_cache() {
+        let mut loader = ModuleLoader::new();
+        loader.record_source("a", PathBuf::from("a.lua"));
+        loader.record_source("b", PathBuf::from("b.lua"));
+        loader.record_source("a", PathBuf::from("lib/a.lua"));
+        assert_eq!(
+            loader.source_files(),
+            vec![PathBuf::from("b.lua"), PathBuf::from("lib/a.lua")]
+        );
+        loader.clear_cache();
+        assert!(loader.source_files().is_empty());
+    }
+
     #[test]
     fn test_add_search_path() {
         let mut loader = ModuleLoader::new();
         loader.add_search_path(PathBuf::from("custom"));
-        assert_eq!(loader.search_paths.len(), 4);
+        assert_eq!(
+            loader.path(),
+            format!("{};custom/?.lua;custom/?/init.lua", DEFAULT_PATH)
+        );
     }
 
     #[test]
@@ -109,31 +254,39 @@ mod tests {
         let loader = ModuleLoader::new();
         // Test path resolution logic (file won't exist)
         let result = loader.resolve_module("config.server");
-        assert!(result.is_err());
-        // Should have tried paths like config/server.lua
+        let message = result.unwrap_err();
+        assert!(message.contains("no file './config/server.lua'"));
+        assert!(message.contains("no file './config/server/init.lua'"));
+    }
+
+    #[test]
+    fn test_resolve_with_custom_path() {
+        let loader = ModuleLoader::new();
+        loader.set_path("fixtures/modules/?.lua");
+        assert_eq!(
+            loader.resolve_module("utils.math").unwrap(),
+            PathBuf::from("fixtures/modules/utils/math.lua")
+        );
+        assert!(loader.resolve_module("missing").is_err());
     }
 
     #[test]
     fn test_is_cached() {
-        let mut loader = ModuleLoader::new();
+        let loader = ModuleLoader::new();
         assert!(!loader.is_cached("mymodule"));
 
-        loader
-            .loaded_modules
-            .insert("mymodule".to_string(), LuaValue::Nil);
+        loader.set_loaded("mymodule", LuaValue::Boolean(true));
         assert!(loader.is_cached("mymodule"));
     }
 
     #[test]
     fn test_clear_cache() {
         let mut loader = ModuleLoader::new();
-        loader
-            .loaded_modules
-            .insert("module1".to_string(), LuaValue::Number(42.0));
+        loader.set_loaded("module1", LuaValue::Number(42.0));
         loader.loading.insert("module2".to_string());
 
         loader.clear_cache();
-        assert!(loader.loaded_modules.is_empty());
+        assert_eq!(loader.cached_count(), 0);
         assert!(loader.loading.is_empty());
     }
 }
@@ -0,0 +1,966 @@
+//! The net library for Lua scripts, behind the `net` feature
+//!
+//! `net.http_get(url)` and `net.http_request{...}` speak HTTP/1.1 over a
+//! plain `TcpStream`. There is no TLS, so only `http://` URLs work, and
+//! redirects are returned rather than followed. `net.connect(host, port)`
+//! opens a TCP connection with `send`, `receive` and `close` methods.
+//! Failing to reach or talk to a host comes back as `nil, message, errno`,
+//! as it does for the io functions; bad arguments raise an error.
+//!
+//! Every function first checks the interpreter's `NetworkAccess`, so a host
+//! running untrusted scripts can switch the network off, which also stops
+//! connections and functions a script already holds. Leaving `net` out of
+//! a `sandbox` hides the library altogether.
+
+use crate::error_types::{LuaError, LuaResult};
+use crate::file_io::io_failure;
+use crate::lua_value::{LuaFunction, LuaTable, LuaValue, TableData};
+use crate::stdlib::timer::get_seconds;
+use crate::stdlib::validation;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::rc::Rc;
+use std::time::Duration;
+
+/// How long connecting, and each read or write, may take unless the
+/// script gives a `timeout`
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Most a response body may hold; a larger one fails instead of filling
+/// memory
+const MAX_BODY: usize = 64 * 1024 * 1024;
+
+/// Longest line a response head may have
+const MAX_HEAD_LINE: usize = 64 * 1024;
+
+/// Metatable key holding a connection's `Connection`
+const CONNECTION_KEY: &str = "__connection";
+
+/// Whether scripts may use the network; clones share the switch
+#[derive(Debug, Clone)]
+pub struct NetworkAccess(Rc<Cell<bool>>);
+
+impl NetworkAccess {
+    /// Access starts allowed
+    pub fn new() -> Self {
+        NetworkAccess(Rc::new(Cell::new(true)))
+    }
+
+    pub fn set_allowed(&self, allowed: bool) {
+        self.0.set(allowed);
+    }
+
+    pub fn is_allowed(&self) -> bool {
+        self.0.get()
+    }
+
+    fn check(&self, name: &str) -> LuaResult<()> {
+        if self.is_allowed() {
+            Ok(())
+        } else {
+            Err(LuaError::runtime("network access is disabled", name))
+        }
+    }
+}
+
+impl Default for NetworkAccess {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The parts of an `http://` URL a request needs
+#[derive(Debug, PartialEq, Eq)]
+struct Url {
+    /// Host and port as written, for the `Host` header
+    authority: String,
+    /// Host to connect to, without the brackets of an IPv6 address
+    host: String,
+    port: u16,
+    /// Path and query, at least `/`
+    path: String,
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.into())
+}
+
+fn bad_response(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn parse_url(url: &str) -> io::Result<Url> {
+    let rest = match url.split_once("://") {
+        Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => rest,
+        Some((scheme, _)) if scheme.eq_ignore_ascii_case("https") => {
+            return Err(invalid("https is not supported (net has no TLS)"))
+        }
+        Some((scheme, _)) => return Err(invalid(format!("unsupported scheme '{}'", scheme))),
+        None => return Err(invalid("not an http:// URL")),
+    };
+    let (authority, path) = match rest.find(['/', '?', '#']) {
+        Some(at) => (&rest[..at], &rest[at..]),
+        None => (rest, ""),
+    };
+    // The fragment stays with the client
+    let path = path.split('#').next().unwrap_or_default();
+    let path = match path.strip_prefix('?') {
+        Some(query) => format!("/?{}", query),
+        None if path.is_empty() => "/".to_string(),
+        None => path.to_string(),
+    };
+    let (host, port) = match authority.rfind(':') {
+        Some(at) if !authority[at..].contains(']') => {
+            let port = authority[at + 1..]
+                .parse()
+                .map_err(|_| invalid(format!("bad port in '{}'", authority)))?;
+            (&authority[..at], port)
+        }
+        _ => (authority, 80),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return Err(invalid("the URL has no host"));
+    }
+    Ok(Url {
+        authority: authority.to_string(),
+        host: host.to_string(),
+        port,
+        path,
+    })
+}
+
+/// Connect to the first address of `host` that answers within `timeout`,
+/// which also bounds every later read and write
+fn connect(host: &str, port: u16, timeout: Duration) -> io::Result<TcpStream> {
+    let mut failure = None;
+    for address in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                return Ok(stream);
+            }
+            Err(e) => failure = Some(e),
+        }
+    }
+    Err(failure.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "no address found for the host")
+    }))
+}
+
+/// What a script asks `http_request` for
+struct Request {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Option<String>,
+    timeout: Duration,
+}
+
+/// What the server answered
+struct Response {
+    status: u16,
+    reason: String,
+    /// Names in lower case, in the order they came
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+fn send_request(request: &Request) -> io::Result<Response> {
+    let url = parse_url(&request.url)?;
+    let has_header = |name: &str| {
+        request
+            .headers
+            .iter()
+            .any(|(given, _)| given.eq_ignore_ascii_case(name))
+    };
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\n",
+        request.method, url.path, url.authority
+    );
+    if !has_header("user-agent") {
+        head.push_str("User-Agent: muscm\r\n");
+    }
+    if !has_header("connection") {
+        head.push_str("Connection: close\r\n");
+    }
+    for (name, value) in &request.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if let Some(body) = &request.body {
+        if !has_header("content-length") {
+            head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+    }
+    head.push_str("\r\n");
+
+    let mut stream = connect(&url.host, url.port, request.timeout)?;
+    stream.write_all(head.as_bytes())?;
+    if let Some(body) = &request.body {
+        stream.write_all(body.as_bytes())?;
+    }
+    stream.flush()?;
+    read_response(&mut BufReader::new(stream), &request.method)
+}
+
+/// One line of the response head, without its line ending
+fn read_head_line(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut line = Vec::new();
+    let limit = MAX_HEAD_LINE as u64 + 1;
+    if reader.by_ref().take(limit).read_until(b'\n', &mut line)? == 0 {
+        return Err(bad_response(
+            "the connection closed before the response ended",
+        ));
+    }
+    if line.len() > MAX_HEAD_LINE {
+        return Err(bad_response(format!(
+            "response line longer than {} bytes",
+            MAX_HEAD_LINE
+        )));
+    }
+    while matches!(line.last(), Some(b'\n' | b'\r')) {
+        line.pop();
+    }
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+fn read_response(reader: &mut impl BufRead, method: &str) -> io::Result<Response> {
+    let status_line = read_head_line(reader)?;
+    let mut parts = status_line.splitn(3, ' ');
+    if !parts.next().unwrap_or_default().starts_with("HTTP/") {
+        return Err(bad_response(format!(
+            "not an HTTP response: {}",
+            status_line
+        )));
+    }
+    let status: u16 = parts
+        .next()
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| bad_response(format!("bad status line: {}", status_line)))?;
+    let reason = parts.next().unwrap_or_default().to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let line = read_head_line(reader)?;
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| bad_response(format!("bad header line: {}", line)))?;
+        headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(given, _)| given == name)
+            .map(|(_, value)| value.as_str())
+    };
+
+    let mut body = Vec::new();
+    let bodiless = method.eq_ignore_ascii_case("HEAD")
+        || (100..200).contains(&status)
+        || status == 204
+        || status == 304;
+    if bodiless {
+    } else if header("transfer-encoding").is_some_and(|te| te.contains("chunked")) {
+        loop {
+            let size_line = read_head_line(reader)?;
+            let size = size_line.split(';').next().unwrap_or_default().trim();
+            let size = usize::from_str_radix(size, 16)
+                .map_err(|_| bad_response(format!("bad chunk size: {}", size_line)))?;
+            if size == 0 {
+                // Skip any trailers
+                while !read_head_line(reader)?.is_empty() {}
+                break;
+            }
+            let start = body.len();
+            let end = start
+                .checked_add(size)
+                .filter(|&end| end <= MAX_BODY)
+                .ok_or_else(body_too_large)?;
+            body.resize(end, 0);
+            reader.read_exact(&mut body[start..])?;
+            read_head_line(reader)?;
+        }
+    } else if let Some(length) = header("content-length") {
+        let length: u64 = length
+            .parse()
+            .map_err(|_| bad_response(format!("bad Content-Length: {}", length)))?;
+        if length > MAX_BODY as u64 {
+            return Err(body_too_large());
+        }
+        reader.take(length).read_to_end(&mut body)?;
+        if (body.len() as u64) < length {
+            return Err(bad_response("the connection closed before the body ended"));
+        }
+    } else {
+        reader.take(MAX_BODY as u64 + 1).read_to_end(&mut body)?;
+        if body.len() > MAX_BODY {
+            return Err(body_too_large());
+        }
+    }
+
+    Ok(Response {
+        status,
+        reason,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
+
+fn body_too_large() -> io::Error {
+    bad_response(format!("response body larger than {} bytes", MAX_BODY))
+}
+
+fn new_table(data: TableData, metatable: Option<HashMap<String, LuaValue>>) -> LuaValue {
+    LuaValue::Table(Rc::new(RefCell::new(LuaTable {
+        data,
+        metatable: metatable.map(|fields| Box::new(fields.into())),
+        frozen: false,
+        removed: None,
+    })))
+}
+
+fn string(value: impl AsRef<str>) -> LuaValue {
+    LuaValue::String(value.as_ref().into())
+}
+
+/// Response headers as a table; a repeated header's values are joined
+/// with commas
+fn headers_table(headers: &[(String, String)]) -> LuaValue {
+    let mut joined: Vec<(&str, String)> = Vec::new();
+    for (name, value) in headers {
+        match joined.iter_mut().find(|(seen, _)| seen == name) {
+            Some((_, all)) => {
+                all.push_str(", ");
+                all.push_str(value);
+            }
+            None => joined.push((name, value.clone())),
+        }
+    }
+    new_table(
+        joined
+            .into_iter()
+            .map(|(name, value)| (string(name), string(value)))
+            .collect(),
+        None,
+    )
+}
+
+/// Text that goes into a request head as is; line breaks would let it
+/// start headers of its own
+fn head_text(name: &str, what: &str, value: &LuaValue) -> LuaResult<String> {
+    let text = validation::get_string(name, 0, value)?;
+    if text.contains(['\r', '\n']) {
+        return Err(LuaError::value(format!(
+            "bad {} for '{}' (line breaks are not allowed)",
+            what, name
+        )));
+    }
+    Ok(text)
+}
+
+/// A header name, which must be an HTTP token: no spaces, colons or line
+/// breaks that would end it early
+fn header_name(name: &str, value: &LuaValue) -> LuaResult<String> {
+    let text = head_text(name, "header", value)?;
+    let token = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+    if text.is_empty() || !text.chars().all(token) {
+        return Err(LuaError::value(format!(
+            "bad header name '{}' for '{}'",
+            text, name
+        )));
+    }
+    Ok(text)
+}
+
+fn get_timeout(name: &str, value: Option<&LuaValue>) -> LuaResult<Duration> {
+    match value {
+        None | Some(LuaValue::Nil) => Ok(DEFAULT_TIMEOUT),
+        Some(value) => {
+            let timeout = get_seconds(name, 0, value)?;
+            if timeout.is_zero() {
+                return Err(LuaError::value(format!(
+                    "bad timeout for '{}' (must be positive)",
+                    name
+                )));
+            }
+            Ok(timeout)
+        }
+    }
+}
+
+/// Read the fields of `net.http_request`'s table argument
+fn request_from_table(table: &LuaTable) -> LuaResult<Request> {
+    const NAME: &str = "net.http_request";
+    let field = |key: &str| table.get_str(key).filter(|v| !matches!(v, LuaValue::Nil));
+    let url = match field("url") {
+        Some(url) => head_text(NAME, "url", url)?,
+        None => return Err(LuaError::value("net.http_request needs a url field")),
+    };
+    let method = match field("method") {
+        Some(method) => head_text(NAME, "method", method)?.to_ascii_uppercase(),
+        None => "GET".to_string(),
+    };
+    if method.is_empty() || method.contains(' ') {
+        return Err(LuaError::value(format!(
+            "bad method '{}' for 'net.http_request'",
+            method
+        )));
+    }
+    let mut headers = Vec::new();
+    if let Some(given) = field("headers") {
+        let given = validation::get_table(NAME, 0, given)?;
+        for (name, value) in &given.borrow().data {
+            headers.push((header_name(NAME, name)?, head_text(NAME, "header", value)?));
+        }
+    }
+    let body = field("body")
+        .map(|body| validation::get_string(NAME, 0, body))
+        .transpose()?;
+    Ok(Request {
+        method,
+        url,
+        headers,
+        body,
+        timeout: get_timeout(NAME, field("timeout"))?,
+    })
+}
+
+/// Create net.http_get(url [, timeout])
+/// Returns the body, the status code and the headers, or nil, a message
+/// and the errno when the request fails
+pub fn create_net_http_get(
+    access: NetworkAccess,
+) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
+    Rc::new(move |args| {
+        access.check("net.http_get")?;
+        validation::require_args("net.http_get", &args, 1, Some(2))?;
+        let request = Request {
+            method: "GET".to_string(),
+            url: head_text("net.http_get", "url", &args[0])?,
+            headers: Vec::new(),
+            body: None,
+            timeout: get_timeout("net.http_get", args.get(1))?,
+        };
+        Ok(match send_request(&request) {
+            Ok(response) => vec![
+                string(response.body),
+                LuaValue::Number(response.status as f64),
+                headers_table(&response.headers),
+            ],
+            Err(e) => io_failure(Some(&request.url), &e),
+        })
+    })
+}
+
+/// Create net.http_request{url = ..., method = ..., headers = ...,
+/// body = ..., timeout = ...}
+/// Returns a table with the status, reason, headers and body, or nil, a
+/// message and the errno when the request fails
+pub fn create_net_http_request(
+    access: NetworkAccess,
+) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
+    Rc::new(move |args| {
+        access.check("net.http_request")?;
+        validation::require_args("net.http_request", &args, 1, Some(1))?;
+        let table = validation::get_table("net.http_request", 0, &args[0])?;
+        let request = request_from_table(&table.borrow())?;
+        Ok(match send_request(&request) {
+            Ok(response) => {
+                let mut data = TableData::new();
+                data.insert(string("status"), LuaValue::Number(response.status as f64));
+                data.insert(string("reason"), string(&response.reason));
+                data.insert(string("headers"), headers_table(&response.headers));
+                data.insert(string("body"), string(response.body));
+                vec![new_table(data, None)]
+            }
+            Err(e) => io_failure(Some(&request.url), &e),
+        })
+    })
+}
+
+/// An open TCP connection; `None` once closed
+struct Connection(Option<BufReader<TcpStream>>);
+
+/// Run `op` on the stream of the connection value `args[0]`
+fn with_connection(
+    name: &str,
+    args: &[LuaValue],
+    op: impl FnOnce(&mut BufReader<TcpStream>) -> LuaResult<Vec<LuaValue>>,
+) -> LuaResult<Vec<LuaValue>> {
+    let value = args.first().unwrap_or(&LuaValue::Nil);
+    let userdata = match value {
+        LuaValue::Table(t) => match t
+            .borrow()
+            .metatable
+            .as_ref()
+            .and_then(|mt| mt.get(CONNECTION_KEY))
+        {
+            Some(LuaValue::UserData(userdata)) => Some(userdata),
+            _ => None,
+        },
+        _ => None,
+    };
+    let userdata =
+        userdata.ok_or_else(|| LuaError::type_error("connection", value.type_name(), name))?;
+    let mut borrow = userdata.borrow_mut();
+    let connection = borrow
+        .downcast_mut::<Connection>()
+        .ok_or_else(|| LuaError::type_error("connection", value.type_name(), name))?;
+    match &mut connection.0 {
+        Some(stream) => op(stream),
+        None => Err(LuaError::runtime(
+            "attempt to use a closed connection",
+            name,
+        )),
+    }
+}
+
+/// Create conn:send(data), which returns the number of bytes sent
+fn create_connection_send(
+    access: NetworkAccess,
+) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
+    Rc::new(move |args| {
+        access.check("send")?;
+        validation::require_args("send", &args, 2, Some(2))?;
+        let data = validation::get_bytes("send", 1, &args[1])?;
+        with_connection("send", &args, |stream| {
+            let sent = stream
+                .get_mut()
+                .write_all(data.as_bytes())
+                .and_then(|_| stream.get_mut().flush());
+            Ok(match sent {
+                Ok(()) => vec![LuaValue::Number(data.len() as f64)],
+                Err(e) => io_failure(None, &e),
+            })
+        })
+    })
+}
+
+/// Create conn:receive([pattern])
+/// Reads a line without its ending (`"l"`, the default), everything until
+/// the peer closes (`"a"`) or up to `n` bytes. A line or bytes asked for
+/// after the peer has closed give nil and `"closed"`.
+fn create_connection_receive(
+    access: NetworkAccess,
+) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
+    Rc::new(move |args| {
+        access.check("receive")?;
+        validation::require_args("receive", &args, 1, Some(2))?;
+        let closed = || vec![LuaValue::Nil, string("closed")];
+        with_connection("receive", &args, |stream| {
+            let mut data = Vec::new();
+            let read = match args.get(1) {
+                None | Some(LuaValue::Nil) => stream.read_until(b'\n', &mut data),
+                Some(LuaValue::String(pattern)) => {
+                    match pattern.strip_prefix(b"*").unwrap_or(pattern) {
+                        b"l" => stream.read_until(b'\n', &mut data),
+                        b"a" => match stream.read_to_end(&mut data) {
+                            Ok(_) => return Ok(vec![LuaValue::String(data.into())]),
+                            Err(e) => Err(e),
+                        },
+                        _ => {
+                            return Err(LuaError::value(format!(
+                                "bad argument #1 to 'receive' (invalid pattern '{}')",
+                                pattern
+                            )))
+                        }
+                    }
+                }
+                Some(count) => {
+                    let count = validation::get_integer("receive", 1, count)?.max(0) as u64;
+                    if count == 0 {
+                        return Ok(vec![string("")]);
+                    }
+                    stream.take(count).read_to_end(&mut data)
+                }
+            };
+            match read {
+                Ok(0) => Ok(closed()),
+                Ok(_) => {
+                    if matches!(
+                        args.get(1),
+                        None | Some(LuaValue::Nil | LuaValue::String(_))
+                    ) {
+                        while matches!(data.last(), Some(b'\n' | b'\r')) {
+                            data.pop();
+                        }
+                    }
+                    Ok(vec![LuaValue::String(data.into())])
+                }
+                Err(e) => Ok(io_failure(None, &e)),
+            }
+        })
+    })
+}
+
+/// Create conn:close()
+fn create_connection_close() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
+    Rc::new(|args| {
+        validation::require_args("close", &args, 1, Some(1))?;
+        let value = args[0].clone();
+        with_connection("close", &args, |_| Ok(Vec::new()))?;
+        if let LuaValue::Table(t) = value {
+            if let Some(LuaValue::UserData(userdata)) = t
+                .borrow()
+                .metatable
+                .as_ref()
+                .and_then(|mt| mt.get(CONNECTION_KEY))
+            {
+                if let Some(connection) = userdata.borrow_mut().downcast_mut::<Connection>() {
+                    connection.0 = None;
+                }
+            }
+        }
+        Ok(vec![LuaValue::Boolean(true)])
+    })
+}
+
+/// Create net.connect(host, port [, timeout])
+/// Returns a connection, or nil, a message and the errno when the host
+/// cannot be reached
+pub fn create_net_connect(
+    access: NetworkAccess,
+    methods: LuaValue,
+) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
+    Rc::new(move |args| {
+        access.check("net.connect")?;
+        validation::require_args("net.connect", &args, 2, Some(3))?;
+        let host = validation::get_string("net.connect", 0, &args[0])?;
+        let port = validation::get_integer("net.connect", 1, &args[1])?;
+        let port = u16::try_from(port)
+            .map_err(|_| LuaError::value("bad argument #2 to 'net.connect' (port out of range)"))?;
+        let timeout = get_timeout("net.connect", args.get(2))?;
+        let stream = match connect(&host, port, timeout) {
+            Ok(stream) => stream,
+            Err(e) => return Ok(io_failure(Some(&format!("{}:{}", host, port)), &e)),
+        };
+        let connection: Box<dyn std::any::Any> = Box::new(Connection(Some(BufReader::new(stream))));
+        let mut metatable = HashMap::new();
+        metatable.insert(
+            CONNECTION_KEY.to_string(),
+            LuaValue::UserData(Rc::new(RefCell::new(connection))),
+        );
+        metatable.insert("__index".to_string(), methods.clone());
+        metatable.insert("__name".to_string(), string("tcp"));
+        Ok(vec![new_table(TableData::new(), Some(metatable))])
+    })
+}
+
+/// Create the net table, checking `access` on every call
+pub fn create_net_table(access: NetworkAccess) -> LuaValue {
+    let multi = |f: Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>>| {
+        LuaValue::Function(Rc::new(LuaFunction::MultiBuiltin(f)))
+    };
+    let methods = new_table(
+        [
+            ("send", multi(create_connection_send(access.clone()))),
+            ("receive", multi(create_connection_receive(access.clone()))),
+            ("close", multi(create_connection_close())),
+        ]
+        .into_iter()
+        .map(|(name, f)| (string(name), f))
+        .collect(),
+        None,
+    );
+    new_table(
+        [
+            ("http_get", multi(create_net_http_get(access.clone()))),
+            (
+                "http_request",
+                multi(create_net_http_request(access.clone())),
+            ),
+            ("connect", multi(create_net_connect(access, methods))),
+        ]
+        .into_iter()
+        .map(|(name, f)| (string(name), f))
+        .collect(),
+        None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::ControlFlow;
+    use crate::lua_interpreter::LuaInterpreter;
+    use crate::lua_parser::parse_source;
+    use crate::vm::execute_chunk;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    fn run(interp: &mut LuaInterpreter, code: &str) -> LuaResult<Vec<LuaValue>> {
+        let block = parse_source(code).map_err(LuaError::value)?;
+        match execute_chunk(&block, interp)? {
+            ControlFlow::Return(values) => Ok(values),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Serve one connection with `reply`, sending back the request's head
+    /// and body as the server read them
+    fn serve_once(reply: &'static str) -> (u16, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sent, received) = mpsc::channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str(&String::from_utf8(body).unwrap());
+            reader.get_mut().write_all(reply.as_bytes()).unwrap();
+            sent.send(request).unwrap();
+        });
+        (port, received)
+    }
+
+    #[test]
+    fn test_parse_url() {
+        let url = parse_url("http://example.com:8080/a/b?c=d#top").unwrap();
+        assert_eq!(
+            url,
+            Url {
+                authority: "example.com:8080".into(),
+                host: "example.com".into(),
+                port: 8080,
+                path: "/a/b?c=d".into(),
+            }
+        );
+        assert_eq!(parse_url("http://[::1]").unwrap().host, "::1");
+        assert_eq!(parse_url("HTTP://host?q").unwrap().path, "/?q");
+        assert_eq!(parse_url("http://host").unwrap().port, 80);
+        for bad in [
+            "https://host/",
+            "ftp://host",
+            "host/path",
+            "http://:80/",
+            "http://h:x/",
+        ] {
+            assert!(parse_url(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_http_get() {
+        let (port, request) = serve_once(
+            "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\nSet-Cookie: a\r\n\
+             Set-Cookie: b\r\nContent-Length: 7\r\n\r\nmissing and more",
+        );
+        let values = run(
+            &mut LuaInterpreter::new(),
+            &format!(
+                "local body, status, headers = net.http_get('http://127.0.0.1:{}/page?x=1')\n\
+                 return body, status, headers['content-type'], headers['set-cookie']",
+                port
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            values,
+            vec![
+                string("missing"),
+                LuaValue::Number(404.0),
+                string("text/plain"),
+                string("a, b"),
+            ]
+        );
+        let request = request.recv().unwrap();
+        assert!(
+            request.starts_with(&format!(
+                "GET /page?x=1 HTTP/1.1\r\nHost: 127.0.0.1:{}\r\n",
+                port
+            )),
+            "{}",
+            request
+        );
+        assert!(request.contains("Connection: close\r\n"), "{}", request);
+    }
+
+    #[test]
+    fn test_http_request_with_a_body_and_a_chunked_reply() {
+        let (port, request) = serve_once(
+            "HTTP/1.1 201 Created\r\nTransfer-Encoding: chunked\r\n\r\n\
+             4\r\nmade\r\n6;ext=1\r\n it ok\r\n0\r\nTrailer: x\r\n\r\n",
+        );
+        let values = run(
+            &mut LuaInterpreter::new(),
+            &format!(
+                "local reply = net.http_request{{url = 'http://localhost:{}/items', \
+                 method = 'post', headers = {{['X-Token'] = 'abc'}}, body = 'name=x', timeout = 5}}\n\
+                 return reply.status, reply.reason, reply.body",
+                port
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            values,
+            vec![
+                LuaValue::Number(201.0),
+                string("Created"),
+                string("made it ok")
+            ]
+        );
+        let request = request.recv().unwrap();
+        assert!(
+            request.starts_with("POST /items HTTP/1.1\r\n"),
+            "{}",
+            request
+        );
+        assert!(request.contains("X-Token: abc\r\n"), "{}", request);
+        assert!(
+            request.ends_with("Content-Length: 6\r\n\r\nname=x"),
+            "{}",
+            request
+        );
+    }
+
+    #[test]
+    fn test_oversized_bodies_fail() {
+        for reply in [
+            "HTTP/1.1 200 OK\r\nContent-Length: 99999999999\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1\r\na\r\nffffffffffffffff\r\n",
+        ] {
+            let (port, _) = serve_once(reply);
+            let values = run(
+                &mut LuaInterpreter::new(),
+                &format!("return net.http_get('http://127.0.0.1:{}/')", port),
+            )
+            .unwrap();
+            assert_eq!(values[0], LuaValue::Nil);
+            let LuaValue::String(message) = &values[1] else {
+                panic!("expected a message, got {:?}", values);
+            };
+            assert!(
+                message.to_string().contains("response body larger than"),
+                "{}",
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn test_tcp_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            reader
+                .get_mut()
+                .write_all(format!("echo {}\r\n12345rest", line.trim_end()).as_bytes())
+                .unwrap();
+        });
+        let mut interp = LuaInterpreter::new();
+        let values = run(
+            &mut interp,
+            &format!(
+                "conn = assert(net.connect('127.0.0.1', {}))\n\
+                 local sent = conn:send('hello\\r\\n')\n\
+                 local line = conn:receive()\n\
+                 local first = conn:receive(5)\n\
+                 local rest = conn:receive('*a')\n\
+                 local after, message = conn:receive()\n\
+                 return sent, line, first, rest, after, message, conn:close()",
+                port
+            ),
+        );
+        server.join().unwrap();
+        assert_eq!(
+            values.unwrap(),
+            vec![
+                LuaValue::Number(7.0),
+                string("echo hello"),
+                string("12345"),
+                string("rest"),
+                LuaValue::Nil,
+                string("closed"),
+                LuaValue::Boolean(true),
+            ]
+        );
+        let err = run(&mut interp, "conn:send('more')").unwrap_err();
+        assert!(err.to_string().contains("closed connection"), "{}", err);
+    }
+
+    #[test]
+    fn test_failures_and_disabled_access() {
+        // A port nothing listens on
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut interp = LuaInterpreter::new();
+        let values = run(
+            &mut interp,
+            &format!(
+                "local a, refused = net.http_get('http://127.0.0.1:{0}/')\n\
+                 local b, tls = net.http_get('https://example.com/')\n\
+                 local c, connect = net.connect('127.0.0.1', {0})\n\
+                 return a, b, c, tls, refused ~= nil, connect ~= nil",
+                port
+            ),
+        )
+        .unwrap();
+        assert_eq!(values[..3], [LuaValue::Nil, LuaValue::Nil, LuaValue::Nil]);
+        assert_eq!(
+            values[3],
+            string("https://example.com/: https is not supported (net has no TLS)")
+        );
+        assert_eq!(
+            values[4..],
+            [LuaValue::Boolean(true), LuaValue::Boolean(true)]
+        );
+
+        for code in [
+            "net.http_get('http://a\\r\\nb/')",
+            "net.http_request{url = 'http://localhost/', headers = {['X-A: b'] = 'c'}}",
+            "net.http_request{url = 'http://localhost/', headers = {['X\\r\\nY'] = 'c'}}",
+            "net.http_request{url = 'http://localhost/', headers = {A = 'b\\nC: d'}}",
+            "net.http_request{method = 'GET'}",
+            "net.connect('localhost', 70000)",
+            "net.http_get('http://localhost/', 0)",
+        ] {
+            assert!(run(&mut interp, code).is_err(), "{}", code);
+        }
+
+        // Switched off, functions a script kept stop working too
+        run(&mut interp, "kept = net.http_get").unwrap();
+        let child = interp.fork_env();
+        interp.network.set_allowed(false);
+        assert!(!child.network.is_allowed());
+        let err = run(&mut interp, "return kept('http://127.0.0.1/')").unwrap_err();
+        assert!(
+            err.to_string().contains("network access is disabled"),
+            "{}",
+            err
+        );
+
+        let mut sandboxed = LuaInterpreter::new().sandbox(&["print"]);
+        assert_eq!(
+            run(&mut sandboxed, "return net").unwrap(),
+            vec![LuaValue::Nil]
+        );
+    }
+}
@@ -193,11 +193,11 @@ fn parse_expr(input: &str) -> IResult<&str, SExpr> {
         parse_unquote,
         parse_quasi_quote,
         parse_quote,
-        map(parse_bool, |b| SExpr::Bool(b)),
-        map(parse_char, |c| SExpr::Char(c)),
+        map(parse_bool, SExpr::Bool),
+        map(parse_char, SExpr::Char),
         map(parse_string, |s| SExpr::String(s.to_string())),
-        map(parse_number, |n| SExpr::Number(n)),
-        map(parse_atom, |a| SExpr::Atom(a)),
+        map(parse_number, SExpr::Number),
+        map(parse_atom, SExpr::Atom),
     ))
     .parse(input)
 }
@@ -0,0 +1,581 @@
+/// Optimization pass over a parsed Lua chunk
+///
+/// Runs between parsing and execution and rewrites the tree into a cheaper
+/// one with the same behaviour:
+///
+/// - Operators whose operands are all literals are folded into a literal,
+///   using the executor's own arithmetic so results match exactly. An
+///   operation that would raise an error, like `1 // 0` or `"a" + 1`, is
+///   left alone to fail at run time.
+/// - `if` arms whose condition folds to a constant are dropped or taken:
+///   `if true then ... end` becomes a `do` block, and `while false` loops
+///   disappear.
+/// - The bounds and step of a numeric `for` are folded, so a loop over
+///   `1, 2 ^ 10` evaluates no arithmetic before it starts.
+/// - Reads of a standard library global such as `print` or `math` become
+///   `Expression::Global`, which looks in the globals table directly
+///   instead of walking the scope stack. A name some enclosing block
+///   declares as a local, parameter or chunk-level function is left alone.
+///
+/// The optimized chunk gets a copy of the arena with blocks and bodies
+/// rewritten under their old ids; blocks of dropped statements stay in it
+/// unused. Comments are not carried over, as the result is only executed. The
+/// `run` command skips the pass with `--no-optimize`, which keeps the tree
+/// exactly as written when debugging.
+use crate::executor::Executor;
+use crate::lua_parser::{
+    BinaryOp, Block, BlockId, Chunk, Expression, Field, FieldKey, FunctionId, LuaArena, Numeral,
+    ReturnStatement, Statement,
+};
+use crate::lua_value::LuaValue;
+use crate::stack::with_headroom;
+use std::collections::HashSet;
+
+/// Optimize a chunk, treating `builtins` as the names of the globals it
+/// starts with
+pub fn optimize(chunk: &Chunk, builtins: &HashSet<String>) -> Chunk {
+    let mut optimizer = Optimizer {
+        builtins,
+        from: &chunk.arena,
+        arena: chunk.arena.as_ref().clone(),
+        scopes: vec![Vec::new()],
+        executor: Executor::new(),
+    };
+    let block = optimizer.block_body(chunk.root);
+    *optimizer.arena.block_mut(chunk.root) = block;
+    Chunk::new(optimizer.arena, chunk.root)
+}
+
+struct Optimizer<'a> {
+    builtins: &'a HashSet<String>,
+    /// The arena being optimized
+    from: &'a LuaArena,
+    /// Its copy, where optimized blocks replace the originals
+    arena: LuaArena,
+    /// Names declared by each enclosing block, innermost last
+    scopes: Vec<Vec<String>>,
+    /// Only used for its operator semantics
+    executor: Executor,
+}
+
+impl Optimizer<'_> {
+    fn declare(&mut self, name: &str) {
+        self.scopes
+            .last_mut()
+            .expect("always one scope")
+            .push(name.to_string());
+    }
+
+    fn is_declared(&self, name: &str) -> bool {
+        self.scopes.iter().flatten().any(|n| n == name)
+    }
+
+    /// Optimize block `id` in a scope of its own
+    fn block(&mut self, id: BlockId) -> BlockId {
+        self.scopes.push(Vec::new());
+        *self.arena.block_mut(id) = self.block_body(id);
+        self.scopes.pop();
+        id
+    }
+
+    fn block_body(&mut self, id: BlockId) -> Block {
+        let from = self.from;
+        let block = from.block(id);
+        let mut statements = Vec::with_capacity(block.statements.len());
+        let mut lines = Vec::with_capacity(block.lines.len());
+        for (i, statement) in block.statements.iter().enumerate() {
+            if let Some(statement) = self.statement(statement) {
+                statements.push(statement);
+            }
+            // Dropped statements take their line with them
+            if let Some(&line) = block.lines.get(i) {
+                lines.resize(statements.len(), line);
+            }
+        }
+        let return_statement = block.return_statement.as_ref().map(|ret| ReturnStatement {
+            expression_list: self.expressions(&ret.expression_list),
+        });
+        if let Some(&line) = block.lines.get(block.statements.len()) {
+            lines.push(line);
+        }
+        Block {
+            statements,
+            return_statement,
+            lines,
+            columns: Vec::new(),
+            trivia: Vec::new(),
+        }
+    }
+
+    /// The optimized statement, or `None` when it can never do anything
+    fn statement(&mut self, statement: &Statement) -> Option<Statement> {
+        with_headroom(|| self.statement_node(statement))
+    }
+
+    fn statement_node(&mut self, statement: &Statement) -> Option<Statement> {
+        let optimized = match statement {
+            Statement::Empty
+            | Statement::Break
+            | Statement::Label(_)
+            | Statement::Goto(_)
+            | Statement::LocalSlots { .. }
+            | Statement::ForNumericSlot { .. }
+            | Statement::ForGenericSlots { .. } => statement.clone(),
+            Statement::Assignment { variables, values } => Statement::Assignment {
+                variables: variables.iter().map(|v| self.target(v)).collect(),
+                values: self.expressions(values),
+            },
+            Statement::FunctionCall(call) => Statement::FunctionCall(self.expression(call)),
+            Statement::Do(body) => Statement::Do(self.block(*body)),
+            Statement::While { condition, body } => {
+                let condition = self.expression(condition);
+                if constant(&condition).is_some_and(|c| !c.is_truthy()) {
+                    return None;
+                }
+                Statement::While {
+                    condition,
+                    body: self.block(*body),
+                }
+            }
+            Statement::Repeat { body, condition } => {
+                // The condition can see the body's locals
+                self.scopes.push(Vec::new());
+                *self.arena.block_mut(*body) = self.block_body(*body);
+                let condition = self.expression(condition);
+                self.scopes.pop();
+                Statement::Repeat {
+                    body: *body,
+                    condition,
+                }
+            }
+            Statement::If {
+                condition,
+                then_block,
+                elseif_parts,
+                else_block,
+            } => {
+                let arms = std::iter::once((condition, *then_block))
+                    .chain(elseif_parts.iter().map(|(cond, block)| (cond, *block)));
+                return self.if_arms(arms, *else_block);
+            }
+            Statement::ForNumeric {
+                var,
+                start,
+                end,
+                step,
+                body,
+            } => {
+                let start = self.expression(start);
+                let end = self.expression(end);
+                let step = step.as_ref().map(|s| self.expression(s));
+                self.scopes.push(vec![var.clone()]);
+                let body = self.block(*body);
+                self.scopes.pop();
+                Statement::ForNumeric {
+                    var: var.clone(),
+                    start,
+                    end,
+                    step,
+                    body,
+                }
+            }
+            Statement::ForGeneric {
+                vars,
+                iterables,
+                body,
+            } => {
+                let iterables = self.expressions(iterables);
+                self.scopes.push(vars.clone());
+                let body = self.block(*body);
+                self.scopes.pop();
+                Statement::ForGeneric {
+                    vars: vars.clone(),
+                    iterables,
+                    body,
+                }
+            }
+            Statement::FunctionDecl { name, body } => {
+                // At chunk level this binds the name in the current scope;
+                // inside a function treating it as a local only means the
+                // name is not pre-resolved
+                if name.is_simple() {
+                    self.declare(name.base());
+                }
+                Statement::FunctionDecl {
+                    name: name.clone(),
+                    body: self.function(*body),
+                }
+            }
+            Statement::LocalFunction { name, body } => {
+                // Declared before the body so the function can call itself
+                self.declare(name);
+                Statement::LocalFunction {
+                    name: name.clone(),
+                    body: self.function(*body),
+                }
+            }
+            Statement::LocalVars { names, values } => {
+                let values = values.as_ref().map(|v| self.expressions(v));
+                for name in names {
+                    self.declare(name);
+                }
+                Statement::LocalVars {
+                    names: names.clone(),
+                    values,
+                }
+            }
+        };
+        Some(optimized)
+    }
+
+    /// Rebuild an `if` from its arms, dropping the ones whose condition is
+    /// constantly false and stopping at one that is constantly true
+    fn if_arms<'b>(
+        &mut self,
+        arms: impl Iterator<Item = (&'b Expression, BlockId)>,
+        else_block: Option<BlockId>,
+    ) -> Option<Statement> {
+        let mut kept: Vec<(Expression, BlockId)> = Vec::new();
+        let mut otherwise = None;
+        for (condition, block) in arms {
+            let condition = self.expression(condition);
+            match constant(&condition).map(|c| c.is_truthy()) {
+                Some(false) => {}
+                Some(true) => {
+                    otherwise = Some(self.block(block));
+                    break;
+                }
+                None => kept.push((condition, self.block(block))),
+            }
+        }
+        if otherwise.is_none() {
+            otherwise = else_block.map(|block| self.block(block));
+        }
+
+        if kept.is_empty() {
+            // Still a block of its own, so its locals stay inside it
+            return otherwise.map(Statement::Do);
+        }
+        let mut kept = kept.into_iter();
+        let (condition, then_block) = kept.next().expect("checked not empty");
+        Some(Statement::If {
+            condition,
+            then_block,
+            elseif_parts: kept.collect(),
+            else_block: otherwise,
+        })
+    }
+
+    fn function(&mut self, id: FunctionId) -> FunctionId {
+        let from = self.from;
+        let body = from.function(id);
+        // Enclosing locals stay visible as upvalues
+        self.scopes.push(body.params.clone());
+        *self.arena.block_mut(body.block) = self.block_body(body.block);
+        self.scopes.pop();
+        id
+    }
+
+    fn expressions(&mut self, exprs: &[Expression]) -> Vec<Expression> {
+        exprs.iter().map(|e| self.expression(e)).collect()
+    }
+
+    /// An assignment target: a bare name is written, not read, so it stays
+    /// as it is
+    fn target(&mut self, expr: &Expression) -> Expression {
+        match expr {
+            Expression::Identifier(_) => expr.clone(),
+            _ => self.expression(expr),
+        }
+    }
+
+    fn expression(&mut self, expr: &Expression) -> Expression {
+        with_headroom(|| self.expression_node(expr))
+    }
+
+    fn expression_node(&mut self, expr: &Expression) -> Expression {
+        match expr {
+            Expression::Nil
+            | Expression::Boolean(_)
+            | Expression::Number(_)
+            | Expression::String(_)
+            | Expression::Varargs
+            | Expression::Local { .. }
+            | Expression::Upvalue { .. }
+            | Expression::Global(_) => expr.clone(),
+            Expression::Identifier(name) => {
+                if self.builtins.contains(&**name) && !self.is_declared(name) {
+                    Expression::Global(name.clone())
+                } else {
+                    expr.clone()
+                }
+            }
+            Expression::BinaryOp { left, op, right } => {
+                let left = self.expression(left);
+                let right = self.expression(right);
+                self.fold_binary(left, op, right)
+            }
+            Expression::UnaryOp { op, operand } => {
+                let operand = self.expression(operand);
+                constant(&operand)
+                    .and_then(|value| self.executor.apply_unary_op(op, value).ok())
+                    .and_then(literal)
+                    .unwrap_or_else(|| Expression::UnaryOp {
+                        op: op.clone(),
+                        operand: Box::new(operand),
+                    })
+            }
+            Expression::TableIndexing { object, index } => Expression::TableIndexing {
+                object: Box::new(self.expression(object)),
+                index: Box::new(self.expression(index)),
+            },
+            Expression::FieldAccess { object, field } => Expression::FieldAccess {
+                object: Box::new(self.expression(object)),
+                field: field.clone(),
+            },
+            Expression::FunctionCall { function, args } => Expression::FunctionCall {
+                function: Box::new(self.expression(function)),
+                args: self.expressions(args),
+            },
+            Expression::Paren(inner) => Expression::Paren(Box::new(self.expression(inner))),
+            Expression::MethodCall {
+                object,
+                method,
+                args,
+            } => Expression::MethodCall {
+                object: Box::new(self.expression(object)),
+                method: method.clone(),
+                args: self.expressions(args),
+            },
+            Expression::TableConstructor { fields } => Expression::TableConstructor {
+                fields: fields
+                    .iter()
+                    .map(|field| Field {
+                        key: match &field.key {
+                            FieldKey::Bracket(key) => {
+                                FieldKey::Bracket(Box::new(self.expression(key)))
+                            }
+                            key => key.clone(),
+                        },
+                        value: self.expression(&field.value),
+                    })
+                    .collect(),
+            },
+            Expression::FunctionDef(body) => Expression::FunctionDef(self.function(*body)),
+        }
+    }
+
+    fn fold_binary(&self, left: Expression, op: &BinaryOp, right: Expression) -> Expression {
+        match (constant(&left), op) {
+            // `and`/`or` pick an operand; a call or `...` stands for a
+            // single value there, so it cannot take the operator's place
+            (Some(value), BinaryOp::And | BinaryOp::Or)
+                if value.is_truthy() == (*op == BinaryOp::Or) || single_valued(&right) =>
+            {
+                if value.is_truthy() == (*op == BinaryOp::And) {
+                    right
+                } else {
+                    left
+                }
+            }
+            (Some(l), _) if !matches!(op, BinaryOp::And | BinaryOp::Or) => constant(&right)
+                .and_then(|r| self.executor.apply_binary_op(&l, op, &r).ok())
+                .and_then(literal)
+                .unwrap_or_else(|| binary(left, op, right)),
+            _ => binary(left, op, right),
+        }
+    }
+}
+
+fn binary(left: Expression, op: &BinaryOp, right: Expression) -> Expression {
+    Expression::BinaryOp {
+        left: Box::new(left),
+        op: op.clone(),
+        right: Box::new(right),
+    }
+}
+
+/// The value of a literal
+fn constant(expr: &Expression) -> Option<LuaValue> {
+    match expr {
+        Expression::Nil => Some(LuaValue::Nil),
+        Expression::Boolean(b) => Some(LuaValue::Boolean(*b)),
+        Expression::Number(n) => Some(LuaValue::Number(n.to_f64())),
+        Expression::String(s) => Some(LuaValue::String(s.clone())),
+        _ => None,
+    }
+}
+
+/// A literal for a folded value; no numeral spells infinity or NaN, so
+/// those are left to be computed at run time
+fn literal(value: LuaValue) -> Option<Expression> {
+    match value {
+        LuaValue::Nil => Some(Expression::Nil),
+        LuaValue::Boolean(b) => Some(Expression::Boolean(b)),
+        LuaValue::Number(n) if n.is_finite() => Some(Expression::Number(Numeral::Float(n))),
+        LuaValue::String(s) => Some(Expression::String(s)),
+        _ => None,
+    }
+}
+
+/// Whether an expression yields exactly one value wherever it appears
+fn single_valued(expr: &Expression) -> bool {
+    !matches!(
+        expr,
+        Expression::FunctionCall { .. } | Expression::MethodCall { .. } | Expression::Varargs
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua_interpreter::LuaInterpreter;
+    use crate::lua_parser::parse_source;
+    use crate::vm::execute_chunk;
+
+    fn builtins() -> HashSet<String> {
+        LuaInterpreter::new().global_names().into_iter().collect()
+    }
+
+    fn optimized(code: &str) -> Chunk {
+        optimize(&parse_source(code).unwrap(), &builtins())
+    }
+
+    fn returned(code: &str) -> Vec<Expression> {
+        optimized(code)
+            .return_statement
+            .clone()
+            .unwrap()
+            .expression_list
+    }
+
+    #[test]
+    fn test_folds_constant_operators() {
+        assert_eq!(
+            returned("return 1 + 2 * 3, 2 ^ 10, -(4), 'a' .. 1 .. 'b', #'abc', not nil"),
+            vec![
+                Expression::Number(Numeral::Float(7.0)),
+                Expression::Number(Numeral::Float(1024.0)),
+                Expression::Number(Numeral::Float(-4.0)),
+                Expression::String("a1b".into()),
+                Expression::Number(Numeral::Float(3.0)),
+                Expression::Boolean(true),
+            ]
+        );
+        assert_eq!(
+            returned("return 1 < 2, 'a' == 'b', nil or 5, false and x"),
+            vec![
+                Expression::Boolean(true),
+                Expression::Boolean(false),
+                Expression::Number(Numeral::Integer(5)),
+                Expression::Boolean(false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_leaves_errors_and_calls_to_run_time() {
+        let [Expression::BinaryOp { .. }, Expression::BinaryOp { .. }, Expression::BinaryOp { .. }] =
+            &returned("return 1 // 0, 'a' + 1, true and f()")[..]
+        else {
+            panic!("expected the operators to be kept");
+        };
+    }
+
+    #[test]
+    fn test_removes_constant_branches() {
+        let block = optimized(
+            "if false then print(1) elseif true then x = 2 else x = 3 end\n\
+             while nil do end\n\
+             if y then x = 4 elseif false then x = 5 end",
+        );
+        assert_eq!(block.statements.len(), 2);
+        assert_eq!(block.lines, vec![1, 3]);
+        let Statement::Do(taken) = &block.statements[0] else {
+            panic!("expected the taken branch as a block");
+        };
+        assert_eq!(block.block(*taken).statements.len(), 1);
+        let Statement::If {
+            elseif_parts,
+            else_block,
+            ..
+        } = &block.statements[1]
+        else {
+            panic!("expected the if to stay");
+        };
+        assert!(elseif_parts.is_empty() && else_block.is_none());
+    }
+
+    #[test]
+    fn test_folds_loop_bounds() {
+        let block = optimized("for i = 1, 2 ^ 4, 10 - 8 do end");
+        let Statement::ForNumeric { end, step, .. } = &block.statements[0] else {
+            panic!("expected a numeric for");
+        };
+        assert_eq!(end, &Expression::Number(Numeral::Float(16.0)));
+        assert_eq!(step, &Some(Expression::Number(Numeral::Float(2.0))));
+    }
+
+    #[test]
+    fn test_pre_resolves_builtins_unless_shadowed() {
+        let block = optimized(
+            "print(type)\n\
+             local function f(print) return print, string end\n\
+             do local type = 1 end\n\
+             return type",
+        );
+        assert_eq!(
+            block.statements[0],
+            Statement::FunctionCall(Expression::FunctionCall {
+                function: Box::new(Expression::Global("print".into())),
+                args: vec![Expression::Global("type".into())],
+            })
+        );
+        let Statement::LocalFunction { body, .. } = &block.statements[1] else {
+            panic!("expected a local function");
+        };
+        assert_eq!(
+            block
+                .block(block.function(*body).block)
+                .return_statement
+                .as_ref()
+                .unwrap()
+                .expression_list,
+            vec![
+                Expression::Identifier("print".into()),
+                Expression::Global("string".into()),
+            ]
+        );
+        // The local in the `do` block has gone out of scope
+        assert_eq!(
+            block.return_statement.clone().unwrap().expression_list,
+            vec![Expression::Global("type".into())]
+        );
+    }
+
+    #[test]
+    fn test_optimized_chunks_behave_the_same() {
+        let code = "local t = {}\n\
+                    for i = 1, 2 + 1 do t[#t + 1] = i * (10 / 5) end\n\
+                    if 1 > 2 then t = nil end\n\
+                    local function print(s) return s .. '!' end\n\
+                    return print(tostring(t[3])), math.floor(7 / 2), 0.1 + 0.2";
+        let block = parse_source(code).unwrap();
+        let run = |block: &Chunk| {
+            let result = execute_chunk(block, &mut LuaInterpreter::new()).unwrap();
+            format!("{:?}", result)
+        };
+        assert_eq!(run(&optimize(&block, &builtins())), run(&block));
+    }
+
+    #[test]
+    fn test_deep_expression_chains_are_copied_safely() {
+        // Left associative, so the tree is as deep as the chain is long
+        let code = format!("local y = 1\nreturn {}", vec!["y"; 20_000].join(" + "));
+        let chunk = optimized(&code);
+        assert!(matches!(
+            chunk.return_statement.as_ref().unwrap().expression_list[0],
+            Expression::BinaryOp { .. }
+        ));
+    }
+}
@@ -2,12 +2,17 @@
 //! Converts tokens into an AST of nested S-expressions
 
 use crate::ast::{Arena, NodeId, SExpr};
-use crate::tokenizer::{tokenize_string, Token, TokenType};
+use crate::scheme_number;
+use crate::tokenizer::{Token, TokenType, Tokenizer};
 use std::fmt;
-
-pub struct Parser {
-    tokens: Vec<Token>,
-    pos: usize,
+use std::iter::Peekable;
+
+/// Parses tokens as they are pulled from any token iterator, such as a
+/// `Tokenizer` or a `Vec<Token>`
+pub struct Parser<I: Iterator<Item = Token>> {
+    tokens: Peekable<I>,
+    /// Byte offset just past the last token consumed
+    end: usize,
     arena: Arena,
 }
 
@@ -23,70 +28,36 @@ impl fmt::Display for ParseError {
     }
 }
 
-impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
+impl<I: Iterator<Item = Token>> Parser<I> {
+    pub fn new(tokens: impl IntoIterator<Item = Token, IntoIter = I>) -> Self {
         Parser {
-            tokens,
-            pos: 0,
+            tokens: tokens.into_iter().peekable(),
+            end: 0,
             arena: Arena::new(),
         }
     }
 
-    fn peek(&self) -> Option<&Token> {
-        self.tokens.get(self.pos)
+    fn peek(&mut self) -> Option<&Token> {
+        self.tokens.peek()
     }
 
     fn consume(&mut self) -> Option<Token> {
-        if self.pos < self.tokens.len() {
-            let token = self.tokens[self.pos].clone();
-            self.pos += 1;
-            Some(token)
-        } else {
-            None
-        }
+        let token = self.tokens.next()?;
+        self.end = token.end;
+        Some(token)
     }
 
-    fn current_line(&self) -> usize {
+    fn current_line(&mut self) -> usize {
         self.peek().map(|t| t.line).unwrap_or(0)
     }
 
-    fn error(&self, message: &str) -> ParseError {
+    fn error(&mut self, message: &str) -> ParseError {
         ParseError {
             message: message.to_string(),
             line: self.current_line(),
         }
     }
 
-    fn parse_string(&mut self) -> Result<NodeId, ParseError> {
-        // Opening quote already consumed
-        // todo: this is not good string parsing because white space is thrown
-        // this is because the tokenizer should have token string
-        let mut content = String::new();
-        let mut first = true;
-
-        loop {
-            match self.peek() {
-                Some(Token {
-                    token_type: TokenType::DQuote,
-                    ..
-                }) => {
-                    self.consume();
-                    let expr = SExpr::String(content);
-                    return Ok(self.arena.alloc(expr));
-                }
-                Some(token) => {
-                    if !first {
-                        content.push(' ');
-                    }
-                    content.push_str(&token.literal);
-                    self.consume();
-                    first = false;
-                }
-                None => return Err(self.error("Unterminated string")),
-            }
-        }
-    }
-
     fn parse_list(&mut self) -> Result<NodeId, ParseError> {
         // Opening paren already consumed
         let mut items = Vec::new();
@@ -111,17 +82,26 @@ impl Parser {
                     }
                     self.consume();
                     let cdr_id = self.parse_expr()?;
-                    items.push(cdr_id);
 
-                    // Convert (a . b) to (a . b) by creating improper list
                     match self.peek() {
                         Some(Token {
                             token_type: TokenType::RParen,
                             ..
                         }) => {
                             self.consume();
-                            // Build improper list
-                            let expr = SExpr::List(items);
+                            // (a . (b c)) is (a b c) and (a . (b . c)) is (a b . c)
+                            let expr = match self.arena.get(cdr_id) {
+                                Some(SExpr::List(rest)) => {
+                                    items.extend(rest);
+                                    SExpr::List(items)
+                                }
+                                Some(SExpr::DottedList(rest, tail)) => {
+                                    let tail = *tail;
+                                    items.extend(rest);
+                                    SExpr::DottedList(items, tail)
+                                }
+                                _ => SExpr::DottedList(items, cdr_id),
+                            };
                             return Ok(self.arena.alloc(expr));
                         }
                         _ => return Err(self.error("Expected ) after dot notation")),
@@ -165,27 +145,36 @@ impl Parser {
                 let char_part = &s[2..];
                 let c = match char_part {
                     "space" => ' ',
-                    "newline" => '\n',
+                    "newline" | "linefeed" => '\n',
                     "tab" => '\t',
                     "return" => '\r',
-                    s if s.len() == 1 => s.chars().next().unwrap(),
+                    "null" | "nul" => '\0',
+                    "alarm" => '\x07',
+                    "backspace" => '\x08',
+                    "delete" => '\x7f',
+                    "escape" => '\x1b',
+                    s if s.chars().count() == 1 => s.chars().next().unwrap(),
+                    // `#\x41` names a character by its hex code point
+                    s if s.len() > 1 && s.starts_with('x') => u32::from_str_radix(&s[1..], 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| self.error(&format!("Unknown character literal: {}", s)))?,
                     _ => return Err(self.error(&format!("Unknown character literal: {}", s))),
                 };
                 SExpr::Char(c)
             }
-            _ => return Err(self.error(&format!("Unknown sharp constant: {}", literal))),
+            s => match scheme_number::parse_prefixed(s, 10) {
+                Some(number) => number,
+                None => return Err(self.error(&format!("Unknown sharp constant: {}", literal))),
+            },
         };
         Ok(self.arena.alloc(expr))
     }
 
     fn parse_atom(&mut self, literal: &str) -> Result<NodeId, ParseError> {
-        // Try to parse as number
-        let expr = if let Ok(n) = literal.parse::<f64>() {
-            SExpr::Number(n)
-        } else {
-            // Otherwise it's an atom
-            SExpr::Atom(literal.to_string())
-        };
+        // Try to parse as number, otherwise it's an atom
+        let expr = scheme_number::parse_literal(literal)
+            .unwrap_or_else(|| SExpr::Atom(literal.to_string()));
         Ok(self.arena.alloc(expr))
     }
 
@@ -196,10 +185,20 @@ impl Parser {
                 ..
             }) => self.parse_list(),
 
+            Some(Token {
+                token_type: TokenType::String,
+                literal,
+                ..
+            }) => Ok(self.arena.alloc(SExpr::String(literal))),
+
             Some(Token {
                 token_type: TokenType::DQuote,
+                line,
                 ..
-            }) => self.parse_string(),
+            }) => Err(ParseError {
+                message: "Unterminated string".to_string(),
+                line,
+            }),
 
             Some(Token {
                 token_type: TokenType::Quote,
@@ -281,26 +280,53 @@ impl Parser {
 
         Ok((self.arena, node_ids))
     }
+
+    /// Parse only the first datum, returning it with the byte offset just
+    /// past its last token, or `None` when the input holds no datum
+    pub fn parse_one(mut self) -> Result<Option<(Arena, NodeId, usize)>, ParseError> {
+        match self.peek() {
+            Some(Token {
+                token_type: TokenType::Eof,
+                ..
+            })
+            | None => Ok(None),
+            _ => {
+                let node_id = self.parse_expr()?;
+                Ok(Some((self.arena, node_id, self.end)))
+            }
+        }
+    }
 }
 
 pub fn parse(input: &str) -> Result<(Arena, Vec<NodeId>), ParseError> {
-    let tokens = tokenize_string(input);
-    let parser = Parser::new(tokens);
-    parser.parse()
+    Parser::new(Tokenizer::new(input)).parse()
+}
+
+/// Parse the first datum of `input`, as used by the `read` builtin; only
+/// the tokens of that datum are lexed
+pub fn parse_datum(input: &str) -> Result<Option<(Arena, NodeId, usize)>, ParseError> {
+    Parser::new(Tokenizer::new(input)).parse_one()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parser_accepts_collected_tokens() {
+        let tokens = crate::tokenizer::tokenize_string("(a b) c");
+        let (_, node_ids) = Parser::new(tokens).parse().unwrap();
+        assert_eq!(node_ids.len(), 2);
+    }
+
     #[test]
     fn test_parse_simple_list() {
         let (arena, node_ids) = parse("(+ 1 2)").unwrap();
         assert_eq!(node_ids.len(), 1);
         if let Some(SExpr::List(ids)) = arena.get(node_ids[0]) {
             assert_eq!(ids.len(), 3);
-            assert_eq!(arena.get(ids[1]), Some(&SExpr::Number(1.0)));
-            assert_eq!(arena.get(ids[2]), Some(&SExpr::Number(2.0)));
+            assert_eq!(arena.get(ids[1]), Some(&SExpr::Integer(1)));
+            assert_eq!(arena.get(ids[2]), Some(&SExpr::Integer(2)));
         } else {
             panic!("Expected list");
         }
@@ -348,6 +374,22 @@ mod tests {
         assert_eq!(node_ids.len(), 1);
     }
 
+    #[test]
+    fn test_parse_dotted_list() {
+        let (arena, node_ids) = parse("(a b . c)").unwrap();
+        let Some(SExpr::DottedList(items, tail)) = arena.get(node_ids[0]) else {
+            panic!("expected a dotted list");
+        };
+        assert_eq!(items.len(), 2);
+        assert_eq!(arena.get(*tail), Some(&SExpr::Atom("c".to_string())));
+
+        // A list after the dot is spliced into the items
+        let (arena, node_ids) = parse("(a . (b . (c)))").unwrap();
+        assert!(matches!(arena.get(node_ids[0]), Some(SExpr::List(items)) if items.len() == 3));
+        assert!(parse("(. a)").is_err());
+        assert!(parse("(a . b c)").is_err());
+    }
+
     #[test]
     fn test_parse_multiple_exprs() {
         let (_arena, node_ids) = parse("42 hello (+ 1 2)").unwrap();
@@ -385,4 +427,12 @@ mod tests {
         let (_arena, node_ids) = parse(input).unwrap();
         assert_eq!(node_ids.len(), 2);
     }
+
+    #[test]
+    fn test_parse_datum_reports_end_offset() {
+        let (arena, node_id, end) = parse_datum("  (a b) rest").unwrap().unwrap();
+        assert!(matches!(arena.get(node_id), Some(SExpr::List(ids)) if ids.len() == 2));
+        assert_eq!(end, 7);
+        assert!(parse_datum("   ").unwrap().is_none());
+    }
 }
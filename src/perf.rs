@@ -0,0 +1,54 @@
+/// Evaluator counters for measuring the tree-walker's hot paths
+///
+/// Every `Executor` keeps a `PerfCounters` and bumps it as it runs, so a
+/// benchmark or test can check how much work a script did, not just how
+/// long it took. Counting is a handful of integer increments per node and
+/// is always on.
+use std::fmt;
+
+/// Work done by an `Executor` since it was created or last reset
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PerfCounters {
+    /// Statements executed, not counting a block's final `return`
+    pub statements: u64,
+    /// Function calls of any kind: Lua functions and builtins
+    pub calls: u64,
+    /// Tables created by table constructors
+    pub tables_created: u64,
+    /// Closures created by function expressions and declarations
+    pub closures_created: u64,
+    /// Indexing reads, one per table visited along an `__index` chain
+    pub table_reads: u64,
+    /// Indexing writes from assignments
+    pub table_writes: u64,
+}
+
+impl PerfCounters {
+    /// Values allocated by the evaluator: tables plus closures
+    pub fn allocations(&self) -> u64 {
+        self.tables_created + self.closures_created
+    }
+
+    /// Table operations of either kind
+    pub fn table_ops(&self) -> u64 {
+        self.table_reads + self.table_writes
+    }
+}
+
+impl fmt::Display for PerfCounters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "statements: {}, calls: {}, allocations: {} ({} tables, {} closures), \
+             table ops: {} ({} reads, {} writes)",
+            self.statements,
+            self.calls,
+            self.allocations(),
+            self.tables_created,
+            self.closures_created,
+            self.table_ops(),
+            self.table_reads,
+            self.table_writes
+        )
+    }
+}
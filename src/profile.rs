@@ -0,0 +1,206 @@
+/// Time and call counts per function and per statement kind
+///
+/// Profiling is opt-in: `Executor::enable_profiling` starts it and
+/// `Executor::profile` returns what has been recorded. Each call and each
+/// statement then reads the clock twice, which is far more than the
+/// always-on `PerfCounters` cost, so it stays off unless asked for.
+///
+/// Lua functions are counted per definition, so every closure made from
+/// the same `function ... end` adds to one entry; builtins are counted per
+/// function value. Statement times include the statements nested inside,
+/// so a `while` loop's time covers its whole body.
+use crate::lua_value::{LuaFunction, LuaValue};
+use crate::traceback::CallName;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Time spent in one function
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionProfile {
+    /// Name the first recorded call site used, `?` when it used none
+    pub name: String,
+    /// Line of the definition; `None` for builtins and unknown lines
+    pub line: Option<usize>,
+    pub builtin: bool,
+    pub calls: u64,
+    /// Time in the function, including the functions it called; recursive
+    /// calls are counted once, from the outermost one
+    pub total: Duration,
+    /// Time in the function's own code
+    pub own: Duration,
+}
+
+impl FunctionProfile {
+    /// Name and where the function came from, as the report shows it
+    pub fn label(&self) -> String {
+        match (self.builtin, self.line) {
+            (true, _) => format!("{} [builtin]", self.name),
+            (false, Some(line)) => format!("{} (line {})", self.name, line),
+            (false, None) => self.name.clone(),
+        }
+    }
+}
+
+/// Time spent in one kind of statement
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatementProfile {
+    /// As `Statement::kind` names it
+    pub kind: &'static str,
+    pub count: u64,
+    /// Includes nested statements
+    pub total: Duration,
+}
+
+/// Everything recorded while profiling
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// Sorted by own time, longest first
+    pub functions: Vec<FunctionProfile>,
+    /// Sorted by total time, longest first
+    pub statements: Vec<StatementProfile>,
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>10} {:>10} {:>8}  function",
+            "own ms", "total ms", "calls"
+        )?;
+        for function in &self.functions {
+            writeln!(
+                f,
+                "{:>10.3} {:>10.3} {:>8}  {}",
+                millis(function.own),
+                millis(function.total),
+                function.calls,
+                function.label()
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(f, "{:>10} {:>8}  statement", "total ms", "count")?;
+        for statement in &self.statements {
+            writeln!(
+                f,
+                "{:>10.3} {:>8}  {}",
+                millis(statement.total),
+                statement.count,
+                statement.kind
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// A function call in progress
+struct Active {
+    key: (usize, usize),
+    started: Instant,
+    /// Time spent in the functions this call made
+    callees: Duration,
+}
+
+/// Records calls and statements for an `Executor`
+#[derive(Default)]
+pub(crate) struct Profiler {
+    functions: HashMap<(usize, usize), (FunctionProfile, usize)>,
+    statements: HashMap<&'static str, StatementProfile>,
+    stack: Vec<Active>,
+}
+
+impl Profiler {
+    /// A call to `function` is starting
+    pub(crate) fn enter(&mut self, function: &LuaValue, name: Option<&CallName>) {
+        let LuaValue::Function(f) = function else {
+            return;
+        };
+        let (key, line, builtin) = match f.as_ref() {
+            LuaFunction::User { body, .. } => {
+                (body.key(), body.lines.map(|(first, _)| first), false)
+            }
+            // No arena shares a builtin's address
+            _ => ((Rc::as_ptr(f) as usize, 0), None, true),
+        };
+        let (entry, depth) = self.functions.entry(key).or_insert_with(|| {
+            let profile = FunctionProfile {
+                name: "?".to_string(),
+                line,
+                builtin,
+                calls: 0,
+                total: Duration::ZERO,
+                own: Duration::ZERO,
+            };
+            (profile, 0)
+        });
+        if entry.name == "?" {
+            if let Some(name) = name {
+                entry.name = name.name().to_string();
+            }
+        }
+        entry.calls += 1;
+        *depth += 1;
+        self.stack.push(Active {
+            key,
+            started: Instant::now(),
+            callees: Duration::ZERO,
+        });
+    }
+
+    /// The innermost call has returned or failed
+    pub(crate) fn exit(&mut self) {
+        let Some(active) = self.stack.pop() else {
+            return;
+        };
+        let elapsed = active.started.elapsed();
+        if let Some((entry, depth)) = self.functions.get_mut(&active.key) {
+            entry.own += elapsed.saturating_sub(active.callees);
+            *depth -= 1;
+            if *depth == 0 {
+                entry.total += elapsed;
+            }
+        }
+        if let Some(caller) = self.stack.last_mut() {
+            caller.callees += elapsed;
+        }
+    }
+
+    /// A statement of `kind` took `elapsed`
+    pub(crate) fn statement(&mut self, kind: &'static str, elapsed: Duration) {
+        let entry = self
+            .statements
+            .entry(kind)
+            .or_insert_with(|| StatementProfile {
+                kind,
+                count: 0,
+                total: Duration::ZERO,
+            });
+        entry.count += 1;
+        entry.total += elapsed;
+    }
+
+    pub(crate) fn profile(&self) -> Profile {
+        let mut functions: Vec<FunctionProfile> = self
+            .functions
+            .values()
+            .map(|(profile, _)| profile.clone())
+            .collect();
+        functions.sort_by(|a, b| {
+            b.own
+                .cmp(&a.own)
+                .then(b.calls.cmp(&a.calls))
+                .then_with(|| a.label().cmp(&b.label()))
+        });
+        let mut statements: Vec<StatementProfile> = self.statements.values().cloned().collect();
+        statements.sort_by(|a, b| b.total.cmp(&a.total).then(a.kind.cmp(b.kind)));
+        Profile {
+            functions,
+            statements,
+        }
+    }
+}
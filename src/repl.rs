@@ -0,0 +1,317 @@
+//! Line editing for the interactive REPLs
+//!
+//! Both languages share one history file, `~/.muscm_history`. Tab completes
+//! the names bound when the prompt was shown: Scheme's environment, or Lua's
+//! globals together with the fields of global tables such as `string.format`.
+//! While an entry has unclosed brackets, the closing ones are hinted after
+//! the cursor, and the bracket matching the one at the cursor is highlighted.
+
+use crate::cli::Lang;
+use crate::lua_interpreter::LuaInterpreter;
+use crate::lua_value::LuaValue;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::{CmdKind, Highlighter, MatchingBracketHighlighter};
+use rustyline::hint::Hinter;
+use rustyline::history::FileHistory;
+use rustyline::validate::Validator;
+use rustyline::{CompletionType, Config, Context, Editor};
+use std::borrow::Cow;
+use std::env;
+use std::iter::Peekable;
+use std::path::PathBuf;
+use std::str::Chars;
+
+/// Line editor shared by the Lua and Scheme REPLs
+pub struct Repl {
+    editor: Editor<Helper, FileHistory>,
+    /// Where entries are appended; `None` without a home directory
+    history: Option<PathBuf>,
+}
+
+impl Repl {
+    /// An editor for `lang` with the history loaded, when there is one
+    pub fn new(lang: Lang) -> Result<Self, String> {
+        let config = Config::builder()
+            .max_history_size(1000)
+            .map_err(|e| format!("repl: {}", e))?
+            .auto_add_history(false)
+            .completion_type(CompletionType::List)
+            .build();
+        let mut editor = Editor::with_config(config).map_err(|e| format!("repl: {}", e))?;
+        editor.set_helper(Some(Helper::new(lang)));
+
+        let history = history_path();
+        if let Some(path) = &history {
+            // The file is missing until the first entry is saved
+            let _ = editor.load_history(path);
+        }
+        Ok(Repl { editor, history })
+    }
+
+    /// Read one entry, continuing over lines until `complete` accepts it,
+    /// with `names` offered for completion
+    ///
+    /// Returns `None` at end of input. A blank line submits whatever has been
+    /// typed so its error can be reported, and Ctrl-C discards it.
+    pub fn read_entry(
+        &mut self,
+        prompt: &str,
+        names: Vec<String>,
+        complete: impl Fn(&str) -> bool,
+    ) -> Option<String> {
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.names = names;
+        }
+        let mut entry = String::new();
+        loop {
+            if let Some(helper) = self.editor.helper_mut() {
+                helper.pending.clone_from(&entry);
+            }
+            let line = match self
+                .editor
+                .readline(if entry.is_empty() { prompt } else { ".. " })
+            {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => {
+                    entry.clear();
+                    continue;
+                }
+                Err(_) => return (!entry.is_empty()).then(|| self.remember(entry)),
+            };
+            if line.trim().is_empty() && !entry.is_empty() {
+                return Some(self.remember(entry));
+            }
+            entry.push_str(&line);
+            entry.push('\n');
+            if complete(&entry) {
+                return Some(self.remember(entry));
+            }
+        }
+    }
+
+    /// Add an entry to the history and append it to the history file
+    fn remember(&mut self, entry: String) -> String {
+        let line = entry.trim_end();
+        if !line.trim().is_empty() && self.editor.add_history_entry(line).unwrap_or(false) {
+            if let Some(path) = &self.history {
+                let _ = self.editor.append_history(path);
+            }
+        }
+        entry
+    }
+}
+
+/// `~/.muscm_history`
+fn history_path() -> Option<PathBuf> {
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?;
+    Some(PathBuf::from(home).join(".muscm_history"))
+}
+
+/// Names a Lua REPL can complete: the globals, and `table.field` for the
+/// string keys of global tables
+pub fn lua_names(interpreter: &LuaInterpreter) -> Vec<String> {
+    let mut names = Vec::new();
+    for (name, value) in &interpreter.globals.borrow().data {
+        let LuaValue::String(name) = name else {
+            continue;
+        };
+        names.push(name.to_string());
+        if let LuaValue::Table(table) = value {
+            for key in table.borrow().data.keys() {
+                if let LuaValue::String(key) = key {
+                    names.push(format!("{}.{}", name, key));
+                }
+            }
+        }
+    }
+    names
+}
+
+/// Completion, hints and highlighting for one language
+struct Helper {
+    lang: Lang,
+    /// Names bound when the prompt was shown
+    names: Vec<String>,
+    /// Lines of the entry typed before the current one
+    pending: String,
+    brackets: MatchingBracketHighlighter,
+}
+
+impl Helper {
+    fn new(lang: Lang) -> Self {
+        Helper {
+            lang,
+            names: Vec::new(),
+            pending: String::new(),
+            brackets: MatchingBracketHighlighter::new(),
+        }
+    }
+}
+
+impl rustyline::Helper for Helper {}
+
+impl Completer for Helper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = word_start(&line[..pos], self.lang);
+        Ok((start, candidates(&self.names, &line[start..pos])))
+    }
+}
+
+impl Hinter for Helper {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<String> {
+        if pos < line.len() {
+            return None;
+        }
+        let closing = closers(&format!("{}{}", self.pending, line), self.lang);
+        (!closing.is_empty()).then_some(closing)
+    }
+}
+
+impl Highlighter for Helper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        self.brackets.highlight(line, pos)
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("\x1b[2m{}\x1b[0m", hint))
+    }
+
+    fn highlight_char(&self, line: &str, pos: usize, kind: CmdKind) -> bool {
+        self.brackets.highlight_char(line, pos, kind)
+    }
+}
+
+impl Validator for Helper {}
+
+/// Whether `c` can be part of a name being completed
+fn is_name_char(c: char, lang: Lang) -> bool {
+    match lang {
+        Lang::Lua => c.is_alphanumeric() || c == '_' || c == '.',
+        Lang::Scheme => !c.is_whitespace() && !"()[]'`,\";".contains(c),
+    }
+}
+
+/// Byte offset where the name ending at the end of `before` starts
+fn word_start(before: &str, lang: Lang) -> usize {
+    before
+        .char_indices()
+        .rev()
+        .find(|(_, c)| !is_name_char(*c, lang))
+        .map_or(0, |(i, c)| i + c.len_utf8())
+}
+
+/// The names starting with `prefix`, sorted; nothing for an empty prefix
+fn candidates(names: &[String], prefix: &str) -> Vec<String> {
+    if prefix.is_empty() {
+        return Vec::new();
+    }
+    let mut found: Vec<String> = names
+        .iter()
+        .filter(|name| name.starts_with(prefix))
+        .cloned()
+        .collect();
+    found.sort();
+    found.dedup();
+    found
+}
+
+/// The text that closes the brackets `entry` leaves open, ignoring
+/// brackets in strings, comments and Scheme character literals
+fn closers(entry: &str, lang: Lang) -> String {
+    let mut open = Vec::new();
+    let mut chars = entry.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => open.push(')'),
+            '[' => open.push(']'),
+            '{' => open.push('}'),
+            ')' | ']' | '}' if open.last() == Some(&c) => {
+                open.pop();
+            }
+            '"' => skip_string(&mut chars, '"'),
+            '\'' if lang == Lang::Lua => skip_string(&mut chars, '\''),
+            ';' if lang == Lang::Scheme => skip_line(&mut chars),
+            '-' if lang == Lang::Lua && chars.peek() == Some(&'-') => skip_line(&mut chars),
+            '#' if lang == Lang::Scheme && chars.peek() == Some(&'\\') => {
+                chars.next();
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+    open.iter().rev().collect()
+}
+
+/// Skip past the closing `quote` of a string, honouring backslash escapes
+fn skip_string(chars: &mut Peekable<Chars<'_>>, quote: char) {
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if c == quote {
+            return;
+        }
+    }
+}
+
+fn skip_line(chars: &mut Peekable<Chars<'_>>) {
+    for c in chars.by_ref() {
+        if c == '\n' {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Environment;
+
+    #[test]
+    fn test_closers_skip_strings_and_comments() {
+        assert_eq!(closers("(define (f x", Lang::Scheme), "))");
+        assert_eq!(closers("(f \")\" #\\) ; )\n", Lang::Scheme), ")");
+        assert_eq!(closers("#(1 [2", Lang::Scheme), "])");
+        assert_eq!(closers("(+ 1 2)", Lang::Scheme), "");
+        assert_eq!(closers("print(f({1, ')'}", Lang::Lua), "))");
+        assert_eq!(closers("t[g( -- (\n", Lang::Lua), ")]");
+    }
+
+    #[test]
+    fn test_completion_uses_the_name_at_the_cursor() {
+        let names: Vec<String> = ["string-length", "string-append", "substring"]
+            .map(String::from)
+            .to_vec();
+        let line = "(display (string-a";
+        let start = word_start(line, Lang::Scheme);
+        assert_eq!(&line[start..], "string-a");
+        assert_eq!(candidates(&names, &line[start..]), ["string-append"]);
+        assert!(candidates(&names, "").is_empty());
+
+        let line = "print(string.fo";
+        assert_eq!(&line[word_start(line, Lang::Lua)..], "string.fo");
+    }
+
+    #[test]
+    fn test_names_come_from_the_interpreters() {
+        let interpreter = LuaInterpreter::new();
+        let names = lua_names(&interpreter);
+        assert!(names.iter().any(|n| n == "print"));
+        assert_eq!(candidates(&names, "string.for"), ["string.format"]);
+
+        let mut env = Environment::new();
+        env.define("my-var".to_string(), crate::interpreter::SVal::Integer(1));
+        let names = env.child().names();
+        assert!(names.iter().any(|n| n == "my-var"));
+        assert!(names.iter().any(|n| n == "car"));
+    }
+}
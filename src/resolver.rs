@@ -0,0 +1,593 @@
+/// Resolution pass for the tree-walking executor
+///
+/// Rewrites every function body so its locals live in numbered slots of a
+/// per-call frame instead of a stack of name-keyed hash maps. References
+/// become `Expression::Local` (a slot of the running function),
+/// `Expression::Upvalue` (a cell captured from an enclosing function) or
+/// `Expression::Global` (no enclosing binding at all), and the body's
+/// `FunctionBody::layout` records how many slots it needs and where each
+/// upvalue comes from.
+///
+/// Chunk-level code is left as it is. Its locals keep living in the
+/// interpreter's scopes, where a REPL can still see them between lines, and
+/// names a function borrows from that level stay `Expression::Identifier` so
+/// they are captured by name when the closure is created.
+///
+/// The resolved chunk gets a copy of the arena with every block and body
+/// rewritten under its old id, so ids mean the same in both.
+use crate::intern::intern;
+use crate::lua_parser::{
+    Block, BlockId, Capture, Chunk, Expression, Field, FieldKey, FrameLayout, FunctionId, LuaArena,
+    ReturnStatement, Statement,
+};
+use crate::stack::with_headroom;
+use std::rc::Rc;
+
+/// Resolve all function bodies in a chunk
+pub fn resolve(chunk: &Chunk) -> Chunk {
+    resolve_within(chunk, Vec::new())
+}
+
+/// Resolve a chunk that runs where the chunk-level locals `outer` are
+/// already in scope, as a REPL line runs after the lines before it, so its
+/// functions capture those locals instead of reading globals of the same
+/// names
+pub fn resolve_within(chunk: &Chunk, outer: Vec<String>) -> Chunk {
+    let mut resolver = Resolver {
+        from: &chunk.arena,
+        arena: chunk.arena.as_ref().clone(),
+        chunk_scopes: vec![outer],
+        functions: Vec::new(),
+    };
+    let block = resolver.block_body(chunk.root);
+    *resolver.arena.block_mut(chunk.root) = block;
+    Chunk::new(resolver.arena, chunk.root)
+}
+
+struct Resolver<'a> {
+    /// The arena being resolved
+    from: &'a LuaArena,
+    /// Its copy, where resolved blocks and bodies replace the originals
+    arena: LuaArena,
+    /// Locals declared at chunk level, by lexical scope
+    chunk_scopes: Vec<Vec<String>>,
+    /// Functions being resolved, innermost last
+    functions: Vec<FunctionScope>,
+}
+
+#[derive(Default)]
+struct FunctionScope {
+    /// Lexical scopes of `(name, slot)` bindings, innermost last
+    scopes: Vec<Vec<(String, usize)>>,
+    next_slot: usize,
+    slot_count: usize,
+    /// Name of the first local declared in each slot
+    slot_names: Vec<Rc<str>>,
+    /// Upvalues in index order, by the name they were resolved from
+    captures: Vec<(String, Capture)>,
+}
+
+impl FunctionScope {
+    fn local(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(n, _)| n == name)
+            .map(|(_, slot)| *slot)
+    }
+
+    fn declare(&mut self, name: &str) -> usize {
+        let slot = self.next_slot;
+        self.next_slot += 1;
+        self.slot_count = self.slot_count.max(self.next_slot);
+        if slot == self.slot_names.len() {
+            self.slot_names.push(intern(name));
+        }
+        self.scopes
+            .last_mut()
+            .expect("locals are declared inside a scope")
+            .push((name.to_string(), slot));
+        slot
+    }
+}
+
+impl Resolver<'_> {
+    fn in_function(&self) -> bool {
+        !self.functions.is_empty()
+    }
+
+    fn push_scope(&mut self) -> usize {
+        match self.functions.last_mut() {
+            Some(function) => {
+                function.scopes.push(Vec::new());
+                function.next_slot
+            }
+            None => {
+                self.chunk_scopes.push(Vec::new());
+                0
+            }
+        }
+    }
+
+    fn pop_scope(&mut self, saved_slot: usize) {
+        match self.functions.last_mut() {
+            Some(function) => {
+                function.scopes.pop();
+                function.next_slot = saved_slot;
+            }
+            None => {
+                self.chunk_scopes.pop();
+            }
+        }
+    }
+
+    /// Declare a local in the innermost function, returning its slot. At
+    /// chunk level the name is only recorded and `None` is returned.
+    fn declare(&mut self, name: &str) -> Option<usize> {
+        match self.functions.last_mut() {
+            Some(function) => Some(function.declare(name)),
+            None => {
+                self.chunk_scopes
+                    .last_mut()
+                    .expect("chunk always has a scope")
+                    .push(name.to_string());
+                None
+            }
+        }
+    }
+
+    /// Declare each of `names` in order, returning their slots at function
+    /// level. Every name is declared even at chunk level, where the first
+    /// `None` would otherwise stop a plain `collect`.
+    fn declare_all(&mut self, names: &[String]) -> Option<Vec<usize>> {
+        let slots: Vec<Option<usize>> = names.iter().map(|n| self.declare(n)).collect();
+        slots.into_iter().collect()
+    }
+
+    fn name(&mut self, name: &str) -> Expression {
+        let Some(function) = self.functions.last() else {
+            return Expression::Identifier(intern(name));
+        };
+        if let Some(slot) = function.local(name) {
+            return Expression::Local {
+                name: intern(name),
+                slot,
+            };
+        }
+        if let Some(index) = self.upvalue(self.functions.len() - 1, name) {
+            return Expression::Upvalue {
+                name: intern(name),
+                index,
+            };
+        }
+        if self
+            .chunk_scopes
+            .iter()
+            .any(|scope| scope.iter().any(|n| n == name))
+        {
+            Expression::Identifier(intern(name))
+        } else {
+            Expression::Global(intern(name))
+        }
+    }
+
+    /// Find or create the upvalue for `name` in function `level`, threading
+    /// it through every function between it and the defining one
+    fn upvalue(&mut self, level: usize, name: &str) -> Option<usize> {
+        if let Some(index) = self.functions[level]
+            .captures
+            .iter()
+            .position(|(n, _)| n == name)
+        {
+            return Some(index);
+        }
+        if level == 0 {
+            return None;
+        }
+        let capture = match self.functions[level - 1].local(name) {
+            Some(slot) => Capture::Slot(slot),
+            None => Capture::Upvalue(self.upvalue(level - 1, name)?),
+        };
+        let captures = &mut self.functions[level].captures;
+        captures.push((name.to_string(), capture));
+        Some(captures.len() - 1)
+    }
+
+    /// Resolve block `id` in a scope of its own
+    fn block(&mut self, id: BlockId) -> BlockId {
+        let saved = self.push_scope();
+        let block = self.block_body(id);
+        self.pop_scope(saved);
+        *self.arena.block_mut(id) = block;
+        id
+    }
+
+    fn block_body(&mut self, id: BlockId) -> Block {
+        let from = self.from;
+        let block = from.block(id);
+        let mut statements = Vec::with_capacity(block.statements.len());
+        let mut lines = Vec::with_capacity(block.lines.len());
+        for (i, statement) in block.statements.iter().enumerate() {
+            self.statement(statement, &mut statements);
+            // Statements the resolver splits off keep the original's line
+            if let Some(&line) = block.lines.get(i) {
+                lines.resize(statements.len(), line);
+            }
+        }
+        let return_statement = block.return_statement.as_ref().map(|ret| ReturnStatement {
+            expression_list: self.expressions(&ret.expression_list),
+        });
+        if let Some(&line) = block.lines.get(block.statements.len()) {
+            lines.push(line);
+        }
+        // Resolved blocks are only executed, so columns and comments are not
+        // carried over
+        Block {
+            statements,
+            return_statement,
+            lines,
+            columns: Vec::new(),
+            trivia: Vec::new(),
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement, out: &mut Vec<Statement>) {
+        with_headroom(|| self.statement_node(statement, out))
+    }
+
+    fn statement_node(&mut self, statement: &Statement, out: &mut Vec<Statement>) {
+        let resolved = match statement {
+            Statement::Empty
+            | Statement::Break
+            | Statement::Label(_)
+            | Statement::Goto(_)
+            | Statement::LocalSlots { .. }
+            | Statement::ForNumericSlot { .. }
+            | Statement::ForGenericSlots { .. } => statement.clone(),
+            Statement::Assignment { variables, values } => Statement::Assignment {
+                variables: self.expressions(variables),
+                values: self.expressions(values),
+            },
+            Statement::FunctionCall(call) => Statement::FunctionCall(self.expression(call)),
+            Statement::Do(body) => Statement::Do(self.block(*body)),
+            Statement::While { condition, body } => Statement::While {
+                condition: self.expression(condition),
+                body: self.block(*body),
+            },
+            Statement::Repeat { body, condition } => {
+                // The condition can see the body's locals
+                let saved = self.push_scope();
+                *self.arena.block_mut(*body) = self.block_body(*body);
+                let condition = self.expression(condition);
+                self.pop_scope(saved);
+                Statement::Repeat {
+                    body: *body,
+                    condition,
+                }
+            }
+            Statement::If {
+                condition,
+                then_block,
+                elseif_parts,
+                else_block,
+            } => Statement::If {
+                condition: self.expression(condition),
+                then_block: self.block(*then_block),
+                elseif_parts: elseif_parts
+                    .iter()
+                    .map(|(cond, block)| (self.expression(cond), self.block(*block)))
+                    .collect(),
+                else_block: else_block.map(|b| self.block(b)),
+            },
+            Statement::ForNumeric {
+                var,
+                start,
+                end,
+                step,
+                body,
+            } => {
+                let start = self.expression(start);
+                let end = self.expression(end);
+                let step = step.as_ref().map(|s| self.expression(s));
+                let saved = self.push_scope();
+                let slot = self.declare(var);
+                let body = self.block(*body);
+                self.pop_scope(saved);
+                match slot {
+                    Some(slot) => Statement::ForNumericSlot {
+                        slot,
+                        start,
+                        end,
+                        step,
+                        body,
+                    },
+                    None => Statement::ForNumeric {
+                        var: var.clone(),
+                        start,
+                        end,
+                        step,
+                        body,
+                    },
+                }
+            }
+            Statement::ForGeneric {
+                vars,
+                iterables,
+                body,
+            } => {
+                let iterables = self.expressions(iterables);
+                let saved = self.push_scope();
+                let slots = self.declare_all(vars);
+                let body = self.block(*body);
+                self.pop_scope(saved);
+                match slots {
+                    Some(slots) => Statement::ForGenericSlots {
+                        slots,
+                        iterables,
+                        body,
+                    },
+                    None => Statement::ForGeneric {
+                        vars: vars.clone(),
+                        iterables,
+                        body,
+                    },
+                }
+            }
+            Statement::FunctionDecl { name, body } if self.in_function() => {
+                // Inside a function, `function a.b:c()` is an assignment to a
+                // resolved target
+                let mut target = self.name(name.base());
+                for field in name.fields() {
+                    target = Expression::FieldAccess {
+                        object: Box::new(target),
+                        field: intern(field),
+                    };
+                }
+                Statement::Assignment {
+                    variables: vec![target],
+                    values: vec![Expression::FunctionDef(self.function(*body))],
+                }
+            }
+            Statement::FunctionDecl { name, body } => {
+                // At chunk level the executor binds a plain name in the
+                // current scope, like a local
+                if name.is_simple() {
+                    self.declare(name.base());
+                }
+                Statement::FunctionDecl {
+                    name: name.clone(),
+                    body: self.function(*body),
+                }
+            }
+            Statement::LocalFunction { name, body } => {
+                // Declared before the body so the function can call itself
+                match self.declare(name) {
+                    Some(slot) => {
+                        out.push(Statement::LocalSlots {
+                            slots: vec![slot],
+                            values: None,
+                        });
+                        Statement::Assignment {
+                            variables: vec![Expression::Local {
+                                name: intern(name),
+                                slot,
+                            }],
+                            values: vec![Expression::FunctionDef(self.function(*body))],
+                        }
+                    }
+                    None => Statement::LocalFunction {
+                        name: name.clone(),
+                        body: self.function(*body),
+                    },
+                }
+            }
+            Statement::LocalVars { names, values } => {
+                // Values are resolved before the names come into scope
+                let values = values.as_ref().map(|v| self.expressions(v));
+                let slots = self.declare_all(names);
+                match slots {
+                    Some(slots) => Statement::LocalSlots { slots, values },
+                    None => Statement::LocalVars {
+                        names: names.clone(),
+                        values,
+                    },
+                }
+            }
+        };
+        out.push(resolved);
+    }
+
+    /// Resolve function body `id`, filling in its layout
+    fn function(&mut self, id: FunctionId) -> FunctionId {
+        let from = self.from;
+        let body = from.function(id);
+        let mut function = FunctionScope::default();
+        function.scopes.push(Vec::new());
+        for param in &body.params {
+            function.declare(param);
+        }
+        self.functions.push(function);
+        *self.arena.block_mut(body.block) = self.block_body(body.block);
+        let function = self.functions.pop().expect("pushed above");
+
+        self.arena.function_mut(id).layout = Some(FrameLayout {
+            slot_count: function.slot_count,
+            captures: function.captures.iter().map(|(_, c)| *c).collect(),
+            slot_names: function.slot_names,
+            capture_names: function.captures.iter().map(|(n, _)| intern(n)).collect(),
+        });
+        id
+    }
+
+    fn expressions(&mut self, exprs: &[Expression]) -> Vec<Expression> {
+        exprs.iter().map(|e| self.expression(e)).collect()
+    }
+
+    fn expression(&mut self, expr: &Expression) -> Expression {
+        with_headroom(|| self.expression_node(expr))
+    }
+
+    fn expression_node(&mut self, expr: &Expression) -> Expression {
+        match expr {
+            Expression::Nil
+            | Expression::Boolean(_)
+            | Expression::Number(_)
+            | Expression::String(_)
+            | Expression::Varargs
+            | Expression::Local { .. }
+            | Expression::Upvalue { .. }
+            | Expression::Global(_) => expr.clone(),
+            Expression::Identifier(name) => self.name(name),
+            Expression::BinaryOp { left, op, right } => Expression::BinaryOp {
+                left: Box::new(self.expression(left)),
+                op: op.clone(),
+                right: Box::new(self.expression(right)),
+            },
+            Expression::UnaryOp { op, operand } => Expression::UnaryOp {
+                op: op.clone(),
+                operand: Box::new(self.expression(operand)),
+            },
+            Expression::TableIndexing { object, index } => Expression::TableIndexing {
+                object: Box::new(self.expression(object)),
+                index: Box::new(self.expression(index)),
+            },
+            Expression::FieldAccess { object, field } => Expression::FieldAccess {
+                object: Box::new(self.expression(object)),
+                field: field.clone(),
+            },
+            Expression::FunctionCall { function, args } => Expression::FunctionCall {
+                function: Box::new(self.expression(function)),
+                args: self.expressions(args),
+            },
+            Expression::Paren(inner) => Expression::Paren(Box::new(self.expression(inner))),
+            Expression::MethodCall {
+                object,
+                method,
+                args,
+            } => Expression::MethodCall {
+                object: Box::new(self.expression(object)),
+                method: method.clone(),
+                args: self.expressions(args),
+            },
+            Expression::TableConstructor { fields } => Expression::TableConstructor {
+                fields: fields
+                    .iter()
+                    .map(|field| Field {
+                        key: match &field.key {
+                            FieldKey::Bracket(key) => {
+                                FieldKey::Bracket(Box::new(self.expression(key)))
+                            }
+                            other => other.clone(),
+                        },
+                        value: self.expression(&field.value),
+                    })
+                    .collect(),
+            },
+            Expression::FunctionDef(body) => Expression::FunctionDef(self.function(*body)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua_parser::{parse, tokenize, FunctionBody, TokenSlice};
+
+    fn resolve_code(code: &str) -> Chunk {
+        let tokens = tokenize(code).unwrap();
+        let (_, chunk) = parse(TokenSlice::from(tokens.as_slice())).unwrap();
+        resolve(&chunk)
+    }
+
+    /// The function `block` returns first
+    fn function_body<'a>(chunk: &'a Chunk, block: &Block) -> &'a FunctionBody {
+        match &block.return_statement.as_ref().unwrap().expression_list[0] {
+            Expression::FunctionDef(body) => chunk.function(*body),
+            other => panic!("Expected function, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_params_and_locals_get_slots() {
+        let chunk = resolve_code("return function(a, b) local c = a + b return c, print end");
+        let body = function_body(&chunk, &chunk);
+        assert_eq!(body.layout.as_ref().unwrap().slot_count, 3);
+        assert_eq!(
+            chunk
+                .block(body.block)
+                .return_statement
+                .as_ref()
+                .unwrap()
+                .expression_list,
+            vec![
+                Expression::Local {
+                    name: "c".into(),
+                    slot: 2
+                },
+                Expression::Global("print".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sibling_scopes_reuse_slots() {
+        let chunk = resolve_code("return function() do local a end do local b end end");
+        let body = function_body(&chunk, &chunk);
+        assert_eq!(body.layout.as_ref().unwrap().slot_count, 1);
+    }
+
+    #[test]
+    fn test_upvalues_thread_through_functions() {
+        let chunk = resolve_code(
+            "return function() local x = 1 return function() return function() return x end end end",
+        );
+        let outer = function_body(&chunk, &chunk);
+        let middle = function_body(&chunk, chunk.block(outer.block));
+        let inner = function_body(&chunk, chunk.block(middle.block));
+        assert_eq!(
+            middle.layout.as_ref().unwrap().captures,
+            vec![Capture::Slot(0)]
+        );
+        assert_eq!(
+            inner.layout.as_ref().unwrap().captures,
+            vec![Capture::Upvalue(0)]
+        );
+    }
+
+    #[test]
+    fn test_outer_locals_stay_chunk_level() {
+        let tokens = tokenize("return function() return x, y end").unwrap();
+        let (_, chunk) = parse(TokenSlice::from(tokens.as_slice())).unwrap();
+        let chunk = resolve_within(&chunk, vec!["x".to_string()]);
+        let body = function_body(&chunk, &chunk);
+        assert_eq!(
+            chunk
+                .block(body.block)
+                .return_statement
+                .as_ref()
+                .unwrap()
+                .expression_list,
+            vec![
+                Expression::Identifier("x".into()),
+                Expression::Global("y".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_chunk_level_is_untouched() {
+        let chunk = resolve_code("local x = 1 y = x return function() return x end");
+        assert!(matches!(chunk.statements[0], Statement::LocalVars { .. }));
+        let body = function_body(&chunk, &chunk);
+        assert_eq!(
+            chunk
+                .block(body.block)
+                .return_statement
+                .as_ref()
+                .unwrap()
+                .expression_list,
+            vec![Expression::Identifier("x".into())]
+        );
+    }
+}
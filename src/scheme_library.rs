@@ -0,0 +1,209 @@
+//! Splitting Scheme programs into files: `load`, `define-library` and `import`
+//!
+//! `(load "file.scm")` evaluates a file in the caller's environment.
+//! `(define-library (name ...) decl...)` evaluates its `begin` bodies in a
+//! fresh top-level environment and records the values of the names listed
+//! in `export`. `(import (name ...))` copies those values into the importing
+//! environment, first loading the file that defines the library when it is
+//! not known yet. The file is found with the same `;`-separated templates
+//! as Lua's `package.path`, with the library name's parts joined by `/` in
+//! place of `?`. Each library is defined once per program; importing it
+//! again reuses the recorded exports. In an `Environment::sandbox`, both
+//! forms must be among the allowed names, and library bodies see only the
+//! sandbox's bindings.
+
+use crate::ast::{Arena, NodeId, SExpr};
+use crate::interpreter::{Environment, Interpreter, SVal};
+use crate::macro_expander::expand_program;
+use crate::module_loader::search_path;
+use crate::parser::parse;
+use std::collections::HashMap;
+
+/// Templates searched by `import` when nothing else is configured
+pub const DEFAULT_LIBRARY_PATH: &str = "./?.sld;./?.scm;./lib/?.sld;./lib/?.scm";
+
+/// Libraries known to a program, shared by all of its environments
+#[derive(Debug)]
+pub struct Libraries {
+    /// `;`-separated templates searched by `import`
+    pub path: String,
+    /// Exported bindings of every library defined so far, by name
+    loaded: HashMap<String, Vec<(String, SVal)>>,
+    /// Files and libraries being loaded, innermost last, to report cycles
+    loading: Vec<String>,
+}
+
+impl Libraries {
+    pub fn new() -> Self {
+        Libraries {
+            path: DEFAULT_LIBRARY_PATH.to_string(),
+            loaded: HashMap::new(),
+            loading: Vec::new(),
+        }
+    }
+
+    /// Whether a library with this name, e.g. "(utils strings)", is defined
+    pub fn is_loaded(&self, name: &str) -> bool {
+        self.loaded.contains_key(name)
+    }
+}
+
+impl Default for Libraries {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Evaluate every form of a file in `env`
+pub fn load(path: &str, env: &mut Environment) -> Result<SVal, String> {
+    let key = format!("file {}", path);
+    if env.libraries().borrow().loading.contains(&key) {
+        return Err(format!("load: circular load of {}", path));
+    }
+    let code =
+        std::fs::read_to_string(path).map_err(|e| format!("load: cannot read {}: {}", path, e))?;
+    let (mut arena, nodes) = parse(&code).map_err(|e| format!("load: {}: {}", path, e))?;
+    let nodes = expand_program(&mut arena, &nodes).map_err(|e| format!("load: {}: {}", path, e))?;
+
+    loading(env, key, |env| {
+        for node in nodes {
+            let expr = arena.get(node).ok_or("Invalid node reference")?;
+            Interpreter::eval(expr, env, &arena)?;
+        }
+        Ok(SVal::Nil)
+    })
+}
+
+/// Evaluate `(define-library name decl...)` and record the library's exports
+pub fn define_library(ids: &[NodeId], env: &Environment, arena: &Arena) -> Result<SVal, String> {
+    sandboxed("define-library", env)?;
+    if ids.len() < 2 {
+        return Err("define-library expects a library name".to_string());
+    }
+    let name = display_name(&library_name(ids[1], arena)?);
+    let mut lib_env = env.library_root();
+    let mut exports = Vec::new();
+
+    for id in &ids[2..] {
+        let decl = match arena.get(*id) {
+            Some(SExpr::List(decl)) if !decl.is_empty() => decl,
+            _ => return Err(format!("define-library {}: invalid declaration", name)),
+        };
+        match arena.get(decl[0]) {
+            Some(SExpr::Atom(kind)) if kind == "export" => {
+                for export in &decl[1..] {
+                    match arena.get(*export) {
+                        Some(SExpr::Atom(symbol)) => exports.push(symbol.clone()),
+                        _ => return Err(format!("define-library {}: invalid export", name)),
+                    }
+                }
+            }
+            Some(SExpr::Atom(kind)) if kind == "import" => {
+                import(decl, &mut lib_env, arena)?;
+            }
+            Some(SExpr::Atom(kind)) if kind == "begin" => {
+                for form in &decl[1..] {
+                    let expr = arena.get(*form).ok_or("Invalid expression reference")?;
+                    Interpreter::eval(expr, &mut lib_env, arena)?;
+                }
+            }
+            _ => return Err(format!("define-library {}: unknown declaration", name)),
+        }
+    }
+
+    let bindings = exports
+        .into_iter()
+        .map(|symbol| match lib_env.lookup(&symbol) {
+            Some(value) => Ok((symbol, value)),
+            None => Err(format!("library {} exports undefined {}", name, symbol)),
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    env.libraries().borrow_mut().loaded.insert(name, bindings);
+    Ok(SVal::Nil)
+}
+
+/// Evaluate `(import (name ...) ...)`, defining each library's exports in `env`
+///
+/// `(scheme ...)` libraries name the builtins, which every environment
+/// already has.
+pub fn import(ids: &[NodeId], env: &mut Environment, arena: &Arena) -> Result<SVal, String> {
+    sandboxed("import", env)?;
+    for id in &ids[1..] {
+        let parts = library_name(*id, arena)?;
+        if parts[0] == "scheme" {
+            continue;
+        }
+        let name = display_name(&parts);
+        if !env.libraries().borrow().is_loaded(&name) {
+            find_library(&name, &parts.join("/"), env)?;
+        }
+        let bindings = env.libraries().borrow().loaded[&name].clone();
+        for (symbol, value) in bindings {
+            env.define(symbol, value);
+        }
+    }
+    Ok(SVal::Nil)
+}
+
+/// Refuse `form` in a sandbox that does not allow it
+fn sandboxed(form: &str, env: &Environment) -> Result<(), String> {
+    if env.allows(form) {
+        Ok(())
+    } else {
+        Err(format!("{} is not allowed in this sandbox", form))
+    }
+}
+
+/// Load the file that should define library `name`
+///
+/// `file_name` is the name's parts joined by `/`, which replaces the `?`
+/// in the search templates.
+fn find_library(name: &str, file_name: &str, env: &mut Environment) -> Result<(), String> {
+    let key = format!("library {}", name);
+    if env.libraries().borrow().loading.contains(&key) {
+        return Err(format!("import: circular import of library {}", name));
+    }
+    let path = search_path(&env.libraries().borrow().path, file_name)
+        .map_err(|tried| format!("Library not found: {}{}", name, tried))?;
+    let path = path.display().to_string();
+
+    loading(env, key, |env| load(&path, env))?;
+    if env.libraries().borrow().is_loaded(name) {
+        Ok(())
+    } else {
+        Err(format!("{} does not define library {}", path, name))
+    }
+}
+
+/// Run `body` with `key` marked as loading
+fn loading(
+    env: &mut Environment,
+    key: String,
+    body: impl FnOnce(&mut Environment) -> Result<SVal, String>,
+) -> Result<SVal, String> {
+    env.libraries().borrow_mut().loading.push(key);
+    let result = body(env);
+    env.libraries().borrow_mut().loading.pop();
+    result
+}
+
+/// The parts of a library name such as `(utils strings)`
+fn library_name(id: NodeId, arena: &Arena) -> Result<Vec<String>, String> {
+    let parts = match arena.get(id) {
+        Some(SExpr::List(parts)) if !parts.is_empty() => parts,
+        _ => return Err("Library names are non-empty lists".to_string()),
+    };
+    parts
+        .iter()
+        .map(|part| match arena.get(*part) {
+            Some(SExpr::Atom(symbol)) => Ok(symbol.clone()),
+            Some(SExpr::Integer(n)) if *n >= 0 => Ok(n.to_string()),
+            _ => Err("Library names are made of symbols and integers".to_string()),
+        })
+        .collect()
+}
+
+/// The key a library is known by, written as in the source
+fn display_name(parts: &[String]) -> String {
+    format!("({})", parts.join(" "))
+}
@@ -0,0 +1,645 @@
+//! Numeric tower for the Scheme interpreter
+//!
+//! Exact integers are stored as `i64` and spill into `BigInt` on overflow,
+//! exact non-integers are `BigRational`, and inexact reals are `f64`.
+//! Exact results are always normalised to the smallest representation, so
+//! `(/ 4 2)` yields the integer `2` and not the rational `2/1`. Mixing an
+//! inexact operand into an operation makes the result inexact.
+
+use crate::ast::SExpr;
+use crate::interpreter::SVal;
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+/// A number lifted to the two levels arithmetic works on
+enum Num {
+    Exact(BigRational),
+    Inexact(f64),
+}
+
+/// Binary arithmetic operations
+#[derive(Debug, Clone, Copy)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// Rounding modes for floor, ceiling, round and truncate
+#[derive(Debug, Clone, Copy)]
+pub enum Rounding {
+    Floor,
+    Ceiling,
+    Round,
+    Truncate,
+}
+
+fn classify(v: &SVal, op: &str) -> Result<Num, String> {
+    match v {
+        SVal::Integer(n) => Ok(Num::Exact(BigRational::from_integer(BigInt::from(*n)))),
+        SVal::BigInt(n) => Ok(Num::Exact(BigRational::from_integer(n.clone()))),
+        SVal::Rational(r) => Ok(Num::Exact(r.clone())),
+        SVal::Number(f) => Ok(Num::Inexact(*f)),
+        _ => Err(format!("{} expects numbers", op)),
+    }
+}
+
+/// Check whether a value is any kind of number
+pub fn is_number(v: &SVal) -> bool {
+    matches!(
+        v,
+        SVal::Integer(_) | SVal::BigInt(_) | SVal::Rational(_) | SVal::Number(_)
+    )
+}
+
+/// Check whether a number is exact
+pub fn is_exact(v: &SVal) -> bool {
+    matches!(v, SVal::Integer(_) | SVal::BigInt(_) | SVal::Rational(_))
+}
+
+/// Check whether a number has an integer value (exact or inexact)
+pub fn is_integer(v: &SVal) -> bool {
+    match v {
+        SVal::Integer(_) | SVal::BigInt(_) => true,
+        SVal::Number(f) => f.is_finite() && f.fract() == 0.0,
+        _ => false,
+    }
+}
+
+/// Wrap a big integer, using the `i64` representation when it fits
+pub fn from_bigint(n: BigInt) -> SVal {
+    match n.to_i64() {
+        Some(i) => SVal::Integer(i),
+        None => SVal::BigInt(n),
+    }
+}
+
+/// Wrap an exact rational, reducing it to an integer when possible
+pub fn from_rational(r: BigRational) -> SVal {
+    if r.is_integer() {
+        from_bigint(r.to_integer())
+    } else {
+        SVal::Rational(r)
+    }
+}
+
+/// Convert any number to a float
+pub fn to_f64(v: &SVal) -> Option<f64> {
+    match v {
+        SVal::Integer(n) => Some(*n as f64),
+        SVal::BigInt(n) => n.to_f64(),
+        SVal::Rational(r) => r.to_f64(),
+        SVal::Number(f) => Some(*f),
+        _ => None,
+    }
+}
+
+/// Convert a non-negative integer-valued number to an index
+pub fn to_index(v: &SVal) -> Option<usize> {
+    match v {
+        SVal::Integer(n) => usize::try_from(*n).ok(),
+        SVal::Number(f) if *f >= 0.0 && f.fract() == 0.0 => Some(*f as usize),
+        _ => None,
+    }
+}
+
+/// Apply a binary arithmetic operation
+pub fn arith(op: Op, a: &SVal, b: &SVal) -> Result<SVal, String> {
+    let name = match op {
+        Op::Add => "+",
+        Op::Sub => "-",
+        Op::Mul => "*",
+        Op::Div => "/",
+    };
+
+    // Fast path for machine integers that don't overflow
+    if let (SVal::Integer(x), SVal::Integer(y)) = (a, b) {
+        let result = match op {
+            Op::Add => x.checked_add(*y),
+            Op::Sub => x.checked_sub(*y),
+            Op::Mul => x.checked_mul(*y),
+            Op::Div => None,
+        };
+        if let Some(n) = result {
+            return Ok(SVal::Integer(n));
+        }
+    }
+
+    match (classify(a, name)?, classify(b, name)?) {
+        (Num::Exact(x), Num::Exact(y)) => {
+            let result = match op {
+                Op::Add => x + y,
+                Op::Sub => x - y,
+                Op::Mul => x * y,
+                Op::Div => {
+                    if y.is_zero() {
+                        return Err("Division by zero".to_string());
+                    }
+                    x / y
+                }
+            };
+            Ok(from_rational(result))
+        }
+        _ => {
+            let x = to_f64(a).unwrap_or(f64::NAN);
+            let y = to_f64(b).unwrap_or(f64::NAN);
+            let result = match op {
+                Op::Add => x + y,
+                Op::Sub => x - y,
+                Op::Mul => x * y,
+                Op::Div => x / y,
+            };
+            Ok(SVal::Number(result))
+        }
+    }
+}
+
+/// Compare two numbers; `None` when either is NaN
+pub fn compare(a: &SVal, b: &SVal, op: &str) -> Result<Option<Ordering>, String> {
+    match (classify(a, op)?, classify(b, op)?) {
+        (Num::Exact(x), Num::Exact(y)) => Ok(Some(x.cmp(&y))),
+        _ => {
+            let x = to_f64(a).unwrap_or(f64::NAN);
+            let y = to_f64(b).unwrap_or(f64::NAN);
+            Ok(x.partial_cmp(&y))
+        }
+    }
+}
+
+/// Absolute value of a number
+pub fn abs(v: &SVal) -> Result<SVal, String> {
+    match classify(v, "abs")? {
+        Num::Exact(r) => Ok(from_rational(r.abs())),
+        Num::Inexact(f) => Ok(SVal::Number(f.abs())),
+    }
+}
+
+/// Round a number to an integer value, keeping its exactness
+pub fn round_with(v: &SVal, mode: Rounding, op: &str) -> Result<SVal, String> {
+    match classify(v, op)? {
+        Num::Exact(r) => {
+            let rounded = match mode {
+                Rounding::Floor => r.floor(),
+                Rounding::Ceiling => r.ceil(),
+                Rounding::Truncate => r.trunc(),
+                Rounding::Round => {
+                    // Scheme rounds halfway cases to even
+                    let floor = r.floor();
+                    let diff = &r - &floor;
+                    let half = BigRational::new(BigInt::one(), BigInt::from(2));
+                    match diff.cmp(&half) {
+                        Ordering::Less => floor,
+                        Ordering::Greater => floor + BigRational::one(),
+                        Ordering::Equal if floor.to_integer().is_even() => floor,
+                        Ordering::Equal => floor + BigRational::one(),
+                    }
+                }
+            };
+            Ok(from_rational(rounded))
+        }
+        Num::Inexact(f) => Ok(SVal::Number(match mode {
+            Rounding::Floor => f.floor(),
+            Rounding::Ceiling => f.ceil(),
+            Rounding::Truncate => f.trunc(),
+            Rounding::Round => f.round_ties_even(),
+        })),
+    }
+}
+
+/// Convert a number to its inexact equivalent
+pub fn to_inexact(v: &SVal) -> Result<SVal, String> {
+    to_f64(v)
+        .map(SVal::Number)
+        .ok_or_else(|| "exact->inexact expects a number".to_string())
+}
+
+/// Convert a number to its exact equivalent
+pub fn to_exact(v: &SVal) -> Result<SVal, String> {
+    match v {
+        SVal::Number(f) => BigRational::from_float(*f)
+            .map(from_rational)
+            .ok_or_else(|| format!("Cannot convert {} to an exact number", format_real(*f))),
+        v if is_exact(v) => Ok(v.clone()),
+        _ => Err("inexact->exact expects a number".to_string()),
+    }
+}
+
+/// Raise a number to a power, exactly when both operands are exact and the
+/// exponent is an integer
+pub fn expt(base: &SVal, exponent: &SVal) -> Result<SVal, String> {
+    let exact_exponent = match exponent {
+        SVal::Integer(n) => Some(*n),
+        _ => None,
+    };
+
+    if let (Num::Exact(b), Some(e)) = (classify(base, "expt")?, exact_exponent) {
+        let magnitude = u32::try_from(e.unsigned_abs())
+            .map_err(|_| "expt exponent is too large".to_string())?;
+        let numer: BigInt = b.numer().pow(magnitude);
+        let denom: BigInt = b.denom().pow(magnitude);
+        if e < 0 {
+            if numer.is_zero() {
+                return Err("Division by zero".to_string());
+            }
+            return Ok(from_rational(BigRational::new(denom, numer)));
+        }
+        return Ok(from_rational(BigRational::new(numer, denom)));
+    }
+
+    let b = to_f64(base).ok_or("expt expects numbers")?;
+    let e = to_f64(exponent).ok_or("expt expects numbers")?;
+    Ok(SVal::Number(b.powf(e)))
+}
+
+/// Integer division operations
+#[derive(Debug, Clone, Copy)]
+pub enum IntDiv {
+    /// Truncating quotient
+    Quotient,
+    /// Remainder with the sign of the dividend
+    Remainder,
+    /// Modulo with the sign of the divisor
+    Modulo,
+}
+
+/// Apply quotient, remainder or modulo to two integers
+pub fn int_div(op: IntDiv, a: &SVal, b: &SVal, name: &str) -> Result<SVal, String> {
+    if !is_integer(a) || !is_integer(b) {
+        return Err(format!("{} expects integers", name));
+    }
+
+    if is_exact(a) && is_exact(b) {
+        let to_big = |v: &SVal| match v {
+            SVal::Integer(n) => BigInt::from(*n),
+            SVal::BigInt(n) => n.clone(),
+            _ => unreachable!(),
+        };
+        let (x, y) = (to_big(a), to_big(b));
+        if y.is_zero() {
+            return Err("Division by zero".to_string());
+        }
+        let result = match op {
+            IntDiv::Quotient => &x / &y,
+            IntDiv::Remainder => &x % &y,
+            IntDiv::Modulo => x.mod_floor(&y),
+        };
+        return Ok(from_bigint(result));
+    }
+
+    let x = to_f64(a).unwrap_or(f64::NAN);
+    let y = to_f64(b).unwrap_or(f64::NAN);
+    if y == 0.0 {
+        return Err("Division by zero".to_string());
+    }
+    Ok(SVal::Number(match op {
+        IntDiv::Quotient => (x / y).trunc(),
+        IntDiv::Remainder => x % y,
+        IntDiv::Modulo => x - y * (x / y).floor(),
+    }))
+}
+
+/// Numerator or denominator of a rational number
+pub fn rational_part(v: &SVal, numerator: bool) -> Result<SVal, String> {
+    let name = if numerator {
+        "numerator"
+    } else {
+        "denominator"
+    };
+    match classify(v, name)? {
+        Num::Exact(r) => Ok(from_bigint(if numerator {
+            r.numer().clone()
+        } else {
+            r.denom().clone()
+        })),
+        Num::Inexact(_) => to_inexact(&rational_part(&to_exact(v)?, numerator)?),
+    }
+}
+
+/// Format an inexact real the way Scheme prints it (`4.0`, `+inf.0`);
+/// very large and very small magnitudes use an exponent (`1e21`, `1.5e-8`)
+/// so the digits stay readable and still read back as inexact
+pub fn format_real(f: f64) -> String {
+    if f.is_nan() {
+        "+nan.0".to_string()
+    } else if f.is_infinite() {
+        if f > 0.0 { "+inf.0" } else { "-inf.0" }.to_string()
+    } else if f != 0.0 && (f.abs() >= 1e21 || f.abs() < 1e-7) {
+        format!("{:e}", f)
+    } else if f.fract() == 0.0 {
+        format!("{}.0", f)
+    } else {
+        format!("{}", f)
+    }
+}
+
+/// Check a radix argument; Scheme only has digits for 2, 8, 10 and 16
+pub fn to_radix(v: &SVal, op: &str) -> Result<u32, String> {
+    match v {
+        SVal::Integer(radix @ (2 | 8 | 10 | 16)) => Ok(*radix as u32),
+        _ => Err(format!("{} expects a radix of 2, 8, 10 or 16", op)),
+    }
+}
+
+/// Write a number in `radix`: exact numbers in any radix, inexact ones
+/// only in radix 10
+pub fn format_radix(v: &SVal, radix: u32) -> Result<String, String> {
+    match v {
+        SVal::Integer(n) => Ok(BigInt::from(*n).to_str_radix(radix)),
+        SVal::BigInt(n) => Ok(n.to_str_radix(radix)),
+        SVal::Rational(r) => Ok(format!(
+            "{}/{}",
+            r.numer().to_str_radix(radix),
+            r.denom().to_str_radix(radix)
+        )),
+        SVal::Number(f) if radix == 10 => Ok(format_real(*f)),
+        SVal::Number(_) => Err(format!(
+            "number->string cannot write an inexact number in radix {}",
+            radix
+        )),
+        _ => Err("number->string expects a number".to_string()),
+    }
+}
+
+/// Parse a numeric literal: integers, big integers, `n/d` rationals and reals
+pub fn parse_literal(s: &str) -> Option<SExpr> {
+    let digits = s.strip_prefix(['+', '-']).unwrap_or(s);
+    if !digits.starts_with(|c: char| c.is_ascii_digit() || c == '.')
+        || !digits.contains(|c: char| c.is_ascii_digit())
+    {
+        return None;
+    }
+
+    if let Ok(n) = s.parse::<i64>() {
+        return Some(SExpr::Integer(n));
+    }
+    if let Ok(n) = BigInt::from_str(s) {
+        return Some(SExpr::BigInt(n));
+    }
+    if let Some((numer, denom)) = s.split_once('/') {
+        let numer = BigInt::from_str(numer).ok()?;
+        let denom = BigInt::from_str(denom).ok()?;
+        if denom.is_zero() || denom.is_negative() {
+            return None;
+        }
+        return to_literal(from_rational(BigRational::new(numer, denom)));
+    }
+    s.parse::<f64>().ok().map(SExpr::Number)
+}
+
+/// Parse a number written in `radix` unless a `#x`, `#o`, `#b` or `#d`
+/// prefix says otherwise; an `#e` or `#i` prefix makes it exact or
+/// inexact. Outside radix 10 only integers and `n/d` rationals are read.
+pub fn parse_prefixed(s: &str, radix: u32) -> Option<SExpr> {
+    let mut radix = radix;
+    let mut exactness = None;
+    let mut radix_given = false;
+    let mut rest = s;
+    while let Some(prefix) = rest.strip_prefix('#') {
+        let mut chars = prefix.chars();
+        match chars.next()?.to_ascii_lowercase() {
+            'e' | 'i' if exactness.is_some() => return None,
+            'e' => exactness = Some(true),
+            'i' => exactness = Some(false),
+            _ if radix_given => return None,
+            c => {
+                radix = match c {
+                    'x' => 16,
+                    'd' => 10,
+                    'o' => 8,
+                    'b' => 2,
+                    _ => return None,
+                };
+                radix_given = true;
+            }
+        }
+        rest = chars.as_str();
+    }
+
+    let literal = if radix == 10 {
+        parse_literal(rest)?
+    } else {
+        let (numer, denom) = match rest.split_once('/') {
+            Some((numer, denom)) => (numer, Some(denom)),
+            None => (rest, None),
+        };
+        let numer = parse_integer(numer, radix)?;
+        let denom = match denom {
+            Some(denom) if !denom.starts_with(['+', '-']) => parse_integer(denom, radix)?,
+            Some(_) => return None,
+            None => BigInt::one(),
+        };
+        if denom.is_zero() {
+            return None;
+        }
+        to_literal(from_rational(BigRational::new(numer, denom)))?
+    };
+    match exactness {
+        None => Some(literal),
+        Some(exact) => {
+            let value = from_literal(&literal)?;
+            let converted = if exact {
+                to_exact(&value)
+            } else {
+                to_inexact(&value)
+            };
+            to_literal(converted.ok()?)
+        }
+    }
+}
+
+/// An optionally signed integer, with only digits of `radix` after the sign
+fn parse_integer(s: &str, radix: u32) -> Option<BigInt> {
+    let digits = s.strip_prefix(['+', '-']).unwrap_or(s);
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    BigInt::parse_bytes(s.as_bytes(), radix)
+}
+
+fn to_literal(v: SVal) -> Option<SExpr> {
+    match v {
+        SVal::Integer(n) => Some(SExpr::Integer(n)),
+        SVal::BigInt(n) => Some(SExpr::BigInt(n)),
+        SVal::Rational(r) => Some(SExpr::Rational(r)),
+        SVal::Number(f) => Some(SExpr::Number(f)),
+        _ => None,
+    }
+}
+
+fn from_literal(e: &SExpr) -> Option<SVal> {
+    match e {
+        SExpr::Integer(n) => Some(SVal::Integer(*n)),
+        SExpr::BigInt(n) => Some(SVal::BigInt(n.clone())),
+        SExpr::Rational(r) => Some(SVal::Rational(r.clone())),
+        SExpr::Number(f) => Some(SVal::Number(*f)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(n: i64) -> SVal {
+        SVal::Integer(n)
+    }
+
+    #[test]
+    fn test_integer_overflow_promotes_to_bigint() {
+        let result = arith(Op::Mul, &int(i64::MAX), &int(2)).unwrap();
+        assert!(matches!(result, SVal::BigInt(_)));
+        assert_eq!(result.to_string(), "18446744073709551614");
+    }
+
+    #[test]
+    fn test_bigint_shrinks_back() {
+        let big = arith(Op::Add, &int(i64::MAX), &int(1)).unwrap();
+        let back = arith(Op::Sub, &big, &int(1)).unwrap();
+        assert!(matches!(back, SVal::Integer(i64::MAX)));
+    }
+
+    #[test]
+    fn test_exact_division() {
+        assert_eq!(arith(Op::Div, &int(1), &int(3)).unwrap().to_string(), "1/3");
+        assert!(matches!(
+            arith(Op::Div, &int(4), &int(2)),
+            Ok(SVal::Integer(2))
+        ));
+        assert!(arith(Op::Div, &int(1), &int(0)).is_err());
+    }
+
+    #[test]
+    fn test_inexact_contagion() {
+        let result = arith(Op::Add, &int(1), &SVal::Number(0.5)).unwrap();
+        assert!(matches!(result, SVal::Number(n) if n == 1.5));
+    }
+
+    #[test]
+    fn test_rounding_modes() {
+        let r = parse_rational("7/2");
+        assert!(matches!(
+            round_with(&r, Rounding::Floor, "floor"),
+            Ok(SVal::Integer(3))
+        ));
+        assert!(matches!(
+            round_with(&r, Rounding::Ceiling, "ceiling"),
+            Ok(SVal::Integer(4))
+        ));
+        assert!(matches!(
+            round_with(&r, Rounding::Round, "round"),
+            Ok(SVal::Integer(4))
+        ));
+        let r = parse_rational("5/2");
+        assert!(matches!(
+            round_with(&r, Rounding::Round, "round"),
+            Ok(SVal::Integer(2))
+        ));
+        let r = parse_rational("-7/2");
+        assert!(matches!(
+            round_with(&r, Rounding::Truncate, "truncate"),
+            Ok(SVal::Integer(-3))
+        ));
+    }
+
+    #[test]
+    fn test_expt() {
+        assert_eq!(
+            expt(&int(2), &int(100)).unwrap().to_string(),
+            "1267650600228229401496703205376"
+        );
+        assert_eq!(expt(&int(2), &int(-2)).unwrap().to_string(), "1/4");
+        assert!(matches!(expt(&SVal::Number(2.0), &int(3)), Ok(SVal::Number(n)) if n == 8.0));
+    }
+
+    #[test]
+    fn test_int_div_signs() {
+        assert!(matches!(
+            int_div(IntDiv::Quotient, &int(-7), &int(2), "q"),
+            Ok(SVal::Integer(-3))
+        ));
+        assert!(matches!(
+            int_div(IntDiv::Remainder, &int(-7), &int(2), "r"),
+            Ok(SVal::Integer(-1))
+        ));
+        assert!(matches!(
+            int_div(IntDiv::Modulo, &int(-7), &int(2), "m"),
+            Ok(SVal::Integer(1))
+        ));
+    }
+
+    #[test]
+    fn test_exactness_conversions() {
+        assert_eq!(to_exact(&SVal::Number(0.5)).unwrap().to_string(), "1/2");
+        assert!(matches!(to_inexact(&parse_rational("1/4")), Ok(SVal::Number(n)) if n == 0.25));
+        assert!(to_exact(&SVal::Number(f64::INFINITY)).is_err());
+    }
+
+    #[test]
+    fn test_parse_literal() {
+        assert_eq!(parse_literal("42"), Some(SExpr::Integer(42)));
+        assert_eq!(parse_literal("-7"), Some(SExpr::Integer(-7)));
+        assert_eq!(parse_literal("6/3"), Some(SExpr::Integer(2)));
+        assert!(matches!(parse_literal("1/3"), Some(SExpr::Rational(_))));
+        assert!(matches!(
+            parse_literal("100000000000000000000"),
+            Some(SExpr::BigInt(_))
+        ));
+        assert_eq!(parse_literal("2.5"), Some(SExpr::Number(2.5)));
+        assert_eq!(parse_literal("-"), None);
+        assert_eq!(parse_literal("..."), None);
+        assert_eq!(parse_literal("inf"), None);
+        assert_eq!(parse_literal("1/0"), None);
+    }
+
+    #[test]
+    fn test_format_real() {
+        assert_eq!(format_real(4.0), "4.0");
+        assert_eq!(format_real(2.5), "2.5");
+        assert_eq!(format_real(f64::NEG_INFINITY), "-inf.0");
+        assert_eq!(format_real(f64::NAN), "+nan.0");
+        assert_eq!(format_real(1e21), "1e21");
+        assert_eq!(format_real(-1.5e-8), "-1.5e-8");
+        assert_eq!(format_real(0.0), "0.0");
+    }
+
+    #[test]
+    fn test_radix() {
+        assert_eq!(format_radix(&int(255), 16).unwrap(), "ff");
+        assert_eq!(format_radix(&int(-5), 2).unwrap(), "-101");
+        assert_eq!(format_radix(&parse_rational("3/8"), 2).unwrap(), "11/1000");
+        assert_eq!(format_radix(&SVal::Number(0.5), 10).unwrap(), "0.5");
+        assert!(format_radix(&SVal::Number(0.5), 2).is_err());
+        assert!(to_radix(&int(3), "op").is_err());
+
+        assert_eq!(parse_prefixed("ff", 16), Some(SExpr::Integer(255)));
+        assert_eq!(parse_prefixed("-1010", 2), Some(SExpr::Integer(-10)));
+        assert_eq!(parse_prefixed("#x-1F", 10), Some(SExpr::Integer(-31)));
+        assert_eq!(parse_prefixed("#b101", 16), Some(SExpr::Integer(5)));
+        assert_eq!(parse_prefixed("#o17", 10), Some(SExpr::Integer(15)));
+        assert_eq!(
+            parse_prefixed("#e1.5", 10),
+            Some(SExpr::Rational(BigRational::new(
+                BigInt::from(3),
+                BigInt::from(2)
+            )))
+        );
+        assert_eq!(parse_prefixed("#i#x10", 10), Some(SExpr::Number(16.0)));
+        assert_eq!(parse_prefixed("12", 2), None);
+        assert_eq!(parse_prefixed("1_0", 16), None);
+        assert_eq!(parse_prefixed("#x#x1", 10), None);
+        assert_eq!(parse_prefixed("#e#i1", 10), None);
+        assert_eq!(parse_prefixed("1.5", 16), None);
+    }
+
+    fn parse_rational(s: &str) -> SVal {
+        match parse_literal(s) {
+            Some(SExpr::Rational(r)) => SVal::Rational(r),
+            other => panic!("expected rational, got {:?}", other),
+        }
+    }
+}
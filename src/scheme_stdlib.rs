@@ -1,4 +1,5 @@
 use crate::interpreter::{Environment, SVal};
+use std::rc::Rc;
 
 /// Register all built-in Scheme functions in the environment
 pub fn register_stdlib(env: &mut Environment) {
@@ -32,6 +33,34 @@ pub fn register_stdlib(env: &mut Environment) {
                 arity: None,
             },
         ),
+        (
+            "quotient",
+            SVal::BuiltinProc {
+                name: "quotient".to_string(),
+                arity: Some(2),
+            },
+        ),
+        (
+            "remainder",
+            SVal::BuiltinProc {
+                name: "remainder".to_string(),
+                arity: Some(2),
+            },
+        ),
+        (
+            "modulo",
+            SVal::BuiltinProc {
+                name: "modulo".to_string(),
+                arity: Some(2),
+            },
+        ),
+        (
+            "expt",
+            SVal::BuiltinProc {
+                name: "expt".to_string(),
+                arity: Some(2),
+            },
+        ),
         // Comparison
         (
             "=",
@@ -68,6 +97,28 @@ pub fn register_stdlib(env: &mut Environment) {
                 arity: Some(2),
             },
         ),
+        // Equivalence
+        (
+            "eq?",
+            SVal::BuiltinProc {
+                name: "eq?".to_string(),
+                arity: Some(2),
+            },
+        ),
+        (
+            "eqv?",
+            SVal::BuiltinProc {
+                name: "eqv?".to_string(),
+                arity: Some(2),
+            },
+        ),
+        (
+            "equal?",
+            SVal::BuiltinProc {
+                name: "equal?".to_string(),
+                arity: Some(2),
+            },
+        ),
         // Type predicates
         (
             "number?",
@@ -97,6 +148,84 @@ pub fn register_stdlib(env: &mut Environment) {
                 arity: Some(1),
             },
         ),
+        (
+            "integer?",
+            SVal::BuiltinProc {
+                name: "integer?".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "rational?",
+            SVal::BuiltinProc {
+                name: "rational?".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "real?",
+            SVal::BuiltinProc {
+                name: "real?".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "exact?",
+            SVal::BuiltinProc {
+                name: "exact?".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "inexact?",
+            SVal::BuiltinProc {
+                name: "inexact?".to_string(),
+                arity: Some(1),
+            },
+        ),
+        // Exactness conversions
+        (
+            "exact->inexact",
+            SVal::BuiltinProc {
+                name: "exact->inexact".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "inexact->exact",
+            SVal::BuiltinProc {
+                name: "inexact->exact".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "exact",
+            SVal::BuiltinProc {
+                name: "exact".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "inexact",
+            SVal::BuiltinProc {
+                name: "inexact".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "numerator",
+            SVal::BuiltinProc {
+                name: "numerator".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "denominator",
+            SVal::BuiltinProc {
+                name: "denominator".to_string(),
+                arity: Some(1),
+            },
+        ),
         // List operations
         (
             "car",
@@ -147,6 +276,48 @@ pub fn register_stdlib(env: &mut Environment) {
                 arity: None,
             },
         ),
+        (
+            "memq",
+            SVal::BuiltinProc {
+                name: "memq".to_string(),
+                arity: Some(2),
+            },
+        ),
+        (
+            "memv",
+            SVal::BuiltinProc {
+                name: "memv".to_string(),
+                arity: Some(2),
+            },
+        ),
+        (
+            "member",
+            SVal::BuiltinProc {
+                name: "member".to_string(),
+                arity: Some(2),
+            },
+        ),
+        (
+            "assq",
+            SVal::BuiltinProc {
+                name: "assq".to_string(),
+                arity: Some(2),
+            },
+        ),
+        (
+            "assv",
+            SVal::BuiltinProc {
+                name: "assv".to_string(),
+                arity: Some(2),
+            },
+        ),
+        (
+            "assoc",
+            SVal::BuiltinProc {
+                name: "assoc".to_string(),
+                arity: Some(2),
+            },
+        ),
         (
             "atom?",
             SVal::BuiltinProc {
@@ -154,6 +325,49 @@ pub fn register_stdlib(env: &mut Environment) {
                 arity: Some(1),
             },
         ),
+        // Higher-order procedures
+        (
+            "map",
+            SVal::BuiltinProc {
+                name: "map".to_string(),
+                arity: None,
+            },
+        ),
+        (
+            "for-each",
+            SVal::BuiltinProc {
+                name: "for-each".to_string(),
+                arity: None,
+            },
+        ),
+        (
+            "filter",
+            SVal::BuiltinProc {
+                name: "filter".to_string(),
+                arity: Some(2),
+            },
+        ),
+        (
+            "fold-left",
+            SVal::BuiltinProc {
+                name: "fold-left".to_string(),
+                arity: None,
+            },
+        ),
+        (
+            "fold-right",
+            SVal::BuiltinProc {
+                name: "fold-right".to_string(),
+                arity: None,
+            },
+        ),
+        (
+            "apply",
+            SVal::BuiltinProc {
+                name: "apply".to_string(),
+                arity: None,
+            },
+        ),
         // I/O
         (
             "display",
@@ -162,13 +376,143 @@ pub fn register_stdlib(env: &mut Environment) {
                 arity: None,
             },
         ),
+        (
+            "write",
+            SVal::BuiltinProc {
+                name: "write".to_string(),
+                arity: None,
+            },
+        ),
         (
             "newline",
             SVal::BuiltinProc {
                 name: "newline".to_string(),
+                arity: None,
+            },
+        ),
+        // Control
+        (
+            "call/cc",
+            SVal::BuiltinProc {
+                name: "call/cc".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "call-with-current-continuation",
+            SVal::BuiltinProc {
+                name: "call-with-current-continuation".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "call/ec",
+            SVal::BuiltinProc {
+                name: "call/ec".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "call-with-escape-continuation",
+            SVal::BuiltinProc {
+                name: "call-with-escape-continuation".to_string(),
+                arity: Some(1),
+            },
+        ),
+        // String ports
+        (
+            "open-input-string",
+            SVal::BuiltinProc {
+                name: "open-input-string".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "open-output-string",
+            SVal::BuiltinProc {
+                name: "open-output-string".to_string(),
                 arity: Some(0),
             },
         ),
+        (
+            "get-output-string",
+            SVal::BuiltinProc {
+                name: "get-output-string".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "with-output-to-string",
+            SVal::BuiltinProc {
+                name: "with-output-to-string".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "read",
+            SVal::BuiltinProc {
+                name: "read".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "eof-object?",
+            SVal::BuiltinProc {
+                name: "eof-object?".to_string(),
+                arity: Some(1),
+            },
+        ),
+        // File ports
+        (
+            "open-input-file",
+            SVal::BuiltinProc {
+                name: "open-input-file".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "open-output-file",
+            SVal::BuiltinProc {
+                name: "open-output-file".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "read-line",
+            SVal::BuiltinProc {
+                name: "read-line".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "read-char",
+            SVal::BuiltinProc {
+                name: "read-char".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "write-string",
+            SVal::BuiltinProc {
+                name: "write-string".to_string(),
+                arity: None,
+            },
+        ),
+        (
+            "close-port",
+            SVal::BuiltinProc {
+                name: "close-port".to_string(),
+                arity: Some(1),
+            },
+        ),
+        // Libraries
+        (
+            "load",
+            SVal::BuiltinProc {
+                name: "load".to_string(),
+                arity: Some(1),
+            },
+        ),
         // Mathematical functions
         (
             "abs",
@@ -283,6 +627,13 @@ pub fn register_stdlib(env: &mut Environment) {
                 arity: Some(3),
             },
         ),
+        (
+            "string-ref",
+            SVal::BuiltinProc {
+                name: "string-ref".to_string(),
+                arity: Some(2),
+            },
+        ),
         (
             "string-upcase",
             SVal::BuiltinProc {
@@ -308,13 +659,198 @@ pub fn register_stdlib(env: &mut Environment) {
             "string->number",
             SVal::BuiltinProc {
                 name: "string->number".to_string(),
-                arity: Some(1),
+                arity: None,
             },
         ),
         (
             "number->string",
             SVal::BuiltinProc {
                 name: "number->string".to_string(),
+                arity: None,
+            },
+        ),
+        (
+            "make-string",
+            SVal::BuiltinProc {
+                name: "make-string".to_string(),
+                arity: None,
+            },
+        ),
+        (
+            "string-copy",
+            SVal::BuiltinProc {
+                name: "string-copy".to_string(),
+                arity: None,
+            },
+        ),
+        (
+            "string-set!",
+            SVal::BuiltinProc {
+                name: "string-set!".to_string(),
+                arity: Some(3),
+            },
+        ),
+        (
+            "string-fill!",
+            SVal::BuiltinProc {
+                name: "string-fill!".to_string(),
+                arity: Some(2),
+            },
+        ),
+        (
+            "string-join",
+            SVal::BuiltinProc {
+                name: "string-join".to_string(),
+                arity: None,
+            },
+        ),
+        // Characters
+        (
+            "char?",
+            SVal::BuiltinProc {
+                name: "char?".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "char->integer",
+            SVal::BuiltinProc {
+                name: "char->integer".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "integer->char",
+            SVal::BuiltinProc {
+                name: "integer->char".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "char-upcase",
+            SVal::BuiltinProc {
+                name: "char-upcase".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "char-downcase",
+            SVal::BuiltinProc {
+                name: "char-downcase".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "char-alphabetic?",
+            SVal::BuiltinProc {
+                name: "char-alphabetic?".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "char-numeric?",
+            SVal::BuiltinProc {
+                name: "char-numeric?".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "char-whitespace?",
+            SVal::BuiltinProc {
+                name: "char-whitespace?".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "char-upper-case?",
+            SVal::BuiltinProc {
+                name: "char-upper-case?".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "char-lower-case?",
+            SVal::BuiltinProc {
+                name: "char-lower-case?".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "char=?",
+            SVal::BuiltinProc {
+                name: "char=?".to_string(),
+                arity: None,
+            },
+        ),
+        (
+            "char<?",
+            SVal::BuiltinProc {
+                name: "char<?".to_string(),
+                arity: None,
+            },
+        ),
+        (
+            "char>?",
+            SVal::BuiltinProc {
+                name: "char>?".to_string(),
+                arity: None,
+            },
+        ),
+        (
+            "char<=?",
+            SVal::BuiltinProc {
+                name: "char<=?".to_string(),
+                arity: None,
+            },
+        ),
+        (
+            "char>=?",
+            SVal::BuiltinProc {
+                name: "char>=?".to_string(),
+                arity: None,
+            },
+        ),
+        (
+            "string->list",
+            SVal::BuiltinProc {
+                name: "string->list".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "list->string",
+            SVal::BuiltinProc {
+                name: "list->string".to_string(),
+                arity: Some(1),
+            },
+        ),
+        // Process environment
+        (
+            "getenv",
+            SVal::BuiltinProc {
+                name: "getenv".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "get-environment-variable",
+            SVal::BuiltinProc {
+                name: "get-environment-variable".to_string(),
+                arity: Some(1),
+            },
+        ),
+        (
+            "get-environment-variables",
+            SVal::BuiltinProc {
+                name: "get-environment-variables".to_string(),
+                arity: Some(0),
+            },
+        ),
+        // Interop
+        (
+            "lua-eval",
+            SVal::BuiltinProc {
+                name: "lua-eval".to_string(),
                 arity: Some(1),
             },
         ),
@@ -325,6 +861,116 @@ pub fn register_stdlib(env: &mut Environment) {
     }
 }
 
+/// `eq?`: identity. Values without an identity of their own (symbols,
+/// booleans, characters, numbers and the empty list) compare by value;
+/// non-empty lists, vectors and strings are copied rather than shared, so
+/// they are never `eq?`.
+pub fn is_eq(a: &SVal, b: &SVal) -> bool {
+    match (a, b) {
+        (SVal::String(x), SVal::String(y)) if !x.is_empty() || !y.is_empty() => false,
+        (SVal::List(x), SVal::List(y)) | (SVal::Vector(x), SVal::Vector(y))
+            if !x.is_empty() || !y.is_empty() =>
+        {
+            false
+        }
+        _ => is_eqv(a, b),
+    }
+}
+
+/// `eqv?`: like `eq?`, but numbers are equal when they have the same
+/// exactness and value, and empty strings and vectors are equivalent
+pub fn is_eqv(a: &SVal, b: &SVal) -> bool {
+    match (a, b) {
+        // Compare the bits so NaN is eqv? to itself and 0.0 is not eqv? to -0.0
+        (SVal::Number(x), SVal::Number(y)) => x.to_bits() == y.to_bits(),
+        (SVal::String(x), SVal::String(y)) => x.is_empty() && y.is_empty(),
+        (SVal::Vector(x), SVal::Vector(y)) => x.is_empty() && y.is_empty(),
+        (SVal::List(x), SVal::List(y)) => x.is_empty() && y.is_empty(),
+        (SVal::List(items), SVal::Nil) | (SVal::Nil, SVal::List(items)) => items.is_empty(),
+        (SVal::BuiltinProc { name: x, .. }, SVal::BuiltinProc { name: y, .. }) => x == y,
+        (
+            SVal::UserProc {
+                body: x_body,
+                scope: x_scope,
+                ..
+            },
+            SVal::UserProc {
+                body: y_body,
+                scope: y_scope,
+                ..
+            },
+        ) => Rc::ptr_eq(x_body, y_body) && Rc::ptr_eq(x_scope, y_scope),
+        _ => a == b,
+    }
+}
+
+/// `equal?`: lists, improper lists, vectors and strings compare element by element,
+/// everything else as `eqv?`
+pub fn is_equal(a: &SVal, b: &SVal) -> bool {
+    match (a, b) {
+        (SVal::String(x), SVal::String(y)) => x == y,
+        (SVal::MutableString(x), y) | (y, SVal::MutableString(x)) => match y {
+            SVal::String(y) => *x.borrow() == *y,
+            SVal::MutableString(y) => *x.borrow() == *y.borrow(),
+            _ => false,
+        },
+        (SVal::List(x), SVal::List(y)) | (SVal::Vector(x), SVal::Vector(y)) => {
+            x.len() == y.len() && x.iter().zip(y).all(|(x, y)| is_equal(x, y))
+        }
+        (SVal::DottedList(x, x_tail), SVal::DottedList(y, y_tail)) => {
+            x.len() == y.len()
+                && x.iter().zip(y).all(|(x, y)| is_equal(x, y))
+                && is_equal(x_tail, y_tail)
+        }
+        _ => is_eqv(a, b),
+    }
+}
+
+/// Pick the equivalence predicate used by the `mem*` and `ass*` builtins
+fn equivalence(name: &str) -> fn(&SVal, &SVal) -> bool {
+    match name {
+        "memq" | "assq" => is_eq,
+        "memv" | "assv" => is_eqv,
+        _ => is_equal,
+    }
+}
+
+/// `memq`, `memv` and `member`: the first tail of `list` whose car is
+/// equivalent to `item`, or `#f`
+pub fn member(name: &str, item: &SVal, list: &SVal) -> Result<SVal, String> {
+    let same = equivalence(name);
+    match list {
+        SVal::List(items) => Ok(items
+            .iter()
+            .position(|x| same(item, x))
+            .map_or(SVal::Bool(false), |i| SVal::List(items[i..].to_vec()))),
+        SVal::Nil => Ok(SVal::Bool(false)),
+        other => Err(format!("{} expects a list, got {}", name, other)),
+    }
+}
+
+/// `assq`, `assv` and `assoc`: the first pair in the association list
+/// whose car is equivalent to `key`, or `#f`
+pub fn assoc(name: &str, key: &SVal, alist: &SVal) -> Result<SVal, String> {
+    let same = equivalence(name);
+    let entries = match alist {
+        SVal::List(entries) => entries.as_slice(),
+        SVal::Nil => &[],
+        other => return Err(format!("{} expects a list, got {}", name, other)),
+    };
+    for entry in entries {
+        match entry {
+            SVal::List(pair) | SVal::DottedList(pair, _) if !pair.is_empty() => {
+                if same(key, &pair[0]) {
+                    return Ok(entry.clone());
+                }
+            }
+            other => return Err(format!("{} expects a list of pairs, got {}", name, other)),
+        }
+    }
+    Ok(SVal::Bool(false))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -348,8 +994,23 @@ mod tests {
         assert!(env.lookup("list").is_some());
         assert!(env.lookup("length").is_some());
         assert!(env.lookup("append").is_some());
+        assert!(env.lookup("equal?").is_some());
+        assert!(env.lookup("assoc").is_some());
+        assert!(env.lookup("map").is_some());
+        assert!(env.lookup("apply").is_some());
         assert!(env.lookup("display").is_some());
         assert!(env.lookup("newline").is_some());
+        assert!(env.lookup("write").is_some());
+        assert!(env.lookup("open-input-string").is_some());
+        assert!(env.lookup("with-output-to-string").is_some());
+        assert!(env.lookup("read").is_some());
+        assert!(env.lookup("open-input-file").is_some());
+        assert!(env.lookup("close-port").is_some());
+        assert!(env.lookup("call/cc").is_some());
+        assert!(env.lookup("lua-eval").is_some());
+        assert!(env.lookup("getenv").is_some());
+        assert!(env.lookup("get-environment-variables").is_some());
+        assert!(env.lookup("call-with-escape-continuation").is_some());
 
         // Verify math functions are registered
         assert!(env.lookup("abs").is_some());
@@ -362,6 +1023,15 @@ mod tests {
         assert!(env.lookup("min").is_some());
         assert!(env.lookup("max").is_some());
 
+        // Verify numeric tower functions are registered
+        assert!(env.lookup("quotient").is_some());
+        assert!(env.lookup("modulo").is_some());
+        assert!(env.lookup("expt").is_some());
+        assert!(env.lookup("exact?").is_some());
+        assert!(env.lookup("inexact?").is_some());
+        assert!(env.lookup("exact->inexact").is_some());
+        assert!(env.lookup("inexact->exact").is_some());
+
         // Verify string functions are registered
         assert!(env.lookup("string?").is_some());
         assert!(env.lookup("string-length").is_some());
@@ -21,9 +21,7 @@ pub struct ScopeManager {
 impl ScopeManager {
     /// Create a new empty scope manager
     pub fn new() -> Self {
-        ScopeManager {
-            stack: Vec::new(),
-        }
+        ScopeManager { stack: Vec::new() }
     }
 
     /// Push a new scope onto the stack
@@ -36,7 +34,8 @@ impl ScopeManager {
     /// Pop the current scope from the stack
     /// Returns the popped scope, or an error if stack is empty
     pub fn pop(&mut self) -> Result<HashMap<String, LuaValue>, String> {
-        self.stack.pop()
+        self.stack
+            .pop()
             .ok_or_else(|| "Cannot pop from empty scope stack".to_string())
     }
 
@@ -100,11 +99,13 @@ impl ScopeManager {
     }
 
     /// Get the raw scope stack (for advanced operations or migration)
+    #[allow(clippy::should_implement_trait)]
     pub fn as_ref(&self) -> &Vec<HashMap<String, LuaValue>> {
         &self.stack
     }
 
     /// Get a mutable reference to the raw scope stack
+    #[allow(clippy::should_implement_trait)]
     pub fn as_mut(&mut self) -> &mut Vec<HashMap<String, LuaValue>> {
         &mut self.stack
     }
@@ -141,13 +142,13 @@ mod tests {
         let mut manager = ScopeManager::new();
         manager.push();
         manager.push();
-        
+
         assert!(manager.pop().is_ok());
         assert_eq!(manager.depth(), 1);
-        
+
         assert!(manager.pop().is_ok());
         assert_eq!(manager.depth(), 0);
-        
+
         // Pop from empty stack should fail
         assert!(manager.pop().is_err());
     }
@@ -156,8 +157,10 @@ mod tests {
     fn test_define_variable() {
         let mut manager = ScopeManager::new();
         manager.push();
-        
-        assert!(manager.define("x".to_string(), LuaValue::Number(42.0)).is_ok());
+
+        assert!(manager
+            .define("x".to_string(), LuaValue::Number(42.0))
+            .is_ok());
         assert_eq!(manager.lookup("x"), Some(LuaValue::Number(42.0)));
     }
 
@@ -172,22 +175,26 @@ mod tests {
     #[test]
     fn test_lookup_across_scopes() {
         let mut manager = ScopeManager::new();
-        
+
         // Define in outer scope
         manager.push();
-        manager.define("x".to_string(), LuaValue::Number(1.0)).unwrap();
-        
+        manager
+            .define("x".to_string(), LuaValue::Number(1.0))
+            .unwrap();
+
         // Define in inner scope
         manager.push();
-        manager.define("y".to_string(), LuaValue::Number(2.0)).unwrap();
-        
+        manager
+            .define("y".to_string(), LuaValue::Number(2.0))
+            .unwrap();
+
         // Can lookup both
         assert_eq!(manager.lookup("x"), Some(LuaValue::Number(1.0)));
         assert_eq!(manager.lookup("y"), Some(LuaValue::Number(2.0)));
-        
+
         // Pop inner scope
         manager.pop().unwrap();
-        
+
         // Can still lookup x (from outer scope)
         assert_eq!(manager.lookup("x"), Some(LuaValue::Number(1.0)));
         // But not y
@@ -197,21 +204,25 @@ mod tests {
     #[test]
     fn test_shadow_variable() {
         let mut manager = ScopeManager::new();
-        
+
         // Define in outer scope
         manager.push();
-        manager.define("x".to_string(), LuaValue::Number(1.0)).unwrap();
-        
+        manager
+            .define("x".to_string(), LuaValue::Number(1.0))
+            .unwrap();
+
         // Shadow in inner scope
         manager.push();
-        manager.define("x".to_string(), LuaValue::Number(2.0)).unwrap();
-        
+        manager
+            .define("x".to_string(), LuaValue::Number(2.0))
+            .unwrap();
+
         // Lookup returns inner value
         assert_eq!(manager.lookup("x"), Some(LuaValue::Number(2.0)));
-        
+
         // Pop inner scope
         manager.pop().unwrap();
-        
+
         // Now returns outer value
         assert_eq!(manager.lookup("x"), Some(LuaValue::Number(1.0)));
     }
@@ -219,10 +230,12 @@ mod tests {
     #[test]
     fn test_update_variable() {
         let mut manager = ScopeManager::new();
-        
+
         manager.push();
-        manager.define("x".to_string(), LuaValue::Number(1.0)).unwrap();
-        
+        manager
+            .define("x".to_string(), LuaValue::Number(1.0))
+            .unwrap();
+
         // Update existing variable
         assert!(manager.update("x", LuaValue::Number(2.0)).is_ok());
         assert_eq!(manager.lookup("x"), Some(LuaValue::Number(2.0)));
@@ -232,7 +245,7 @@ mod tests {
     fn test_update_nonexistent_variable() {
         let mut manager = ScopeManager::new();
         manager.push();
-        
+
         // Update nonexistent variable
         let result = manager.update("nonexistent", LuaValue::Number(1.0));
         assert!(result.is_err());
@@ -241,21 +254,23 @@ mod tests {
     #[test]
     fn test_update_across_scopes() {
         let mut manager = ScopeManager::new();
-        
+
         // Define in outer scope
         manager.push();
-        manager.define("x".to_string(), LuaValue::Number(1.0)).unwrap();
-        
+        manager
+            .define("x".to_string(), LuaValue::Number(1.0))
+            .unwrap();
+
         // Enter inner scope
         manager.push();
-        
+
         // Update should modify outer scope's variable
         assert!(manager.update("x", LuaValue::Number(2.0)).is_ok());
         assert_eq!(manager.lookup("x"), Some(LuaValue::Number(2.0)));
-        
+
         // Pop inner scope
         manager.pop().unwrap();
-        
+
         // Outer scope should have updated value
         assert_eq!(manager.lookup("x"), Some(LuaValue::Number(2.0)));
     }
@@ -263,19 +278,24 @@ mod tests {
     #[test]
     fn test_current_scope() {
         let mut manager = ScopeManager::new();
-        
+
         // No scope
         assert!(manager.current_scope().is_none());
-        
+
         // Add scope
         manager.push();
         assert!(manager.current_scope().is_some());
-        
+
         // Define variable in current scope
-        manager.define("x".to_string(), LuaValue::Number(42.0)).unwrap();
-        
+        manager
+            .define("x".to_string(), LuaValue::Number(42.0))
+            .unwrap();
+
         // Can access through current_scope
-        assert_eq!(manager.current_scope().unwrap().get("x"), Some(&LuaValue::Number(42.0)));
+        assert_eq!(
+            manager.current_scope().unwrap().get("x"),
+            Some(&LuaValue::Number(42.0))
+        );
     }
 
     #[test]
@@ -284,9 +304,9 @@ mod tests {
         manager.push();
         manager.push();
         manager.push();
-        
+
         assert_eq!(manager.depth(), 3);
-        
+
         manager.clear();
         assert_eq!(manager.depth(), 0);
         assert!(manager.is_empty());
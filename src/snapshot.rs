@@ -0,0 +1,586 @@
+//! Saving and restoring the global environment of a `LuaInterpreter`
+//!
+//! A snapshot flattens everything reachable from the globals into numbered
+//! tables, closures and upvalue cells that refer to each other by index, so
+//! shared and cyclic structures come back exactly as they were. Lua
+//! functions are kept as their source (the arena of the chunk they were
+//! parsed from and the id of their body) together with the cells they
+//! captured. Builtins and standard library tables cannot be written out;
+//! they are saved by the global path they live at (`print`,
+//! `string.upper`) and looked up again in the interpreter being restored
+//! into. Changes a script made to the standard library tables themselves
+//! are not saved.
+
+use crate::error_types::{LuaError, LuaResult};
+use crate::lua_interpreter::LuaInterpreter;
+use crate::lua_parser::{FunctionId, FunctionRef, LuaArena};
+use crate::lua_string::LuaString;
+use crate::lua_value::{LuaFunction, LuaTable, LuaValue, Metatable, TableData};
+use crate::upvalues::{ClosureState, Upvalue, UpvalueCell};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Bumped whenever the layout of a snapshot changes
+const VERSION: u32 = 3;
+
+/// A value inside a snapshot; references point into the snapshot's lists
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Value {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    /// `inf`, `-inf` or `nan`, which JSON has no numbers for
+    NonFinite(String),
+    String(LuaString),
+    Table(usize),
+    Function(usize),
+    /// A builtin or library table, by the global path it is found at
+    Library(String),
+}
+
+/// Entries and metatable of one table
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TableImage {
+    pub entries: Vec<(Value, Value)>,
+    pub metatable: Option<Value>,
+}
+
+/// A Lua closure: which body it runs and which cells it captured
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionImage {
+    /// The arena the body is in, as an index into `Snapshot::arenas`
+    pub arena: usize,
+    pub body: FunctionId,
+    /// Upvalues captured by name, as `(name, cell)`
+    pub upvalues: Vec<(String, usize)>,
+    /// Cells captured by a resolved body, in `FrameLayout::captures` order
+    pub cells: Vec<usize>,
+}
+
+/// The saved global environment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    /// Globals sorted by name
+    pub globals: Vec<(String, Value)>,
+    pub tables: Vec<TableImage>,
+    pub functions: Vec<FunctionImage>,
+    /// Arenas of the chunks the functions were parsed from, shared by every
+    /// closure made from the same chunk
+    pub arenas: Vec<LuaArena>,
+    /// Upvalue cells, shared by every closure that captured them
+    pub cells: Vec<Value>,
+}
+
+impl Snapshot {
+    /// Save the globals of `interp`
+    pub fn take(interp: &LuaInterpreter) -> LuaResult<Snapshot> {
+        let mut saver = Saver::new(interp);
+        let mut names = interp.global_names();
+        names.sort();
+        let mut globals = Vec::with_capacity(names.len());
+        for name in names {
+            let value = saver.value(&interp.get_global(&name))?;
+            globals.push((name, value));
+        }
+        saver.finish()?;
+        saver.image.globals = globals;
+        Ok(saver.image)
+    }
+
+    /// Define the saved globals in `interp`, replacing globals of the same
+    /// name and leaving the others alone
+    pub fn restore(&self, interp: &mut LuaInterpreter) -> LuaResult<()> {
+        if self.version != VERSION {
+            return Err(LuaError::value(format!(
+                "snapshot version {} is not supported (expected {})",
+                self.version, VERSION
+            )));
+        }
+        let loader = Loader::new(self, interp)?;
+        for (cell, value) in loader.cells.iter().zip(&self.cells) {
+            *cell.borrow_mut() = loader.value(value)?;
+        }
+        for (table, image) in loader.tables.iter().zip(&self.tables) {
+            let mut table = table.borrow_mut();
+            for (key, value) in &image.entries {
+                table.data.insert(loader.value(key)?, loader.value(value)?);
+            }
+            if let Some(metatable) = &image.metatable {
+                let LuaValue::Table(metatable) = loader.value(metatable)? else {
+                    return Err(LuaError::value("snapshot metatable is not a table"));
+                };
+                table.metatable = Some(Box::new(Metatable::new(metatable)));
+            }
+        }
+        let mut globals = Vec::with_capacity(self.globals.len());
+        for (name, value) in &self.globals {
+            globals.push((name, loader.value(value)?));
+        }
+        for (name, value) in globals {
+            interp.set_global(name.as_str(), value);
+        }
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> LuaResult<String> {
+        serde_json::to_string(self)
+            .map_err(|e| LuaError::value(format!("cannot write snapshot: {}", e)))
+    }
+
+    pub fn from_json(text: &str) -> LuaResult<Snapshot> {
+        serde_json::from_str(text)
+            .map_err(|e| LuaError::value(format!("cannot read snapshot: {}", e)))
+    }
+}
+
+/// Global paths of the builtins and library tables a fresh interpreter has
+fn library_paths() -> Vec<(String, Option<String>)> {
+    let fresh = LuaInterpreter::new();
+    let mut paths = Vec::new();
+    for name in fresh.global_names() {
+        match fresh.get_global(&name) {
+            LuaValue::Function(_) => paths.push((name.clone(), None)),
+            LuaValue::Table(table) => {
+                paths.push((name.clone(), None));
+                for (key, field) in &table.borrow().data {
+                    if let (LuaValue::String(key), LuaValue::Function(_)) = (key, field) {
+                        paths.push((name.clone(), Some(key.to_string())));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    // Prefer the short names when a builtin is reachable from several paths
+    paths.sort_by_key(|(name, field)| (field.is_some(), name.clone(), field.clone()));
+    paths
+}
+
+fn lookup_path(interp: &LuaInterpreter, name: &str, field: Option<&str>) -> Option<LuaValue> {
+    let value = interp.globals.borrow().get_str(name)?.clone();
+    match field {
+        None => Some(value),
+        Some(field) => match value {
+            LuaValue::Table(table) => table
+                .borrow()
+                .data
+                .get(&LuaValue::String(field.into()))
+                .cloned(),
+            _ => None,
+        },
+    }
+}
+
+enum Pending {
+    Table(Rc<RefCell<LuaTable>>, usize),
+    Cell(UpvalueCell, usize),
+}
+
+/// Walks the globals, numbering every table, closure, arena and cell once
+struct Saver {
+    /// Builtins and library tables of the interpreter being saved, by address
+    library: HashMap<usize, String>,
+    tables: HashMap<usize, usize>,
+    functions: HashMap<usize, usize>,
+    arenas: HashMap<usize, usize>,
+    cells: HashMap<usize, usize>,
+    /// Tables and cells numbered but not written out yet
+    pending: Vec<Pending>,
+    image: Snapshot,
+}
+
+impl Saver {
+    fn new(interp: &LuaInterpreter) -> Self {
+        let mut library = HashMap::new();
+        for (name, field) in library_paths() {
+            let value = lookup_path(interp, &name, field.as_deref());
+            if let Some(address) = value.and_then(|v| v.address()) {
+                let path = match field {
+                    Some(field) => format!("{}.{}", name, field),
+                    None => name,
+                };
+                library.entry(address).or_insert(path);
+            }
+        }
+        Saver {
+            library,
+            tables: HashMap::new(),
+            functions: HashMap::new(),
+            arenas: HashMap::new(),
+            cells: HashMap::new(),
+            pending: Vec::new(),
+            image: Snapshot {
+                version: VERSION,
+                globals: Vec::new(),
+                tables: Vec::new(),
+                functions: Vec::new(),
+                arenas: Vec::new(),
+                cells: Vec::new(),
+            },
+        }
+    }
+
+    fn value(&mut self, value: &LuaValue) -> LuaResult<Value> {
+        if let Some(path) = value.address().and_then(|a| self.library.get(&a)) {
+            return Ok(Value::Library(path.clone()));
+        }
+        Ok(match value {
+            LuaValue::Nil => Value::Nil,
+            LuaValue::Boolean(b) => Value::Boolean(*b),
+            LuaValue::Number(n) if n.is_finite() => Value::Number(*n),
+            LuaValue::Number(n) => Value::NonFinite(n.to_string()),
+            LuaValue::String(s) => Value::String(s.clone()),
+            LuaValue::Table(table) => Value::Table(self.table(table)),
+            LuaValue::Function(function) => Value::Function(self.function(function)?),
+            LuaValue::UserData(_) => {
+                return Err(LuaError::value("cannot save userdata in a snapshot"));
+            }
+            LuaValue::Thread(_) => {
+                return Err(LuaError::value("cannot save a coroutine in a snapshot"));
+            }
+        })
+    }
+
+    fn table(&mut self, table: &Rc<RefCell<LuaTable>>) -> usize {
+        let next = self.image.tables.len();
+        let id = *self
+            .tables
+            .entry(Rc::as_ptr(table) as usize)
+            .or_insert(next);
+        if id == next {
+            self.image.tables.push(TableImage::default());
+            self.pending.push(Pending::Table(Rc::clone(table), id));
+        }
+        id
+    }
+
+    fn cell(&mut self, cell: &UpvalueCell) -> usize {
+        let next = self.image.cells.len();
+        let id = *self.cells.entry(Rc::as_ptr(cell) as usize).or_insert(next);
+        if id == next {
+            self.image.cells.push(Value::Nil);
+            self.pending.push(Pending::Cell(Rc::clone(cell), id));
+        }
+        id
+    }
+
+    fn function(&mut self, function: &Rc<LuaFunction>) -> LuaResult<usize> {
+        let address = Rc::as_ptr(function) as usize;
+        if let Some(&id) = self.functions.get(&address) {
+            return Ok(id);
+        }
+        let LuaFunction::User { body, captured } = function.as_ref() else {
+            return Err(LuaError::value(
+                "cannot save a builtin function that is not part of the standard library",
+            ));
+        };
+        let next = self.image.arenas.len();
+        let arena = *self
+            .arenas
+            .entry(Rc::as_ptr(&body.arena) as usize)
+            .or_insert(next);
+        if arena == next {
+            self.image.arenas.push(body.arena.as_ref().clone());
+        }
+        let upvalues = captured
+            .upvalues
+            .iter()
+            .map(|upvalue| (upvalue.name.clone(), self.cell(&upvalue.cell)))
+            .collect();
+        let cells = captured.cells.iter().map(|cell| self.cell(cell)).collect();
+        let id = self.image.functions.len();
+        self.functions.insert(address, id);
+        self.image.functions.push(FunctionImage {
+            arena,
+            body: body.id,
+            upvalues,
+            cells,
+        });
+        Ok(id)
+    }
+
+    /// Write out everything numbered so far, numbering what that reaches
+    fn finish(&mut self) -> LuaResult<()> {
+        while let Some(pending) = self.pending.pop() {
+            match pending {
+                Pending::Cell(cell, id) => {
+                    let value = self.value(&cell.borrow())?;
+                    self.image.cells[id] = value;
+                }
+                Pending::Table(table, id) => {
+                    let table = table.borrow();
+                    let mut image = TableImage::default();
+                    for (key, value) in &table.data {
+                        image.entries.push((self.value(key)?, self.value(value)?));
+                    }
+                    if let Some(metatable) = &table.metatable {
+                        let metatable = LuaValue::Table(metatable.table());
+                        image.metatable = Some(self.value(&metatable)?);
+                    }
+                    self.image.tables[id] = image;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Empty tables, cells and finished closures for every index in a snapshot
+struct Loader {
+    tables: Vec<Rc<RefCell<LuaTable>>>,
+    functions: Vec<Rc<LuaFunction>>,
+    cells: Vec<UpvalueCell>,
+    /// Library paths resolved against the interpreter being restored into
+    library: HashMap<String, LuaValue>,
+}
+
+impl Loader {
+    fn new(snapshot: &Snapshot, interp: &LuaInterpreter) -> LuaResult<Self> {
+        let tables = (0..snapshot.tables.len())
+            .map(|_| {
+                Rc::new(RefCell::new(LuaTable {
+                    data: TableData::new(),
+                    metatable: None,
+                    frozen: false,
+                    removed: None,
+                }))
+            })
+            .collect();
+        let cells: Vec<UpvalueCell> = (0..snapshot.cells.len())
+            .map(|_| Rc::new(RefCell::new(LuaValue::Nil)))
+            .collect();
+        let arenas: Vec<Rc<LuaArena>> = snapshot.arenas.iter().cloned().map(Rc::new).collect();
+        let cell = |id: usize| {
+            cells
+                .get(id)
+                .cloned()
+                .ok_or_else(|| missing("upvalue cell", id))
+        };
+        let mut functions = Vec::with_capacity(snapshot.functions.len());
+        for image in &snapshot.functions {
+            let arena = arenas
+                .get(image.arena)
+                .cloned()
+                .ok_or_else(|| missing("arena", image.arena))?;
+            if image.body >= arena.function_count() {
+                return Err(missing("function body", image.body));
+            }
+            let body = FunctionRef {
+                arena,
+                id: image.body,
+            };
+            let mut captured = ClosureState::new();
+            for (name, id) in &image.upvalues {
+                captured
+                    .upvalues
+                    .push(Upvalue::new(name.clone(), cell(*id)?));
+            }
+            for id in &image.cells {
+                captured.cells.push(cell(*id)?);
+            }
+            functions.push(Rc::new(LuaFunction::User { body, captured }));
+        }
+        let mut library = HashMap::new();
+        for path in snapshot.library_paths() {
+            let (name, field) = match path.split_once('.') {
+                Some((name, field)) => (name, Some(field)),
+                None => (path, None),
+            };
+            let value = lookup_path(interp, name, field).ok_or_else(|| {
+                LuaError::value(format!(
+                    "snapshot refers to '{}', which is not defined",
+                    path
+                ))
+            })?;
+            library.insert(path.to_string(), value);
+        }
+        Ok(Loader {
+            tables,
+            functions,
+            cells,
+            library,
+        })
+    }
+
+    fn value(&self, value: &Value) -> LuaResult<LuaValue> {
+        Ok(match value {
+            Value::Nil => LuaValue::Nil,
+            Value::Boolean(b) => LuaValue::Boolean(*b),
+            Value::Number(n) => LuaValue::Number(*n),
+            Value::NonFinite(text) => match text.as_str() {
+                "inf" => LuaValue::Number(f64::INFINITY),
+                "-inf" => LuaValue::Number(f64::NEG_INFINITY),
+                "NaN" | "nan" => LuaValue::Number(f64::NAN),
+                _ => {
+                    return Err(LuaError::value(format!(
+                        "bad number '{}' in snapshot",
+                        text
+                    )))
+                }
+            },
+            Value::String(s) => LuaValue::String(s.clone()),
+            Value::Table(id) => LuaValue::Table(
+                self.tables
+                    .get(*id)
+                    .cloned()
+                    .ok_or_else(|| missing("table", *id))?,
+            ),
+            Value::Function(id) => LuaValue::Function(
+                self.functions
+                    .get(*id)
+                    .cloned()
+                    .ok_or_else(|| missing("function", *id))?,
+            ),
+            Value::Library(path) => self.library[path].clone(),
+        })
+    }
+}
+
+impl Snapshot {
+    /// Every library path the snapshot refers to
+    fn library_paths(&self) -> Vec<&str> {
+        let tables = self.tables.iter().flat_map(|table| {
+            let entries = table.entries.iter().flat_map(|(k, v)| [k, v]);
+            entries.chain(&table.metatable)
+        });
+        self.globals
+            .iter()
+            .map(|(_, value)| value)
+            .chain(&self.cells)
+            .chain(tables)
+            .filter_map(|value| match value {
+                Value::Library(path) => Some(path.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+fn missing(what: &str, id: usize) -> LuaError {
+    LuaError::value(format!("snapshot refers to missing {} {}", what, id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::{ControlFlow, Executor};
+    use crate::lua_parser::{parse, tokenize, TokenSlice};
+
+    fn run(interp: &mut LuaInterpreter, code: &str) -> LuaResult<Vec<LuaValue>> {
+        let tokens = tokenize(code).map_err(LuaError::value)?;
+        let (_, block) = parse(TokenSlice::from(tokens.as_slice()))
+            .map_err(|e| LuaError::value(e.to_string()))?;
+        match Executor::new().execute_block(&block, interp)? {
+            ControlFlow::Return(values) => Ok(values),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn round_trip(code: &str) -> LuaInterpreter {
+        let mut interp = LuaInterpreter::new();
+        run(&mut interp, code).unwrap();
+        let json = Snapshot::take(&interp).unwrap().to_json().unwrap();
+        let mut restored = LuaInterpreter::new();
+        Snapshot::from_json(&json)
+            .unwrap()
+            .restore(&mut restored)
+            .unwrap();
+        restored
+    }
+
+    #[test]
+    fn test_tables_keep_cycles_and_sharing() {
+        let mut interp = round_trip(
+            "node = {name = 'a', [1] = 2.5, [true] = 1/0}
+             node.self = node
+             shared = {}
+             pair = {shared, shared}",
+        );
+        let result = run(
+            &mut interp,
+            "return node.self == node, node.self.name, node[1], node[true],
+                    pair[1] == pair[2], shared == pair[1]",
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            vec![
+                LuaValue::Boolean(true),
+                LuaValue::String("a".into()),
+                LuaValue::Number(2.5),
+                LuaValue::Number(f64::INFINITY),
+                LuaValue::Boolean(true),
+                LuaValue::Boolean(true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_closures_share_their_upvalues() {
+        let mut interp = round_trip(
+            "local count = 10
+             function bump() count = count + 1 return count end
+             function peek() return count end
+             bump()",
+        );
+        let result = run(&mut interp, "bump() return peek()").unwrap();
+        assert_eq!(result, vec![LuaValue::Number(12.0)]);
+    }
+
+    #[test]
+    fn test_builtins_and_metatables_are_relinked() {
+        let mut interp = round_trip(
+            "up = string.upper
+             p = print
+             defaults = {hi = 'hello'}
+             v = setmetatable({}, {__index = defaults,
+                                   __concat = function(a, b) return b .. '!' end})",
+        );
+        let result = run(
+            &mut interp,
+            "return up == string.upper, p == print, v.hi, v .. 'hey'",
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            vec![
+                LuaValue::Boolean(true),
+                LuaValue::Boolean(true),
+                LuaValue::String("hello".into()),
+                LuaValue::String("hey!".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_file_round_trip_and_unsaveable_values() {
+        let path = std::env::temp_dir().join(format!("muscm_snapshot_{}.json", std::process::id()));
+        let mut interp = LuaInterpreter::new();
+        run(&mut interp, "score = 42 names = {'x', 'y'}").unwrap();
+        interp.save_snapshot(&path).unwrap();
+
+        let mut restored = LuaInterpreter::new();
+        restored.load_snapshot(&path).unwrap();
+        let result = run(&mut restored, "return score, #names, names[2]").unwrap();
+        assert_eq!(
+            result,
+            vec![
+                LuaValue::Number(42.0),
+                LuaValue::Number(2.0),
+                LuaValue::String("y".into()),
+            ]
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        interp.set_global(
+            "handle",
+            LuaValue::UserData(Rc::new(RefCell::new(Box::new(1u8)))),
+        );
+        assert!(Snapshot::take(&interp).is_err());
+        assert!(restored.load_snapshot(&path).is_err());
+    }
+}
@@ -0,0 +1,224 @@
+/// Which file a running Lua statement came from
+///
+/// A host hands each chunk it runs to `enter_chunk` with the file it was
+/// loaded from, and to `register` when it needs statements inside the
+/// chunk's functions attributed to that file even after the chunk has
+/// finished. A statement belongs to the file of the innermost running Lua
+/// function, or of the running chunk for top-level code. Coverage and the
+/// debugger keep one each.
+use crate::lua_parser::{
+    Block, BlockId, Chunk, Expression, FieldKey, FunctionId, LuaArena, Statement,
+};
+use crate::lua_value::{LuaFunction, LuaValue};
+use crate::stack::with_headroom;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Files of registered functions and running chunks
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    /// Arenas of registered chunks and the file each came from; every
+    /// function body in an arena was parsed from its file, and holding the
+    /// arenas keeps their addresses from being reused
+    arenas: HashMap<usize, (Rc<LuaArena>, Rc<str>)>,
+    /// Files of the chunks running, innermost last
+    chunks: Vec<Rc<str>>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remember that the functions defined in `chunk` come from `file`
+    pub fn register(&mut self, file: &Rc<str>, chunk: &Chunk) {
+        let key = Rc::as_ptr(&chunk.arena) as usize;
+        self.arenas
+            .insert(key, (Rc::clone(&chunk.arena), Rc::clone(file)));
+    }
+
+    /// The chunk loaded from `file` is about to run
+    pub fn enter_chunk(&mut self, file: Rc<str>) {
+        self.chunks.push(file);
+    }
+
+    /// The chunk from the matching `enter_chunk` has finished
+    pub fn leave_chunk(&mut self) {
+        self.chunks.pop();
+    }
+
+    /// File of a statement running inside `function`, or in the running
+    /// chunk's top-level code when that is `None` or a builtin; `None` for
+    /// a Lua function from a chunk that was never registered
+    pub fn file_of(&self, function: Option<&LuaValue>) -> Option<Rc<str>> {
+        if let Some(LuaValue::Function(f)) = function {
+            if let LuaFunction::User { body, .. } = f.as_ref() {
+                let key = Rc::as_ptr(&body.arena) as usize;
+                let (_, file) = self.arenas.get(&key)?;
+                return Some(Rc::clone(file));
+            }
+        }
+        self.chunks.last().cloned()
+    }
+}
+
+/// Visit the top-level block of `chunk` and every block nested in it,
+/// function bodies included
+pub(crate) fn walk_chunk(chunk: &Chunk, visit: &mut impl FnMut(&Block)) {
+    walk_block(&chunk.arena, chunk.root, visit);
+}
+
+fn walk_block<'a>(arena: &'a LuaArena, id: BlockId, visit: &mut impl FnMut(&'a Block)) {
+    let block = arena.block(id);
+    visit(block);
+    for statement in &block.statements {
+        with_headroom(|| walk_statement(arena, statement, visit));
+    }
+    if let Some(ret) = &block.return_statement {
+        for expr in &ret.expression_list {
+            walk_expression(arena, expr, visit);
+        }
+    }
+}
+
+fn walk_function<'a>(arena: &'a LuaArena, id: FunctionId, visit: &mut impl FnMut(&'a Block)) {
+    walk_block(arena, arena.function(id).block, visit);
+}
+
+fn walk_statement<'a>(
+    arena: &'a LuaArena,
+    statement: &'a Statement,
+    visit: &mut impl FnMut(&'a Block),
+) {
+    match statement {
+        Statement::Empty
+        | Statement::Break
+        | Statement::Label(_)
+        | Statement::Goto(_)
+        | Statement::LocalVars { values: None, .. }
+        | Statement::LocalSlots { values: None, .. } => {}
+        Statement::Assignment { variables, values } => {
+            for expr in variables.iter().chain(values) {
+                walk_expression(arena, expr, visit);
+            }
+        }
+        Statement::FunctionCall(expr) => walk_expression(arena, expr, visit),
+        Statement::Do(body) => walk_block(arena, *body, visit),
+        Statement::While { condition, body } | Statement::Repeat { body, condition } => {
+            walk_expression(arena, condition, visit);
+            walk_block(arena, *body, visit);
+        }
+        Statement::If {
+            condition,
+            then_block,
+            elseif_parts,
+            else_block,
+        } => {
+            walk_expression(arena, condition, visit);
+            walk_block(arena, *then_block, visit);
+            for (condition, block) in elseif_parts {
+                walk_expression(arena, condition, visit);
+                walk_block(arena, *block, visit);
+            }
+            if let Some(block) = else_block {
+                walk_block(arena, *block, visit);
+            }
+        }
+        Statement::ForNumeric {
+            start,
+            end,
+            step,
+            body,
+            ..
+        }
+        | Statement::ForNumericSlot {
+            start,
+            end,
+            step,
+            body,
+            ..
+        } => {
+            for expr in [start, end].into_iter().chain(step) {
+                walk_expression(arena, expr, visit);
+            }
+            walk_block(arena, *body, visit);
+        }
+        Statement::ForGeneric {
+            iterables, body, ..
+        }
+        | Statement::ForGenericSlots {
+            iterables, body, ..
+        } => {
+            for expr in iterables {
+                walk_expression(arena, expr, visit);
+            }
+            walk_block(arena, *body, visit);
+        }
+        Statement::FunctionDecl { body, .. } | Statement::LocalFunction { body, .. } => {
+            walk_function(arena, *body, visit)
+        }
+        Statement::LocalVars {
+            values: Some(values),
+            ..
+        }
+        | Statement::LocalSlots {
+            values: Some(values),
+            ..
+        } => {
+            for expr in values {
+                walk_expression(arena, expr, visit);
+            }
+        }
+    }
+}
+
+fn walk_expression<'a>(
+    arena: &'a LuaArena,
+    expr: &'a Expression,
+    visit: &mut impl FnMut(&'a Block),
+) {
+    with_headroom(|| match expr {
+        Expression::FunctionDef(body) => walk_function(arena, *body, visit),
+        Expression::BinaryOp { left, right, .. } => {
+            walk_expression(arena, left, visit);
+            walk_expression(arena, right, visit);
+        }
+        Expression::UnaryOp { operand, .. } | Expression::Paren(operand) => {
+            walk_expression(arena, operand, visit)
+        }
+        Expression::TableIndexing { object, index } => {
+            walk_expression(arena, object, visit);
+            walk_expression(arena, index, visit);
+        }
+        Expression::FieldAccess { object, .. } => walk_expression(arena, object, visit),
+        Expression::FunctionCall { function, args } => {
+            walk_expression(arena, function, visit);
+            for arg in args {
+                walk_expression(arena, arg, visit);
+            }
+        }
+        Expression::MethodCall { object, args, .. } => {
+            walk_expression(arena, object, visit);
+            for arg in args {
+                walk_expression(arena, arg, visit);
+            }
+        }
+        Expression::TableConstructor { fields } => {
+            for field in fields {
+                if let FieldKey::Bracket(key) = &field.key {
+                    walk_expression(arena, key, visit);
+                }
+                walk_expression(arena, &field.value, visit);
+            }
+        }
+        Expression::Nil
+        | Expression::Boolean(_)
+        | Expression::Number(_)
+        | Expression::String(_)
+        | Expression::Varargs
+        | Expression::Identifier(_)
+        | Expression::Local { .. }
+        | Expression::Upvalue { .. }
+        | Expression::Global(_) => {}
+    })
+}
@@ -0,0 +1,132 @@
+//! Stack headroom for recursive tree walks
+//!
+//! Parsing, resolving, compiling and evaluating Lua recurse once per level
+//! of nesting, so `((((...))))`, a long `..` chain or a deep Lua recursion
+//! can need more native stack than the thread has. Each recursive walk
+//! passes its per-node work through `with_headroom`, which moves onto a
+//! fresh heap-allocated stack segment when the current one runs low.
+//! Dropping a deep syntax tree or a long chain of tables recurses the same
+//! way, so their `Drop` impls check `running_low` first. Nesting is then
+//! limited by memory instead of aborting the process, and Lua recursion by
+//! `LuaInterpreter::max_call_depth`.
+//!
+//! Coroutines run on stacks of their own (see `coroutines`), so the end of
+//! the stack in use is tracked per thread here rather than by `stacker`,
+//! which only knows the thread's own stack and its segments. Code that
+//! switches stacks goes through `switching`, and a coroutine declares its
+//! stack with `enter` when it starts.
+
+/// Grow when less than this much stack is left; the most a walk uses
+/// between two calls, with room to spare for unoptimized builds
+const RED_ZONE: usize = 256 * 1024;
+
+/// Size of each new stack segment
+const SEGMENT: usize = 4 * 1024 * 1024;
+
+#[cfg(not(target_family = "wasm"))]
+mod native {
+    use super::SEGMENT;
+    use corosensei::stack::{DefaultStack, Stack};
+    use std::cell::Cell;
+
+    thread_local! {
+        /// Lowest address the stack in use may reach, when it is known;
+        /// starts as the end of the thread's own stack
+        static LIMIT: Cell<Option<usize>> = Cell::new(
+            stacker::remaining_stack().map(|left| stack_pointer() - left)
+        );
+    }
+
+    /// Roughly where the stack is now
+    #[inline(always)]
+    fn stack_pointer() -> usize {
+        let here = 0u8;
+        std::hint::black_box(&here) as *const u8 as usize
+    }
+
+    pub fn remaining() -> Option<usize> {
+        LIMIT
+            .with(Cell::get)
+            .map(|limit| stack_pointer().saturating_sub(limit))
+    }
+
+    pub fn grow<R>(f: impl FnOnce() -> R) -> R {
+        let stack = DefaultStack::new(SEGMENT).expect("out of memory for a stack segment");
+        let limit = stack.limit().get();
+        switching(|| {
+            corosensei::on_stack(stack, || {
+                enter(limit);
+                f()
+            })
+        })
+    }
+
+    pub fn enter(limit: usize) {
+        LIMIT.with(|cell| cell.set(Some(limit)));
+    }
+
+    /// Puts the saved limit back, also when a coroutine is unwound
+    struct Restore(Option<usize>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            LIMIT.with(|cell| cell.set(self.0));
+        }
+    }
+
+    pub fn switching<R>(switch: impl FnOnce() -> R) -> R {
+        let _restore = Restore(LIMIT.with(Cell::get));
+        switch()
+    }
+}
+
+/// `stacker` knows the one stack a WebAssembly module has
+#[cfg(target_family = "wasm")]
+mod wasm {
+    use super::SEGMENT;
+
+    pub fn remaining() -> Option<usize> {
+        stacker::remaining_stack()
+    }
+
+    pub fn grow<R>(f: impl FnOnce() -> R) -> R {
+        stacker::grow(SEGMENT, f)
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+use native as imp;
+#[cfg(target_family = "wasm")]
+use wasm as imp;
+
+/// Run `f`, first switching to a new stack segment if this one is nearly
+/// used up
+#[inline]
+pub fn with_headroom<R>(f: impl FnOnce() -> R) -> R {
+    if running_low() {
+        imp::grow(f)
+    } else {
+        f()
+    }
+}
+
+/// Whether the current stack segment is close enough to its end that the
+/// next level of a walk should go through `with_headroom`
+#[inline]
+pub fn running_low() -> bool {
+    imp::remaining().is_some_and(|left| left < RED_ZONE)
+}
+
+/// Declare the stack a coroutine has just started on, whose lowest usable
+/// address is `limit`
+#[cfg(not(target_family = "wasm"))]
+pub(crate) fn enter(limit: usize) {
+    native::enter(limit);
+}
+
+/// Run `switch`, which moves to another stack and comes back to this one,
+/// keeping track of where this one ends
+#[cfg(not(target_family = "wasm"))]
+pub(crate) fn switching<R>(switch: impl FnOnce() -> R) -> R {
+    native::switching(switch)
+}
@@ -0,0 +1,162 @@
+use super::validation;
+use crate::error_types::{LuaError, LuaResult};
+/// Core base-library functions: assert, select, unpack, the raw accessors
+/// and collectgarbage
+///
+/// Several of these return more than one value, so they are registered as
+/// `LuaFunction::MultiBuiltin`. The raw functions read and write a table's
+/// own entries without consulting its metatable.
+use crate::gc::CycleCollector;
+use crate::lua_value::LuaValue;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type MultiFn = Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>>;
+
+/// Create assert(v [, message, ...])
+/// Returns all of its arguments when v is truthy, raises message otherwise
+pub fn create_assert() -> MultiFn {
+    Rc::new(|args| {
+        validation::require_args("assert", &args, 1, None)?;
+        if args[0].is_truthy() {
+            return Ok(args);
+        }
+        let message = match args.get(1) {
+            None | Some(LuaValue::Nil) => "assertion failed!".to_string(),
+            Some(LuaValue::String(s)) => s.to_string(),
+            Some(other) => other.to_string(),
+        };
+        Err(LuaError::user(message, 1))
+    })
+}
+
+/// Create select(n, ...)
+/// Returns the arguments after position n (counting from the end when n is
+/// negative), or their count for select('#', ...)
+pub fn create_select() -> MultiFn {
+    Rc::new(|args| {
+        validation::require_args("select", &args, 1, None)?;
+        let rest = &args[1..];
+        if matches!(&args[0], LuaValue::String(s) if *s == "#") {
+            return Ok(vec![LuaValue::Number(rest.len() as f64)]);
+        }
+        let n = validation::get_integer("select", 0, &args[0])?;
+        let start = if n < 0 { rest.len() as i64 + n } else { n - 1 };
+        if n == 0 || start < 0 {
+            return Err(LuaError::value("select() index out of range"));
+        }
+        Ok(rest.iter().skip(start as usize).cloned().collect())
+    })
+}
+
+/// Create unpack(t [, i [, j]])
+/// Returns t[i], ..., t[j]; i defaults to 1 and j to the length of t
+pub fn create_unpack() -> MultiFn {
+    Rc::new(|args| {
+        validation::require_args("unpack", &args, 1, Some(3))?;
+        let table = validation::get_table("unpack", 0, &args[0])?;
+        let table = table.borrow();
+        let bound = |index: usize, default: i64| match args.get(index) {
+            None | Some(LuaValue::Nil) => Ok(default),
+            Some(value) => validation::get_integer("unpack", index, value),
+        };
+        let first = bound(1, 1)?;
+        let last = bound(2, table.border() as i64)?;
+        Ok((first..=last).map(|i| table.get_int(i)).collect())
+    })
+}
+
+/// Create rawget(t, k)
+pub fn create_rawget() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|args| {
+        validation::require_args("rawget", &args, 2, Some(2))?;
+        let table = validation::get_table("rawget", 0, &args[0])?;
+        let value = table.borrow().data.get(&args[1]).cloned();
+        Ok(value.unwrap_or(LuaValue::Nil))
+    })
+}
+
+/// Create rawset(t, k, v)
+/// Assigning nil removes the key; returns t
+pub fn create_rawset() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|args| {
+        validation::require_args("rawset", &args, 3, Some(3))?;
+        let table = validation::get_table("rawset", 0, &args[0])?;
+        match &args[1] {
+            LuaValue::Nil => return Err(LuaError::value("rawset() table index is nil")),
+            LuaValue::Number(n) if n.is_nan() => {
+                return Err(LuaError::value("rawset() table index is NaN"))
+            }
+            _ => {}
+        }
+        let mut table = table.borrow_mut();
+        table.check_writable()?;
+        table.set(args[1].clone(), args[2].clone());
+        Ok(args[0].clone())
+    })
+}
+
+/// Create rawequal(a, b)
+/// Compares without __eq: identity for tables, functions and userdata
+pub fn create_rawequal() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|args| {
+        validation::require_args("rawequal", &args, 2, Some(2))?;
+        Ok(LuaValue::Boolean(args[0] == args[1]))
+    })
+}
+
+/// Create rawlen(v)
+/// Length of a string in bytes or of a table's sequence, ignoring __len
+pub fn create_rawlen() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|args| {
+        validation::require_args("rawlen", &args, 1, Some(1))?;
+        let len = match &args[0] {
+            LuaValue::String(s) => s.len(),
+            LuaValue::Table(t) => t.borrow().border(),
+            other => {
+                return Err(LuaError::type_error(
+                    "table or string",
+                    other.type_name(),
+                    "rawlen",
+                ))
+            }
+        };
+        Ok(LuaValue::Number(len as f64))
+    })
+}
+
+/// Create collectgarbage([opt])
+/// Runs or controls the cycle collector. "collect" (the default) returns
+/// the number of tables it freed, where PUC-Lua returns 0; "count" returns
+/// an estimate of the memory tables use, in kilobytes
+pub fn create_collectgarbage(gc: Rc<RefCell<CycleCollector>>) -> MultiFn {
+    Rc::new(move |args| {
+        let option = match args.first() {
+            None | Some(LuaValue::Nil) => "collect".to_string(),
+            Some(option) => validation::get_string("collectgarbage", 0, option)?,
+        };
+        let mut gc = gc.borrow_mut();
+        let result = match option.as_str() {
+            "collect" => LuaValue::Number(gc.collect() as f64),
+            "step" => {
+                gc.collect();
+                LuaValue::Boolean(true)
+            }
+            "count" => LuaValue::Number(gc.estimated_bytes() as f64 / 1024.0),
+            "isrunning" => LuaValue::Boolean(gc.automatic),
+            "stop" | "restart" => {
+                gc.automatic = option == "restart";
+                LuaValue::Number(0.0)
+            }
+            // The only mode there is
+            "incremental" | "generational" => LuaValue::String("incremental".into()),
+            other => {
+                return Err(LuaError::value(format!(
+                    "collectgarbage() invalid option '{}'",
+                    other
+                )))
+            }
+        };
+        Ok(vec![result])
+    })
+}
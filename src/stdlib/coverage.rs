@@ -0,0 +1,86 @@
+use super::validation;
+use crate::coverage::Coverage;
+use crate::error_types::LuaResult;
+/// The coverage library: coverage.start, coverage.stop, coverage.report
+///
+/// Scripts share the interpreter's `Coverage`, which only records once the
+/// host has enabled it (`muscm run --coverage`); `start` and `stop` pause
+/// and resume recording around code that should not count. A script cannot
+/// turn coverage on itself, since the chunks that ran before would be
+/// missing from the report, so without `--coverage` `start` returns nil
+/// and a message saying so.
+use crate::lua_value::{LuaFunction, LuaTable, LuaValue, TableData};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type CoverageSlot = Rc<RefCell<Coverage>>;
+
+/// Create coverage.start(), which resumes recording and returns true, or
+/// nil and a message when coverage is not enabled
+pub fn create_coverage_start(
+    coverage: CoverageSlot,
+) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
+    Rc::new(move |args| {
+        validation::require_args("coverage.start", &args, 0, Some(0))?;
+        if !coverage.borrow_mut().start() {
+            return Ok(vec![
+                LuaValue::Nil,
+                LuaValue::String("coverage is not enabled (run the script with --coverage)".into()),
+            ]);
+        }
+        Ok(vec![LuaValue::Boolean(true)])
+    })
+}
+
+/// Create coverage.stop(), which pauses recording
+pub fn create_coverage_stop(
+    coverage: CoverageSlot,
+) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(move |args| {
+        validation::require_args("coverage.stop", &args, 0, Some(0))?;
+        coverage.borrow_mut().stop();
+        Ok(LuaValue::Nil)
+    })
+}
+
+/// Create coverage.report(), which returns the lines recorded so far as an
+/// lcov tracefile
+pub fn create_coverage_report(
+    coverage: CoverageSlot,
+) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(move |args| {
+        validation::require_args("coverage.report", &args, 0, Some(0))?;
+        Ok(LuaValue::String(coverage.borrow().lcov().into()))
+    })
+}
+
+/// Create the coverage table
+pub fn create_coverage_table(coverage: CoverageSlot) -> LuaValue {
+    let mut table = TableData::new();
+    let functions = [
+        (
+            "start",
+            LuaFunction::MultiBuiltin(create_coverage_start(Rc::clone(&coverage))),
+        ),
+        (
+            "stop",
+            LuaFunction::Builtin(create_coverage_stop(Rc::clone(&coverage))),
+        ),
+        (
+            "report",
+            LuaFunction::Builtin(create_coverage_report(coverage)),
+        ),
+    ];
+    for (name, function) in functions {
+        table.insert(
+            LuaValue::String(name.into()),
+            LuaValue::Function(Rc::new(function)),
+        );
+    }
+    LuaValue::Table(Rc::new(RefCell::new(LuaTable {
+        data: table,
+        metatable: None,
+        frozen: false,
+        removed: None,
+    })))
+}
@@ -0,0 +1,440 @@
+use super::validation;
+use crate::error_types::{LuaError, LuaResult};
+use crate::hooks::{Hook, HookFunction, HookMask};
+/// The debug library: hooks, stack tracebacks and introspection
+///
+/// `debug.sethook` shares its slot with `LuaInterpreter::set_hook`, so a
+/// script's hook replaces the host's and the other way around.
+/// `debug.traceback`, `debug.getinfo` and `debug.getlocal` read the call
+/// stack the executor maintains. A Lua function's `source` is the name of
+/// the chunk it was loaded from (`@path` for a file), or `"=?"` for a
+/// chunk run without one.
+use crate::lua_interpreter::LuaInterpreter;
+use crate::lua_value::{LuaFunction, LuaTable, LuaValue, TableData};
+use crate::traceback::{CallTrace, StackLevel, TraceFrame};
+use crate::upvalues::UpvalueCell;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type HookSlot = Rc<RefCell<Option<Hook>>>;
+
+/// Create debug.sethook([hook, mask [, count]])
+/// Installs hook for the events in mask ("c", "r", "l") and every count
+/// statements; with no hook, removes the current one
+pub fn create_debug_sethook(slot: HookSlot) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(move |args| {
+        validation::require_args("debug.sethook", &args, 0, Some(3))?;
+        let function = match args.first() {
+            None | Some(LuaValue::Nil) => {
+                *slot.borrow_mut() = None;
+                return Ok(LuaValue::Nil);
+            }
+            Some(f @ LuaValue::Function(_)) => f.clone(),
+            Some(other) => {
+                return Err(LuaError::type_error(
+                    "function",
+                    other.type_name(),
+                    "debug.sethook",
+                ))
+            }
+        };
+        let mask = match args.get(1) {
+            None | Some(LuaValue::Nil) => HookMask::default(),
+            Some(mask) => HookMask::parse(&validation::get_string("debug.sethook", 1, mask)?),
+        };
+        let count = match args.get(2) {
+            None | Some(LuaValue::Nil) => 0,
+            Some(count) => validation::get_integer("debug.sethook", 2, count)?.max(0) as u64,
+        };
+        *slot.borrow_mut() = Some(Hook::new(HookFunction::Lua(function), mask, count));
+        Ok(LuaValue::Nil)
+    })
+}
+
+/// Create debug.gethook()
+/// Returns the hook function, its mask and its count, or nil when no hook
+/// is set; a hook installed by the host shows as "external hook"
+pub fn create_debug_gethook(
+    slot: HookSlot,
+) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
+    Rc::new(move |_args| {
+        let Some(hook) = &*slot.borrow() else {
+            return Ok(vec![LuaValue::Nil]);
+        };
+        let function = match &hook.function {
+            HookFunction::Lua(function) => function.clone(),
+            HookFunction::Host(_) => LuaValue::String("external hook".into()),
+        };
+        Ok(vec![
+            function,
+            LuaValue::String(hook.mask.as_string().into()),
+            LuaValue::Number(hook.count as f64),
+        ])
+    })
+}
+
+/// Create debug.traceback([message [, level]])
+/// Returns message followed by the stack traceback, starting `level` calls
+/// up (1, the default, is the caller); a message that is neither a string
+/// nor nil is returned unchanged
+pub fn create_debug_traceback(
+    trace: Rc<RefCell<CallTrace>>,
+) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(move |args| {
+        validation::require_args("debug.traceback", &args, 0, Some(2))?;
+        let message = match args.first() {
+            None | Some(LuaValue::Nil) => None,
+            Some(LuaValue::String(s)) => Some(s.clone()),
+            Some(n @ LuaValue::Number(_)) => Some(n.to_string_value().into()),
+            Some(other) => return Ok(other.clone()),
+        };
+        let level = match args.get(1) {
+            None | Some(LuaValue::Nil) => 1,
+            Some(level) => validation::get_integer("debug.traceback", 1, level)?.max(0) as usize,
+        };
+        let traceback = trace.borrow().traceback(level);
+        Ok(LuaValue::String(match message {
+            Some(message) => format!("{}\n{}", message, traceback).into(),
+            None => traceback.into(),
+        }))
+    })
+}
+
+fn new_table(fields: TableData) -> LuaValue {
+    LuaValue::Table(Rc::new(RefCell::new(LuaTable {
+        data: fields,
+        metatable: None,
+        frozen: false,
+        removed: None,
+    })))
+}
+
+fn set_field(fields: &mut TableData, key: &str, value: LuaValue) {
+    fields.insert(LuaValue::String(key.into()), value);
+}
+
+/// Set `source` and `short_src` for a chunk named `source`
+fn set_source(fields: &mut TableData, source: Option<&Rc<str>>) {
+    let source = source.map_or("=?", |source| source);
+    let short = match source.strip_prefix(['@', '=']) {
+        Some(name) => name.to_string(),
+        None => format!("[string \"{}\"]", source.lines().next().unwrap_or("")),
+    };
+    set_field(fields, "source", LuaValue::String(source.into()));
+    set_field(fields, "short_src", LuaValue::String(short.into()));
+}
+
+/// The fields `debug.getinfo` reports for a function value
+fn function_info(function: &LuaValue) -> TableData {
+    let mut fields = TableData::new();
+    let number = |n: i64| LuaValue::Number(n as f64);
+    let user = match function {
+        LuaValue::Function(f) => match f.as_ref() {
+            LuaFunction::User { body, .. } => Some((
+                body.params.len(),
+                body.varargs,
+                body.lines,
+                body.arena.source(),
+            )),
+            _ => None,
+        },
+        _ => None,
+    };
+    if let Some((nparams, varargs, lines, source)) = user {
+        let (first, last) = match lines {
+            Some((first, last)) => (first as i64, last as i64),
+            None => (-1, -1),
+        };
+        set_field(&mut fields, "what", LuaValue::String("Lua".into()));
+        set_source(&mut fields, source);
+        set_field(&mut fields, "linedefined", number(first));
+        set_field(&mut fields, "lastlinedefined", number(last));
+        set_field(&mut fields, "nparams", number(nparams as i64));
+        set_field(&mut fields, "isvararg", LuaValue::Boolean(varargs));
+    } else {
+        set_field(&mut fields, "what", LuaValue::String("C".into()));
+        set_field(&mut fields, "source", LuaValue::String("=[C]".into()));
+        set_field(&mut fields, "short_src", LuaValue::String("[C]".into()));
+        set_field(&mut fields, "linedefined", number(-1));
+        set_field(&mut fields, "lastlinedefined", number(-1));
+        set_field(&mut fields, "nparams", number(0));
+        set_field(&mut fields, "isvararg", LuaValue::Boolean(true));
+    }
+    set_field(&mut fields, "nups", number(upvalues(function).len() as i64));
+    set_field(&mut fields, "func", function.clone());
+    fields
+}
+
+/// The fields `debug.getinfo` reports for a running call
+fn frame_info(frame: &TraceFrame) -> TableData {
+    let mut fields = function_info(&frame.function);
+    let line = match frame.line {
+        _ if frame.is_builtin() => -1.0,
+        0 => -1.0,
+        line => line as f64,
+    };
+    set_field(&mut fields, "currentline", LuaValue::Number(line));
+    if let Some(name) = &frame.name {
+        set_field(
+            &mut fields,
+            "name",
+            LuaValue::String(name.name().clone().into()),
+        );
+        set_field(
+            &mut fields,
+            "namewhat",
+            LuaValue::String(name.kind().into()),
+        );
+    } else {
+        set_field(&mut fields, "namewhat", LuaValue::String("".into()));
+    }
+    fields
+}
+
+/// The fields `debug.getinfo` reports for the main chunk
+fn main_info(trace: &CallTrace) -> TableData {
+    let mut fields = TableData::new();
+    let line = match trace.chunk_line() {
+        0 => -1.0,
+        line => line as f64,
+    };
+    set_field(&mut fields, "what", LuaValue::String("main".into()));
+    set_source(&mut fields, trace.chunk_source());
+    set_field(&mut fields, "linedefined", LuaValue::Number(0.0));
+    set_field(&mut fields, "lastlinedefined", LuaValue::Number(-1.0));
+    set_field(&mut fields, "nparams", LuaValue::Number(0.0));
+    set_field(&mut fields, "isvararg", LuaValue::Boolean(true));
+    set_field(&mut fields, "nups", LuaValue::Number(0.0));
+    set_field(&mut fields, "currentline", LuaValue::Number(line));
+    set_field(&mut fields, "namewhat", LuaValue::String("".into()));
+    fields
+}
+
+/// Create debug.getinfo(f | level [, what])
+/// Returns a table describing a function, or the call running `level`
+/// calls up (0 is getinfo itself, 1 its caller); nil past the main chunk.
+/// Every field is filled in whatever `what` asks for
+pub fn create_debug_getinfo(
+    trace: Rc<RefCell<CallTrace>>,
+) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(move |args| {
+        validation::require_args("debug.getinfo", &args, 1, Some(2))?;
+        if let Some(what) = args.get(1).filter(|what| !matches!(what, LuaValue::Nil)) {
+            validation::get_string("debug.getinfo", 1, what)?;
+        }
+        if let LuaValue::Function(_) = &args[0] {
+            return Ok(new_table(function_info(&args[0])));
+        }
+        let level = validation::get_integer("debug.getinfo", 0, &args[0])?;
+        let trace = trace.borrow();
+        let fields = match usize::try_from(level).ok().and_then(|l| trace.level(l)) {
+            Some(StackLevel::Call(frame)) => frame_info(frame),
+            Some(StackLevel::Main) => main_info(&trace),
+            None => return Ok(LuaValue::Nil),
+        };
+        Ok(new_table(fields))
+    })
+}
+
+/// The upvalues of a function with their names, in `debug.getupvalue`
+/// order: those of a resolved body first, then those captured by name
+fn upvalues(function: &LuaValue) -> Vec<(Rc<str>, UpvalueCell)> {
+    let LuaValue::Function(f) = function else {
+        return Vec::new();
+    };
+    let LuaFunction::User { body, captured } = f.as_ref() else {
+        return Vec::new();
+    };
+    let names = body
+        .layout
+        .iter()
+        .flat_map(|layout| layout.capture_names.iter());
+    let resolved = names.cloned().zip(captured.cells.iter().cloned());
+    let by_name = captured
+        .upvalues
+        .iter()
+        .map(|upvalue| (upvalue.name.as_str().into(), upvalue.cell.clone()));
+    resolved.chain(by_name).collect()
+}
+
+/// The `n`th upvalue of `args[0]`, for getupvalue and setupvalue
+fn nth_upvalue(name: &str, args: &[LuaValue]) -> LuaResult<Option<(Rc<str>, UpvalueCell)>> {
+    if !matches!(&args[0], LuaValue::Function(_)) {
+        return Err(LuaError::type_error("function", args[0].type_name(), name));
+    }
+    let n = validation::get_integer(name, 1, &args[1])?;
+    let index = usize::try_from(n).ok().and_then(|n| n.checked_sub(1));
+    Ok(index.and_then(|i| upvalues(&args[0]).into_iter().nth(i)))
+}
+
+/// Create debug.getupvalue(f, n)
+/// Returns the name and value of f's nth upvalue, or nothing when it has
+/// no such upvalue
+pub fn create_debug_getupvalue() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
+    Rc::new(|args| {
+        validation::require_args("debug.getupvalue", &args, 2, Some(2))?;
+        Ok(match nth_upvalue("debug.getupvalue", &args)? {
+            Some((name, cell)) => vec![LuaValue::String(name.into()), cell.borrow().clone()],
+            None => Vec::new(),
+        })
+    })
+}
+
+/// Create debug.setupvalue(f, n, v)
+/// Assigns v to f's nth upvalue and returns its name, or nothing when f
+/// has no such upvalue
+pub fn create_debug_setupvalue() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
+    Rc::new(|args| {
+        validation::require_args("debug.setupvalue", &args, 3, Some(3))?;
+        Ok(match nth_upvalue("debug.setupvalue", &args)? {
+            Some((name, cell)) => {
+                *cell.borrow_mut() = args[2].clone();
+                vec![LuaValue::String(name.into())]
+            }
+            None => Vec::new(),
+        })
+    })
+}
+
+/// Name of parameter `n` of a function, as `debug.getlocal(f, n)` reports
+/// it, or nil when there is none
+fn parameter_name(function: &LuaValue, n: i64) -> Vec<LuaValue> {
+    let name = match function {
+        LuaValue::Function(f) => match f.as_ref() {
+            LuaFunction::User { body, .. } => usize::try_from(n)
+                .ok()
+                .and_then(|n| body.params.get(n.checked_sub(1)?)),
+            _ => None,
+        },
+        _ => None,
+    };
+    vec![name.map_or(LuaValue::Nil, |param| {
+        LuaValue::String(param.as_str().into())
+    })]
+}
+
+/// Create debug.getlocal(f | level, n)
+/// With a function, returns the name of its nth parameter. Stack levels
+/// need the interpreter's frames, so the executor runs those calls itself
+/// through `getlocal_at`
+pub fn create_debug_getlocal() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
+    Rc::new(|args| {
+        validation::require_args("debug.getlocal", &args, 2, Some(2))?;
+        let n = validation::get_integer("debug.getlocal", 1, &args[1])?;
+        match &args[0] {
+            function @ LuaValue::Function(_) => Ok(parameter_name(function, n)),
+            _ => Err(LuaError::runtime(
+                "stack levels are only available to running scripts",
+                "debug.getlocal",
+            )),
+        }
+    })
+}
+
+/// Run debug.getlocal(f | level, n) for the executor
+///
+/// Returns the name and value of local `n` of the call `level` calls up
+/// (1 is getlocal's caller), or nil when there is no such local.
+/// Locals are read from resolved functions' slots, in slot order; a slot
+/// that sibling blocks share goes by the first local declared in it.
+/// Functions running without slots only show their parameters, and only
+/// at level 1, and locals of the main chunk are not visible.
+pub fn getlocal_at(args: &[LuaValue], interp: &LuaInterpreter) -> LuaResult<Vec<LuaValue>> {
+    validation::require_args("debug.getlocal", args, 2, Some(2))?;
+    let n = validation::get_integer("debug.getlocal", 1, &args[1])?;
+    if let function @ LuaValue::Function(_) = &args[0] {
+        return Ok(parameter_name(function, n));
+    }
+    let level = validation::get_integer("debug.getlocal", 0, &args[0])?;
+    let trace = interp.trace.borrow();
+    let frame = match usize::try_from(level).ok().and_then(|l| trace.level(l)) {
+        Some(StackLevel::Call(frame)) => frame,
+        Some(StackLevel::Main) => return Ok(vec![LuaValue::Nil]),
+        None => return Err(LuaError::value("debug.getlocal() level out of range")),
+    };
+    Ok(match local_at(frame, level, n, interp) {
+        Some((name, value)) => vec![LuaValue::String(name.into()), value],
+        None => vec![LuaValue::Nil],
+    })
+}
+
+/// Name and value of local `n` of the call `frame`, `level` calls up
+fn local_at(
+    frame: &TraceFrame,
+    level: i64,
+    n: i64,
+    interp: &LuaInterpreter,
+) -> Option<(Rc<str>, LuaValue)> {
+    let index = usize::try_from(n).ok()?.checked_sub(1)?;
+    let LuaValue::Function(f) = &frame.function else {
+        return None;
+    };
+    let LuaFunction::User { body, .. } = f.as_ref() else {
+        return None;
+    };
+    match (&body.layout, frame.slot_frame) {
+        (Some(layout), Some(slot_frame)) => layout.slot_names.get(index).and_then(|name| {
+            let cell = interp.frames.get(slot_frame)?.slots.get(index)?;
+            Some((name.clone(), cell.borrow().clone()))
+        }),
+        (None, _) if level == 1 => body.params.get(index).map(|param| {
+            let value = interp.lookup(param).unwrap_or(LuaValue::Nil);
+            (param.as_str().into(), value)
+        }),
+        _ => None,
+    }
+}
+
+/// Create the debug table
+pub fn create_debug_table(
+    hook: HookSlot,
+    trace: Rc<RefCell<CallTrace>>,
+    getlocal: Rc<LuaFunction>,
+) -> LuaValue {
+    let mut debug_table = TableData::new();
+    debug_table.insert(
+        LuaValue::String("sethook".into()),
+        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_debug_sethook(
+            Rc::clone(&hook),
+        )))),
+    );
+    debug_table.insert(
+        LuaValue::String("gethook".into()),
+        LuaValue::Function(Rc::new(LuaFunction::MultiBuiltin(create_debug_gethook(
+            hook,
+        )))),
+    );
+    debug_table.insert(
+        LuaValue::String("traceback".into()),
+        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_debug_traceback(
+            Rc::clone(&trace),
+        )))),
+    );
+    debug_table.insert(
+        LuaValue::String("getinfo".into()),
+        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_debug_getinfo(trace)))),
+    );
+    debug_table.insert(
+        LuaValue::String("getlocal".into()),
+        LuaValue::Function(getlocal),
+    );
+    debug_table.insert(
+        LuaValue::String("getupvalue".into()),
+        LuaValue::Function(Rc::new(
+            LuaFunction::MultiBuiltin(create_debug_getupvalue()),
+        )),
+    );
+    debug_table.insert(
+        LuaValue::String("setupvalue".into()),
+        LuaValue::Function(Rc::new(
+            LuaFunction::MultiBuiltin(create_debug_setupvalue()),
+        )),
+    );
+
+    LuaValue::Table(Rc::new(RefCell::new(LuaTable {
+        data: debug_table,
+        metatable: None,
+        frozen: false,
+        removed: None,
+    })))
+}
@@ -0,0 +1,452 @@
+//! `inspect(value [, options])`: a readable dump of any value
+//!
+//! Modelled on kikito's inspect.lua. Strings are quoted, tables are shown
+//! with their sequence on the opening line and their other keys one per
+//! line in a stable order (numbers, booleans, strings, then the rest), and
+//! a table with a metatable ends with a `<metatable>` field. Tables,
+//! functions and userdata get numbers in the order they are met; a table
+//! reached more than once is labelled `<1>` where it is shown in full and
+//! appears as `<table 1>` everywhere else, so cycles end.
+//!
+//! Options are a table with `depth` (levels of tables shown before `{...}`),
+//! `indent` (default two spaces) and `newline` (default `"\n"`).
+
+use super::validation;
+use crate::error_types::LuaResult;
+use crate::lua_parser::KEYWORDS;
+use crate::lua_string::LuaString;
+use crate::lua_value::{LuaTable, LuaValue};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// How `inspect` lays out its dump
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InspectOptions {
+    /// Levels of nested tables shown before the rest is cut to `{...}`
+    pub depth: usize,
+    pub indent: String,
+    pub newline: String,
+}
+
+impl Default for InspectOptions {
+    fn default() -> Self {
+        InspectOptions {
+            depth: usize::MAX,
+            indent: "  ".to_string(),
+            newline: "\n".to_string(),
+        }
+    }
+}
+
+impl InspectOptions {
+    fn from_lua(value: Option<&LuaValue>) -> LuaResult<Self> {
+        let table = match value {
+            None | Some(LuaValue::Nil) => return Ok(Self::default()),
+            Some(value) => validation::get_table("inspect", 2, value)?,
+        };
+        let table = table.borrow();
+        let get = |key: &str| {
+            table
+                .data
+                .get(&LuaValue::String(key.into()))
+                .filter(|value| !matches!(value, LuaValue::Nil))
+        };
+        let mut options = Self::default();
+        if let Some(depth) = get("depth") {
+            let depth = validation::get_number("inspect", 2, depth)?;
+            // `math.huge` is the usual way to say no limit
+            options.depth = if depth >= usize::MAX as f64 {
+                usize::MAX
+            } else {
+                depth.max(0.0) as usize
+            };
+        }
+        if let Some(indent) = get("indent") {
+            options.indent = validation::get_string("inspect", 2, indent)?;
+        }
+        if let Some(newline) = get("newline") {
+            options.newline = validation::get_string("inspect", 2, newline)?;
+        }
+        Ok(options)
+    }
+}
+
+/// Dump `value` as `inspect` shows it
+pub fn inspect(value: &LuaValue, options: &InspectOptions) -> String {
+    let mut inspector = Inspector {
+        options,
+        references: HashMap::new(),
+        ids: HashMap::new(),
+        next_id: [1; 4],
+        shown: HashSet::new(),
+        level: 0,
+        out: String::new(),
+    };
+    inspector.count(value);
+    inspector.value(value);
+    inspector.out
+}
+
+/// Create the `inspect` global
+pub fn create_inspect() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|args| {
+        let options = InspectOptions::from_lua(args.get(1))?;
+        let value = args.first().unwrap_or(&LuaValue::Nil);
+        Ok(LuaValue::String(inspect(value, &options).into()))
+    })
+}
+
+/// Numbering is kept apart for each kind of reference value
+fn kind(value: &LuaValue) -> Option<usize> {
+    match value {
+        LuaValue::Table(_) => Some(0),
+        LuaValue::Function(_) => Some(1),
+        LuaValue::UserData(_) => Some(2),
+        LuaValue::Thread(_) => Some(3),
+        _ => None,
+    }
+}
+
+/// Where keys of each type sort among the others
+fn rank(value: &LuaValue) -> u8 {
+    match value {
+        LuaValue::Number(_) => 0,
+        LuaValue::Boolean(_) => 1,
+        LuaValue::String(_) => 2,
+        LuaValue::Table(_) => 3,
+        LuaValue::Function(_) => 4,
+        LuaValue::UserData(_) => 5,
+        LuaValue::Thread(_) => 6,
+        LuaValue::Nil => 7,
+    }
+}
+
+fn compare_keys(a: &LuaValue, b: &LuaValue) -> Ordering {
+    rank(a).cmp(&rank(b)).then_with(|| match (a, b) {
+        (LuaValue::Number(x), LuaValue::Number(y)) => x.total_cmp(y),
+        (LuaValue::Boolean(x), LuaValue::Boolean(y)) => x.cmp(y),
+        (LuaValue::String(x), LuaValue::String(y)) => x.cmp(y),
+        _ => a.address().cmp(&b.address()),
+    })
+}
+
+/// Whether `key` can be written bare, as in `key = value`
+fn is_identifier(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !KEYWORDS.contains_key(key)
+}
+
+/// A string in double quotes, escaped so it reads back as the same value
+fn quote(s: &LuaString) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    let mut chars = s.chars_or_bytes().peekable();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            Ok(c) => c,
+            Err(byte) => {
+                out.push_str(&format!("\\{:03}", byte));
+                continue;
+            }
+        };
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_ascii_control() => {
+                // A following digit would otherwise extend the escape
+                if matches!(chars.peek(), Some(Ok(c)) if c.is_ascii_digit()) {
+                    out.push_str(&format!("\\{:03}", c as u32));
+                } else {
+                    out.push_str(&format!("\\{}", c as u32));
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The entries of a table split into its sequence 1..n and the rest, sorted
+fn entries(table: &LuaTable) -> (Vec<LuaValue>, Vec<(LuaValue, LuaValue)>) {
+    let mut sequence = Vec::new();
+    while let Some(value) = table
+        .data
+        .get(&LuaValue::Number((sequence.len() + 1) as f64))
+        .filter(|value| !matches!(value, LuaValue::Nil))
+    {
+        sequence.push(value.clone());
+    }
+    let mut rest: Vec<(LuaValue, LuaValue)> = table
+        .data
+        .iter()
+        .filter(|(key, value)| {
+            !matches!(value, LuaValue::Nil)
+                && !matches!(key, LuaValue::Number(n)
+                    if n.fract() == 0.0 && *n >= 1.0 && *n <= sequence.len() as f64)
+        })
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    rest.sort_by(|a, b| compare_keys(&a.0, &b.0));
+    (sequence, rest)
+}
+
+/// A metatable's fields as sorted entries
+fn metatable_entries(table: &LuaTable) -> Option<Vec<(LuaValue, LuaValue)>> {
+    let metatable = table.metatable.as_ref()?.table();
+    let mut fields: Vec<(LuaValue, LuaValue)> = metatable
+        .borrow()
+        .data
+        .iter()
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    fields.sort_by(|a, b| compare_keys(&a.0, &b.0));
+    Some(fields)
+}
+
+struct Inspector<'a> {
+    options: &'a InspectOptions,
+    /// How many times each table is reached, by address
+    references: HashMap<usize, usize>,
+    /// Number given to each table, function and userdata, by address
+    ids: HashMap<usize, usize>,
+    /// Next number for each kind
+    next_id: [usize; 4],
+    /// Tables already shown in full, or being shown
+    shown: HashSet<usize>,
+    level: usize,
+    out: String,
+}
+
+impl Inspector<'_> {
+    /// Count the references to every table reachable from `value`
+    fn count(&mut self, value: &LuaValue) {
+        let LuaValue::Table(table) = value else {
+            return;
+        };
+        let count = self
+            .references
+            .entry(Rc::as_ptr(table) as usize)
+            .or_insert(0);
+        *count += 1;
+        if *count > 1 {
+            return;
+        }
+        let table = table.borrow();
+        for (key, value) in &table.data {
+            self.count(key);
+            self.count(value);
+        }
+        if let Some(metatable) = &table.metatable {
+            let metatable = metatable.table();
+            for value in metatable.borrow().data.values() {
+                self.count(value);
+            }
+        }
+    }
+
+    fn id(&mut self, value: &LuaValue) -> usize {
+        let (Some(kind), Some(address)) = (kind(value), value.address()) else {
+            return 0;
+        };
+        *self.ids.entry(address).or_insert_with(|| {
+            let id = self.next_id[kind];
+            self.next_id[kind] += 1;
+            id
+        })
+    }
+
+    fn newline(&mut self) {
+        self.out.push_str(&self.options.newline);
+        for _ in 0..self.level {
+            self.out.push_str(&self.options.indent);
+        }
+    }
+
+    fn value(&mut self, value: &LuaValue) {
+        match value {
+            LuaValue::String(s) => self.out.push_str(&quote(s)),
+            LuaValue::Table(table) => crate::stack::with_headroom(|| self.table(table)),
+            LuaValue::Function(_) | LuaValue::UserData(_) | LuaValue::Thread(_) => {
+                let id = self.id(value);
+                self.out
+                    .push_str(&format!("<{} {}>", value.type_name(), id));
+            }
+            other => self.out.push_str(&other.to_string()),
+        }
+    }
+
+    fn table(&mut self, table: &Rc<RefCell<LuaTable>>) {
+        let address = Rc::as_ptr(table) as usize;
+        let value = LuaValue::Table(table.clone());
+        if self.shown.contains(&address) {
+            let id = self.id(&value);
+            self.out.push_str(&format!("<table {}>", id));
+            return;
+        }
+        if self.level >= self.options.depth {
+            self.out.push_str("{...}");
+            return;
+        }
+        self.shown.insert(address);
+        if self.references.get(&address).is_some_and(|&n| n > 1) {
+            let id = self.id(&value);
+            self.out.push_str(&format!("<{}>", id));
+        }
+
+        let (sequence, rest, metatable) = {
+            let table = table.borrow();
+            let (sequence, rest) = entries(&table);
+            (sequence, rest, metatable_entries(&table))
+        };
+        if sequence.is_empty() && rest.is_empty() && metatable.is_none() {
+            self.out.push_str("{}");
+            return;
+        }
+
+        self.level += 1;
+        self.out.push('{');
+        for (i, item) in sequence.iter().enumerate() {
+            self.out.push_str(if i == 0 { " " } else { ", " });
+            self.value(item);
+        }
+        let mut first = sequence.is_empty();
+        for (key, item) in &rest {
+            self.field_start(&mut first);
+            self.key(key);
+            self.out.push_str(" = ");
+            self.value(item);
+        }
+        let keyed = !rest.is_empty() || metatable.is_some();
+        if let Some(fields) = metatable {
+            self.field_start(&mut first);
+            self.out.push_str("<metatable> = ");
+            self.fields(&fields);
+        }
+        self.level -= 1;
+        // A plain sequence closes on the line it opened on
+        if keyed {
+            self.newline();
+            self.out.push('}');
+        } else {
+            self.out.push_str(" }");
+        }
+    }
+
+    /// A metatable, shown as a table of its fields
+    fn fields(&mut self, fields: &[(LuaValue, LuaValue)]) {
+        if fields.is_empty() {
+            self.out.push_str("{}");
+            return;
+        }
+        if self.level >= self.options.depth {
+            self.out.push_str("{...}");
+            return;
+        }
+        self.level += 1;
+        self.out.push('{');
+        let mut first = true;
+        for (key, item) in fields {
+            self.field_start(&mut first);
+            self.key(key);
+            self.out.push_str(" = ");
+            self.value(item);
+        }
+        self.level -= 1;
+        self.newline();
+        self.out.push('}');
+    }
+
+    /// Start a line for a keyed field, after a comma unless it is the first
+    /// thing in the table
+    fn field_start(&mut self, first: &mut bool) {
+        if !*first {
+            self.out.push(',');
+        }
+        *first = false;
+        self.newline();
+    }
+
+    fn key(&mut self, key: &LuaValue) {
+        match key {
+            LuaValue::String(s) if s.to_str().is_some_and(is_identifier) => {
+                self.out.push_str(&s.to_str_lossy())
+            }
+            key => {
+                self.out.push('[');
+                self.value(key);
+                self.out.push(']');
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::eval_lua;
+
+    fn inspected(code: &str) -> String {
+        eval_lua(code).unwrap().to_string_value()
+    }
+
+    #[test]
+    fn test_layout() {
+        assert_eq!(
+            inspected("return inspect({1, 'two', b = {x = true}, a = 1, [3.5] = {4, 5}})"),
+            "{ 1, \"two\",\n  [3.5] = { 4, 5 },\n  a = 1,\n  b = {\n    x = true\n  }\n}"
+        );
+        assert_eq!(inspected("return inspect({})"), "{}");
+        assert_eq!(inspected("return inspect('a\\nb')"), "\"a\\nb\"");
+        assert_eq!(
+            inspected("return inspect({['end'] = 1, [true] = 2})"),
+            "{\n  [true] = 2,\n  [\"end\"] = 1\n}"
+        );
+        assert_eq!(
+            inspected("return inspect({f = print, g = print})"),
+            "{\n  f = <function 1>,\n  g = <function 1>\n}"
+        );
+    }
+
+    #[test]
+    fn test_cycles_and_metatables() {
+        assert_eq!(
+            inspected("local t = {1} t.me = t return inspect(t)"),
+            "<1>{ 1,\n  me = <table 1>\n}"
+        );
+        assert_eq!(
+            inspected("local s = {} return inspect({s, s})"),
+            "{ <1>{}, <table 1> }"
+        );
+        assert_eq!(
+            inspected("return inspect(setmetatable({}, {__index = {y = 1}}))"),
+            "{\n  <metatable> = {\n    __index = {\n      y = 1\n    }\n  }\n}"
+        );
+    }
+
+    #[test]
+    fn test_options() {
+        assert_eq!(
+            inspected("return inspect({a = {b = {}}}, {depth = 1})"),
+            "{\n  a = {...}\n}"
+        );
+        assert_eq!(
+            inspected("return inspect({a = {1}}, {newline = ' ', indent = ''})"),
+            "{ a = { 1 } }"
+        );
+        assert_eq!(
+            inspected("return inspect({1}, {depth = math.huge})"),
+            "{ 1 }"
+        );
+        assert!(eval_lua("return inspect({}, 3)").is_err());
+        assert!(eval_lua("return inspect({}, {indent = {}})").is_err());
+    }
+}
@@ -1,56 +1,75 @@
 use super::validation;
-use crate::error_types::LuaResult;
-use crate::lua_value::LuaFunction;
+use crate::error_types::{LuaError, LuaResult};
 /// Iterator functions for Lua
 use crate::lua_value::LuaValue;
+use crate::lua_value::{LuaTable, TableData};
+use std::cell::RefCell;
 use std::rc::Rc;
 
 /// Create pairs() iterator function
+///
+/// The generic `for` loop iterates tables directly, so pairs() hands back
+/// the table itself.
 pub fn create_pairs() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
-    Rc::new(|_args| {
-        // Return a dummy function for now - full iterator support in future
-        Ok(LuaValue::Function(Rc::new(LuaFunction::Builtin(Rc::new(
-            |_| Ok(LuaValue::Nil),
-        )))))
+    Rc::new(|args| {
+        validation::require_args("pairs", &args, 1, Some(1))?;
+        validation::get_table("pairs", 0, &args[0])?;
+        Ok(args[0].clone())
     })
 }
 
 /// Create ipairs() iterator function
+///
+/// Returns a table holding only the array part `t[1..n]`, stopping at the
+/// first nil, for the generic `for` loop to iterate.
 pub fn create_ipairs() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
-    Rc::new(|_args| {
-        // Return a dummy function for now - full iterator support in future
-        Ok(LuaValue::Function(Rc::new(LuaFunction::Builtin(Rc::new(
-            |_| Ok(LuaValue::Nil),
-        )))))
+    Rc::new(|args| {
+        validation::require_args("ipairs", &args, 1, Some(1))?;
+        let table_ref = validation::get_table("ipairs", 0, &args[0])?;
+        let table = table_ref.borrow();
+
+        let mut data = TableData::new();
+        let mut i = 1.0;
+        while let Some(value) = table.data.get(&LuaValue::Number(i)) {
+            if *value == LuaValue::Nil {
+                break;
+            }
+            data.insert(LuaValue::Number(i), value.clone());
+            i += 1.0;
+        }
+
+        Ok(LuaValue::Table(Rc::new(RefCell::new(LuaTable {
+            data,
+            metatable: None,
+            frozen: false,
+            removed: None,
+        }))))
     })
 }
 
 /// Create next() function for generic iteration
-pub fn create_next() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+///
+/// Returns the key after `index` and its value, or nil past the last one.
+/// Keys come back in insertion order (see `TableData`), so repeated
+/// traversals of an unchanged table always agree, and clearing the field
+/// just visited does not end the traversal.
+pub fn create_next() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
     Rc::new(|args| {
         validation::require_args("next", &args, 1, Some(2))?;
         let table_ref = validation::get_table("next", 0, &args[0])?;
 
         let table = table_ref.borrow();
 
-        if args.len() == 1 {
-            // Get first key
-            if let Some(key) = table.data.keys().next() {
-                return Ok(key.clone());
-            }
-        } else {
-            // Get next key after given key
-            let mut found = false;
-            for key in table.data.keys() {
-                if found {
-                    return Ok(key.clone());
-                }
-                if key == &args[1] {
-                    found = true;
-                }
-            }
-        }
+        let index = match args.get(1) {
+            None | Some(LuaValue::Nil) => 0,
+            Some(key) => table
+                .next_index(key)
+                .ok_or_else(|| LuaError::value("invalid key to 'next'"))?,
+        };
 
-        Ok(LuaValue::Nil)
+        Ok(match table.data.get_index(index) {
+            Some((key, value)) => vec![key.clone(), value.clone()],
+            None => vec![LuaValue::Nil],
+        })
     })
 }
@@ -0,0 +1,231 @@
+use super::validation;
+use crate::error_types::{LuaError, LuaResult};
+/// JSON encoding and decoding for Lua
+///
+/// Tables whose keys are exactly 1..n encode as arrays, every other table
+/// as an object with its keys converted to strings. JSON `null` decodes to
+/// the `json.null` sentinel so it survives inside arrays and objects;
+/// both `nil` and `json.null` encode as `null`.
+use crate::lua_value::LuaValue;
+use crate::lua_value::{format_number, LuaFunction, LuaTable, TableData};
+use serde_json::{Map, Number, Value};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Marker type behind the `json.null` userdata
+struct JsonNull;
+
+thread_local! {
+    static NULL: LuaValue = LuaValue::UserData(Rc::new(RefCell::new(Box::new(JsonNull))));
+}
+
+/// The shared `json.null` sentinel
+pub fn json_null() -> LuaValue {
+    NULL.with(|null| null.clone())
+}
+
+fn is_json_null(value: &LuaValue) -> bool {
+    matches!(value, LuaValue::UserData(ud) if ud.borrow().is::<JsonNull>())
+}
+
+/// Options accepted by `json.encode`
+#[derive(Debug, Default)]
+struct EncodeOptions {
+    /// Spaces per level when pretty-printing; `None` for compact output
+    indent: Option<usize>,
+    /// Encode `{}` as `[]` instead of `{}`
+    empty_as_array: bool,
+}
+
+impl EncodeOptions {
+    fn from_lua(value: Option<&LuaValue>) -> LuaResult<Self> {
+        let table = match value {
+            None | Some(LuaValue::Nil) => return Ok(Self::default()),
+            Some(value) => validation::get_table("json.encode", 1, value)?,
+        };
+        let table = table.borrow();
+        let get = |key: &str| table.data.get(&LuaValue::String(key.into()));
+        let indent = match get("indent") {
+            None | Some(LuaValue::Nil) | Some(LuaValue::Boolean(false)) => None,
+            Some(LuaValue::Boolean(true)) => Some(2),
+            Some(n) => Some(validation::get_integer("json.encode", 1, n)?.max(0) as usize),
+        };
+        let empty_as_array = get("empty_as_array").is_some_and(LuaValue::is_truthy);
+        Ok(EncodeOptions {
+            indent,
+            empty_as_array,
+        })
+    }
+}
+
+/// Convert a Lua value to JSON
+fn to_json(value: &LuaValue, options: &EncodeOptions, seen: &mut Vec<usize>) -> LuaResult<Value> {
+    match value {
+        LuaValue::Nil => Ok(Value::Null),
+        LuaValue::Boolean(b) => Ok(Value::Bool(*b)),
+        LuaValue::Number(n) => number_to_json(*n),
+        LuaValue::String(s) => Ok(Value::String(s.to_string())),
+        LuaValue::Table(table) => {
+            let id = table.as_ptr() as usize;
+            if seen.contains(&id) {
+                return Err(LuaError::runtime(
+                    "cannot encode a table that contains itself",
+                    "json.encode",
+                ));
+            }
+            seen.push(id);
+            let result = table_to_json(&table.borrow(), options, seen);
+            seen.pop();
+            result
+        }
+        other if is_json_null(other) => Ok(Value::Null),
+        other => Err(LuaError::runtime(
+            format!("cannot encode a {} value", other.type_name()),
+            "json.encode",
+        )),
+    }
+}
+
+fn number_to_json(n: f64) -> LuaResult<Value> {
+    if n.fract() == 0.0 && n.abs() < 9_007_199_254_740_992.0 {
+        return Ok(Value::Number(Number::from(n as i64)));
+    }
+    Number::from_f64(n).map(Value::Number).ok_or_else(|| {
+        LuaError::runtime(
+            format!("cannot encode number {}", format_number(n)),
+            "json.encode",
+        )
+    })
+}
+
+/// The length of the table if its keys are exactly 1..n
+fn array_length(table: &LuaTable) -> Option<usize> {
+    let n = table.data.len();
+    (1..=n)
+        .all(|i| table.data.contains_key(&LuaValue::Number(i as f64)))
+        .then_some(n)
+}
+
+fn table_to_json(
+    table: &LuaTable,
+    options: &EncodeOptions,
+    seen: &mut Vec<usize>,
+) -> LuaResult<Value> {
+    if table.data.is_empty() {
+        return Ok(if options.empty_as_array {
+            Value::Array(Vec::new())
+        } else {
+            Value::Object(Map::new())
+        });
+    }
+    if let Some(n) = array_length(table) {
+        let items = (1..=n)
+            .map(|i| to_json(&table.data[&LuaValue::Number(i as f64)], options, seen))
+            .collect::<LuaResult<Vec<_>>>()?;
+        return Ok(Value::Array(items));
+    }
+
+    let mut entries = Vec::with_capacity(table.data.len());
+    for (key, value) in &table.data {
+        let key = match key {
+            LuaValue::String(s) => s.to_string(),
+            LuaValue::Number(_) => key.to_string(),
+            other => {
+                return Err(LuaError::runtime(
+                    format!("cannot use a {} as an object key", other.type_name()),
+                    "json.encode",
+                ))
+            }
+        };
+        entries.push((key, to_json(value, options, seen)?));
+    }
+    // Table iteration order is arbitrary; sorted keys keep output stable
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(Value::Object(entries.into_iter().collect()))
+}
+
+/// Convert decoded JSON to a Lua value
+fn from_json(value: Value) -> LuaValue {
+    match value {
+        Value::Null => json_null(),
+        Value::Bool(b) => LuaValue::Boolean(b),
+        Value::Number(n) => LuaValue::Number(n.as_f64().unwrap_or(f64::NAN)),
+        Value::String(s) => LuaValue::String(s.into()),
+        Value::Array(items) => {
+            let data = items
+                .into_iter()
+                .enumerate()
+                .map(|(i, item)| (LuaValue::Number((i + 1) as f64), from_json(item)))
+                .collect();
+            new_table(data)
+        }
+        Value::Object(fields) => {
+            let data = fields
+                .into_iter()
+                .map(|(key, item)| (LuaValue::String(key.into()), from_json(item)))
+                .collect();
+            new_table(data)
+        }
+    }
+}
+
+fn new_table(data: TableData) -> LuaValue {
+    LuaValue::Table(Rc::new(RefCell::new(LuaTable {
+        data,
+        metatable: None,
+        frozen: false,
+        removed: None,
+    })))
+}
+
+/// Create json.encode(value [, options])
+///
+/// `options.indent` pretty-prints with that many spaces (or 2 for `true`);
+/// `options.empty_as_array` encodes empty tables as `[]`.
+pub fn create_json_encode() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|args| {
+        validation::require_args("json.encode", &args, 1, Some(2))?;
+        let options = EncodeOptions::from_lua(args.get(1))?;
+        let value = to_json(&args[0], &options, &mut Vec::new())?;
+
+        let text = match options.indent {
+            None => value.to_string(),
+            Some(width) => {
+                let indent = " ".repeat(width);
+                let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+                let mut out = Vec::new();
+                let mut serializer = serde_json::Serializer::with_formatter(&mut out, formatter);
+                serde::Serialize::serialize(&value, &mut serializer)
+                    .map_err(|e| LuaError::runtime(e.to_string(), "json.encode"))?;
+                String::from_utf8(out).expect("serde_json writes UTF-8")
+            }
+        };
+        Ok(LuaValue::String(text.into()))
+    })
+}
+
+/// Create json.decode(text)
+pub fn create_json_decode() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|args| {
+        validation::require_args("json.decode", &args, 1, Some(1))?;
+        let text = validation::get_string("json.decode", 0, &args[0])?;
+        let value: Value = serde_json::from_str(&text)
+            .map_err(|e| LuaError::runtime(format!("invalid JSON: {}", e), "json.decode"))?;
+        Ok(from_json(value))
+    })
+}
+
+/// Create the json table
+pub fn create_json_table() -> LuaValue {
+    let mut json_table = TableData::new();
+    json_table.insert(
+        LuaValue::String("encode".into()),
+        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_json_encode()))),
+    );
+    json_table.insert(
+        LuaValue::String("decode".into()),
+        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_json_decode()))),
+    );
+    json_table.insert(LuaValue::String("null".into()), json_null());
+    new_table(json_table)
+}
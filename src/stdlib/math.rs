@@ -1,10 +1,9 @@
 use super::validation;
 use crate::error_types::{LuaError, LuaResult};
-use crate::lua_value::LuaTable;
 /// Math library functions for Lua
 use crate::lua_value::LuaValue;
+use crate::lua_value::{LuaTable, TableData};
 use std::cell::RefCell;
-use std::collections::HashMap;
 use std::rc::Rc;
 
 /// Create math.abs() function
@@ -64,71 +63,321 @@ pub fn create_math_max() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
     })
 }
 
-/// Create math.random() function
-pub fn create_math_random() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+/// Create a math function of one number, e.g. math.sqrt
+fn unary(
+    name: &'static str,
+    op: fn(f64) -> f64,
+) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(move |args| {
+        validation::require_args(name, &args, 1, Some(1))?;
+        let x = validation::get_number(name, 0, &args[0])?;
+        Ok(LuaValue::Number(op(x)))
+    })
+}
+
+/// Create math.sqrt() function
+pub fn create_math_sqrt() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    unary("math.sqrt", f64::sqrt)
+}
+
+/// Create math.exp() function
+pub fn create_math_exp() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    unary("math.exp", f64::exp)
+}
+
+/// Create math.sin() function (radians)
+pub fn create_math_sin() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    unary("math.sin", f64::sin)
+}
+
+/// Create math.cos() function (radians)
+pub fn create_math_cos() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    unary("math.cos", f64::cos)
+}
+
+/// Create math.tan() function (radians)
+pub fn create_math_tan() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    unary("math.tan", f64::tan)
+}
+
+/// Create math.asin() function
+pub fn create_math_asin() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    unary("math.asin", f64::asin)
+}
+
+/// Create math.acos() function
+pub fn create_math_acos() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    unary("math.acos", f64::acos)
+}
+
+/// Create math.deg() function: radians to degrees
+pub fn create_math_deg() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    unary("math.deg", f64::to_degrees)
+}
+
+/// Create math.rad() function: degrees to radians
+pub fn create_math_rad() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    unary("math.rad", f64::to_radians)
+}
+
+/// Create math.atan(y [, x]) function
+/// With two arguments, uses the signs of both to find the quadrant
+pub fn create_math_atan() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
     Rc::new(|args| {
-        use std::time::{SystemTime, UNIX_EPOCH};
-
-        // Simple pseudo-random using system time
-        let seed = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_nanos() as u64;
-
-        let rand = ((seed.wrapping_mul(1103515245).wrapping_add(12345)) / 65536) % 32768;
-        let normalized = (rand as f64) / 32768.0;
-
-        match args.len() {
-            0 => Ok(LuaValue::Number(normalized)),
-            1 => {
-                let max = validation::get_number("math.random", 0, &args[0])? as i64;
-                Ok(LuaValue::Number(((rand % (max as u64)) + 1) as f64))
-            }
-            2 => {
-                let a = validation::get_number("math.random", 0, &args[0])? as i64;
-                let b = validation::get_number("math.random", 1, &args[1])? as i64;
-                let min = a.min(b);
-                let max = a.max(b);
-                let range = (max - min + 1) as u64;
-                Ok(LuaValue::Number(((rand % range) + min as u64) as f64))
+        validation::require_args("math.atan", &args, 1, Some(2))?;
+        let y = validation::get_number("math.atan", 0, &args[0])?;
+        let x = match args.get(1) {
+            Some(x) => validation::get_number("math.atan", 1, x)?,
+            None => 1.0,
+        };
+        Ok(LuaValue::Number(y.atan2(x)))
+    })
+}
+
+/// Create math.log(x [, base]) function
+/// Natural logarithm unless a base is given
+pub fn create_math_log() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|args| {
+        validation::require_args("math.log", &args, 1, Some(2))?;
+        let x = validation::get_number("math.log", 0, &args[0])?;
+        let result = match args.get(1) {
+            None => x.ln(),
+            Some(base) => match validation::get_number("math.log", 1, base)? {
+                2.0 => x.log2(),
+                10.0 => x.log10(),
+                base => x.ln() / base.ln(),
+            },
+        };
+        Ok(LuaValue::Number(result))
+    })
+}
+
+/// Create math.pow(x, y) function
+pub fn create_math_pow() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|args| {
+        validation::require_args("math.pow", &args, 2, Some(2))?;
+        let x = validation::get_number("math.pow", 0, &args[0])?;
+        let y = validation::get_number("math.pow", 1, &args[1])?;
+        Ok(LuaValue::Number(x.powf(y)))
+    })
+}
+
+/// Create math.fmod(x, y) function
+/// The remainder of x / y rounded towards zero, so it has the sign of x
+pub fn create_math_fmod() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|args| {
+        validation::require_args("math.fmod", &args, 2, Some(2))?;
+        let x = validation::get_number("math.fmod", 0, &args[0])?;
+        let y = validation::get_number("math.fmod", 1, &args[1])?;
+        if y == 0.0 && x.fract() == 0.0 && y.fract() == 0.0 {
+            return Err(LuaError::value("math.fmod() divisor is zero"));
+        }
+        Ok(LuaValue::Number(x % y))
+    })
+}
+
+/// Create math.modf(x) function
+/// Returns the integral part of x and its fractional part
+pub fn create_math_modf() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
+    Rc::new(|args| {
+        validation::require_args("math.modf", &args, 1, Some(1))?;
+        let x = validation::get_number("math.modf", 0, &args[0])?;
+        let integral = x.trunc();
+        let fraction = if x.is_infinite() { 0.0 } else { x - integral };
+        Ok(vec![LuaValue::Number(integral), LuaValue::Number(fraction)])
+    })
+}
+
+/// Seedable pseudo-random generator behind math.random (xoshiro256**)
+#[derive(Debug, Clone)]
+pub struct Random {
+    state: [u64; 4],
+}
+
+impl Random {
+    /// A generator whose sequence is fixed by `seed`
+    pub fn with_seed(seed: u64) -> Self {
+        // Expand the seed with splitmix64 so that nearby seeds diverge
+        let mut x = seed;
+        let mut next = || {
+            x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = x;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        Random {
+            state: [next(), next(), next(), next()],
+        }
+    }
+
+    /// A generator seeded from the clock
+    pub fn from_time() -> Self {
+        Self::with_seed(crate::file_io::unix_time().as_nanos() as u64)
+    }
+
+    /// The next 64 random bits
+    pub fn next_u64(&mut self) -> u64 {
+        let [s0, s1, s2, s3] = &mut self.state;
+        let result = s1.wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = *s1 << 17;
+        *s2 ^= *s0;
+        *s3 ^= *s1;
+        *s1 ^= *s2;
+        *s0 ^= *s3;
+        *s2 ^= t;
+        *s3 = s3.rotate_left(45);
+        result
+    }
+
+    /// A float uniformly distributed in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// An integer uniformly distributed in [low, high]
+    pub fn next_in(&mut self, low: i64, high: i64) -> i64 {
+        let span = high.wrapping_sub(low) as u64;
+        if span == u64::MAX {
+            return self.next_u64() as i64;
+        }
+        // Reject draws from the incomplete last block to avoid modulo bias
+        let count = span + 1;
+        let zone = u64::MAX - u64::MAX % count;
+        loop {
+            let draw = self.next_u64();
+            if draw < zone {
+                return low.wrapping_add((draw % count) as i64);
             }
-            _ => Err(LuaError::arg_count("math.random", 2, args.len())),
         }
+    }
+}
+
+/// Create math.random([m [, n]]) function
+///
+/// With no arguments returns a float in [0, 1); with one, an integer in
+/// [1, m]; with two, an integer in [m, n].
+pub fn create_math_random(
+    rng: Rc<RefCell<Random>>,
+) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(move |args| {
+        let (low, high) = match args.len() {
+            0 => return Ok(LuaValue::Number(rng.borrow_mut().next_f64())),
+            1 => (1, validation::get_integer("math.random", 0, &args[0])?),
+            2 => (
+                validation::get_integer("math.random", 0, &args[0])?,
+                validation::get_integer("math.random", 1, &args[1])?,
+            ),
+            _ => return Err(LuaError::arg_count("math.random", 2, args.len())),
+        };
+        if low > high {
+            return Err(LuaError::value("math.random() interval is empty"));
+        }
+        Ok(LuaValue::Number(rng.borrow_mut().next_in(low, high) as f64))
+    })
+}
+
+/// Create math.randomseed([x]) function
+/// Reseeds the generator math.random uses; from the clock when x is absent
+pub fn create_math_randomseed(
+    rng: Rc<RefCell<Random>>,
+) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(move |args| {
+        validation::require_args("math.randomseed", &args, 0, Some(1))?;
+        *rng.borrow_mut() = match args.first() {
+            Some(seed) => {
+                // Integral seeds match LuaInterpreter::set_random_seed
+                let seed = validation::get_number("math.randomseed", 0, seed)?;
+                if seed.fract() == 0.0 {
+                    Random::with_seed(seed as i64 as u64)
+                } else {
+                    Random::with_seed(seed.to_bits())
+                }
+            }
+            None => Random::from_time(),
+        };
+        Ok(LuaValue::Nil)
     })
 }
 
 /// Create the math table with all math functions
-pub fn create_math_table() -> LuaValue {
+/// `rng` is the generator shared by math.random and math.randomseed
+pub fn create_math_table(rng: Rc<RefCell<Random>>) -> LuaValue {
     use crate::lua_value::LuaFunction;
 
-    let mut math_table = HashMap::new();
+    let mut math_table = TableData::new();
     math_table.insert(
-        LuaValue::String("abs".to_string()),
+        LuaValue::String("abs".into()),
         LuaValue::Function(Rc::new(LuaFunction::Builtin(create_math_abs()))),
     );
     math_table.insert(
-        LuaValue::String("floor".to_string()),
+        LuaValue::String("floor".into()),
         LuaValue::Function(Rc::new(LuaFunction::Builtin(create_math_floor()))),
     );
     math_table.insert(
-        LuaValue::String("ceil".to_string()),
+        LuaValue::String("ceil".into()),
         LuaValue::Function(Rc::new(LuaFunction::Builtin(create_math_ceil()))),
     );
     math_table.insert(
-        LuaValue::String("min".to_string()),
+        LuaValue::String("min".into()),
         LuaValue::Function(Rc::new(LuaFunction::Builtin(create_math_min()))),
     );
     math_table.insert(
-        LuaValue::String("max".to_string()),
+        LuaValue::String("max".into()),
         LuaValue::Function(Rc::new(LuaFunction::Builtin(create_math_max()))),
     );
+    for (name, function) in [
+        ("sqrt", create_math_sqrt()),
+        ("exp", create_math_exp()),
+        ("log", create_math_log()),
+        ("pow", create_math_pow()),
+        ("sin", create_math_sin()),
+        ("cos", create_math_cos()),
+        ("tan", create_math_tan()),
+        ("asin", create_math_asin()),
+        ("acos", create_math_acos()),
+        ("atan", create_math_atan()),
+        ("deg", create_math_deg()),
+        ("rad", create_math_rad()),
+    ] {
+        math_table.insert(
+            LuaValue::String(name.into()),
+            LuaValue::Function(Rc::new(LuaFunction::Builtin(function))),
+        );
+    }
+    math_table.insert(
+        LuaValue::String("fmod".into()),
+        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_math_fmod()))),
+    );
+    math_table.insert(
+        LuaValue::String("modf".into()),
+        LuaValue::Function(Rc::new(LuaFunction::MultiBuiltin(create_math_modf()))),
+    );
+
+    math_table.insert(
+        LuaValue::String("random".into()),
+        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_math_random(
+            Rc::clone(&rng),
+        )))),
+    );
+    math_table.insert(
+        LuaValue::String("randomseed".into()),
+        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_math_randomseed(rng)))),
+    );
+
+    math_table.insert(
+        LuaValue::String("pi".into()),
+        LuaValue::Number(std::f64::consts::PI),
+    );
     math_table.insert(
-        LuaValue::String("random".to_string()),
-        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_math_random()))),
+        LuaValue::String("huge".into()),
+        LuaValue::Number(f64::INFINITY),
     );
 
     LuaValue::Table(Rc::new(RefCell::new(LuaTable {
         data: math_table,
         metatable: None,
+        frozen: false,
+        removed: None,
     })))
 }
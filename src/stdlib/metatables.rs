@@ -1,10 +1,9 @@
 use super::validation;
+use crate::coroutines::{self, LuaThread};
 use crate::error_types::{LuaError, LuaResult};
-use crate::lua_value::LuaTable;
 /// Metatable and error handling functions for Lua
-use crate::lua_value::LuaValue;
+use crate::lua_value::{LuaFunction, LuaTable, LuaValue, Metatable, TableData};
 use std::cell::RefCell;
-use std::collections::HashMap;
 use std::rc::Rc;
 
 /// Create the setmetatable() function
@@ -13,20 +12,11 @@ pub fn create_setmetatable() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>>
     Rc::new(|args| {
         validation::require_args("setmetatable", &args, 2, Some(2))?;
         let table = validation::get_table("setmetatable", 0, &args[0])?;
+        table.borrow().check_writable()?;
 
         match &args[1] {
             LuaValue::Table(mt) => {
-                // Convert the table's LuaValue-keyed data into String-keyed metamethods
-                let mut metatable: HashMap<String, LuaValue> = HashMap::new();
-                let mt_borrow = mt.borrow();
-
-                for (key, value) in &mt_borrow.data {
-                    if let LuaValue::String(key_str) = key {
-                        metatable.insert(key_str.clone(), value.clone());
-                    }
-                }
-
-                table.borrow_mut().metatable = Some(Box::new(metatable));
+                table.borrow_mut().metatable = Some(Box::new(Metatable::new(Rc::clone(mt))));
                 Ok(args[0].clone())
             }
             LuaValue::Nil => {
@@ -34,74 +24,97 @@ pub fn create_setmetatable() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>>
                 table.borrow_mut().metatable = None;
                 Ok(args[0].clone())
             }
-            _ => Err(LuaError::type_error("table or nil", args[1].type_name(), "setmetatable")),
+            _ => Err(LuaError::type_error(
+                "table or nil",
+                args[1].type_name(),
+                "setmetatable",
+            )),
         }
     })
 }
 
 /// Create the getmetatable() function
-/// Returns the metatable of a table
-pub fn create_getmetatable() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
-    Rc::new(|args| {
+/// Returns the metatable of a table, or the metatable all strings share
+pub fn create_getmetatable(
+    string_metatable: Rc<RefCell<LuaTable>>,
+) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(move |args| {
         validation::require_args("getmetatable", &args, 1, Some(1))?;
 
         match &args[0] {
-            LuaValue::Table(table) => {
-                match &table.borrow().metatable {
-                    Some(mt) => {
-                        // Convert String-keyed metamethods back to LuaValue-keyed table
-                        let mut table_data: HashMap<LuaValue, LuaValue> = HashMap::new();
-                        for (key, value) in mt.iter() {
-                            table_data.insert(LuaValue::String(key.clone()), value.clone());
-                        }
-
-                        Ok(LuaValue::Table(Rc::new(RefCell::new(LuaTable {
-                            data: table_data,
-                            metatable: None,
-                        }))))
-                    }
-                    None => Ok(LuaValue::Nil),
-                }
-            }
+            LuaValue::Table(table) => match &table.borrow().metatable {
+                Some(mt) => Ok(LuaValue::Table(mt.table())),
+                None => Ok(LuaValue::Nil),
+            },
+            LuaValue::String(_) => Ok(LuaValue::Table(Rc::clone(&string_metatable))),
             _ => Ok(LuaValue::Nil),
         }
     })
 }
 
+/// Results of a protected call: `true` and the function's results, or
+/// `false` and the error message. Cancelling the script is not an error a
+/// script can catch, so it goes on unwinding.
+pub(crate) fn protected(result: LuaResult<Vec<LuaValue>>) -> LuaResult<Vec<LuaValue>> {
+    match result {
+        Ok(mut values) => {
+            values.insert(0, LuaValue::Boolean(true));
+            Ok(values)
+        }
+        Err(LuaError::Cancelled) => Err(LuaError::Cancelled),
+        Err(e) => Ok(vec![
+            LuaValue::Boolean(false),
+            LuaValue::String(e.message().into()),
+        ]),
+    }
+}
+
+/// Call a builtin for pcall() or xpcall() run outside the executor; Lua
+/// functions need the executor, which runs these calls itself
+fn call_builtin(name: &str, f: &LuaValue, args: Vec<LuaValue>) -> LuaResult<Vec<LuaValue>> {
+    match f {
+        LuaValue::Function(function) => match function.as_ref() {
+            LuaFunction::Builtin(builtin) => protected(builtin(args).map(|v| vec![v])),
+            LuaFunction::MultiBuiltin(builtin) => protected(builtin(args)),
+            LuaFunction::User { .. } | LuaFunction::Coroutine(_) => Err(LuaError::runtime(
+                format!(
+                    "{} of a Lua function is only available to running scripts",
+                    name
+                ),
+                name,
+            )),
+        },
+        _ => Err(LuaError::type_error("function", f.type_name(), name)),
+    }
+}
+
 /// Create the pcall() function
-/// Protected call - calls a function in protected mode, catching errors
+/// Protected call: calls a function, returning `true` and its results, or
+/// `false` and the error message when it raises one. Scripts get this
+/// through `Executor::protected_call`.
 pub fn create_pcall() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
     Rc::new(|args| {
         validation::require_args("pcall", &args, 1, None)?;
-
-        // For now, return a simple implementation
-        // In full implementation, this would actually catch errors from function execution
-        match &args[0] {
-            LuaValue::Function(_) => {
-                // Return success (true) and nil as placeholder
-                Ok(LuaValue::Boolean(true))
-            }
-            _ => Err(LuaError::type_error("function", args[0].type_name(), "pcall")),
-        }
+        let values = call_builtin("pcall", &args[0], args[1..].to_vec())?;
+        Ok(values[0].clone())
     })
 }
 
 /// Create the xpcall() function
-/// Extended protected call with custom error handler
+/// Extended protected call: like pcall(), but an error's message first
+/// goes through the handler, whose result is returned after `false`
 pub fn create_xpcall() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
     Rc::new(|args| {
         validation::require_args("xpcall", &args, 2, None)?;
-
-        match (&args[0], &args[1]) {
-            (LuaValue::Function(_), LuaValue::Function(_)) => {
-                // Return success (true) and nil as placeholder
-                Ok(LuaValue::Boolean(true))
-            }
-            (LuaValue::Function(_), _) => {
-                Err(LuaError::type_error("function", args[1].type_name(), "xpcall"))
-            }
-            _ => Err(LuaError::type_error("function", args[0].type_name(), "xpcall")),
+        if !matches!(args[1], LuaValue::Function(_)) {
+            return Err(LuaError::type_error(
+                "function",
+                args[1].type_name(),
+                "xpcall",
+            ));
         }
+        let values = call_builtin("xpcall", &args[0], args[2..].to_vec())?;
+        Ok(values[0].clone())
     })
 }
 
@@ -113,7 +126,7 @@ pub fn create_error() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
             "".to_string()
         } else {
             match &args[0] {
-                LuaValue::String(s) => s.clone(),
+                LuaValue::String(s) => s.to_string(),
                 v => v.to_string(),
             }
         };
@@ -122,45 +135,92 @@ pub fn create_error() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
 }
 
 /// Create the coroutine module table
-pub fn create_coroutine_table() -> LuaValue {
-    use crate::lua_value::LuaFunction;
-
-    let mut coro_table = HashMap::new();
-
-    // coroutine.create
+/// `resume` is the `coroutine.resume` the executor runs itself, so the
+/// coroutine runs with the script's interpreter; so do the functions
+/// `coroutine.wrap` makes (see `LuaFunction::Coroutine`)
+pub fn create_coroutine_table(resume: Rc<LuaFunction>) -> LuaValue {
+    let mut coro_table = TableData::new();
     coro_table.insert(
-        LuaValue::String("create".to_string()),
-        LuaValue::Function(Rc::new(LuaFunction::Builtin(Rc::new(|_| {
-            Err(LuaError::runtime("coroutine.create() requires executor context", "coroutine"))
-        })))),
+        LuaValue::String("resume".into()),
+        LuaValue::Function(resume),
     );
-
-    // coroutine.resume
-    coro_table.insert(
-        LuaValue::String("resume".to_string()),
-        LuaValue::Function(Rc::new(LuaFunction::Builtin(Rc::new(|_| {
-            Err(LuaError::runtime("coroutine.resume() requires executor context", "coroutine"))
-        })))),
+    let mut set = |name: &str, function: LuaFunction| {
+        coro_table.insert(
+            LuaValue::String(name.into()),
+            LuaValue::Function(Rc::new(function)),
+        );
+    };
+    set(
+        "create",
+        LuaFunction::Builtin(Rc::new(|args| {
+            validation::require_args("coroutine.create", &args, 1, Some(1))?;
+            validation::require_type("coroutine.create", 0, &args[0], "function")?;
+            Ok(LuaValue::Thread(LuaThread::new(args[0].clone())?))
+        })),
     );
-
-    // coroutine.yield
-    coro_table.insert(
-        LuaValue::String("yield".to_string()),
-        LuaValue::Function(Rc::new(LuaFunction::Builtin(Rc::new(|_| {
-            Err(LuaError::runtime("coroutine.yield() requires executor context", "coroutine"))
-        })))),
+    set(
+        "yield",
+        LuaFunction::MultiBuiltin(Rc::new(coroutines::yield_values)),
     );
-
-    // coroutine.status
-    coro_table.insert(
-        LuaValue::String("status".to_string()),
-        LuaValue::Function(Rc::new(LuaFunction::Builtin(Rc::new(|_| {
-            Err(LuaError::runtime("coroutine.status() requires executor context", "coroutine"))
-        })))),
+    set(
+        "status",
+        LuaFunction::Builtin(Rc::new(|args| {
+            validation::require_args("coroutine.status", &args, 1, Some(1))?;
+            let thread = validation::get_thread("coroutine.status", 0, &args[0])?;
+            Ok(LuaValue::String(thread.status().to_string().into()))
+        })),
+    );
+    set(
+        "wrap",
+        LuaFunction::Builtin(Rc::new(|args| {
+            validation::require_args("coroutine.wrap", &args, 1, Some(1))?;
+            validation::require_type("coroutine.wrap", 0, &args[0], "function")?;
+            let thread = LuaThread::new(args[0].clone())?;
+            Ok(LuaValue::Function(Rc::new(LuaFunction::Coroutine(thread))))
+        })),
+    );
+    // The running coroutine and false, or nil and true in the main chunk
+    set(
+        "running",
+        LuaFunction::MultiBuiltin(Rc::new(|_| {
+            Ok(match coroutines::running() {
+                Some(thread) => vec![LuaValue::Thread(thread), LuaValue::Boolean(false)],
+                None => vec![LuaValue::Nil, LuaValue::Boolean(true)],
+            })
+        })),
+    );
+    set(
+        "isyieldable",
+        LuaFunction::Builtin(Rc::new(|_| {
+            Ok(LuaValue::Boolean(coroutines::running().is_some()))
+        })),
+    );
+    set(
+        "close",
+        LuaFunction::Builtin(Rc::new(|args| {
+            validation::require_args("coroutine.close", &args, 1, Some(1))?;
+            let thread = validation::get_thread("coroutine.close", 0, &args[0])?;
+            coroutines::close(&thread)?;
+            Ok(LuaValue::Boolean(true))
+        })),
     );
 
     LuaValue::Table(Rc::new(RefCell::new(LuaTable {
         data: coro_table,
         metatable: None,
+        frozen: false,
+        removed: None,
     })))
 }
+
+/// Create the coroutine.resume() function
+/// Scripts get this through `Executor::resume_coroutine`, which runs the
+/// coroutine with their interpreter
+pub fn create_coroutine_resume() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
+    Rc::new(|_| {
+        Err(LuaError::runtime(
+            "coroutine.resume() is only available to running scripts",
+            "coroutine",
+        ))
+    })
+}
@@ -1,75 +1,106 @@
+pub mod base;
+pub mod coverage;
+pub mod debug;
+pub mod inspect;
 pub mod iterators;
+pub mod json;
 pub mod math;
 pub mod metatables;
 pub mod string;
 pub mod table;
+pub mod timer;
 pub mod types;
+pub mod utf8;
 /// Standard Library Module Organization
 ///
 /// This module provides essential Lua standard library functions organized by submodule:
 /// - string: string.len, string.sub, string.upper, string.lower
-/// - math: math.abs, math.floor, math.ceil, math.min, math.max, math.random
-/// - table: table.insert, table.remove
+/// - utf8: utf8.char, utf8.codepoint, utf8.len, utf8.charpattern
+/// - math: math.abs, math.floor, math.ceil, math.min, math.max, math.sqrt, math.exp,
+///   math.log, math.pow, trig functions, math.fmod, math.modf, math.random,
+///   math.randomseed, math.pi, math.huge
+/// - table: table.insert, table.remove, table.unpack
+/// - base: assert(), select(), unpack(), rawget(), rawset(), rawequal(), rawlen(),
+///   collectgarbage()
+/// - timer: timer.after, timer.every, timer.cancel, timer.sleep
 /// - types: type(), tonumber(), tostring()
+/// - coverage: coverage.start, coverage.stop, coverage.report
+/// - debug: debug.sethook, debug.gethook, debug.traceback, debug.getinfo,
+///   debug.getlocal, debug.getupvalue, debug.setupvalue
 /// - iterators: pairs(), ipairs(), next()
+/// - inspect: inspect(), a readable dump of nested tables
+/// - json: json.encode, json.decode, json.null
 /// - metatables: setmetatable(), getmetatable(), pcall(), xpcall(), error(), coroutine
-/// - io: print, io.read, io.write, io.open, io.input, io.output
-/// - os: os.execute, os.exit, os.getenv, os.setenv, os.time, os.remove, os.rename, os.tmpname
-/// - require: Module system for loading .lua files
+/// - io: print, io.read, io.write, io.lines, io.open, io.popen, io.close, io.input, io.output,
+///   io.stdin, io.stdout, io.stderr
+/// - os: os.execute, os.exit, os.getenv, os.environ, os.setenv, os.time, os.remove, os.rename,
+///   os.tmpname
+/// - require: Module system for loading .lua files, configured through package.path,
+///   package.loaded and package.preload
 pub mod validation;
 
 use crate::error_types::{LuaError, LuaResult};
+use crate::host_io::OutputSink;
 use crate::lua_value::LuaValue;
 use std::rc::Rc;
 
-/// Create the print function that outputs values to stdout
-pub fn create_print() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
-    Rc::new(|args| {
-        let output = args
+/// Create the print function that writes values to `sink` (stdout unless a host redirects it)
+pub fn create_print(sink: OutputSink) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(move |args| {
+        let mut output = args
             .iter()
-            .map(|v| match v {
-                LuaValue::String(s) => s.clone(),
-                LuaValue::Nil => "nil".to_string(),
-                LuaValue::Boolean(b) => b.to_string(),
-                LuaValue::Number(n) => {
-                    if n.fract() == 0.0 && !n.is_infinite() {
-                        format!("{}", *n as i64)
-                    } else {
-                        n.to_string()
-                    }
-                }
-                LuaValue::Table(_) => "table".to_string(),
-                LuaValue::Function(_) => "function".to_string(),
-                LuaValue::UserData(_) => "userdata".to_string(),
-            })
+            .map(|v| v.to_lua_string().to_vec())
             .collect::<Vec<_>>()
-            .join("\t");
+            .join(&b'\t');
+        output.push(b'\n');
 
-        println!("{}", output);
+        sink.write_bytes(&output)
+            .map_err(|e| LuaError::runtime(format!("print() error: {}", e), "io"))?;
         Ok(LuaValue::Nil)
     })
 }
 
 // Re-export public functions from submodules for backward compatibility
+pub use base::{
+    create_assert, create_collectgarbage, create_rawequal, create_rawget, create_rawlen,
+    create_rawset, create_select, create_unpack,
+};
+pub use coverage::create_coverage_table;
+pub use debug::{
+    create_debug_gethook, create_debug_getinfo, create_debug_getlocal, create_debug_getupvalue,
+    create_debug_sethook, create_debug_setupvalue, create_debug_table, create_debug_traceback,
+};
+pub use inspect::create_inspect;
 pub use iterators::{create_ipairs, create_next, create_pairs};
+pub use json::create_json_table;
 pub use math::{
-    create_math_abs, create_math_ceil, create_math_floor, create_math_max, create_math_min,
-    create_math_random, create_math_table,
+    create_math_abs, create_math_acos, create_math_asin, create_math_atan, create_math_ceil,
+    create_math_cos, create_math_deg, create_math_exp, create_math_floor, create_math_fmod,
+    create_math_log, create_math_max, create_math_min, create_math_modf, create_math_pow,
+    create_math_rad, create_math_random, create_math_randomseed, create_math_sin, create_math_sqrt,
+    create_math_table, create_math_tan, Random,
 };
 pub use metatables::{
-    create_coroutine_table, create_error, create_getmetatable, create_pcall, create_setmetatable,
-    create_xpcall,
+    create_coroutine_resume, create_coroutine_table, create_error, create_getmetatable,
+    create_pcall, create_setmetatable, create_xpcall,
 };
 pub use string::{
-    create_string_len, create_string_lower, create_string_sub, create_string_table,
-    create_string_upper,
+    create_string_format, create_string_len, create_string_lower, create_string_sub,
+    create_string_table, create_string_upper,
+};
+pub use table::{
+    create_table_deepcopy, create_table_freeze, create_table_insert, create_table_isfrozen,
+    create_table_move, create_table_pack, create_table_remove, create_table_table,
 };
-pub use table::{create_table_insert, create_table_remove, create_table_table};
+pub use timer::{create_timer_sleep, create_timer_table};
 pub use types::{create_tonumber, create_tostring, create_type};
+pub use utf8::{create_utf8_char, create_utf8_codepoint, create_utf8_len, create_utf8_table};
 
 /// Create an io table with I/O functions (delegates to file_io module)
-pub fn create_io_table() -> LuaValue {
-    crate::file_io::create_enhanced_io_table()
+pub fn create_io_table(
+    streams: std::rc::Rc<std::cell::RefCell<crate::file_io::IoStreams>>,
+) -> LuaValue {
+    crate::file_io::create_enhanced_io_table(streams)
 }
 
 /// Create an os table with all os functions (delegates to file_io module)
@@ -90,8 +121,14 @@ pub fn create_require(
         }
 
         let module_name = match &args[0] {
-            LuaValue::String(s) => s.clone(),
-            _ => return Err(LuaError::type_error("string", args[0].type_name(), "require")),
+            LuaValue::String(s) => s.to_string(),
+            _ => {
+                return Err(LuaError::type_error(
+                    "string",
+                    args[0].type_name(),
+                    "require",
+                ))
+            }
         };
 
         // Note: The actual module loading happens in Executor::execute_call
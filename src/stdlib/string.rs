@@ -1,58 +1,59 @@
 use super::validation;
-use crate::error_types::LuaResult;
-use crate::lua_value::LuaTable;
+use crate::error_types::{LuaError, LuaResult};
 /// String library functions for Lua
+///
+/// Strings are bytes: lengths and positions count bytes, and
+/// `string.upper`/`string.lower` change ASCII letters only, like Lua in the
+/// C locale. The `utf8` library works with whole characters.
+use crate::lua_string::LuaString;
 use crate::lua_value::LuaValue;
+use crate::lua_value::{LuaTable, TableData};
 use std::cell::RefCell;
-use std::collections::HashMap;
 use std::rc::Rc;
 
 /// Create string.len() function
 pub fn create_string_len() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
     Rc::new(|args| {
         validation::require_args("string.len", &args, 1, Some(1))?;
-        let s = validation::get_string("string.len", 0, &args[0])?;
+        let s = validation::get_bytes("string.len", 0, &args[0])?;
         Ok(LuaValue::Number(s.len() as f64))
     })
 }
 
 /// Create string.sub() function
+///
+/// Indices count bytes, as in Lua, so a range may start or end inside a
+/// multi-byte character and keep just the bytes it covers.
 pub fn create_string_sub() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
     Rc::new(|args| {
         validation::require_args("string.sub", &args, 2, None)?;
 
-        let s = validation::get_string("string.sub", 0, &args[0])?;
-        let start_lua = validation::get_integer("string.sub", 1, &args[1])? as i32;
-
-        let end_lua = if args.len() >= 3 {
-            validation::get_integer("string.sub", 2, &args[2])? as i32
-        } else {
-            s.len() as i32
+        let s = validation::get_bytes("string.sub", 0, &args[0])?;
+        let len = s.len() as i64;
+        let start = validation::get_integer("string.sub", 1, &args[1])?;
+        let end = match args.get(2) {
+            Some(end) => validation::get_integer("string.sub", 2, end)?,
+            None => -1,
         };
 
-        let len = s.len() as i32;
-
-        // Convert Lua 1-based indices to 0-based Rust indices
-        let i = if start_lua < 0 {
-            (len + start_lua).max(0) as usize
-        } else {
-            ((start_lua - 1).min(len)).max(0) as usize
+        // 1-based and inclusive; negative indices count from the end
+        let start = match start {
+            i if i < -len => 1,
+            i if i < 0 => len + i + 1,
+            i => i.max(1),
         };
-
-        let j = if end_lua < 0 {
-            (len + end_lua).max(-1) as usize
-        } else {
-            (end_lua.min(len)) as usize
+        let end = match end {
+            j if j < -len => 0,
+            j if j < 0 => len + j + 1,
+            j => j.min(len),
         };
 
-        // j is the last character index (1-based), so we need j as the exclusive end
-        let end = if j < i { i } else { j };
-
-        if i > s.len() {
-            return Ok(LuaValue::String(String::new()));
+        if start > end {
+            return Ok(LuaValue::String("".into()));
         }
-
-        Ok(LuaValue::String(s[i..end.min(s.len())].to_string()))
+        Ok(LuaValue::String(
+            s[(start - 1) as usize..end as usize].into(),
+        ))
     })
 }
 
@@ -60,8 +61,8 @@ pub fn create_string_sub() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
 pub fn create_string_upper() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
     Rc::new(|args| {
         validation::require_args("string.upper", &args, 1, Some(1))?;
-        let s = validation::get_string("string.upper", 0, &args[0])?;
-        Ok(LuaValue::String(s.to_uppercase()))
+        let s = validation::get_bytes("string.upper", 0, &args[0])?;
+        Ok(LuaValue::String(s.to_ascii_uppercase().into()))
     })
 }
 
@@ -69,8 +70,278 @@ pub fn create_string_upper() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>>
 pub fn create_string_lower() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
     Rc::new(|args| {
         validation::require_args("string.lower", &args, 1, Some(1))?;
-        let s = validation::get_string("string.lower", 0, &args[0])?;
-        Ok(LuaValue::String(s.to_lowercase()))
+        let s = validation::get_bytes("string.lower", 0, &args[0])?;
+        Ok(LuaValue::String(s.to_ascii_lowercase().into()))
+    })
+}
+
+/// One `%` conversion of a `string.format` template: flags, width,
+/// precision and the conversion letter
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    conversion: char,
+}
+
+/// The rest of a `string.format` template
+type Template<'a> = std::iter::Peekable<std::slice::Iter<'a, u8>>;
+
+impl Spec {
+    /// Parse the spec after a `%`, leaving `chars` on the next byte
+    fn parse(chars: &mut Template) -> LuaResult<Spec> {
+        let mut spec = Spec {
+            left: false,
+            plus: false,
+            space: false,
+            alternate: false,
+            zero: false,
+            width: 0,
+            precision: None,
+            conversion: '%',
+        };
+        while let Some(&&c) = chars.peek() {
+            match c {
+                b'-' => spec.left = true,
+                b'+' => spec.plus = true,
+                b' ' => spec.space = true,
+                b'#' => spec.alternate = true,
+                b'0' => spec.zero = true,
+                _ => break,
+            }
+            chars.next();
+        }
+        // Lua allows at most two digits for width and precision
+        let digits = |chars: &mut Template| {
+            let mut n = 0;
+            for _ in 0..2 {
+                match chars.peek().filter(|c| c.is_ascii_digit()) {
+                    Some(d) => {
+                        n = n * 10 + (*d - b'0') as usize;
+                        chars.next();
+                    }
+                    None => break,
+                }
+            }
+            n
+        };
+        spec.width = digits(chars);
+        if chars.peek() == Some(&&b'.') {
+            chars.next();
+            spec.precision = Some(digits(chars));
+        }
+        spec.conversion = chars.next().map(|&c| c as char).ok_or_else(|| {
+            LuaError::value("string.format: invalid conversion '%' to format string")
+        })?;
+        Ok(spec)
+    }
+
+    /// The sign a non-negative number gets from the `+` and space flags
+    fn positive_sign(&self) -> &'static str {
+        match (self.plus, self.space) {
+            (true, _) => "+",
+            (false, true) => " ",
+            _ => "",
+        }
+    }
+
+    /// Pad `body` to the width in bytes; zeros go between the sign or `0x`
+    /// prefix and the digits
+    fn pad(&self, prefix: &str, body: &[u8], numeric: bool) -> Vec<u8> {
+        let fill = self.width.saturating_sub(prefix.len() + body.len());
+        let (before, zeros, after) = if self.left {
+            (0, 0, fill)
+        } else if self.zero && numeric {
+            (0, fill, 0)
+        } else {
+            (fill, 0, 0)
+        };
+        let mut out = vec![b' '; before];
+        out.extend_from_slice(prefix.as_bytes());
+        out.extend(std::iter::repeat_n(b'0', zeros));
+        out.extend_from_slice(body);
+        out.extend(std::iter::repeat_n(b' ', after));
+        out
+    }
+}
+
+/// A number argument that `%d`, `%x` and friends can print
+fn integer_arg(n: f64) -> LuaResult<i64> {
+    if n.fract() == 0.0 && (-9.223_372_036_854_776e18..9.223_372_036_854_776e18).contains(&n) {
+        Ok(n as i64)
+    } else {
+        Err(LuaError::value(
+            "string.format: number has no integer representation",
+        ))
+    }
+}
+
+/// A float in `%e`, `%f` or `%g` with C's precision rules
+fn format_float(spec: &Spec, n: f64) -> String {
+    let upper = spec.conversion.is_ascii_uppercase();
+    if !n.is_finite() {
+        let text = if n.is_nan() { "nan" } else { "inf" };
+        return if upper {
+            text.to_uppercase()
+        } else {
+            text.to_string()
+        };
+    }
+    let precision = spec.precision.unwrap_or(6);
+    let text = match spec.conversion.to_ascii_lowercase() {
+        'e' => {
+            let scientific = format!("{:.*e}", precision, n.abs());
+            let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+            let exponent = exponent.parse().unwrap_or(0);
+            format!("{}{}", mantissa, crate::lua_value::c_exponent(exponent))
+        }
+        'f' => format!("{:.*}", precision, n.abs()),
+        _ => crate::lua_value::format_general(n.abs(), precision),
+    };
+    if upper {
+        text.to_uppercase()
+    } else {
+        text
+    }
+}
+
+/// A string as `%q` writes it: quoted so Lua reads back the same value
+fn quote(s: &LuaString) -> Vec<u8> {
+    let mut out = vec![b'"'];
+    for (i, &b) in s.iter().enumerate() {
+        match b {
+            b'"' => out.extend_from_slice(b"\\\""),
+            b'\\' => out.extend_from_slice(b"\\\\"),
+            b'\n' => out.extend_from_slice(b"\\\n"),
+            b'\r' => out.extend_from_slice(b"\\r"),
+            b if b.is_ascii_control() => {
+                // A following digit would otherwise extend the escape
+                if s.get(i + 1).is_some_and(|c| c.is_ascii_digit()) {
+                    out.extend(format!("\\{:03}", b).bytes());
+                } else {
+                    out.extend(format!("\\{}", b).bytes());
+                }
+            }
+            b => out.push(b),
+        }
+    }
+    out.push(b'"');
+    out
+}
+
+/// Format one argument for `spec`
+fn format_arg(spec: &Spec, arg: &LuaValue) -> LuaResult<Vec<u8>> {
+    const NAME: &str = "string.format";
+    match spec.conversion {
+        'd' | 'i' => {
+            let n = integer_arg(validation::get_number(NAME, 0, arg)?)?;
+            let mut digits = n.unsigned_abs().to_string();
+            if let Some(precision) = spec.precision {
+                digits = format!("{:0>1$}", digits, precision);
+            }
+            let sign = if n < 0 { "-" } else { spec.positive_sign() };
+            Ok(spec.pad(sign, digits.as_bytes(), spec.precision.is_none()))
+        }
+        'x' | 'X' | 'o' => {
+            let n = integer_arg(validation::get_number(NAME, 0, arg)?)?;
+            let (digits, prefix) = match spec.conversion {
+                'x' => (format!("{:x}", n), "0x"),
+                'X' => (format!("{:X}", n), "0X"),
+                _ => (format!("{:o}", n), "0"),
+            };
+            let digits = match spec.precision {
+                Some(precision) => format!("{:0>1$}", digits, precision),
+                None => digits,
+            };
+            let prefix = if spec.alternate && n != 0 { prefix } else { "" };
+            Ok(spec.pad(prefix, digits.as_bytes(), spec.precision.is_none()))
+        }
+        'c' => {
+            let n = integer_arg(validation::get_number(NAME, 0, arg)?)?;
+            let byte = u8::try_from(n)
+                .map_err(|_| LuaError::value("string.format: value out of range for '%c'"))?;
+            Ok(spec.pad("", &[byte], false))
+        }
+        'e' | 'E' | 'f' | 'F' | 'g' | 'G' => {
+            let n = validation::get_number(NAME, 0, arg)?;
+            let sign = if n.is_sign_negative() && !n.is_nan() {
+                "-"
+            } else {
+                spec.positive_sign()
+            };
+            Ok(spec.pad(sign, format_float(spec, n).as_bytes(), n.is_finite()))
+        }
+        's' => {
+            let s = arg.to_lua_string();
+            let s = match spec.precision {
+                Some(precision) => &s[..precision.min(s.len())],
+                None => &s[..],
+            };
+            Ok(spec.pad("", s, false))
+        }
+        'q' => match arg {
+            LuaValue::String(s) => Ok(quote(s)),
+            LuaValue::Number(n) if n.fract() == 0.0 && n.is_finite() => Ok(arg.to_string().into()),
+            // Enough digits that the value reads back exactly
+            LuaValue::Number(n) if n.is_finite() => Ok(format!("{:e}", n).into()),
+            LuaValue::Number(n) if n.is_nan() => Ok(b"(0/0)".to_vec()),
+            LuaValue::Number(n) if *n > 0.0 => Ok(b"1e9999".to_vec()),
+            LuaValue::Number(_) => Ok(b"-1e9999".to_vec()),
+            LuaValue::Nil | LuaValue::Boolean(_) => Ok(arg.to_string().into()),
+            _ => Err(LuaError::value("string.format: value has no literal form")),
+        },
+        'p' => {
+            let text = match arg.address() {
+                Some(address) => format!("{:#x}", address),
+                None => "(null)".to_string(),
+            };
+            Ok(spec.pad("", text.as_bytes(), false))
+        }
+        c => Err(LuaError::value(format!(
+            "string.format: invalid conversion '%{}' to format string",
+            c
+        ))),
+    }
+}
+
+/// Create string.format() function
+///
+/// Supports C's `%d %i %c %x %X %o %e %E %f %F %g %G %s %%` with flags,
+/// width and precision, plus Lua's `%q`, and `%p` for the address of a
+/// table, function or userdata (`(null)` for other values). `%s` shows
+/// values like `tostring` does, without consulting `__tostring`.
+pub fn create_string_format() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|args| {
+        validation::require_args("string.format", &args, 1, None)?;
+        let template = validation::get_bytes("string.format", 0, &args[0])?;
+
+        let mut out = Vec::new();
+        let mut next_arg = 1;
+        let mut chars = template.iter().peekable();
+        while let Some(&c) = chars.next() {
+            if c != b'%' {
+                out.push(c);
+                continue;
+            }
+            let spec = Spec::parse(&mut chars)?;
+            if spec.conversion == '%' {
+                out.push(b'%');
+                continue;
+            }
+            let arg = args.get(next_arg).ok_or_else(|| {
+                LuaError::value(format!(
+                    "bad argument #{} to 'string.format' (no value)",
+                    next_arg + 1
+                ))
+            })?;
+            next_arg += 1;
+            out.extend(format_arg(&spec, arg)?);
+        }
+        Ok(LuaValue::String(out.into()))
     })
 }
 
@@ -78,26 +349,32 @@ pub fn create_string_lower() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>>
 pub fn create_string_table() -> LuaValue {
     use crate::lua_value::LuaFunction;
 
-    let mut string_table = HashMap::new();
+    let mut string_table = TableData::new();
     string_table.insert(
-        LuaValue::String("len".to_string()),
+        LuaValue::String("len".into()),
         LuaValue::Function(Rc::new(LuaFunction::Builtin(create_string_len()))),
     );
     string_table.insert(
-        LuaValue::String("sub".to_string()),
+        LuaValue::String("sub".into()),
         LuaValue::Function(Rc::new(LuaFunction::Builtin(create_string_sub()))),
     );
     string_table.insert(
-        LuaValue::String("upper".to_string()),
+        LuaValue::String("upper".into()),
         LuaValue::Function(Rc::new(LuaFunction::Builtin(create_string_upper()))),
     );
     string_table.insert(
-        LuaValue::String("lower".to_string()),
+        LuaValue::String("lower".into()),
         LuaValue::Function(Rc::new(LuaFunction::Builtin(create_string_lower()))),
     );
+    string_table.insert(
+        LuaValue::String("format".into()),
+        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_string_format()))),
+    );
 
     LuaValue::Table(Rc::new(RefCell::new(LuaTable {
         data: string_table,
         metatable: None,
+        frozen: false,
+        removed: None,
     })))
 }
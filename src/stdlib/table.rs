@@ -1,80 +1,212 @@
 use super::validation;
-use crate::error_types::LuaResult;
-use crate::lua_value::LuaTable;
+use crate::error_types::{LuaError, LuaResult};
 /// Table library functions for Lua
 use crate::lua_value::LuaValue;
+use crate::lua_value::{LuaTable, TableData};
+use crate::stack::with_headroom;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
 /// Create table.insert() function
+///
+/// `table.insert(t, v)` appends; `table.insert(t, pos, v)` shifts `t[pos]`
+/// to `t[#t]` up by one first, with `pos` between 1 and `#t + 1`.
 pub fn create_table_insert() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
     Rc::new(|args| {
-        validation::require_args("table.insert", &args, 2, None)?;
+        validation::require_args("table.insert", &args, 2, Some(3))?;
         let table_ref = validation::get_table("table.insert", 0, &args[0])?;
-
-        let value = args.get(2).cloned().unwrap_or_else(|| args[1].clone());
-        let index = if args.len() >= 3 {
-            validation::get_integer("table.insert", 1, &args[1])?
-        } else {
-            -1 // Append at end
-        };
-
         let mut table = table_ref.borrow_mut();
+        table.check_writable()?;
+        let end = table.border() as i64 + 1;
 
-        // Find the length of the table (count numeric keys)
-        let mut len = 0i64;
-        for key in table.data.keys() {
-            if let LuaValue::Number(n) = key {
-                if n.fract() == 0.0 {
-                    len = len.max(*n as i64);
+        let (pos, value) = match &args[1..] {
+            [value] => (end, value.clone()),
+            [pos, value] => {
+                let pos = validation::get_integer("table.insert", 1, pos)?;
+                if !(1..=end).contains(&pos) {
+                    return Err(LuaError::value(
+                        "bad argument #2 to 'insert' (position out of bounds)",
+                    ));
                 }
+                (pos, value.clone())
             }
-        }
-
-        let pos = if index < 0 { len + 1 } else { index };
+            _ => unreachable!("require_args checked the count"),
+        };
 
-        table.data.insert(LuaValue::Number(pos as f64), value);
+        for i in (pos..end).rev() {
+            let moved = table.get_int(i);
+            table.set_int(i + 1, moved);
+        }
+        table.set_int(pos, value);
         Ok(LuaValue::Nil)
     })
 }
 
 /// Create table.remove() function
+///
+/// Removes and returns `t[pos]` (default `t[#t]`), shifting the elements
+/// after it down by one. `pos` may be `#t + 1`, or `#t` on an empty table.
 pub fn create_table_remove() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
     Rc::new(|args| {
         validation::require_args("table.remove", &args, 1, Some(2))?;
         let table_ref = validation::get_table("table.remove", 0, &args[0])?;
+        let mut table = table_ref.borrow_mut();
+        table.check_writable()?;
+        let size = table.border() as i64;
 
-        let index = if args.len() >= 2 {
-            validation::get_integer("table.remove", 1, &args[1])?
-        } else {
-            -1 // Remove from end
+        let pos = match args.get(1) {
+            None | Some(LuaValue::Nil) => size,
+            Some(pos) => validation::get_integer("table.remove", 1, pos)?,
         };
+        if pos != size && !(1..=size + 1).contains(&pos) {
+            return Err(LuaError::value(
+                "bad argument #2 to 'remove' (position out of bounds)",
+            ));
+        }
 
-        let mut table = table_ref.borrow_mut();
+        let removed = table.get_int(pos);
+        for i in pos..size {
+            let moved = table.get_int(i + 1);
+            table.set_int(i, moved);
+        }
+        table.set_int(pos.max(size), LuaValue::Nil);
+        Ok(removed)
+    })
+}
 
-        // Find the length
-        let mut len = 0i64;
-        for key in table.data.keys() {
-            if let LuaValue::Number(n) = key {
-                if n.fract() == 0.0 {
-                    len = len.max(*n as i64);
-                }
-            }
+/// Create table.pack(...)
+/// Returns a new table holding the arguments at 1..n, with `n` set to
+/// their count so trailing nils are not lost
+pub fn create_table_pack() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|args| {
+        let mut table = LuaTable {
+            data: TableData::with_capacity(args.len() + 1),
+            metatable: None,
+            frozen: false,
+            removed: None,
+        };
+        let n = args.len();
+        for (i, value) in args.into_iter().enumerate() {
+            table.set_int(i as i64 + 1, value);
         }
+        table
+            .data
+            .insert(LuaValue::String("n".into()), LuaValue::Number(n as f64));
+        Ok(LuaValue::Table(Rc::new(RefCell::new(table))))
+    })
+}
 
-        let pos = if index < 0 { len } else { index };
+/// Create table.move(a1, f, e, t [, a2])
+/// Copies `a1[f..=e]` to `a2[t..]` (`a2` defaults to `a1`) and returns
+/// `a2`. Overlapping ranges in one table copy back to front when the
+/// destination starts inside the source, so every element arrives intact.
+pub fn create_table_move() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|args| {
+        validation::require_args("table.move", &args, 4, Some(5))?;
+        let source = validation::get_table("table.move", 0, &args[0])?;
+        let first = validation::get_integer("table.move", 1, &args[1])?;
+        let last = validation::get_integer("table.move", 2, &args[2])?;
+        let target = validation::get_integer("table.move", 3, &args[3])?;
+        let dest = match args.get(4) {
+            None | Some(LuaValue::Nil) => Rc::clone(&source),
+            Some(dest) => validation::get_table("table.move", 4, dest)?,
+        };
+        dest.borrow().check_writable()?;
 
-        if pos <= 0 || pos > len {
-            return Ok(LuaValue::Nil);
+        if last >= first {
+            if first <= 0 && last >= i64::MAX + first {
+                return Err(LuaError::value(
+                    "bad argument #3 to 'move' (too many elements to move)",
+                ));
+            }
+            let count = last - first;
+            if target > i64::MAX - count {
+                return Err(LuaError::value(
+                    "bad argument #4 to 'move' (destination wrap around)",
+                ));
+            }
+            let overlapping = Rc::ptr_eq(&source, &dest) && target > first && target <= last;
+            let mut copy = |i: i64| {
+                let value = source.borrow().get_int(first + i);
+                dest.borrow_mut().set_int(target + i, value);
+            };
+            if overlapping {
+                (0..=count).rev().for_each(&mut copy);
+            } else {
+                (0..=count).for_each(&mut copy);
+            }
         }
+        Ok(LuaValue::Table(dest))
+    })
+}
 
-        let removed = table
-            .data
-            .remove(&LuaValue::Number(pos as f64))
-            .unwrap_or(LuaValue::Nil);
+/// Create table.deepcopy(t)
+/// Returns a copy of `t` and of every table reachable from it, keys
+/// included. A table reached twice is copied once, so shared and cyclic
+/// structure is kept; metatables are shared with the original and the
+/// copies are never frozen.
+pub fn create_table_deepcopy() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|args| {
+        validation::require_args("table.deepcopy", &args, 1, Some(1))?;
+        validation::get_table("table.deepcopy", 0, &args[0])?;
+        Ok(deep_copy(&args[0], &mut HashMap::new()))
+    })
+}
 
-        Ok(removed)
+/// `value`, with tables replaced by their copies in `copies`, keyed by the
+/// original's address
+fn deep_copy(
+    value: &LuaValue,
+    copies: &mut HashMap<*const RefCell<LuaTable>, LuaValue>,
+) -> LuaValue {
+    let LuaValue::Table(table) = value else {
+        return value.clone();
+    };
+    if let Some(copy) = copies.get(&Rc::as_ptr(table)) {
+        return copy.clone();
+    }
+    let copy = Rc::new(RefCell::new(LuaTable {
+        data: TableData::new(),
+        metatable: table.borrow().metatable.clone(),
+        frozen: false,
+        removed: None,
+    }));
+    copies.insert(Rc::as_ptr(table), LuaValue::Table(Rc::clone(&copy)));
+    let entries: Vec<(LuaValue, LuaValue)> = table
+        .borrow()
+        .data
+        .iter()
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    for (key, value) in entries {
+        let key = with_headroom(|| deep_copy(&key, copies));
+        let value = with_headroom(|| deep_copy(&value, copies));
+        copy.borrow_mut().data.insert(key, value);
+    }
+    LuaValue::Table(copy)
+}
+
+/// Create table.freeze(t)
+/// Makes `t` read-only and returns it. Assigning its fields, `rawset`,
+/// the table functions that change it and `setmetatable` then fail; tables
+/// it holds are not frozen with it.
+pub fn create_table_freeze() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|args| {
+        validation::require_args("table.freeze", &args, 1, Some(1))?;
+        let table = validation::get_table("table.freeze", 0, &args[0])?;
+        table.borrow_mut().frozen = true;
+        Ok(args[0].clone())
+    })
+}
+
+/// Create table.isfrozen(t)
+pub fn create_table_isfrozen() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|args| {
+        validation::require_args("table.isfrozen", &args, 1, Some(1))?;
+        let table = validation::get_table("table.isfrozen", 0, &args[0])?;
+        let frozen = table.borrow().frozen;
+        Ok(LuaValue::Boolean(frozen))
     })
 }
 
@@ -82,18 +214,46 @@ pub fn create_table_remove() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>>
 pub fn create_table_table() -> LuaValue {
     use crate::lua_value::LuaFunction;
 
-    let mut table_table = HashMap::new();
+    let mut table_table = TableData::new();
     table_table.insert(
-        LuaValue::String("insert".to_string()),
+        LuaValue::String("insert".into()),
         LuaValue::Function(Rc::new(LuaFunction::Builtin(create_table_insert()))),
     );
     table_table.insert(
-        LuaValue::String("remove".to_string()),
+        LuaValue::String("remove".into()),
         LuaValue::Function(Rc::new(LuaFunction::Builtin(create_table_remove()))),
     );
+    table_table.insert(
+        LuaValue::String("pack".into()),
+        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_table_pack()))),
+    );
+    table_table.insert(
+        LuaValue::String("move".into()),
+        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_table_move()))),
+    );
+    table_table.insert(
+        LuaValue::String("deepcopy".into()),
+        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_table_deepcopy()))),
+    );
+    table_table.insert(
+        LuaValue::String("freeze".into()),
+        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_table_freeze()))),
+    );
+    table_table.insert(
+        LuaValue::String("isfrozen".into()),
+        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_table_isfrozen()))),
+    );
+    table_table.insert(
+        LuaValue::String("unpack".into()),
+        LuaValue::Function(Rc::new(LuaFunction::MultiBuiltin(
+            super::base::create_unpack(),
+        ))),
+    );
 
     LuaValue::Table(Rc::new(RefCell::new(LuaTable {
         data: table_table,
         metatable: None,
+        frozen: false,
+        removed: None,
     })))
 }
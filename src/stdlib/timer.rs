@@ -0,0 +1,109 @@
+use super::validation;
+use crate::error_types::{LuaError, LuaResult};
+/// The timer library: timer.after, timer.every, timer.cancel, timer.sleep
+///
+/// Callbacks are queued in the interpreter's `Timers` and run by the
+/// executor, either while a script waits in `timer.sleep` or after the
+/// main chunk under `muscm run --loop`. A callback gets its timer's id, so
+/// a repeating one can cancel itself.
+use crate::lua_value::{LuaFunction, LuaTable, LuaValue, TableData};
+use crate::timers::Timers;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+type TimersSlot = Rc<RefCell<Timers>>;
+
+/// A time in seconds given to `name`, which must not be negative
+pub(crate) fn get_seconds(name: &str, index: usize, arg: &LuaValue) -> LuaResult<Duration> {
+    let seconds = validation::get_number(name, index, arg)?;
+    Duration::try_from_secs_f64(seconds).map_err(|_| {
+        LuaError::value(format!(
+            "bad argument #{} to '{}' (time must be a non-negative number)",
+            index + 1,
+            name
+        ))
+    })
+}
+
+fn get_callback(name: &str, arg: &LuaValue) -> LuaResult<LuaValue> {
+    match arg {
+        LuaValue::Function(_) => Ok(arg.clone()),
+        _ => Err(LuaError::type_error("function", arg.type_name(), name)),
+    }
+}
+
+/// Create timer.after(seconds, fn), which runs `fn` once, `seconds` from
+/// now, and returns the timer's id
+pub fn create_timer_after(timers: TimersSlot) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(move |args| {
+        validation::require_args("timer.after", &args, 2, Some(2))?;
+        let delay = get_seconds("timer.after", 0, &args[0])?;
+        let callback = get_callback("timer.after", &args[1])?;
+        let id = timers.borrow_mut().add(delay, None, callback);
+        Ok(LuaValue::Number(id as f64))
+    })
+}
+
+/// Create timer.every(seconds, fn), which runs `fn` every `seconds` until
+/// the timer is cancelled, and returns the timer's id
+pub fn create_timer_every(timers: TimersSlot) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(move |args| {
+        validation::require_args("timer.every", &args, 2, Some(2))?;
+        let interval = get_seconds("timer.every", 0, &args[0])?;
+        if interval.is_zero() {
+            return Err(LuaError::value(
+                "bad argument #1 to 'timer.every' (interval must be positive)",
+            ));
+        }
+        let callback = get_callback("timer.every", &args[1])?;
+        let id = timers.borrow_mut().add(interval, Some(interval), callback);
+        Ok(LuaValue::Number(id as f64))
+    })
+}
+
+/// Create timer.cancel(id), which returns whether the timer was still
+/// queued
+pub fn create_timer_cancel(timers: TimersSlot) -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(move |args| {
+        validation::require_args("timer.cancel", &args, 1, Some(1))?;
+        let id = validation::get_integer("timer.cancel", 0, &args[0])?;
+        let cancelled = id > 0 && timers.borrow_mut().cancel(id as u64);
+        Ok(LuaValue::Boolean(cancelled))
+    })
+}
+
+/// Create timer.sleep(seconds)
+/// Waiting runs the timers that fall due, which needs the executor, so
+/// the executor runs these calls itself through `Executor::sleep`
+pub fn create_timer_sleep() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|_| {
+        Err(LuaError::runtime(
+            "timer.sleep is only available to running scripts",
+            "timer.sleep",
+        ))
+    })
+}
+
+/// Create the timer table
+pub fn create_timer_table(timers: TimersSlot, sleep: Rc<LuaFunction>) -> LuaValue {
+    let mut table = TableData::new();
+    let functions = [
+        ("after", create_timer_after(Rc::clone(&timers))),
+        ("every", create_timer_every(Rc::clone(&timers))),
+        ("cancel", create_timer_cancel(timers)),
+    ];
+    for (name, function) in functions {
+        table.insert(
+            LuaValue::String(name.into()),
+            LuaValue::Function(Rc::new(LuaFunction::Builtin(function))),
+        );
+    }
+    table.insert(LuaValue::String("sleep".into()), LuaValue::Function(sleep));
+    LuaValue::Table(Rc::new(RefCell::new(LuaTable {
+        data: table,
+        metatable: None,
+        frozen: false,
+        removed: None,
+    })))
+}
@@ -0,0 +1,183 @@
+use super::validation;
+use crate::error_types::{LuaError, LuaResult};
+/// UTF-8 library functions for Lua
+///
+/// Positions are byte offsets into the string, as in `string.sub`; these
+/// functions are the way to work with whole characters. Decoding follows
+/// Lua 5.4: sequences of up to six bytes are read, overlong encodings are
+/// invalid, and surrogates and code points past U+10FFFF are invalid too
+/// unless the `lax` argument is true.
+use crate::lua_string::utf8_encode;
+use crate::lua_value::{LuaFunction, LuaTable, LuaValue, TableData};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Pattern matching exactly one UTF-8 byte sequence, as Lua writes it
+pub const CHARPATTERN: &[u8] = b"[\x00-\x7F\xC2-\xFD][\x80-\xBF]*";
+
+/// Largest code point `lax` decoding accepts
+const MAX_UTF: u32 = 0x7FFF_FFFF;
+
+/// Resolve a 1-based byte position, negative ones counting from the end
+fn position(pos: i64, len: usize) -> i64 {
+    let len = len as i64;
+    if pos >= 0 {
+        pos
+    } else if -pos > len {
+        0
+    } else {
+        len + pos + 1
+    }
+}
+
+/// Decode the sequence at the start of `bytes`: its code point and length,
+/// or `None` when it is not valid UTF-8
+fn decode(bytes: &[u8], strict: bool) -> Option<(u32, usize)> {
+    // Smallest code point for each number of continuation bytes, so
+    // overlong encodings are rejected
+    const LIMITS: [u32; 6] = [u32::MAX, 0x80, 0x800, 0x1_0000, 0x20_0000, 0x400_0000];
+    let mut first = *bytes.first()? as u32;
+    if first < 0x80 {
+        return Some((first, 1));
+    }
+    let mut code = 0;
+    let mut count = 0;
+    // Each leading one after the first announces a continuation byte
+    while first & 0x40 != 0 {
+        count += 1;
+        let next = *bytes.get(count)? as u32;
+        if next & 0xC0 != 0x80 {
+            return None;
+        }
+        code = (code << 6) | (next & 0x3F);
+        first <<= 1;
+    }
+    if count > 5 {
+        return None;
+    }
+    code |= (first & 0x7F) << (count * 5);
+    if code > MAX_UTF || code < LIMITS[count] {
+        return None;
+    }
+    if strict && (code > 0x10_FFFF || (0xD800..=0xDFFF).contains(&code)) {
+        return None;
+    }
+    Some((code, count + 1))
+}
+
+/// Whether the optional `lax` argument at `index` is true
+fn lax(args: &[LuaValue], index: usize) -> bool {
+    args.get(index).is_some_and(LuaValue::is_truthy)
+}
+
+/// Create utf8.char(...)
+/// Returns the string made of the given code points, each up to 2^31 - 1
+pub fn create_utf8_char() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<LuaValue>> {
+    Rc::new(|args| {
+        let mut s = Vec::new();
+        for (i, arg) in args.iter().enumerate() {
+            let code = validation::get_integer("utf8.char", i, arg)?;
+            let bytes = u32::try_from(code)
+                .ok()
+                .and_then(utf8_encode)
+                .ok_or_else(|| LuaError::value("utf8.char() value out of range"))?;
+            s.extend(bytes);
+        }
+        Ok(LuaValue::String(s.into()))
+    })
+}
+
+/// Create utf8.codepoint(s [, i [, j [, lax]]])
+/// Returns the code points of the characters starting between byte
+/// positions i and j (default i, which defaults to 1)
+pub fn create_utf8_codepoint() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
+    Rc::new(|args| {
+        validation::require_args("utf8.codepoint", &args, 1, Some(4))?;
+        let s = validation::get_bytes("utf8.codepoint", 0, &args[0])?;
+        let i = match args.get(1) {
+            Some(i) => position(validation::get_integer("utf8.codepoint", 1, i)?, s.len()),
+            None => 1,
+        };
+        let j = match args.get(2) {
+            Some(j) => position(validation::get_integer("utf8.codepoint", 2, j)?, s.len()),
+            None => i,
+        };
+        if i < 1 || j > s.len() as i64 {
+            return Err(LuaError::value("utf8.codepoint() out of bounds"));
+        }
+        let strict = !lax(&args, 3);
+        let mut codes = Vec::new();
+        let mut at = (i - 1) as usize;
+        while (at as i64) < j {
+            let (code, len) = decode(&s[at..], strict)
+                .ok_or_else(|| LuaError::value("utf8.codepoint() invalid UTF-8 code"))?;
+            codes.push(LuaValue::Number(code as f64));
+            at += len;
+        }
+        Ok(codes)
+    })
+}
+
+/// Create utf8.len(s [, i [, j [, lax]]])
+/// Counts the characters starting between byte positions i (default 1)
+/// and j (default -1). When a sequence there is not valid UTF-8, returns
+/// nil and the position of its first byte
+pub fn create_utf8_len() -> Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> {
+    Rc::new(|args| {
+        validation::require_args("utf8.len", &args, 1, Some(4))?;
+        let s = validation::get_bytes("utf8.len", 0, &args[0])?;
+        let i = match args.get(1) {
+            Some(i) => position(validation::get_integer("utf8.len", 1, i)?, s.len()),
+            None => 1,
+        };
+        let j = match args.get(2) {
+            Some(j) => position(validation::get_integer("utf8.len", 2, j)?, s.len()),
+            None => s.len() as i64,
+        };
+        if i < 1 || i > s.len() as i64 + 1 {
+            return Err(LuaError::value("utf8.len() initial position out of bounds"));
+        }
+        if j > s.len() as i64 {
+            return Err(LuaError::value("utf8.len() final position out of bounds"));
+        }
+        let strict = !lax(&args, 3);
+        let mut count = 0;
+        let mut at = (i - 1) as usize;
+        while (at as i64) < j {
+            match decode(&s[at..], strict) {
+                Some((_, len)) => at += len,
+                None => return Ok(vec![LuaValue::Nil, LuaValue::Number(at as f64 + 1.0)]),
+            }
+            count += 1;
+        }
+        Ok(vec![LuaValue::Number(count as f64)])
+    })
+}
+
+/// Create the utf8 table with all UTF-8 functions
+pub fn create_utf8_table() -> LuaValue {
+    let mut utf8_table = TableData::new();
+    utf8_table.insert(
+        LuaValue::String("char".into()),
+        LuaValue::Function(Rc::new(LuaFunction::Builtin(create_utf8_char()))),
+    );
+    utf8_table.insert(
+        LuaValue::String("codepoint".into()),
+        LuaValue::Function(Rc::new(LuaFunction::MultiBuiltin(create_utf8_codepoint()))),
+    );
+    utf8_table.insert(
+        LuaValue::String("len".into()),
+        LuaValue::Function(Rc::new(LuaFunction::MultiBuiltin(create_utf8_len()))),
+    );
+    utf8_table.insert(
+        LuaValue::String("charpattern".into()),
+        LuaValue::String(CHARPATTERN.into()),
+    );
+
+    LuaValue::Table(Rc::new(RefCell::new(LuaTable {
+        data: utf8_table,
+        metatable: None,
+        frozen: false,
+        removed: None,
+    })))
+}
@@ -2,7 +2,9 @@
 ///
 /// Provides consistent argument validation and type checking
 /// to eliminate ~150 lines of duplicated boilerplate across stdlib.
+use crate::coroutines::LuaThread;
 use crate::error_types::{LuaError, LuaResult};
+use crate::lua_string::LuaString;
 use crate::lua_value::{LuaTable, LuaValue};
 use std::cell::RefCell;
 use std::rc::Rc;
@@ -40,12 +42,7 @@ pub fn require_args(
 /// * `index` - Argument position (0-based)
 /// * `arg` - The argument to validate
 /// * `expected` - Expected type name
-pub fn require_type(
-    name: &str,
-    index: usize,
-    arg: &LuaValue,
-    expected: &str,
-) -> LuaResult<()> {
+pub fn require_type(name: &str, _index: usize, arg: &LuaValue, expected: &str) -> LuaResult<()> {
     let got = arg.type_name();
     if got != expected {
         Err(LuaError::type_error(expected, got, name))
@@ -60,20 +57,35 @@ pub fn require_type(
 /// * `name` - Function name for error messages
 /// * `index` - Argument position (0-based)
 /// * `arg` - The argument to extract
-pub fn get_number(name: &str, index: usize, arg: &LuaValue) -> LuaResult<f64> {
+pub fn get_number(name: &str, _index: usize, arg: &LuaValue) -> LuaResult<f64> {
     match arg {
         LuaValue::Number(n) => Ok(*n),
         _ => Err(LuaError::type_error("number", arg.type_name(), name)),
     }
 }
 
-/// Extract string with type checking
+/// Extract string with type checking, as text
+///
+/// Invalid UTF-8 becomes U+FFFD; use `get_bytes` where the bytes matter.
 ///
 /// # Arguments
 /// * `name` - Function name for error messages
 /// * `index` - Argument position (0-based)
 /// * `arg` - The argument to extract
-pub fn get_string(name: &str, index: usize, arg: &LuaValue) -> LuaResult<String> {
+pub fn get_string(name: &str, _index: usize, arg: &LuaValue) -> LuaResult<String> {
+    match arg {
+        LuaValue::String(s) => Ok(s.to_string()),
+        _ => Err(LuaError::type_error("string", arg.type_name(), name)),
+    }
+}
+
+/// Extract string with type checking, as its bytes
+///
+/// # Arguments
+/// * `name` - Function name for error messages
+/// * `index` - Argument position (0-based)
+/// * `arg` - The argument to extract
+pub fn get_bytes(name: &str, _index: usize, arg: &LuaValue) -> LuaResult<LuaString> {
     match arg {
         LuaValue::String(s) => Ok(s.clone()),
         _ => Err(LuaError::type_error("string", arg.type_name(), name)),
@@ -86,11 +98,7 @@ pub fn get_string(name: &str, index: usize, arg: &LuaValue) -> LuaResult<String>
 /// * `name` - Function name for error messages
 /// * `index` - Argument position (0-based)
 /// * `arg` - The argument to extract
-pub fn get_table(
-    name: &str,
-    index: usize,
-    arg: &LuaValue,
-) -> LuaResult<Rc<RefCell<LuaTable>>> {
+pub fn get_table(name: &str, _index: usize, arg: &LuaValue) -> LuaResult<Rc<RefCell<LuaTable>>> {
     match arg {
         LuaValue::Table(t) => Ok(t.clone()),
         _ => Err(LuaError::type_error("table", arg.type_name(), name)),
@@ -103,7 +111,7 @@ pub fn get_table(
 /// * `name` - Function name for error messages
 /// * `index` - Argument position (0-based)
 /// * `arg` - The argument to extract
-pub fn get_boolean(name: &str, index: usize, arg: &LuaValue) -> LuaResult<bool> {
+pub fn get_boolean(name: &str, _index: usize, arg: &LuaValue) -> LuaResult<bool> {
     match arg {
         LuaValue::Boolean(b) => Ok(*b),
         _ => Err(LuaError::type_error("boolean", arg.type_name(), name)),
@@ -116,9 +124,22 @@ pub fn get_boolean(name: &str, index: usize, arg: &LuaValue) -> LuaResult<bool>
 /// * `name` - Function name for error messages
 /// * `index` - Argument position (0-based)
 /// * `arg` - The argument to extract
-pub fn get_integer(name: &str, index: usize, arg: &LuaValue) -> LuaResult<i64> {
+pub fn get_integer(name: &str, _index: usize, arg: &LuaValue) -> LuaResult<i64> {
     match arg {
         LuaValue::Number(n) => Ok(*n as i64),
         _ => Err(LuaError::type_error("number", arg.type_name(), name)),
     }
 }
+
+/// Extract coroutine with type checking
+///
+/// # Arguments
+/// * `name` - Function name for error messages
+/// * `index` - Argument position (0-based)
+/// * `arg` - The argument to extract
+pub fn get_thread(name: &str, _index: usize, arg: &LuaValue) -> LuaResult<Rc<LuaThread>> {
+    match arg {
+        LuaValue::Thread(t) => Ok(t.clone()),
+        _ => Err(LuaError::type_error("coroutine", arg.type_name(), name)),
+    }
+}
@@ -0,0 +1,594 @@
+//! The test runner behind `muscm test`
+//!
+//! Test files are Lua scripts named `*_test.lua` or kept in a `spec`
+//! directory. They declare tests busted-style:
+//!
+//! ```lua
+//! describe("stack", function()
+//!   before_each(function() s = Stack.new() end)
+//!   it("starts empty", function()
+//!     expect(s:size()).to_equal(0)
+//!     expect(s:peek()).to_be_nil()
+//!   end)
+//! end)
+//! ```
+//!
+//! Builtins cannot call back into Lua, so `describe`, `it`, `before_each`
+//! and `after_each` only record what they were given; the runner then calls
+//! each `describe` body to collect the tests inside it, and each test
+//! between the hooks of every group around it. A test passes when it
+//! returns and fails with the error it raised. `expect(value)` returns the
+//! native matchers, and `expect(value).never` the same ones negated.
+
+use crate::error_types::{LuaError, LuaResult};
+use crate::executor::Executor;
+use crate::lua_interpreter::LuaInterpreter;
+use crate::lua_parser::parse_source;
+use crate::lua_value::{LuaFunction, LuaTable, LuaValue, TableData};
+use crate::resolver::resolve;
+use std::cell::RefCell;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Difference below which `to_be_close_to` calls two numbers equal
+const DEFAULT_DELTA: f64 = 1e-9;
+/// How deep `show` prints nested tables
+const SHOW_DEPTH: usize = 3;
+
+/// The outcome of one test
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestResult {
+    /// Names of the enclosing `describe`s and the test, space separated
+    pub name: String,
+    /// Why the test failed; `None` when it passed
+    pub error: Option<String>,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// The tests of one file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub path: PathBuf,
+    pub results: Vec<TestResult>,
+    /// The file could not be read or parsed, or raised an error outside
+    /// any test; counts as one failure
+    pub error: Option<String>,
+}
+
+impl Report {
+    pub fn passed(&self) -> usize {
+        self.results.iter().filter(|result| result.passed()).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.passed() + usize::from(self.error.is_some())
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.path.display())?;
+        for result in &self.results {
+            match &result.error {
+                None => writeln!(f, "  ok   {}", result.name)?,
+                Some(error) => writeln!(f, "  FAIL {}\n       {}", result.name, error)?,
+            }
+        }
+        if let Some(error) = &self.error {
+            writeln!(f, "  FAIL (file)\n       {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Test files under `paths`, sorted; files named directly are always
+/// included, directories are searched for `*_test.lua` and `spec/*.lua`
+pub fn discover(paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            walk(path, &mut files)?;
+        } else {
+            files.push(path.clone());
+        }
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let hidden = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with('.') || name == "target");
+        if hidden {
+            continue;
+        }
+        if path.is_dir() {
+            walk(&path, files)?;
+        } else if is_test_file(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn is_test_file(path: &Path) -> bool {
+    if path.extension().and_then(|ext| ext.to_str()) != Some("lua") {
+        return false;
+    }
+    let named = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .is_some_and(|stem| stem.ends_with("_test"));
+    let in_spec = path
+        .parent()
+        .and_then(|parent| parent.file_name())
+        .is_some_and(|name| name == "spec");
+    named || in_spec
+}
+
+/// Run the tests in the file at `path`
+pub fn run_file(interp: &mut LuaInterpreter, path: &Path) -> Report {
+    match std::fs::read_to_string(path) {
+        Ok(code) => run_source(interp, path, &code),
+        Err(e) => Report {
+            path: path.to_path_buf(),
+            results: Vec::new(),
+            error: Some(format!("cannot read file: {}", e)),
+        },
+    }
+}
+
+/// Run the tests `code` declares, reporting them under `path`
+pub fn run_source(interp: &mut LuaInterpreter, path: &Path, code: &str) -> Report {
+    let mut report = Report {
+        path: path.to_path_buf(),
+        results: Vec::new(),
+        error: None,
+    };
+    let block = match parse_source(code) {
+        Ok(block) => resolve(&block),
+        Err(e) => {
+            report.error = Some(e);
+            return report;
+        }
+    };
+    let mut runner = Runner {
+        executor: Executor::new(),
+        entries: install(interp),
+        results: Vec::new(),
+    };
+    if let Err(e) = runner.executor.execute_block(&block, interp) {
+        report.error = Some(e.to_string());
+        return report;
+    }
+    let entries = runner.take_entries();
+    runner.run_group(interp, "", entries, &[], &[]);
+    report.results = runner.results;
+    report
+}
+
+/// What a test file declared, in order
+enum Entry {
+    Describe(String, LuaValue),
+    It(String, LuaValue),
+    BeforeEach(LuaValue),
+    AfterEach(LuaValue),
+}
+
+type Entries = Rc<RefCell<Vec<Entry>>>;
+
+/// Define `describe`, `it`, `before_each`, `after_each` and `expect`,
+/// returning where the first four record their arguments
+fn install(interp: &mut LuaInterpreter) -> Entries {
+    let entries: Entries = Rc::default();
+    let named = |name: &'static str, make: fn(String, LuaValue) -> Entry| {
+        let entries = Rc::clone(&entries);
+        builtin(move |args| {
+            let title = match args.first() {
+                Some(LuaValue::String(s)) => s.to_string(),
+                other => {
+                    let got = other.map_or("no value", LuaValue::type_name);
+                    return Err(LuaError::type_error("string", got, name));
+                }
+            };
+            let function = function_arg(name, &args, 1)?;
+            entries.borrow_mut().push(make(title, function));
+            Ok(LuaValue::Nil)
+        })
+    };
+    let hook = |name: &'static str, make: fn(LuaValue) -> Entry| {
+        let entries = Rc::clone(&entries);
+        builtin(move |args| {
+            let function = function_arg(name, &args, 0)?;
+            entries.borrow_mut().push(make(function));
+            Ok(LuaValue::Nil)
+        })
+    };
+    interp.set_global("describe", named("describe", Entry::Describe));
+    interp.set_global("it", named("it", Entry::It));
+    interp.set_global("before_each", hook("before_each", Entry::BeforeEach));
+    interp.set_global("after_each", hook("after_each", Entry::AfterEach));
+    interp.set_global(
+        "expect",
+        builtin(|args| Ok(matchers(args.into_iter().next().unwrap_or(LuaValue::Nil)))),
+    );
+    entries
+}
+
+fn function_arg(name: &str, args: &[LuaValue], index: usize) -> LuaResult<LuaValue> {
+    match args.get(index) {
+        Some(value @ LuaValue::Function(_)) => Ok(value.clone()),
+        other => {
+            let got = other.map_or("no value", LuaValue::type_name);
+            Err(LuaError::type_error("function", got, name))
+        }
+    }
+}
+
+struct Runner {
+    executor: Executor,
+    entries: Entries,
+    results: Vec<TestResult>,
+}
+
+impl Runner {
+    fn take_entries(&self) -> Vec<Entry> {
+        std::mem::take(&mut *self.entries.borrow_mut())
+    }
+
+    /// Run the tests of one group; `before` hooks run outermost first and
+    /// `after` hooks innermost first
+    fn run_group(
+        &mut self,
+        interp: &mut LuaInterpreter,
+        prefix: &str,
+        entries: Vec<Entry>,
+        before: &[LuaValue],
+        after: &[LuaValue],
+    ) {
+        let mut before = before.to_vec();
+        let mut inner_after = Vec::new();
+        for entry in &entries {
+            match entry {
+                Entry::BeforeEach(function) => before.push(function.clone()),
+                Entry::AfterEach(function) => inner_after.push(function.clone()),
+                _ => {}
+            }
+        }
+        inner_after.reverse();
+        let after: Vec<LuaValue> = inner_after.into_iter().chain(after.to_vec()).collect();
+
+        for entry in entries {
+            match entry {
+                Entry::Describe(title, body) => {
+                    let name = join(prefix, &title);
+                    match self.executor.call_function(body, Vec::new(), interp) {
+                        Ok(_) => {
+                            let inner = self.take_entries();
+                            self.run_group(interp, &name, inner, &before, &after);
+                        }
+                        Err(e) => {
+                            self.take_entries();
+                            self.results.push(TestResult {
+                                name,
+                                error: Some(e.to_string()),
+                            });
+                        }
+                    }
+                }
+                Entry::It(title, test) => {
+                    let error = self.run_test(interp, test, &before, &after);
+                    self.results.push(TestResult {
+                        name: join(prefix, &title),
+                        error,
+                    });
+                }
+                Entry::BeforeEach(_) | Entry::AfterEach(_) => {}
+            }
+        }
+    }
+
+    /// Run one test between its hooks; the first error wins, and the
+    /// `after` hooks run even when the test failed
+    fn run_test(
+        &mut self,
+        interp: &mut LuaInterpreter,
+        test: LuaValue,
+        before: &[LuaValue],
+        after: &[LuaValue],
+    ) -> Option<String> {
+        let mut result = before
+            .iter()
+            .try_for_each(|hook| self.call(interp, hook.clone()))
+            .and_then(|_| self.call(interp, test));
+        for hook in after {
+            let hooked = self.call(interp, hook.clone());
+            result = result.and(hooked);
+        }
+        if !self.take_entries().is_empty() && result.is_ok() {
+            result = Err(LuaError::runtime(
+                "describe, it and the hooks cannot be called inside a test",
+                "it",
+            ));
+        }
+        result.err().map(|e| e.to_string())
+    }
+
+    fn call(&mut self, interp: &mut LuaInterpreter, function: LuaValue) -> LuaResult<()> {
+        self.executor
+            .call_function(function, Vec::new(), interp)
+            .map(|_| ())
+    }
+}
+
+fn join(prefix: &str, title: &str) -> String {
+    if prefix.is_empty() {
+        title.to_string()
+    } else {
+        format!("{} {}", prefix, title)
+    }
+}
+
+fn builtin(f: impl Fn(Vec<LuaValue>) -> LuaResult<LuaValue> + 'static) -> LuaValue {
+    LuaValue::Function(Rc::new(LuaFunction::Builtin(Rc::new(f))))
+}
+
+fn table(data: TableData) -> LuaValue {
+    LuaValue::Table(Rc::new(RefCell::new(LuaTable {
+        data,
+        metatable: None,
+        frozen: false,
+        removed: None,
+    })))
+}
+
+/// The table `expect(actual)` returns, with `never` holding the negations
+fn matchers(actual: LuaValue) -> LuaValue {
+    let mut data = matcher_table(&actual, false);
+    data.insert(
+        LuaValue::String("never".into()),
+        table(matcher_table(&actual, true)),
+    );
+    table(data)
+}
+
+type Check = fn(&LuaValue, &[LuaValue]) -> LuaResult<(bool, String)>;
+
+fn matcher_table(actual: &LuaValue, negated: bool) -> TableData {
+    let checks: [(&str, Check); 8] = [
+        ("to_be", |actual, args| {
+            let expected = arg(args, 0);
+            Ok((*actual == expected, format!("be {}", show(&expected))))
+        }),
+        ("to_equal", |actual, args| {
+            let expected = arg(args, 0);
+            let equal = deep_equal(actual, &expected, &mut Vec::new());
+            Ok((equal, format!("equal {}", show(&expected))))
+        }),
+        ("to_be_truthy", |actual, _| {
+            Ok((actual.is_truthy(), "be truthy".to_string()))
+        }),
+        ("to_be_falsy", |actual, _| {
+            Ok((!actual.is_truthy(), "be falsy".to_string()))
+        }),
+        ("to_be_nil", |actual, _| {
+            Ok((*actual == LuaValue::Nil, "be nil".to_string()))
+        }),
+        ("to_be_a", |actual, args| {
+            let LuaValue::String(name) = arg(args, 0) else {
+                return Err(LuaError::type_error(
+                    "string",
+                    arg(args, 0).type_name(),
+                    "to_be_a",
+                ));
+            };
+            Ok((name == actual.type_name(), format!("be a {}", name)))
+        }),
+        ("to_contain", |actual, args| {
+            let item = arg(args, 0);
+            let found = match (actual, &item) {
+                (LuaValue::String(s), LuaValue::String(part)) => {
+                    part.is_empty() || s.windows(part.len()).any(|w| w == &part[..])
+                }
+                (LuaValue::Table(t), _) => t
+                    .borrow()
+                    .data
+                    .values()
+                    .any(|value| deep_equal(value, &item, &mut Vec::new())),
+                _ => {
+                    return Err(LuaError::runtime(
+                        "to_contain needs a table, or a string and a substring",
+                        "to_contain",
+                    ))
+                }
+            };
+            Ok((found, format!("contain {}", show(&item))))
+        }),
+        ("to_be_close_to", |actual, args| {
+            let expected = arg(args, 0).to_number()?;
+            let delta = match args.get(1) {
+                Some(delta) => delta.to_number()?,
+                None => DEFAULT_DELTA,
+            };
+            let close = actual
+                .to_number()
+                .is_ok_and(|n| (n - expected).abs() <= delta);
+            Ok((close, format!("be within {} of {}", delta, expected)))
+        }),
+    ];
+    let mut data = TableData::new();
+    for (name, check) in checks {
+        let actual = actual.clone();
+        let matcher = builtin(move |args| {
+            let (held, description) = check(&actual, &args)?;
+            if held != negated {
+                return Ok(LuaValue::Nil);
+            }
+            let not = if negated { "not " } else { "" };
+            Err(LuaError::user(
+                format!("expected {} {}to {}", show(&actual), not, description),
+                1,
+            ))
+        });
+        data.insert(LuaValue::String(name.into()), matcher);
+    }
+    data
+}
+
+fn arg(args: &[LuaValue], index: usize) -> LuaValue {
+    args.get(index).cloned().unwrap_or(LuaValue::Nil)
+}
+
+/// Tables compare by contents, everything else as `rawequal` does;
+/// `seen` holds the pairs of tables already being compared, so cycles end
+fn deep_equal(a: &LuaValue, b: &LuaValue, seen: &mut Vec<(usize, usize)>) -> bool {
+    let (LuaValue::Table(x), LuaValue::Table(y)) = (a, b) else {
+        return a == b;
+    };
+    let pair = (x.as_ptr() as usize, y.as_ptr() as usize);
+    if Rc::ptr_eq(x, y) || seen.contains(&pair) {
+        return true;
+    }
+    seen.push(pair);
+    let (x, y) = (x.borrow(), y.borrow());
+    x.data.len() == y.data.len()
+        && x.data.iter().all(|(key, value)| {
+            y.data
+                .get(key)
+                .is_some_and(|other| deep_equal(value, other, seen))
+        })
+}
+
+/// A value as failure messages show it: strings quoted, tables by contents
+fn show(value: &LuaValue) -> String {
+    show_at(value, SHOW_DEPTH)
+}
+
+fn show_at(value: &LuaValue, depth: usize) -> String {
+    match value {
+        LuaValue::String(s) => format!("{:?}", s),
+        LuaValue::Table(t) if depth > 0 => {
+            let t = t.borrow();
+            let fields: Vec<String> = t
+                .data
+                .iter()
+                .enumerate()
+                .map(|(i, (key, value))| {
+                    let value = show_at(value, depth - 1);
+                    match key {
+                        LuaValue::Number(n) if *n == (i + 1) as f64 => value,
+                        LuaValue::String(name) => format!("{} = {}", name, value),
+                        _ => format!("[{}] = {}", show_at(key, 0), value),
+                    }
+                })
+                .collect();
+            format!("{{{}}}", fields.join(", "))
+        }
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(code: &str) -> Report {
+        run_source(&mut LuaInterpreter::new(), Path::new("t_test.lua"), code)
+    }
+
+    fn outcomes(report: &Report) -> Vec<(&str, bool)> {
+        report
+            .results
+            .iter()
+            .map(|result| (result.name.as_str(), result.passed()))
+            .collect()
+    }
+
+    #[test]
+    fn test_groups_hooks_and_outcomes() {
+        let report = run("log = {}
+             describe('outer', function()
+               before_each(function() log[#log + 1] = 'b' end)
+               after_each(function() log[#log + 1] = 'a' end)
+               it('passes', function() log[#log + 1] = 't' end)
+               describe('inner', function()
+                 it('fails', function() expect(1).to_equal(2) end)
+               end)
+             end)
+             it('runs at the top level', function() expect(log).to_contain('t') end)");
+        assert_eq!(report.error, None);
+        assert_eq!(
+            outcomes(&report),
+            vec![
+                ("outer passes", true),
+                ("outer inner fails", false),
+                ("runs at the top level", true),
+            ]
+        );
+        assert_eq!(
+            report.results[1].error.as_deref(),
+            Some("expected 1 to equal 2")
+        );
+        assert_eq!((report.passed(), report.failed()), (2, 1));
+    }
+
+    #[test]
+    fn test_matchers() {
+        let report = run("it('all hold', function()
+               expect({1, {x = 'y'}}).to_equal({1, {x = 'y'}})
+               expect({}).never.to_be({})
+               expect('hello').to_contain('ell')
+               expect(nil).to_be_nil()
+               expect(0).to_be_truthy()
+               expect(false).to_be_falsy()
+               expect(print).to_be_a('function')
+               expect(0.1 + 0.2).to_be_close_to(0.3)
+             end)
+             it('shows tables', function()
+               expect({1, k = 'v'}).never.to_equal({1, k = 'v'})
+             end)");
+        assert_eq!(report.results[0].error, None);
+        assert_eq!(
+            report.results[1].error.as_deref(),
+            Some("expected {1, k = \"v\"} not to equal {1, k = \"v\"}")
+        );
+    }
+
+    #[test]
+    fn test_file_errors_count_as_failures() {
+        let report = run("it('never runs', function() end) error('broken')");
+        assert!(report.results.is_empty());
+        assert_eq!(report.failed(), 1);
+        let report = run("it(");
+        assert!(report.error.is_some());
+    }
+
+    #[test]
+    fn test_discovers_test_files() {
+        let dir = std::env::temp_dir().join(format!("muscm-discover-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("spec")).unwrap();
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        for file in ["spec/stack.lua", "src/queue_test.lua", "src/queue.lua"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        let found = discover(std::slice::from_ref(&dir)).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            found,
+            vec![dir.join("spec/stack.lua"), dir.join("src/queue_test.lua")]
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! Timers for Lua scripts
+//!
+//! `timer.after` and `timer.every` queue a callback here; nothing runs it
+//! until the executor drives the queue, either in `Executor::run_timers`,
+//! which `muscm run --loop` calls once the main chunk has finished, or
+//! while a script waits in `timer.sleep`. Every callback runs on the
+//! interpreter's own thread, so periodic jobs need no thread per script.
+//!
+//! Times are read from the same clock as `os.time`, which also works in a
+//! browser.
+use crate::file_io::unix_time;
+use crate::lua_value::LuaValue;
+use std::time::Duration;
+
+/// Identifies a queued timer, for `timer.cancel`
+pub type TimerId = u64;
+
+#[derive(Debug)]
+struct Timer {
+    id: TimerId,
+    /// Clock time it next fires at
+    due: Duration,
+    /// Time between firings of a repeating timer
+    interval: Option<Duration>,
+    callback: LuaValue,
+}
+
+/// The queue of timers one interpreter and its children share
+#[derive(Debug, Default)]
+pub struct Timers {
+    next_id: TimerId,
+    queue: Vec<Timer>,
+}
+
+impl Timers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current time on the timers' clock
+    pub fn now() -> Duration {
+        unix_time()
+    }
+
+    /// Queue `callback` to run `delay` from now, and every `interval`
+    /// after that when one is given
+    pub fn add(
+        &mut self,
+        delay: Duration,
+        interval: Option<Duration>,
+        callback: LuaValue,
+    ) -> TimerId {
+        self.next_id += 1;
+        self.queue.push(Timer {
+            id: self.next_id,
+            due: Self::now() + delay,
+            interval,
+            callback,
+        });
+        self.next_id
+    }
+
+    /// Remove a timer; false when it already ran or was cancelled
+    pub fn cancel(&mut self, id: TimerId) -> bool {
+        let before = self.queue.len();
+        self.queue.retain(|timer| timer.id != id);
+        self.queue.len() < before
+    }
+
+    /// Number of queued timers
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// When the earliest timer fires, if any is queued
+    pub fn next_due(&self) -> Option<Duration> {
+        self.queue.iter().map(|timer| timer.due).min()
+    }
+
+    /// Take the earliest timer that is due at `now`, with its callback
+    ///
+    /// A repeating timer stays queued for its next firing, which is one
+    /// interval after this one, or `now` when it has fallen behind. Timers
+    /// due at the same time fire in the order they were queued.
+    pub fn take_due(&mut self, now: Duration) -> Option<(TimerId, LuaValue)> {
+        let index = (0..self.queue.len())
+            .filter(|&i| self.queue[i].due <= now)
+            .min_by_key(|&i| (self.queue[i].due, self.queue[i].id))?;
+        let timer = &mut self.queue[index];
+        let fired = (timer.id, timer.callback.clone());
+        match timer.interval {
+            Some(interval) => timer.due = (timer.due + interval).max(now),
+            None => {
+                self.queue.remove(index);
+            }
+        }
+        Some(fired)
+    }
+}
+
+/// Wait for `duration` on the interpreter's thread
+pub fn pause(duration: Duration) {
+    // A browser cannot block its thread, so there the caller polls the clock
+    #[cfg(not(target_family = "wasm"))]
+    std::thread::sleep(duration);
+    #[cfg(target_family = "wasm")]
+    let _ = duration;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_due_timers_fire_in_order() {
+        let mut timers = Timers::new();
+        let later = timers.add(Duration::from_secs(60), None, LuaValue::Number(1.0));
+        let first = timers.add(Duration::ZERO, None, LuaValue::Number(2.0));
+        let second = timers.add(Duration::ZERO, None, LuaValue::Number(3.0));
+        let now = Timers::now();
+        assert_eq!(timers.take_due(now), Some((first, LuaValue::Number(2.0))));
+        assert_eq!(timers.take_due(now), Some((second, LuaValue::Number(3.0))));
+        assert_eq!(timers.take_due(now), None);
+        assert_eq!(timers.len(), 1);
+        assert!(timers.next_due().unwrap() > now);
+        assert!(timers.cancel(later));
+        assert!(!timers.cancel(later));
+        assert!(timers.is_empty());
+    }
+
+    #[test]
+    fn test_repeating_timers_stay_queued() {
+        let mut timers = Timers::new();
+        let id = timers.add(Duration::ZERO, Some(Duration::from_secs(10)), LuaValue::Nil);
+        let now = Timers::now();
+        assert_eq!(timers.take_due(now), Some((id, LuaValue::Nil)));
+        assert_eq!(timers.take_due(now), None);
+        // Far behind, it fires once and is then due again right away
+        let late = now + Duration::from_secs(100);
+        assert!(timers.take_due(late).is_some());
+        assert_eq!(timers.next_due(), Some(late));
+        assert_eq!(timers.len(), 1);
+    }
+}
@@ -1,5 +1,10 @@
 //! Tokenizer for Scheme expressions
 //! Based on TinyScheme tokenization logic
+//!
+//! `Tokenizer` is an iterator that lexes one token at a time, so a parser
+//! can consume tokens without collecting them first. Every token starts in
+//! a fresh lexer state, which lets `retokenize` update the tokens of an
+//! edited text by re-lexing only around the edit.
 
 use std::fmt;
 
@@ -10,7 +15,10 @@ pub enum TokenType {
     Dot,
     Atom,
     Quote,
+    /// A `"` with no closing quote after it
     DQuote,
+    /// A string literal; `literal` holds its contents with escapes decoded
+    String,
     BQuote,
     Comma,
     AtMark,
@@ -29,6 +37,7 @@ impl fmt::Display for TokenType {
             TokenType::Atom => write!(f, "Atom"),
             TokenType::Quote => write!(f, "Quote"),
             TokenType::DQuote => write!(f, "DQuote"),
+            TokenType::String => write!(f, "String"),
             TokenType::BQuote => write!(f, "BQuote"),
             TokenType::Comma => write!(f, "Comma"),
             TokenType::AtMark => write!(f, "AtMark"),
@@ -49,6 +58,7 @@ pub struct Token {
     pub literal: String,
 }
 
+/// Lazily yields the tokens of `input`, stopping before `Eof`
 pub struct Tokenizer<'a> {
     input: &'a str,
     pos: usize,
@@ -57,11 +67,13 @@ pub struct Tokenizer<'a> {
 
 impl<'a> Tokenizer<'a> {
     pub fn new(input: &'a str) -> Self {
-        Tokenizer {
-            input,
-            pos: 0,
-            line: 1,
-        }
+        Self::starting_at(input, 0, 1)
+    }
+
+    /// Start lexing at byte offset `pos`, which is on `line`; `pos` must be
+    /// where a token or the whitespace before one begins
+    pub fn starting_at(input: &'a str, pos: usize, line: usize) -> Self {
+        Tokenizer { input, pos, line }
     }
 
     fn peek(&self) -> Option<u8> {
@@ -107,6 +119,25 @@ impl<'a> Tokenizer<'a> {
         }
     }
 
+    /// Read the rest of a string literal whose opening quote was consumed,
+    /// decoding the escapes `write` produces; `None` if it never closes
+    fn string_body(&mut self) -> Option<String> {
+        let mut bytes = Vec::new();
+        loop {
+            match self.consume()? {
+                b'"' => return String::from_utf8(bytes).ok(),
+                b'\\' => match self.consume()? {
+                    b'n' => bytes.push(b'\n'),
+                    b't' => bytes.push(b'\t'),
+                    b'r' => bytes.push(b'\r'),
+                    c @ (b'"' | b'\\') => bytes.push(c),
+                    c => bytes.extend([b'\\', c]),
+                },
+                c => bytes.push(c),
+            }
+        }
+    }
+
     pub fn next_token(&mut self) -> Token {
         loop {
             // Skip leading whitespace
@@ -166,6 +197,18 @@ impl<'a> Tokenizer<'a> {
                 }
                 Some(b'"') => {
                     self.consume();
+                    if let Some(literal) = self.string_body() {
+                        return Token {
+                            token_type: TokenType::String,
+                            start: start_pos,
+                            end: self.pos,
+                            line: start_line,
+                            literal,
+                        };
+                    }
+                    // Unterminated: hand back just the quote and lex on after it
+                    self.pos = start_pos + 1;
+                    self.line = start_line;
                     return Token {
                         token_type: TokenType::DQuote,
                         start: start_pos,
@@ -267,10 +310,19 @@ impl<'a> Tokenizer<'a> {
                             // Loop to get next token after shebang
                             continue;
                         }
-                        Some(c) if Self::is_one_of(" tfodxb\\", c) => {
+                        Some(c) if Self::is_one_of(" tfodxbei\\", c) => {
                             // Sharp constant: consume the special char and any following atom chars
                             self.consume();
-                            if c != b' ' {
+                            if c == b'\\' {
+                                // The character itself may be a delimiter such as `(` or a
+                                // space, and may take several bytes
+                                if self.consume().is_some() {
+                                    while self.peek().is_some_and(|b| b & 0xC0 == 0x80) {
+                                        self.consume();
+                                    }
+                                }
+                                self.skip_atom();
+                            } else if c != b' ' {
                                 self.skip_atom();
                             }
                             let literal = self.input[start_pos..self.pos].to_string();
@@ -320,15 +372,16 @@ impl<'a> Tokenizer<'a> {
 
     /// Get all tokens until EOF
     pub fn tokenize(&mut self) -> Vec<Token> {
-        let mut tokens = Vec::new();
-        loop {
-            let token = self.next_token();
-            if token.token_type == TokenType::Eof {
-                break;
-            }
-            tokens.push(token);
-        }
-        tokens
+        self.collect()
+    }
+}
+
+impl Iterator for Tokenizer<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.next_token();
+        (token.token_type != TokenType::Eof).then_some(token)
     }
 }
 
@@ -337,6 +390,68 @@ pub fn tokenize_string(input: &str) -> Vec<Token> {
     tokenizer.tokenize()
 }
 
+/// A change to tokenized text, in byte offsets: `start..old_end` of the old
+/// text became `start..new_end` of the new text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextEdit {
+    pub start: usize,
+    pub old_end: usize,
+    pub new_end: usize,
+}
+
+/// Update `tokens`, the tokens of the text before `edit`, to those of
+/// `input`, the text after it
+///
+/// Tokens that end before the edit are kept as they are. Lexing resumes
+/// after the last of them and stops as soon as it produces a token that
+/// the old text had at the same place past the edit; the rest of the old
+/// tokens are reused, moved by the edit's change in length and lines.
+/// The result is always what `tokenize_string(input)` would return.
+pub fn retokenize(tokens: &[Token], input: &str, edit: TextEdit) -> Vec<Token> {
+    // A token's lexing looks one byte past its end, so it is only safe to
+    // keep when that byte comes before the edit. A lone `"` looked for a
+    // closing quote all the way to the end, so none is kept, nor what follows
+    let kept = tokens.partition_point(|t| t.end < edit.start);
+    let kept = tokens[..kept]
+        .iter()
+        .position(|t| t.token_type == TokenType::DQuote)
+        .unwrap_or(kept);
+    let mut result = tokens[..kept].to_vec();
+    // Only string literals span lines, so count the newlines in the last one
+    let (pos, line) = result.last().map_or((0, 1), |t| {
+        (t.end, t.line + input[t.start..t.end].matches('\n').count())
+    });
+
+    let shift = |offset: usize| offset + edit.new_end - edit.old_end;
+    let mut old = kept;
+    for token in Tokenizer::starting_at(input, pos, line) {
+        if token.start >= edit.new_end {
+            // Skip old tokens that start before this one's old position
+            while old < tokens.len()
+                && (tokens[old].start < edit.old_end || shift(tokens[old].start) < token.start)
+            {
+                old += 1;
+            }
+            if let Some(same) = tokens.get(old).filter(|t| {
+                shift(t.start) == token.start
+                    && t.token_type == token.token_type
+                    && t.literal == token.literal
+            }) {
+                let lines = token.line as isize - same.line as isize;
+                result.extend(tokens[old..].iter().map(|t| Token {
+                    start: shift(t.start),
+                    end: shift(t.end),
+                    line: (t.line as isize + lines) as usize,
+                    ..t.clone()
+                }));
+                return result;
+            }
+        }
+        result.push(token);
+    }
+    result
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -447,19 +562,36 @@ mod tests {
     fn test_sharp_const() {
         let tokens = tokenize_string("#t #f #\\n #x1F");
         assert_eq!(tokens.len(), 4);
-        for i in 0..4 {
-            assert_eq!(tokens[i].token_type, TokenType::SharpConst);
+        for token in &tokens {
+            assert_eq!(token.token_type, TokenType::SharpConst);
         }
     }
 
     #[test]
     fn test_string_literal() {
-        let tokens = tokenize_string("\"hello world\"");
-        // Tokenizer returns DQuote, then atoms for the content, then another DQuote
-        // String parsing is left to the parser
-        assert_eq!(tokens[0].token_type, TokenType::DQuote);
-        assert_eq!(tokens[0].literal, "\"");
-        assert!(tokens.len() >= 3); // at least opening quote, content, closing quote
+        let tokens = tokenize_string(r#"("hello  world" "a\"b\\c\nd\te\rf" "(;)")"#);
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(tokens[1].token_type, TokenType::String);
+        assert_eq!(tokens[1].literal, "hello  world");
+        assert_eq!(tokens[2].literal, "a\"b\\c\nd\te\rf");
+        assert_eq!(tokens[3].literal, "(;)");
+        assert_eq!(tokens[4].token_type, TokenType::RParen);
+    }
+
+    #[test]
+    fn test_unterminated_string_is_a_lone_dquote() {
+        let tokens = tokenize_string("(display \"oops)");
+        assert_eq!(tokens[2].token_type, TokenType::DQuote);
+        assert_eq!(tokens[3].literal, "oops");
+        assert_eq!(tokens[4].token_type, TokenType::RParen);
+    }
+
+    #[test]
+    fn test_shebang_line_is_skipped() {
+        let tokens = tokenize_string("#!/usr/bin/env muscm\n(display 1)");
+        assert_eq!(tokens.len(), 4);
+        assert_eq!(tokens[0].token_type, TokenType::LParen);
+        assert_eq!(tokens[0].line, 2);
     }
 
     #[test]
@@ -581,4 +713,66 @@ mod tests {
         assert_eq!(tokens[1].token_type, TokenType::SharpConst);
         assert_eq!(tokens[2].token_type, TokenType::SharpConst);
     }
+
+    #[test]
+    fn test_tokens_are_yielded_lazily() {
+        let mut tokenizer = Tokenizer::new("(a\n b)");
+        let first = tokenizer.next().unwrap();
+        assert_eq!(
+            (first.token_type, first.start, first.line),
+            (TokenType::LParen, 0, 1)
+        );
+        let rest: Vec<_> = tokenizer.map(|t| (t.literal, t.line)).collect();
+        assert_eq!(
+            rest,
+            vec![("a".into(), 1), ("b".into(), 2), (")".into(), 2)]
+        );
+    }
+
+    // Apply `replacement` to `old` at `range` and check retokenizing
+    // matches tokenizing the new text from scratch
+    fn check_retokenize(old: &str, range: std::ops::Range<usize>, replacement: &str) {
+        let new = format!(
+            "{}{}{}",
+            &old[..range.start],
+            replacement,
+            &old[range.end..]
+        );
+        let edit = TextEdit {
+            start: range.start,
+            old_end: range.end,
+            new_end: range.start + replacement.len(),
+        };
+        let tokens = retokenize(&tokenize_string(old), &new, edit);
+        assert_eq!(
+            tokens,
+            tokenize_string(&new),
+            "editing {:?} into {:?}",
+            old,
+            new
+        );
+    }
+
+    #[test]
+    fn test_retokenize_matches_full_tokenize() {
+        let old = "(define (f x)\n  (+ x 1)) ; done\n(f 2)\n";
+        check_retokenize(old, 9..9, "ab");
+        check_retokenize(old, 11..12, "yy");
+        check_retokenize(old, 12..12, " z");
+        check_retokenize(old, 14..14, "\n\n");
+        check_retokenize(old, 16..17, "-");
+        check_retokenize(old, 0..0, ";");
+        check_retokenize(old, 25..25, "\n");
+        check_retokenize(old, 27..33, "");
+        check_retokenize(old, old.len()..old.len(), "(g)");
+        check_retokenize(old, 0..old.len(), "'x");
+        check_retokenize("(a . b)", 4..4, "c");
+
+        let strings = "(f \"a\nb\" x)\n(g \"c\")\n";
+        check_retokenize(strings, 11..11, "y");
+        check_retokenize(strings, 18..18, "\\\"");
+        check_retokenize(strings, 4..4, "\"");
+        check_retokenize("(f \"a) (g b)", 12..12, "\"");
+        check_retokenize("(f \"a) (g \"b)", 11..12, "");
+    }
 }
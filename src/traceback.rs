@@ -0,0 +1,304 @@
+/// Call-stack tracking for tracebacks
+///
+/// The tree-walking executor pushes a frame for every function it calls and
+/// records the line of each statement in the innermost frame. The stack is
+/// shared with the interpreter so `debug.traceback` can read it, and the
+/// traceback at the point an error was raised is kept until the host
+/// reports the error.
+use crate::error_types::LuaError;
+use crate::lua_value::{LuaFunction, LuaValue};
+use std::fmt;
+use std::rc::Rc;
+
+/// How a call site named the function it called, in Lua's wording
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallName {
+    Global(Rc<str>),
+    Local(Rc<str>),
+    Upvalue(Rc<str>),
+    Field(Rc<str>),
+    Method(Rc<str>),
+}
+
+impl CallName {
+    /// The name, without what kind of name it is
+    pub fn name(&self) -> &Rc<str> {
+        match self {
+            CallName::Global(name)
+            | CallName::Local(name)
+            | CallName::Upvalue(name)
+            | CallName::Field(name)
+            | CallName::Method(name) => name,
+        }
+    }
+
+    /// What kind of name it is, as `debug.getinfo` reports in `namewhat`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CallName::Global(_) => "global",
+            CallName::Local(_) => "local",
+            CallName::Upvalue(_) => "upvalue",
+            CallName::Field(_) => "field",
+            CallName::Method(_) => "method",
+        }
+    }
+}
+
+impl fmt::Display for CallName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallName::Global(name) => write!(f, "function '{}'", name),
+            CallName::Local(name) => write!(f, "local '{}'", name),
+            CallName::Upvalue(name) => write!(f, "upvalue '{}'", name),
+            CallName::Field(name) => write!(f, "field '{}'", name),
+            CallName::Method(name) => write!(f, "method '{}'", name),
+        }
+    }
+}
+
+/// One active function call
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    /// None when the call site did not name the function
+    pub name: Option<CallName>,
+    /// The function being called
+    pub function: LuaValue,
+    /// Index of the call's slot frame in `LuaInterpreter::frames`, for
+    /// resolved Lua functions
+    pub slot_frame: Option<usize>,
+    /// Line of the statement running in this frame (0 when unknown)
+    pub line: usize,
+}
+
+impl TraceFrame {
+    /// Builtins run no statements, so they have no line
+    pub fn is_builtin(&self) -> bool {
+        !matches!(&self.function, LuaValue::Function(f) if matches!(**f, LuaFunction::User { .. }))
+    }
+}
+
+/// One level of the call stack, as `debug` functions address it
+#[derive(Debug, Clone, Copy)]
+pub enum StackLevel<'a> {
+    Call(&'a TraceFrame),
+    Main,
+}
+
+/// Levels a long traceback keeps from the innermost and outermost ends,
+/// the counts C Lua's `luaL_traceback` uses
+const INNERMOST_LEVELS: usize = 10;
+const OUTERMOST_LEVELS: usize = 11;
+
+/// The executor's call stack
+#[derive(Debug, Default)]
+pub struct CallTrace {
+    frames: Vec<TraceFrame>,
+    /// Line of the statement running in the main chunk
+    chunk_line: usize,
+    /// Name of the main chunk (see `LuaArena::source`)
+    chunk_source: Option<Rc<str>>,
+    /// Traceback of the most recent error, with the error it belongs to
+    error: Option<(LuaError, String)>,
+}
+
+impl CallTrace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, name: Option<CallName>, function: LuaValue, slot_frame: Option<usize>) {
+        self.frames.push(TraceFrame {
+            name,
+            function,
+            slot_frame,
+            line: 0,
+        });
+    }
+
+    pub fn pop(&mut self) {
+        self.frames.pop();
+    }
+
+    /// Number of active calls
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Active calls, outermost first
+    pub fn frames(&self) -> &[TraceFrame] {
+        &self.frames
+    }
+
+    /// What runs `level` calls up from the innermost frame, or `None` when
+    /// the stack is not that deep
+    pub fn level(&self, level: usize) -> Option<StackLevel<'_>> {
+        match self.frames.len().checked_sub(level)? {
+            0 => Some(StackLevel::Main),
+            above => Some(StackLevel::Call(&self.frames[above - 1])),
+        }
+    }
+
+    /// Line running in the main chunk (0 when unknown)
+    pub fn chunk_line(&self) -> usize {
+        self.chunk_line
+    }
+
+    /// Name of the main chunk, when it was given one
+    pub fn chunk_source(&self) -> Option<&Rc<str>> {
+        self.chunk_source.as_ref()
+    }
+
+    /// A chunk named `source` is about to run; it is the main chunk when
+    /// no call is running
+    pub fn enter_chunk(&mut self, source: Option<&Rc<str>>) {
+        if self.frames.is_empty() {
+            self.chunk_source = source.cloned();
+        }
+    }
+
+    /// Line of the statement running in the innermost frame (0 when unknown)
+    pub fn current_line(&self) -> usize {
+        self.frames
+            .last()
+            .map_or(self.chunk_line, |frame| frame.line)
+    }
+
+    /// Record the line of the statement about to run in the innermost frame
+    pub fn set_line(&mut self, line: usize) {
+        match self.frames.last_mut() {
+            Some(frame) => frame.line = line,
+            None => self.chunk_line = line,
+        }
+    }
+
+    /// Render the stack as Lua does, skipping the `level` innermost calls
+    ///
+    /// A deep stack, as after a stack overflow, keeps only its innermost
+    /// and outermost levels, with a line saying how many were left out.
+    pub fn traceback(&self, level: usize) -> String {
+        let shown = self.frames.len().saturating_sub(level);
+        let mut lines: Vec<String> = self.frames[..shown]
+            .iter()
+            .rev()
+            .map(|frame| {
+                let location = if frame.is_builtin() {
+                    "[C]".to_string()
+                } else {
+                    line_label(frame.line)
+                };
+                match &frame.name {
+                    Some(name) => format!("\n\t{}: in {}", location, name),
+                    None => format!("\n\t{}: in ?", location),
+                }
+            })
+            .collect();
+        if level <= self.frames.len() {
+            lines.push(format!(
+                "\n\t{}: in main chunk",
+                line_label(self.chunk_line)
+            ));
+        }
+        if lines.len() > INNERMOST_LEVELS + OUTERMOST_LEVELS {
+            let skipped = lines.len() - INNERMOST_LEVELS - OUTERMOST_LEVELS;
+            lines.splice(
+                INNERMOST_LEVELS..lines.len() - OUTERMOST_LEVELS,
+                [format!("\n\t...\t(skipping {} levels)", skipped)],
+            );
+        }
+        let mut out = String::from("stack traceback:");
+        out.extend(lines);
+        out
+    }
+
+    /// Remember the traceback for `error` as it propagates out of a frame
+    ///
+    /// The first call for an error wins, so the traceback shows where it
+    /// was raised rather than where it was last seen.
+    pub fn record_error(&mut self, error: &LuaError) {
+        if !matches!(&self.error, Some((recorded, _)) if recorded == error) {
+            self.error = Some((error.clone(), self.traceback(0)));
+        }
+    }
+
+    /// Take the traceback recorded for `error`, if it is the latest error
+    pub fn take_error_traceback(&mut self, error: &LuaError) -> Option<String> {
+        match self.error.take() {
+            Some((recorded, traceback)) if &recorded == error => Some(traceback),
+            _ => None,
+        }
+    }
+}
+
+fn line_label(line: usize) -> String {
+    if line == 0 {
+        "?".to_string()
+    } else {
+        format!("line {}", line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua_parser::{Block, FunctionBody, FunctionRef, LuaArena};
+    use crate::upvalues::ClosureState;
+
+    fn lua_function() -> LuaValue {
+        let mut arena = LuaArena::new();
+        let block = arena.alloc_block(Block::default());
+        let id = arena.alloc_function(FunctionBody {
+            params: Vec::new(),
+            varargs: false,
+            block,
+            layout: None,
+            lines: None,
+        });
+        LuaValue::Function(Rc::new(LuaFunction::User {
+            body: FunctionRef {
+                arena: Rc::new(arena),
+                id,
+            },
+            captured: ClosureState::new(),
+        }))
+    }
+
+    #[test]
+    fn test_traceback_lists_innermost_first() {
+        let mut trace = CallTrace::new();
+        trace.set_line(9);
+        trace.push(Some(CallName::Local("outer".into())), lua_function(), None);
+        trace.set_line(5);
+        trace.push(Some(CallName::Global("error".into())), LuaValue::Nil, None);
+        assert_eq!(
+            trace.traceback(0),
+            "stack traceback:\n\t[C]: in function 'error'\n\tline 5: in local 'outer'\n\tline 9: in main chunk"
+        );
+        assert_eq!(
+            trace.traceback(1),
+            "stack traceback:\n\tline 5: in local 'outer'\n\tline 9: in main chunk"
+        );
+        assert_eq!(trace.traceback(3), "stack traceback:");
+    }
+
+    #[test]
+    fn test_error_traceback_is_kept_from_the_innermost_frame() {
+        let mut trace = CallTrace::new();
+        let error = LuaError::user("boom", 1);
+        trace.push(None, lua_function(), None);
+        trace.set_line(2);
+        trace.record_error(&error);
+        trace.pop();
+        trace.record_error(&error);
+
+        let other = LuaError::user("other", 1);
+        assert_eq!(trace.take_error_traceback(&other), None);
+        trace.push(None, lua_function(), None);
+        trace.set_line(2);
+        trace.record_error(&error);
+        assert_eq!(
+            trace.take_error_traceback(&error).as_deref(),
+            Some("stack traceback:\n\tline 2: in ?\n\t?: in main chunk")
+        );
+        assert_eq!(trace.take_error_traceback(&error), None);
+    }
+}
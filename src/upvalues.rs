@@ -1,43 +1,61 @@
 /// Upvalue handling for closures
 /// Enables proper variable capture in nested functions
 ///
-/// Note: This is a simplified implementation for Phase 7.
-/// Full implementation would require comprehensive AST analysis.
+/// Local variables live in shared cells, so a closure captures the cell
+/// rather than a copy of the value. Every closure created in the same scope
+/// sees writes made by the others, and by the scope itself.
+use crate::lua_parser::{
+    BlockId, Expression, FieldKey, FunctionBody, FunctionId, LuaArena, Statement,
+};
 use crate::lua_value::LuaValue;
-use std::collections::HashMap;
+use crate::stack::with_headroom;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+/// Storage for a local variable, shared between its scope and any closures
+pub type UpvalueCell = Rc<RefCell<LuaValue>>;
 
 /// Represents a variable from an outer scope that is captured by a closure
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct Upvalue {
     /// Name of the captured variable
     pub name: String,
-    /// Scope depth where the variable is defined
-    pub scope_depth: usize,
-    /// Current value of the upvalue
-    pub value: LuaValue,
+    /// Cell shared with the defining scope
+    pub cell: UpvalueCell,
 }
 
 impl Upvalue {
-    pub fn new(name: String, scope_depth: usize, value: LuaValue) -> Self {
-        Upvalue {
-            name,
-            scope_depth,
-            value,
-        }
+    pub fn new(name: String, cell: UpvalueCell) -> Self {
+        Upvalue { name, cell }
+    }
+
+    /// Current value of the captured variable
+    pub fn get(&self) -> LuaValue {
+        self.cell.borrow().clone()
+    }
+
+    /// Write through to the captured variable
+    pub fn set(&self, value: LuaValue) {
+        *self.cell.borrow_mut() = value;
     }
 }
 
-/// Closure state - captures the upvalues when a function is created
+/// Closure state - the upvalues captured when a function is created
 #[derive(Debug, Clone)]
 pub struct ClosureState {
-    /// Captured upvalues with their values at closure creation time
+    /// Captured upvalues, one per free local the body references
     pub upvalues: Vec<Upvalue>,
+    /// Cells captured from enclosing frames of a resolved function, in
+    /// `FrameLayout::captures` order
+    pub cells: Vec<UpvalueCell>,
 }
 
 impl ClosureState {
     pub fn new() -> Self {
         ClosureState {
             upvalues: Vec::new(),
+            cells: Vec::new(),
         }
     }
 
@@ -48,31 +66,178 @@ impl ClosureState {
     pub fn get_upvalue(&self, name: &str) -> Option<&Upvalue> {
         self.upvalues.iter().find(|u| u.name == name)
     }
+}
 
-    pub fn update_upvalue(&mut self, name: &str, value: LuaValue) {
-        if let Some(upvalue) = self.upvalues.iter_mut().find(|u| u.name == name) {
-            upvalue.value = value;
-        }
+impl Default for ClosureState {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    pub fn to_locals(&self) -> HashMap<String, LuaValue> {
-        self.upvalues
-            .iter()
-            .map(|u| (u.name.clone(), u.value.clone()))
-            .collect()
+/// Names a function body references that are not its own parameters.
+///
+/// This is deliberately conservative: names bound by locals inside the body
+/// are still reported, and the caller only captures those that resolve to a
+/// local in the defining scope. Capturing an extra cell is harmless because
+/// the body's own `local` shadows it. Nested blocks and functions are found
+/// in `arena`.
+pub fn find_free_variables(arena: &LuaArena, body: &FunctionBody) -> Vec<String> {
+    let mut names = BTreeSet::new();
+    collect_block(arena, body.block, &mut names);
+    for param in &body.params {
+        names.remove(param);
     }
+    names.into_iter().collect()
 }
 
-impl Default for ClosureState {
-    fn default() -> Self {
-        Self::new()
+fn collect_block(arena: &LuaArena, block: BlockId, names: &mut BTreeSet<String>) {
+    let block = arena.block(block);
+    for statement in &block.statements {
+        collect_statement(arena, statement, names);
     }
+    if let Some(ret) = &block.return_statement {
+        for expr in &ret.expression_list {
+            collect_expression(arena, expr, names);
+        }
+    }
+}
+
+fn collect_statement(arena: &LuaArena, statement: &Statement, names: &mut BTreeSet<String>) {
+    with_headroom(|| collect_statement_node(arena, statement, names))
 }
 
-/// Simple free variable finder for Phase 7
-/// In a full implementation, this would do comprehensive AST analysis
-pub fn find_free_variables(_params: &[String]) -> Vec<String> {
-    // Phase 7 simplified: return empty list
-    // Full implementation would analyze the function body to find undefined references
-    Vec::new()
+fn collect_statement_node(arena: &LuaArena, statement: &Statement, names: &mut BTreeSet<String>) {
+    match statement {
+        Statement::Empty | Statement::Break | Statement::Label(_) | Statement::Goto(_) => {}
+        Statement::Assignment { variables, values } => {
+            for expr in variables.iter().chain(values) {
+                collect_expression(arena, expr, names);
+            }
+        }
+        Statement::FunctionCall(expr) => collect_expression(arena, expr, names),
+        Statement::Do(body) => collect_block(arena, *body, names),
+        Statement::While { condition, body } | Statement::Repeat { body, condition } => {
+            collect_expression(arena, condition, names);
+            collect_block(arena, *body, names);
+        }
+        Statement::If {
+            condition,
+            then_block,
+            elseif_parts,
+            else_block,
+        } => {
+            collect_expression(arena, condition, names);
+            collect_block(arena, *then_block, names);
+            for (cond, block) in elseif_parts {
+                collect_expression(arena, cond, names);
+                collect_block(arena, *block, names);
+            }
+            if let Some(block) = else_block {
+                collect_block(arena, *block, names);
+            }
+        }
+        Statement::ForNumeric {
+            start,
+            end,
+            step,
+            body,
+            ..
+        }
+        | Statement::ForNumericSlot {
+            start,
+            end,
+            step,
+            body,
+            ..
+        } => {
+            collect_expression(arena, start, names);
+            collect_expression(arena, end, names);
+            if let Some(step) = step {
+                collect_expression(arena, step, names);
+            }
+            collect_block(arena, *body, names);
+        }
+        Statement::ForGeneric {
+            iterables, body, ..
+        }
+        | Statement::ForGenericSlots {
+            iterables, body, ..
+        } => {
+            for expr in iterables {
+                collect_expression(arena, expr, names);
+            }
+            collect_block(arena, *body, names);
+        }
+        Statement::FunctionDecl { name, body } => {
+            // `function t.a.b()` assigns through `t`
+            names.insert(name.base().to_string());
+            collect_function(arena, *body, names);
+        }
+        Statement::LocalFunction { body, .. } => collect_function(arena, *body, names),
+        Statement::LocalVars { values, .. } | Statement::LocalSlots { values, .. } => {
+            for expr in values.iter().flatten() {
+                collect_expression(arena, expr, names);
+            }
+        }
+    }
+}
+
+fn collect_function(arena: &LuaArena, body: FunctionId, names: &mut BTreeSet<String>) {
+    // Nested closures capture through this function, so their free
+    // variables are free here too
+    names.extend(find_free_variables(arena, arena.function(body)));
+}
+
+fn collect_expression(arena: &LuaArena, expr: &Expression, names: &mut BTreeSet<String>) {
+    with_headroom(|| collect_expression_node(arena, expr, names))
+}
+
+fn collect_expression_node(arena: &LuaArena, expr: &Expression, names: &mut BTreeSet<String>) {
+    match expr {
+        // Resolved references are bound through slots, not by name
+        Expression::Nil
+        | Expression::Boolean(_)
+        | Expression::Number(_)
+        | Expression::String(_)
+        | Expression::Varargs
+        | Expression::Local { .. }
+        | Expression::Upvalue { .. }
+        | Expression::Global(_) => {}
+        Expression::Identifier(name) => {
+            names.insert(name.to_string());
+        }
+        Expression::BinaryOp { left, right, .. } => {
+            collect_expression(arena, left, names);
+            collect_expression(arena, right, names);
+        }
+        Expression::UnaryOp { operand, .. } | Expression::Paren(operand) => {
+            collect_expression(arena, operand, names)
+        }
+        Expression::TableIndexing { object, index } => {
+            collect_expression(arena, object, names);
+            collect_expression(arena, index, names);
+        }
+        Expression::FieldAccess { object, .. } => collect_expression(arena, object, names),
+        Expression::FunctionCall { function, args } => {
+            collect_expression(arena, function, names);
+            for arg in args {
+                collect_expression(arena, arg, names);
+            }
+        }
+        Expression::MethodCall { object, args, .. } => {
+            collect_expression(arena, object, names);
+            for arg in args {
+                collect_expression(arena, arg, names);
+            }
+        }
+        Expression::TableConstructor { fields } => {
+            for field in fields {
+                if let FieldKey::Bracket(key) = &field.key {
+                    collect_expression(arena, key, names);
+                }
+                collect_expression(arena, &field.value, names);
+            }
+        }
+        Expression::FunctionDef(body) => collect_function(arena, *body, names),
+    }
 }
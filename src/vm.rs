@@ -0,0 +1,854 @@
+/// Stack-based VM for compiled Lua code
+///
+/// Runs the bytecode produced by the `compiler` module, for main chunks and
+/// for the bodies of resolved functions as they are called. Operators,
+/// indexing and calls go through the same `Executor` helpers as the
+/// tree-walker, and locals live in the same slot frames, so both paths
+/// agree on semantics and call each other freely; the VM only removes the
+/// per-node dispatch from the hot path.
+use crate::compiler::{self, Chunk, Instr, Target};
+use crate::error_types::{LuaError, LuaResult};
+use crate::executor::{ControlFlow, Executor, GenericFor, NumericFor};
+use crate::lua_interpreter::{LuaInterpreter, SlotFrame};
+use crate::lua_parser::{self, BinaryOp, FunctionId, FunctionRef, LuaArena};
+use crate::lua_value::LuaValue;
+use crate::resolver;
+use crate::stack::with_headroom;
+use crate::upvalues::Upvalue;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+/// Which executor runs a chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    Vm,
+    TreeWalker,
+}
+
+/// Execute a chunk with the engine `engine_for` picks: the VM when it is
+/// turned on and can run the chunk, else the tree-walking executor. Either
+/// way the chunk is resolved first, so its functions see the chunk-level
+/// locals earlier chunks left in the interpreter and can run on the VM.
+pub fn execute_chunk(
+    source: &lua_parser::Chunk,
+    interp: &mut LuaInterpreter,
+) -> LuaResult<ControlFlow> {
+    let resolved = resolver::resolve_within(source, interp.local_names());
+    let mut vm = Vm::new();
+    match bytecode_for(&resolved, &vm.executor, interp) {
+        Some(chunk) => vm.run(&chunk, interp),
+        None => vm.executor.execute_block(&resolved, interp),
+    }
+}
+
+/// The engine `execute_chunk` runs `source`'s main chunk on; the functions
+/// it calls pick theirs as they are called
+pub fn engine_for(source: &lua_parser::Chunk, interp: &LuaInterpreter) -> Engine {
+    let resolved = resolver::resolve_within(source, interp.local_names());
+    match bytecode_for(&resolved, &Executor::new(), interp) {
+        Some(_) => Engine::Vm,
+        None => Engine::TreeWalker,
+    }
+}
+
+/// The compiled chunk, if it should run on the VM
+///
+/// Hooks, coverage, the debugger and the profiler only see the
+/// tree-walker, so nothing is compiled while one of them is on (see
+/// `Executor::vm_allowed`), nor is code that uses the `debug` library,
+/// which can install a hook part way through.
+fn bytecode_for(
+    source: &lua_parser::Chunk,
+    executor: &Executor,
+    interp: &LuaInterpreter,
+) -> Option<Chunk> {
+    if !executor.vm_allowed(interp) {
+        return None;
+    }
+    compiler::compile(source).ok().filter(avoids_debug)
+}
+
+fn avoids_debug(chunk: &Chunk) -> bool {
+    !chunk.names.iter().any(|name| name == "debug")
+}
+
+/// Bytecode of the functions the VM has been asked to run, by definition
+#[derive(Default)]
+pub struct CodeCache {
+    functions: HashMap<(usize, FunctionId), Compiled>,
+    /// Size at which entries of freed arenas are next dropped
+    sweep_at: usize,
+}
+
+struct Compiled {
+    /// Keeps the arena's address from being reused while it is a key
+    arena: Weak<LuaArena>,
+    /// `None` when the body runs on the tree-walker
+    code: Option<Rc<Chunk>>,
+}
+
+impl CodeCache {
+    /// The bytecode for `body`, compiled the first time it is asked for;
+    /// `None` when the body has to run on the tree-walker
+    pub(crate) fn function(&mut self, body: &FunctionRef) -> Option<Rc<Chunk>> {
+        if let Some(entry) = self.functions.get(&body.key()) {
+            return entry.code.clone();
+        }
+        if self.functions.len() >= self.sweep_at {
+            self.functions
+                .retain(|_, entry| entry.arena.strong_count() > 0);
+            self.sweep_at = (self.functions.len() * 2).max(64);
+        }
+        let code = compiler::compile_function(body)
+            .ok()
+            .filter(avoids_debug)
+            .map(Rc::new);
+        self.functions.insert(
+            body.key(),
+            Compiled {
+                arena: Rc::downgrade(&body.arena),
+                code: code.clone(),
+            },
+        );
+        code
+    }
+}
+
+/// Run a compiled function body in the frame `Executor::call_value` set up
+/// for it
+pub(crate) fn run_function(
+    code: &Chunk,
+    executor: &mut Executor,
+    interp: &mut LuaInterpreter,
+) -> LuaResult<ControlFlow> {
+    with_headroom(|| execute(code, executor, interp))
+}
+
+/// Virtual machine running a compiled main chunk
+pub struct Vm {
+    /// Used for operators, table access and calls, and to run the
+    /// functions the VM does not compile
+    executor: Executor,
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Vm {
+            executor: Executor::new(),
+        }
+    }
+
+    /// Run a compiled chunk to completion
+    ///
+    /// Its locals get a slot frame of their own. The line of each statement
+    /// is recorded as it starts, and an error leaves its traceback behind,
+    /// as in the tree-walker.
+    pub fn run(&mut self, chunk: &Chunk, interp: &mut LuaInterpreter) -> LuaResult<ControlFlow> {
+        let arena = match &chunk.arena {
+            Some(arena) => Rc::clone(arena),
+            None => Rc::clone(self.executor.arena()),
+        };
+        interp.trace.borrow_mut().enter_chunk(arena.source());
+        let caller = self.executor.set_arena(arena);
+        // Unused slots share one placeholder until declared
+        let unset = Rc::new(RefCell::new(LuaValue::Nil));
+        interp.frames.push(SlotFrame {
+            slots: vec![unset; chunk.slot_count],
+            upvalues: Vec::new(),
+        });
+        let result = with_headroom(|| execute(chunk, &mut self.executor, interp));
+        interp.frames.pop();
+        self.executor.set_arena(caller);
+        result
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn execute(
+    chunk: &Chunk,
+    executor: &mut Executor,
+    interp: &mut LuaInterpreter,
+) -> LuaResult<ControlFlow> {
+    run_code(chunk, executor, interp).map_err(|e| Executor::traced(e, interp))
+}
+
+fn pop(stack: &mut Vec<LuaValue>) -> LuaValue {
+    stack.pop().expect("compiler keeps the stack balanced")
+}
+
+fn peek(stack: &[LuaValue]) -> &LuaValue {
+    stack.last().expect("compiler keeps the stack balanced")
+}
+
+/// Push a call's values adjusted to `results` of them, or all of them for
+/// `None`; returns how many were pushed
+fn push_results(stack: &mut Vec<LuaValue>, values: Vec<LuaValue>, results: Option<usize>) -> usize {
+    match results {
+        Some(1) => stack.push(values.into_iter().next().unwrap_or(LuaValue::Nil)),
+        Some(count) => {
+            let padding = count.saturating_sub(values.len());
+            stack.extend(values.into_iter().take(count));
+            stack.extend(std::iter::repeat_n(LuaValue::Nil, padding));
+        }
+        None => {
+            let count = values.len();
+            stack.extend(values);
+            return count;
+        }
+    }
+    results.unwrap_or_default()
+}
+
+/// Store a field of a table being constructed, without metamethods
+fn construct(table: &LuaValue, key: LuaValue, value: LuaValue) {
+    if let LuaValue::Table(t) = table {
+        t.borrow_mut().set(key, value);
+    }
+}
+
+/// Assign the variable `name` the way unresolved code does: a local of the
+/// interpreter's scopes if one is visible, else a global
+fn assign_name(
+    executor: &mut Executor,
+    name: &str,
+    value: LuaValue,
+    interp: &mut LuaInterpreter,
+) -> LuaResult<()> {
+    if interp.lookup(name).is_some() {
+        interp
+            .update(name, value)
+            .map_err(|e| LuaError::runtime(e, "assignment"))
+    } else {
+        executor.assign_global(name, value, interp)
+    }
+}
+
+fn run_code(
+    chunk: &Chunk,
+    executor: &mut Executor,
+    interp: &mut LuaInterpreter,
+) -> LuaResult<ControlFlow> {
+    let mut stack = Vec::new();
+    // Counter of each running numeric `for`, indexed by its variable's slot
+    let mut loops: Vec<NumericFor> = Vec::new();
+    // Iterator of each running generic `for`
+    let mut iterators: Vec<Option<GenericFor>> = Vec::new();
+    // How many values the last call or `...` left open
+    let mut open = 0;
+    let mut pc = 0;
+    let mut line = None;
+
+    while let Some(instr) = chunk.code.get(pc) {
+        if interp.cancel.is_cancelled() {
+            return Err(LuaError::Cancelled);
+        }
+        let instr_line = chunk.lines.get(pc).copied();
+        if instr_line != line {
+            line = instr_line;
+            interp.trace.borrow_mut().set_line(line.unwrap_or(0));
+        }
+        pc += 1;
+        match instr {
+            Instr::LoadConst(i) => stack.push(chunk.constants[*i].clone()),
+            Instr::GetLocal(slot) => stack.push(interp.get_slot(*slot)),
+            Instr::SetLocal(slot) => {
+                let value = pop(&mut stack);
+                interp.set_slot(*slot, value);
+            }
+            Instr::DeclareLocal(slot) => {
+                let value = pop(&mut stack);
+                interp.declare_slot(*slot, value);
+            }
+            Instr::DeclareNamed { slot, name } => {
+                let value = pop(&mut stack);
+                interp.declare_slot(*slot, value);
+                let cell = interp.slot_cell(*slot);
+                interp.define_cell(chunk.names[*name].clone(), cell);
+            }
+            Instr::GetUpvalue(index) => stack.push(interp.get_upvalue(*index)),
+            Instr::SetUpvalue(index) => {
+                let value = pop(&mut stack);
+                interp.set_upvalue(*index, value);
+            }
+            Instr::GetName(i) => {
+                let name = &chunk.names[*i];
+                let value = match interp.lookup(name) {
+                    Some(value) => value,
+                    None => executor.undefined_global(name, interp)?,
+                };
+                stack.push(value);
+            }
+            Instr::SetName(i) => {
+                let value = pop(&mut stack);
+                assign_name(executor, &chunk.names[*i], value, interp)?;
+            }
+            Instr::GetGlobal(i) => {
+                let name = &chunk.names[*i];
+                let value = interp.globals.borrow().get_str(name).cloned();
+                let value = match value {
+                    Some(value) => value,
+                    None => executor.undefined_global(name, interp)?,
+                };
+                stack.push(value);
+            }
+            Instr::SetGlobal(i) => {
+                let value = pop(&mut stack);
+                executor.assign_global(&chunk.names[*i], value, interp)?;
+            }
+            Instr::Binary(op) => {
+                let right = pop(&mut stack);
+                let left = pop(&mut stack);
+                let result = match (op, &left, &right) {
+                    // Fast path for the common all-number arithmetic
+                    (BinaryOp::Add, LuaValue::Number(l), LuaValue::Number(r)) => {
+                        LuaValue::Number(l + r)
+                    }
+                    (BinaryOp::Subtract, LuaValue::Number(l), LuaValue::Number(r)) => {
+                        LuaValue::Number(l - r)
+                    }
+                    (BinaryOp::Multiply, LuaValue::Number(l), LuaValue::Number(r)) => {
+                        LuaValue::Number(l * r)
+                    }
+                    (BinaryOp::Lt, LuaValue::Number(l), LuaValue::Number(r)) => {
+                        LuaValue::Boolean(l < r)
+                    }
+                    (BinaryOp::Lte, LuaValue::Number(l), LuaValue::Number(r)) => {
+                        LuaValue::Boolean(l <= r)
+                    }
+                    (BinaryOp::Gt, LuaValue::Number(l), LuaValue::Number(r)) => {
+                        LuaValue::Boolean(l > r)
+                    }
+                    (BinaryOp::Gte, LuaValue::Number(l), LuaValue::Number(r)) => {
+                        LuaValue::Boolean(l >= r)
+                    }
+                    (BinaryOp::Eq | BinaryOp::Neq, _, _) => {
+                        let equal = executor.values_equal(&left, &right, interp)?;
+                        LuaValue::Boolean(equal == (*op == BinaryOp::Eq))
+                    }
+                    (BinaryOp::Concat, _, _) => executor.concat(&left, &right, interp)?,
+                    _ => executor.apply_binary_op(&left, op, &right)?,
+                };
+                stack.push(result);
+            }
+            Instr::Unary(op) => {
+                let operand = pop(&mut stack);
+                let result = executor.apply_unary_op(op, operand)?;
+                stack.push(result);
+            }
+            Instr::Index => {
+                let key = pop(&mut stack);
+                let table = pop(&mut stack);
+                let value = executor.table_get(&table, key, interp)?;
+                stack.push(value);
+            }
+            Instr::SetIndex => {
+                let value = pop(&mut stack);
+                let key = pop(&mut stack);
+                let table = pop(&mut stack);
+                executor.table_set(&table, key, value, interp)?;
+            }
+            Instr::Assign(index) => {
+                let targets = &chunk.assignments[*index];
+                let values = stack.split_off(stack.len() - targets.len());
+                let fields = targets.iter().filter(|t| **t == Target::Field).count();
+                let mut places = stack.split_off(stack.len() - 2 * fields).into_iter();
+                for (target, value) in targets.iter().zip(values) {
+                    match target {
+                        Target::Local(slot) => interp.set_slot(*slot, value),
+                        Target::Upvalue(index) => interp.set_upvalue(*index, value),
+                        Target::Name(i) => assign_name(executor, &chunk.names[*i], value, interp)?,
+                        Target::Global(i) => {
+                            executor.assign_global(&chunk.names[*i], value, interp)?
+                        }
+                        Target::Field => {
+                            let (Some(table), Some(key)) = (places.next(), places.next()) else {
+                                unreachable!("each field target pushed a table and a key");
+                            };
+                            executor.table_set(&table, key, value, interp)?;
+                        }
+                    }
+                }
+            }
+            Instr::NewTable => stack.push(executor.new_table(interp)),
+            Instr::TableSet => {
+                let value = pop(&mut stack);
+                let key = pop(&mut stack);
+                construct(peek(&stack), key, value);
+            }
+            Instr::TableAppend(index) => {
+                let value = pop(&mut stack);
+                construct(peek(&stack), LuaValue::Number(*index as f64), value);
+            }
+            Instr::TableAppendOpen(index) => {
+                let values = stack.split_off(stack.len() - open);
+                let table = peek(&stack);
+                for (offset, value) in values.into_iter().enumerate() {
+                    construct(table, LuaValue::Number((index + offset) as f64), value);
+                }
+            }
+            Instr::Closure(index) => {
+                let spec = &chunk.closures[*index];
+                let upvalues = spec
+                    .captures
+                    .iter()
+                    .filter_map(|(name, slot)| {
+                        let cell = match slot {
+                            Some(slot) => interp.slot_cell(*slot),
+                            None => interp.lookup_cell(name)?,
+                        };
+                        Some(Upvalue::new(name.clone(), cell))
+                    })
+                    .collect();
+                let body = FunctionRef {
+                    arena: Rc::clone(executor.arena()),
+                    id: spec.function,
+                };
+                stack.push(executor.make_closure(body, upvalues, interp));
+            }
+            Instr::DeclareFunction { decl, slot } => {
+                let value = pop(&mut stack);
+                let name = &chunk.declarations[*decl];
+                match slot {
+                    Some(slot) if name.is_simple() => interp.set_slot(*slot, value),
+                    Some(slot) => Executor::store_declared(name, interp.get_slot(*slot), value)?,
+                    None => executor.declare_function(name, value, interp)?,
+                }
+            }
+            Instr::Method(key) => {
+                let object = pop(&mut stack);
+                let key = chunk.constants[*key].clone();
+                let method = executor.table_get(&object, key, interp)?;
+                stack.push(method);
+                stack.push(object);
+            }
+            Instr::Call {
+                argc,
+                spread,
+                results,
+                name,
+            } => {
+                let argc = if *spread { argc + open } else { *argc };
+                let args = stack.split_off(stack.len() - argc);
+                let func = pop(&mut stack);
+                let values = executor.call_named(func, args, name.clone(), interp)?;
+                open = push_results(&mut stack, values, *results);
+            }
+            Instr::Varargs(results) => {
+                let values = executor.varargs().to_vec();
+                open = push_results(&mut stack, values, *results);
+            }
+            Instr::Pop => {
+                pop(&mut stack);
+            }
+            Instr::Jump(target) => pc = *target,
+            Instr::JumpIfFalse(target) => {
+                if !pop(&mut stack).is_truthy() {
+                    pc = *target;
+                }
+            }
+            Instr::JumpIfFalseOrPop(target) => {
+                if peek(&stack).is_truthy() {
+                    pop(&mut stack);
+                } else {
+                    pc = *target;
+                }
+            }
+            Instr::JumpIfTrueOrPop(target) => {
+                if peek(&stack).is_truthy() {
+                    pc = *target;
+                } else {
+                    pop(&mut stack);
+                }
+            }
+            Instr::ForPrep { slot, exit } => {
+                let step = pop(&mut stack).to_number()?;
+                let limit = pop(&mut stack).to_number()?;
+                let start = pop(&mut stack).to_number()?;
+                let mut range = NumericFor::new(start, limit, step)?;
+                match range.next() {
+                    Some(i) => {
+                        interp.declare_slot(*slot, LuaValue::Number(i));
+                        if loops.len() <= *slot {
+                            loops.resize(slot + 1, NumericFor::Done);
+                        }
+                        loops[*slot] = range;
+                    }
+                    None => pc = *exit,
+                }
+            }
+            Instr::ForLoop { slot, body } => {
+                if let Some(i) = loops[*slot].next() {
+                    interp.declare_slot(*slot, LuaValue::Number(i));
+                    pc = *body;
+                }
+            }
+            Instr::GenericPrep {
+                iter,
+                count,
+                spread,
+            } => {
+                let count = if *spread { count + open } else { *count };
+                let values = stack.split_off(stack.len() - count);
+                if iterators.len() <= *iter {
+                    iterators.resize_with(iter + 1, || None);
+                }
+                iterators[*iter] = Some(GenericFor::new(values));
+            }
+            Instr::GenericLoop { iter, vars, exit } => {
+                let passes = iterators[*iter]
+                    .as_mut()
+                    .expect("the loop was prepared first");
+                match passes.next(executor, interp)? {
+                    Some(mut values) => {
+                        let slots = &chunk.slot_lists[*vars];
+                        values.resize(slots.len(), LuaValue::Nil);
+                        for (slot, value) in slots.iter().zip(values) {
+                            interp.declare_slot(*slot, value);
+                        }
+                    }
+                    None => {
+                        iterators[*iter] = None;
+                        pc = *exit;
+                    }
+                }
+            }
+            Instr::Return { count, spread } => {
+                let count = if *spread { count + open } else { *count };
+                let values = stack.split_off(stack.len() - count);
+                return Ok(if values.is_empty() && pc == chunk.code.len() {
+                    ControlFlow::Normal
+                } else {
+                    ControlFlow::Return(values)
+                });
+            }
+        }
+    }
+
+    Ok(ControlFlow::Normal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hooks::HookMask;
+    use crate::lua_parser::{parse, tokenize, TokenSlice};
+
+    /// An interpreter that keeps everything on the tree-walker
+    fn tree_walker() -> LuaInterpreter {
+        let mut interp = LuaInterpreter::new();
+        interp.use_vm = false;
+        interp
+    }
+
+    fn run_both(code: &str) -> (LuaResult<ControlFlow>, LuaResult<ControlFlow>) {
+        let tokens = tokenize(code).unwrap();
+        let (_, block) = parse(TokenSlice::from(tokens.as_slice())).unwrap();
+        let block = resolver::resolve(&block);
+        let chunk = compiler::compile(&block).expect("chunk should compile");
+
+        let vm_result = Vm::new().run(&chunk, &mut LuaInterpreter::new());
+        let tree_result = Executor::new().execute_block(&block, &mut tree_walker());
+        (vm_result, tree_result)
+    }
+
+    /// Run `code` on both engines and check they return the same values
+    fn same_on_both(code: &str) -> Vec<LuaValue> {
+        let (vm, tree) = run_both(code);
+        let vm = returned(vm);
+        assert_eq!(vm, returned(tree), "{}", code);
+        vm
+    }
+
+    fn returned(result: LuaResult<ControlFlow>) -> Vec<LuaValue> {
+        match result.unwrap() {
+            ControlFlow::Return(values) => values,
+            other => panic!("Expected return, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_numeric_loop_matches_tree_walker() {
+        let (vm, tree) =
+            run_both("local sum = 0 for i = 1, 100 do sum = sum + i * 2 end return sum");
+        assert_eq!(returned(vm), vec![LuaValue::Number(10100.0)]);
+        assert_eq!(returned(tree), vec![LuaValue::Number(10100.0)]);
+    }
+
+    #[test]
+    fn test_equality_matches_tree_walker() {
+        let code = "local f = print return f == print, print == type, 1 == '1', 2 ~= 2";
+        let (vm, tree) = run_both(code);
+        let expected: Vec<LuaValue> = [true, false, false, false]
+            .into_iter()
+            .map(LuaValue::Boolean)
+            .collect();
+        assert_eq!(returned(vm), expected);
+        assert_eq!(returned(tree), expected);
+    }
+
+    #[test]
+    fn test_concat_matches_tree_walker() {
+        let (vm, tree) = run_both("return 1 .. '' .. 0.5, 1 / 3 .. ''");
+        let expected = vec![
+            LuaValue::String("10.5".into()),
+            LuaValue::String("0.33333333333333".into()),
+        ];
+        assert_eq!(returned(vm), expected);
+        assert_eq!(returned(tree), expected);
+        let (vm, tree) = run_both("return 'a' .. nil");
+        assert!(vm.is_err() && tree.is_err());
+    }
+
+    #[test]
+    fn test_integer_loops_stop_at_the_limit() {
+        // Past 2^53 a float counter would get stuck at 2^53; an integer one
+        // runs exactly three times
+        let code = "local n, last = 0, 0 for i = 2^53, 2^53 + 2 do n = n + 1 last = i end \
+                    local m = 0 for i = 10, 1.5, -3 do m = m + i end \
+                    local k = 0 for i = 1, 0 do k = k + 1 end return n, last, m, k";
+        let (vm, tree) = run_both(code);
+        let vm = returned(vm);
+        assert_eq!(
+            vm,
+            vec![
+                LuaValue::Number(3.0),
+                LuaValue::Number(9007199254740994.0),
+                LuaValue::Number(10.0 + 7.0 + 4.0),
+                LuaValue::Number(0.0),
+            ]
+        );
+        assert_eq!(vm, returned(tree));
+    }
+
+    #[test]
+    fn test_while_break_and_logic() {
+        let code = "local n = 0 while true do n = n + 1 if n >= 5 and not false then break end end return n, n > 3 or nil";
+        let (vm, tree) = run_both(code);
+        let vm = returned(vm);
+        assert_eq!(vm, vec![LuaValue::Number(5.0), LuaValue::Boolean(true)]);
+        assert_eq!(vm, returned(tree));
+    }
+
+    #[test]
+    fn test_repeat_sees_body_locals() {
+        let (vm, _) = run_both("local i = 0 repeat local j = i + 1 i = j until j >= 3 return i");
+        assert_eq!(returned(vm), vec![LuaValue::Number(3.0)]);
+    }
+
+    #[test]
+    fn test_calls_and_globals() {
+        let (vm, tree) = run_both("x = math.floor(7.5) return x .. \"!\"");
+        assert_eq!(returned(vm), returned(tree));
+    }
+
+    #[test]
+    fn test_errors_match_tree_walker() {
+        let (vm, tree) = run_both("return undefined_name + 1");
+        assert_eq!(vm.unwrap_err().message(), tree.unwrap_err().message());
+
+        let (vm, _) = run_both("for i = 1, 10, 0 do end");
+        assert!(vm.is_err());
+    }
+
+    #[test]
+    fn test_errors_leave_a_traceback() {
+        for (code, expected) in [
+            (
+                "local x = 1\ntostring(x)\nlocal y = x + nil",
+                "stack traceback:\n\tline 3: in main chunk",
+            ),
+            (
+                "local s = 'a'\nerror('boom')",
+                "stack traceback:\n\t[C]: in function 'error'\n\tline 2: in main chunk",
+            ),
+        ] {
+            let block = lua_parser::parse_source(code).unwrap();
+            let chunk = compiler::compile(&block).expect("chunk should compile");
+            let mut vm_interp = LuaInterpreter::new();
+            let vm_err = Vm::new().run(&chunk, &mut vm_interp).unwrap_err();
+            assert_eq!(
+                vm_interp.error_traceback(&vm_err).as_deref(),
+                Some(expected)
+            );
+            let mut tree_interp = tree_walker();
+            let tree_err = Executor::new()
+                .execute_block(&block, &mut tree_interp)
+                .unwrap_err();
+            assert_eq!(
+                tree_interp.error_traceback(&tree_err).as_deref(),
+                Some(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn test_functions_and_closures_match_tree_walker() {
+        let code =
+            "local function fib(n) if n < 2 then return n end return fib(n - 1) + fib(n - 2) end
+                    local function counter()
+                        local n = 0
+                        return function() n = n + 1 return n end
+                    end
+                    local c = counter() c() c()
+                    local fs = {}
+                    for i = 1, 3 do fs[i] = function() return i end end
+                    return fib(15), c(), fs[1]() + fs[3]()";
+        assert_eq!(
+            same_on_both(code),
+            vec![
+                LuaValue::Number(610.0),
+                LuaValue::Number(3.0),
+                LuaValue::Number(4.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_calls_keep_every_result_where_lua_does() {
+        let code = "local function two() return 1, 2 end
+                    local function count(...) return select('#', ...) end
+                    local function pass(...) return ... end
+                    local t = {two(), two()}
+                    local a, b, c = two()
+                    return count(two()), count(two(), 5), count((two())), #t, c, pass(7, two())";
+        let expected = [2.0, 2.0, 1.0, 3.0]
+            .into_iter()
+            .map(LuaValue::Number)
+            .chain([LuaValue::Nil])
+            .chain([7.0, 1.0, 2.0].into_iter().map(LuaValue::Number))
+            .collect::<Vec<_>>();
+        assert_eq!(same_on_both(code), expected);
+    }
+
+    #[test]
+    fn test_tables_and_methods_match_tree_walker() {
+        let code = "local Account = {} Account.__index = Account
+                    function Account.new(balance) return setmetatable({balance = balance}, Account) end
+                    function Account:deposit(n) self.balance = self.balance + n return self end
+                    local a = Account.new(10)
+                    for i = 1, 5 do a:deposit(i) end
+                    local t = {x = 1, [2] = 'b', 'a'}
+                    t.y, t[3] = t.x + 1, 'c'
+                    local keys = 0
+                    for k, v in pairs(t) do keys = keys + 1 end
+                    return a.balance, t[1], t[2], t.y, t[3], keys";
+        assert_eq!(
+            same_on_both(code),
+            vec![
+                LuaValue::Number(25.0),
+                LuaValue::String("a".into()),
+                LuaValue::String("b".into()),
+                LuaValue::Number(2.0),
+                LuaValue::String("c".into()),
+                LuaValue::Number(5.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_generic_for_matches_tree_walker() {
+        let code = "local sum = 0
+                    for i, v in ipairs({10, 20, 30}) do sum = sum + i * v end
+                    local n = 0
+                    local function gen() n = n + 1 if n <= 3 then return n end end
+                    for v in gen do sum = sum + v end
+                    for k in pairs({a = 1}) do if k == 'a' then break end end
+                    return sum";
+        assert_eq!(same_on_both(code), vec![LuaValue::Number(146.0)]);
+        let (vm, tree) = run_both("for x in 5 do end");
+        assert_eq!(vm.unwrap_err().message(), tree.unwrap_err().message());
+    }
+
+    #[test]
+    fn test_assignment_evaluates_targets_first() {
+        let code = "local t, i = {}, 1 t[i], i = i, 2 local a, b = 1, 2 a, b = b, a
+                    return t[1], t[2], i, a, b";
+        assert_eq!(
+            same_on_both(code),
+            vec![
+                LuaValue::Number(1.0),
+                LuaValue::Nil,
+                LuaValue::Number(2.0),
+                LuaValue::Number(2.0),
+                LuaValue::Number(1.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_called_functions_run_on_the_vm() {
+        let block = lua_parser::parse_source(
+            "local function sum(n) local s = 0 for i = 1, n do s = s + i end return s end
+             return sum(100)",
+        )
+        .unwrap();
+        let mut interp = LuaInterpreter::new();
+        assert_eq!(engine_for(&block, &interp), Engine::Vm);
+        assert_eq!(
+            returned(execute_chunk(&block, &mut interp)),
+            vec![LuaValue::Number(5050.0)]
+        );
+        let cache = interp.code_cache.borrow();
+        assert_eq!(cache.functions.len(), 1);
+        assert!(cache.functions.values().all(|entry| entry.code.is_some()));
+    }
+
+    #[test]
+    fn test_execute_chunk_falls_back() {
+        let code = "local n = 0 ::top:: n = n + 1 return n";
+        let block = lua_parser::parse_source(code).unwrap();
+        assert!(compiler::compile(&block).is_err());
+        let mut interp = LuaInterpreter::new();
+        assert_eq!(engine_for(&block, &interp), Engine::TreeWalker);
+        let result = execute_chunk(&block, &mut interp);
+        assert_eq!(returned(result), vec![LuaValue::Number(1.0)]);
+    }
+
+    #[test]
+    fn test_later_chunks_see_earlier_locals() {
+        let mut interp = LuaInterpreter::new();
+        for code in [
+            "local x = 20",
+            "local function add(n) return x + n end y = add(1)",
+        ] {
+            let block = lua_parser::parse_source(code).unwrap();
+            assert_eq!(engine_for(&block, &interp), Engine::Vm);
+            execute_chunk(&block, &mut interp).unwrap();
+        }
+        let block = lua_parser::parse_source("x = x + 1 return x, y").unwrap();
+        assert_eq!(
+            returned(execute_chunk(&block, &mut interp)),
+            vec![LuaValue::Number(21.0), LuaValue::Number(21.0)]
+        );
+    }
+
+    #[test]
+    fn test_vm_runs_unless_turned_off() {
+        let block =
+            lua_parser::parse_source("local s = 0 for i = 1, 3 do s = s + i end return s").unwrap();
+        let mut interp = LuaInterpreter::new();
+        assert_eq!(engine_for(&block, &interp), Engine::Vm);
+        assert_eq!(
+            returned(execute_chunk(&block, &mut interp)),
+            vec![LuaValue::Number(6.0)]
+        );
+
+        interp.use_vm = false;
+        assert_eq!(engine_for(&block, &interp), Engine::TreeWalker);
+        assert_eq!(
+            returned(execute_chunk(&block, &mut interp)),
+            vec![LuaValue::Number(6.0)]
+        );
+
+        // The tree-walker runs hooks and the VM does not
+        interp.use_vm = true;
+        interp.set_hook(|_| Ok(()), HookMask::default(), 0);
+        assert_eq!(engine_for(&block, &interp), Engine::TreeWalker);
+    }
+}
@@ -0,0 +1,100 @@
+//! JavaScript bindings for the WebAssembly build
+//!
+//! Built with `wasm-pack build --target web -- --features wasm`, the crate
+//! exports `evalLua` and `evalScheme` for one-off evaluation and a
+//! `Playground` class that keeps its interpreters between calls, as an
+//! in-browser playground needs. A browser has no stdout, so what `print`,
+//! `io.write` and `display` write goes to the output callback, and is
+//! dropped without one. Results come back as the text `tostring` or the
+//! Scheme REPL would show; errors are thrown as their message.
+
+use crate::eval::Session;
+use js_sys::Function;
+use wasm_bindgen::prelude::*;
+
+/// Run a Lua chunk in a fresh interpreter and return its first value
+#[wasm_bindgen(js_name = evalLua)]
+pub fn eval_lua(source: &str, on_output: Option<Function>) -> Result<String, String> {
+    let mut playground = Playground::new();
+    if let Some(callback) = on_output {
+        playground.set_output(callback);
+    }
+    playground.eval_lua(source)
+}
+
+/// Run Scheme source in a fresh environment and return its last value
+#[wasm_bindgen(js_name = evalScheme)]
+pub fn eval_scheme(source: &str, on_output: Option<Function>) -> Result<String, String> {
+    let mut playground = Playground::new();
+    if let Some(callback) = on_output {
+        playground.set_output(callback);
+    }
+    playground.eval_scheme(source)
+}
+
+/// A Lua interpreter and a Scheme environment that live between calls
+#[wasm_bindgen]
+pub struct Playground {
+    session: Session,
+}
+
+#[wasm_bindgen]
+impl Playground {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Playground {
+        Playground {
+            session: Session::new(),
+        }
+    }
+
+    /// Send all output of both languages to `callback`, one string per
+    /// write
+    #[wasm_bindgen(js_name = setOutput)]
+    pub fn set_output(&mut self, callback: Function) {
+        let write = move |text: &str| {
+            // A throwing callback only loses the output it was given
+            let _ = callback.call1(&JsValue::NULL, &JsValue::from_str(text));
+        };
+        let scheme = write.clone();
+        self.session.lua().output.set_callback(write);
+        self.session.scheme().output_sink().set_callback(scheme);
+    }
+
+    #[wasm_bindgen(js_name = evalLua)]
+    pub fn eval_lua(&mut self, source: &str) -> Result<String, String> {
+        self.session
+            .eval_lua(source)
+            .map(|value| value.to_string_value())
+            .map_err(|e| e.to_string())
+    }
+
+    #[wasm_bindgen(js_name = evalScheme)]
+    pub fn eval_scheme(&mut self, source: &str) -> Result<String, String> {
+        self.session
+            .eval_scheme(source)
+            .map(|value| value.to_string())
+    }
+}
+
+impl Default for Playground {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_results_come_back_as_text() {
+        let mut playground = Playground::new();
+        assert_eq!(playground.eval_lua("x = 20").unwrap(), "nil");
+        assert_eq!(playground.eval_lua("x + 1.5").unwrap(), "21.5");
+        assert_eq!(playground.eval_scheme("(list 1 2)").unwrap(), "(1 2)");
+        assert!(playground
+            .eval_lua("error('boom')")
+            .unwrap_err()
+            .contains("boom"));
+    }
+}
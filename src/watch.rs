@@ -0,0 +1,113 @@
+//! File watching for `muscm watch`
+//!
+//! Polls the modification time and size of a set of files, so it works
+//! everywhere without a platform notification API. A file that disappears
+//! or reappears counts as changed; editors that save by renaming a new file
+//! into place are caught the same way.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// What a file looked like when last checked; `None` when it was missing
+type Stamp = Option<(SystemTime, u64)>;
+
+fn stamp(path: &Path) -> Stamp {
+    let meta = std::fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+/// A set of files and how they looked when last checked
+#[derive(Debug, Default)]
+pub struct FileWatch {
+    files: Vec<(PathBuf, Stamp)>,
+}
+
+impl FileWatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start watching `path` as it is now; paths already watched are kept
+    /// with their earlier stamp, so a change in between is still reported
+    pub fn watch(&mut self, path: impl Into<PathBuf>) {
+        let path = path.into();
+        if !self.files.iter().any(|(watched, _)| *watched == path) {
+            let now = stamp(&path);
+            self.files.push((path, now));
+        }
+    }
+
+    /// Stop watching everything
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+
+    /// The watched files, in the order they were added
+    pub fn files(&self) -> impl Iterator<Item = &Path> {
+        self.files.iter().map(|(path, _)| path.as_path())
+    }
+
+    /// Files that changed since the last check, taking their new stamps
+    pub fn changed(&mut self) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        for (path, last) in &mut self.files {
+            let now = stamp(path);
+            if now != *last {
+                *last = now;
+                changed.push(path.clone());
+            }
+        }
+        changed
+    }
+
+    /// Block until at least one file changes, checking every `interval`
+    pub fn wait(&mut self, interval: Duration) -> Vec<PathBuf> {
+        loop {
+            let changed = self.changed();
+            if !changed.is_empty() {
+                return changed;
+            }
+            std::thread::sleep(interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+
+    #[test]
+    fn test_reports_modified_and_removed_files() {
+        let dir = std::env::temp_dir().join(format!("muscm_watch_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("main.lua");
+        let module = dir.join("util.lua");
+        fs::write(&script, "print(1)").unwrap();
+        fs::write(&module, "return {}").unwrap();
+
+        let mut watch = FileWatch::new();
+        watch.watch(&script);
+        watch.watch(&module);
+        watch.watch(&script);
+        assert_eq!(watch.files().count(), 2);
+        assert!(watch.changed().is_empty());
+
+        // Same size, so only the timestamp tells the versions apart
+        fs::write(&script, "print(2)").unwrap();
+        let later = SystemTime::now() + Duration::from_secs(5);
+        File::options()
+            .write(true)
+            .open(&script)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert_eq!(watch.changed(), vec![script.clone()]);
+        assert!(watch.changed().is_empty());
+
+        fs::remove_file(&module).unwrap();
+        assert_eq!(watch.wait(Duration::from_millis(1)), vec![module.clone()]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+use muscm::bridge::scheme_eval;
+use muscm::executor::{ControlFlow, Executor};
+use muscm::interpreter::{Environment, Interpreter, SVal};
+use muscm::lua_interpreter::LuaInterpreter;
+use muscm::lua_parser::{parse as parse_lua, tokenize, TokenSlice};
+use muscm::lua_value::LuaValue;
+use muscm::parser::parse;
+
+// Helper function to evaluate Scheme code and print the last result
+fn eval_scheme(code: &str) -> Result<String, String> {
+    let mut env = Environment::new();
+    let (arena, nodes) = parse(code).map_err(|e| e.message)?;
+    let mut result = SVal::Nil;
+    for node in nodes {
+        result = Interpreter::eval(arena.get(node).unwrap(), &mut env, &arena)?;
+    }
+    Ok(result.to_string())
+}
+
+// Helper function to run a Lua chunk and return its first return value
+fn eval_lua(code: &str) -> Result<LuaValue, String> {
+    let tokens = tokenize(code)?;
+    let token_slice = TokenSlice::from(tokens.as_slice());
+    let (_, block) = parse_lua(token_slice).map_err(|e| format!("{:?}", e))?;
+
+    let mut executor = Executor::new();
+    let mut interp = LuaInterpreter::new();
+    match executor
+        .execute_block(&block, &mut interp)
+        .map_err(|e| e.to_string())?
+    {
+        ControlFlow::Return(values) => Ok(values.into_iter().next().unwrap_or(LuaValue::Nil)),
+        _ => Ok(LuaValue::Nil),
+    }
+}
+
+#[test]
+fn test_lua_eval_returns_scalars() {
+    assert_eq!(eval_scheme(r#"(lua-eval "return 1 + 2")"#).unwrap(), "3");
+    assert_eq!(eval_scheme(r#"(lua-eval "return 0.5")"#).unwrap(), "0.5");
+    assert_eq!(
+        eval_scheme(r#"(lua-eval "return tostring(7)")"#).unwrap(),
+        "\"7\""
+    );
+    assert_eq!(eval_scheme(r#"(lua-eval "return true")"#).unwrap(), "#t");
+}
+
+#[test]
+fn test_lua_sequence_becomes_list() {
+    assert_eq!(
+        eval_scheme(r#"(lua-eval "return {10, 20, 30}")"#).unwrap(),
+        "(10 20 30)"
+    );
+}
+
+#[test]
+fn test_lua_function_called_from_scheme() {
+    let code = r#"
+        (define double (lua-eval "return function(x) return x * 2 end"))
+        (+ 1 (double 20))
+    "#;
+    assert_eq!(eval_scheme(code).unwrap(), "41");
+}
+
+#[test]
+fn test_lua_function_receives_scheme_list() {
+    let code = r#"
+        (define second (lua-eval "return function(t) return t[2] end"))
+        (second (list 'a 'b 'c))
+    "#;
+    assert_eq!(eval_scheme(code).unwrap(), "\"b\"");
+}
+
+#[test]
+fn test_lua_errors_surface_in_scheme() {
+    assert!(eval_scheme(r#"(lua-eval "return nil + 1")"#).is_err());
+    assert!(eval_scheme("(lua-eval 42)").is_err());
+}
+
+#[test]
+fn test_scheme_eval_from_lua() {
+    let result = eval_lua(r#"return scheme.eval("(* 6 7)")"#).unwrap();
+    assert!(matches!(result, LuaValue::Number(n) if n == 42.0));
+}
+
+#[test]
+fn test_scheme_procedure_called_from_lua() {
+    let code = r#"
+        local square = scheme.eval("(define (square x) (* x x)) square")
+        return square(9)
+    "#;
+    let result = eval_lua(code).unwrap();
+    assert!(matches!(result, LuaValue::Number(n) if n == 81.0));
+}
+
+#[test]
+fn test_scheme_list_becomes_lua_table() {
+    let result = eval_lua(r#"local t = scheme.eval("(list 1 2 3)") return t[3]"#).unwrap();
+    assert!(matches!(result, LuaValue::Number(n) if n == 3.0));
+}
+
+#[test]
+fn test_scheme_eval_errors() {
+    assert!(scheme_eval("(car 1)").is_err());
+    assert!(eval_lua(r#"return scheme.eval(1)"#).is_err());
+}
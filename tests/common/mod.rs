@@ -0,0 +1,27 @@
+//! Helpers shared by the Scheme integration tests; each test binary uses
+//! only some of them
+#![allow(dead_code)]
+
+use muscm::interpreter::{Environment, Interpreter, SVal};
+use muscm::parser::parse;
+
+/// Evaluate every top-level form of `code` in `env` and return the value
+/// of the last one
+pub fn eval_in(code: &str, env: &mut Environment) -> Result<SVal, String> {
+    let (arena, nodes) = parse(code).map_err(|e| e.message)?;
+    let mut result = SVal::Nil;
+    for node in nodes {
+        result = Interpreter::eval(arena.get(node).unwrap(), env, &arena)?;
+    }
+    Ok(result)
+}
+
+/// Evaluate `code` in a fresh environment
+pub fn eval_all(code: &str) -> Result<SVal, String> {
+    eval_in(code, &mut Environment::new())
+}
+
+/// Evaluate `code` in a fresh environment and print its value
+pub fn eval_to_string(code: &str) -> Result<String, String> {
+    eval_all(code).map(|value| value.to_string())
+}
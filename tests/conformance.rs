@@ -0,0 +1,138 @@
+//! Conformance suite: Lua scripts run against their expected output
+//!
+//! Every `.lua` file under `fixtures/conformance`, in any subdirectory, sits
+//! next to a `.expected` file holding what real Lua 5.4 prints for it. Each
+//! script is tokenized, parsed and run four ways: through `execute_chunk`
+//! (the VM wherever it can compile the chunk and the functions it calls),
+//! through `execute_chunk` after the `optimize` pass, on the tree-walker
+//! alone after resolution, and on the tree-walker with name-based scopes.
+//! All four must print exactly the expected text.
+//!
+//! A script that fails to parse or stops with an error prints `error: `
+//! and the message as its last line, so fixtures can pin down errors too.
+//!
+//! To cover a new behaviour, add a script and its `.expected` file; group
+//! related scripts in a subdirectory.
+
+use muscm::executor::Executor;
+use muscm::lua_interpreter::LuaInterpreter;
+use muscm::lua_parser::{parse_source, Chunk};
+use muscm::optimize::optimize;
+use muscm::resolver::resolve;
+use muscm::vm::execute_chunk;
+use std::path::{Path, PathBuf};
+
+/// Runs a parsed chunk, reporting an error as its message
+type Mode = fn(&Chunk, &mut LuaInterpreter) -> Result<(), String>;
+
+/// Ways of running a chunk; each must print the expected output
+const MODES: &[(&str, Mode)] = &[
+    ("execute_chunk", |block, interp| {
+        execute_chunk(block, interp)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }),
+    ("optimized", |block, interp| {
+        let builtins = interp.global_names().into_iter().collect();
+        execute_chunk(&optimize(block, &builtins), interp)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }),
+    ("resolved tree-walker", |block, interp| {
+        interp.use_vm = false;
+        Executor::new()
+            .execute_block(&resolve(block), interp)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }),
+    ("tree-walker", |block, interp| {
+        interp.use_vm = false;
+        Executor::new()
+            .execute_block(block, interp)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }),
+];
+
+/// Every `.lua` file under `dir`, sorted by path
+fn scripts(dir: &Path) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            found.extend(scripts(&path));
+        } else if path.extension().is_some_and(|e| e == "lua") {
+            found.push(path);
+        }
+    }
+    found.sort();
+    found
+}
+
+/// What a script prints when run one way
+fn run(code: &str, mode: Mode) -> String {
+    let mut interp = LuaInterpreter::new();
+    let captured = interp.output.capture();
+    if let Err(e) = parse_source(code).and_then(|block| mode(&block, &mut interp)) {
+        captured.borrow_mut().push_str(&format!("error: {}\n", e));
+    }
+    let printed = captured.borrow().clone();
+    printed
+}
+
+/// Line-by-line differences, `-` for expected and `+` for actual
+fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let mut out = String::new();
+    for i in 0..expected.len().max(actual.len()) {
+        let (want, got) = (expected.get(i), actual.get(i));
+        if want != got {
+            if let Some(want) = want {
+                out.push_str(&format!("  {:>3} - {}\n", i + 1, want));
+            }
+            if let Some(got) = got {
+                out.push_str(&format!("  {:>3} + {}\n", i + 1, got));
+            }
+        }
+    }
+    if out.is_empty() {
+        out.push_str("  (differs only in the final newline)\n");
+    }
+    out
+}
+
+#[test]
+fn test_conformance() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/conformance");
+    let scripts = scripts(&root);
+    assert!(!scripts.is_empty(), "no scripts under {}", root.display());
+
+    let mut failures = Vec::new();
+    for script in &scripts {
+        let name = script.strip_prefix(&root).unwrap().display();
+        let code = std::fs::read_to_string(script).unwrap();
+        let Ok(expected) = std::fs::read_to_string(script.with_extension("expected")) else {
+            failures.push(format!("{}: no .expected file", name));
+            continue;
+        };
+        for (mode, execute) in MODES {
+            let actual = run(&code, *execute);
+            if actual != expected {
+                failures.push(format!(
+                    "{} ({}):\n{}",
+                    name,
+                    mode,
+                    diff(&expected, &actual)
+                ));
+            }
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} failing runs across {} scripts:\n{}",
+        failures.len(),
+        scripts.len(),
+        failures.join("\n")
+    );
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use common::eval_all;
+use muscm::interpreter::SVal;
+
+#[test]
+fn test_call_cc_normal_return() {
+    let result = eval_all("(call/cc (lambda (k) (+ 1 2)))");
+    assert_eq!(result, Ok(SVal::Integer(3)));
+}
+
+#[test]
+fn test_call_cc_escapes_pending_computation() {
+    let result = eval_all("(+ 1 (call/cc (lambda (k) (+ 10 (k 42)))))");
+    assert_eq!(result, Ok(SVal::Integer(43)));
+}
+
+#[test]
+fn test_escape_from_recursion() {
+    // Stop searching as soon as a negative number is found
+    let code = "
+        (define (find-negative lst return)
+          (cond ((null? lst) #f)
+                ((< (car lst) 0) (return (car lst)))
+                (else (find-negative (cdr lst) return))))
+        (call-with-current-continuation
+          (lambda (return) (find-negative (list 1 2 -3 4 -5) return)))
+    ";
+    assert_eq!(eval_all(code), Ok(SVal::Integer(-3)));
+}
+
+#[test]
+fn test_nested_escape_skips_inner_call() {
+    let code = "
+        (call/ec (lambda (outer)
+          (+ 100 (call/ec (lambda (inner) (outer 1))))))
+    ";
+    assert_eq!(eval_all(code), Ok(SVal::Integer(1)));
+
+    let code = "
+        (call/ec (lambda (outer)
+          (+ 100 (call/ec (lambda (inner) (inner 1))))))
+    ";
+    assert_eq!(eval_all(code), Ok(SVal::Integer(101)));
+}
+
+#[test]
+fn test_continuation_is_one_shot() {
+    let code = "
+        (define k (call/cc (lambda (c) c)))
+        (k 5)
+    ";
+    let result = eval_all(code);
+    assert!(matches!(result, Err(ref e) if e.contains("dynamic extent")));
+}
+
+#[test]
+fn test_errors_still_propagate() {
+    assert!(eval_all("(call/cc (lambda (k) (car 1)))").is_err());
+    assert!(eval_all("(call/cc 1)").is_err());
+}
@@ -2,6 +2,8 @@
 //!
 //! Tests all error variants and their properties
 
+#![allow(clippy::unnecessary_literal_unwrap)]
+
 use muscm::error_types::*;
 
 #[test]
@@ -286,7 +288,7 @@ fn test_error_display_impl() {
         ("file", Box::new(LuaError::file("f", "r"))),
     ];
 
-    for (expected_word, err) in errors {
+    for (_expected_word, err) in errors {
         let display_str = format!("{}", err);
         assert!(!display_str.is_empty());
         // Each should contain some meaningful information
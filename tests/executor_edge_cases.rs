@@ -343,3 +343,73 @@ end
     let result = execute_code(code);
     assert!(result.is_ok(), "If-elseif-else should work");
 }
+
+#[test]
+fn test_deeply_nested_parentheses() {
+    let depth = 20000;
+    let code = format!(
+        "local y = 2\nlocal x = {}y{}\nif x ~= 2 then error('wrong value') end",
+        "(".repeat(depth),
+        ")".repeat(depth)
+    );
+    let result = execute_code(&code);
+    assert!(
+        result.is_ok(),
+        "Deeply nested parentheses should work: {:?}",
+        result
+    );
+}
+
+#[test]
+fn test_long_concatenation_expression() {
+    // `..` is right associative, so this nests 20000 levels deep
+    let code = format!(
+        "local y = 'a'\nlocal s = {}\nif #s ~= 20000 then error('wrong length') end",
+        vec!["y"; 20000].join(" .. ")
+    );
+    let result = execute_code(&code);
+    assert!(
+        result.is_ok(),
+        "Long concatenation chains should work: {:?}",
+        result
+    );
+}
+
+#[test]
+fn test_deeply_nested_calls_and_tables() {
+    let depth = 10000;
+    let code = format!(
+        "local function f(v) return v end\n\
+         local x = {}1{}\n\
+         local t = {}1{}\n\
+         for i = 1, {} do t = t[1] end\n\
+         if x ~= 1 or t ~= 1 then error('wrong value') end",
+        "f(".repeat(depth),
+        ")".repeat(depth),
+        "{".repeat(depth),
+        "}".repeat(depth),
+        depth
+    );
+    let result = execute_code(&code);
+    assert!(
+        result.is_ok(),
+        "Deeply nested calls and tables should work: {:?}",
+        result
+    );
+}
+
+#[test]
+fn test_deeply_nested_blocks() {
+    let depth = 10000;
+    let code = format!(
+        "local n = 0\n{}n = n + 1{}\nif n ~= 1 then error('wrong value') end",
+        "do ".repeat(depth),
+        " end".repeat(depth)
+    );
+    let result = execute_code(&code);
+    assert!(
+        result.is_ok(),
+        "Deeply nested blocks should work: {:?}",
+        result
+    );
+}
@@ -0,0 +1,135 @@
+mod common;
+
+use common::{eval_all, eval_in};
+use muscm::interpreter::{Environment, SVal};
+use std::path::PathBuf;
+
+/// A fresh path in the temp directory, removed when dropped
+struct TempPath(PathBuf);
+
+impl TempPath {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("muscm_scm_{}_{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        TempPath(path)
+    }
+
+    fn scheme(&self) -> String {
+        format!("{:?}", self.0.display().to_string())
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[test]
+fn test_write_then_read_file() {
+    let path = TempPath::new("roundtrip.txt");
+    let code = format!(
+        r#"
+        (define out (open-output-file {0}))
+        (write-string "first line" out)
+        (newline out)
+        (write '(a 1) out)
+        (close-port out)
+        (define in (open-input-file {0}))
+        (define line (read-line in))
+        (define datum (read in))
+        (list line datum (eof-object? (read-line in)))
+    "#,
+        path.scheme()
+    );
+    let result = eval_all(&code).unwrap();
+    assert_eq!(result.to_string(), "(\"first line\" (a 1) #t)");
+    assert_eq!(
+        std::fs::read_to_string(&path.0).unwrap(),
+        "first line\n(a 1)"
+    );
+}
+
+#[test]
+fn test_read_char_and_lines() {
+    let path = TempPath::new("chars.txt");
+    std::fs::write(&path.0, "héllo\r\nsecond\nlast").unwrap();
+    let code = format!(
+        r#"
+        (define in (open-input-file {}))
+        (define c1 (read-char in))
+        (define c2 (read-char in))
+        (list c1 c2 (read-line in) (read-line in) (read-line in) (eof-object? (read-char in)))
+    "#,
+        path.scheme()
+    );
+    let result = eval_all(&code).unwrap();
+    assert_eq!(
+        result.to_string(),
+        "(#\\h #\\é \"llo\" \"second\" \"last\" #t)"
+    );
+}
+
+#[test]
+fn test_closed_ports_reject_io() {
+    let path = TempPath::new("closed.txt");
+    let code = format!(
+        "(define out (open-output-file {})) (close-port out) (write-string \"x\" out)",
+        path.scheme()
+    );
+    assert_eq!(
+        eval_all(&code),
+        Err("Cannot write to a closed port".to_string())
+    );
+    assert!(
+        eval_all(r#"(define p (open-input-string "x")) (close-port p) (read-char p)"#).is_err()
+    );
+    assert!(eval_all(r#"(open-input-file "/nonexistent/muscm/file.scm")"#).is_err());
+}
+
+#[test]
+fn test_write_string_defaults_to_current_output() {
+    let result = eval_all(r#"(with-output-to-string (lambda () (write-string "ab")))"#);
+    assert_eq!(result, Ok(SVal::String("ab".to_string())));
+}
+
+#[test]
+fn test_sandbox_hides_file_builtins() {
+    let env = Environment::new();
+    let mut sandbox = env.sandbox(&["+", "read-line", "missing"]);
+    assert_eq!(eval_in("(+ 1 2)", &mut sandbox), Ok(SVal::Integer(3)));
+    assert_eq!(
+        eval_in(r#"(open-input-file "x")"#, &mut sandbox),
+        Err("Unbound variable: open-input-file".to_string())
+    );
+    assert_eq!(
+        eval_in(r#"(getenv "HOME")"#, &mut sandbox),
+        Err("Unbound variable: getenv".to_string())
+    );
+}
+
+#[test]
+fn test_environment_and_command_line() {
+    std::env::set_var("MUSCM_SCM_TEST_VAR", "value");
+    let mut env = Environment::new();
+    env.set_command_line("main.scm", &["a".to_string(), "b".to_string()]);
+    let values = eval_in(
+        r#"(list (getenv "MUSCM_SCM_TEST_VAR")
+                 (get-environment-variable "MUSCM_SCM_TEST_UNSET")
+                 (car (cdr (assoc "MUSCM_SCM_TEST_VAR" (get-environment-variables))))
+                 (command-line))"#,
+        &mut env,
+    );
+    let Ok(SVal::List(values)) = values else {
+        panic!("expected a list, got {:?}", values);
+    };
+    let string = |s: &str| SVal::String(s.to_string());
+    assert_eq!(
+        values[..3],
+        [string("value"), SVal::Bool(false), string("value")]
+    );
+    let SVal::List(line) = &values[3] else {
+        panic!("(command-line) should return a list");
+    };
+    assert_eq!(line[..], [string("main.scm"), string("a"), string("b")]);
+}
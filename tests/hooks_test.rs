@@ -0,0 +1,132 @@
+use muscm::error_types::LuaError;
+use muscm::executor::{ControlFlow, Executor};
+use muscm::hooks::{HookEvent, HookMask};
+use muscm::lua_interpreter::LuaInterpreter;
+use muscm::lua_parser::parse_source;
+use muscm::lua_value::LuaValue;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+fn run(interp: &mut LuaInterpreter, code: &str) -> Result<Vec<LuaValue>, LuaError> {
+    let block = parse_source(code).expect("Failed to parse");
+    match Executor::new().execute_block(&block, interp)? {
+        ControlFlow::Return(values) => Ok(values),
+        _ => Ok(Vec::new()),
+    }
+}
+
+#[test]
+fn test_cancel_from_another_thread() {
+    let mut interp = LuaInterpreter::new();
+    let token = interp.cancellation_token();
+    let canceller = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        token.cancel();
+    });
+
+    let result = run(&mut interp, "while true do end");
+    canceller.join().unwrap();
+    assert_eq!(result, Err(LuaError::Cancelled));
+
+    // Once reset, the interpreter runs scripts again
+    interp.cancellation_token().reset();
+    assert_eq!(
+        run(&mut interp, "return 1").unwrap(),
+        vec![LuaValue::Number(1.0)]
+    );
+}
+
+#[test]
+fn test_host_count_hook_as_watchdog() {
+    let mut interp = LuaInterpreter::new();
+    let budget = Rc::new(RefCell::new(0));
+    let spent = Rc::clone(&budget);
+    interp.set_hook(
+        move |event| {
+            assert_eq!(event, HookEvent::Count);
+            *spent.borrow_mut() += 1;
+            if *spent.borrow() >= 5 {
+                return Err(LuaError::runtime("instruction budget exceeded", "watchdog"));
+            }
+            Ok(())
+        },
+        HookMask::default(),
+        100,
+    );
+
+    let err = run(&mut interp, "local n = 0 while true do n = n + 1 end").unwrap_err();
+    assert!(err.to_string().contains("budget exceeded"), "{}", err);
+    assert_eq!(*budget.borrow(), 5);
+
+    interp.clear_hook();
+    assert!(run(&mut interp, "local n = 0 for i = 1, 1000 do n = n + 1 end").is_ok());
+}
+
+#[test]
+fn test_host_line_and_call_hooks() {
+    let mut interp = LuaInterpreter::new();
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let seen = Rc::clone(&events);
+    interp.set_hook(
+        move |event| {
+            seen.borrow_mut().push(event);
+            Ok(())
+        },
+        HookMask::parse("crl"),
+        0,
+    );
+
+    run(
+        &mut interp,
+        "local function f()\n  return 1\nend\nlocal x = f()\nx = x + 1",
+    )
+    .unwrap();
+    assert_eq!(
+        *events.borrow(),
+        vec![
+            HookEvent::Line(1),
+            HookEvent::Line(4),
+            HookEvent::Call,
+            HookEvent::Line(2),
+            HookEvent::Return,
+            HookEvent::Line(5),
+        ]
+    );
+}
+
+#[test]
+fn test_debug_sethook_from_lua() {
+    let mut interp = LuaInterpreter::new();
+    let values = run(
+        &mut interp,
+        r#"
+        local calls, lines = 0, {}
+        local function hook(event, line)
+            if event == "call" then calls = calls + 1 end
+            if event == "line" then lines[#lines + 1] = line end
+        end
+        local function noop() end
+        debug.sethook(hook, "cl")
+        noop()
+        noop()
+        local f, mask, count = debug.gethook()
+        debug.sethook()
+        noop()
+        return calls, mask, count, type(f), debug.gethook() == nil, lines[1]
+        "#,
+    )
+    .unwrap();
+    // The calls are the two noops, gethook and the final sethook
+    assert_eq!(
+        values,
+        vec![
+            LuaValue::Number(4.0),
+            LuaValue::String("cl".into()),
+            LuaValue::Number(0.0),
+            LuaValue::String("function".into()),
+            LuaValue::Boolean(true),
+            LuaValue::Number(9.0),
+        ]
+    );
+}
@@ -0,0 +1,77 @@
+use muscm::executor::Executor;
+use muscm::interpreter::{Environment, Interpreter};
+use muscm::lua_interpreter::LuaInterpreter;
+use muscm::lua_parser::{parse as parse_lua, tokenize, TokenSlice};
+use muscm::parser::parse;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+fn run_lua(interp: &mut LuaInterpreter, code: &str) {
+    let tokens = tokenize(code).expect("Failed to tokenize");
+    let (_, block) = parse_lua(TokenSlice::from(tokens.as_slice())).expect("Failed to parse");
+    Executor::new()
+        .execute_block(&block, interp)
+        .expect("script failed");
+}
+
+#[test]
+fn test_lua_print_and_io_write_are_captured() {
+    let mut interp = LuaInterpreter::new();
+    let captured = interp.output.capture();
+    run_lua(
+        &mut interp,
+        r#"
+        print("a", 1, nil)
+        io.write("b", 2.5)
+        io.stdout:write("\n")
+    "#,
+    );
+    assert_eq!(*captured.borrow(), "a\t1\tnil\nb2.5\n");
+}
+
+#[test]
+fn test_lua_print_uses_tostring_metamethod() {
+    let mut interp = LuaInterpreter::new();
+    let captured = interp.output.capture();
+    run_lua(
+        &mut interp,
+        r#"
+        local v = setmetatable({}, {__tostring = function() return "vec" end})
+        print(v, 1)
+    "#,
+    );
+    assert_eq!(*captured.borrow(), "vec\t1\n");
+}
+
+#[test]
+fn test_lua_output_to_callback_and_host_input() {
+    let mut interp = LuaInterpreter::new();
+    let lines = Rc::new(RefCell::new(Vec::new()));
+    let sink = Rc::clone(&lines);
+    interp
+        .output
+        .set_callback(move |text| sink.borrow_mut().push(text.to_string()));
+    interp.input.set_text("first\nsecond\n");
+    run_lua(
+        &mut interp,
+        r#"
+        local line = io.read()
+        print(line:upper())
+        for rest in io.lines() do print(rest) end
+    "#,
+    );
+    assert_eq!(*lines.borrow(), vec!["FIRST\n", "second\n"]);
+}
+
+#[test]
+fn test_scheme_display_is_captured() {
+    let mut env = Environment::new();
+    let captured = env.output_sink().capture();
+    let (arena, nodes) =
+        parse(r#"(define (greet x) (begin (display "hi-") (display x) (newline))) (greet 42)"#)
+            .unwrap();
+    for node in nodes {
+        Interpreter::eval(arena.get(node).unwrap(), &mut env, &arena).unwrap();
+    }
+    assert_eq!(*captured.borrow(), "hi-42\n");
+}
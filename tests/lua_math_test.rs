@@ -0,0 +1,157 @@
+use muscm::executor::{ControlFlow, Executor};
+use muscm::lua_interpreter::LuaInterpreter;
+use muscm::lua_parser::{parse as parse_lua, tokenize, TokenSlice};
+use muscm::lua_value::LuaValue;
+
+fn run(code: &str) -> Result<Vec<LuaValue>, String> {
+    let tokens = tokenize(code).expect("Failed to tokenize");
+    let token_slice = TokenSlice::from(tokens.as_slice());
+    let (_, block) = parse_lua(token_slice).expect("Failed to parse");
+
+    let mut interp = LuaInterpreter::new();
+    match Executor::new().execute_block(&block, &mut interp) {
+        Ok(ControlFlow::Return(values)) => Ok(values),
+        Ok(other) => panic!("Expected return, got {:?}", other),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn numbers(code: &str) -> Vec<f64> {
+    run(code)
+        .unwrap()
+        .into_iter()
+        .map(|value| match value {
+            LuaValue::Number(n) => n,
+            other => panic!("Expected number, got {:?}", other),
+        })
+        .collect()
+}
+
+fn assert_close(actual: &[f64], expected: &[f64]) {
+    assert_eq!(actual.len(), expected.len());
+    for (a, e) in actual.iter().zip(expected) {
+        assert!((a - e).abs() < 1e-9, "{:?} != {:?}", actual, expected);
+    }
+}
+
+#[test]
+fn test_constants() {
+    let values = numbers("return math.pi, math.huge, -math.huge");
+    assert_eq!(
+        values,
+        vec![std::f64::consts::PI, f64::INFINITY, f64::NEG_INFINITY]
+    );
+}
+
+#[test]
+fn test_roots_powers_and_logs() {
+    let values = numbers(
+        "return math.sqrt(16), math.pow(2, 10), math.exp(0), math.log(math.exp(2)), \
+         math.log(8, 2), math.log(1000, 10)",
+    );
+    assert_close(&values, &[4.0, 1024.0, 1.0, 2.0, 3.0, 3.0]);
+}
+
+#[test]
+fn test_trigonometry() {
+    let values = numbers(
+        "return math.sin(math.pi / 2), math.cos(0), math.tan(0), math.asin(1), \
+         math.acos(1), math.atan(1), math.atan(1, -1), math.deg(math.pi), math.rad(180)",
+    );
+    let pi = std::f64::consts::PI;
+    assert_close(
+        &values,
+        &[
+            1.0,
+            1.0,
+            0.0,
+            pi / 2.0,
+            0.0,
+            pi / 4.0,
+            3.0 * pi / 4.0,
+            180.0,
+            pi,
+        ],
+    );
+}
+
+#[test]
+fn test_fmod_and_modf() {
+    let values = numbers(
+        "local i, f = math.modf(3.75)
+         local ni, nf = math.modf(-2.5)
+         return math.fmod(7, 3), math.fmod(-7, 3), i, f, ni, nf",
+    );
+    assert_eq!(values, vec![1.0, -1.0, 3.0, 0.75, -2.0, -0.5]);
+
+    let err = run("return math.fmod(1, 0)").unwrap_err();
+    assert!(err.contains("divisor is zero"), "{}", err);
+}
+
+#[test]
+fn test_floor_division_and_modulo_round_down() {
+    let values = numbers(
+        "return 7 // 2, -7 // 2, 7 // -2, -7 // -2,
+                7 % 3, -7 % 3, 7 % -3, -7 % -3,
+                5.5 % 2, -5.5 % 2, 5.5 // -2, -5 % math.huge",
+    );
+    assert_eq!(
+        values,
+        vec![
+            3.0,
+            -4.0,
+            -4.0,
+            3.0,
+            1.0,
+            2.0,
+            -2.0,
+            -1.0,
+            1.5,
+            0.5,
+            -3.0,
+            f64::INFINITY
+        ]
+    );
+}
+
+#[test]
+fn test_floor_division_and_modulo_by_zero() {
+    let values = numbers("return 1 // 0, -1 // 0, 1 % 0, 7 // 0.0, -7 % 0, 0 // 0");
+    assert_eq!(values[..2], [f64::INFINITY, f64::NEG_INFINITY]);
+    assert!(values[2].is_nan());
+    assert_eq!(values[3], f64::INFINITY);
+    assert!(values[4].is_nan() && values[5].is_nan(), "{:?}", values);
+}
+
+#[test]
+fn test_random_ranges() {
+    let values = numbers(
+        "local ok = true
+         for i = 1, 200 do
+             local f = math.random()
+             local n = math.random(6)
+             local m = math.random(-3, 3)
+             ok = ok and f >= 0 and f < 1 and n >= 1 and n <= 6 and n == math.floor(n)
+                     and m >= -3 and m <= 3
+         end
+         if ok then return 1 end
+         return 0",
+    );
+    assert_eq!(values, vec![1.0]);
+
+    let err = run("return math.random(5, 1)").unwrap_err();
+    assert!(err.contains("interval is empty"), "{}", err);
+}
+
+#[test]
+fn test_randomseed_repeats_sequence() {
+    let values = numbers(
+        "math.randomseed(42)
+         local a, b, c = math.random(1000), math.random(1000), math.random()
+         math.randomseed(42)
+         local x, y, z = math.random(1000), math.random(1000), math.random()
+         if a == x and b == y and c == z then return 1 end
+         return 0",
+    );
+    assert_eq!(values, vec![1.0]);
+}
@@ -0,0 +1,272 @@
+use muscm::executor::{ControlFlow, Executor};
+use muscm::lua_interpreter::LuaInterpreter;
+use muscm::lua_parser::{parse as parse_lua, tokenize, TokenSlice};
+use muscm::lua_value::LuaValue;
+
+fn run(code: &str) -> Result<Vec<LuaValue>, String> {
+    let tokens = tokenize(code).expect("Failed to tokenize");
+    let token_slice = TokenSlice::from(tokens.as_slice());
+    let (_, block) = parse_lua(token_slice).expect("Failed to parse");
+
+    let mut interp = LuaInterpreter::new();
+    match Executor::new().execute_block(&block, &mut interp) {
+        Ok(ControlFlow::Return(values)) => Ok(values),
+        Ok(other) => panic!("Expected return, got {:?}", other),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn strings(code: &str) -> Vec<String> {
+    run(code)
+        .unwrap()
+        .into_iter()
+        .map(|value| match value {
+            LuaValue::String(s) => s.to_string(),
+            other => panic!("Expected string, got {:?}", other),
+        })
+        .collect()
+}
+
+fn bytes(code: &str) -> Vec<Vec<u8>> {
+    run(code)
+        .unwrap()
+        .into_iter()
+        .map(|value| match value {
+            LuaValue::String(s) => s.to_vec(),
+            other => panic!("Expected string, got {:?}", other),
+        })
+        .collect()
+}
+
+fn numbers(code: &str) -> Vec<f64> {
+    run(code)
+        .unwrap()
+        .into_iter()
+        .map(|value| match value {
+            LuaValue::Number(n) => n,
+            other => panic!("Expected number, got {:?}", other),
+        })
+        .collect()
+}
+
+#[test]
+fn test_sub_indices() {
+    let values = strings(
+        "local s = 'hello'
+         return s:sub(2), s:sub(2, -1), s:sub(-3), s:sub(-3, -2), s:sub(0), s:sub(-100, 2),
+                s:sub(4, 2), s:sub(6), s:sub(2, 100)",
+    );
+    assert_eq!(
+        values,
+        ["ello", "ello", "llo", "ll", "hello", "he", "", "", "ello"]
+    );
+}
+
+#[test]
+fn test_sub_counts_bytes_without_panicking() {
+    // "é" is the two bytes C3 A9, so byte 2 is in the middle of it
+    let values = bytes(
+        "local s = 'héllo' return s:sub(1, 3), s:sub(2, 3), s:sub(1, 2), s:sub(3), s:sub(3, 3)",
+    );
+    assert_eq!(
+        values,
+        [
+            &b"h\xc3\xa9"[..],
+            b"\xc3\xa9",
+            b"h\xc3",
+            b"\xa9llo",
+            b"\xa9"
+        ]
+    );
+    assert_eq!(numbers("return string.len('héllo')"), [6.0]);
+    let values =
+        run("local s = 'héllo' return s:sub(2, 2) == '\\xC3', s:sub(2, 2) .. s:sub(3, 3) == 'é'")
+            .unwrap();
+    assert_eq!(values, [LuaValue::Boolean(true), LuaValue::Boolean(true)]);
+}
+
+#[test]
+fn test_strings_hold_bytes() {
+    assert_eq!(
+        numbers(r#"return #"\xff", #"\255\0\1", #"\u{7FFFFFFF}", #("\xff" .. "\xfe")"#),
+        [1.0, 3.0, 6.0, 2.0]
+    );
+    assert_eq!(
+        bytes(
+            r#"return "\xff\65", "a\z
+                  b", "\u{E9}", string.format("%c%c", 255, 0)"#
+        ),
+        [&b"\xffA"[..], b"ab", b"\xc3\xa9", b"\xff\x00"]
+    );
+    // Only ASCII letters change case
+    assert_eq!(
+        bytes("return string.upper('héllo\\xff'), string.lower('ÉCOLE')"),
+        [&b"H\xc3\xa9LLO\xff"[..], b"\xc3\x89cole"]
+    );
+    // %q writes the bytes back so Lua reads the same string
+    assert_eq!(
+        bytes(r#"return string.format("%q|%s|%.1s", "\xff\n\0" .. "1", "\xfe", "\xc3\xa9")"#),
+        [&b"\"\xff\\\n\\0001\"|\xfe|\xc3"[..]]
+    );
+}
+
+#[test]
+fn test_utf8_char_and_codepoint() {
+    assert_eq!(strings("return utf8.char(72, 233, 20013)")[0], "Hé中");
+    assert_eq!(strings("return utf8.char()")[0], "");
+    assert_eq!(
+        numbers("return utf8.codepoint('Hé中', 1, -1)"),
+        [72.0, 233.0, 20013.0]
+    );
+    assert_eq!(numbers("return utf8.codepoint('Hé中', 2)"), [233.0]);
+    assert_eq!(numbers("return utf8.codepoint('Hé中', -3)"), [20013.0]);
+
+    let error = run("return utf8.codepoint('Hé', 3)").unwrap_err();
+    assert!(error.contains("invalid UTF-8 code"), "{}", error);
+    let error = run("return utf8.codepoint('abc', 4)").unwrap_err();
+    assert!(error.contains("out of bounds"), "{}", error);
+    let error = run("return utf8.char(-1)").unwrap_err();
+    assert!(error.contains("value out of range"), "{}", error);
+}
+
+#[test]
+fn test_utf8_len() {
+    assert_eq!(numbers("return utf8.len('Hé中')"), [3.0]);
+    assert_eq!(numbers("return utf8.len('Hé中', 2)"), [2.0]);
+    assert_eq!(numbers("return utf8.len('Hé中', 1, 2)"), [2.0]);
+    assert_eq!(numbers("return utf8.len('')"), [0.0]);
+
+    let values = run("return utf8.len('Hé', 3)").unwrap();
+    assert!(matches!(values[..], [LuaValue::Nil, LuaValue::Number(n)] if n == 3.0));
+}
+
+#[test]
+fn test_utf8_rejects_invalid_sequences() {
+    // A stray byte, a truncated sequence, an overlong NUL, a surrogate
+    // and a code point past U+10FFFF
+    for (code, position) in [
+        (r#"utf8.len("a\xffb")"#, 2.0),
+        (r#"utf8.len("ab\xc3")"#, 3.0),
+        (r#"utf8.len("\xc0\x80")"#, 1.0),
+        (r#"utf8.len("x\u{D800}")"#, 2.0),
+        (r#"utf8.len("\u{110000}")"#, 1.0),
+    ] {
+        let values = run(&format!("return {}", code)).unwrap();
+        assert_eq!(
+            values,
+            [LuaValue::Nil, LuaValue::Number(position)],
+            "{}",
+            code
+        );
+    }
+    // lax accepts what Lua's extended UTF-8 can encode
+    assert_eq!(
+        numbers(r#"return utf8.len("x\u{D800}\u{7FFFFFFF}", 1, -1, true)"#),
+        [3.0]
+    );
+    assert_eq!(
+        numbers(r#"return utf8.codepoint("\u{D800}", 1, 1, true)"#),
+        [55296.0]
+    );
+    assert_eq!(
+        numbers("return #utf8.char(0x7FFFFFFF), utf8.codepoint(utf8.char(0x7FFFFFFF), 1, 1, true)"),
+        [6.0, 2147483647.0]
+    );
+    let error = run(r#"return utf8.codepoint("a\xff", 1, -1)"#).unwrap_err();
+    assert!(error.contains("invalid UTF-8 code"), "{}", error);
+}
+
+#[test]
+fn test_utf8_charpattern() {
+    assert_eq!(
+        bytes("return utf8.charpattern")[0],
+        b"[\0-\x7F\xC2-\xFD][\x80-\xBF]*"
+    );
+}
+
+#[test]
+fn test_format_conversions() {
+    let values = strings(
+        r#"return string.format("%d|%5d|%-5d|%05d|%+d|%.3d", 42, 42, 42, -42, 7, 5),
+                  string.format("%x|%X|%#x|%o|%c", 255, 255, 255, 8, 65),
+                  string.format("%.2f|%e|%g|%g|%10.3f", 3.14159, 12345.678, 0.0001, 2^70, -2.5),
+                  string.format("%s|%.2s|%5s|%%|%q", "hi", "hello", "x", 'a "b"\n')"#,
+    );
+    assert_eq!(
+        values,
+        [
+            "42|   42|42   |-0042|+7|005",
+            "ff|FF|0xff|10|A",
+            "3.14|1.234568e+04|0.0001|1.18059e+21|    -2.500",
+            "hi|he|    x|%|\"a \\\"b\\\"\\\n\"",
+        ]
+    );
+    assert!(run("return string.format('%d', 1.5)").is_err());
+    assert!(run("return string.format('%d')").is_err());
+}
+
+#[test]
+fn test_identity_strings() {
+    let values = run("local t, u = {}, {}
+         local function f() end
+         return tostring(t) == tostring(t), tostring(t) ~= tostring(u),
+                tostring(t) == 'table: ' .. string.format('%p', t),
+                tostring(f) == 'function: ' .. string.format('%p', f),
+                tostring(print):sub(1, 18), string.format('%p', 1)")
+    .unwrap();
+    assert_eq!(
+        values,
+        vec![
+            LuaValue::Boolean(true),
+            LuaValue::Boolean(true),
+            LuaValue::Boolean(true),
+            LuaValue::Boolean(true),
+            LuaValue::String("function: builtin:".into()),
+            LuaValue::String("(null)".into()),
+        ]
+    );
+    assert!(strings("return tostring({})")[0].starts_with("table: 0x"));
+}
+
+#[test]
+fn test_tostring_metamethod() {
+    let values = strings(
+        "local point = setmetatable({x = 1, y = 2}, {
+             __tostring = function(p) return '(' .. p.x .. ', ' .. p.y .. ')' end
+         })
+         return tostring(point), tostring(42)",
+    );
+    assert_eq!(values, ["(1, 2)", "42"]);
+    let err = run("return tostring(setmetatable({}, {__tostring = function() return 1 end}))")
+        .unwrap_err();
+    assert!(err.contains("'__tostring' must return a string"), "{}", err);
+}
+
+#[test]
+fn test_strings_share_a_metatable_indexing_the_string_library() {
+    let values = run("local s = 'abc'
+         local mt = getmetatable(s)
+         function string.shout(x) return x:upper() .. '!' end
+         return mt.__index == string, mt == getmetatable(''), s.len == string.len,
+                s:shout(), s.missing, s:len()")
+    .unwrap();
+    assert_eq!(
+        values,
+        [
+            LuaValue::Boolean(true),
+            LuaValue::Boolean(true),
+            LuaValue::Boolean(true),
+            LuaValue::String("ABC!".into()),
+            LuaValue::Nil,
+            LuaValue::Number(3.0),
+        ]
+    );
+
+    // Method lookups go through __index, so replacing it changes them
+    let values = strings(
+        "getmetatable('').__index = {greet = function(s) return 'hi ' .. s end}
+         return ('bob'):greet()",
+    );
+    assert_eq!(values, ["hi bob"]);
+    assert!(run("return ('x'):upper2()").is_err());
+}
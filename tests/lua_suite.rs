@@ -0,0 +1,265 @@
+//! Runs Lua test suite files and checks their scores against a baseline
+//!
+//! `fixtures/lua_suite` holds files in the style of the Lua 5.4 test suite
+//! (`constructs.lua`, `strings.lua`, `literals.lua`, `vararg.lua`): plain
+//! scripts of `assert`s covering the same ground as the PUC-Lua files of
+//! those names, written for this repository. The official files can be run
+//! instead by pointing `LUA_TESTS_DIR` at an unpacked
+//! `lua-5.4.x-tests.tar.gz`; their scores are reported but not compared.
+//!
+//! The files are scored rather than required to pass: `assert` is replaced
+//! by one that counts passing and failing assertions and carries on, and a
+//! file either runs to its end or stops at its first error.
+//! `fixtures/lua_suite/baseline.txt` records the best score so far for each
+//! file. A file that passes fewer assertions than its baseline, fails more,
+//! stops where it used to finish, or has no baseline yet fails the test.
+//! Run with `LUA_SUITE_BLESS=1` to write the current scores back once they
+//! improve, and `--nocapture` to see the report.
+
+use muscm::error_types::LuaResult;
+use muscm::lua_interpreter::LuaInterpreter;
+use muscm::lua_parser::parse_source;
+use muscm::lua_value::{LuaFunction, LuaValue};
+use muscm::vm::execute_chunk;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// Suite files this harness runs
+const SELECTED: &[&str] = &[
+    "constructs.lua",
+    "strings.lua",
+    "literals.lua",
+    "vararg.lua",
+];
+
+/// How long one file may run before it is cancelled
+const TIME_LIMIT: Duration = Duration::from_secs(20);
+
+/// How one suite file did
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Score {
+    passed: usize,
+    failed: usize,
+    /// Reached the `print "OK"` every file ends with
+    completed: bool,
+    /// Messages of the first failing assertions
+    failures: Vec<String>,
+    /// What stopped the file, if it did not complete
+    error: Option<String>,
+}
+
+fn vendored_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/lua_suite")
+}
+
+/// The official suite, when `LUA_TESTS_DIR` points at a copy
+fn official_dir() -> Option<PathBuf> {
+    std::env::var_os("LUA_TESTS_DIR").map(PathBuf::from)
+}
+
+fn baseline_path() -> PathBuf {
+    vendored_dir().join("baseline.txt")
+}
+
+/// Globals the suite reads to decide what to run, and an `assert` that
+/// keeps score
+fn install_compat(interp: &mut LuaInterpreter, score: &Rc<RefCell<Score>>) {
+    // Skip stress tests, non-portable checks and the messages about them
+    for flag in ["_soft", "_port", "_nomsg"] {
+        interp.set_global(flag, LuaValue::Boolean(true));
+    }
+
+    let score = Rc::clone(score);
+    let assert: Rc<dyn Fn(Vec<LuaValue>) -> LuaResult<Vec<LuaValue>>> = Rc::new(move |args| {
+        let mut score = score.borrow_mut();
+        if args.first().is_some_and(LuaValue::is_truthy) {
+            score.passed += 1;
+        } else {
+            score.failed += 1;
+            if score.failures.len() < 5 {
+                let message = match args.get(1) {
+                    None | Some(LuaValue::Nil) => "assertion failed!".to_string(),
+                    Some(message) => message.to_string(),
+                };
+                score.failures.push(message);
+            }
+        }
+        Ok(args)
+    });
+    interp.set_global(
+        "assert",
+        LuaValue::Function(Rc::new(LuaFunction::MultiBuiltin(assert))),
+    );
+}
+
+/// Run one suite file, cancelling it if it takes too long
+fn run_file(path: &Path) -> Score {
+    let score = Rc::new(RefCell::new(Score::default()));
+    let mut interp = LuaInterpreter::new();
+    let output = interp.output.capture();
+    install_compat(&mut interp, &score);
+
+    let (done, finished) = mpsc::channel::<()>();
+    let cancel = interp.cancel.clone();
+    std::thread::spawn(move || {
+        if finished.recv_timeout(TIME_LIMIT) == Err(mpsc::RecvTimeoutError::Timeout) {
+            cancel.cancel();
+        }
+    });
+    // The official files are Latin-1 in places
+    let code = String::from_utf8_lossy(&std::fs::read(path).unwrap()).into_owned();
+    let result = parse_source(&code)
+        .and_then(|block| execute_chunk(&block, &mut interp).map_err(|e| e.to_string()));
+    drop(done);
+
+    let mut score = score.borrow().clone();
+    match result {
+        // A file can also end early without an error, so look for its last
+        // line of output
+        Ok(_) if output.borrow().trim_end().ends_with("OK") => score.completed = true,
+        Ok(_) => score.error = Some("ended before its last line".to_string()),
+        Err(e) => score.error = Some(e),
+    }
+    score
+}
+
+/// Best score so far of one file
+#[derive(Debug, Clone, Copy)]
+struct Best {
+    passed: usize,
+    failed: usize,
+    completed: bool,
+}
+
+/// Best scores so far: `file passed failed completed` per line
+fn read_baseline() -> BTreeMap<String, Best> {
+    let text = std::fs::read_to_string(baseline_path()).unwrap_or_default();
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [file, passed, failed, completed] = fields[..] else {
+                panic!("malformed baseline line: {}", line);
+            };
+            let best = Best {
+                passed: passed.parse().expect("passed count"),
+                failed: failed.parse().expect("failed count"),
+                completed: completed == "yes",
+            };
+            (file.to_string(), best)
+        })
+        .collect()
+}
+
+fn write_baseline(baseline: &BTreeMap<String, Best>) {
+    let mut text = String::from(
+        "# Best score of each file in fixtures/lua_suite: assertions passed\n\
+         # and failed, and whether the file runs to its end. Written by\n\
+         # `LUA_SUITE_BLESS=1 cargo test --test lua_suite`; see tests/lua_suite.rs.\n",
+    );
+    for (file, best) in baseline {
+        let completed = if best.completed { "yes" } else { "no" };
+        text.push_str(&format!(
+            "{} {} {} {}\n",
+            file, best.passed, best.failed, completed
+        ));
+    }
+    std::fs::write(baseline_path(), text).unwrap();
+}
+
+/// Run the selected files in `dir`, printing a line for each
+fn run_suite(dir: &Path) -> Vec<(&'static str, Score)> {
+    let missing: Vec<&str> = SELECTED
+        .iter()
+        .copied()
+        .filter(|file| !dir.join(file).exists())
+        .collect();
+    assert!(
+        missing.is_empty(),
+        "Lua test suite files missing from {}: {}",
+        dir.display(),
+        missing.join(", ")
+    );
+    SELECTED
+        .iter()
+        .map(|&file| {
+            let score = run_file(&dir.join(file));
+            println!(
+                "{:<16} {:>5} passed {:>5} failed  {}",
+                file,
+                score.passed,
+                score.failed,
+                match &score.error {
+                    None => "completed".to_string(),
+                    Some(e) => format!("stopped: {}", e.lines().next().unwrap_or_default()),
+                }
+            );
+            for failure in &score.failures {
+                println!("    failed: {}", failure);
+            }
+            (file, score)
+        })
+        .collect()
+}
+
+#[test]
+fn test_lua_suite_does_not_regress() {
+    let mut baseline = read_baseline();
+    let bless = std::env::var_os("LUA_SUITE_BLESS").is_some();
+
+    let mut regressions = Vec::new();
+    for (file, score) in run_suite(&vendored_dir()) {
+        let current = Best {
+            passed: score.passed,
+            failed: score.failed,
+            completed: score.completed,
+        };
+        let Some(&best) = baseline.get(file) else {
+            if bless {
+                baseline.insert(file.to_string(), current);
+            } else {
+                regressions.push(format!("{}: no baseline yet", file));
+            }
+            continue;
+        };
+        if current.passed < best.passed
+            || current.failed > best.failed
+            || (best.completed && !current.completed)
+        {
+            regressions.push(format!(
+                "{}: {} passed, {} failed{} (baseline {} passed, {} failed{})",
+                file,
+                current.passed,
+                current.failed,
+                if current.completed { "" } else { ", stopped" },
+                best.passed,
+                best.failed,
+                if best.completed { ", completed" } else { "" }
+            ));
+        } else if bless {
+            baseline.insert(file.to_string(), current);
+        }
+    }
+
+    if bless {
+        write_baseline(&baseline);
+    }
+    assert!(
+        regressions.is_empty(),
+        "Lua test suite regressed:\n{}",
+        regressions.join("\n")
+    );
+}
+
+/// Scores of the official files, which have no baseline here
+#[test]
+fn test_official_lua_suite_report() {
+    if let Some(dir) = official_dir() {
+        run_suite(&dir);
+    }
+}
@@ -50,7 +50,7 @@ fn test_require_with_exports() {
     assert!(result.is_ok(), "Execution failed: {:?}", result);
 
     let host = interp.lookup("host").expect("host variable not found");
-    assert_eq!(host, LuaValue::String("localhost".to_string()));
+    assert_eq!(host, LuaValue::String("localhost".into()));
 
     let port = interp.lookup("port").expect("port variable not found");
     assert_eq!(port, LuaValue::Number(8080.0));
@@ -114,3 +114,93 @@ fn test_module_loader_cached_count() {
     let loader = interp.module_loader.borrow();
     assert_eq!(loader.cached_count(), 0);
 }
+
+fn run_with_modules(interp: &mut LuaInterpreter, code: &str) {
+    let tokens = tokenize(code).expect("Failed to tokenize");
+    let token_slice = TokenSlice::from(tokens.as_slice());
+    let (_, block) = parse_lua(token_slice).expect("Failed to parse");
+
+    let result = Executor::new().execute_block(&block, interp);
+    assert!(result.is_ok(), "Execution failed: {:?}", result);
+}
+
+#[test]
+fn test_package_path_and_init_modules() {
+    let mut interp = LuaInterpreter::new();
+
+    run_with_modules(
+        &mut interp,
+        r#"
+        package.path = "fixtures/modules/?.lua;fixtures/modules/?/init.lua"
+        local pkg = require("pkg")
+        local math_utils = require("utils.math")
+        name = pkg.name
+        squared = math_utils.square(3)
+        cached = package.loaded["utils.math"] == math_utils
+    "#,
+    );
+
+    assert_eq!(interp.lookup("name"), Some(LuaValue::String("pkg".into())));
+    assert_eq!(interp.lookup("squared"), Some(LuaValue::Number(9.0)));
+    assert_eq!(interp.lookup("cached"), Some(LuaValue::Boolean(true)));
+}
+
+#[test]
+fn test_package_loaded_is_mutable() {
+    let mut interp = LuaInterpreter::new();
+    interp.add_module_search_path(PathBuf::from("fixtures/modules"));
+
+    run_with_modules(
+        &mut interp,
+        r#"
+        package.loaded.fake = {answer = 42}
+        answer = require("fake").answer
+        local first = require("simple")
+        package.loaded.simple = nil
+        reloaded = require("simple") ~= first
+    "#,
+    );
+
+    assert_eq!(interp.lookup("answer"), Some(LuaValue::Number(42.0)));
+    assert_eq!(interp.lookup("reloaded"), Some(LuaValue::Boolean(true)));
+}
+
+#[test]
+fn test_preload_from_host() {
+    let mut interp = LuaInterpreter::new();
+    interp.preload_module(
+        "host.greeting",
+        std::rc::Rc::new(|args| {
+            let name = args.first().map(|v| v.to_string()).unwrap_or_default();
+            Ok(LuaValue::String(format!("hello from {}", name).into()))
+        }),
+    );
+
+    run_with_modules(
+        &mut interp,
+        r#"
+        greeting = require("host.greeting")
+        cached = package.loaded["host.greeting"] == greeting
+    "#,
+    );
+
+    assert_eq!(
+        interp.lookup("greeting"),
+        Some(LuaValue::String("hello from host.greeting".into()))
+    );
+    assert_eq!(interp.lookup("cached"), Some(LuaValue::Boolean(true)));
+}
+
+#[test]
+fn test_missing_module_lists_searched_files() {
+    let mut interp = LuaInterpreter::new();
+    let tokens =
+        tokenize(r#"package.path = "nowhere/?.lua" require("a.b")"#).expect("Failed to tokenize");
+    let (_, block) = parse_lua(TokenSlice::from(tokens.as_slice())).expect("Failed to parse");
+
+    let error = Executor::new()
+        .execute_block(&block, &mut interp)
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("no file 'nowhere/a/b.lua'"), "{}", error);
+}
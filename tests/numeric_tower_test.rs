@@ -0,0 +1,89 @@
+mod common;
+
+use common::eval_to_string;
+
+#[test]
+fn test_integer_literals_are_exact() {
+    assert_eq!(eval_to_string("42").unwrap(), "42");
+    assert_eq!(eval_to_string("(exact? 42)").unwrap(), "#t");
+    assert_eq!(eval_to_string("(inexact? 4.0)").unwrap(), "#t");
+    assert_eq!(eval_to_string("(+ 1 2)").unwrap(), "3");
+}
+
+#[test]
+fn test_division_produces_rationals() {
+    assert_eq!(eval_to_string("(/ 1 3)").unwrap(), "1/3");
+    assert_eq!(eval_to_string("(/ 6 3)").unwrap(), "2");
+    assert_eq!(eval_to_string("(+ 1/2 1/3)").unwrap(), "5/6");
+    assert_eq!(eval_to_string("(/ 2)").unwrap(), "1/2");
+}
+
+#[test]
+fn test_exact_division_by_zero_is_error() {
+    assert!(eval_to_string("(/ 1 0)").is_err());
+    assert_eq!(eval_to_string("(/ 1.0 0)").unwrap(), "+inf.0");
+}
+
+#[test]
+fn test_bignum_arithmetic() {
+    assert_eq!(
+        eval_to_string("(expt 2 100)").unwrap(),
+        "1267650600228229401496703205376"
+    );
+    assert_eq!(
+        eval_to_string("(* 9223372036854775807 2)").unwrap(),
+        "18446744073709551614"
+    );
+    assert_eq!(
+        eval_to_string("(- 100000000000000000000 99999999999999999999)").unwrap(),
+        "1"
+    );
+}
+
+#[test]
+fn test_mixed_exactness_is_contagious() {
+    assert_eq!(eval_to_string("(+ 1 0.5)").unwrap(), "1.5");
+    assert_eq!(eval_to_string("(exact? (* 2 1.0))").unwrap(), "#f");
+    assert_eq!(eval_to_string("(= 1/2 0.5)").unwrap(), "#t");
+    assert_eq!(eval_to_string("(< 1/3 0.34)").unwrap(), "#t");
+}
+
+#[test]
+fn test_exactness_conversions() {
+    assert_eq!(eval_to_string("(exact->inexact 1/4)").unwrap(), "0.25");
+    assert_eq!(eval_to_string("(inexact->exact 0.5)").unwrap(), "1/2");
+    assert_eq!(eval_to_string("(exact 3.0)").unwrap(), "3");
+    assert_eq!(eval_to_string("(inexact 3)").unwrap(), "3.0");
+}
+
+#[test]
+fn test_integer_division() {
+    assert_eq!(eval_to_string("(quotient 17 5)").unwrap(), "3");
+    assert_eq!(eval_to_string("(remainder -17 5)").unwrap(), "-2");
+    assert_eq!(eval_to_string("(modulo -17 5)").unwrap(), "3");
+    assert!(eval_to_string("(quotient 1 0)").is_err());
+}
+
+#[test]
+fn test_numerator_denominator_and_rounding() {
+    assert_eq!(eval_to_string("(numerator 6/4)").unwrap(), "3");
+    assert_eq!(eval_to_string("(denominator 6/4)").unwrap(), "2");
+    assert_eq!(eval_to_string("(round 5/2)").unwrap(), "2");
+    assert_eq!(eval_to_string("(floor -7/2)").unwrap(), "-4");
+    assert_eq!(eval_to_string("(integer? 4/2)").unwrap(), "#t");
+    assert_eq!(eval_to_string("(rational? 1/3)").unwrap(), "#t");
+}
+
+#[test]
+fn test_radix_conversions() {
+    assert_eq!(eval_to_string("(number->string 255 16)").unwrap(), "\"ff\"");
+    assert_eq!(eval_to_string("(number->string -6 2)").unwrap(), "\"-110\"");
+    assert_eq!(eval_to_string("(string->number \"777\" 8)").unwrap(), "511");
+    assert_eq!(eval_to_string("(string->number \"#xff\")").unwrap(), "255");
+    assert_eq!(eval_to_string("(string->number \"19\" 8)").unwrap(), "#f");
+    assert_eq!(eval_to_string("(+ #x10 #b11 #o7)").unwrap(), "26");
+    assert_eq!(eval_to_string("#e0.75").unwrap(), "3/4");
+    assert_eq!(eval_to_string("#i1/4").unwrap(), "0.25");
+    assert!(eval_to_string("(number->string 1.5 16)").is_err());
+    assert!(eval_to_string("(number->string 10 3)").is_err());
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use common::eval_to_string;
+
+#[test]
+fn test_quasiquote_without_unquote() {
+    assert_eq!(eval_to_string("`(a b c)").unwrap(), "(a b c)");
+    assert_eq!(eval_to_string("`x").unwrap(), "x");
+}
+
+#[test]
+fn test_unquote() {
+    let code = "(define b 2) `(a ,b ,(+ b 1))";
+    assert_eq!(eval_to_string(code).unwrap(), "(a 2 3)");
+}
+
+#[test]
+fn test_unquote_splicing() {
+    let code = "(define c (list 3 4)) `(1 2 ,@c 5)";
+    assert_eq!(eval_to_string(code).unwrap(), "(1 2 3 4 5)");
+}
+
+#[test]
+fn test_unquote_splicing_empty_list() {
+    assert_eq!(eval_to_string("`(1 ,@'() 2)").unwrap(), "(1 2)");
+}
+
+#[test]
+fn test_unquote_splicing_requires_list() {
+    assert!(eval_to_string("`(1 ,@2)").is_err());
+}
+
+#[test]
+fn test_quasiquote_in_nested_lists_and_vectors() {
+    let code = "(define x 7) `((a ,x) #(b ,x))";
+    assert_eq!(eval_to_string(code).unwrap(), "((a 7) #(b 7))");
+}
+
+#[test]
+fn test_long_form_quasiquote() {
+    let code = "(define x 1) (quasiquote (a (unquote x) (unquote-splicing (list 2 3))))";
+    assert_eq!(eval_to_string(code).unwrap(), "(a 1 2 3)");
+}
+
+#[test]
+fn test_nested_quasiquote_keeps_inner_unquote() {
+    // The inner unquote belongs to the inner quasiquote and stays as data
+    let code = "(define x 1) `(a `(b ,(c ,x)))";
+    assert_eq!(
+        eval_to_string(code).unwrap(),
+        "(a (quasiquote (b (unquote (c 1)))))"
+    );
+}
+
+#[test]
+fn test_nested_quasiquote_splicing_stays_as_data() {
+    let code = "(define xs (list 1 2)) `(a `(b ,@xs))";
+    assert_eq!(
+        eval_to_string(code).unwrap(),
+        "(a (quasiquote (b (unquote-splicing xs))))"
+    );
+}
+
+#[test]
+fn test_quote_keeps_abbreviations_as_data() {
+    assert_eq!(
+        eval_to_string("'(a `b ,c ,@d)").unwrap(),
+        "(a (quasiquote b) (unquote c) (unquote-splicing d))"
+    );
+}
+
+#[test]
+fn test_unquote_outside_quasiquote_is_error() {
+    assert!(eval_to_string(",x").is_err());
+    assert!(eval_to_string("(unquote x)").is_err());
+}
+
+#[test]
+fn test_quasiquote_builds_code() {
+    // Macro-style list building: assemble a call and check its shape
+    let code = "(define op '+) (define args (list 1 2 3)) `(,op ,@args)";
+    assert_eq!(eval_to_string(code).unwrap(), "(+ 1 2 3)");
+}
@@ -0,0 +1,201 @@
+mod common;
+
+use common::eval_in;
+use muscm::interpreter::{Environment, SVal};
+use std::path::{Path, PathBuf};
+
+/// A fresh directory in the temp directory, removed when dropped
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("muscm_lib_{}_{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    fn write(&self, file: &str, code: &str) {
+        let path = self.0.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, code).unwrap();
+    }
+
+    fn file(&self, file: &str) -> String {
+        format!("{:?}", self.0.join(file).display().to_string())
+    }
+
+    /// An environment whose `import` searches this directory
+    fn env(&self) -> Environment {
+        let env = Environment::new();
+        let dir = Path::new(&self.0);
+        env.set_library_path(&format!(
+            "{};{}",
+            dir.join("?.sld").display(),
+            dir.join("?.scm").display()
+        ));
+        env
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn test_load_defines_in_caller_environment() {
+    let dir = TempDir::new("load");
+    dir.write(
+        "helpers.scm",
+        "(define (square x) (* x x)) (define scale 3)",
+    );
+    let mut env = dir.env();
+    let code = format!("(load {}) (* scale (square 4))", dir.file("helpers.scm"));
+    assert_eq!(eval_in(&code, &mut env), Ok(SVal::Integer(48)));
+    // Procedures from the loaded file outlive its parse
+    assert_eq!(eval_in("(square 5)", &mut env), Ok(SVal::Integer(25)));
+}
+
+#[test]
+fn test_import_finds_library_on_search_path() {
+    let dir = TempDir::new("import");
+    dir.write(
+        "utils/math.sld",
+        "(define-library (utils math)
+           (export double counter)
+           (import (scheme base))
+           (begin
+             (define hidden 2)
+             (define (double x) (* hidden x))
+             (define count 0)
+             (define (counter) (set! count (+ count 1)) count)))",
+    );
+    let mut env = dir.env();
+    let result = eval_in(
+        "(import (scheme base) (utils math))
+         (counter)
+         (list (double 21) (counter))",
+        &mut env,
+    );
+    assert_eq!(result.map(|v| v.to_string()), Ok("(42 2)".to_string()));
+    assert_eq!(
+        eval_in("hidden", &mut env),
+        Err("Unbound variable: hidden".to_string())
+    );
+
+    // A second import reuses the library rather than loading it again
+    assert_eq!(
+        eval_in("(import (utils math)) (counter)", &mut env),
+        Ok(SVal::Integer(3))
+    );
+}
+
+#[test]
+fn test_library_defined_in_program() {
+    let mut env = Environment::new();
+    let result = eval_in(
+        "(define-library (greet)
+           (export greet)
+           (begin (define (greet name) (string-append \"hi-\" name))))
+         (import (greet))
+         (greet \"bob\")",
+        &mut env,
+    );
+    assert_eq!(result, Ok(SVal::String("hi-bob".to_string())));
+}
+
+#[test]
+fn test_circular_imports_are_reported() {
+    let dir = TempDir::new("cycle");
+    dir.write(
+        "a.sld",
+        "(define-library (a) (export x) (import (b)) (begin (define x 1)))",
+    );
+    dir.write(
+        "b.sld",
+        "(define-library (b) (export y) (import (a)) (begin (define y 2)))",
+    );
+    let mut env = dir.env();
+    assert_eq!(
+        eval_in("(import (a))", &mut env),
+        Err("import: circular import of library (a)".to_string())
+    );
+
+    dir.write("self.scm", &format!("(load {})", dir.file("self.scm")));
+    let err = eval_in(&format!("(load {})", dir.file("self.scm")), &mut env).unwrap_err();
+    assert!(err.starts_with("load: circular load of"), "{}", err);
+}
+
+#[test]
+fn test_import_errors() {
+    let dir = TempDir::new("errors");
+    dir.write("empty.sld", "(define x 1)");
+    dir.write(
+        "bad.sld",
+        "(define-library (bad) (export missing) (begin (define x 1)))",
+    );
+    let mut env = dir.env();
+    let err = eval_in("(import (nowhere))", &mut env).unwrap_err();
+    assert!(
+        err.starts_with("Library not found: (nowhere)\n\tno file"),
+        "{}",
+        err
+    );
+    let err = eval_in("(import (empty))", &mut env).unwrap_err();
+    assert!(err.ends_with("does not define library (empty)"), "{}", err);
+    assert_eq!(
+        eval_in("(import (bad))", &mut env),
+        Err("library (bad) exports undefined missing".to_string())
+    );
+}
+
+#[test]
+fn test_libraries_stay_in_the_sandbox() {
+    let dir = TempDir::new("sandbox");
+    let escape = dir.0.join("escape.txt");
+    let program = format!(
+        "(define-library (esc)
+           (export f w c)
+           (begin (define f open-output-file) (define w write-string) (define c close-port)))
+         (import (esc))
+         (define p (f {:?}))
+         (w \"escaped\" p)
+         (c p)",
+        escape.display().to_string()
+    );
+    dir.write(
+        "lib.sld",
+        "(define-library (lib) (export x) (begin (define x 1)))",
+    );
+
+    let mut sandbox = dir.env().sandbox(&["display"]);
+    assert_eq!(
+        eval_in(&program, &mut sandbox),
+        Err("define-library is not allowed in this sandbox".to_string())
+    );
+    assert_eq!(
+        eval_in("(import (lib))", &mut sandbox),
+        Err("import is not allowed in this sandbox".to_string())
+    );
+
+    // Allowed, library bodies still only see what the sandbox does
+    let mut sandbox = dir.env().sandbox(&["display", "define-library", "import"]);
+    assert_eq!(
+        eval_in(&program, &mut sandbox),
+        Err("Unbound variable: open-output-file".to_string())
+    );
+    assert!(!escape.exists());
+    assert_eq!(
+        eval_in("(import (lib)) x", &mut sandbox),
+        Ok(SVal::Integer(1))
+    );
+
+    // A sandbox made inside another cannot allow more than it
+    let mut nested = dir.env().sandbox(&["display"]).sandbox(&["import"]);
+    assert_eq!(
+        eval_in("(import (lib))", &mut nested),
+        Err("import is not allowed in this sandbox".to_string())
+    );
+}
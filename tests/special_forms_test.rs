@@ -0,0 +1,223 @@
+mod common;
+
+use common::eval_in;
+use muscm::interpreter::{Environment, SVal};
+
+#[test]
+fn test_cond_first_true_clause() {
+    let mut env = Environment::new();
+    let result = eval_in("(cond ((> 1 2) 1) ((< 1 2) 2) (else 3))", &mut env);
+    assert!(matches!(result, Ok(SVal::Integer(2))));
+}
+
+#[test]
+fn test_cond_else_clause() {
+    let mut env = Environment::new();
+    let result = eval_in("(cond ((> 1 2) 1) (else 3))", &mut env);
+    assert!(matches!(result, Ok(SVal::Integer(3))));
+}
+
+#[test]
+fn test_cond_test_only_clause_returns_test_value() {
+    let mut env = Environment::new();
+    let result = eval_in("(cond (#f 1) ((+ 2 3)))", &mut env);
+    assert!(matches!(result, Ok(SVal::Integer(5))));
+}
+
+#[test]
+fn test_cond_arrow_clause() {
+    let mut env = Environment::new();
+    let result = eval_in(
+        "(cond ((+ 1 1) => (lambda (x) (* x 10))) (else 0))",
+        &mut env,
+    );
+    assert!(matches!(result, Ok(SVal::Integer(20))));
+}
+
+#[test]
+fn test_cond_no_match() {
+    let mut env = Environment::new();
+    let result = eval_in("(cond (#f 1))", &mut env);
+    assert!(matches!(result, Ok(SVal::Nil)));
+}
+
+#[test]
+fn test_cond_else_must_be_last() {
+    let mut env = Environment::new();
+    let result = eval_in("(cond (else 1) (#t 2))", &mut env);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_case_matches_datum() {
+    let mut env = Environment::new();
+    let result = eval_in(
+        "(case (* 2 3) ((2 3 5 7) 'prime) ((1 4 6 8 9) 'composite))",
+        &mut env,
+    );
+    assert!(matches!(result, Ok(SVal::Atom(ref s)) if s == "composite"));
+}
+
+#[test]
+fn test_case_symbols_and_else() {
+    let mut env = Environment::new();
+    let result = eval_in("(case 'x ((a e i o u) 'vowel) (else 'consonant))", &mut env);
+    assert!(matches!(result, Ok(SVal::Atom(ref s)) if s == "consonant"));
+}
+
+#[test]
+fn test_case_arrow_clause() {
+    let mut env = Environment::new();
+    let result = eval_in("(case 4 ((4) => (lambda (x) (+ x 1))) (else 0))", &mut env);
+    assert!(matches!(result, Ok(SVal::Integer(5))));
+}
+
+#[test]
+fn test_when_and_unless() {
+    let mut env = Environment::new();
+    let result = eval_in("(when (< 1 2) 1 2 3)", &mut env);
+    assert!(matches!(result, Ok(SVal::Integer(3))));
+
+    let result = eval_in("(when (> 1 2) 1)", &mut env);
+    assert!(matches!(result, Ok(SVal::Nil)));
+
+    let result = eval_in("(unless (> 1 2) 'ran)", &mut env);
+    assert!(matches!(result, Ok(SVal::Atom(ref s)) if s == "ran"));
+
+    let result = eval_in("(unless (< 1 2) 'ran)", &mut env);
+    assert!(matches!(result, Ok(SVal::Nil)));
+}
+
+#[test]
+fn test_and_returns_last_or_first_false() {
+    let mut env = Environment::new();
+    assert!(matches!(eval_in("(and)", &mut env), Ok(SVal::Bool(true))));
+    assert!(matches!(
+        eval_in("(and 1 2 3)", &mut env),
+        Ok(SVal::Integer(3))
+    ));
+    assert!(matches!(
+        eval_in("(and 1 #f 3)", &mut env),
+        Ok(SVal::Bool(false))
+    ));
+}
+
+#[test]
+fn test_or_returns_first_true_value() {
+    let mut env = Environment::new();
+    assert!(matches!(eval_in("(or)", &mut env), Ok(SVal::Bool(false))));
+    assert!(matches!(
+        eval_in("(or #f 2 3)", &mut env),
+        Ok(SVal::Integer(2))
+    ));
+    assert!(matches!(
+        eval_in("(or #f #f)", &mut env),
+        Ok(SVal::Bool(false))
+    ));
+}
+
+#[test]
+fn test_and_or_short_circuit() {
+    let mut env = Environment::new();
+    // The unbound variable would error if it were evaluated
+    assert!(matches!(
+        eval_in("(and #f undefined-var)", &mut env),
+        Ok(SVal::Bool(false))
+    ));
+    assert!(matches!(
+        eval_in("(or 1 undefined-var)", &mut env),
+        Ok(SVal::Integer(1))
+    ));
+}
+
+#[test]
+fn test_set_updates_nearest_binding() {
+    let mut env = Environment::new();
+    let result = eval_in(
+        "(define x 1) (define (shadow x) (set! x 10) x) (list (shadow 5) x)",
+        &mut env,
+    );
+    assert_eq!(result.map(|v| v.to_string()), Ok("(10 1)".to_string()));
+    assert!(matches!(
+        eval_in("(set! x 2) x", &mut env),
+        Ok(SVal::Integer(2))
+    ));
+}
+
+#[test]
+fn test_set_unbound_variable_errors() {
+    let mut env = Environment::new();
+    assert_eq!(
+        eval_in("(set! missing 1)", &mut env).map(|v| v.to_string()),
+        Err("Unbound variable: missing".to_string())
+    );
+}
+
+#[test]
+fn test_begin_returns_last_value() {
+    let mut env = Environment::new();
+    assert!(matches!(
+        eval_in("(define x 1) (begin (set! x (+ x 1)) (* x 10))", &mut env),
+        Ok(SVal::Integer(20))
+    ));
+    assert!(matches!(eval_in("(begin)", &mut env), Ok(SVal::Nil)));
+}
+
+#[test]
+fn test_closures_share_captured_bindings() {
+    let mut env = Environment::new();
+    let result = eval_in(
+        "(define (make-counter)
+           (define n 0)
+           (lambda () (set! n (+ n 1)) n))
+         (define c (make-counter))
+         (define d (make-counter))
+         (c) (c) (d)
+         (list (c) (d))",
+        &mut env,
+    );
+    assert_eq!(result.map(|v| v.to_string()), Ok("(3 2)".to_string()));
+}
+
+#[test]
+fn test_closures_observe_later_mutations() {
+    let mut env = Environment::new();
+    let result = eval_in(
+        "(define x 1) (define (get-x) x) (set! x 5) (get-x)",
+        &mut env,
+    );
+    assert!(matches!(result, Ok(SVal::Integer(5))));
+}
+
+#[test]
+fn test_dotted_formals_collect_remaining_arguments() {
+    let mut env = Environment::new();
+    let result = eval_in(
+        "(define (f a b . rest) (list a b rest))
+         (f 1 2 3 4)",
+        &mut env,
+    );
+    assert_eq!(result.unwrap().to_string(), "(1 2 (3 4))");
+    assert!(matches!(
+        eval_in("(null? (car (cdr (cdr (f 1 2)))))", &mut env),
+        Ok(SVal::Bool(true))
+    ));
+    assert!(eval_in("(f 1)", &mut env).is_err());
+
+    let result = eval_in("(define (sum . xs) (apply + xs)) (sum 1 2 3)", &mut env);
+    assert!(matches!(result, Ok(SVal::Integer(6))));
+    assert!(matches!(eval_in("(sum)", &mut env), Ok(SVal::Integer(0))));
+}
+
+#[test]
+fn test_variadic_lambdas() {
+    let mut env = Environment::new();
+    let result = eval_in("((lambda args args) 1 2 3)", &mut env);
+    assert_eq!(result.unwrap().to_string(), "(1 2 3)");
+    let result = eval_in("((lambda (x . more) (length more)) 1 2 3)", &mut env);
+    assert!(matches!(result, Ok(SVal::Integer(2))));
+    let result = eval_in("(apply (lambda (a . r) (cons a r)) 1 (list 2 3))", &mut env);
+    assert_eq!(result.unwrap().to_string(), "(1 2 3)");
+    assert!(eval_in("(lambda (a . a) a)", &mut env).is_err());
+    assert!(eval_in("(lambda (a . (b)) a)", &mut env).is_ok());
+}
@@ -0,0 +1,549 @@
+use muscm::executor::{ControlFlow, Executor};
+use muscm::lua_interpreter::LuaInterpreter;
+use muscm::lua_parser::{parse as parse_lua, tokenize, TokenSlice};
+use muscm::lua_value::LuaValue;
+
+fn run(code: &str) -> Result<Vec<LuaValue>, String> {
+    let tokens = tokenize(code).expect("Failed to tokenize");
+    let token_slice = TokenSlice::from(tokens.as_slice());
+    let (_, block) = parse_lua(token_slice).expect("Failed to parse");
+
+    let mut interp = LuaInterpreter::new();
+    match Executor::new().execute_block(&block, &mut interp) {
+        Ok(ControlFlow::Return(values)) => Ok(values),
+        Ok(other) => panic!("Expected return, got {:?}", other),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn numbers(values: &[f64]) -> Vec<LuaValue> {
+    values.iter().map(|n| LuaValue::Number(*n)).collect()
+}
+
+#[test]
+fn test_multiple_returns_expand_in_last_position() {
+    let result = run(r#"
+        local function three() return 1, 2, 3 end
+        local a, b, c = three()
+        local t = {three()}
+        local u = {three(), 10}
+        return a + b + c, #t, #u
+    "#);
+    assert_eq!(result.unwrap(), numbers(&[6.0, 3.0, 2.0]));
+}
+
+#[test]
+fn test_varargs_and_select() {
+    let result = run(r#"
+        local function count(...) return select('#', ...) end
+        local function last(...) return select(-1, ...) end
+        local function from_second(...) return select(2, ...) end
+        return count(1, nil, 3), last(4, 5, 6), from_second(7, 8, 9)
+    "#);
+    assert_eq!(result.unwrap(), numbers(&[3.0, 6.0, 8.0, 9.0]));
+}
+
+#[test]
+fn test_select_out_of_range() {
+    let err = run("return select(0, 1, 2)").unwrap_err();
+    assert!(err.contains("index out of range"), "{}", err);
+}
+
+#[test]
+fn test_assert_returns_its_arguments() {
+    let result = run(r#"
+        local v, msg = assert(42, "unused")
+        return v, msg
+    "#);
+    assert_eq!(
+        result.unwrap(),
+        vec![LuaValue::Number(42.0), LuaValue::String("unused".into())]
+    );
+}
+
+#[test]
+fn test_assert_failure_messages() {
+    let err = run("return assert(false)").unwrap_err();
+    assert!(err.contains("assertion failed!"), "{}", err);
+
+    let err = run(r#"return assert(nil, "custom failure")"#).unwrap_err();
+    assert!(err.contains("custom failure"), "{}", err);
+}
+
+#[test]
+fn test_unpack_and_table_unpack() {
+    let result = run(r#"
+        local t = {10, 20, 30, 40}
+        local a, b = unpack(t)
+        local c, d = table.unpack(t, 3)
+        local e = table.unpack(t, 2, 2)
+        return a, b, c, d, e, select('#', unpack(t, 1, 6))
+    "#);
+    assert_eq!(
+        result.unwrap(),
+        numbers(&[10.0, 20.0, 30.0, 40.0, 20.0, 6.0])
+    );
+}
+
+#[test]
+fn test_table_pack_keeps_nils() {
+    let result = run(r#"
+        local p = table.pack(1, nil, 3, nil)
+        local empty = table.pack()
+        return p.n, p[1], p[3], #empty, empty.n
+    "#);
+    assert_eq!(result.unwrap(), numbers(&[4.0, 1.0, 3.0, 0.0, 0.0]));
+}
+
+#[test]
+fn test_table_move_handles_overlap() {
+    let result = run(r#"
+        local up = {1, 2, 3, 4, 5}
+        table.move(up, 1, 3, 3)
+        local down = {1, 2, 3, 4, 5}
+        table.move(down, 3, 5, 1)
+        local other = table.move({7, 8}, 1, 2, 2, {0})
+        local same = table.move({1}, 1, 0, 5)
+        return up[1], up[2], up[3], up[4], up[5],
+               down[1], down[2], down[3], down[4], down[5],
+               other[1], other[2], other[3], #same
+    "#);
+    assert_eq!(
+        result.unwrap(),
+        numbers(&[1.0, 2.0, 1.0, 2.0, 3.0, 3.0, 4.0, 5.0, 4.0, 5.0, 0.0, 7.0, 8.0, 1.0])
+    );
+}
+
+#[test]
+fn test_table_deepcopy_keeps_shared_and_cyclic_structure() {
+    let result = run(r#"
+        local shared = {n = 1}
+        local t = {a = shared, b = shared, list = {1, 2}}
+        t.self = t
+        setmetatable(t, {__index = {other = 'inherited'}})
+        local copy = table.deepcopy(t)
+        copy.a.n = 2
+        copy.list[1] = 10
+        return shared.n, t.list[1], copy.b.n, copy.self == copy, copy.a ~= shared,
+               copy.other, table.isfrozen(table.deepcopy(table.freeze({})))
+    "#);
+    assert_eq!(
+        result.unwrap(),
+        vec![
+            LuaValue::Number(1.0),
+            LuaValue::Number(1.0),
+            LuaValue::Number(2.0),
+            LuaValue::Boolean(true),
+            LuaValue::Boolean(true),
+            LuaValue::String("inherited".into()),
+            LuaValue::Boolean(false),
+        ]
+    );
+}
+
+#[test]
+fn test_table_deepcopy_keeps_the_metatable() {
+    let result = run(r#"
+        local mt = {__tostring = function() return 'point' end, [1] = 'kept'}
+        local t = setmetatable({}, mt)
+        local copy = table.deepcopy(t)
+        return getmetatable(t) == mt, getmetatable(copy) == mt, tostring(copy),
+               getmetatable(copy)[1]
+    "#);
+    assert_eq!(
+        result.unwrap(),
+        vec![
+            LuaValue::Boolean(true),
+            LuaValue::Boolean(true),
+            LuaValue::String("point".into()),
+            LuaValue::String("kept".into()),
+        ]
+    );
+}
+
+#[test]
+fn test_frozen_tables_reject_every_change() {
+    for change in [
+        "config.width = 100",
+        "config.height = 24",
+        "rawset(config, 'width', 1)",
+        "table.insert(config, 1)",
+        "table.remove(config)",
+        "table.move({1}, 1, 1, 1, config)",
+        "setmetatable(config, {})",
+        "function config.f() end",
+    ] {
+        let code = format!(
+            "local config = table.freeze({{width = 80}}) {} return 1",
+            change
+        );
+        let err = run(&code).unwrap_err();
+        assert!(err.contains("frozen table"), "{}: {}", change, err);
+    }
+
+    // Freezing is shallow, and reading is unaffected
+    let result = run(r#"
+        local config = table.freeze({width = 80, inner = {}})
+        config.inner.x = 1
+        return config.width, config.inner.x, table.isfrozen(config), table.isfrozen(config.inner)
+    "#);
+    assert_eq!(
+        result.unwrap(),
+        vec![
+            LuaValue::Number(80.0),
+            LuaValue::Number(1.0),
+            LuaValue::Boolean(true),
+            LuaValue::Boolean(false),
+        ]
+    );
+}
+
+#[test]
+fn test_frozen_globals_table() {
+    for change in ["existing = 2", "fresh = 3", "_G.fresh = 3"] {
+        let code = format!("existing = 1 table.freeze(_G) {} return 1", change);
+        let err = run(&code).unwrap_err();
+        assert!(err.contains("frozen table"), "{}: {}", change, err);
+    }
+}
+
+#[test]
+fn test_length_agrees_across_table_functions() {
+    let result = run(r#"
+        local t = {1, 2, 3}
+        t[3] = nil
+        local after_nil = #t
+        table.insert(t, 1, 0)
+        table.insert(t, 9)
+        local removed = table.remove(t, 1)
+        local last = table.remove(t)
+        local sparse = {1, 2, nil, 4}
+        return after_nil, rawlen(t), #t, select('#', table.unpack(t)), removed, last,
+               t[1], t[2], #{n = 1}, rawlen(sparse) == #sparse
+    "#);
+    assert_eq!(
+        result.unwrap(),
+        vec![
+            LuaValue::Number(2.0),
+            LuaValue::Number(2.0),
+            LuaValue::Number(2.0),
+            LuaValue::Number(2.0),
+            LuaValue::Number(0.0),
+            LuaValue::Number(9.0),
+            LuaValue::Number(1.0),
+            LuaValue::Number(2.0),
+            LuaValue::Number(0.0),
+            LuaValue::Boolean(true),
+        ]
+    );
+    let err = run("table.insert({}, 5, 1)").unwrap_err();
+    assert!(err.contains("position out of bounds"), "{}", err);
+}
+
+#[test]
+fn test_raw_access_bypasses_metatable() {
+    let result = run(r#"
+        local defaults = {color = "red"}
+        local t = setmetatable({}, {__index = defaults})
+        local through = t.color
+        local raw = rawget(t, "color")
+        rawset(t, "size", 3)
+        return through, raw, t.size, rawequal(t, t), rawequal(t, {}), rawlen({1, 2}), rawlen("abc")
+    "#);
+    assert_eq!(
+        result.unwrap(),
+        vec![
+            LuaValue::String("red".into()),
+            LuaValue::Nil,
+            LuaValue::Number(3.0),
+            LuaValue::Boolean(true),
+            LuaValue::Boolean(false),
+            LuaValue::Number(2.0),
+            LuaValue::Number(3.0),
+        ]
+    );
+}
+
+#[test]
+fn test_pairs_and_next_follow_insertion_order() {
+    let result = run(r#"
+        local t = {}
+        for _, key in ipairs({"zeta", "alpha", "mid", "beta", "omega"}) do
+            t[key] = true
+        end
+        t[3] = true
+        t.alpha = false
+
+        local seen = ""
+        for k in pairs(t) do seen = seen .. k .. " " end
+
+        local walked = ""
+        local k = next(t)
+        while k ~= nil do
+            walked = walked .. k .. " "
+            k = next(t, k)
+        end
+        return seen, walked
+    "#);
+    let order = LuaValue::String("zeta alpha mid beta omega 3 ".into());
+    assert_eq!(result.unwrap(), vec![order.clone(), order]);
+}
+
+#[test]
+fn test_assigning_nil_removes_the_key() {
+    let result = run(r#"
+        local t = {a = 1, b = 2, c = 3}
+        t.a = nil
+        local seen = ""
+        for k, v in pairs(t) do seen = seen .. k .. "=" .. v .. " " end
+        local k, v = next(t)
+        return seen, k, v
+    "#);
+    assert_eq!(
+        result.unwrap(),
+        vec![
+            LuaValue::String("b=2 c=3 ".into()),
+            LuaValue::String("b".into()),
+            LuaValue::Number(2.0),
+        ]
+    );
+}
+
+#[test]
+fn test_next_continues_after_clearing_the_current_key() {
+    let result = run(r#"
+        local t = {a = 1, b = 2, c = 3}
+        local sum = 0
+        local k, v = next(t)
+        while k ~= nil do
+            sum = sum + v
+            t[k] = nil
+            k, v = next(t, k)
+        end
+        return sum, next(t)
+    "#);
+    assert_eq!(result.unwrap(), vec![LuaValue::Number(6.0), LuaValue::Nil]);
+}
+
+#[test]
+fn test_collectgarbage_frees_table_cycles() {
+    let result = run(r#"
+        local keep = {}
+        keep.self = keep
+        for i = 1, 10 do
+            local a, b = {}, {}
+            a.other, b.other = b, a
+        end
+        local before = collectgarbage("count")
+        local freed = collectgarbage()
+        return freed, keep.self == keep, collectgarbage("count") < before
+    "#);
+    assert_eq!(
+        result.unwrap(),
+        vec![
+            LuaValue::Number(20.0),
+            LuaValue::Boolean(true),
+            LuaValue::Boolean(true)
+        ]
+    );
+}
+
+#[test]
+fn test_collectgarbage_options() {
+    let result = run(r#"
+        collectgarbage("stop")
+        local stopped = collectgarbage("isrunning")
+        collectgarbage("restart")
+        return stopped, collectgarbage("isrunning"), collectgarbage("step")
+    "#);
+    assert_eq!(
+        result.unwrap(),
+        vec![
+            LuaValue::Boolean(false),
+            LuaValue::Boolean(true),
+            LuaValue::Boolean(true)
+        ]
+    );
+
+    let err = run(r#"return collectgarbage("bogus")"#).unwrap_err();
+    assert!(err.contains("invalid option 'bogus'"), "{}", err);
+}
+
+#[test]
+fn test_weak_tables_forget_collected_entries() {
+    let result = run(r#"
+        local cache = setmetatable({}, {__mode = "k"})
+        local names = setmetatable({}, {__mode = "v"})
+        local live = {}
+        cache[live] = "live"
+        cache[{}] = "garbage"
+        names.live, names.garbage, names.text = live, {}, "kept"
+        collectgarbage()
+        local keys = 0
+        for _ in pairs(cache) do keys = keys + 1 end
+        return keys, cache[live], names.live == live, names.garbage, names.text
+    "#);
+    assert_eq!(
+        result.unwrap(),
+        vec![
+            LuaValue::Number(1.0),
+            LuaValue::String("live".into()),
+            LuaValue::Boolean(true),
+            LuaValue::Nil,
+            LuaValue::String("kept".into())
+        ]
+    );
+}
+
+#[test]
+fn test_globals_table_and_its_metatable() {
+    let result = run(r#"
+        x = 1
+        _G.y = 2
+        rawset(_G, "z", 3)
+        local declared = {}
+        setmetatable(_G, {
+            __index = function(t, name) return "default " .. name end,
+            __newindex = function(t, name, value)
+                declared[#declared + 1] = name
+                rawset(t, name, value)
+            end,
+        })
+        w = 4
+        w = 5
+        return _G.x, y, rawget(_G, "z"), w, #declared, missing, rawget(_G, "missing")
+    "#);
+    assert_eq!(
+        result.unwrap(),
+        vec![
+            LuaValue::Number(1.0),
+            LuaValue::Number(2.0),
+            LuaValue::Number(3.0),
+            LuaValue::Number(5.0),
+            LuaValue::Number(1.0),
+            LuaValue::String("default missing".into()),
+            LuaValue::Nil
+        ]
+    );
+}
+
+#[test]
+fn test_index_and_newindex_functions_on_any_table() {
+    let result = run(r#"
+        setmetatable(_G, {__index = function(t, name) return "hook " .. name end})
+        local log = {}
+        local proxy = setmetatable({}, {
+            __index = function(t, key) return key * 2 end,
+            __newindex = function(t, key, value) log[#log + 1] = key .. "=" .. value end,
+        })
+        proxy.a = 1
+        local store = {}
+        local redirect = setmetatable({}, {__newindex = store})
+        redirect.b = 2
+        getmetatable("").__index = function(s, key) return key end
+        return _G.qqq, proxy[21], log[1], rawget(proxy, "a"), store.b, rawget(redirect, "b"), ("s").anything
+    "#);
+    assert_eq!(
+        result.unwrap(),
+        vec![
+            LuaValue::String("hook qqq".into()),
+            LuaValue::Number(42.0),
+            LuaValue::String("a=1".into()),
+            LuaValue::Nil,
+            LuaValue::Number(2.0),
+            LuaValue::Nil,
+            LuaValue::String("anything".into()),
+        ]
+    );
+}
+
+#[test]
+fn test_globals_are_the_entries_of_g() {
+    let result = run(r#"
+        for i = 1, 3 do _G["dynamic" .. i] = i * 10 end
+        local sum, names = 0, 0
+        for name, value in pairs(_G) do
+            if type(name) == "string" and name:sub(1, 7) == "dynamic" then
+                sum = sum + value
+                names = names + 1
+            end
+        end
+        dynamic2 = nil
+        return sum, names, dynamic3, _G._G == _G, rawget(_G, "dynamic2")
+    "#);
+    assert_eq!(
+        result.unwrap(),
+        vec![
+            LuaValue::Number(60.0),
+            LuaValue::Number(3.0),
+            LuaValue::Number(30.0),
+            LuaValue::Boolean(true),
+            LuaValue::Nil
+        ]
+    );
+}
+
+#[test]
+fn test_chunk_locals_stay_out_of_g() {
+    let result = run(r#"
+        local function read_g() return g end
+        local function kind(v) return type(v) end
+        local g = 5
+        local type = 5
+        local function define() function made_global() return g end end
+        define()
+        return rawget(_G, "g"), read_g(), rawget(_G, "type") ~= 5, kind(1),
+            made_global(), rawget(_G, "read_g")
+    "#);
+    assert_eq!(
+        result.unwrap(),
+        vec![
+            LuaValue::Nil,
+            LuaValue::Nil,
+            LuaValue::Boolean(true),
+            LuaValue::String("number".into()),
+            LuaValue::Number(5.0),
+            LuaValue::Nil
+        ]
+    );
+}
+
+#[test]
+fn test_pcall_and_xpcall_catch_errors() {
+    let result = run(r#"
+        local ok, sum, extra = pcall(function(a, b) return a + b, "more" end, 1, 2)
+        local failed, message = pcall(error, "boom")
+        local caught = 0
+        for i = 1, 3 do
+            if not pcall(function() error("again " .. i) end) then caught = caught + 1 end
+        end
+        local not_function, call_message = pcall(42)
+        local xok, handled = xpcall(function() error("deep") end,
+            function(m) return "handled: " .. m end)
+        local yok, y = xpcall(function(x) return x * 2 end, print, 21)
+        local zok, z = xpcall(error, function() error("in handler") end, "first")
+        return ok, sum, extra, failed, message, caught, not_function,
+            type(call_message), xok, handled, yok, y, zok, z
+    "#);
+    assert_eq!(
+        result.unwrap(),
+        vec![
+            LuaValue::Boolean(true),
+            LuaValue::Number(3.0),
+            LuaValue::String("more".into()),
+            LuaValue::Boolean(false),
+            LuaValue::String("boom".into()),
+            LuaValue::Number(3.0),
+            LuaValue::Boolean(false),
+            LuaValue::String("string".into()),
+            LuaValue::Boolean(false),
+            LuaValue::String("handled: deep".into()),
+            LuaValue::Boolean(true),
+            LuaValue::Number(42.0),
+            LuaValue::Boolean(false),
+            LuaValue::String("in handler".into()),
+        ]
+    );
+    let err = run("return xpcall(print, 'not a handler')").unwrap_err();
+    assert!(err.contains("function"), "{}", err);
+}
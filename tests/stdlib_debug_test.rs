@@ -0,0 +1,156 @@
+use muscm::executor::ControlFlow;
+use muscm::lua_interpreter::LuaInterpreter;
+use muscm::lua_parser::parse_source;
+use muscm::lua_value::LuaValue;
+use muscm::vm::execute_chunk;
+
+fn run(code: &str) -> Vec<LuaValue> {
+    let block = parse_source(code).expect("Failed to parse");
+    let mut interp = LuaInterpreter::new();
+    match execute_chunk(&block, &mut interp) {
+        Ok(ControlFlow::Return(values)) => values,
+        other => panic!("Expected return, got {:?}", other),
+    }
+}
+
+fn string(s: &str) -> LuaValue {
+    LuaValue::String(s.into())
+}
+
+#[test]
+fn test_getinfo_describes_functions() {
+    let result = run("local function add(a, b, ...)\n  return a + b\nend\n\
+         local info, c = debug.getinfo(add), debug.getinfo(print)\n\
+         return info.what, info.linedefined, info.lastlinedefined, info.nparams,\n\
+         info.isvararg, info.source, c.what, c.short_src, c.linedefined");
+    assert_eq!(
+        result,
+        vec![
+            string("Lua"),
+            LuaValue::Number(1.0),
+            LuaValue::Number(3.0),
+            LuaValue::Number(2.0),
+            LuaValue::Boolean(true),
+            string("=?"),
+            string("C"),
+            string("[C]"),
+            LuaValue::Number(-1.0),
+        ]
+    );
+}
+
+#[test]
+fn test_getinfo_describes_stack_levels() {
+    let result = run("local function whoami()\n  local me = debug.getinfo(1)\n  \
+         local caller = debug.getinfo(2)\n  \
+         return me.name, me.namewhat, me.currentline, caller.what, caller.currentline\n\
+         end\n\
+         local a, b, c, d, e = whoami()\n\
+         return a, b, c, d, e, debug.getinfo(0).name, debug.getinfo(50)");
+    assert_eq!(
+        result,
+        vec![
+            string("whoami"),
+            string("global"),
+            LuaValue::Number(2.0),
+            string("main"),
+            LuaValue::Number(6.0),
+            string("getinfo"),
+            LuaValue::Nil,
+        ]
+    );
+}
+
+#[test]
+fn test_getlocal_reads_parameters_and_locals() {
+    let result = run(r#"
+        local function outer(x)
+            local y = x * 2
+            local function inner(z)
+                local name, value = debug.getlocal(2, 2)
+                return z, name, value, debug.getlocal(1, 1)
+            end
+            return inner(y + 1)
+        end
+        local z, name, value, pname, pvalue = outer(5)
+        return z, name, value, pname, pvalue, debug.getlocal(outer, 1), debug.getlocal(1, 9)
+        "#);
+    assert_eq!(
+        result,
+        vec![
+            LuaValue::Number(11.0),
+            string("y"),
+            LuaValue::Number(10.0),
+            string("z"),
+            LuaValue::Number(11.0),
+            string("x"),
+            LuaValue::Nil,
+        ]
+    );
+}
+
+#[test]
+fn test_getlocal_out_of_range_is_nil() {
+    let result = run(
+        "local function f(a) return select('#', debug.getlocal(1, 99)) end\n\
+         return f(1), select('#', debug.getlocal(f, 99)), debug.getlocal(f, 99)",
+    );
+    assert_eq!(
+        result,
+        vec![LuaValue::Number(1.0), LuaValue::Number(1.0), LuaValue::Nil]
+    );
+}
+
+#[test]
+fn test_getinfo_reports_the_chunk_name() {
+    let block = parse_source(
+        "local function f() end\n\
+         local main = debug.getinfo(1)\n\
+         return debug.getinfo(f).source, debug.getinfo(f).short_src, main.source, main.short_src",
+    )
+    .expect("Failed to parse")
+    .with_source("@scripts/demo.lua");
+    let mut interp = LuaInterpreter::new();
+    let result = match execute_chunk(&block, &mut interp) {
+        Ok(ControlFlow::Return(values)) => values,
+        other => panic!("Expected return, got {:?}", other),
+    };
+    assert_eq!(
+        result,
+        vec![
+            string("@scripts/demo.lua"),
+            string("scripts/demo.lua"),
+            string("@scripts/demo.lua"),
+            string("scripts/demo.lua"),
+        ]
+    );
+}
+
+#[test]
+fn test_getupvalue_and_setupvalue() {
+    let result = run(r#"
+        local function counter()
+            local count = 0
+            return function()
+                count = count + 1
+                return count
+            end
+        end
+        local tick = counter()
+        tick()
+        local name, value = debug.getupvalue(tick, 1)
+        local set = debug.setupvalue(tick, 1, 41)
+        return name, value, set, tick(), debug.getupvalue(tick, 2), debug.getinfo(tick).nups
+        "#);
+    assert_eq!(
+        result,
+        vec![
+            string("count"),
+            LuaValue::Number(1.0),
+            string("count"),
+            LuaValue::Number(42.0),
+            LuaValue::Nil,
+            LuaValue::Number(1.0),
+        ]
+    );
+}
@@ -0,0 +1,485 @@
+use muscm::executor::{ControlFlow, Executor};
+use muscm::lua_interpreter::LuaInterpreter;
+use muscm::lua_parser::{parse as parse_lua, tokenize, TokenSlice};
+use muscm::lua_value::LuaValue;
+use std::path::PathBuf;
+
+fn run(code: &str) -> Result<Vec<LuaValue>, String> {
+    let tokens = tokenize(code).expect("Failed to tokenize");
+    let token_slice = TokenSlice::from(tokens.as_slice());
+    let (_, block) = parse_lua(token_slice).expect("Failed to parse");
+
+    let mut interp = LuaInterpreter::new();
+    match Executor::new().execute_block(&block, &mut interp) {
+        Ok(ControlFlow::Return(values)) => Ok(values),
+        Ok(other) => panic!("Expected return, got {:?}", other),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// A fresh path in the temp directory, removed when dropped
+struct TempPath(PathBuf);
+
+impl TempPath {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("muscm_io_{}_{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        TempPath(path)
+    }
+
+    fn lua(&self) -> String {
+        self.0.display().to_string()
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn string(s: &str) -> LuaValue {
+    LuaValue::String(s.into())
+}
+
+#[test]
+fn test_write_then_read_modes() {
+    let path = TempPath::new("modes");
+    let values = run(&format!(
+        r#"
+        local p = "{}"
+        local f = io.open(p, "w")
+        f:write("one\n", 2, "\n"):write("three")
+        f:close()
+        f = io.open(p, "a")
+        f:write("\nfour")
+        f:close()
+        f = io.open(p, "rb")
+        local first, second = f:read("l"), f:read("n")
+        local rest = f:read("a")
+        f:close()
+        return first, second, rest
+    "#,
+        path.lua()
+    ))
+    .unwrap();
+    assert_eq!(
+        values,
+        vec![
+            string("one"),
+            LuaValue::Number(2.0),
+            string("\nthree\nfour")
+        ]
+    );
+}
+
+#[test]
+fn test_read_numbers_stop_at_the_numeral() {
+    let path = TempPath::new("numerals");
+    let values = run(&format!(
+        r#"
+        local p = "{}"
+        local f = io.open(p, "w")
+        f:write("12abc -0x10,3.5e2 .5 1e")
+        f:close()
+        f = io.open(p, "r")
+        local a, rest = f:read("n", 3)
+        local b = f:read("n")
+        local comma = f:read(1)
+        local c, d = f:read("n", "n")
+        local bad, after = f:read("n"), f:read("a")
+        f:close()
+        return a, rest, b, comma, c, d, bad, after
+    "#,
+        path.lua()
+    ))
+    .unwrap();
+    assert_eq!(
+        values,
+        vec![
+            LuaValue::Number(12.0),
+            string("abc"),
+            LuaValue::Number(-16.0),
+            string(","),
+            LuaValue::Number(350.0),
+            LuaValue::Number(0.5),
+            LuaValue::Nil,
+            string(""),
+        ]
+    );
+}
+
+#[test]
+fn test_read_returns_a_value_per_format() {
+    let path = TempPath::new("formats");
+    let values = run(&format!(
+        r##"
+        local p = "{}"
+        local f = io.open(p, "w")
+        f:write("first\nsecond\n")
+        f:close()
+        f = io.open(p, "r")
+        local counts = {{select("#", f:read("l", "L")), select("#", f:read("l", "l", "l"))}}
+        f:close()
+        f = io.open(p, "r")
+        local a, b = f:read("l", "L")
+        f:close()
+        return counts[1], counts[2], a, b
+    "##,
+        path.lua()
+    ))
+    .unwrap();
+    assert_eq!(
+        values,
+        vec![
+            LuaValue::Number(2.0),
+            LuaValue::Number(1.0),
+            string("first"),
+            string("second\n"),
+        ]
+    );
+}
+
+#[test]
+fn test_write_formats_numbers_like_tostring() {
+    let path = TempPath::new("numbers");
+    let values = run(&format!(
+        r#"
+        local p = "{}"
+        local f = io.open(p, "w")
+        f:write(0.1 + 0.2, " ", 2^63, " ", 2^100, " ", 1/0, " ", 7)
+        f:close()
+        f = io.open(p, "r")
+        local text = f:read("a")
+        f:close()
+        return text, tostring(0.1 + 0.2) .. " " .. 2^63 .. " " .. 2^100
+    "#,
+        path.lua()
+    ))
+    .unwrap();
+    assert_eq!(
+        values,
+        vec![
+            string("0.3 9.2233720368548e+18 1.2676506002282e+30 inf 7"),
+            string("0.3 9.2233720368548e+18 1.2676506002282e+30")
+        ]
+    );
+}
+
+#[test]
+fn test_update_modes_and_seek() {
+    let path = TempPath::new("seek");
+    let values = run(&format!(
+        r#"
+        local f = io.open("{}", "w+")
+        f:write("hello world")
+        local size = f:seek("end")
+        f:seek("set", 6)
+        local word = f:read(5)
+        f:seek("set")
+        f:write("J")
+        f:seek("cur", -1)
+        local first = f:read(5)
+        f:seek("end")
+        local at_end = f:read(1)
+        f:close()
+        return size, word, first, at_end
+    "#,
+        path.lua()
+    ))
+    .unwrap();
+    assert_eq!(
+        values,
+        vec![
+            LuaValue::Number(11.0),
+            string("world"),
+            string("Jello"),
+            LuaValue::Nil
+        ]
+    );
+}
+
+#[test]
+fn test_buffering_and_flush() {
+    let path = TempPath::new("buffering");
+    let values = run(&format!(
+        r#"
+        local p = "{0}"
+        local w = io.open(p, "w")
+        w:write("buffered")
+        local before = io.open(p, "r"):read("a")
+        w:flush()
+        local after = io.open(p, "r"):read("a")
+        w:setvbuf("no")
+        w:write("!")
+        local unbuffered = io.open(p, "r"):read("a")
+        w:close()
+        return before, after, unbuffered
+    "#,
+        path.lua()
+    ))
+    .unwrap();
+    assert_eq!(
+        values,
+        vec![string(""), string("buffered"), string("buffered!")]
+    );
+}
+
+#[test]
+fn test_lines_and_io_type() {
+    let path = TempPath::new("lines");
+    std::fs::write(&path.0, "a\nb\nc\n").unwrap();
+    let values = run(&format!(
+        r#"
+        local f = io.open("{}")
+        local seen = ""
+        for line in f:lines() do
+            seen = seen .. line .. ";"
+        end
+        local open_type = io.type(f)
+        f:close()
+        return seen, open_type, io.type(f), io.type(42)
+    "#,
+        path.lua()
+    ))
+    .unwrap();
+    assert_eq!(
+        values,
+        vec![
+            string("a;b;c;"),
+            string("file"),
+            string("closed file"),
+            LuaValue::Nil
+        ]
+    );
+}
+
+#[test]
+fn test_errors() {
+    let path = TempPath::new("errors");
+    let p = path.lua();
+    // Misuse raises
+    assert!(run(&format!(r#"return io.open("{}", "rw")"#, p)).is_err());
+    assert!(run(&format!(
+        r#"local f = io.open("{}", "w") f:close() return f:read()"#,
+        p
+    ))
+    .is_err());
+    assert!(run(&format!(
+        r#"local f = io.open("{}") return f:seek("middle")"#,
+        p
+    ))
+    .is_err());
+    assert!(run("return io.open(42)").is_err());
+
+    // write takes only strings and numbers, and writes nothing otherwise
+    let err = run(r#"return io.write("a", {})"#).unwrap_err();
+    assert!(
+        err.contains("bad argument #2 to 'write' (string expected, got table)"),
+        "{}",
+        err
+    );
+    let err = run(&format!(
+        r#"local f = io.open("{}", "w") f:write(1, nil) f:close()"#,
+        p
+    ))
+    .unwrap_err();
+    assert!(
+        err.contains("bad argument #2 to 'write' (string expected, got nil)"),
+        "{}",
+        err
+    );
+    assert_eq!(std::fs::read_to_string(&path.0).unwrap(), "");
+}
+
+#[test]
+fn test_failures_return_nil_message_errno() {
+    let path = TempPath::new("failures");
+    let p = path.lua();
+    let values = run(&format!(r#"return io.open("{}", "r")"#, p)).unwrap();
+    assert_eq!(
+        values,
+        vec![
+            LuaValue::Nil,
+            string(&format!("{}: No such file or directory", p)),
+            LuaValue::Number(2.0)
+        ]
+    );
+    let values = run(&format!(
+        r#"
+        local f = io.open("{p}", "w")
+        local read = {{f:read()}}
+        f:close()
+        f = io.open("{p}", "r")
+        local written, message, code = f:write("x")
+        f:close()
+        local removed = os.remove("{p}")
+        local again, why = os.remove("{p}")
+        local moved, _, errno = os.rename("{p}", "{p}.new")
+        return read[1], type(read[2]), read[3], written, message, code,
+            removed, again, why, moved, errno
+    "#
+    ))
+    .unwrap();
+    assert_eq!(values[0], LuaValue::Nil);
+    assert_eq!(values[1], string("string"));
+    assert!(matches!(values[2], LuaValue::Number(_)));
+    assert_eq!(values[3], LuaValue::Nil);
+    assert!(
+        matches!(&values[4], LuaValue::String(s) if s.to_string().contains("not opened for writing"))
+    );
+    assert!(matches!(values[5], LuaValue::Number(_)));
+    assert_eq!(values[6], LuaValue::Boolean(true));
+    assert_eq!(values[7], LuaValue::Nil);
+    assert_eq!(
+        values[8],
+        string(&format!("{}: No such file or directory", p))
+    );
+    assert_eq!(values[9], LuaValue::Nil);
+    assert_eq!(values[10], LuaValue::Number(2.0));
+}
+
+#[test]
+fn test_default_streams_redirect() {
+    let input = TempPath::new("filter_in");
+    let output = TempPath::new("filter_out");
+    std::fs::write(&input.0, "alpha\nbeta\n").unwrap();
+    let values = run(&format!(
+        r#"
+        local was_stdout = io.output() == io.stdout
+        io.input("{}")
+        io.output("{}")
+        local first = io.read()
+        io.write("1:", first, "\n")
+        for line in io.lines() do
+            io.write("2:", line, "\n")
+        end
+        io.close()
+        io.output(io.stdout)
+        return was_stdout, io.output() == io.stdout, io.type(io.stderr)
+    "#,
+        input.lua(),
+        output.lua()
+    ))
+    .unwrap();
+    assert_eq!(
+        values,
+        vec![
+            LuaValue::Boolean(true),
+            LuaValue::Boolean(true),
+            string("file")
+        ]
+    );
+    assert_eq!(
+        std::fs::read_to_string(&output.0).unwrap(),
+        "1:alpha\n2:beta\n"
+    );
+}
+
+#[test]
+fn test_standard_stream_restrictions() {
+    assert!(run("return io.stdout:seek('set')").is_err());
+    assert!(run("return io.stdout:close()").is_err());
+    assert_eq!(run("return io.stdin:write('x')").unwrap()[0], LuaValue::Nil);
+    assert!(run("return io.input(42)").is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_popen_reads_command_output() {
+    let values = run(r#"
+        local pipe = io.popen("printf 'one\ntwo\n'")
+        local lines = {}
+        for line in pipe:lines() do lines[#lines + 1] = line end
+        local ok, kind, code = pipe:close()
+        local failed = io.popen("exit 3")
+        local fok, fkind, fcode = failed:close()
+        return lines[1], lines[2], ok, kind, code, fok, fkind, fcode
+    "#)
+    .unwrap();
+    assert_eq!(
+        values,
+        vec![
+            string("one"),
+            string("two"),
+            LuaValue::Boolean(true),
+            string("exit"),
+            LuaValue::Number(0.0),
+            LuaValue::Nil,
+            string("exit"),
+            LuaValue::Number(3.0),
+        ]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_popen_writes_command_input() {
+    let path = TempPath::new("popen_write");
+    let values = run(&format!(
+        r#"
+        local pipe = io.popen("cat > {}", "w")
+        pipe:write("piped", "\n")
+        return pipe:close(), io.type(pipe)
+    "#,
+        path.lua()
+    ))
+    .unwrap();
+    assert_eq!(values, vec![LuaValue::Boolean(true), string("closed file")]);
+    assert_eq!(std::fs::read_to_string(&path.0).unwrap(), "piped\n");
+    assert!(run("return io.popen('true', 'rw')").is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_os_execute_reports_status() {
+    let values = run(r#"
+        local ok, kind, code = os.execute("exit 0")
+        local bad, bad_kind, bad_code = os.execute("exit 7")
+        local sig, sig_kind, signal = os.execute("kill -9 $$")
+        return os.execute(), ok, kind, code, bad, bad_kind, bad_code, sig, sig_kind, signal
+    "#)
+    .unwrap();
+    assert_eq!(
+        values,
+        vec![
+            LuaValue::Boolean(true),
+            LuaValue::Boolean(true),
+            string("exit"),
+            LuaValue::Number(0.0),
+            LuaValue::Nil,
+            string("exit"),
+            LuaValue::Number(7.0),
+            LuaValue::Nil,
+            string("signal"),
+            LuaValue::Number(9.0),
+        ]
+    );
+}
+
+#[test]
+fn test_getenv_and_environ() {
+    let result = run(r#"
+        os.setenv("MUSCM_TEST_EMPTY", "")
+        os.setenv("MUSCM_TEST_SET", "yes")
+        local env = os.environ()
+        local sorted, previous = true, ""
+        for name in pairs(env) do
+            if name < previous then sorted = false end
+            previous = name
+        end
+        return os.getenv("MUSCM_TEST_UNSET"), os.getenv("MUSCM_TEST_EMPTY"),
+               os.getenv("MUSCM_TEST_SET"), env.MUSCM_TEST_SET, sorted
+    "#)
+    .unwrap();
+    assert_eq!(
+        result,
+        vec![
+            LuaValue::Nil,
+            string(""),
+            string("yes"),
+            string("yes"),
+            LuaValue::Boolean(true),
+        ]
+    );
+}
@@ -0,0 +1,93 @@
+use muscm::executor::{ControlFlow, Executor};
+use muscm::lua_interpreter::LuaInterpreter;
+use muscm::lua_parser::{parse as parse_lua, tokenize, TokenSlice};
+use muscm::lua_value::LuaValue;
+
+fn run(code: &str) -> Result<Vec<LuaValue>, String> {
+    let tokens = tokenize(code).expect("Failed to tokenize");
+    let token_slice = TokenSlice::from(tokens.as_slice());
+    let (_, block) = parse_lua(token_slice).expect("Failed to parse");
+
+    let mut interp = LuaInterpreter::new();
+    match Executor::new().execute_block(&block, &mut interp) {
+        Ok(ControlFlow::Return(values)) => Ok(values),
+        Ok(other) => panic!("Expected return, got {:?}", other),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn string(s: &str) -> LuaValue {
+    LuaValue::String(s.into())
+}
+
+#[test]
+fn test_encode_arrays_objects_and_scalars() {
+    let values = run(r#"
+        return json.encode({1, "two", true}),
+            json.encode({name = "x", list = {1.5, json.null}, empty = {}}),
+            json.encode(nil),
+            json.encode({}, {empty_as_array = true})
+    "#)
+    .unwrap();
+    assert_eq!(
+        values,
+        vec![
+            string(r#"[1,"two",true]"#),
+            string(r#"{"empty":{},"list":[1.5,null],"name":"x"}"#),
+            string("null"),
+            string("[]"),
+        ]
+    );
+}
+
+#[test]
+fn test_encode_sparse_tables_as_objects_and_pretty_prints() {
+    let values = run(r#"
+        return json.encode({[1] = "a", [3] = "c"}), json.encode({a = {1}}, {indent = 2})
+    "#)
+    .unwrap();
+    assert_eq!(
+        values,
+        vec![
+            string(r#"{"1":"a","3":"c"}"#),
+            string("{\n  \"a\": [\n    1\n  ]\n}"),
+        ]
+    );
+}
+
+#[test]
+fn test_decode_nested_values() {
+    let values = run(r#"
+        local v = json.decode('{"items": [10, null, {"ok": true}], "name": "n\\u00e9"}')
+        return #v.items, v.items[1], v.items[2] == json.null, v.items[3].ok, v.name
+    "#)
+    .unwrap();
+    assert_eq!(
+        values,
+        vec![
+            LuaValue::Number(3.0),
+            LuaValue::Number(10.0),
+            LuaValue::Boolean(true),
+            LuaValue::Boolean(true),
+            string("né"),
+        ]
+    );
+}
+
+#[test]
+fn test_round_trip() {
+    let values = run(r#"
+        local text = '{"a":[1,2,{"b":null}],"c":"d"}'
+        return json.encode(json.decode(text)) == text
+    "#)
+    .unwrap();
+    assert_eq!(values, vec![LuaValue::Boolean(true)]);
+}
+
+#[test]
+fn test_errors() {
+    assert!(run("return json.decode('{bad')").is_err());
+    assert!(run("return json.encode(print)").is_err());
+    assert!(run("local t = {} t.self = t return json.encode(t)").is_err());
+    assert!(run("return json.encode(0/0)").is_err());
+}
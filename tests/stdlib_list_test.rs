@@ -0,0 +1,199 @@
+use muscm::interpreter::{Environment, Interpreter, SVal};
+use muscm::parser::parse;
+
+// Evaluate every top-level form and render the last result with `write`
+fn eval_all(code: &str) -> Result<String, String> {
+    let mut env = Environment::new();
+    let (arena, nodes) = parse(code).map_err(|e| e.message)?;
+    let mut result = SVal::Nil;
+    for node in nodes {
+        result = Interpreter::eval(arena.get(node).unwrap(), &mut env, &arena)?;
+    }
+    Ok(result.to_string())
+}
+
+#[test]
+fn test_eq_compares_identity() {
+    assert_eq!(eval_all("(eq? 'a 'a)"), Ok("#t".to_string()));
+    assert_eq!(eval_all("(eq? '() '())"), Ok("#t".to_string()));
+    assert_eq!(eval_all("(eq? (list 1) (list 1))"), Ok("#f".to_string()));
+    assert_eq!(eval_all("(eq? car car)"), Ok("#t".to_string()));
+    assert_eq!(
+        eval_all("(define (f) 1) (define g f) (list (eq? f g) (eq? f (lambda () 1)))"),
+        Ok("(#t #f)".to_string())
+    );
+}
+
+#[test]
+fn test_eqv_compares_numbers_by_exactness_and_value() {
+    assert_eq!(eval_all("(eqv? 2 2)"), Ok("#t".to_string()));
+    assert_eq!(eval_all("(eqv? 2 2.0)"), Ok("#f".to_string()));
+    assert_eq!(eval_all("(eqv? 1/2 (/ 2 4))"), Ok("#t".to_string()));
+    assert_eq!(eval_all("(eqv? #\\a #\\a)"), Ok("#t".to_string()));
+    assert_eq!(eval_all("(eqv? \"\" \"\")"), Ok("#t".to_string()));
+    assert_eq!(eval_all("(eqv? \"ab\" \"ab\")"), Ok("#f".to_string()));
+}
+
+#[test]
+fn test_equal_compares_structure() {
+    assert_eq!(
+        eval_all("(equal? '(1 (2 \"x\") #(3)) (list 1 (list 2 \"x\") '#(3)))"),
+        Ok("#t".to_string())
+    );
+    assert_eq!(eval_all("(equal? '(1 2) '(1 2.0))"), Ok("#f".to_string()));
+    assert_eq!(eval_all("(equal? '(1 2) '(1 2 3))"), Ok("#f".to_string()));
+    assert_eq!(
+        eval_all("(equal? 1 2 3)"),
+        Err("equal? expects exactly 2 arguments".to_string())
+    );
+}
+
+#[test]
+fn test_member_returns_tail() {
+    assert_eq!(eval_all("(memq 'c '(a b c d))"), Ok("(c d)".to_string()));
+    assert_eq!(eval_all("(memq 'e '(a b c d))"), Ok("#f".to_string()));
+    assert_eq!(eval_all("(memv 2.0 '(1 2 3))"), Ok("#f".to_string()));
+    assert_eq!(
+        eval_all("(member '(1) '((0) (1) (2)))"),
+        Ok("((1) (2))".to_string())
+    );
+    assert_eq!(eval_all("(member 1 '())"), Ok("#f".to_string()));
+}
+
+#[test]
+fn test_assoc_finds_first_pair() {
+    assert_eq!(
+        eval_all("(assq 'b '((a 1) (b 2) (b 3)))"),
+        Ok("(b 2)".to_string())
+    );
+    assert_eq!(eval_all("(assv 5 '((2 3) (5 7)))"), Ok("(5 7)".to_string()));
+    assert_eq!(
+        eval_all("(assoc \"b\" '((\"a\" 1) (\"b\" 2)))"),
+        Ok("(\"b\" 2)".to_string())
+    );
+    assert_eq!(
+        eval_all("(assoc '(x) '(((x) found)))"),
+        Ok("((x) found)".to_string())
+    );
+    assert_eq!(eval_all("(assq 'z '((a 1)))"), Ok("#f".to_string()));
+    assert_eq!(
+        eval_all("(assq 'a '(1 2))"),
+        Err("assq expects a list of pairs, got 1".to_string())
+    );
+}
+
+#[test]
+fn test_map_over_several_lists() {
+    assert_eq!(
+        eval_all("(map (lambda (x) (* x x)) '(1 2 3))"),
+        Ok("(1 4 9)".to_string())
+    );
+    assert_eq!(
+        eval_all("(map + '(1 2 3) '(10 20))"),
+        Ok("(11 22)".to_string())
+    );
+    assert_eq!(eval_all("(map car '())"), Ok("()".to_string()));
+}
+
+#[test]
+fn test_for_each_runs_for_effect() {
+    assert_eq!(
+        eval_all(
+            "(define total 0)
+             (for-each (lambda (x y) (set! total (+ total (* x y)))) '(1 2) '(3 4))
+             total"
+        ),
+        Ok("11".to_string())
+    );
+}
+
+#[test]
+fn test_filter_keeps_matching_items() {
+    assert_eq!(
+        eval_all("(filter (lambda (x) (> x 1)) '(3 1 2 0))"),
+        Ok("(3 2)".to_string())
+    );
+    assert_eq!(eval_all("(filter symbol? '(1 2))"), Ok("()".to_string()));
+}
+
+#[test]
+fn test_folds_associate_in_opposite_directions() {
+    assert_eq!(
+        eval_all("(fold-left (lambda (acc x) (cons x acc)) '() '(1 2 3))"),
+        Ok("(3 2 1)".to_string())
+    );
+    assert_eq!(
+        eval_all("(fold-right cons '() '(1 2 3))"),
+        Ok("(1 2 3)".to_string())
+    );
+    assert_eq!(eval_all("(fold-left - 0 '(1 2 3))"), Ok("-6".to_string()));
+    assert_eq!(eval_all("(fold-right - 0 '(1 2 3))"), Ok("2".to_string()));
+    assert_eq!(
+        eval_all("(fold-left (lambda (acc x y) (+ acc (* x y))) 0 '(1 2) '(3 4))"),
+        Ok("11".to_string())
+    );
+}
+
+#[test]
+fn test_apply_spreads_last_argument() {
+    assert_eq!(eval_all("(apply + '(1 2 3))"), Ok("6".to_string()));
+    assert_eq!(eval_all("(apply + 1 2 '(3 4))"), Ok("10".to_string()));
+    assert_eq!(
+        eval_all("(apply (lambda (a b) (list b a)) '(1 2))"),
+        Ok("(2 1)".to_string())
+    );
+    assert_eq!(
+        eval_all("(apply + 1)"),
+        Err("apply expects a list as last argument, got 1".to_string())
+    );
+}
+
+#[test]
+fn test_dotted_pairs_read_and_print() {
+    assert_eq!(eval_all("(cons 1 2)"), Ok("(1 . 2)".to_string()));
+    assert_eq!(eval_all("'(1 . 2)"), Ok("(1 . 2)".to_string()));
+    assert_eq!(eval_all("'(1 2 . 3)"), Ok("(1 2 . 3)".to_string()));
+    assert_eq!(eval_all("(cons 1 (cons 2 3))"), Ok("(1 2 . 3)".to_string()));
+    assert_eq!(eval_all("'(1 . (2 3))"), Ok("(1 2 3)".to_string()));
+    assert_eq!(eval_all("'(1 . ())"), Ok("(1)".to_string()));
+    assert_eq!(
+        eval_all("(cons \"a\" #\\b)"),
+        Ok("(\"a\" . #\\b)".to_string())
+    );
+    assert_eq!(
+        eval_all("(define x 2) `(1 . ,x)"),
+        Ok("(1 . 2)".to_string())
+    );
+}
+
+#[test]
+fn test_pair_accessors_on_improper_lists() {
+    assert_eq!(eval_all("(car '(1 . 2))"), Ok("1".to_string()));
+    assert_eq!(eval_all("(cdr '(1 . 2))"), Ok("2".to_string()));
+    assert_eq!(eval_all("(cdr '(1 2 . 3))"), Ok("(2 . 3)".to_string()));
+    assert_eq!(
+        eval_all("(append '(1) '(2) 3)"),
+        Ok("(1 2 . 3)".to_string())
+    );
+    assert_eq!(
+        eval_all("(assq 'b '((a . 1) (b . 2)))"),
+        Ok("(b . 2)".to_string())
+    );
+    assert_eq!(
+        eval_all("(equal? (cons 1 2) '(1 . 2))"),
+        Ok("#t".to_string())
+    );
+    assert_eq!(eval_all("(equal? '(1 . 2) '(1 2))"), Ok("#f".to_string()));
+}
+
+#[test]
+fn test_list_predicates_reject_improper_lists() {
+    assert_eq!(eval_all("(pair? '(1 . 2))"), Ok("#t".to_string()));
+    assert_eq!(eval_all("(list? '(1 . 2))"), Ok("#f".to_string()));
+    assert_eq!(eval_all("(list? (cons 1 '()))"), Ok("#t".to_string()));
+    assert_eq!(
+        eval_all("(length '(1 2 . 3))"),
+        Err("length expects a proper list".to_string())
+    );
+    assert!(eval_all("(map car '(1 . 2))").is_err());
+}
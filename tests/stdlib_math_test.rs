@@ -7,7 +7,7 @@ fn test_abs() {
 
     let (arena, nodes) = parse("(abs -5)").unwrap();
     let result = Interpreter::eval(arena.get(nodes[0]).unwrap(), &mut env, &arena);
-    assert!(matches!(result, Ok(SVal::Number(n)) if n == 5.0));
+    assert!(matches!(result, Ok(SVal::Integer(5))));
 
     let (arena, nodes) = parse("(abs 3.5)").unwrap();
     let result = Interpreter::eval(arena.get(nodes[0]).unwrap(), &mut env, &arena);
@@ -77,9 +77,9 @@ fn test_min_max() {
 
     let (arena, nodes) = parse("(min 3 1 4 1 5)").unwrap();
     let result = Interpreter::eval(arena.get(nodes[0]).unwrap(), &mut env, &arena);
-    assert!(matches!(result, Ok(SVal::Number(n)) if n == 1.0));
+    assert!(matches!(result, Ok(SVal::Integer(1))));
 
     let (arena, nodes) = parse("(max 3 1 4 1 5)").unwrap();
     let result = Interpreter::eval(arena.get(nodes[0]).unwrap(), &mut env, &arena);
-    assert!(matches!(result, Ok(SVal::Number(n)) if n == 5.0));
+    assert!(matches!(result, Ok(SVal::Integer(5))));
 }
@@ -20,11 +20,11 @@ fn test_string_length() {
 
     let (arena, nodes) = parse("(string-length \"hello\")").unwrap();
     let result = Interpreter::eval(arena.get(nodes[0]).unwrap(), &mut env, &arena);
-    assert!(matches!(result, Ok(SVal::Number(n)) if n == 5.0));
+    assert!(matches!(result, Ok(SVal::Integer(5))));
 
     let (arena, nodes) = parse("(string-length \"\")").unwrap();
     let result = Interpreter::eval(arena.get(nodes[0]).unwrap(), &mut env, &arena);
-    assert!(matches!(result, Ok(SVal::Number(n)) if n == 0.0));
+    assert!(matches!(result, Ok(SVal::Integer(0))));
 }
 
 #[test]
@@ -68,16 +68,17 @@ fn test_string_append() {
 
     let (arena, nodes) = parse("(string-append)").unwrap();
     let result = Interpreter::eval(arena.get(nodes[0]).unwrap(), &mut env, &arena);
-    assert!(matches!(result, Ok(SVal::String(ref s)) if s == ""));
+    assert!(matches!(result, Ok(SVal::String(ref s)) if s.is_empty()));
 }
 
 #[test]
+#[allow(clippy::approx_constant)]
 fn test_string_to_number() {
     let mut env = Environment::new();
 
     let (arena, nodes) = parse("(string->number \"42\")").unwrap();
     let result = Interpreter::eval(arena.get(nodes[0]).unwrap(), &mut env, &arena);
-    assert!(matches!(result, Ok(SVal::Number(n)) if n == 42.0));
+    assert!(matches!(result, Ok(SVal::Integer(42))));
 
     let (arena, nodes) = parse("(string->number \"3.14\")").unwrap();
     let result = Interpreter::eval(arena.get(nodes[0]).unwrap(), &mut env, &arena);
@@ -100,3 +101,195 @@ fn test_number_to_string() {
     let result = Interpreter::eval(arena.get(nodes[0]).unwrap(), &mut env, &arena);
     assert!(matches!(result, Ok(SVal::String(ref s)) if s == "3.14"));
 }
+
+fn eval_last(code: &str) -> Result<SVal, String> {
+    let mut env = Environment::new();
+    let (arena, nodes) = parse(code).map_err(|e| e.message)?;
+    let mut result = SVal::Nil;
+    for node in nodes {
+        result = Interpreter::eval(arena.get(node).unwrap(), &mut env, &arena)?;
+    }
+    Ok(result)
+}
+
+#[test]
+fn test_char_literals_and_conversions() {
+    assert!(matches!(eval_last("#\\a"), Ok(SVal::Char('a'))));
+    assert!(matches!(eval_last("#\\("), Ok(SVal::Char('('))));
+    assert!(matches!(eval_last("#\\é"), Ok(SVal::Char('é'))));
+    assert!(matches!(eval_last("#\\x41"), Ok(SVal::Char('A'))));
+    assert!(matches!(
+        eval_last("(char? #\\space)"),
+        Ok(SVal::Bool(true))
+    ));
+    assert!(matches!(eval_last("(char? \"a\")"), Ok(SVal::Bool(false))));
+    assert!(matches!(
+        eval_last("(char->integer #\\A)"),
+        Ok(SVal::Integer(65))
+    ));
+    assert!(matches!(
+        eval_last("(integer->char 955)"),
+        Ok(SVal::Char('λ'))
+    ));
+    assert!(eval_last("(integer->char 55296)").is_err());
+    assert!(matches!(
+        eval_last("(char-upcase #\\a)"),
+        Ok(SVal::Char('A'))
+    ));
+    assert!(matches!(
+        eval_last("(char-downcase #\\Z)"),
+        Ok(SVal::Char('z'))
+    ));
+    assert!(matches!(
+        eval_last("(char-upcase #\\ß)"),
+        Ok(SVal::Char('ß'))
+    ));
+    assert!(matches!(
+        eval_last("(char-numeric? #\\7)"),
+        Ok(SVal::Bool(true))
+    ));
+    assert!(matches!(
+        eval_last("(char<? #\\a #\\b #\\c)"),
+        Ok(SVal::Bool(true))
+    ));
+    assert!(matches!(
+        eval_last("(char=? #\\a #\\b)"),
+        Ok(SVal::Bool(false))
+    ));
+    assert_eq!(eval_last("#\\newline").unwrap().to_string(), "#\\newline");
+}
+
+#[test]
+fn test_string_list_round_trip() {
+    assert_eq!(
+        eval_last("(string->list \"abc\")").unwrap().to_string(),
+        "(#\\a #\\b #\\c)"
+    );
+    assert!(matches!(eval_last("(string->list \"\")"), Ok(SVal::Nil)));
+    assert!(matches!(
+        eval_last("(list->string (map char-upcase (string->list \"hey\")))"),
+        Ok(SVal::String(ref s)) if s == "HEY"
+    ));
+    assert!(matches!(eval_last("(list->string '())"), Ok(SVal::String(ref s)) if s.is_empty()));
+    assert!(eval_last("(list->string (list 1 2))").is_err());
+}
+
+#[test]
+fn test_strings_index_by_character() {
+    assert!(matches!(
+        eval_last("(string-length (make-string 2 #\\λ))"),
+        Ok(SVal::Integer(2))
+    ));
+    assert!(matches!(
+        eval_last("(string-length \"aλb\")"),
+        Ok(SVal::Integer(3))
+    ));
+    assert!(matches!(
+        eval_last("(substring \"aλb\" 1 2)"),
+        Ok(SVal::String(ref s)) if s == "λ"
+    ));
+    assert!(matches!(
+        eval_last("(substring (string-copy \"ñandú\") 3 5)"),
+        Ok(SVal::String(ref s)) if s == "dú"
+    ));
+    assert!(matches!(
+        eval_last("(string-ref \"aλb\" 1)"),
+        Ok(SVal::Char('λ'))
+    ));
+    assert!(matches!(
+        eval_last("(string-ref \"aλb\" 2)"),
+        Ok(SVal::Char('b'))
+    ));
+    assert!(matches!(
+        eval_last("(string-copy \"aλb\" 1)"),
+        Ok(SVal::MutableString(ref s)) if *s.borrow() == "λb"
+    ));
+
+    // Bad indices are errors, never byte-boundary panics
+    assert!(eval_last("(substring \"aλb\" 1 4)").is_err());
+    assert!(eval_last("(substring \"aλb\" 2 1)").is_err());
+    assert!(eval_last("(substring \"aλb\" -1 1)").is_err());
+    assert!(eval_last("(string-ref \"aλb\" 3)").is_err());
+    assert!(eval_last("(string-ref \"\" 0)").is_err());
+}
+
+#[test]
+fn test_mutable_strings() {
+    let code = "(define s (make-string 3 #\\a))
+                (define alias s)
+                (define kept (list s))
+                (string-set! alias 1 #\\b)
+                s";
+    assert_eq!(eval_last(code).unwrap().to_string(), "\"aba\"");
+    assert_eq!(
+        eval_last(&format!("{} (car kept)", code))
+            .unwrap()
+            .to_string(),
+        "\"aba\""
+    );
+    assert!(matches!(
+        eval_last(&format!("{} (string-length s)", code)),
+        Ok(SVal::Integer(3))
+    ));
+    assert!(matches!(
+        eval_last(&format!("{} (list (eq? s alias) (equal? s \"aba\"))", code)),
+        Ok(SVal::List(ref flags)) if flags == &[SVal::Bool(true), SVal::Bool(true)]
+    ));
+
+    // Copies are independent of their source
+    let code = "(define s (string-copy \"hello\" 1 4))
+                (define t (string-copy s))
+                (string-fill! t #\\z)
+                (string-append s t)";
+    assert!(matches!(eval_last(code), Ok(SVal::String(ref s)) if s == "ellzzz"));
+    assert!(
+        matches!(eval_last("(make-string 2)"), Ok(SVal::MutableString(ref s)) if *s.borrow() == "  ")
+    );
+}
+
+#[test]
+fn test_mutators_on_multibyte_strings() {
+    let code = "(define s (string-copy \"aλb\"))
+                (string-set! s 1 #\\x)
+                (string-set! s 2 #\\é)
+                s";
+    assert_eq!(eval_last(code).unwrap().to_string(), "\"axé\"");
+    assert!(matches!(
+        eval_last(&format!("{} (list (string-length s) (string-ref s 2))", code)),
+        Ok(SVal::List(ref items)) if items == &[SVal::Integer(3), SVal::Char('é')]
+    ));
+
+    let code = "(define s (make-string 3 #\\λ))
+                (string-set! s 0 #\\a)
+                (string-fill! s #\\ü)
+                s";
+    assert_eq!(eval_last(code).unwrap().to_string(), "\"üüü\"");
+    assert!(matches!(
+        eval_last(&format!("{} (substring s 1 3)", code)),
+        Ok(SVal::String(ref s)) if s == "üü"
+    ));
+
+    // The last character is the highest valid index, whatever its width
+    assert!(eval_last("(string-set! (make-string 2 #\\λ) 2 #\\x)").is_err());
+    assert!(matches!(
+        eval_last("(define s (make-string 2 #\\λ)) (string-set! s 1 #\\x) s"),
+        Ok(SVal::MutableString(ref s)) if *s.borrow() == "λx"
+    ));
+}
+
+#[test]
+fn test_string_mutation_errors_and_join() {
+    assert!(eval_last("(string-set! \"literal\" 0 #\\x)").is_err());
+    assert!(eval_last("(string-fill! \"literal\" #\\x)").is_err());
+    assert!(eval_last("(string-set! (make-string 2) 2 #\\x)").is_err());
+    assert!(eval_last("(string-copy \"abc\" 2 1)").is_err());
+    assert!(matches!(
+        eval_last("(string-join (list \"a\" (string-copy \"b\") \"c\") \"-\")"),
+        Ok(SVal::String(ref s)) if s == "a-b-c"
+    ));
+    assert!(
+        matches!(eval_last("(string-join (list \"x\" \"y\"))"), Ok(SVal::String(ref s)) if s == "x y")
+    );
+    assert!(matches!(eval_last("(string-join '())"), Ok(SVal::String(ref s)) if s.is_empty()));
+    assert!(eval_last("(string-join (list 1 2))").is_err());
+}
@@ -0,0 +1,108 @@
+use muscm::error_types::LuaError;
+use muscm::executor::{ControlFlow, Executor};
+use muscm::lua_interpreter::LuaInterpreter;
+use muscm::lua_parser::parse_source;
+use muscm::lua_value::LuaValue;
+use std::time::Duration;
+
+fn run(interp: &mut LuaInterpreter, code: &str) -> Result<Vec<LuaValue>, LuaError> {
+    let block = parse_source(code).expect("Failed to parse");
+    match Executor::new().execute_block(&block, interp)? {
+        ControlFlow::Return(values) => Ok(values),
+        _ => Ok(Vec::new()),
+    }
+}
+
+#[test]
+fn test_loop_runs_timers_until_none_are_left() {
+    let mut interp = LuaInterpreter::new();
+    run(
+        &mut interp,
+        r#"
+        log = {}
+        timer.after(0.3, function() log[#log + 1] = 'late' end)
+        timer.after(0, function() log[#log + 1] = 'soon' end)
+        local ticks = 0
+        timer.every(0.01, function(id)
+            ticks = ticks + 1
+            log[#log + 1] = 'tick'
+            if ticks == 3 then timer.cancel(id) end
+        end)
+        local dropped = timer.after(0, function() log[#log + 1] = 'dropped' end)
+        assert(timer.cancel(dropped) and not timer.cancel(dropped))
+        "#,
+    )
+    .unwrap();
+    // Nothing fires until something drives the timers
+    assert_eq!(
+        run(&mut interp, "return #log").unwrap(),
+        vec![LuaValue::Number(0.0)]
+    );
+
+    Executor::new().run_timers(&mut interp).unwrap();
+    assert!(interp.timers.borrow().is_empty());
+    assert_eq!(
+        run(
+            &mut interp,
+            "local s = log[1] for i = 2, #log do s = s .. ' ' .. log[i] end return s"
+        )
+        .unwrap(),
+        vec![LuaValue::String("soon tick tick tick late".into())]
+    );
+}
+
+#[test]
+fn test_sleep_fires_timers_that_fall_due() {
+    let mut interp = LuaInterpreter::new();
+    let result = run(
+        &mut interp,
+        r#"
+        local fired = 0
+        timer.after(0, function() fired = fired + 1 end)
+        timer.after(60, function() fired = fired + 1 end)
+        timer.sleep(0.02)
+        return fired
+        "#,
+    );
+    assert_eq!(result.unwrap(), vec![LuaValue::Number(1.0)]);
+    assert_eq!(interp.timers.borrow().len(), 1);
+}
+
+#[test]
+fn test_timer_errors() {
+    for (code, message) in [
+        ("timer.after(-1, print)", "non-negative"),
+        ("timer.every(0, print)", "interval must be positive"),
+        ("timer.after(1, 'print')", "function"),
+        ("timer.sleep('soon')", "number"),
+    ] {
+        let err = run(&mut LuaInterpreter::new(), code)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(message), "{}: {}", code, err);
+    }
+
+    // An error in a callback stops the loop
+    let mut interp = LuaInterpreter::new();
+    run(
+        &mut interp,
+        "timer.after(0, function() error('in callback') end)",
+    )
+    .unwrap();
+    let err = Executor::new().run_timers(&mut interp).unwrap_err();
+    assert!(err.to_string().contains("in callback"), "{}", err);
+}
+
+#[test]
+fn test_endless_loop_can_be_cancelled() {
+    let mut interp = LuaInterpreter::new();
+    run(&mut interp, "timer.every(0.01, function() end)").unwrap();
+    let token = interp.cancellation_token();
+    let canceller = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        token.cancel();
+    });
+    let result = Executor::new().run_timers(&mut interp);
+    canceller.join().unwrap();
+    assert_eq!(result, Err(LuaError::Cancelled));
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use common::eval_all;
+use muscm::interpreter::SVal;
+
+#[test]
+fn test_with_output_to_string_captures_display() {
+    let result = eval_all(
+        r#"(with-output-to-string (lambda () (begin (display "hi") (display #\!) (newline))))"#,
+    );
+    assert_eq!(result, Ok(SVal::String("hi!\n".to_string())));
+}
+
+#[test]
+fn test_write_quotes_strings_and_chars() {
+    let result = eval_all(r#"(with-output-to-string (lambda () (write (list "a" #\b 'c 1/2))))"#);
+    assert_eq!(result, Ok(SVal::String("(\"a\" #\\b c 1/2)".to_string())));
+
+    let result = eval_all(r#"(with-output-to-string (lambda () (display (list "a" #\b))))"#);
+    assert_eq!(result, Ok(SVal::String("(a b)".to_string())));
+}
+
+#[test]
+fn test_output_string_port() {
+    let code = r#"
+        (define out (open-output-string))
+        (write 'sym out)
+        (display "-" out)
+        (write 42 out)
+        (get-output-string out)
+    "#;
+    assert_eq!(eval_all(code), Ok(SVal::String("sym-42".to_string())));
+}
+
+#[test]
+fn test_read_from_input_string() {
+    let code = r#"
+        (define in (open-input-string "(1 (2 three)) foo"))
+        (define first (read in))
+        (define second (read in))
+        (list first second (eof-object? (read in)))
+    "#;
+    let result = eval_all(code).unwrap();
+    assert_eq!(result.to_string(), "((1 (2 three)) foo #t)");
+}
+
+#[test]
+fn test_write_then_read_roundtrip() {
+    let code = r#"
+        (define data '(a (b 1/3) #(c 2.5) #t #\x))
+        (define text (with-output-to-string (lambda () (write data))))
+        (read (open-input-string text))
+    "#;
+    let result = eval_all(code).unwrap();
+    assert_eq!(result.to_string(), "(a (b 1/3) #(c 2.5) #t #\\x)");
+
+    let code = r#"
+        (define data '("a\"b" "back\\slash" "line\nbreak" "tab\tand\rreturn" "  two  spaces " ""))
+        (define text (with-output-to-string (lambda () (write data))))
+        (list (equal? data (read (open-input-string text))) (length (read (open-input-string text))))
+    "#;
+    let result = eval_all(code).unwrap();
+    assert_eq!(result.to_string(), "(#t 6)");
+}
+
+#[test]
+fn test_read_requires_input_port() {
+    assert!(eval_all("(read (open-output-string))").is_err());
+    assert!(eval_all("(read 42)").is_err());
+    assert!(eval_all(r#"(get-output-string (open-input-string "x"))"#).is_err());
+}
+
+#[test]
+fn test_procedures_print_name_and_arity() {
+    let code = r#"
+        (define (add a b) (+ a b))
+        (define sq (lambda (x) (* x x)))
+        (define (log fmt . args) fmt)
+        (list add sq log (lambda () 1) car +)
+    "#;
+    let result = eval_all(code).unwrap();
+    assert_eq!(
+        result.to_string(),
+        "(#<procedure:add/2> #<procedure:sq/1> #<procedure:log/1+> #<procedure/0> \
+         #<builtin:car/1> #<builtin:+/*>)"
+    );
+}
+
+#[test]
+fn test_write_deeply_nested_list() {
+    let mut value = SVal::List(vec![]);
+    for i in 0..200_000 {
+        value = SVal::DottedList(vec![SVal::Integer(i)], Box::new(value));
+    }
+    let text = value.to_string();
+    assert!(text.starts_with("(199999 . (199998 . "));
+    assert_eq!(text.matches(')').count(), 200_001);
+    // The nested values are dropped one level at a time
+    while let SVal::DottedList(_, tail) = value {
+        value = *tail;
+    }
+}
@@ -0,0 +1,162 @@
+use muscm::error_types::LuaError;
+use muscm::executor::{ControlFlow, Executor};
+use muscm::lua_interpreter::LuaInterpreter;
+use muscm::lua_parser::parse_source;
+use muscm::lua_value::LuaValue;
+use muscm::resolver;
+
+fn run(interp: &mut LuaInterpreter, code: &str) -> Result<Vec<LuaValue>, LuaError> {
+    let block = resolver::resolve(&parse_source(code).expect("Failed to parse"));
+    match Executor::new().execute_block(&block, interp)? {
+        ControlFlow::Return(values) => Ok(values),
+        _ => Ok(Vec::new()),
+    }
+}
+
+#[test]
+fn test_uncaught_error_keeps_traceback_from_where_it_was_raised() {
+    let mut interp = LuaInterpreter::new();
+    let err = run(
+        &mut interp,
+        "local t = {}\nfunction t.inner(x)\n  error('bad ' .. x)\nend\nlocal function outer()\n  t.inner('thing')\nend\nouter()",
+    )
+    .unwrap_err();
+    assert_eq!(err.to_string(), LuaError::user("bad thing", 1).to_string());
+    assert_eq!(
+        interp.error_traceback(&err).as_deref(),
+        Some(
+            "stack traceback:\n\
+             \t[C]: in function 'error'\n\
+             \tline 3: in field 'inner'\n\
+             \tline 6: in function 'outer'\n\
+             \tline 8: in main chunk"
+        )
+    );
+    // Taken once, and every frame was popped on the way out
+    assert_eq!(interp.error_traceback(&err), None);
+    assert_eq!(interp.trace.borrow().depth(), 0);
+}
+
+#[test]
+fn test_error_in_main_chunk() {
+    let mut interp = LuaInterpreter::new();
+    let err = run(&mut interp, "local x = 1\nlocal y = x + nil").unwrap_err();
+    assert_eq!(
+        interp.error_traceback(&err).as_deref(),
+        Some("stack traceback:\n\tline 2: in main chunk")
+    );
+}
+
+#[test]
+fn test_debug_traceback() {
+    let mut interp = LuaInterpreter::new();
+    let values = run(
+        &mut interp,
+        "local obj = {}\nfunction obj:where(level)\n  return debug.traceback('here', level)\nend\nreturn obj:where(), obj:where(2), debug.traceback({}) ~= nil, debug.traceback()",
+    )
+    .unwrap();
+    assert_eq!(
+        values[0],
+        LuaValue::String(
+            "here\nstack traceback:\n\tline 3: in method 'where'\n\tline 5: in main chunk".into()
+        )
+    );
+    assert_eq!(
+        values[1],
+        LuaValue::String("here\nstack traceback:\n\tline 5: in main chunk".into())
+    );
+    assert_eq!(values[2], LuaValue::Boolean(true));
+    assert_eq!(
+        values[3],
+        LuaValue::String("stack traceback:\n\tline 5: in main chunk".into())
+    );
+}
+
+#[test]
+fn test_pcall_catches_a_stack_overflow_and_the_script_goes_on() {
+    let mut interp = LuaInterpreter::new();
+    interp.max_call_depth = 200;
+    let values = run(
+        &mut interp,
+        r#"
+        local function down(n) return 1 + down(n + 1) end
+        local ok, err = pcall(down, 1)
+        local again = pcall(down, 1)
+        local function count(n) if n == 0 then return 0 end return 1 + count(n - 1) end
+        return ok, err, again, count(150)
+        "#,
+    )
+    .unwrap();
+    assert_eq!(values[0], LuaValue::Boolean(false));
+    assert!(
+        matches!(&values[1], LuaValue::String(s) if s.to_string().contains("stack overflow (more than 200 nested calls)")),
+        "{:?}",
+        values[1]
+    );
+    assert_eq!(
+        values[2..],
+        [LuaValue::Boolean(false), LuaValue::Number(150.0)]
+    );
+    assert!(interp.call_stack.is_empty());
+}
+
+#[test]
+fn test_runaway_recursion_is_a_stack_overflow_error() {
+    let mut interp = LuaInterpreter::new();
+    interp.max_call_depth = 200;
+    let err = run(
+        &mut interp,
+        "local function down(n)\n  return 1 + down(n + 1)\nend\ndown(1)",
+    )
+    .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("stack overflow (more than 200 nested calls)"),
+        "{}",
+        err
+    );
+    let traceback = interp.error_traceback(&err).unwrap();
+    assert!(
+        traceback.starts_with("stack traceback:\n\tline 2: in function 'down'"),
+        "{}",
+        traceback
+    );
+    assert!(
+        traceback.ends_with("line 4: in main chunk"),
+        "{}",
+        traceback
+    );
+    assert!(
+        traceback.contains("\n\t...\t(skipping 180 levels)\n"),
+        "{}",
+        traceback
+    );
+    assert_eq!(traceback.lines().count(), 23);
+
+    // Every call returned, so the depth is back to zero and calls work again
+    assert_eq!(interp.trace.borrow().depth(), 0);
+    let values = run(
+        &mut interp,
+        "local function down(n)\n  if n == 0 then return 0 end\n  return 1 + down(n - 1)\nend\nreturn down(150)",
+    )
+    .unwrap();
+    assert_eq!(values, vec![LuaValue::Number(150.0)]);
+}
+
+#[test]
+fn test_strict_globals_name_the_undefined_variable_and_line() {
+    let code = "local function f()\n  return undefined_name\nend\nreturn f()";
+    assert_eq!(
+        run(&mut LuaInterpreter::new(), code).unwrap(),
+        vec![LuaValue::Nil]
+    );
+
+    let mut interp = LuaInterpreter::new();
+    interp.strict_globals = true;
+    let err = run(&mut interp, code).unwrap_err().to_string();
+    assert!(
+        err.contains("undefined global 'undefined_name' at line 2"),
+        "{}",
+        err
+    );
+}